@@ -0,0 +1,274 @@
+use crate::config::artnet::{ArtNetConfig, DMX_CHANNELS};
+use crate::config::{ConfigStore, NUM_ZONES};
+use crate::output::{SinkErrors, SinkMessage, ThreadedSink};
+use crate::sync::{LockExt, TrackedMutex};
+use crate::udp::{Destination, ErrorReporter};
+use std::sync::mpsc::TryRecvError;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+const ARTNET_ID: &[u8; 8] = b"Art-Net\0";
+const OP_DMX: u16 = 0x5000;
+const PROTOCOL_VERSION: u16 = 14;
+/// Length of the ArtDmx header before the channel data
+const HEADER_LEN: usize = 18;
+/// Blackout frames sent when output stops, UDP may lose one
+const BLACKOUT_FRAMES: usize = 3;
+
+/// Starts the sender thread. Frames go out at `artnet.frame_rate_hz` while
+/// output is enabled, however fast or slow samples arrive.
+pub fn start(app_config: Arc<TrackedMutex<ConfigStore>>) -> ThreadedSink {
+    ThreadedSink::spawn("Art-Net", |config| config.artnet.enabled, move |rx, sink_errors| {
+        let mut sender = ArtNetSender::new(sink_errors);
+        let mut magnitude = [0.0; NUM_ZONES];
+        let mut next_frame = Instant::now();
+        let config = loop {
+            // Only the latest sample of each zone ends up in the frame
+            let closed = loop {
+                match rx.try_recv() {
+                    Ok(SinkMessage::Sample(event)) => {
+                        magnitude[event.processed.zone] = event.processed.value_normalized.abs();
+                    }
+                    Ok(SinkMessage::Flush) => magnitude = [0.0; NUM_ZONES],
+                    Err(TryRecvError::Empty) => break false,
+                    Err(TryRecvError::Disconnected) => break true,
+                }
+            };
+            let config = app_config.lock_or_recover().artnet.clone();
+            if closed {
+                break config;
+            }
+            if config.enabled {
+                sender.send_levels(&config, &magnitude);
+            } else {
+                sender.stop(&config);
+            }
+
+            next_frame += Duration::from_secs_f64(1.0 / config.frame_rate_hz);
+            let now = Instant::now();
+            if next_frame > now {
+                std::thread::sleep(next_frame - now);
+            } else {
+                // Fell behind, don't send a burst of frames to catch up
+                next_frame = now;
+            }
+        };
+
+        sender.stop(&config);
+    })
+}
+
+struct ArtNetSender {
+    destination: Option<Destination>,
+    /// Sequence number of the last frame, 1-255; receivers use it to reorder
+    sequence: u8,
+    /// Whether frames have been sent since output was last stopped
+    sending: bool,
+    errors: ErrorReporter,
+}
+
+impl ArtNetSender {
+    fn new(sink_errors: SinkErrors) -> Self {
+        Self {
+            destination: None,
+            sequence: 0,
+            sending: false,
+            errors: ErrorReporter::new("Art-Net output", sink_errors),
+        }
+    }
+
+    fn send_levels(&mut self, config: &ArtNetConfig, magnitude: &[f64; NUM_ZONES]) {
+        if !self.sending {
+            info!(
+                "Art-Net output started to {}:{}, universe {}:{}:{}",
+                config.host, config.port, config.net, config.subnet, config.universe
+            );
+            self.sending = true;
+        }
+        let mut data = [0u8; DMX_CHANNELS as usize];
+        for (mapping, magnitude) in config.zones.iter().zip(magnitude) {
+            let level = ((magnitude * mapping.scale).min(1.0) * 255.0).round() as u8;
+            for &channel in &mapping.channels {
+                if let Some(slot) = usize::from(channel).checked_sub(1).and_then(|i| data.get_mut(i)) {
+                    *slot = (*slot).max(level);
+                }
+            }
+        }
+        self.send_frame(config, &data[..frame_len(config)]);
+    }
+
+    /// Ends output, blacking out the universe if configured to.
+    fn stop(&mut self, config: &ArtNetConfig) {
+        if !self.sending {
+            return;
+        }
+        if config.blackout_on_shutdown {
+            let data = [0u8; DMX_CHANNELS as usize];
+            for _ in 0..BLACKOUT_FRAMES {
+                self.send_frame(config, &data[..frame_len(config)]);
+            }
+        }
+        self.destination = None;
+        self.sending = false;
+        info!("Art-Net output stopped");
+    }
+
+    fn send_frame(&mut self, config: &ArtNetConfig, data: &[u8]) {
+        // 0 would tell receivers not to reorder frames
+        self.sequence = self.sequence % 255 + 1;
+        let packet = art_dmx_packet(self.sequence, config.port_address(), data);
+        let destination = Destination::get(&mut self.destination, &config.host, config.port);
+        let target = destination.target();
+        match destination.send(&packet) {
+            Ok(()) => self.errors.succeeded(&target),
+            Err(e) => self.errors.failed(&target, &e),
+        }
+    }
+}
+
+/// Channels sent per frame: up to the highest one in use, rounded up to the
+/// even length ArtDmx requires.
+fn frame_len(config: &ArtNetConfig) -> usize {
+    let highest = config
+        .zones
+        .iter()
+        .flat_map(|mapping| mapping.channels.iter().copied())
+        .max()
+        .unwrap_or(0)
+        .min(DMX_CHANNELS);
+    usize::from(highest.max(2).next_multiple_of(2))
+}
+
+/// An ArtDmx packet: the `Art-Net` ID, opcode (little endian), protocol
+/// version, sequence, physical port, port address (little endian, so the
+/// SubUni byte comes before Net), data length (big endian) and the channels.
+fn art_dmx_packet(sequence: u8, port_address: u16, data: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + data.len());
+    packet.extend_from_slice(ARTNET_ID);
+    packet.extend_from_slice(&OP_DMX.to_le_bytes());
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    packet.push(sequence);
+    packet.push(0);
+    packet.extend_from_slice(&port_address.to_le_bytes());
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::artnet::ArtNetZone;
+    use std::net::UdpSocket;
+
+    fn receiver() -> (UdpSocket, ArtNetConfig) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let config = ArtNetConfig {
+            enabled: true,
+            host: "127.0.0.1".to_string(),
+            port: socket.local_addr().unwrap().port(),
+            ..ArtNetConfig::default()
+        };
+        (socket, config)
+    }
+
+    fn receive(socket: &UdpSocket) -> Vec<u8> {
+        let mut buf = [0u8; 1024];
+        let len = socket.recv(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn art_dmx_packet_layout() {
+        let packet = art_dmx_packet(7, 0x1234, &[1, 2, 3, 4]);
+        assert_eq!(
+            packet,
+            [
+                b'A', b'r', b't', b'-', b'N', b'e', b't', 0, // ID
+                0x00, 0x50, // OpDmx, little endian
+                0, 14, // protocol version, big endian
+                7,    // sequence
+                0,    // physical port
+                0x34, 0x12, // SubUni, Net
+                0, 4, // data length, big endian
+                1, 2, 3, 4,
+            ]
+        );
+        assert_eq!(packet.len(), HEADER_LEN + 4);
+    }
+
+    #[test]
+    fn port_address_packs_net_subnet_and_universe() {
+        let config = ArtNetConfig {
+            net: 0x7f,
+            subnet: 0xa,
+            universe: 0x5,
+            ..ArtNetConfig::default()
+        };
+        assert_eq!(config.port_address(), 0x7fa5);
+        assert_eq!(&art_dmx_packet(1, config.port_address(), &[0, 0])[14..16], [0xa5, 0x7f]);
+    }
+
+    #[test]
+    fn frame_len_is_even_and_covers_the_highest_channel() {
+        let mut config = ArtNetConfig::default();
+        assert_eq!(frame_len(&config), 8);
+        config.zones = vec![ArtNetZone::default(); NUM_ZONES];
+        assert_eq!(frame_len(&config), 2);
+        config.zones[3].channels = vec![5];
+        assert_eq!(frame_len(&config), 6);
+        config.zones[0].channels = vec![512];
+        assert_eq!(frame_len(&config), 512);
+    }
+
+    #[test]
+    fn levels_are_scaled_capped_and_merged() {
+        let (socket, mut config) = receiver();
+        config.zones[1].channels = vec![1, 2];
+        let (errors, _) = SinkErrors::channel();
+        let mut sender = ArtNetSender::new(errors);
+
+        let mut magnitude = [0.0; NUM_ZONES];
+        magnitude[0] = 0.1; // 0.5 of full at the default scale of 5
+        magnitude[1] = 0.05;
+        magnitude[2] = 0.9; // capped
+        sender.send_levels(&config, &magnitude);
+        let packet = receive(&socket);
+        assert_eq!(packet[12], 1);
+        assert_eq!(&packet[16..18], [0, 8]);
+        // Channel 1 follows the higher of zones 0 and 1
+        assert_eq!(&packet[HEADER_LEN..], [128, 64, 255, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn sequence_wraps_from_255_to_1() {
+        let (socket, config) = receiver();
+        let (errors, _) = SinkErrors::channel();
+        let mut sender = ArtNetSender::new(errors);
+        sender.sequence = 254;
+        for expected in [255, 1] {
+            sender.send_levels(&config, &[0.0; NUM_ZONES]);
+            assert_eq!(receive(&socket)[12], expected);
+        }
+    }
+
+    #[test]
+    fn stopping_blacks_out_the_universe() {
+        let (socket, config) = receiver();
+        let (errors, _) = SinkErrors::channel();
+        let mut sender = ArtNetSender::new(errors);
+        sender.send_levels(&config, &[1.0; NUM_ZONES]);
+        assert_eq!(&receive(&socket)[HEADER_LEN..], [255; 8]);
+
+        sender.stop(&config);
+        for _ in 0..BLACKOUT_FRAMES {
+            assert_eq!(&receive(&socket)[HEADER_LEN..], [0; 8]);
+        }
+        // Stopped already, nothing more is sent
+        sender.stop(&config);
+        socket.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+        assert!(socket.recv(&mut [0u8; 1024]).is_err());
+    }
+}
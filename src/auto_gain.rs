@@ -0,0 +1,248 @@
+use crate::config::{AutoGainConfig, NUM_ZONES};
+use crate::sync::LockExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// Slices of the rolling window, each keeping the peak of its time; the
+/// oldest slice is dropped as a whole
+const WINDOW_SLICES: usize = 8;
+
+/// Learns the gain of one zone that brings its recent peak magnitude to the
+/// target level
+#[derive(Debug, Clone, Copy)]
+pub struct ZoneGain {
+    gain: f64,
+    /// Peak of every slice of the window, `slice` is the current one
+    peaks: [f64; WINDOW_SLICES],
+    slice: usize,
+    slice_started: Option<Instant>,
+    /// Time of the previous update, the adaptation runs from there
+    last: Option<Instant>,
+}
+
+impl ZoneGain {
+    pub fn new(gain: f64) -> Self {
+        Self {
+            gain,
+            peaks: [0.0; WINDOW_SLICES],
+            slice: 0,
+            slice_started: None,
+            last: None,
+        }
+    }
+
+    pub fn gain(&self) -> f64 {
+        self.gain
+    }
+
+    /// Peak magnitude within the window
+    pub fn window_peak(&self) -> f64 {
+        self.peaks.iter().copied().fold(0.0, f64::max)
+    }
+
+    /// Feeds the ungained magnitude `level` at `now` and returns the gain.
+    /// The gain moves towards `target / window peak`, within the bounds, at
+    /// the adaptation rate; it stays where it is while frozen or while the
+    /// window peak is below `min_peak`, so a zone at rest isn't amplified.
+    pub fn update(&mut self, level: f64, now: Instant, config: &AutoGainConfig) -> f64 {
+        self.advance_window(now, config.window());
+        self.peaks[self.slice] = self.peaks[self.slice].max(level);
+        let last = self.last.replace(now);
+        let peak = self.window_peak();
+        if config.frozen || peak < config.min_peak {
+            return self.gain;
+        }
+        let Some(last) = last else {
+            return self.gain;
+        };
+        let target = (config.target / peak).clamp(config.min_gain, config.max_gain);
+        let dt = now.saturating_duration_since(last).as_secs_f64();
+        // Exponential approach, independent of the sample rate
+        self.gain += (target - self.gain) * (1.0 - (-config.adaptation_per_sec * dt).exp());
+        self.gain
+    }
+
+    fn advance_window(&mut self, now: Instant, window: Duration) {
+        let slice_length = window / WINDOW_SLICES as u32;
+        let Some(started) = self.slice_started else {
+            self.slice_started = Some(now);
+            return;
+        };
+        let elapsed = now.saturating_duration_since(started);
+        if elapsed >= window {
+            self.peaks = [0.0; WINDOW_SLICES];
+            self.slice_started = Some(now);
+            return;
+        }
+        let mut started = started;
+        while now.saturating_duration_since(started) >= slice_length {
+            self.slice = (self.slice + 1) % WINDOW_SLICES;
+            self.peaks[self.slice] = 0.0;
+            started += slice_length;
+        }
+        self.slice_started = Some(started);
+    }
+}
+
+/// The learned gains of every output zone, shared by the pipeline, which
+/// applies them, and the Configuration tab, which shows and resets them
+#[derive(Clone)]
+pub struct AutoGain {
+    zones: Arc<Mutex<[ZoneGain; NUM_ZONES]>>,
+}
+
+impl Default for AutoGain {
+    fn default() -> Self {
+        Self {
+            zones: Arc::new(Mutex::new([ZoneGain::new(1.0); NUM_ZONES])),
+        }
+    }
+}
+
+impl AutoGain {
+    /// Starts from the gains saved in `config` if it persists them, otherwise
+    /// from unity gain.
+    pub fn new(config: &AutoGainConfig) -> Self {
+        let auto_gain = Self::default();
+        if config.persist {
+            let mut zones = auto_gain.zones.lock_or_recover();
+            for (zone, &gain) in zones.iter_mut().zip(&config.gains) {
+                *zone = ZoneGain::new(gain);
+            }
+        }
+        auto_gain
+    }
+
+    /// Learns from the ungained magnitude `level` of `zone` and returns the
+    /// gain to apply, 1 while auto gain is disabled.
+    pub fn update(&self, zone: usize, level: f64, now: Instant, config: &AutoGainConfig) -> f64 {
+        if !config.enabled {
+            return 1.0;
+        }
+        self.zones.lock_or_recover()[zone].update(level, now, config)
+    }
+
+    pub fn gains(&self) -> [f64; NUM_ZONES] {
+        self.zones.lock_or_recover().map(|zone| zone.gain())
+    }
+
+    /// Forgets what was learned, every zone starts again from unity gain.
+    pub fn reset(&self) {
+        *self.zones.lock_or_recover() = [ZoneGain::new(1.0); NUM_ZONES];
+        info!("Auto gain reset");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::TAU;
+
+    /// Samples per second of every simulated zone
+    const RATE: u32 = 100;
+
+    fn enabled() -> AutoGainConfig {
+        AutoGainConfig {
+            enabled: true,
+            ..Default::default()
+        }
+    }
+
+    /// Plays zones of `sensitivities` for `secs`, each squeezed twice a
+    /// second up to its sensitivity, and returns every zone's gained peak
+    /// over the last second.
+    fn play(auto_gain: &AutoGain, sensitivities: &[f64], secs: u32, config: &AutoGainConfig) -> Vec<f64> {
+        let start = Instant::now();
+        let mut peaks = vec![0.0; sensitivities.len()];
+        for index in 0..secs * RATE {
+            let now = start + Duration::from_secs(1) * index / RATE;
+            let squeeze = (TAU * f64::from(index) / f64::from(RATE)).sin().abs();
+            for (zone, sensitivity) in sensitivities.iter().enumerate() {
+                let level = sensitivity * squeeze;
+                let gained = level * auto_gain.update(zone, level, now, config);
+                if index >= (secs - 1) * RATE {
+                    peaks[zone] = f64::max(peaks[zone], gained);
+                }
+            }
+        }
+        peaks
+    }
+
+    #[test]
+    fn zones_of_different_sensitivity_converge() {
+        let auto_gain = AutoGain::default();
+        let peaks = play(&auto_gain, &[0.25, 0.5, 1.0, 1.5], 120, &enabled());
+        for (zone, peak) in peaks.iter().enumerate() {
+            assert!((peak - 0.8).abs() < 0.01, "zone {} peaks at {}", zone, peak);
+        }
+        let gains = auto_gain.gains();
+        assert!((gains[0] - 3.2).abs() < 0.03 && (gains[3] - 0.8 / 1.5).abs() < 0.01, "{:?}", gains);
+    }
+
+    #[test]
+    fn adaptation_is_slow() {
+        let auto_gain = AutoGain::default();
+        play(&auto_gain, &[0.4], 1, &enabled());
+        // A second at 0.1/s covers about a tenth of the way from 1 to 2
+        let gain = auto_gain.gains()[0];
+        assert!(gain > 1.05 && gain < 1.15, "{}", gain);
+    }
+
+    #[test]
+    fn gains_stay_within_the_bounds() {
+        let auto_gain = AutoGain::default();
+        play(&auto_gain, &[0.1, 0.99], 300, &enabled());
+        let gains = auto_gain.gains();
+        assert!(gains[0] <= 4.0 && gains[0] > 3.9, "{:?}", gains);
+        let strict = AutoGainConfig {
+            min_gain: 0.9,
+            ..enabled()
+        };
+        let auto_gain = AutoGain::default();
+        play(&auto_gain, &[0.99], 300, &strict);
+        assert!(auto_gain.gains()[0] >= 0.9, "{:?}", auto_gain.gains());
+    }
+
+    #[test]
+    fn quiet_frozen_and_disabled_zones_keep_their_gain() {
+        let auto_gain = AutoGain::default();
+        play(&auto_gain, &[0.04], 30, &enabled());
+        let frozen = AutoGainConfig {
+            frozen: true,
+            ..enabled()
+        };
+        play(&auto_gain, &[0.0, 0.2], 30, &frozen);
+        assert_eq!(auto_gain.gains(), [1.0; NUM_ZONES]);
+        assert_eq!(auto_gain.update(0, 0.2, Instant::now(), &AutoGainConfig::default()), 1.0);
+    }
+
+    #[test]
+    fn the_window_forgets_old_peaks() {
+        let config = enabled();
+        let start = Instant::now();
+        let mut zone = ZoneGain::new(1.0);
+        zone.update(0.9, start, &config);
+        zone.update(0.1, start + Duration::from_secs(5), &config);
+        assert_eq!(zone.window_peak(), 0.9);
+        // A slice after the peak's slice ran out of the window
+        zone.update(0.1, start + Duration::from_millis(11_300), &config);
+        assert_eq!(zone.window_peak(), 0.1);
+        zone.update(0.2, start + Duration::from_secs(30), &config);
+        assert_eq!(zone.window_peak(), 0.2);
+    }
+
+    #[test]
+    fn persisted_gains_are_the_start() {
+        let saved = AutoGainConfig {
+            persist: true,
+            gains: vec![2.0, 0.5],
+            ..enabled()
+        };
+        let auto_gain = AutoGain::new(&saved);
+        assert_eq!(auto_gain.gains()[..3], [2.0, 0.5, 1.0]);
+        assert_eq!(AutoGain::new(&AutoGainConfig { persist: false, ..saved }).gains(), [1.0; NUM_ZONES]);
+        auto_gain.reset();
+        assert_eq!(auto_gain.gains(), [1.0; NUM_ZONES]);
+    }
+}
@@ -0,0 +1,224 @@
+//! The hint asking the firmware how many samples to batch per notification,
+//! raised while the host can't keep up and lowered again while it idles.
+
+use crate::config::BatchingConfig;
+use crate::sample::Sample;
+use crate::sync::LockExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Larger jumps of the sequence number are a firmware restart, not loss
+const MAX_SEQUENCE_GAP: u16 = 1024;
+
+/// How loaded the host was over one window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoadWindow {
+    /// Share of the window spent processing samples, 0 to 1
+    pub busy: f64,
+    /// Samples lost on the link or dropped by taps behind
+    pub drops: u64,
+}
+
+/// Decides the batching hint from the load of one window after another.
+/// The hint doubles once `sustain_windows` windows in a row were overloaded
+/// and halves once as many idled, within `min_samples` and `max_samples`;
+/// load between `idle_percent` and `overload_percent` changes nothing, so a
+/// step that just helped isn't undone by the next window. Pure, the session
+/// writes what it returns and tells it what the device read back.
+#[derive(Debug, Clone)]
+pub struct BatchController {
+    config: BatchingConfig,
+    /// What the device holds, as far as known
+    hint: u8,
+    overloaded: u32,
+    idle: u32,
+}
+
+impl BatchController {
+    /// A controller for a device holding `hint`
+    pub fn new(config: BatchingConfig, hint: u8) -> Self {
+        Self {
+            config,
+            hint,
+            overloaded: 0,
+            idle: 0,
+        }
+    }
+
+    pub fn hint(&self) -> u8 {
+        self.hint
+    }
+
+    /// Takes new settings, starting to count windows over.
+    pub fn set_config(&mut self, config: BatchingConfig) {
+        self.config = config;
+        self.overloaded = 0;
+        self.idle = 0;
+    }
+
+    /// The hint to write right away: the manual one, or the one held brought
+    /// within the bounds when adapting. `None` when the device holds it
+    /// already, or neither is asked for.
+    pub fn pending(&self) -> Option<u8> {
+        let target = match self.config.manual_samples {
+            Some(manual) => manual,
+            None if self.config.adaptive => self.bounded(),
+            None => return None,
+        };
+        (target != self.hint).then_some(target)
+    }
+
+    /// Counts the load of one more window, returning the hint to write once
+    /// overload or idling lasted long enough to change it.
+    pub fn update(&mut self, window: LoadWindow) -> Option<u8> {
+        if !self.config.adaptive || self.config.manual_samples.is_some() {
+            return None;
+        }
+        let percent = window.busy * 100.0;
+        let overloaded = window.drops > 0 || percent >= self.config.overload_percent;
+        let idle = !overloaded && percent <= self.config.idle_percent;
+        self.overloaded = if overloaded { self.overloaded + 1 } else { 0 };
+        self.idle = if idle { self.idle + 1 } else { 0 };
+
+        let hint = self.bounded();
+        let next = if self.overloaded >= self.config.sustain_windows {
+            hint.saturating_mul(2).min(self.config.max_samples)
+        } else if self.idle >= self.config.sustain_windows {
+            (hint / 2).max(self.config.min_samples)
+        } else {
+            return None;
+        };
+        // The next step waits for as many windows of the new hint
+        self.overloaded = 0;
+        self.idle = 0;
+        (next != self.hint).then_some(next)
+    }
+
+    /// Takes the hint the device read back after a write.
+    pub fn confirm(&mut self, hint: u8) {
+        self.hint = hint;
+    }
+
+    fn bounded(&self) -> u8 {
+        self.hint.clamp(self.config.min_samples, self.config.max_samples)
+    }
+}
+
+/// Measures the device session's load one window after another: the time
+/// spent in the pipeline, the samples the sequence numbers of extended
+/// packets show lost on the link, and those taps dropped.
+#[derive(Debug, Clone)]
+pub struct LoadMeter {
+    started: Instant,
+    busy: Duration,
+    lost: u64,
+    last_sequence: Option<u16>,
+    /// Tap drops when the window started
+    tap_drops: u64,
+}
+
+impl LoadMeter {
+    pub fn new(now: Instant, tap_drops: u64) -> Self {
+        Self {
+            started: now,
+            busy: Duration::ZERO,
+            lost: 0,
+            last_sequence: None,
+            tap_drops,
+        }
+    }
+
+    /// Counts the samples lost before `sample`.
+    pub fn sample(&mut self, sample: &Sample) {
+        if let Some(sequence) = sample.sequence {
+            if let Some(last) = self.last_sequence {
+                let gap = sequence.wrapping_sub(last).wrapping_sub(1);
+                if gap < MAX_SEQUENCE_GAP {
+                    self.lost += u64::from(gap);
+                }
+            }
+            self.last_sequence = Some(sequence);
+        }
+    }
+
+    /// Counts `time` spent processing.
+    pub fn busy(&mut self, time: Duration) {
+        self.busy += time;
+    }
+
+    /// The load since the last window, ended at `now` with `tap_drops`
+    /// dropped in total, starting the next.
+    pub fn window(&mut self, now: Instant, tap_drops: u64) -> LoadWindow {
+        let elapsed = now.saturating_duration_since(self.started).as_secs_f64();
+        let busy = if elapsed > 0.0 {
+            (self.busy.as_secs_f64() / elapsed).min(1.0)
+        } else {
+            0.0
+        };
+        let window = LoadWindow {
+            busy,
+            drops: self.lost + tap_drops.saturating_sub(self.tap_drops),
+        };
+        self.started = now;
+        self.busy = Duration::ZERO;
+        self.lost = 0;
+        self.tap_drops = tap_drops;
+        window
+    }
+}
+
+/// What the device session knows about batching, for the Configuration tab
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchingState {
+    /// Whether the device takes a hint; `None` until connected
+    pub supported: Option<bool>,
+    /// The hint the device read back last
+    pub hint: Option<u8>,
+    /// The load of the last window
+    pub load: Option<LoadWindow>,
+    /// Why the last write or read of the hint failed
+    pub error: Option<String>,
+}
+
+/// The batching state shared between the device session writing it and
+/// the GUI showing it
+#[derive(Debug, Clone, Default)]
+pub struct BatchingStatus {
+    state: Arc<Mutex<BatchingState>>,
+}
+
+impl BatchingStatus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self) -> BatchingState {
+        self.state.lock_or_recover().clone()
+    }
+
+    pub fn set_supported(&self, supported: bool) {
+        self.state.lock_or_recover().supported = Some(supported);
+    }
+
+    pub fn set_load(&self, load: LoadWindow) {
+        self.state.lock_or_recover().load = Some(load);
+    }
+
+    /// The device holds `hint`, read back after an `error` if any.
+    pub fn set_hint(&self, hint: Option<u8>, error: Option<String>) {
+        let mut state = self.state.lock_or_recover();
+        if hint.is_some() {
+            state.hint = hint;
+        }
+        state.error = error;
+    }
+}
+
+/// Batching served by the device session: its settings, replaced by those
+/// the GUI sends, and where it reports
+pub struct BatchingRequests {
+    pub config: BatchingConfig,
+    pub updates: mpsc::Receiver<BatchingConfig>,
+    pub status: BatchingStatus,
+}
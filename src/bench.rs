@@ -0,0 +1,171 @@
+use crate::config::{AppConfig, ConfigStore, PlotSource, NUM_ZONES};
+use crate::midi::{MidiOutputState, NullMidiSink, PortTarget};
+use crate::midi_output::MidiOutputSink;
+use crate::pipeline::{Pipeline, SampleTap};
+use crate::plot_history::{PlotFeed, PlotPoint};
+use crate::simulator;
+use crate::sync::{lock_reports, tracked, LockSide};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio::task::JoinError;
+use tracing::info;
+
+/// Samples buffered between the simulator and the pipeline, like a BLE
+/// notification stream would
+const SOURCE_QUEUE_SIZE: usize = 1024;
+/// Events the stuck output accepts before it starts dropping
+const STUCK_TAP_QUEUE_SIZE: usize = 64;
+/// How often the simulated GUI takes a plot frame
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+/// Plot width in pixels the simulated GUI decimates the points to
+const PLOT_WIDTH: f64 = 1000.0;
+
+#[derive(Error, Debug)]
+pub enum BenchError {
+    #[error("Simulated source failed: {0}")]
+    Source(#[from] JoinError),
+    #[error("Plot consumer panicked")]
+    PlotConsumer,
+    #[error("Drop rate {percent:.3}% exceeds the limit of {limit}%")]
+    TooManyDrops { percent: f64, limit: f64 },
+}
+
+pub struct BenchOptions {
+    /// Samples per second, spread across all zones
+    pub rate: f64,
+    pub duration: Duration,
+    /// Fail when more than this percentage of samples is dropped
+    pub max_drop_percent: f64,
+}
+
+/// Runs the simulated source through the full pipeline, with MIDI going to a
+/// null sink, plot frames read as if the window was open and one output that
+/// never reads its events, then prints a report. Fails if too many samples
+/// were dropped at the source; the stuck output must not cause that.
+pub async fn run(app_config: AppConfig, options: &BenchOptions) -> Result<(), BenchError> {
+    let method = app_config.midi.method;
+    let plot_duration = app_config.plot_duration_secs;
+    let app_config = Arc::new(tracked("app_config", ConfigStore::new(app_config)));
+    let mut midi_output = MidiOutputState::new(PortTarget::Any);
+    midi_output.set_connected(NullMidiSink, PortTarget::Any, "null sink".to_string());
+    let midi_output = Arc::new(Mutex::new(midi_output));
+
+    let plot_feed = PlotFeed::default();
+    let mut pipeline = Pipeline::new(app_config, MidiOutputSink::new(midi_output), Some(plot_feed.clone()));
+    let (stuck_tap, _stuck_rx, _) = SampleTap::new(STUCK_TAP_QUEUE_SIZE);
+    pipeline.add_tap(stuck_tap);
+    let stop_plot = Arc::new(AtomicBool::new(false));
+    let plot = spawn_plot_consumer(plot_feed, plot_duration, stop_plot.clone());
+
+    info!(
+        "Benchmarking {} samples/s for {:.1} s",
+        options.rate,
+        options.duration.as_secs_f64()
+    );
+    let (sample_tx, mut sample_rx) = mpsc::channel(SOURCE_QUEUE_SIZE);
+    let source = tokio::spawn(simulator::run(options.rate, options.duration, sample_tx));
+    while let Some(sample) = sample_rx.recv().await {
+        pipeline.handle_sample(sample);
+    }
+    let source = source.await?;
+    stop_plot.store(true, Ordering::Relaxed);
+    let plot = plot.join().map_err(|_| BenchError::PlotConsumer)?;
+
+    let stats = pipeline.stats();
+    let drop_percent = if source.generated == 0 {
+        0.0
+    } else {
+        100.0 * source.dropped as f64 / source.generated as f64
+    };
+    println!("Benchmark: {} samples/s for {:.1} s, MIDI method {:?}", options.rate, options.duration.as_secs_f64(), method);
+    println!("  samples generated    {}", source.generated);
+    println!("  samples processed    {}", stats.processed);
+    println!("  dropped at source    {} ({:.3}%)", source.dropped, drop_percent);
+    let tap_drops: Vec<String> = stats.tap_drops.iter().map(u64::to_string).collect();
+    println!(
+        "  dropped by outputs   {} (per zone: {})",
+        stats.tap_drops.iter().sum::<u64>(),
+        tap_drops.join(" ")
+    );
+    println!(
+        "  processing latency   p50 {:?}, p95 {:?}, p99 {:?}, max {:?}",
+        stats.latency.percentile(0.50),
+        stats.latency.percentile(0.95),
+        stats.latency.percentile(0.99),
+        stats.latency.max()
+    );
+    println!("  peak plot memory     {:.1} KiB", plot.peak_bytes as f64 / 1024.0);
+    let frames = plot.frames.max(1) as f64;
+    println!(
+        "  plot points / frame  {:.0} drawn of {:.0} held, {:.1} of {} zones rebuilt",
+        plot.drawn as f64 / frames,
+        plot.held as f64 / frames,
+        plot.rebuilt as f64 / frames,
+        NUM_ZONES
+    );
+    for report in lock_reports() {
+        for (side, counts) in LockSide::ALL.iter().zip(&report.sides).filter(|(_, counts)| counts.acquisitions > 0) {
+            println!(
+                "  lock {:<12} {:<8} {} taken, {} contended, {:?} waited",
+                report.name,
+                side.name(),
+                counts.acquisitions,
+                counts.contended,
+                Duration::from_nanos(counts.wait_nanos)
+            );
+        }
+    }
+
+    if drop_percent > options.max_drop_percent {
+        return Err(BenchError::TooManyDrops {
+            percent: drop_percent,
+            limit: options.max_drop_percent,
+        });
+    }
+    Ok(())
+}
+
+/// What the simulated GUI did with the plot frames
+#[derive(Default)]
+struct PlotConsumerStats {
+    frames: u64,
+    /// Points in the frames taken, summed over all frames
+    held: u64,
+    /// Points handed to the plot lines, summed over all frames
+    drawn: u64,
+    /// Zone lines decimated again, summed over all frames
+    rebuilt: u64,
+    peak_bytes: usize,
+}
+
+/// Takes a plot frame once per frame and turns it into line points like the
+/// GUI does: decimated to `PLOT_WIDTH`, rebuilt only for zones with new
+/// samples and copied for every line.
+fn spawn_plot_consumer(plot: PlotFeed, plot_duration: f64, stop: Arc<AtomicBool>) -> JoinHandle<PlotConsumerStats> {
+    std::thread::spawn(move || {
+        let bucket_secs = plot_duration / PLOT_WIDTH;
+        let mut stats = PlotConsumerStats::default();
+        let mut lines: [(Option<u64>, Vec<[f64; 2]>); NUM_ZONES] = Default::default();
+        while !stop.load(Ordering::Relaxed) {
+            let frame = plot.load();
+            for (zone, (built_for, points)) in lines.iter_mut().enumerate() {
+                if *built_for != Some(frame.generation(zone)) {
+                    frame.decimate_into(zone, bucket_secs, PlotSource::Normalized, points);
+                    *built_for = Some(frame.generation(zone));
+                    stats.rebuilt += 1;
+                }
+                stats.drawn += points.len() as u64;
+                std::hint::black_box(points.clone());
+            }
+            stats.frames += 1;
+            stats.held += frame.len() as u64;
+            stats.peak_bytes = stats.peak_bytes.max(frame.len() * std::mem::size_of::<PlotPoint>());
+            std::thread::sleep(FRAME_INTERVAL);
+        }
+        stats
+    })
+}
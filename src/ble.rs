@@ -0,0 +1,815 @@
+use crate::batching::{BatchController, BatchingRequests, LoadMeter};
+use crate::config::{
+    diff_zone_configs, BleConfig, parse_zone_configs, read_config_blob, write_zone_configs, ConfigLayout, DeviceConfigError,
+    DildonicaZoneConfig, WatchdogConfig, ZoneConfigChange, NUM_ZONES,
+};
+use crate::config_compare::ConfigCompare;
+use crate::config_history::{ConfigHistory, HistoryEntry};
+use crate::dfu::{self, DfuRequest};
+use crate::pipeline::Pipeline;
+use crate::sample::Sample;
+use crate::sync::{LockExt, TrackedMutex};
+use crate::watchdog::{LinkHealth, Watchdog, WatchdogEvent};
+use btleplug::api::{Central, CharPropFlags, Characteristic, Manager as _, Peripheral as _, ScanFilter, WriteType};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures::stream::StreamExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, instrument, warn};
+use uuid::Uuid;
+
+pub const DEVICE_MAC: &str = "DB:96:90:70:68:A4";
+
+/// How long to scan for advertisements before looking for the device
+const SCAN_DURATION: Duration = Duration::from_secs(2);
+/// How often the session asks its watchdog whether samples stopped
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Error, Debug)]
+pub enum BleError {
+    #[error("No Bluetooth adapters found")]
+    NoAdapter,
+    #[error("Bluetooth adapter {0} not found")]
+    AdapterNotFound(usize),
+    #[error("Device {0} not found")]
+    DeviceNotFound(String),
+    #[error("{0} characteristic {1} not found")]
+    CharacteristicNotFound(&'static str, Uuid),
+    #[error("The batching hint read back as {0} bytes, expected 1")]
+    BatchingHintLength(usize),
+    #[error("BLE error: {0}")]
+    Btle(#[from] btleplug::Error),
+}
+
+/// A zone config transfer between the app and the device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigOperation {
+    Read,
+    Write,
+}
+
+impl std::fmt::Display for ConfigOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigOperation::Read => write!(f, "read"),
+            ConfigOperation::Write => write!(f, "write"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ActivityState {
+    running: Option<ConfigOperation>,
+    /// The last finished operation and its error, until taken
+    finished: Option<(ConfigOperation, Result<(), String>)>,
+    /// Raw config blob of the last read, also one that couldn't be parsed
+    blob: Option<Vec<u8>>,
+    /// The configs the device was last known to hold, read or written
+    device: Option<[DildonicaZoneConfig; NUM_ZONES]>,
+    /// What the last read changed, until taken
+    report: Option<ConfigReadReport>,
+    /// Writes finished so far, and how the last one went
+    writes: u64,
+    last_write: Option<Result<(), String>>,
+}
+
+/// What a read found changed on the device since the configs were last read
+/// or written; only the GUI shows it
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+#[derive(Debug, Clone)]
+pub struct ConfigReadReport {
+    /// Every field the device now holds differently
+    pub changes: Vec<ZoneConfigChange>,
+    /// The configs read
+    pub device: [DildonicaZoneConfig; NUM_ZONES],
+    /// Zones with unsaved local edits, which the read left alone for the user
+    /// to choose between the edits and `device`
+    pub kept_zones: Vec<usize>,
+}
+
+/// The config read or write running in the device session, shared with the
+/// GUI so it can show a spinner and report the outcome
+#[derive(Debug, Clone, Default)]
+pub struct ConfigActivity {
+    state: Arc<Mutex<ActivityState>>,
+}
+
+impl ConfigActivity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn running(&self) -> Option<ConfigOperation> {
+        self.state.lock_or_recover().running
+    }
+
+    /// The operation finished since the last call and its outcome
+    pub fn take_finished(&self) -> Option<(ConfigOperation, Result<(), String>)> {
+        self.state.lock_or_recover().finished.take()
+    }
+
+    /// Raw config blob the device sent last, for diagnosing layout mismatches
+    pub fn config_blob(&self) -> Option<Vec<u8>> {
+        self.state.lock_or_recover().blob.clone()
+    }
+
+    /// What the read finished since the last call changed, if anything
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn take_read_report(&self) -> Option<ConfigReadReport> {
+        self.state.lock_or_recover().report.take()
+    }
+
+    /// Writes finished so far and the outcome of the last, for waiting on
+    /// one without taking `take_finished` from the GUI
+    pub fn writes(&self) -> (u64, Option<Result<(), String>>) {
+        let state = self.state.lock_or_recover();
+        (state.writes, state.last_write.clone())
+    }
+
+    fn begin(&self, operation: ConfigOperation) {
+        self.state.lock_or_recover().running = Some(operation);
+    }
+
+    fn finish(&self, operation: ConfigOperation, result: Result<(), String>) {
+        let mut state = self.state.lock_or_recover();
+        state.running = None;
+        if operation == ConfigOperation::Write {
+            state.writes += 1;
+            state.last_write = Some(result.clone());
+        }
+        state.finished = Some((operation, result));
+    }
+
+    fn record_blob(&self, blob: Vec<u8>) {
+        self.state.lock_or_recover().blob = Some(blob);
+    }
+
+    /// Remembers `configs` as what the device holds after a write.
+    fn record_written(&self, configs: [DildonicaZoneConfig; NUM_ZONES]) {
+        self.state.lock_or_recover().device = Some(configs);
+    }
+
+    /// Takes the configs `read` from the device into `local`. A zone edited
+    /// locally since the last read or write keeps its edits if the device
+    /// holds something else, and is listed in the report for the user to
+    /// decide. The first read just overwrites.
+    fn record_read(&self, read: [DildonicaZoneConfig; NUM_ZONES], local: &mut [DildonicaZoneConfig; NUM_ZONES]) {
+        let mut state = self.state.lock_or_recover();
+        let Some(previous) = state.device.replace(read) else {
+            *local = read;
+            return;
+        };
+        let changes = diff_zone_configs(&previous, &read);
+        let mut kept_zones = Vec::new();
+        for zone in 0..NUM_ZONES {
+            if local[zone] != previous[zone] && local[zone] != read[zone] {
+                kept_zones.push(zone);
+            } else {
+                local[zone] = read[zone];
+            }
+        }
+        for change in &changes {
+            info!("Device configuration changed: {}", change);
+        }
+        if !changes.is_empty() || !kept_zones.is_empty() {
+            state.report = Some(ConfigReadReport {
+                changes,
+                device: read,
+                kept_zones,
+            });
+        }
+    }
+}
+
+/// Requests from the GUI and the HTTP API served by the device session
+pub struct SessionRequests {
+    /// Zone configs to write
+    pub configs: mpsc::Receiver<[DildonicaZoneConfig; NUM_ZONES]>,
+    pub config_reads: mpsc::Receiver<()>,
+    pub dfu: mpsc::Receiver<DfuRequest>,
+    pub activity: ConfigActivity,
+    /// Where every config write is logged, if anywhere
+    pub history: Option<ConfigHistory>,
+    /// "Write & Compare" from the GUI, fed every sample
+    pub compare: Option<ConfigCompare>,
+    /// Watching for samples that stop on a connection that looks up; `None`
+    /// where the caller doesn't connect again after `SessionEnd::Stalled`
+    pub watchdog: Option<WatchdogConfig>,
+    pub link: LinkHealth,
+    /// Adapting the firmware's notification batching; `None` leaves it be
+    pub batching: Option<BatchingRequests>,
+}
+
+/// Why a device session ended
+#[derive(Debug)]
+pub enum SessionEnd {
+    /// The GUI went away or shutdown was cancelled
+    Closed,
+    /// The device rebooted into its bootloader for this update
+    FirmwareUpdate(DfuRequest),
+    /// Samples stopped arriving and the watchdog gave up on the connection
+    Stalled,
+}
+
+/// The newest of `first` and the requests queued behind it in `rx`, and how
+/// many older ones it replaces: only the latest config write matters, and
+/// repeated reads read the same.
+fn coalesce<T>(first: T, rx: &mut mpsc::Receiver<T>) -> (T, usize) {
+    let mut latest = first;
+    let mut replaced = 0;
+    while let Ok(newer) = rx.try_recv() {
+        latest = newer;
+        replaced += 1;
+    }
+    (latest, replaced)
+}
+
+/// A connected Dildonica with its sample and config characteristics.
+pub struct DeviceConnection {
+    pub device: Peripheral,
+    pub sample_char: Characteristic,
+    pub config_char: Characteristic,
+    /// Takes the batching hint, on firmware that has it
+    pub batching_char: Option<Characteristic>,
+    /// Layout of the configs read last, writes use the same; `None` until
+    /// the first read
+    layout: Mutex<Option<ConfigLayout>>,
+}
+
+/// One peripheral seen while scanning.
+pub struct ScanResult {
+    pub address: String,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    pub advertises_service: bool,
+}
+
+pub(crate) async fn find_adapter(adapter_index: Option<usize>) -> Result<Adapter, BleError> {
+    let manager = Manager::new().await?;
+    let mut adapters = manager.adapters().await?;
+    match adapter_index {
+        Some(index) if index < adapters.len() => Ok(adapters.swap_remove(index)),
+        Some(index) => Err(BleError::AdapterNotFound(index)),
+        None => adapters.into_iter().next().ok_or(BleError::NoAdapter),
+    }
+}
+
+/// Lists the peripherals advertising during a short scan, noting those
+/// advertising the service in `uuids`.
+pub async fn scan(adapter_index: Option<usize>, uuids: &BleConfig) -> Result<Vec<ScanResult>, BleError> {
+    let central = find_adapter(adapter_index).await?;
+    central.start_scan(ScanFilter::default()).await?;
+    tokio::time::sleep(SCAN_DURATION).await;
+    central.stop_scan().await?;
+
+    let mut results = Vec::new();
+    for peripheral in central.peripherals().await? {
+        let properties = peripheral.properties().await?.unwrap_or_default();
+        results.push(ScanResult {
+            address: peripheral.address().to_string(),
+            name: properties.local_name,
+            rssi: properties.rssi,
+            advertises_service: properties.services.contains(&uuids.service_uuid),
+        });
+    }
+    Ok(results)
+}
+
+/// Scans for the device at `address`, connects and discovers the
+/// characteristics in `uuids`.
+#[instrument(skip(adapter_index, uuids))]
+pub async fn connect(adapter_index: Option<usize>, address: &str, uuids: &BleConfig) -> Result<DeviceConnection, BleError> {
+    let central = find_adapter(adapter_index).await?;
+    central.start_scan(ScanFilter::default()).await?;
+    tokio::time::sleep(SCAN_DURATION).await;
+
+    let peripherals = central.peripherals().await?;
+    let device = peripherals
+        .into_iter()
+        .find(|p| p.address().to_string().eq_ignore_ascii_case(address))
+        .ok_or_else(|| BleError::DeviceNotFound(address.to_string()))?;
+
+    info!("Connecting to device...");
+    device.connect().await?;
+
+    info!("Discovering services...");
+    device.discover_services().await?;
+
+    let chars = device.characteristics();
+    let sample_char = chars
+        .iter()
+        .find(|c| c.uuid == uuids.characteristic_uuid)
+        .ok_or(BleError::CharacteristicNotFound("Sample", uuids.characteristic_uuid))?
+        .clone();
+    let config_char = chars
+        .iter()
+        .find(|c| c.uuid == uuids.config_characteristic_uuid)
+        .ok_or(BleError::CharacteristicNotFound("Config", uuids.config_characteristic_uuid))?
+        .clone();
+    let batching_char = chars.iter().find(|c| c.uuid == uuids.batching_characteristic_uuid).cloned();
+
+    Ok(DeviceConnection {
+        device,
+        sample_char,
+        config_char,
+        batching_char,
+        layout: Mutex::new(None),
+    })
+}
+
+impl DeviceConnection {
+    #[instrument(name = "config_read", skip_all)]
+    pub async fn read_configs(&self) -> Result<[DildonicaZoneConfig; NUM_ZONES], DeviceConfigError> {
+        let blob = self.read_config_blob().await?;
+        self.parse_configs(&blob)
+    }
+
+    pub async fn read_config_blob(&self) -> Result<Vec<u8>, DeviceConfigError> {
+        read_config_blob(&self.device, &self.config_char).await
+    }
+
+    /// Parses a blob read from the device and keeps its layout for writing.
+    pub fn parse_configs(&self, blob: &[u8]) -> Result<[DildonicaZoneConfig; NUM_ZONES], DeviceConfigError> {
+        let (configs, layout) = parse_zone_configs(blob, NUM_ZONES)?;
+        *self.layout.lock_or_recover() = Some(layout);
+        Ok(configs.try_into().expect("parse_zone_configs returns one config per requested zone"))
+    }
+
+    /// `configs` as the device stores them in the layout they were read in,
+    /// which for older firmware drops the low comparator threshold.
+    pub fn as_stored(&self, configs: [DildonicaZoneConfig; NUM_ZONES]) -> [DildonicaZoneConfig; NUM_ZONES] {
+        let layout = self.layout.lock_or_recover().unwrap_or_default();
+        configs.map(|config| {
+            layout
+                .parse(&layout.encode(config))
+                .expect("an encoded config parses in its own layout")
+        })
+    }
+
+    /// Writes in the layout the configs were read in, reading them first if
+    /// they haven't been, so older firmware isn't sent a layout it misreads.
+    #[instrument(name = "config_write", skip_all)]
+    pub async fn write_configs(&self, configs: &[DildonicaZoneConfig]) -> Result<(), DeviceConfigError> {
+        let layout = *self.layout.lock_or_recover();
+        let layout = match layout {
+            Some(layout) => layout,
+            None => {
+                self.read_configs().await?;
+                self.layout.lock_or_recover().unwrap_or_default()
+            }
+        };
+        write_zone_configs(&self.device, &self.config_char, configs, layout).await
+    }
+
+    /// The samples the firmware batches per notification, `None` on firmware
+    /// without the batching characteristic
+    pub async fn read_batching_hint(&self) -> Result<Option<u8>, BleError> {
+        let Some(characteristic) = &self.batching_char else {
+            return Ok(None);
+        };
+        match *self.device.read(characteristic).await? {
+            [hint] => Ok(Some(hint)),
+            ref value => Err(BleError::BatchingHintLength(value.len())),
+        }
+    }
+
+    /// Asks the firmware to batch `hint` samples per notification; nothing
+    /// on firmware without the batching characteristic.
+    pub async fn write_batching_hint(&self, hint: u8) -> Result<(), BleError> {
+        if let Some(characteristic) = &self.batching_char {
+            self.device.write(characteristic, &[hint], WriteType::WithResponse).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn disconnect(&self) -> Result<(), BleError> {
+        Ok(self.device.disconnect().await?)
+    }
+}
+
+/// Streams samples from the device into `pipeline` and serves config
+/// read/write requests from the GUI until the GUI goes away or `shutdown`
+/// is cancelled. Held notes are released and the device is disconnected
+/// before returning. A firmware update the device rebooted into its
+/// bootloader for also ends the session, and is returned for the caller to
+/// upload before connecting again; so does a stall the watchdog gave up on,
+/// for the caller to connect again.
+pub async fn run_session(
+    connection: DeviceConnection,
+    pipeline: &mut Pipeline,
+    zone_configs: Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>,
+    requests: &mut SessionRequests,
+    shutdown: &CancellationToken,
+) -> Result<SessionEnd, BleError> {
+    let result = stream_samples(&connection, pipeline, zone_configs, requests, shutdown).await;
+    pipeline.flush_outputs();
+
+    info!("Disconnecting from device...");
+    if let Err(e) = connection.device.unsubscribe(&connection.sample_char).await {
+        warn!("Failed to unsubscribe from notifications: {}", e);
+    }
+    let disconnected = connection.disconnect().await;
+    let end = result?;
+    match (&end, disconnected) {
+        (SessionEnd::Closed, disconnected) => disconnected?,
+        // A device rebooting into its bootloader may have dropped the link already
+        (SessionEnd::FirmwareUpdate(_), _) => {}
+        // A stalled link may be half gone; connecting again decides
+        (SessionEnd::Stalled, Err(e)) => warn!("Failed to disconnect the stalled device: {}", e),
+        (SessionEnd::Stalled, Ok(())) => {}
+    }
+    Ok(end)
+}
+
+async fn stream_samples(
+    connection: &DeviceConnection,
+    pipeline: &mut Pipeline,
+    zone_configs: Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>,
+    requests: &mut SessionRequests,
+    shutdown: &CancellationToken,
+) -> Result<SessionEnd, BleError> {
+    let device = &connection.device;
+
+    // Read initial configuration
+    match read_device_configs(connection, &zone_configs, &requests.activity).await {
+        Ok(()) => info!("Read initial configuration from device"),
+        Err(e) => error!("Failed to read initial configuration: {}", e),
+    }
+
+    // Also trigger a read after startup
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    match read_device_configs(connection, &zone_configs, &requests.activity).await {
+        Ok(()) => info!("Re-read configuration from device after startup"),
+        Err(e) => {
+            error!("Failed to re-read configuration after startup: {}", e);
+            // Otherwise the Configuration tab shows its defaults without a word
+            requests.activity.finish(ConfigOperation::Read, Err(e.to_string()));
+        }
+    }
+
+    if !connection.sample_char.properties.contains(CharPropFlags::NOTIFY) {
+        error!("Sample characteristic does not support notifications");
+        return Ok(SessionEnd::Closed);
+    }
+
+    info!("Subscribing to notifications...");
+    device.subscribe(&connection.sample_char).await?;
+
+    let mut notification_stream = device.notifications().await?;
+    info!("Listening for notifications...");
+    let mut watchdog = requests.watchdog.as_ref().and_then(|config| Watchdog::new(config, Instant::now()));
+    let mut watchdog_tick = tokio::time::interval(WATCHDOG_INTERVAL);
+
+    let mut batching = start_batching(connection, requests).await;
+    let mut batching_tick = batching_interval(requests.batching.as_ref().map_or(WATCHDOG_INTERVAL, |b| b.config.window()));
+    let mut load = LoadMeter::new(Instant::now(), tap_drops(pipeline));
+    // Which format a batch both fit is in
+    let mut extended = None;
+
+    'session: loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            Some(data) = notification_stream.next() => {
+                if let Some(watchdog) = &mut watchdog {
+                    watchdog.sample(Instant::now());
+                }
+                if requests.link.is_suspect() {
+                    info!("Samples are arriving again");
+                    requests.link.set_suspect(false);
+                }
+                match Sample::batch_from_bytes(&data.value, extended) {
+                    Ok(samples) => for sample in samples {
+                        extended = Some(sample.flags.is_some());
+                        load.sample(&sample);
+                        requests.link.record_sample();
+                        let handling = Instant::now();
+                        let running = pipeline.handle_sample(sample);
+                        load.busy(handling.elapsed());
+                        if !running {
+                            info!("Exiting");
+                            break 'session;
+                        }
+                        let compare_write = requests.compare.as_ref().and_then(|compare| compare.add(&sample, Instant::now()));
+                        if let Some(new_configs) = compare_write {
+                            let succeeded = write_device_configs(connection, new_configs, &zone_configs, requests).await;
+                            if let Some(compare) = &requests.compare {
+                                compare.written(succeeded);
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        warn!("Error parsing sensor data: {}", e);
+                        pipeline.record_parse_error();
+                    }
+                };
+            }
+            Some(new_configs) = requests.configs.recv() => {
+                let (new_configs, replaced) = coalesce(new_configs, &mut requests.configs);
+                if replaced > 0 {
+                    info!("Skipping {} superseded configuration writes", replaced);
+                }
+                write_device_configs(connection, new_configs, &zone_configs, requests).await;
+            }
+            Some(()) = requests.config_reads.recv() => {
+                coalesce((), &mut requests.config_reads);
+                info!("Reading configuration from device...");
+                requests.activity.begin(ConfigOperation::Read);
+                let result = read_device_configs(connection, &zone_configs, &requests.activity).await;
+                match &result {
+                    Ok(()) => info!("Configuration read successfully"),
+                    Err(e) => error!("Failed to read configuration: {}", e),
+                }
+                requests.activity.finish(ConfigOperation::Read, result.map_err(|e| e.to_string()));
+            }
+            Some(config) = next_batching_config(&mut requests.batching) => {
+                let Some(batching_requests) = &mut requests.batching else { continue };
+                let (config, _) = coalesce(config, &mut batching_requests.updates);
+                batching_tick = batching_interval(config.window());
+                batching_requests.config = config.clone();
+                if let Some(controller) = &mut batching {
+                    controller.set_config(config);
+                    if let Some(hint) = controller.pending() {
+                        write_batching_hint(connection, controller, hint, batching_requests).await;
+                    }
+                }
+            }
+            _ = batching_tick.tick(), if batching.is_some() => {
+                let window = load.window(Instant::now(), tap_drops(pipeline));
+                let (Some(controller), Some(batching_requests)) = (&mut batching, &requests.batching) else { continue };
+                batching_requests.status.set_load(window);
+                if let Some(hint) = controller.update(window) {
+                    info!(
+                        "Host {} ({:.0} % busy, {} dropped), asking the device to batch {} samples per notification",
+                        if hint > controller.hint() { "overloaded" } else { "idle" },
+                        window.busy * 100.0,
+                        window.drops,
+                        hint
+                    );
+                    write_batching_hint(connection, controller, hint, batching_requests).await;
+                }
+            }
+            Some(request) = requests.dfu.recv() => {
+                if dfu::start(device, &request).await {
+                    return Ok(SessionEnd::FirmwareUpdate(request));
+                }
+            }
+            _ = watchdog_tick.tick(), if watchdog.is_some() => {
+                match watchdog.as_mut().and_then(|watchdog| watchdog.check(Instant::now())) {
+                    Some(WatchdogEvent::Suspect(silent)) => {
+                        // btleplug's stalls leave the link looking up; a real drop reads the same here
+                        let state = match device.is_connected().await {
+                            Ok(true) => "still connected",
+                            Ok(false) => "disconnected",
+                            Err(_) => "in an unknown state",
+                        };
+                        warn!("No samples for {:.1} s with the device {}; the connection is suspect", silent.as_secs_f64(), state);
+                        pipeline.record_stall();
+                        requests.link.set_suspect(true);
+                    }
+                    Some(WatchdogEvent::Reconnect(silent)) => {
+                        warn!("No samples for {:.1} s, connecting to the device again", silent.as_secs_f64());
+                        return Ok(SessionEnd::Stalled);
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+    Ok(SessionEnd::Closed)
+}
+
+/// Every sample the pipeline's taps dropped so far
+fn tap_drops(pipeline: &Pipeline) -> u64 {
+    pipeline.stats().tap_drops.iter().sum()
+}
+
+/// Ticks every `window`, the first time after one
+fn batching_interval(window: Duration) -> tokio::time::Interval {
+    tokio::time::interval_at(tokio::time::Instant::now() + window, window)
+}
+
+/// The next settings the GUI sent for batching, never without batching
+async fn next_batching_config(batching: &mut Option<BatchingRequests>) -> Option<crate::config::BatchingConfig> {
+    match batching {
+        Some(batching) => batching.updates.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Reads the batching hint the device holds and writes the one the settings
+/// ask for. `None` without batching settings, or on a device that takes no
+/// hint or whose hint can't be read.
+async fn start_batching(connection: &DeviceConnection, requests: &SessionRequests) -> Option<BatchController> {
+    let batching = requests.batching.as_ref()?;
+    let hint = match connection.read_batching_hint().await {
+        Ok(Some(hint)) => hint,
+        Ok(None) => {
+            info!("The device takes no batching hint");
+            batching.status.set_supported(false);
+            return None;
+        }
+        Err(e) => {
+            warn!("Failed to read the batching hint: {}", e);
+            batching.status.set_supported(true);
+            batching.status.set_hint(None, Some(e.to_string()));
+            return None;
+        }
+    };
+    info!("The device batches {} samples per notification", hint);
+    batching.status.set_supported(true);
+    batching.status.set_hint(Some(hint), None);
+    let mut controller = BatchController::new(batching.config.clone(), hint);
+    if let Some(pending) = controller.pending() {
+        write_batching_hint(connection, &mut controller, pending, batching).await;
+    }
+    Some(controller)
+}
+
+/// Writes `hint` to the device and verifies it by reading it back, telling
+/// `controller` and the GUI what the device holds afterwards.
+async fn write_batching_hint(connection: &DeviceConnection, controller: &mut BatchController, hint: u8, batching: &BatchingRequests) {
+    let result = match connection.write_batching_hint(hint).await {
+        Ok(()) => connection.read_batching_hint().await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(Some(read)) => {
+            controller.confirm(read);
+            if read == hint {
+                info!("Batching hint of {} samples written", hint);
+                batching.status.set_hint(Some(read), None);
+            } else {
+                warn!("The device reads back a batching hint of {} after {} was written", read, hint);
+                batching.status.set_hint(Some(read), Some(format!("read back {} after writing {}", read, hint)));
+            }
+        }
+        // A controller only runs for a device with the characteristic
+        Ok(None) => {}
+        Err(e) => {
+            warn!("Failed to write the batching hint: {}", e);
+            batching.status.set_hint(None, Some(e.to_string()));
+        }
+    }
+}
+
+/// Writes `new_configs` to the device and into `zone_configs`, verifies and
+/// logs the write and reports it to the GUI. Returns whether it succeeded.
+async fn write_device_configs(
+    connection: &DeviceConnection,
+    new_configs: [DildonicaZoneConfig; NUM_ZONES],
+    zone_configs: &TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>,
+    requests: &SessionRequests,
+) -> bool {
+    info!("Writing new configuration to device...");
+    requests.activity.begin(ConfigOperation::Write);
+    let result = connection.write_configs(&new_configs).await;
+    let entry = match &result {
+        Ok(()) => {
+            info!("Configuration written successfully");
+            *zone_configs.lock_or_recover() = new_configs;
+            requests.activity.record_written(connection.as_stored(new_configs));
+            verify_write(connection, new_configs).await
+        }
+        Err(e) => {
+            error!("Failed to write configuration: {}", e);
+            HistoryEntry::new(new_configs, false, Some(e.to_string()))
+        }
+    };
+    if let Some(history) = &requests.history {
+        if let Err(e) = history.append(&entry) {
+            warn!("Failed to log the configuration write to {}: {}", history.path().display(), e);
+        }
+    }
+    let succeeded = result.is_ok();
+    requests.activity.finish(ConfigOperation::Write, result.map_err(|e| e.to_string()));
+    succeeded
+}
+
+/// Reads the configs back after writing `written`, for the history entry of
+/// the write. They are compared as the device's layout stores them.
+async fn verify_write(connection: &DeviceConnection, written: [DildonicaZoneConfig; NUM_ZONES]) -> HistoryEntry {
+    let expected = connection.as_stored(written);
+    match connection.read_configs().await {
+        Ok(read) if read == expected => HistoryEntry::new(written, true, None),
+        Ok(read) => {
+            let changes = diff_zone_configs(&expected, &read)
+                .iter()
+                .map(|change| change.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            warn!("The device reads back other configuration values than were written: {}", changes);
+            HistoryEntry::new(written, false, Some(format!("read back other values: {}", changes)))
+        }
+        Err(e) => {
+            warn!("Failed to read the configuration back: {}", e);
+            HistoryEntry::new(written, false, Some(format!("reading back failed: {}", e)))
+        }
+    }
+}
+
+/// Reads the device's configs into `zone_configs`, keeping unsaved edits as
+/// `ConfigActivity::record_read` says. The raw blob is kept in `activity`
+/// even when it can't be parsed, so the Configuration tab can show what
+/// arrived.
+async fn read_device_configs(
+    connection: &DeviceConnection,
+    zone_configs: &TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>,
+    activity: &ConfigActivity,
+) -> Result<(), DeviceConfigError> {
+    let blob = connection.read_config_blob().await?;
+    let configs = connection.parse_configs(&blob);
+    activity.record_blob(blob);
+    activity.record_read(configs?, &mut zone_configs.lock_or_recover());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HARDWARE_TEMPLATES;
+
+    #[test]
+    fn queued_writes_coalesce_to_the_newest() {
+        let (tx, mut rx) = mpsc::channel(10);
+        for write in 2..=5 {
+            tx.try_send(write).unwrap();
+        }
+        assert_eq!(coalesce(1, &mut rx), (5, 4));
+        assert!(rx.try_recv().is_err(), "the queue is drained");
+    }
+
+    #[test]
+    fn a_write_with_nothing_queued_is_sent_as_is() {
+        let (_tx, mut rx) = mpsc::channel::<u32>(10);
+        assert_eq!(coalesce(1, &mut rx), (1, 0));
+    }
+
+    #[test]
+    fn a_write_queued_after_coalescing_is_kept_for_the_next_one() {
+        let (tx, mut rx) = mpsc::channel(10);
+        tx.try_send(2).unwrap();
+        assert_eq!(coalesce(1, &mut rx), (2, 1));
+        tx.try_send(3).unwrap();
+        assert_eq!(rx.try_recv().ok(), Some(3));
+    }
+
+    #[test]
+    fn activity_reports_the_running_operation_and_its_outcome_once() {
+        let activity = ConfigActivity::new();
+        assert_eq!(activity.running(), None);
+        activity.begin(ConfigOperation::Write);
+        assert_eq!(activity.running(), Some(ConfigOperation::Write));
+        activity.finish(ConfigOperation::Write, Err("timed out".to_string()));
+        assert_eq!(activity.running(), None);
+        assert_eq!(
+            activity.take_finished(),
+            Some((ConfigOperation::Write, Err("timed out".to_string())))
+        );
+        assert_eq!(activity.take_finished(), None);
+        assert_eq!(activity.writes(), (1, Some(Err("timed out".to_string()))));
+    }
+
+    #[test]
+    fn reads_are_not_counted_as_writes() {
+        let activity = ConfigActivity::new();
+        activity.begin(ConfigOperation::Read);
+        activity.finish(ConfigOperation::Read, Ok(()));
+        assert_eq!(activity.take_finished(), Some((ConfigOperation::Read, Ok(()))));
+        assert_eq!(activity.writes(), (0, None));
+    }
+
+    #[test]
+    fn the_first_read_overwrites_the_local_configs() {
+        let activity = ConfigActivity::new();
+        let mut local = HARDWARE_TEMPLATES[0].zones;
+        let mut read = local;
+        read[3].enabled = !read[3].enabled;
+        activity.record_read(read, &mut local);
+        assert_eq!(local, read);
+        assert!(activity.take_read_report().is_none());
+    }
+
+    #[test]
+    fn a_later_read_keeps_local_edits_the_device_disagrees_with() {
+        let activity = ConfigActivity::new();
+        let device = HARDWARE_TEMPLATES[0].zones;
+        activity.record_written(device);
+        let mut local = device;
+        local[1].enabled = !local[1].enabled;
+        let mut read = device;
+        read[1].cycle_count_end += 10;
+        read[2].cycle_count_end += 10;
+        activity.record_read(read, &mut local);
+
+        assert_eq!(local[1].enabled, !device[1].enabled, "the edit is kept");
+        assert_eq!(local[2], read[2], "an unedited zone takes the device's");
+        let report = activity.take_read_report().unwrap();
+        assert_eq!(report.kept_zones, vec![1]);
+        assert_eq!(report.changes.len(), 2);
+        assert!(activity.take_read_report().is_none());
+    }
+}
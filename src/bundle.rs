@@ -0,0 +1,208 @@
+use crate::config::migrate::{self, MigrationError};
+use crate::config::overrides::ConfigOverride;
+use crate::config::{AppConfig, DildonicaZoneConfig, NUM_ZONES};
+use crate::markers::SessionMarker;
+use crate::sample::{Sample, SampleFlags};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+/// `format` of every bundle header
+pub const BUNDLE_FORMAT: &str = "dildonica-session";
+/// Version written to new bundles; newer bundles are rejected. Version 2
+/// added marker records.
+pub const BUNDLE_VERSION: u32 = 2;
+
+/// Top-level settings `--with-config` takes from a bundle: everything that
+/// changes how samples turn into MIDI, but no ports, hosts or display options
+const REPLAYED_SETTINGS: &[&str] = &["midi", "gesture", "zone_map", "exponential_alpha"];
+/// Settings under `REPLAYED_SETTINGS` that keep the local value, since they
+/// pick a port or the patches of a synth on the recording machine
+const LOCAL_SETTINGS: &[&str] = &["midi.output_port", "midi.virtual_port", "midi.patches"];
+
+#[derive(Error, Debug)]
+pub enum BundleError {
+    #[error("Line {line}: {reason}")]
+    InvalidRecord { line: usize, reason: String },
+    #[error("Session bundle has no header record")]
+    MissingHeader,
+    #[error("Not a session bundle (format `{0}`)")]
+    UnknownFormat(String),
+    #[error("Session bundle version {0} is newer than this app supports ({BUNDLE_VERSION})")]
+    UnsupportedVersion(u32),
+    #[error("Expected {NUM_ZONES} zone configs in the bundle, found {0}")]
+    ZoneCount(usize),
+    #[error("Bundle app config: {0}")]
+    AppConfig(#[from] MigrationError),
+}
+
+/// One line of a session bundle. A bundle is a JSON-lines file: a header
+/// record with the settings the session ran with, then one sample record per
+/// sample as received from the source, then the markers dropped during it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BundleRecord {
+    Header(BundleHeader),
+    Sample(BundleSample),
+    Marker(SessionMarker),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleHeader {
+    /// Always `BUNDLE_FORMAT`
+    pub format: String,
+    pub version: u32,
+    /// Version of the app that recorded the bundle
+    pub app_version: String,
+    /// Host time of the first sample, seconds since the Unix epoch
+    pub started: f64,
+    /// The app config in effect, overrides included, kept as JSON so
+    /// configs of other app versions can be migrated when replaying
+    pub app_config: Value,
+    pub zone_configs: Vec<DildonicaZoneConfig>,
+}
+
+impl BundleHeader {
+    pub fn new(started: f64, app_config: &AppConfig, zone_configs: &[DildonicaZoneConfig; NUM_ZONES]) -> Self {
+        Self {
+            format: BUNDLE_FORMAT.to_string(),
+            version: BUNDLE_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            started,
+            // Serializing a config can't fail, it has no maps with non-string keys
+            app_config: serde_json::to_value(app_config).unwrap_or(Value::Null),
+            zone_configs: zone_configs.to_vec(),
+        }
+    }
+
+    /// The device zone configs of the session
+    pub fn zone_configs(&self) -> Result<[DildonicaZoneConfig; NUM_ZONES], BundleError> {
+        self.zone_configs
+            .as_slice()
+            .try_into()
+            .map_err(|_| BundleError::ZoneCount(self.zone_configs.len()))
+    }
+
+    /// The settings of the session that shape processing, as overrides on
+    /// top of the local config, so they are used while replaying but never
+    /// saved. One override per setting, see `REPLAYED_SETTINGS`.
+    pub fn config_overrides(&self) -> Result<Vec<ConfigOverride>, BundleError> {
+        let app_config = migrate::migrate(self.app_config.clone())?;
+        let mut overrides = Vec::new();
+        for &key in REPLAYED_SETTINGS {
+            if let Some(value) = app_config.get(key) {
+                collect_overrides(key.to_string(), value, &mut overrides);
+            }
+        }
+        Ok(overrides)
+    }
+}
+
+/// Adds an override for every leaf below `key`, so the local config keeps
+/// settings the bundle doesn't have (e.g. from an older version)
+fn collect_overrides(key: String, value: &Value, overrides: &mut Vec<ConfigOverride>) {
+    if LOCAL_SETTINGS.contains(&key.as_str()) {
+        return;
+    }
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                collect_overrides(format!("{}.{}", key, name), field, overrides);
+            }
+        }
+        _ => overrides.push(ConfigOverride {
+            key,
+            value: value.clone(),
+            original: Value::Null,
+        }),
+    }
+}
+
+/// A sample as received from the source, before the zone map
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct BundleSample {
+    /// Host time the sample was handled at, seconds since the Unix epoch
+    pub host_time: f64,
+    pub timestamp: i32,
+    /// Device zone
+    pub zone: usize,
+    /// `None` when the zone had no reading
+    pub value: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flags: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u16>,
+}
+
+impl BundleSample {
+    pub fn new(host_time: f64, sample: &Sample) -> Self {
+        Self {
+            host_time,
+            timestamp: sample.timestamp,
+            zone: sample.zone,
+            value: sample.value,
+            flags: sample.flags.map(|flags| flags.0),
+            sequence: sample.sequence,
+        }
+    }
+
+    pub fn to_sample(self) -> Sample {
+        Sample {
+            timestamp: self.timestamp,
+            zone: self.zone,
+            value: self.value,
+            flags: self.flags.map(SampleFlags),
+            sequence: self.sequence,
+        }
+    }
+}
+
+/// Whether `contents` look like a session bundle rather than a CSV recording
+pub fn is_bundle(contents: &str) -> bool {
+    contents.trim_start().starts_with('{')
+}
+
+/// Parses a session bundle into its header, samples and markers. Samples
+/// with a zone out of range are rejected like malformed records.
+pub fn parse_bundle(contents: &str) -> Result<(BundleHeader, Vec<Sample>, Vec<SessionMarker>), BundleError> {
+    let mut header = None;
+    let mut samples = Vec::new();
+    let mut markers = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let invalid = |reason: String| BundleError::InvalidRecord {
+            line: line_number,
+            reason,
+        };
+        let record: BundleRecord = serde_json::from_str(line).map_err(|e| invalid(e.to_string()))?;
+        match record {
+            BundleRecord::Header(found) if header.is_none() => {
+                if found.format != BUNDLE_FORMAT {
+                    return Err(BundleError::UnknownFormat(found.format));
+                }
+                if found.version > BUNDLE_VERSION {
+                    return Err(BundleError::UnsupportedVersion(found.version));
+                }
+                header = Some(found);
+            }
+            // Only one session per bundle; a second header can't be honoured
+            BundleRecord::Header(_) => {
+                return Err(invalid("second header record".to_string()));
+            }
+            BundleRecord::Sample(_) | BundleRecord::Marker(_) if header.is_none() => return Err(BundleError::MissingHeader),
+            BundleRecord::Sample(sample) if sample.zone >= NUM_ZONES => {
+                return Err(invalid(format!("zone {} is out of range", sample.zone)));
+            }
+            BundleRecord::Sample(sample) => samples.push(sample.to_sample()),
+            BundleRecord::Marker(marker) if marker.zone.is_some_and(|zone| zone >= NUM_ZONES) => {
+                return Err(invalid(format!("marker zone {:?} is out of range", marker.zone)));
+            }
+            BundleRecord::Marker(marker) => markers.push(marker),
+        }
+    }
+    let header = header.ok_or(BundleError::MissingHeader)?;
+    Ok((header, samples, markers))
+}
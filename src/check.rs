@@ -0,0 +1,574 @@
+use crate::batching::{BatchController, LoadWindow};
+use crate::bundle::{self, BundleHeader, BundleRecord, BundleSample};
+use crate::config::migrate::{self, CURRENT_VERSION};
+use crate::config::{
+    parse_c_header, AppConfig, BatchingConfig, ConfigLayout, ConfigStore, MidiOutputMethod, PlotSource, HARDWARE_TEMPLATES, NUM_ZONES,
+};
+use crate::midi::{CaptureMidiSink, MidiOutputState, PortTarget};
+use crate::midi_output::MidiOutputSink;
+use crate::pipeline::Pipeline;
+use crate::sample::{Sample, SampleFlags};
+use crate::simulator::Simulator;
+use crate::sync::tracked;
+use serde_json::{json, Value};
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use thiserror::Error;
+
+/// The MIDI every scenario sent when the fixture was last updated, see
+/// `--write-fixture`
+const FIXTURE: &str = include_str!("../fixtures/check.golden");
+/// Samples per second the simulator sends, across all zones
+const SAMPLE_RATE: u64 = 400;
+/// Simulated time per scenario, long enough for every zone to be squeezed
+/// and let go
+const DURATION_SECS: u64 = 6;
+/// Matching lines shown before and after a difference
+const DIFF_CONTEXT: usize = 3;
+/// Differing lines shown of each side
+const DIFF_LINES: usize = 12;
+
+#[derive(Error, Debug)]
+pub enum CheckError {
+    #[error("Failed to read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("Failed to write {}: {source}", path.display())]
+    Write { path: PathBuf, source: io::Error },
+    #[error("Fixture line {line}: {message}")]
+    Fixture { line: usize, message: String },
+    /// The failures are already printed, this only sets the exit status
+    #[error("{failed} of {total} checks failed")]
+    Failed { failed: usize, total: usize },
+}
+
+pub struct CheckOptions {
+    /// Compare with this fixture instead of the built-in one
+    pub fixture: Option<PathBuf>,
+    /// Write the MIDI this build sends to this file instead of comparing
+    pub write_fixture: Option<PathBuf>,
+}
+
+/// A fixed config the simulator is played through
+struct Scenario {
+    name: &'static str,
+    /// Changes from the defaults
+    configure: fn(&mut AppConfig),
+}
+
+const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "control_change",
+        configure: |config| config.midi.method = MidiOutputMethod::ControlChange,
+    },
+    Scenario {
+        name: "notes",
+        configure: |config| config.midi.method = MidiOutputMethod::Notes,
+    },
+    Scenario {
+        name: "notes_channel_rotation",
+        configure: |config| {
+            config.midi.method = MidiOutputMethod::Notes;
+            config.midi.note_config.channel_rotation = Some(vec![1, 2, 3]);
+        },
+    },
+];
+
+/// Runs the simulator through the pipeline and the MIDI mapping of every
+/// scenario and compares the messages sent with the fixture, then
+/// round-trips the parsers and migrates old configs. Needs no device and
+/// reads no config, so it tells whether this build behaves like the one
+/// the fixture was written with. Prints one line per check, with a diff
+/// for MIDI that differs.
+pub fn run(options: &CheckOptions) -> Result<(), CheckError> {
+    let mut report = Report::default();
+    let sent: Vec<(&str, Vec<String>)> = SCENARIOS
+        .iter()
+        .map(|scenario| (scenario.name, run_scenario(scenario)))
+        .collect();
+
+    if let Some(path) = &options.write_fixture {
+        fs::write(path, fixture_text(&sent)).map_err(|source| CheckError::Write {
+            path: path.clone(),
+            source,
+        })?;
+        println!("Wrote the MIDI of {} scenarios to {}", sent.len(), path.display());
+    } else {
+        let text = match &options.fixture {
+            Some(path) => fs::read_to_string(path).map_err(|source| CheckError::Read {
+                path: path.clone(),
+                source,
+            })?,
+            None => FIXTURE.to_string(),
+        };
+        let expected = parse_fixture(&text)?;
+        for (name, messages) in &sent {
+            let result = match expected.iter().find(|(scenario, _)| scenario == name) {
+                Some((_, lines)) => match diff(lines, messages) {
+                    None => Ok(format!("{} messages", messages.len())),
+                    Some(diff) => Err(diff),
+                },
+                None => Err("not in the fixture".to_string()),
+            };
+            report.record(&format!("midi {}", name), result);
+        }
+        for (scenario, _) in expected.iter().filter(|(scenario, _)| !sent.iter().any(|(name, _)| name == scenario)) {
+            report.record(&format!("midi {}", scenario), Err("in the fixture, but no such scenario".to_string()));
+        }
+    }
+
+    report.record("sample packets", check_sample_packets());
+    report.record("batched sample packets", check_sample_batches());
+    report.record("batching controller", check_batch_controller());
+    report.record("session bundle", check_session_bundle());
+    report.record("device zone configs", check_zone_configs());
+    report.record("C header", check_c_header());
+    report.record("app config JSON and TOML", check_app_config());
+    report.record("migrate legacy MIDI config", check_migration_v0());
+    report.record("migrate version 1 plot_raw", check_migration_v1());
+    report.record("reject newer config version", check_newer_version());
+
+    if report.failed > 0 {
+        return Err(CheckError::Failed {
+            failed: report.failed,
+            total: report.total,
+        });
+    }
+    println!("All {} checks passed", report.total);
+    Ok(())
+}
+
+#[derive(Default)]
+struct Report {
+    failed: usize,
+    total: usize,
+}
+
+impl Report {
+    /// Prints how check `name` went: `Ok` with a detail, `Err` with why
+    fn record(&mut self, name: &str, result: Result<String, String>) {
+        self.total += 1;
+        match result {
+            Ok(detail) => println!("ok    {} ({})", name, detail),
+            Err(reason) => {
+                self.failed += 1;
+                println!("FAIL  {}: {}", name, reason);
+            }
+        }
+    }
+}
+
+/// The messages `scenario` sends, one line each: the device time of the
+/// sample that sent it in milliseconds, or `end` for the release after the
+/// last sample, and its bytes in hex
+fn run_scenario(scenario: &Scenario) -> Vec<String> {
+    let mut app_config = AppConfig::default();
+    (scenario.configure)(&mut app_config);
+    let app_config = Arc::new(tracked("app_config", ConfigStore::new(app_config)));
+    let capture = CaptureMidiSink::new();
+    let mut midi_output = MidiOutputState::new(PortTarget::Any);
+    midi_output.set_connected(capture.clone(), PortTarget::Any, "check capture".to_string());
+    let mut pipeline = Pipeline::new(app_config, MidiOutputSink::new(Arc::new(Mutex::new(midi_output))), None);
+
+    let mut lines = Vec::new();
+    let mut simulator = Simulator::new();
+    let epoch = Instant::now();
+    for index in 0..SAMPLE_RATE * DURATION_SECS {
+        let offset = Duration::from_micros(index * 1_000_000 / SAMPLE_RATE);
+        let timestamp = offset.as_millis() as i32;
+        pipeline.handle_sample_at(simulator.sample(timestamp), UNIX_EPOCH + offset, epoch + offset);
+        lines.extend(capture.take().iter().map(|message| message_line(&timestamp.to_string(), message)));
+    }
+    pipeline.flush_outputs();
+    lines.extend(capture.take().iter().map(|message| message_line("end", message)));
+    lines
+}
+
+fn message_line(time: &str, message: &[u8]) -> String {
+    let mut line = time.to_string();
+    for byte in message {
+        let _ = write!(line, " {:02x}", byte);
+    }
+    line
+}
+
+fn fixture_text(sent: &[(&str, Vec<String>)]) -> String {
+    let mut text = format!(
+        "# MIDI sent by `dildonica check`: {} samples/s of the simulator for {} s per\n\
+         # scenario. Regenerate with `dildonica check --write-fixture fixtures/check.golden`\n\
+         # after a change that is meant to send other MIDI.\n",
+        SAMPLE_RATE, DURATION_SECS
+    );
+    for (name, lines) in sent {
+        let _ = writeln!(text, "\n[{}]", name);
+        for line in lines {
+            let _ = writeln!(text, "{}", line);
+        }
+    }
+    text
+}
+
+/// The lines of every `[scenario]` of a fixture; blank lines and `#`
+/// comments are skipped
+fn parse_fixture(text: &str) -> Result<Vec<(String, Vec<&str>)>, CheckError> {
+    let mut scenarios: Vec<(String, Vec<&str>)> = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            scenarios.push((name.to_string(), Vec::new()));
+            continue;
+        }
+        match scenarios.last_mut() {
+            Some((_, lines)) => lines.push(line),
+            None => {
+                return Err(CheckError::Fixture {
+                    line: index + 1,
+                    message: "message before the first [scenario]".to_string(),
+                })
+            }
+        }
+    }
+    Ok(scenarios)
+}
+
+/// Where `actual` differs from `expected`, `None` if nowhere: the lines
+/// both share around the differing run and that run of each side, marked
+/// `-` for the expected and `+` for the sent
+fn diff(expected: &[&str], actual: &[String]) -> Option<String> {
+    let prefix = expected.iter().zip(actual).take_while(|(e, a)| **e == a.as_str()).count();
+    if prefix == expected.len() && prefix == actual.len() {
+        return None;
+    }
+    let suffix = expected[prefix..]
+        .iter()
+        .rev()
+        .zip(actual[prefix..].iter().rev())
+        .take_while(|(e, a)| **e == a.as_str())
+        .count();
+    let expected_run = &expected[prefix..expected.len() - suffix];
+    let actual_run = &actual[prefix..actual.len() - suffix];
+
+    let mut text = format!(
+        "{} messages sent, {} expected; first difference at message {} (- expected, + sent)",
+        actual.len(),
+        expected.len(),
+        prefix + 1
+    );
+    for line in &expected[prefix.saturating_sub(DIFF_CONTEXT)..prefix] {
+        let _ = write!(text, "\n        {}", line);
+    }
+    for (mark, run) in [("-", expected_run.to_vec()), ("+", actual_run.iter().map(String::as_str).collect())] {
+        for line in run.iter().take(DIFF_LINES) {
+            let _ = write!(text, "\n      {} {}", mark, line);
+        }
+        if run.len() > DIFF_LINES {
+            let _ = write!(text, "\n      {} ... {} more", mark, run.len() - DIFF_LINES);
+        }
+    }
+    let end = expected.len() - suffix;
+    for line in &expected[end..(end + DIFF_CONTEXT).min(expected.len())] {
+        let _ = write!(text, "\n        {}", line);
+    }
+    Some(text)
+}
+
+/// `Err` with `message` unless `holds`
+fn ensure(holds: bool, message: impl FnOnce() -> String) -> Result<(), String> {
+    if holds {
+        Ok(())
+    } else {
+        Err(message())
+    }
+}
+
+/// Samples of the simulator, one without a reading and extended ones with
+/// flags and a sequence number, for the round-trips
+fn round_trip_samples() -> Vec<Sample> {
+    let mut simulator = Simulator::new();
+    let mut samples: Vec<Sample> = (0..4 * NUM_ZONES as i32).map(|index| simulator.sample(index * 7)).collect();
+    samples[1].value = None;
+    for (index, sample) in samples.iter_mut().enumerate().skip(NUM_ZONES) {
+        sample.flags = Some(SampleFlags(index as u8 & 0x07));
+        sample.sequence = Some(u16::MAX - index as u16);
+    }
+    samples
+}
+
+/// The packet the firmware sends for `sample`, see `Sample::from_bytes`
+fn sample_packet(sample: &Sample) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(Sample::EXTENDED_SIZE);
+    packet.extend_from_slice(&sample.timestamp.to_le_bytes());
+    packet.extend_from_slice(&sample.value.unwrap_or(0).to_le_bytes());
+    packet.push(sample.zone as u8);
+    if let (Some(flags), Some(sequence)) = (sample.flags, sample.sequence) {
+        packet.push(flags.0);
+        packet.extend_from_slice(&sequence.to_le_bytes());
+        packet.extend_from_slice(&[0; 4]);
+    }
+    packet
+}
+
+fn check_sample_packets() -> Result<String, String> {
+    let samples = round_trip_samples();
+    for sample in &samples {
+        let packet = sample_packet(sample);
+        let parsed = Sample::from_bytes(&packet).map_err(|e| format!("{:?} didn't parse: {}", sample, e))?;
+        ensure(parsed == *sample, || format!("{:?} parsed as {:?}", sample, parsed))?;
+    }
+    let mut truncated = sample_packet(&samples[0]);
+    truncated.pop();
+    ensure(Sample::from_bytes(&truncated).is_err(), || "a truncated packet parsed".to_string())?;
+    Ok(format!("{} samples", samples.len()))
+}
+
+fn check_sample_batches() -> Result<String, String> {
+    let samples = round_trip_samples();
+    let (legacy, extended) = samples.split_at(NUM_ZONES);
+    let twice: Vec<Sample> = legacy.iter().chain(legacy).copied().collect();
+    // 144 bytes are 9 extended packets or 16 legacy ones, told apart by the format seen last
+    let batches = [
+        (legacy, None),
+        (&twice[..], Some(false)),
+        (&extended[..9], None),
+        (&extended[..9], Some(true)),
+        (extended, Some(true)),
+    ];
+    for (batch, format) in batches {
+        let packet: Vec<u8> = batch.iter().flat_map(sample_packet).collect();
+        let parsed = Sample::batch_from_bytes(&packet, format).map_err(|e| format!("a batch of {} didn't parse: {}", batch.len(), e))?;
+        ensure(parsed == batch, || format!("a batch of {} parsed {} samples, {} differ", batch.len(), parsed.len(), differing(batch, &parsed)))?;
+    }
+    let mut cut = sample_packet(&extended[0]);
+    cut.extend(sample_packet(&extended[1]).iter().take(Sample::SIZE - 1));
+    ensure(Sample::batch_from_bytes(&cut, Some(true)).is_err(), || "a batch with a cut packet parsed".to_string())?;
+    Ok(format!("{} batches", batches.len()))
+}
+
+/// The controller's hint through overload, drops and idling, with load in
+/// between and flapping load that must leave it be
+fn check_batch_controller() -> Result<String, String> {
+    let config = BatchingConfig {
+        adaptive: true,
+        min_samples: 2,
+        max_samples: 8,
+        sustain_windows: 3,
+        ..BatchingConfig::default()
+    };
+    let overloaded = LoadWindow { busy: 0.6, drops: 0 };
+    let dropping = LoadWindow { busy: 0.0, drops: 1 };
+    let between = LoadWindow { busy: 0.3, drops: 0 };
+    let idle = LoadWindow { busy: 0.05, drops: 0 };
+    let mut controller = BatchController::new(config.clone(), 1);
+    ensure(controller.pending() == Some(2), || format!("a hint below the bounds is written as {:?}", controller.pending()))?;
+    controller.confirm(2);
+
+    let steps = [
+        (overloaded, 3, Some(4)),
+        (between, 10, None),
+        (dropping, 3, Some(8)),
+        (overloaded, 6, None),
+        (idle, 3, Some(4)),
+        (idle, 3, Some(2)),
+        (idle, 6, None),
+    ];
+    let mut windows = 0;
+    for (window, count, expected) in steps {
+        let mut written = None;
+        for _ in 0..count {
+            windows += 1;
+            if let Some(hint) = controller.update(window) {
+                ensure(written.is_none(), || format!("window {}: a second hint {} without a sustained load", windows, hint))?;
+                written = Some(hint);
+                controller.confirm(hint);
+            }
+        }
+        ensure(written == expected, || format!("window {}: {:?} written for {:?}, expected {:?}", windows, written, window, expected))?;
+    }
+    for index in 0..20 {
+        let window = if index % 2 == 0 { overloaded } else { idle };
+        ensure(controller.update(window).is_none(), || format!("flapping load changed the hint at window {}", index))?;
+    }
+
+    controller.set_config(BatchingConfig {
+        manual_samples: Some(5),
+        ..config.clone()
+    });
+    ensure(controller.pending() == Some(5), || "the manual hint isn't written".to_string())?;
+    controller.confirm(5);
+    let adapted = (0..6).find_map(|_| controller.update(overloaded));
+    ensure(adapted.is_none(), || format!("{:?} written over the manual hint", adapted))?;
+    controller.set_config(BatchingConfig {
+        adaptive: false,
+        ..config
+    });
+    ensure(controller.pending().is_none(), || "a hint is written with adapting off".to_string())?;
+    Ok(format!("{} windows", windows))
+}
+
+fn check_session_bundle() -> Result<String, String> {
+    let samples = round_trip_samples();
+    let app_config = AppConfig::default();
+    let zone_configs = HARDWARE_TEMPLATES[0].zones;
+    let mut contents = String::new();
+    let header = BundleRecord::Header(BundleHeader::new(1000.0, &app_config, &zone_configs));
+    let records = samples
+        .iter()
+        .enumerate()
+        .map(|(index, sample)| BundleRecord::Sample(BundleSample::new(1000.0 + index as f64 / 100.0, sample)));
+    for record in std::iter::once(header).chain(records) {
+        let line = serde_json::to_string(&record).map_err(|e| e.to_string())?;
+        let _ = writeln!(contents, "{}", line);
+    }
+
+    ensure(bundle::is_bundle(&contents), || "not told apart from CSV".to_string())?;
+    let (header, parsed, markers) = bundle::parse_bundle(&contents).map_err(|e| e.to_string())?;
+    ensure(parsed == samples, || format!("{} samples written, {} differ", samples.len(), differing(&samples, &parsed)))?;
+    ensure(markers.is_empty(), || format!("{} markers parsed, none written", markers.len()))?;
+    let parsed_zones = header.zone_configs().map_err(|e| e.to_string())?;
+    ensure(parsed_zones == zone_configs, || "zone configs differ".to_string())?;
+    let parsed_config = load(header.app_config.clone())?;
+    ensure(to_json(&parsed_config) == to_json(&app_config), || "app config differs".to_string())?;
+    Ok(format!("{} samples", samples.len()))
+}
+
+/// Markers dropped from the GUI and by a gesture as the pipeline stamps
+/// them, and both markers sections of a recording read back
+fn differing(expected: &[Sample], actual: &[Sample]) -> usize {
+    expected.len().abs_diff(actual.len()) + expected.iter().zip(actual).filter(|(e, a)| e != a).count()
+}
+
+fn check_zone_configs() -> Result<String, String> {
+    let mut checked = 0;
+    for template in HARDWARE_TEMPLATES {
+        for &layout in ConfigLayout::all() {
+            let blob: Vec<u8> = template.zones.iter().flat_map(|config| layout.encode(*config)).collect();
+            let detected = ConfigLayout::detect(blob.len(), NUM_ZONES).map_err(|e| e.to_string())?;
+            ensure(detected == layout, || format!("{} blob of `{}` detected as {}", layout.name(), template.name, detected.name()))?;
+            for (zone, bytes) in blob.chunks_exact(layout.size()).enumerate() {
+                let parsed = layout.parse(bytes).map_err(|e| format!("`{}` zone {}: {}", template.name, zone, e))?;
+                ensure(layout.encode(parsed) == bytes, || {
+                    format!("`{}` zone {} changed in the {} layout", template.name, zone, layout.name())
+                })?;
+                if layout == ConfigLayout::Current {
+                    ensure(parsed == template.zones[zone], || format!("`{}` zone {} parsed as {:?}", template.name, zone, parsed))?;
+                }
+                checked += 1;
+            }
+        }
+    }
+    Ok(format!("{} zones", checked))
+}
+
+fn check_c_header() -> Result<String, String> {
+    for template in HARDWARE_TEMPLATES {
+        let mut header = format!("#define CYCLES_END {}u\n\nzone_config_t zones[{}] = {{\n", template.zones[0].cycle_count_end, NUM_ZONES);
+        for (zone, config) in template.zones.iter().enumerate() {
+            // Both initializer styles, and a define, the way firmware headers mix them
+            let end = if config.cycle_count_end == template.zones[0].cycle_count_end {
+                "CYCLES_END".to_string()
+            } else {
+                config.cycle_count_end.to_string()
+            };
+            let _ = if zone % 2 == 0 {
+                writeln!(
+                    header,
+                    "    {{{}, {}, {}, {}, {}, {}}}, // zone {}",
+                    u8::from(config.enabled),
+                    config.midi_control,
+                    config.cycle_count_begin,
+                    end,
+                    config.comp_thresh_lo,
+                    config.comp_thresh_hi,
+                    zone
+                )
+            } else {
+                writeln!(
+                    header,
+                    "    {{ .enabled = {}, .midi_control = {:#x}, .cycle_count_begin = {}, .cycle_count_end = {}, /* zone {} */ .comp_thresh_lo = {}, .comp_thresh_hi = {} }},",
+                    config.enabled, config.midi_control, config.cycle_count_begin, end, zone, config.comp_thresh_lo, config.comp_thresh_hi
+                )
+            };
+        }
+        header.push_str("};\n");
+        let parsed = parse_c_header(&header).map_err(|e| format!("`{}`: {}", template.name, e))?;
+        ensure(parsed == template.zones, || format!("`{}` parsed as {:?}", template.name, parsed))?;
+    }
+    Ok(format!("{} templates", HARDWARE_TEMPLATES.len()))
+}
+
+fn to_json(config: &AppConfig) -> Value {
+    serde_json::to_value(config).unwrap_or(Value::Null)
+}
+
+/// `value` loaded the way `AppConfig::parse_file` does
+fn load(value: Value) -> Result<AppConfig, String> {
+    let value = migrate::migrate(value).map_err(|e| e.to_string())?;
+    let config: AppConfig = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    config.validate().map_err(|e| e.to_string())?;
+    Ok(config)
+}
+
+fn check_app_config() -> Result<String, String> {
+    let mut config = AppConfig::default();
+    // Away from the defaults, so values that aren't saved show
+    config.midi.method = MidiOutputMethod::Notes;
+    config.midi.note_config.channel_rotation = Some(vec![4, 5]);
+    config.plot_sources[3] = PlotSource::Peak;
+    config.zone_groups = vec![vec![0, 1]];
+    config.exponential_alpha = 0.25;
+    let expected = to_json(&config);
+
+    let text = serde_json::to_string_pretty(&config).map_err(|e| e.to_string())?;
+    let value: Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+    ensure(to_json(&load(value)?) == expected, || "JSON round-trip changed the config".to_string())?;
+
+    let text = toml::to_string_pretty(&config).map_err(|e| e.to_string())?;
+    let value = toml::from_str::<toml::Value>(&text)
+        .map_err(|e| e.to_string())
+        .and_then(|value| serde_json::to_value(value).map_err(|e| e.to_string()))?;
+    ensure(to_json(&load(value)?) == expected, || "TOML round-trip changed the config".to_string())?;
+    Ok("changed from the defaults".to_string())
+}
+
+fn check_migration_v0() -> Result<String, String> {
+    // `dildonica_midi_config.json` held only the MIDI settings, of them only
+    // what every version had
+    let mut midi = AppConfig::default().midi;
+    midi.method = MidiOutputMethod::Notes;
+    midi.note_config.base_note = 48;
+    let Value::Object(mut fields) = serde_json::to_value(&midi).map_err(|e| e.to_string())? else {
+        return Err("the MIDI config is no JSON object".to_string());
+    };
+    fields.retain(|key, _| ["method", "control_change_config", "note_config"].contains(&key.as_str()));
+    let config = load(Value::Object(fields))?;
+    ensure(config.version == CURRENT_VERSION, || format!("migrated to version {}", config.version))?;
+    ensure(config.midi.method == MidiOutputMethod::Notes, || "the MIDI method was lost".to_string())?;
+    ensure(config.midi.note_config.base_note == 48, || "the base note was lost".to_string())?;
+    Ok(format!("to version {}", CURRENT_VERSION))
+}
+
+fn check_migration_v1() -> Result<String, String> {
+    let config = load(json!({ "version": 1, "plot_raw": true }))?;
+    ensure(config.plot_sources == [PlotSource::Raw; NUM_ZONES], || {
+        format!("plot_raw became {:?}", config.plot_sources)
+    })?;
+    let config = load(json!({ "version": 1, "plot_raw": false }))?;
+    ensure(config.plot_sources == AppConfig::default().plot_sources, || {
+        format!("plot_raw off became {:?}", config.plot_sources)
+    })?;
+    Ok(format!("to version {}", CURRENT_VERSION))
+}
+
+fn check_newer_version() -> Result<String, String> {
+    let newer = CURRENT_VERSION + 1;
+    match migrate::migrate(json!({ "version": newer })) {
+        Err(_) => Ok(format!("version {}", newer)),
+        Ok(_) => Err(format!("version {} was accepted", newer)),
+    }
+}
@@ -1,58 +1,703 @@
+use super::migrate::{self, MigrationError, CURRENT_VERSION};
+use super::artnet::ArtNetConfig;
+use super::ble::BleConfig;
+use super::device::{DeviceOscillator, ZoneLimits, DEFAULT_OSCILLATOR_HZ, FIRMWARE_LIMITS, MAX_OSCILLATOR_HZ, MIN_OSCILLATOR_HZ};
+use super::drift::DriftConfig;
+use super::gain::AutoGainConfig;
+use super::gamepad::GamepadConfig;
+use super::gesture::GestureConfig;
+use super::http::HttpConfig;
+use super::keys::KeysConfig;
+use super::language::Language;
+use super::markers::MarkerConfig;
 use super::midi::MidiConfig;
+use super::normalization::NormalizationConfig;
+use super::osc::OscConfig;
+use super::plot::{PlotSource, MAX_PLOT_DURATION_SECS, MIN_PLOT_DURATION_SECS, ZONE_PALETTE};
+use super::selftest::SelfTestConfig;
+use super::startup::StartupConfig;
+use super::synth::SynthConfig;
+use super::takes::TakeBufferConfig;
+use super::templates::{hardware_template, DeviceHardware, HardwareTemplate};
+use super::watchdog::WatchdogConfig;
+use super::batching::BatchingConfig;
+use super::overrides::{self, ConfigOverride, OverrideError};
+use super::zones::{
+    create_default_zone_map, validate_zone_groups, validate_zone_map, ZoneGroupReduction, ZoneMapError, ZoneMapPreset,
+    ZoneMapScope, NUM_ZONES,
+};
+use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use std::error::Error;
 use std::fs;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tracing::{debug, error, info, info_span, instrument, warn};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct AppConfig {
+    /// Schema version, see `migrate::CURRENT_VERSION`
+    pub version: u32,
     pub midi: MidiConfig,
-    pub plot_raw: bool,
+    pub gesture: GestureConfig,
+    pub osc: OscConfig,
+    pub artnet: ArtNetConfig,
+    pub synth: SynthConfig,
+    pub gamepad: GamepadConfig,
+    pub keys: KeysConfig,
+    pub http: HttpConfig,
+    pub selftest: SelfTestConfig,
+    pub auto_gain: AutoGainConfig,
+    pub drift: DriftConfig,
+    pub watchdog: WatchdogConfig,
+    /// Samples per notification asked of the firmware
+    pub batching: BatchingConfig,
+    /// Steps run after launch, before playing
+    pub startup: StartupConfig,
+    pub takes: TakeBufferConfig,
+    /// Markers dropped into the session's timeline by a gesture
+    pub markers: MarkerConfig,
+    /// UUIDs of the device's service and characteristics
+    pub ble: BleConfig,
+    /// Indexed by output zone, the value its plot line shows
+    pub plot_sources: [PlotSource; NUM_ZONES],
+    /// Mark the MIDI messages sent on the plot
+    pub plot_midi_events: bool,
+    /// Indexed by output zone, the sRGB color of its plot line
+    pub plot_colors: [[u8; 3]; NUM_ZONES],
+    /// Mark every zone's line with its own shape as well, so zones are told
+    /// apart without their colors
+    pub plot_zone_markers: bool,
+    /// Indexed by output zone, the value is the device zone shown and played
+    /// as that output zone. A permutation of `0..NUM_ZONES`.
     pub zone_map: Vec<usize>,
+    /// Whether `zone_map` orders the plot, the taps and the stats as well, or
+    /// only what MIDI and the zone bindings play
+    pub zone_map_scope: ZoneMapScope,
+    /// Zone maps to switch between from the Configuration tab or with `--map-preset`
+    pub zone_map_presets: Vec<ZoneMapPreset>,
+    /// Output zones played and metered together as one logical zone each,
+    /// see `LogicalZones`; the plot still shows every zone
+    pub zone_groups: Vec<Vec<usize>>,
+    pub zone_group_reduction: ZoneGroupReduction,
+    /// Oscillator frequencies of the devices used, by address; others are
+    /// taken to run at `DEFAULT_OSCILLATOR_HZ`
+    pub device_oscillators: Vec<DeviceOscillator>,
+    /// Hardware revisions of the devices used, by address, set by loading
+    /// a template for them; others get `FIRMWARE_LIMITS`
+    pub device_hardware: Vec<DeviceHardware>,
     pub exponential_alpha: f64,
+    pub normalization: NormalizationConfig,
+    /// Width of the plot's window, also set by zooming the plot
     pub plot_duration_secs: f64,
+    /// Memory the plot history may take across all zones; its oldest points
+    /// are compacted to their lowest and highest values to stay within it
+    pub plot_memory_mb: f64,
+    /// Language of the GUI
+    pub language: Language,
+    /// File this config was loaded from and is saved back to
+    #[serde(skip)]
+    pub config_path: PathBuf,
+    /// Name of the `--profile` in use, if any
+    #[serde(skip)]
+    pub profile: Option<String>,
+    /// `--set` and environment overrides currently applied
+    #[serde(skip)]
+    pub overrides: Vec<ConfigOverride>,
+    /// Why the file couldn't be loaded, until `ConfigStore::new` takes it
+    /// over for safe mode
+    #[serde(skip)]
+    pub load_failure: Option<LoadFailure>,
 }
-fn create_default_zone_map(num_zones: usize) -> Vec<usize> {
-    (0..num_zones).collect()
+
+/// A config file that failed to load: the app runs on the defaults in safe
+/// mode, saving nothing, until the file loads or the defaults are accepted
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadFailure {
+    pub error: String,
+    /// Line and column of the error, when the parser tells
+    pub location: Option<(usize, usize)>,
+    /// Where the broken file was moved so no save can overwrite it, `None`
+    /// if moving it failed
+    pub preserved: Option<PathBuf>,
+}
+
+impl LoadFailure {
+    /// `error` of loading `path`, located in the file's contents if it can
+    /// still be read
+    pub fn new(path: &Path, error: &ConfigError, preserved: Option<PathBuf>) -> Self {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        Self {
+            error: error.to_string(),
+            location: error.location(&contents),
+            preserved,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    TomlRead(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlWrite(#[from] toml::ser::Error),
+    #[error(transparent)]
+    Migration(#[from] MigrationError),
+    #[error(transparent)]
+    Override(#[from] OverrideError),
+    #[error("Invalid override value: {0}")]
+    OverrideValue(serde_json::Error),
+    #[error(transparent)]
+    ZoneMap(#[from] ZoneMapError),
+    /// A setting outside its allowed range
+    #[error("{0}")]
+    Invalid(String),
+}
+
+impl ConfigError {
+    /// Line and column in `contents` where parsing failed, for errors of
+    /// the file's syntax
+    pub fn location(&self, contents: &str) -> Option<(usize, usize)> {
+        match self {
+            ConfigError::Json(e) if e.line() > 0 => Some((e.line(), e.column())),
+            ConfigError::TomlRead(e) => e.span().map(|span| {
+                let before = contents.get(..span.start).unwrap_or(contents);
+                let line = before.matches('\n').count() + 1;
+                let column = before.rsplit('\n').next().map_or(0, |line| line.chars().count()) + 1;
+                (line, column)
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// On-disk format of the config file, chosen by its extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// `.toml` files are TOML, everything else is JSON for compatibility.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Json,
+        }
+    }
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
+            version: CURRENT_VERSION,
             midi: MidiConfig::default(),
-            plot_raw: false,
-            zone_map: create_default_zone_map(8), // Default to 8 zones
+            gesture: GestureConfig::default(),
+            osc: OscConfig::default(),
+            artnet: ArtNetConfig::default(),
+            synth: SynthConfig::default(),
+            gamepad: GamepadConfig::default(),
+            keys: KeysConfig::default(),
+            http: HttpConfig::default(),
+            selftest: SelfTestConfig::default(),
+            auto_gain: AutoGainConfig::default(),
+            drift: DriftConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            batching: BatchingConfig::default(),
+            startup: StartupConfig::default(),
+            takes: TakeBufferConfig::default(),
+            markers: MarkerConfig::default(),
+            ble: BleConfig::default(),
+            plot_sources: [PlotSource::Normalized; NUM_ZONES],
+            plot_midi_events: false,
+            plot_colors: ZONE_PALETTE,
+            plot_zone_markers: true,
+            zone_map: create_default_zone_map(NUM_ZONES),
+            zone_map_scope: ZoneMapScope::Everything,
+            zone_map_presets: Vec::new(),
+            zone_groups: Vec::new(),
+            zone_group_reduction: ZoneGroupReduction::Max,
+            device_oscillators: Vec::new(),
+            device_hardware: Vec::new(),
             exponential_alpha: 0.001,
+            normalization: NormalizationConfig::default(),
             plot_duration_secs: 4.0,
+            plot_memory_mb: 64.0,
+            language: Language::English,
+            config_path: Self::config_file_path(),
+            profile: None,
+            overrides: Vec::new(),
+            load_failure: None,
         }
     }
 }
 
 impl AppConfig {
-    const CONFIG_FILE_NAME: &'static str = "dildonica_config.json";
+    const CONFIG_FILE_NAME: &'static str = "config.json";
+    const LEGACY_CONFIG_FILE_NAME: &'static str = "dildonica_config.json";
+    /// Older still: only the MIDI settings, migrated by the version 0 step
+    const LEGACY_MIDI_CONFIG_FILE_NAME: &'static str = "dildonica_midi_config.json";
+
+    /// Per-user config location, e.g. `~/.config/dildonica/config.json` on Linux.
+    pub fn config_file_path() -> PathBuf {
+        match ProjectDirs::from("", "", "dildonica") {
+            Some(dirs) => dirs.config_dir().join(Self::CONFIG_FILE_NAME),
+            None => {
+                warn!("Could not determine a home directory, using the current directory for config");
+                PathBuf::from(Self::LEGACY_CONFIG_FILE_NAME)
+            }
+        }
+    }
+
+    /// Location of a named profile, `<configdir>/profiles/<name>.json`.
+    pub fn profile_path(name: &str) -> PathBuf {
+        let default_path = Self::config_file_path();
+        let config_dir = default_path.parent().unwrap_or(Path::new(""));
+        config_dir.join("profiles").join(format!("{}.json", name))
+    }
 
-    pub fn save_to_file(&self) -> Result<(), Box<dyn Error>> {
-        let json = serde_json::to_string_pretty(self)?;
-        fs::write(Self::CONFIG_FILE_NAME, json)?;
-        println!("App config saved to {}", Self::CONFIG_FILE_NAME);
+    #[instrument(name = "config_save", skip(self), fields(path = %self.config_path.display()))]
+    pub fn save_to_file(&self) -> Result<(), ConfigError> {
+        let path = &self.config_path;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = if self.overrides.is_empty() {
+            match ConfigFormat::from_path(path) {
+                ConfigFormat::Json => serde_json::to_string_pretty(self)?,
+                ConfigFormat::Toml => toml::to_string_pretty(self)?,
+            }
+        } else {
+            // Overridden fields are saved with the value they had in the file
+            let mut value = serde_json::to_value(self)?;
+            // Backwards, so a key overridden twice gets the file value back
+            for config_override in self.overrides.iter().rev() {
+                overrides::restore_original(&mut value, config_override);
+            }
+            match ConfigFormat::from_path(path) {
+                ConfigFormat::Json => serde_json::to_string_pretty(&value)?,
+                ConfigFormat::Toml => toml::to_string_pretty(&value)?,
+            }
+        };
+
+        // Write to a sibling temp file and rename over the target so a crash
+        // mid-write can never leave a truncated config behind
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, path)?;
+        debug!("App config saved to {}", path.display());
         Ok(())
     }
 
-    pub fn load_from_file() -> Self {
-        if Path::new(Self::CONFIG_FILE_NAME).exists() {
-            match fs::read_to_string(Self::CONFIG_FILE_NAME) {
-                Ok(json) => match serde_json::from_str(&json) {
-                    Ok(config) => {
-                        println!("App config loaded from {}", Self::CONFIG_FILE_NAME);
-                        return config;
+    pub fn load_from_file(path: &Path, profile: Option<String>) -> Self {
+        let _span = info_span!("config_load", path = %path.display()).entered();
+        info!("Using app config file {}", path.display());
+        if path == Self::config_file_path() {
+            Self::migrate_legacy_file(path);
+        }
+
+        let mut config = if path.exists() {
+            match Self::parse_file(path) {
+                Ok(config) => {
+                    info!("App config loaded from {}", path.display());
+                    config
+                }
+                Err(e) => {
+                    error!("Failed to load app config file: {}", e);
+                    let mut failure = LoadFailure::new(path, &e, None);
+                    failure.preserved = Self::preserve_broken_file(path);
+                    warn!("Starting in safe mode with the defaults, nothing is saved until the config file loads or the defaults are accepted");
+                    Self {
+                        load_failure: Some(failure),
+                        ..Self::default()
                     }
-                    Err(e) => eprintln!("Failed to parse app config file: {}", e),
-                },
-                Err(e) => eprintln!("Failed to read app config file: {}", e),
+                }
             }
         } else {
-            println!("No app config file found, using defaults");
+            info!("No app config file found, using defaults");
+            Self::default()
+        };
+        config.config_path = path.to_path_buf();
+        config.profile = profile;
+        config
+    }
+
+    /// Applies `--set`/environment overrides on top of this config. The result
+    /// is validated like a loaded file; on error the config is left unchanged.
+    pub fn apply_overrides(&mut self, mut overrides: Vec<ConfigOverride>) -> Result<(), ConfigError> {
+        if overrides.is_empty() {
+            return Ok(());
+        }
+
+        let mut value = serde_json::to_value(&*self)?;
+        for config_override in &mut overrides {
+            overrides::apply_override(&mut value, config_override)?;
+        }
+        let mut config: Self = serde_json::from_value(value)
+            .map_err(ConfigError::OverrideValue)?;
+        config.validate()?;
+
+        for config_override in &overrides {
+            if overrides::is_sensitive(&config_override.key) {
+                info!("Config override: {} = <redacted>", config_override.key);
+            } else {
+                info!("Config override: {} = {}", config_override.key, config_override.value);
+            }
+        }
+        config.config_path = std::mem::take(&mut self.config_path);
+        config.profile = self.profile.take();
+        config.load_failure = self.load_failure.take();
+        config.overrides = overrides;
+        *self = config;
+        Ok(())
+    }
+
+    /// Whether the field at dotted path `key` is currently overridden.
+    pub fn is_overridden(&self, key: &str) -> bool {
+        self.overrides.iter().any(|o| o.key == key)
+    }
+
+    /// Reads, parses and validates a config file.
+    pub fn parse_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        // Both formats are parsed into a JSON value so one migration chain serves both
+        let value = match ConfigFormat::from_path(path) {
+            ConfigFormat::Json => serde_json::from_str(&contents)?,
+            ConfigFormat::Toml => serde_json::to_value(toml::from_str::<toml::Value>(&contents)?)?,
+        };
+        let value = migrate::migrate(value)?;
+        let config: Self = serde_json::from_value(value)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Moves a config file we can't understand out of the way, to
+    /// `<name>.broken-<local time>`, so saving the defaults can't destroy it.
+    fn preserve_broken_file(path: &Path) -> Option<PathBuf> {
+        let mut broken_path = path.to_path_buf().into_os_string();
+        broken_path.push(format!(".broken-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+        let broken_path = PathBuf::from(broken_path);
+        match fs::rename(path, &broken_path) {
+            Ok(()) => {
+                warn!("Moved {} to {}", path.display(), broken_path.display());
+                Some(broken_path)
+            }
+            Err(e) => {
+                error!("Failed to move the broken app config file aside: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn zone_map_preset(&self, name: &str) -> Option<&ZoneMapPreset> {
+        self.zone_map_presets.iter().find(|preset| preset.name == name)
+    }
+
+    /// Oscillator frequency of the device at `address`
+    pub fn oscillator_hz(&self, address: &str) -> f64 {
+        self.device_oscillators
+            .iter()
+            .find(|oscillator| oscillator.address.eq_ignore_ascii_case(address))
+            .map_or(DEFAULT_OSCILLATOR_HZ, |oscillator| oscillator.hz)
+    }
+
+    pub fn set_oscillator_hz(&mut self, address: &str, hz: f64) {
+        match self
+            .device_oscillators
+            .iter_mut()
+            .find(|oscillator| oscillator.address.eq_ignore_ascii_case(address))
+        {
+            Some(oscillator) => oscillator.hz = hz,
+            None => self.device_oscillators.push(DeviceOscillator {
+                address: address.to_string(),
+                hz,
+            }),
+        }
+    }
+
+    /// Hardware template of the device at `address`, if one was set
+    pub fn hardware_template(&self, address: &str) -> Option<&'static HardwareTemplate> {
+        self.device_hardware
+            .iter()
+            .find(|hardware| hardware.address.eq_ignore_ascii_case(address))
+            .and_then(|hardware| hardware_template(&hardware.template))
+    }
+
+    pub fn set_hardware_template(&mut self, address: &str, template: &HardwareTemplate) {
+        match self
+            .device_hardware
+            .iter_mut()
+            .find(|hardware| hardware.address.eq_ignore_ascii_case(address))
+        {
+            Some(hardware) => hardware.template = template.name.to_string(),
+            None => self.device_hardware.push(DeviceHardware {
+                address: address.to_string(),
+                template: template.name.to_string(),
+            }),
+        }
+    }
+
+    /// What the device at `address` stores of a zone config
+    pub fn zone_limits(&self, address: &str) -> ZoneLimits {
+        self.hardware_template(address).map_or(FIRMWARE_LIMITS, |template| template.limits)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        validate_zone_map(&self.zone_map, NUM_ZONES)?;
+        validate_zone_groups(&self.zone_groups, NUM_ZONES)?;
+        for (index, preset) in self.zone_map_presets.iter().enumerate() {
+            if preset.name.trim().is_empty() {
+                return Err(ConfigError::Invalid("Zone map presets need a name".to_string()));
+            }
+            if self.zone_map_presets[..index].iter().any(|other| other.name == preset.name) {
+                return Err(ConfigError::Invalid(format!("Zone map preset `{}` is defined twice", preset.name)));
+            }
+            validate_zone_map(&preset.zone_map, NUM_ZONES)
+                .map_err(|e| ConfigError::Invalid(format!("Zone map preset `{}`: {}", preset.name, e)))?;
+        }
+        if let Some(oscillator) = self
+            .device_oscillators
+            .iter()
+            .find(|oscillator| !(MIN_OSCILLATOR_HZ..=MAX_OSCILLATOR_HZ).contains(&oscillator.hz))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "The oscillator of device {} must run between {} and {} Hz",
+                oscillator.address, MIN_OSCILLATOR_HZ, MAX_OSCILLATOR_HZ
+            )));
+        }
+        if let Some(hardware) = self
+            .device_hardware
+            .iter()
+            .find(|hardware| hardware_template(&hardware.template).is_none())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "Device {} has hardware template `{}`, which doesn't exist",
+                hardware.address, hardware.template
+            )));
+        }
+        if !(0.0..=1.0).contains(&self.exponential_alpha) {
+            return Err(ConfigError::Invalid("exponential_alpha must be between 0 and 1".to_string()));
+        }
+        self.normalization.validate().map_err(ConfigError::Invalid)?;
+        if !(MIN_PLOT_DURATION_SECS..=MAX_PLOT_DURATION_SECS).contains(&self.plot_duration_secs) {
+            return Err(ConfigError::Invalid(format!(
+                "plot_duration_secs must be between {} and {}",
+                MIN_PLOT_DURATION_SECS, MAX_PLOT_DURATION_SECS
+            )));
+        }
+        if self.plot_memory_mb <= 0.0 {
+            return Err(ConfigError::Invalid("plot_memory_mb must be positive".to_string()));
+        }
+        self.midi.note_config.validate().map_err(ConfigError::Invalid)?;
+        self.midi.looper.validate().map_err(ConfigError::Invalid)?;
+        self.midi.metronome.validate().map_err(ConfigError::Invalid)?;
+        self.midi.lfo.validate().map_err(ConfigError::Invalid)?;
+        self.midi.update.validate().map_err(ConfigError::Invalid)?;
+        self.midi.cc_ramp.validate().map_err(ConfigError::Invalid)?;
+        self.midi.patches.validate().map_err(ConfigError::Invalid)?;
+        self.midi.morph.validate().map_err(ConfigError::Invalid)?;
+        self.midi.peak.validate().map_err(ConfigError::Invalid)?;
+        self.midi.pressure.validate().map_err(ConfigError::Invalid)?;
+        self.midi.mpe.validate().map_err(ConfigError::Invalid)?;
+        if self.midi.sustain_toggle.is_some_and(|binding| binding.zone >= NUM_ZONES) {
+            return Err(ConfigError::Invalid("midi.sustain_toggle zone is out of range".to_string()));
+        }
+        self.gesture.validate().map_err(ConfigError::Invalid)?;
+        self.osc.validate().map_err(ConfigError::Invalid)?;
+        self.artnet.validate().map_err(ConfigError::Invalid)?;
+        self.synth.validate().map_err(ConfigError::Invalid)?;
+        self.gamepad.validate().map_err(ConfigError::Invalid)?;
+        self.keys.validate().map_err(ConfigError::Invalid)?;
+        self.selftest.validate().map_err(ConfigError::Invalid)?;
+        self.auto_gain.validate().map_err(ConfigError::Invalid)?;
+        self.drift.validate().map_err(ConfigError::Invalid)?;
+        self.watchdog.validate().map_err(ConfigError::Invalid)?;
+        self.batching.validate().map_err(ConfigError::Invalid)?;
+        self.startup.validate().map_err(ConfigError::Invalid)?;
+        self.takes.validate().map_err(ConfigError::Invalid)?;
+        self.markers.validate().map_err(ConfigError::Invalid)?;
+        self.ble.validate().map_err(ConfigError::Invalid)?;
+        Ok(())
+    }
+
+    /// Moves a config left in the working directory by older versions to the
+    /// per-user location, unless a config already exists there.
+    fn migrate_legacy_file(path: &Path) {
+        let Some(legacy) = [Self::LEGACY_CONFIG_FILE_NAME, Self::LEGACY_MIDI_CONFIG_FILE_NAME]
+            .iter()
+            .map(Path::new)
+            .find(|legacy| legacy.exists())
+        else {
+            return;
+        };
+        if path.exists() || legacy == path {
+            return;
+        }
+
+        let result = (|| {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            // rename fails across filesystems, so fall back to copying
+            fs::rename(legacy, path).or_else(|_| fs::copy(legacy, path).map(|_| ()))
+        })();
+        match result {
+            Ok(()) => info!(
+                "Migrated app config from {} to {}",
+                legacy.display(),
+                path.display()
+            ),
+            Err(e) => error!("Failed to migrate legacy app config file: {}", e),
         }
-        Self::default()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::midi::{MidiOutputMethod, MusicalScale};
+    use tempfile::TempDir;
+
+    /// `AppConfig` has no `PartialEq`, so configs are compared as JSON
+    fn as_json(config: &AppConfig) -> serde_json::Value {
+        serde_json::to_value(config).unwrap()
+    }
+
+    fn customized() -> AppConfig {
+        let mut config = AppConfig::default();
+        config.midi.method = MidiOutputMethod::Notes;
+        config.midi.note_config.scale = MusicalScale::WholeTone;
+        config.midi.note_config.base_note = 48;
+        config.midi.control_change_config.control_slope = 35.5;
+        config.midi.output_port = Some("Synth In".to_string());
+        config.zone_map = vec![7, 6, 5, 4, 3, 2, 1, 0];
+        config.exponential_alpha = 0.002;
+        config
+    }
+
+    #[test]
+    fn toml_round_trips_the_defaults() {
+        let config = AppConfig::default();
+        let parsed: AppConfig = toml::from_str(&toml::to_string_pretty(&config).unwrap()).unwrap();
+        assert_eq!(as_json(&parsed), as_json(&config));
+    }
+
+    #[test]
+    fn toml_round_trips_enums_and_nested_midi_config() {
+        let config = customized();
+        let parsed: AppConfig = toml::from_str(&toml::to_string_pretty(&config).unwrap()).unwrap();
+        assert_eq!(parsed.midi.method, MidiOutputMethod::Notes);
+        assert_eq!(parsed.midi.note_config.scale, MusicalScale::WholeTone);
+        assert_eq!(as_json(&parsed), as_json(&config));
+    }
+
+    #[test]
+    fn toml_file_saves_and_loads_back() {
+        let dir = TempDir::new().unwrap();
+        let config = AppConfig {
+            config_path: dir.path().join("config.toml"),
+            ..customized()
+        };
+        config.save_to_file().unwrap();
+        let contents = fs::read_to_string(&config.config_path).unwrap();
+        assert!(contents.contains("scale = \"WholeTone\""), "{}", contents);
+
+        let loaded = AppConfig::parse_file(&config.config_path).unwrap();
+        assert_eq!(as_json(&loaded), as_json(&config));
+    }
+
+    #[test]
+    fn toml_missing_fields_take_their_defaults() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(
+            &path,
+            "exponential_alpha = 0.004\n\n\
+             [midi]\n\
+             method = \"Notes\"\n\n\
+             [midi.control_change_config]\n\
+             base_control_number = 20\n\
+             control_slope = 20.0\n\n\
+             [midi.note_config]\n\
+             base_note = 60\n\
+             threshold = 0.1\n\
+             velocity_slope = 100.0\n\
+             scale = \"Blues\"\n",
+        )
+        .unwrap();
+
+        let loaded = AppConfig::parse_file(&path).unwrap();
+        let mut expected = AppConfig {
+            exponential_alpha: 0.004,
+            ..AppConfig::default()
+        };
+        expected.midi.method = MidiOutputMethod::Notes;
+        expected.midi.control_change_config.base_control_number = 20;
+        expected.midi.note_config.scale = MusicalScale::Blues;
+        assert_eq!(as_json(&loaded), as_json(&expected));
+    }
+
+    #[test]
+    fn format_follows_the_extension() {
+        assert_eq!(ConfigFormat::from_path(Path::new("config.toml")), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::from_path(Path::new("CONFIG.TOML")), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::from_path(Path::new("config.json")), ConfigFormat::Json);
+        assert_eq!(ConfigFormat::from_path(Path::new("config")), ConfigFormat::Json);
+    }
+
+    #[test]
+    fn oscillators_are_looked_up_by_address_in_any_case() {
+        let mut config = AppConfig::default();
+        assert_eq!(config.oscillator_hz("AA:BB:CC:DD:EE:FF"), DEFAULT_OSCILLATOR_HZ);
+        config.set_oscillator_hz("aa:bb:cc:dd:ee:ff", 8_000_000.0);
+        config.set_oscillator_hz("AA:BB:CC:DD:EE:FF", 12_000_000.0);
+        assert_eq!(config.device_oscillators.len(), 1);
+        assert_eq!(config.oscillator_hz("Aa:bB:cc:DD:ee:FF"), 12_000_000.0);
+        assert_eq!(config.oscillator_hz("11:22:33:44:55:66"), DEFAULT_OSCILLATOR_HZ);
+    }
+
+    #[test]
+    fn oscillators_must_run_within_the_limits() {
+        let mut config = AppConfig::default();
+        for hz in [MIN_OSCILLATOR_HZ, DEFAULT_OSCILLATOR_HZ, MAX_OSCILLATOR_HZ] {
+            config.set_oscillator_hz("aa:bb", hz);
+            assert!(config.validate().is_ok(), "{} Hz", hz);
+        }
+        for hz in [0.0, MIN_OSCILLATOR_HZ - 1.0, MAX_OSCILLATOR_HZ * 2.0, f64::NAN] {
+            config.set_oscillator_hz("aa:bb", hz);
+            assert!(config.validate().is_err(), "{} Hz", hz);
+        }
+    }
+
+    #[test]
+    fn hardware_is_recorded_by_address_in_any_case() {
+        let mut config = AppConfig::default();
+        assert!(config.hardware_template("AA:BB").is_none());
+        assert_eq!(config.zone_limits("AA:BB"), FIRMWARE_LIMITS);
+        let rev_b = hardware_template("rev-b").unwrap();
+        config.set_hardware_template("aa:bb", hardware_template("rev-a").unwrap());
+        config.set_hardware_template("AA:BB", rev_b);
+        assert_eq!(config.device_hardware.len(), 1);
+        assert_eq!(config.hardware_template("Aa:Bb").unwrap().name, "rev-b");
+        assert_eq!(config.zone_limits("aa:bb"), rev_b.limits);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn unknown_hardware_templates_fail_validation() {
+        let mut config = AppConfig::default();
+        config.device_hardware.push(DeviceHardware {
+            address: "aa:bb".to_string(),
+            template: "rev-z".to_string(),
+        });
+        assert!(config.validate().is_err());
+        assert_eq!(config.zone_limits("aa:bb"), FIRMWARE_LIMITS);
+    }
+}
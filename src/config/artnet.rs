@@ -0,0 +1,106 @@
+use super::zones::NUM_ZONES;
+use serde::{Deserialize, Serialize};
+
+/// Highest DMX channel number in a universe
+pub const DMX_CHANNELS: u16 = 512;
+
+/// How one output zone drives DMX channels
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArtNetZone {
+    /// DMX channels 1-512 set to this zone's level. A channel driven by
+    /// several zones follows the highest of them.
+    pub channels: Vec<u16>,
+    /// Level per unit of normalized magnitude, capped at full brightness
+    pub scale: f64,
+}
+
+impl Default for ArtNetZone {
+    fn default() -> Self {
+        Self {
+            channels: Vec::new(),
+            scale: 5.0,
+        }
+    }
+}
+
+/// Art-Net lighting output. One DMX universe is sent at a fixed frame rate,
+/// repeating each zone's last level while no samples arrive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArtNetConfig {
+    pub enabled: bool,
+    /// Node host name or IP address, or a broadcast address
+    pub host: String,
+    pub port: u16,
+    /// Port address of the universe: net 0-127, subnet 0-15, universe 0-15
+    pub net: u8,
+    pub subnet: u8,
+    pub universe: u8,
+    /// DMX frames per second, 30 to 44
+    pub frame_rate_hz: f64,
+    /// Send all channels at zero when output stops, so lights don't stay on
+    pub blackout_on_shutdown: bool,
+    /// One entry per output zone
+    pub zones: Vec<ArtNetZone>,
+}
+
+impl ArtNetConfig {
+    /// The 15 bit Art-Net port address: net, then subnet and universe nibbles.
+    pub fn port_address(&self) -> u16 {
+        (u16::from(self.net) << 8) | (u16::from(self.subnet) << 4) | u16::from(self.universe)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.net > 127 {
+            return Err("artnet.net must be between 0 and 127".to_string());
+        }
+        if self.subnet > 15 || self.universe > 15 {
+            return Err("artnet.subnet and artnet.universe must be between 0 and 15".to_string());
+        }
+        if !(30.0..=44.0).contains(&self.frame_rate_hz) {
+            return Err("artnet.frame_rate_hz must be between 30 and 44".to_string());
+        }
+        if self.zones.len() != NUM_ZONES {
+            return Err(format!(
+                "artnet.zones has {} entries, expected {}",
+                self.zones.len(),
+                NUM_ZONES
+            ));
+        }
+        for (zone, mapping) in self.zones.iter().enumerate() {
+            if let Some(channel) = mapping.channels.iter().find(|&&c| !(1..=DMX_CHANNELS).contains(&c)) {
+                return Err(format!(
+                    "artnet.zones[{}] uses channel {}, channels are 1 to {}",
+                    zone, channel, DMX_CHANNELS
+                ));
+            }
+            if mapping.scale < 0.0 {
+                return Err(format!("artnet.zones[{}].scale must not be negative", zone));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for ArtNetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "255.255.255.255".to_string(),
+            port: 6454,
+            net: 0,
+            subnet: 0,
+            universe: 0,
+            frame_rate_hz: 40.0,
+            blackout_on_shutdown: true,
+            // Zone N on channel N + 1
+            zones: (1..=NUM_ZONES as u16)
+                .map(|channel| ArtNetZone {
+                    channels: vec![channel],
+                    ..ArtNetZone::default()
+                })
+                .collect(),
+        }
+    }
+}
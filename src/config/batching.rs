@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// The most samples batched into one notification: as many extended packets
+/// as fit its 244 bytes at the largest ATT MTU, 247
+pub const MAX_BATCH_SAMPLES: u8 = 15;
+
+/// The hint written to the firmware's batching characteristic: how many
+/// samples it sends per notification. More batching lowers the BLE overhead
+/// per sample but holds samples back longer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchingConfig {
+    /// Raises the hint while the host is overloaded and lowers it while it
+    /// idles; off leaves the hint the device holds unless `manual_samples`
+    /// is set
+    pub adaptive: bool,
+    /// Bounds of the adapted hint
+    pub min_samples: u8,
+    pub max_samples: u8,
+    /// Written instead of adapting, while set
+    pub manual_samples: Option<u8>,
+    /// Load is measured over windows this long
+    pub window_secs: f64,
+    /// Share of a window spent processing samples above which the host is
+    /// overloaded; so is a window that dropped samples
+    pub overload_percent: f64,
+    /// Share below which it idles
+    pub idle_percent: f64,
+    /// Windows in a row overloaded or idle before the hint changes
+    pub sustain_windows: u32,
+}
+
+impl BatchingConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let samples = 1..=MAX_BATCH_SAMPLES;
+        if !samples.contains(&self.min_samples) || !samples.contains(&self.max_samples) {
+            return Err(format!("batching.min_samples and batching.max_samples must be between 1 and {}", MAX_BATCH_SAMPLES));
+        }
+        if self.min_samples > self.max_samples {
+            return Err("batching.min_samples must not be more than batching.max_samples".to_string());
+        }
+        if self.manual_samples.is_some_and(|manual| !samples.contains(&manual)) {
+            return Err(format!("batching.manual_samples must be between 1 and {}", MAX_BATCH_SAMPLES));
+        }
+        if !(0.25..=60.0).contains(&self.window_secs) {
+            return Err("batching.window_secs must be between 0.25 and 60".to_string());
+        }
+        if !(0.0..=100.0).contains(&self.overload_percent) || !(0.0..=100.0).contains(&self.idle_percent) {
+            return Err("batching.overload_percent and batching.idle_percent must be between 0 and 100".to_string());
+        }
+        // The gap between them is the hysteresis that keeps the hint from flapping
+        if self.idle_percent >= self.overload_percent {
+            return Err("batching.idle_percent must be less than batching.overload_percent".to_string());
+        }
+        if !(1..=100).contains(&self.sustain_windows) {
+            return Err("batching.sustain_windows must be between 1 and 100".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_secs_f64(self.window_secs)
+    }
+}
+
+impl Default for BatchingConfig {
+    fn default() -> Self {
+        Self {
+            adaptive: false,
+            min_samples: 1,
+            max_samples: 8,
+            manual_samples: None,
+            window_secs: 1.0,
+            overload_percent: 50.0,
+            idle_percent: 10.0,
+            sustain_windows: 3,
+        }
+    }
+}
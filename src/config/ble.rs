@@ -0,0 +1,75 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
+
+/// The UUIDs of the stock firmware
+pub const STOCK_SERVICE_UUID: Uuid = Uuid::from_u128(0x64696c640000100080000000cafebabe);
+pub const STOCK_CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0x6f6e69630000100080000000cafebabe);
+pub const STOCK_CONFIG_CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0x6f6e69620000100080000000cafebabe);
+pub const STOCK_BATCHING_CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0x6f6e69610000100080000000cafebabe);
+
+/// The GATT UUIDs the device is found and talked to by, for firmware forks
+/// that changed them. Read every time the device is connected.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BleConfig {
+    /// Advertised by the device, marks it in `scan`
+    #[serde(with = "uuid_string")]
+    pub service_uuid: Uuid,
+    /// Notifies the samples
+    #[serde(with = "uuid_string")]
+    pub characteristic_uuid: Uuid,
+    /// Holds the zone configs
+    #[serde(with = "uuid_string")]
+    pub config_characteristic_uuid: Uuid,
+    /// Takes the samples to batch per notification; firmware without it
+    /// sends one per notification
+    #[serde(with = "uuid_string")]
+    pub batching_characteristic_uuid: Uuid,
+}
+
+impl BleConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let characteristics = [
+            self.characteristic_uuid,
+            self.config_characteristic_uuid,
+            self.batching_characteristic_uuid,
+        ];
+        if (0..characteristics.len()).any(|i| characteristics[i + 1..].contains(&characteristics[i])) {
+            return Err(
+                "ble.characteristic_uuid, ble.config_characteristic_uuid and ble.batching_characteristic_uuid must differ"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn is_stock(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Default for BleConfig {
+    fn default() -> Self {
+        Self {
+            service_uuid: STOCK_SERVICE_UUID,
+            characteristic_uuid: STOCK_CHARACTERISTIC_UUID,
+            config_characteristic_uuid: STOCK_CONFIG_CHARACTERISTIC_UUID,
+            batching_characteristic_uuid: STOCK_BATCHING_CHARACTERISTIC_UUID,
+        }
+    }
+}
+
+/// A UUID as its canonical hyphenated string
+mod uuid_string {
+    use super::*;
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(uuid: &Uuid, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&uuid.hyphenated())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Uuid, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        Uuid::parse_str(text.trim()).map_err(|e| D::Error::custom(format!("`{}` is not a UUID: {}", text, e)))
+    }
+}
@@ -1,16 +1,83 @@
 use btleplug::api::{Characteristic, Peripheral as PeripheralTrait};
 use btleplug::platform::Peripheral;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use thiserror::Error;
+use tracing::warn;
 
 #[derive(Error, Debug)]
 pub enum DeviceConfigError {
     #[error("Data too short")]
     DataTooShort,
+    #[error("{0}")]
+    UnexpectedSize(SizeMismatch),
     #[error("BLE error: {0}")]
     BleError(#[from] btleplug::Error),
 }
 
-#[derive(Clone, Copy, Debug)]
+/// Largest `cycle_count_begin` and `cycle_count_end` the firmware accepts
+pub const MAX_CYCLE_COUNT: u32 = 100000;
+/// Largest comparator threshold the firmware accepts
+pub const MAX_COMP_THRESH: u32 = 10000;
+
+/// The largest zone config values a device stores; the firmware clamps
+/// anything above them without telling
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoneLimits {
+    pub max_cycle_count: u32,
+    pub max_comp_thresh: u32,
+}
+
+/// What any firmware accepts. A hardware template may set lower limits,
+/// never higher ones.
+pub const FIRMWARE_LIMITS: ZoneLimits = ZoneLimits {
+    max_cycle_count: MAX_CYCLE_COUNT,
+    max_comp_thresh: MAX_COMP_THRESH,
+};
+
+impl ZoneLimits {
+    /// Whether these limits are within `FIRMWARE_LIMITS`
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_cycle_count > MAX_CYCLE_COUNT || self.max_comp_thresh > MAX_COMP_THRESH {
+            return Err(format!(
+                "limits must be at most {} cycles and a threshold of {}",
+                MAX_CYCLE_COUNT, MAX_COMP_THRESH
+            ));
+        }
+        Ok(())
+    }
+}
+/// Oscillator frequency assumed for a device without one in
+/// `AppConfig::device_oscillators`
+pub const DEFAULT_OSCILLATOR_HZ: f64 = 16_000_000.0;
+pub const MIN_OSCILLATOR_HZ: f64 = 1_000.0;
+pub const MAX_OSCILLATOR_HZ: f64 = 1_000_000_000.0;
+
+/// Microseconds `cycles` of an oscillator at `oscillator_hz` take
+pub fn cycles_to_micros(cycles: u32, oscillator_hz: f64) -> f64 {
+    f64::from(cycles) * 1_000_000.0 / oscillator_hz
+}
+
+/// The cycle count of an oscillator at `oscillator_hz` nearest to `micros`,
+/// halves rounded away from zero, within `0..=MAX_CYCLE_COUNT`
+pub fn micros_to_cycles(micros: f64, oscillator_hz: f64) -> u32 {
+    let cycles = (micros * oscillator_hz / 1_000_000.0).round();
+    if cycles.is_nan() {
+        return 0;
+    }
+    cycles.clamp(0.0, f64::from(MAX_CYCLE_COUNT)) as u32
+}
+
+/// The oscillator frequency of the device at `address`, for showing its
+/// cycle counts as time. The cycle counts stay what is written.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceOscillator {
+    pub address: String,
+    pub hz: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct DildonicaZoneConfig {
     pub enabled: bool,
     pub midi_control: u8,
@@ -33,18 +100,158 @@ impl Default for DildonicaZoneConfig {
     }
 }
 
+/// Per-zone struct sizes a firmware might plausibly send, for telling a
+/// changed layout from a changed zone count
+const PLAUSIBLE_SIZES: [usize; 3] = [DildonicaZoneConfig::SIZE, DildonicaZoneConfig::LEGACY_SIZE, 24];
+
+/// A config blob whose length fits no layout this frontend reads, with the
+/// layout it most likely has
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SizeMismatch {
+    pub received: usize,
+    pub num_zones: usize,
+}
+
+impl SizeMismatch {
+    /// The zone count and per-zone size the blob divides into, preferring
+    /// the expected zone count and then the current struct size
+    pub fn guess(&self) -> Option<(usize, usize)> {
+        let matching = PLAUSIBLE_SIZES
+            .iter()
+            .find(|&&size| size * self.num_zones == self.received)
+            .map(|&size| (self.num_zones, size));
+        matching.or_else(|| {
+            PLAUSIBLE_SIZES
+                .iter()
+                .find(|&&size| self.received > 0 && self.received.is_multiple_of(size))
+                .map(|&size| (self.received / size, size))
+        })
+    }
+}
+
+impl fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "device sent {} bytes", self.received)?;
+        let guess = self.guess();
+        if let Some((zones, size)) = guess {
+            write!(f, " = {}×{}", zones, size)?;
+        }
+        write!(f, "; this frontend expects {}×{}", self.num_zones, DildonicaZoneConfig::SIZE)?;
+        match guess {
+            Some((zones, _)) if zones != self.num_zones => write!(f, " — different zone count?"),
+            Some((_, size)) if size < DildonicaZoneConfig::SIZE => write!(f, " — firmware too old?"),
+            Some((_, size)) if size > DildonicaZoneConfig::SIZE => write!(f, " — firmware newer than the frontend?"),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The per-zone struct layout of a config blob, told apart by its length
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConfigLayout {
+    /// `DildonicaZoneConfig::SIZE` bytes with a low and high comparator threshold
+    #[default]
+    Current,
+    /// `DildonicaZoneConfig::LEGACY_SIZE` bytes of older firmware, with a
+    /// single comparator threshold
+    Legacy,
+}
+
+impl ConfigLayout {
+    pub fn all() -> &'static [ConfigLayout] {
+        &[ConfigLayout::Current, ConfigLayout::Legacy]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigLayout::Current => "Current",
+            ConfigLayout::Legacy => "Legacy",
+        }
+    }
+
+    /// Bytes per zone
+    pub fn size(&self) -> usize {
+        match self {
+            ConfigLayout::Current => DildonicaZoneConfig::SIZE,
+            ConfigLayout::Legacy => DildonicaZoneConfig::LEGACY_SIZE,
+        }
+    }
+
+    /// The layout of a blob of `len` bytes for `num_zones` zones
+    pub fn detect(len: usize, num_zones: usize) -> Result<Self, SizeMismatch> {
+        Self::all()
+            .iter()
+            .copied()
+            .find(|layout| layout.size() * num_zones == len)
+            .ok_or(SizeMismatch {
+                received: len,
+                num_zones,
+            })
+    }
+
+    pub fn parse(&self, bytes: &[u8]) -> Result<DildonicaZoneConfig, DeviceConfigError> {
+        match self {
+            ConfigLayout::Current => DildonicaZoneConfig::from_bytes(bytes),
+            ConfigLayout::Legacy => DildonicaZoneConfig::from_legacy_bytes(bytes),
+        }
+    }
+
+    pub fn encode(&self, config: DildonicaZoneConfig) -> Vec<u8> {
+        match self {
+            ConfigLayout::Current => config.to_bytes().to_vec(),
+            ConfigLayout::Legacy => config.to_legacy_bytes().to_vec(),
+        }
+    }
+}
+
 impl DildonicaZoneConfig {
     pub const SIZE: usize = 20; // 1 + 1 + 2 (padding) + 4 + 4 + 4 + 4 = 20 bytes (4-byte aligned)
+    /// Older firmware, before the comparator had hysteresis: 1 + 1 + 2
+    /// (padding) + 4 + 4 + 4
+    pub const LEGACY_SIZE: usize = 16;
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.validate_within(&FIRMWARE_LIMITS)
+    }
+
+    /// Whether a device with `limits` stores this config as it is
+    pub fn validate_within(&self, limits: &ZoneLimits) -> Result<(), String> {
+        if self.cycle_count_begin > limits.max_cycle_count || self.cycle_count_end > limits.max_cycle_count {
+            return Err(format!("cycle counts must be at most {}", limits.max_cycle_count));
+        }
+        if self.comp_thresh_lo > limits.max_comp_thresh || self.comp_thresh_hi > limits.max_comp_thresh {
+            return Err(format!("comparator thresholds must be at most {}", limits.max_comp_thresh));
+        }
+        Ok(())
+    }
+
+    /// This config with every value above `limits` lowered to it, as the
+    /// device would store it
+    pub fn clamped(self, limits: &ZoneLimits) -> Self {
+        Self {
+            cycle_count_begin: self.cycle_count_begin.min(limits.max_cycle_count),
+            cycle_count_end: self.cycle_count_end.min(limits.max_cycle_count),
+            comp_thresh_lo: self.comp_thresh_lo.min(limits.max_comp_thresh),
+            comp_thresh_hi: self.comp_thresh_hi.min(limits.max_comp_thresh),
+            ..self
+        }
+    }
 
-    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+    /// Values above `FIRMWARE_LIMITS` are clamped with a warning, so what is
+    /// written is what the device keeps; callers should have validated.
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let config = self.clamped(&FIRMWARE_LIMITS);
+        if config != self {
+            warn!("Zone config {:?} is beyond what the firmware stores, writing {:?}", self, config);
+        }
         let mut bytes = [0u8; Self::SIZE];
-        bytes[0] = self.enabled as u8;
-        bytes[1] = self.midi_control;
+        bytes[0] = config.enabled as u8;
+        bytes[1] = config.midi_control;
         // bytes[2..4] are padding for 4-byte alignment
-        bytes[4..8].copy_from_slice(&self.cycle_count_begin.to_le_bytes());
-        bytes[8..12].copy_from_slice(&self.cycle_count_end.to_le_bytes());
-        bytes[12..16].copy_from_slice(&self.comp_thresh_lo.to_le_bytes());
-        bytes[16..20].copy_from_slice(&self.comp_thresh_hi.to_le_bytes());
+        bytes[4..8].copy_from_slice(&config.cycle_count_begin.to_le_bytes());
+        bytes[8..12].copy_from_slice(&config.cycle_count_end.to_le_bytes());
+        bytes[12..16].copy_from_slice(&config.comp_thresh_lo.to_le_bytes());
+        bytes[16..20].copy_from_slice(&config.comp_thresh_hi.to_le_bytes());
         bytes
     }
 
@@ -63,42 +270,446 @@ impl DildonicaZoneConfig {
             comp_thresh_hi: u32::from_le_bytes(bytes[16..20].try_into().unwrap()),
         })
     }
+
+    /// The single threshold of the legacy layout is read into both
+    /// thresholds
+    pub fn from_legacy_bytes(bytes: &[u8]) -> Result<Self, DeviceConfigError> {
+        if bytes.len() < Self::LEGACY_SIZE {
+            return Err(DeviceConfigError::DataTooShort);
+        }
+
+        let comp_thresh = u32::from_le_bytes(bytes[12..16].try_into().unwrap());
+        Ok(Self {
+            enabled: bytes[0] != 0,
+            midi_control: bytes[1],
+            // Skip bytes[2..4] (padding)
+            cycle_count_begin: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            cycle_count_end: u32::from_le_bytes(bytes[8..12].try_into().unwrap()),
+            comp_thresh_lo: comp_thresh,
+            comp_thresh_hi: comp_thresh,
+        })
+    }
+
+    /// The legacy layout has one threshold, `comp_thresh_hi` is written
+    pub fn to_legacy_bytes(self) -> [u8; Self::LEGACY_SIZE] {
+        let mut bytes = [0u8; Self::LEGACY_SIZE];
+        bytes[..12].copy_from_slice(&self.to_bytes()[..12]);
+        bytes[12..16].copy_from_slice(&self.comp_thresh_hi.min(MAX_COMP_THRESH).to_le_bytes());
+        bytes
+    }
 }
 
-pub async fn read_zone_configs(
-    device: &Peripheral,
-    config_char: &Characteristic,
-    num_zones: usize,
-) -> Result<Vec<DildonicaZoneConfig>, DeviceConfigError> {
-    let data = device.read(config_char).await?;
-    let expected_size = DildonicaZoneConfig::SIZE * num_zones;
+/// A field that differs between two configs of a zone
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZoneConfigChange {
+    pub zone: usize,
+    /// Name of the `DildonicaZoneConfig` field
+    pub field: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+impl fmt::Display for ZoneConfigChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Zone {}: {} {} → {}", self.zone, self.field, self.old, self.new)
+    }
+}
 
-    if data.len() != expected_size {
-        return Err(DeviceConfigError::DataTooShort);
+impl DildonicaZoneConfig {
+    /// Every field by name, for comparing configs
+    fn fields(&self) -> [(&'static str, String); 6] {
+        [
+            ("enabled", self.enabled.to_string()),
+            ("midi_control", self.midi_control.to_string()),
+            ("cycle_count_begin", self.cycle_count_begin.to_string()),
+            ("cycle_count_end", self.cycle_count_end.to_string()),
+            ("comp_thresh_lo", self.comp_thresh_lo.to_string()),
+            ("comp_thresh_hi", self.comp_thresh_hi.to_string()),
+        ]
+    }
+}
+
+/// The fields that differ between `old` and `new`, zone by zone and in field
+/// order. Zones only one of them has are left out.
+pub fn diff_zone_configs(old: &[DildonicaZoneConfig], new: &[DildonicaZoneConfig]) -> Vec<ZoneConfigChange> {
+    let mut changes = Vec::new();
+    for (zone, (old, new)) in old.iter().zip(new).enumerate().filter(|(_, (old, new))| old != new) {
+        for ((field, old), (_, new)) in old.fields().into_iter().zip(new.fields()) {
+            if old != new {
+                changes.push(ZoneConfigChange { zone, field, old, new });
+            }
+        }
     }
+    changes
+}
+
+/// Reads the raw config blob of every zone
+pub async fn read_config_blob(device: &Peripheral, config_char: &Characteristic) -> Result<Vec<u8>, DeviceConfigError> {
+    Ok(device.read(config_char).await?)
+}
 
-    let mut configs = Vec::with_capacity(num_zones);
-    for i in 0..num_zones {
-        let start = i * DildonicaZoneConfig::SIZE;
-        let end = start + DildonicaZoneConfig::SIZE;
-        configs.push(DildonicaZoneConfig::from_bytes(&data[start..end])?);
+/// Splits a config blob into the configs of `num_zones` zones, in the layout
+/// its length says. A legacy layout is warned about, any other length is
+/// rejected with what was received.
+pub fn parse_zone_configs(
+    data: &[u8],
+    num_zones: usize,
+) -> Result<(Vec<DildonicaZoneConfig>, ConfigLayout), DeviceConfigError> {
+    let layout = ConfigLayout::detect(data.len(), num_zones).map_err(DeviceConfigError::UnexpectedSize)?;
+    if layout != ConfigLayout::Current {
+        warn!(
+            "{}; reading it in the {} layout",
+            SizeMismatch {
+                received: data.len(),
+                num_zones,
+            },
+            layout.name().to_lowercase()
+        );
     }
 
-    Ok(configs)
+    let configs = data
+        .chunks_exact(layout.size())
+        .map(|bytes| layout.parse(bytes))
+        .collect::<Result<_, _>>()?;
+    Ok((configs, layout))
 }
 
+/// Writes `configs` in `layout`, the one the device's configs were read in
 pub async fn write_zone_configs(
     device: &Peripheral,
     config_char: &Characteristic,
     configs: &[DildonicaZoneConfig],
+    layout: ConfigLayout,
 ) -> Result<(), DeviceConfigError> {
-    let mut data = Vec::with_capacity(DildonicaZoneConfig::SIZE * configs.len());
+    let mut data = Vec::with_capacity(layout.size() * configs.len());
     for config in configs {
-        data.extend_from_slice(&config.to_bytes());
+        data.extend_from_slice(&layout.encode(*config));
     }
 
     device
         .write(config_char, &data, btleplug::api::WriteType::WithResponse)
         .await?;
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const NUM_ZONES: usize = 8;
+
+    fn config(zone: u32) -> DildonicaZoneConfig {
+        DildonicaZoneConfig {
+            enabled: zone.is_multiple_of(2),
+            midi_control: zone as u8,
+            cycle_count_begin: 1000 + zone,
+            cycle_count_end: 9000 + zone,
+            comp_thresh_lo: 300,
+            comp_thresh_hi: 300,
+        }
+    }
+
+    fn blob(layout: ConfigLayout) -> Vec<u8> {
+        (0..NUM_ZONES as u32).flat_map(|zone| layout.encode(config(zone))).collect()
+    }
+
+    #[test]
+    fn the_length_picks_the_layout() {
+        assert_eq!(ConfigLayout::detect(8 * 20, NUM_ZONES), Ok(ConfigLayout::Current));
+        assert_eq!(ConfigLayout::detect(8 * 16, NUM_ZONES), Ok(ConfigLayout::Legacy));
+        for len in [0, 8 * 20 - 1, 8 * 20 + 1, 8 * 24, 4 * 20] {
+            assert_eq!(
+                ConfigLayout::detect(len, NUM_ZONES),
+                Err(SizeMismatch {
+                    received: len,
+                    num_zones: NUM_ZONES
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn both_layouts_parse_what_they_encode() {
+        for &layout in ConfigLayout::all() {
+            let (configs, detected) = parse_zone_configs(&blob(layout), NUM_ZONES).unwrap();
+            assert_eq!(detected, layout);
+            let expected: Vec<_> = (0..NUM_ZONES as u32).map(config).collect();
+            assert_eq!(configs, expected, "{} layout", layout.name());
+        }
+    }
+
+    #[test]
+    fn the_legacy_threshold_is_read_into_both() {
+        let mut bytes = config(0).to_legacy_bytes();
+        bytes[12..16].copy_from_slice(&1234u32.to_le_bytes());
+        let read = DildonicaZoneConfig::from_legacy_bytes(&bytes).unwrap();
+        assert_eq!((read.comp_thresh_lo, read.comp_thresh_hi), (1234, 1234));
+        let written = DildonicaZoneConfig {
+            comp_thresh_lo: 100,
+            comp_thresh_hi: 200,
+            ..config(0)
+        };
+        assert_eq!(written.to_legacy_bytes()[12..16], 200u32.to_le_bytes());
+    }
+
+    #[test]
+    fn an_unknown_length_is_rejected_with_what_arrived() {
+        let error = parse_zone_configs(&[0; 8 * 24], NUM_ZONES).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "device sent 192 bytes = 8×24; this frontend expects 8×20 — firmware newer than the frontend?"
+        );
+    }
+
+    #[test]
+    fn mismatch_messages() {
+        let message = |received| {
+            SizeMismatch {
+                received,
+                num_zones: NUM_ZONES,
+            }
+            .to_string()
+        };
+        assert_eq!(
+            message(128),
+            "device sent 128 bytes = 8×16; this frontend expects 8×20 — firmware too old?"
+        );
+        assert_eq!(
+            message(80),
+            "device sent 80 bytes = 4×20; this frontend expects 8×20 — different zone count?"
+        );
+        assert_eq!(message(7), "device sent 7 bytes; this frontend expects 8×20");
+        assert_eq!(message(0), "device sent 0 bytes; this frontend expects 8×20");
+    }
+
+    #[test]
+    fn the_guess_prefers_the_expected_zone_count() {
+        // 160 bytes is 8×20 but also 10×16
+        let mismatch = SizeMismatch {
+            received: 160,
+            num_zones: NUM_ZONES,
+        };
+        assert_eq!(mismatch.guess(), Some((8, 20)));
+        let mismatch = SizeMismatch {
+            received: 240,
+            num_zones: NUM_ZONES,
+        };
+        assert_eq!(mismatch.guess(), Some((12, 20)));
+    }
+
+    #[test]
+    fn cycle_counts_convert_to_microseconds() {
+        assert_eq!(cycles_to_micros(16, DEFAULT_OSCILLATOR_HZ), 1.0);
+        assert_eq!(cycles_to_micros(1, DEFAULT_OSCILLATOR_HZ), 0.0625);
+        assert_eq!(cycles_to_micros(MAX_CYCLE_COUNT, DEFAULT_OSCILLATOR_HZ), 6250.0);
+        assert_eq!(cycles_to_micros(1000, 1_000_000.0), 1000.0);
+        assert_eq!(micros_to_cycles(1.0, DEFAULT_OSCILLATOR_HZ), 16);
+        assert_eq!(micros_to_cycles(6250.0, DEFAULT_OSCILLATOR_HZ), MAX_CYCLE_COUNT);
+    }
+
+    #[test]
+    fn every_cycle_count_survives_the_round_trip() {
+        for hz in [DEFAULT_OSCILLATOR_HZ, 12_345_678.0, MIN_OSCILLATOR_HZ, MAX_OSCILLATOR_HZ] {
+            for cycles in 0..=MAX_CYCLE_COUNT {
+                let micros = cycles_to_micros(cycles, hz);
+                assert_eq!(micros_to_cycles(micros, hz), cycles, "{} cycles at {} Hz", cycles, hz);
+            }
+        }
+    }
+
+    #[test]
+    fn microseconds_round_to_the_nearest_cycle() {
+        let cycle = cycles_to_micros(1, DEFAULT_OSCILLATOR_HZ);
+        assert_eq!(micros_to_cycles(100.0 * cycle + 0.49 * cycle, DEFAULT_OSCILLATOR_HZ), 100);
+        assert_eq!(micros_to_cycles(100.0 * cycle + 0.51 * cycle, DEFAULT_OSCILLATOR_HZ), 101);
+        // Halves away from zero, not to even
+        for (halves, cycles) in [(1.0, 1), (3.0, 2), (5.0, 3), (201.0, 101)] {
+            assert_eq!(micros_to_cycles(halves * cycle / 2.0, DEFAULT_OSCILLATOR_HZ), cycles);
+        }
+    }
+
+    #[test]
+    fn microseconds_out_of_range_are_clamped() {
+        for micros in [-1.0, -0.0, f64::NEG_INFINITY, f64::NAN] {
+            assert_eq!(micros_to_cycles(micros, DEFAULT_OSCILLATOR_HZ), 0, "{} µs", micros);
+        }
+        for micros in [6250.1, 1e12, f64::INFINITY] {
+            assert_eq!(micros_to_cycles(micros, DEFAULT_OSCILLATOR_HZ), MAX_CYCLE_COUNT, "{} µs", micros);
+        }
+    }
+
+    /// A config at `cycles` and `thresholds`, for the limit checks
+    fn at(cycles: (u32, u32), thresholds: (u32, u32)) -> DildonicaZoneConfig {
+        DildonicaZoneConfig {
+            cycle_count_begin: cycles.0,
+            cycle_count_end: cycles.1,
+            comp_thresh_lo: thresholds.0,
+            comp_thresh_hi: thresholds.1,
+            ..config(1)
+        }
+    }
+
+    #[test]
+    fn the_firmware_limits_are_the_largest_allowed() {
+        assert_eq!(FIRMWARE_LIMITS.max_cycle_count, 100_000);
+        assert_eq!(FIRMWARE_LIMITS.max_comp_thresh, 10_000);
+        assert!(FIRMWARE_LIMITS.validate().is_ok());
+        let lower = ZoneLimits {
+            max_cycle_count: 50_000,
+            max_comp_thresh: 4095,
+        };
+        assert!(lower.validate().is_ok());
+        for limits in [
+            ZoneLimits {
+                max_cycle_count: MAX_CYCLE_COUNT + 1,
+                ..FIRMWARE_LIMITS
+            },
+            ZoneLimits {
+                max_comp_thresh: MAX_COMP_THRESH + 1,
+                ..FIRMWARE_LIMITS
+            },
+        ] {
+            assert!(limits.validate().is_err(), "{:?}", limits);
+        }
+    }
+
+    #[test]
+    fn configs_are_valid_up_to_the_limits() {
+        let limits = ZoneLimits {
+            max_cycle_count: 50_000,
+            max_comp_thresh: 4095,
+        };
+        assert!(at((50_000, 50_000), (4095, 4095)).validate_within(&limits).is_ok());
+        for (cycles, thresholds) in [((50_001, 0), (0, 0)), ((0, 50_001), (0, 0))] {
+            let error = at(cycles, thresholds).validate_within(&limits).unwrap_err();
+            assert_eq!(error, "cycle counts must be at most 50000");
+        }
+        for (cycles, thresholds) in [((0, 0), (4096, 0)), ((0, 0), (0, 4096))] {
+            let error = at(cycles, thresholds).validate_within(&limits).unwrap_err();
+            assert_eq!(error, "comparator thresholds must be at most 4095");
+        }
+        // What those limits reject the firmware may still take
+        assert!(at((50_001, 50_001), (4096, 4096)).validate().is_ok());
+        assert!(at((MAX_CYCLE_COUNT + 1, 0), (0, 0)).validate().is_err());
+    }
+
+    #[test]
+    fn clamping_lowers_only_what_is_over() {
+        let limits = ZoneLimits {
+            max_cycle_count: 50_000,
+            max_comp_thresh: 4095,
+        };
+        let over = at((60_000, 40_000), (5000, 100));
+        assert_eq!(over.clamped(&limits), at((50_000, 40_000), (4095, 100)));
+        let within = at((1000, 10_000), (100, 4000));
+        assert_eq!(within.clamped(&limits), within);
+        assert_eq!(within.clamped(&FIRMWARE_LIMITS), within);
+    }
+
+    #[test]
+    fn bytes_over_the_firmware_limits_are_written_clamped() {
+        let over = at((u32::MAX, MAX_CYCLE_COUNT + 1), (MAX_COMP_THRESH + 1, u32::MAX));
+        let written = DildonicaZoneConfig::from_bytes(&over.to_bytes()).unwrap();
+        assert_eq!(written, over.clamped(&FIRMWARE_LIMITS));
+        assert_eq!(written, at((MAX_CYCLE_COUNT, MAX_CYCLE_COUNT), (MAX_COMP_THRESH, MAX_COMP_THRESH)));
+        let legacy = DildonicaZoneConfig::from_legacy_bytes(&over.to_legacy_bytes()).unwrap();
+        assert_eq!(legacy, written);
+    }
+
+    /// Enabled, controller 41, cycles 1000..9000, thresholds 300 and 400
+    const CONFIG_BYTES: [u8; DildonicaZoneConfig::SIZE] = [
+        0x01, 0x29, 0x00, 0x00, 0xE8, 0x03, 0x00, 0x00, 0x28, 0x23, 0x00, 0x00, 0x2C, 0x01, 0x00, 0x00, 0x90, 0x01, 0x00,
+        0x00,
+    ];
+
+    fn pinned_config() -> DildonicaZoneConfig {
+        DildonicaZoneConfig {
+            enabled: true,
+            midi_control: 41,
+            cycle_count_begin: 1000,
+            cycle_count_end: 9000,
+            comp_thresh_lo: 300,
+            comp_thresh_hi: 400,
+        }
+    }
+
+    #[test]
+    fn the_byte_layout_is_pinned() {
+        assert_eq!(pinned_config().to_bytes(), CONFIG_BYTES);
+        assert_eq!(DildonicaZoneConfig::from_bytes(&CONFIG_BYTES).unwrap(), pinned_config());
+        // The padding is ignored when read
+        let mut padded = CONFIG_BYTES;
+        padded[2..4].copy_from_slice(&[0xAB, 0xCD]);
+        assert_eq!(DildonicaZoneConfig::from_bytes(&padded).unwrap(), pinned_config());
+        let legacy = [&CONFIG_BYTES[..12], &[0x90, 0x01, 0x00, 0x00]].concat();
+        assert_eq!(pinned_config().to_legacy_bytes().as_slice(), legacy);
+        let expected = DildonicaZoneConfig {
+            comp_thresh_lo: 400,
+            ..pinned_config()
+        };
+        assert_eq!(DildonicaZoneConfig::from_legacy_bytes(&legacy).unwrap(), expected);
+    }
+
+    #[test]
+    fn a_truncated_config_is_too_short() {
+        for len in 0..DildonicaZoneConfig::SIZE {
+            let parsed = DildonicaZoneConfig::from_bytes(&CONFIG_BYTES[..len]);
+            assert!(matches!(parsed, Err(DeviceConfigError::DataTooShort)), "{} bytes", len);
+        }
+        for len in 0..DildonicaZoneConfig::LEGACY_SIZE {
+            let parsed = DildonicaZoneConfig::from_legacy_bytes(&CONFIG_BYTES[..len]);
+            assert!(matches!(parsed, Err(DeviceConfigError::DataTooShort)), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn configs_for_a_zone_past_the_last_are_rejected() {
+        let mut data = blob(ConfigLayout::Current);
+        data.extend(CONFIG_BYTES);
+        let parsed = parse_zone_configs(&data, NUM_ZONES);
+        assert!(matches!(parsed, Err(DeviceConfigError::UnexpectedSize(_))), "{:?}", parsed.map(|(_, layout)| layout));
+        let (configs, _) = parse_zone_configs(&data, NUM_ZONES + 1).unwrap();
+        assert_eq!(configs[NUM_ZONES], pinned_config());
+    }
+
+    /// Any config, its values up to twice what the firmware stores
+    fn any_config() -> impl Strategy<Value = DildonicaZoneConfig> {
+        let cycles = 0..=2 * MAX_CYCLE_COUNT;
+        let thresh = 0..=2 * MAX_COMP_THRESH;
+        (any::<bool>(), any::<u8>(), cycles.clone(), cycles, thresh.clone(), thresh).prop_map(
+            |(enabled, midi_control, cycle_count_begin, cycle_count_end, comp_thresh_lo, comp_thresh_hi)| {
+                DildonicaZoneConfig {
+                    enabled,
+                    midi_control,
+                    cycle_count_begin,
+                    cycle_count_end,
+                    comp_thresh_lo,
+                    comp_thresh_hi,
+                }
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn configs_read_back_as_the_device_stores_them(config in any_config()) {
+            let bytes = config.to_bytes();
+            prop_assert_eq!(&bytes[2..4], &[0, 0], "padding is written as zero");
+            prop_assert_eq!(DildonicaZoneConfig::from_bytes(&bytes).unwrap(), config.clamped(&FIRMWARE_LIMITS));
+            let legacy = DildonicaZoneConfig::from_legacy_bytes(&config.to_legacy_bytes()).unwrap();
+            let threshold = config.comp_thresh_hi.min(MAX_COMP_THRESH);
+            let expected = DildonicaZoneConfig { comp_thresh_lo: threshold, ..config.clamped(&FIRMWARE_LIMITS) };
+            prop_assert_eq!(legacy, expected);
+        }
+
+        #[test]
+        fn arbitrary_config_bytes_never_panic(bytes in proptest::collection::vec(any::<u8>(), 0..4 * 20 * NUM_ZONES)) {
+            let current = DildonicaZoneConfig::from_bytes(&bytes);
+            prop_assert_eq!(current.is_ok(), bytes.len() >= DildonicaZoneConfig::SIZE);
+            let legacy = DildonicaZoneConfig::from_legacy_bytes(&bytes);
+            prop_assert_eq!(legacy.is_ok(), bytes.len() >= DildonicaZoneConfig::LEGACY_SIZE);
+            let detected = ConfigLayout::detect(bytes.len(), NUM_ZONES).ok();
+            prop_assert_eq!(parse_zone_configs(&bytes, NUM_ZONES).ok().map(|(_, layout)| layout), detected);
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Watching the zone baselines for slow drift away from their calibrated
+/// values, as when the enclosure warms up and the device's comparator
+/// thresholds stop matching the raw values
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DriftConfig {
+    pub enabled: bool,
+    /// A zone whose long-term baseline moved further than this from its
+    /// calibrated value, in percent, is warned about
+    pub threshold_percent: f64,
+    /// Window the long-term baseline is the median of; touches much shorter
+    /// than half of it don't move it
+    pub window_secs: f64,
+}
+
+impl DriftConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.threshold_percent > 0.0 && self.threshold_percent <= 100.0) {
+            return Err("drift.threshold_percent must be between 0 and 100".to_string());
+        }
+        if !(10.0..=3600.0).contains(&self.window_secs) {
+            return Err("drift.window_secs must be between 10 and 3600".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_secs_f64(self.window_secs)
+    }
+}
+
+impl Default for DriftConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold_percent: 10.0,
+            window_secs: 300.0,
+        }
+    }
+}
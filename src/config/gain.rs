@@ -0,0 +1,78 @@
+use super::zones::NUM_ZONES;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Automatic per-zone gain that evens out zones of different sensitivity:
+/// each zone's normalized value is scaled so its recent peak reaches
+/// `target`, before gestures and MIDI see it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoGainConfig {
+    pub enabled: bool,
+    /// Magnitude the peak of every zone is brought to
+    pub target: f64,
+    /// Rolling window the peak is taken over
+    pub window_secs: f64,
+    /// Fraction of the distance to the wanted gain covered per second; low
+    /// so the gain doesn't pump with the playing
+    pub adaptation_per_sec: f64,
+    pub min_gain: f64,
+    pub max_gain: f64,
+    /// A zone whose peak stays below this keeps its gain
+    pub min_peak: f64,
+    /// Stops learning and keeps the current gains
+    pub frozen: bool,
+    /// Saves the gains when frozen in the Configuration tab and starts from
+    /// them
+    pub persist: bool,
+    /// Saved gains per output zone
+    pub gains: Vec<f64>,
+}
+
+impl AutoGainConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.target.is_finite() || self.target <= 0.0 {
+            return Err("auto_gain.target must be positive".to_string());
+        }
+        if !(0.1..=600.0).contains(&self.window_secs) {
+            return Err("auto_gain.window_secs must be between 0.1 and 600".to_string());
+        }
+        if !self.adaptation_per_sec.is_finite() || self.adaptation_per_sec <= 0.0 {
+            return Err("auto_gain.adaptation_per_sec must be positive".to_string());
+        }
+        if !self.min_gain.is_finite() || !self.max_gain.is_finite() || self.min_gain <= 0.0 || self.min_gain > self.max_gain {
+            return Err("auto_gain.min_gain must be positive and at most max_gain".to_string());
+        }
+        if !(0.0..1.0).contains(&self.min_peak) {
+            return Err("auto_gain.min_peak must be between 0 and 1".to_string());
+        }
+        if self.gains.len() > NUM_ZONES {
+            return Err(format!("auto_gain.gains has more than {} zones", NUM_ZONES));
+        }
+        if self.gains.iter().any(|gain| !gain.is_finite() || *gain <= 0.0) {
+            return Err("auto_gain.gains must all be positive".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_secs_f64(self.window_secs)
+    }
+}
+
+impl Default for AutoGainConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target: 0.8,
+            window_secs: 10.0,
+            adaptation_per_sec: 0.1,
+            min_gain: 0.25,
+            max_gain: 4.0,
+            min_peak: 0.05,
+            frozen: false,
+            persist: false,
+            gains: Vec::new(),
+        }
+    }
+}
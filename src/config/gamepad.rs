@@ -0,0 +1,102 @@
+use super::zones::NUM_ZONES;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum GamepadAxis {
+    None,
+    LeftX,
+    LeftY,
+    RightX,
+    RightY,
+    LeftTrigger,
+    RightTrigger,
+}
+
+impl GamepadAxis {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GamepadAxis::None => "(none)",
+            GamepadAxis::LeftX => "Left stick X",
+            GamepadAxis::LeftY => "Left stick Y",
+            GamepadAxis::RightX => "Right stick X",
+            GamepadAxis::RightY => "Right stick Y",
+            GamepadAxis::LeftTrigger => "Left trigger",
+            GamepadAxis::RightTrigger => "Right trigger",
+        }
+    }
+}
+
+/// How one output zone drives the virtual gamepad
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GamepadZone {
+    pub axis: GamepadAxis,
+    /// Axis deflection per unit of normalized magnitude, capped at full deflection
+    pub scale: f64,
+    /// Deflect sticks the other way, and triggers from pressed to released
+    pub invert: bool,
+    /// Press this zone's button while the zone is above the note threshold
+    pub button: bool,
+}
+
+impl Default for GamepadZone {
+    fn default() -> Self {
+        Self {
+            axis: GamepadAxis::None,
+            scale: 5.0,
+            invert: false,
+            button: true,
+        }
+    }
+}
+
+/// Virtual gamepad on Linux, used by builds with the `gamepad` feature. Zone
+/// N presses button N (A, B, X, Y, LB, RB, Select, Start) while its magnitude
+/// is above `midi.note_config.threshold`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GamepadConfig {
+    pub enabled: bool,
+    /// One entry per output zone
+    pub zones: Vec<GamepadZone>,
+}
+
+impl GamepadConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.zones.len() != NUM_ZONES {
+            return Err(format!(
+                "gamepad.zones has {} entries, expected {}",
+                self.zones.len(),
+                NUM_ZONES
+            ));
+        }
+        for (zone, mapping) in self.zones.iter().enumerate() {
+            let reused = self.zones[..zone]
+                .iter()
+                .any(|other| other.axis == mapping.axis);
+            if mapping.axis != GamepadAxis::None && reused {
+                return Err(format!("{} is mapped to more than one zone", mapping.axis.name()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        let mut zones = vec![GamepadZone::default(); NUM_ZONES];
+        let axes = [
+            GamepadAxis::LeftX,
+            GamepadAxis::LeftY,
+            GamepadAxis::RightX,
+            GamepadAxis::RightY,
+        ];
+        for (zone, axis) in zones.iter_mut().zip(axes) {
+            zone.axis = axis;
+        }
+        Self {
+            enabled: false,
+            zones,
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+/// Timing of the touch gestures detected per zone. A zone is touched while its
+/// magnitude is above `midi.note_config.threshold`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GestureConfig {
+    /// Touches up to this long are taps
+    pub tap_max_ms: f64,
+    /// A tap starting this soon after the previous one ended is a double tap
+    pub double_tap_ms: f64,
+    /// Touches longer than this are holds
+    pub hold_ms: f64,
+}
+
+impl GestureConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tap_max_ms <= 0.0 || self.double_tap_ms <= 0.0 || self.hold_ms <= 0.0 {
+            return Err("gesture timings must be positive".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        Self {
+            tap_max_ms: 250.0,
+            double_tap_ms: 400.0,
+            hold_ms: 300.0,
+        }
+    }
+}
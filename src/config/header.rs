@@ -0,0 +1,478 @@
+use super::device::DildonicaZoneConfig;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// The fields of the firmware's zone config struct, in declaration order
+const FIELDS: [&str; 6] = [
+    "enabled",
+    "midi_control",
+    "cycle_count_begin",
+    "cycle_count_end",
+    "comp_thresh_lo",
+    "comp_thresh_hi",
+];
+/// Initializers of firmware before the comparator had hysteresis, with one
+/// `comp_thresh`
+const LEGACY_FIELDS: usize = 5;
+
+#[derive(Error, Debug)]
+pub enum HeaderError {
+    #[error("Failed to read {}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("No array of zone config initializers found")]
+    NoArray,
+    #[error("Line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("Zone {zone} has {found} values, expected {} or {}", LEGACY_FIELDS, FIELDS.len())]
+    FieldCount { zone: usize, found: usize },
+    #[error("Zone {zone}: {message}")]
+    Invalid { zone: usize, message: String },
+}
+
+/// The zone configs a header of the firmware project initializes its zone
+/// config array with, e.g. `zone_config_t zones[8] = {{1, 0, 1000, ...}, ...};`.
+/// Comments, hex, octal and binary literals, integer suffixes, `true` and
+/// `false`, names `#define`d to a number, designated initializers and
+/// trailing commas are understood; anything more of C is not.
+pub fn import_c_header(path: &Path) -> Result<Vec<DildonicaZoneConfig>, HeaderError> {
+    let text = fs::read_to_string(path).map_err(|source| HeaderError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parse_c_header(&text)
+}
+
+/// `import_c_header` on the header's text. Of several arrays of struct
+/// initializers the first one declared with "zone" in its type or name is
+/// taken, the first one if none is.
+pub fn parse_c_header(text: &str) -> Result<Vec<DildonicaZoneConfig>, HeaderError> {
+    let (tokens, defines) = tokenize(&strip_comments(text))?;
+
+    let mut candidates = Vec::new();
+    let mut declaration = 0;
+    let mut depth = 0usize;
+    for (index, token) in tokens.iter().enumerate() {
+        match token.kind {
+            Kind::Punct('{') => depth += 1,
+            Kind::Punct('}') => depth = depth.saturating_sub(1),
+            Kind::Punct(';') if depth == 0 => declaration = index + 1,
+            Kind::Punct('=') if depth == 0 => {
+                let opens_array = |offset: usize| matches!(tokens.get(index + offset), Some(Token { kind: Kind::Punct('{'), .. }));
+                if opens_array(1) && opens_array(2) {
+                    let zone = tokens[declaration..index]
+                        .iter()
+                        .any(|token| matches!(&token.kind, Kind::Ident(name) if name.to_ascii_lowercase().contains("zone")));
+                    candidates.push((zone, index + 1));
+                }
+            }
+            _ => {}
+        }
+    }
+    let start = candidates
+        .iter()
+        .find(|(zone, _)| *zone)
+        .or(candidates.first())
+        .map(|&(_, start)| start)
+        .ok_or(HeaderError::NoArray)?;
+
+    Parser {
+        tokens: &tokens,
+        position: start,
+        defines: &defines,
+    }
+    .zone_configs()
+}
+
+/// Replaces every comment with a space, keeping its line breaks so lines
+/// are still counted right
+fn strip_comments(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('/', Some('/')) => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+                stripped.push(' ');
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        stripped.push('\n');
+                    }
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                stripped.push(' ');
+            }
+            ('"', _) | ('\'', _) => {
+                // Kept, a comment marker in a literal is no comment
+                stripped.push(c);
+                let mut escaped = false;
+                for next in chars.by_ref() {
+                    stripped.push(next);
+                    if (next == c && !escaped) || next == '\n' {
+                        break;
+                    }
+                    escaped = next == '\\' && !escaped;
+                }
+            }
+            _ => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+#[derive(Debug)]
+enum Kind {
+    Number(u64),
+    Ident(String),
+    Punct(char),
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: Kind,
+    line: usize,
+}
+
+/// The tokens outside preprocessor lines, and the names those lines
+/// `#define` to a number
+fn tokenize(text: &str) -> Result<(Vec<Token>, HashMap<String, u64>), HeaderError> {
+    let mut tokens = Vec::new();
+    let mut defines = HashMap::new();
+    let mut continued = false;
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let preprocessor = continued || line.trim_start().starts_with('#');
+        continued = preprocessor && line.trim_end().ends_with('\\');
+        if preprocessor {
+            let mut words = line.trim_start().trim_start_matches('#').split_whitespace();
+            if let (Some("define"), Some(name), Some(value), None) = (words.next(), words.next(), words.next(), words.next()) {
+                if let Some(value) = parse_number(value.trim_start_matches('(').trim_end_matches(')')) {
+                    defines.insert(name.to_string(), value);
+                }
+            }
+            continue;
+        }
+
+        let mut chars = line.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            let kind = if c.is_ascii_digit() {
+                let mut end = start + 1;
+                while let Some((i, _)) = chars.next_if(|&(_, c)| c.is_ascii_alphanumeric()) {
+                    end = i + 1;
+                }
+                let literal = &line[start..end];
+                let value = parse_number(literal).ok_or_else(|| HeaderError::Syntax {
+                    line: number,
+                    message: format!("`{}` is not a number", literal),
+                })?;
+                Kind::Number(value)
+            } else if c.is_ascii_alphabetic() || c == '_' {
+                let mut end = start + 1;
+                while let Some((i, _)) = chars.next_if(|&(_, c)| c.is_ascii_alphanumeric() || c == '_') {
+                    end = i + 1;
+                }
+                Kind::Ident(line[start..end].to_string())
+            } else if c == '"' || c == '\'' {
+                // Strings can't be a zone config, only their braces could confuse
+                let mut escaped = false;
+                for (_, next) in chars.by_ref() {
+                    if next == c && !escaped {
+                        break;
+                    }
+                    escaped = next == '\\' && !escaped;
+                }
+                continue;
+            } else if c.is_whitespace() {
+                continue;
+            } else {
+                Kind::Punct(c)
+            };
+            tokens.push(Token { kind, line: number });
+        }
+    }
+    Ok((tokens, defines))
+}
+
+/// A C integer literal: decimal, `0x` hex, `0b` binary or `0` octal, with
+/// any `u` and `l` suffixes
+fn parse_number(literal: &str) -> Option<u64> {
+    let digits = literal.trim_end_matches(['u', 'U', 'l', 'L']);
+    let (digits, radix) = if let Some(hex) = digits.strip_prefix("0x").or(digits.strip_prefix("0X")) {
+        (hex, 16)
+    } else if let Some(binary) = digits.strip_prefix("0b").or(digits.strip_prefix("0B")) {
+        (binary, 2)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        (&digits[1..], 8)
+    } else {
+        (digits, 10)
+    };
+    u64::from_str_radix(digits, radix).ok()
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+    defines: &'a HashMap<String, u64>,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: String) -> HeaderError {
+        let line = self
+            .tokens
+            .get(self.position)
+            .or(self.tokens.last())
+            .map_or(1, |token| token.line);
+        HeaderError::Syntax { line, message }
+    }
+
+    fn next(&mut self) -> Option<&'a Kind> {
+        let token = self.tokens.get(self.position)?;
+        self.position += 1;
+        Some(&token.kind)
+    }
+
+    /// Takes the next token if it is `c`
+    fn eat(&mut self, c: char) -> bool {
+        let found = matches!(self.tokens.get(self.position), Some(Token { kind: Kind::Punct(p), .. }) if *p == c);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), HeaderError> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{}`", c)))
+        }
+    }
+
+    /// `{ {...}, {...}, }` from its opening brace on
+    fn zone_configs(&mut self) -> Result<Vec<DildonicaZoneConfig>, HeaderError> {
+        self.expect('{')?;
+        let mut configs = Vec::new();
+        while !self.eat('}') {
+            let values = self.initializer()?;
+            configs.push(zone_config(configs.len(), &values)?);
+            if !self.eat(',') {
+                self.expect('}')?;
+                break;
+            }
+        }
+        Ok(configs)
+    }
+
+    /// The field values of one `{...}`, positional or designated with
+    /// `.field = value`; fields a designated initializer leaves out are 0,
+    /// as in C
+    fn initializer(&mut self) -> Result<Vec<u64>, HeaderError> {
+        self.expect('{')?;
+        let mut values = Vec::new();
+        let mut designated = false;
+        while !self.eat('}') {
+            let position = if self.eat('.') {
+                let name = match self.next() {
+                    Some(Kind::Ident(name)) => name,
+                    _ => return Err(self.error("expected a field name after `.`".to_string())),
+                };
+                let position = FIELDS
+                    .iter()
+                    .position(|field| field == name)
+                    .ok_or_else(|| self.error(format!("unknown zone config field `{}`", name)))?;
+                self.expect('=')?;
+                designated = true;
+                position
+            } else if designated {
+                return Err(self.error("mixed positional and designated initializers".to_string()));
+            } else {
+                values.len()
+            };
+            let value = self.value()?;
+            if designated {
+                values.resize(values.len().max(FIELDS.len()), 0);
+                values[position] = value;
+            } else {
+                values.push(value);
+            }
+            if !self.eat(',') {
+                self.expect('}')?;
+                break;
+            }
+        }
+        Ok(values)
+    }
+
+    fn value(&mut self) -> Result<u64, HeaderError> {
+        let value = match self.tokens.get(self.position).map(|token| &token.kind) {
+            Some(Kind::Number(value)) => *value,
+            Some(Kind::Ident(name)) if name == "true" => 1,
+            Some(Kind::Ident(name)) if name == "false" => 0,
+            Some(Kind::Ident(name)) => self
+                .defines
+                .get(name)
+                .copied()
+                .ok_or_else(|| self.error(format!("`{}` is not #defined to a number", name)))?,
+            _ => return Err(self.error("expected a number".to_string())),
+        };
+        self.position += 1;
+        Ok(value)
+    }
+}
+
+/// The config of `zone` the field values give; the one threshold of the
+/// legacy layout is taken for both
+fn zone_config(zone: usize, values: &[u64]) -> Result<DildonicaZoneConfig, HeaderError> {
+    let (thresh_lo, thresh_hi) = match values.len() {
+        LEGACY_FIELDS => (values[4], values[4]),
+        n if n == FIELDS.len() => (values[4], values[5]),
+        found => return Err(HeaderError::FieldCount { zone, found }),
+    };
+    let invalid = |message| HeaderError::Invalid { zone, message };
+    let small = |value: u64, field: &str| u8::try_from(value).map_err(|_| invalid(format!("{} {} doesn't fit in a byte", field, value)));
+    let large = |value: u64, field: &str| u32::try_from(value).map_err(|_| invalid(format!("{} {} is too large", field, value)));
+    let config = DildonicaZoneConfig {
+        enabled: values[0] != 0,
+        midi_control: small(values[1], FIELDS[1])?,
+        cycle_count_begin: large(values[2], FIELDS[2])?,
+        cycle_count_end: large(values[3], FIELDS[3])?,
+        comp_thresh_lo: large(thresh_lo, FIELDS[4])?,
+        comp_thresh_hi: large(thresh_hi, FIELDS[5])?,
+    };
+    config.validate().map_err(invalid)?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NUM_ZONES;
+
+    const SAMPLE_HEADER: &str = include_str!("../../tests/fixtures/header/zone_config.h");
+
+    fn zone(enabled: bool, midi_control: u8, cycles: (u32, u32), thresholds: (u32, u32)) -> DildonicaZoneConfig {
+        DildonicaZoneConfig {
+            enabled,
+            midi_control,
+            cycle_count_begin: cycles.0,
+            cycle_count_end: cycles.1,
+            comp_thresh_lo: thresholds.0,
+            comp_thresh_hi: thresholds.1,
+        }
+    }
+
+    /// The zone configs of `zones`, the initializers of an array
+    fn parse_zones(zones: &str) -> Result<Vec<DildonicaZoneConfig>, HeaderError> {
+        parse_c_header(&format!("zone_config_t zones[] = {{\n{}\n}};\n", zones))
+    }
+
+    #[test]
+    fn the_sample_header_gives_its_eight_zones() {
+        let configs = parse_c_header(SAMPLE_HEADER).unwrap();
+        assert_eq!(configs.len(), NUM_ZONES);
+        let expected = [
+            zone(true, 41, (1000, 10000), (100, 4000)),
+            zone(true, 42, (1000, 10000), (100, 4000)),
+            zone(true, 43, (1000, 10000), (100, 4000)),
+            zone(true, 44, (1000, 10000), (100, 4000)),
+            zone(true, 45, (2000, 20000), (200, 3000)),
+            zone(false, 46, (0, 5000), (0, 0)),
+            zone(false, 47, (1000, 10000), (100, 4000)),
+            zone(true, 48, (1000, 10000), (2500, 2500)),
+        ];
+        assert_eq!(configs, expected);
+        // The first four are one config in as many radixes
+        for config in &configs[1..4] {
+            assert_eq!(DildonicaZoneConfig { midi_control: 41, ..*config }, configs[0]);
+        }
+    }
+
+    #[test]
+    fn the_sample_header_imports_from_its_file() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/header/zone_config.h");
+        assert_eq!(import_c_header(&path).unwrap(), parse_c_header(SAMPLE_HEADER).unwrap());
+        let missing = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/header/missing.h");
+        assert!(matches!(import_c_header(&missing), Err(HeaderError::Io { .. })));
+    }
+
+    #[test]
+    fn literals_of_every_radix() {
+        let cases = [
+            ("0", 0),
+            ("1000", 1000),
+            ("0x3E8", 1000),
+            ("0X3e8", 1000),
+            ("01750", 1000),
+            ("0b1111101000", 1000),
+            ("0B1111101000", 1000),
+            ("1000u", 1000),
+            ("1000UL", 1000),
+            ("0x3E8lu", 1000),
+        ];
+        for (literal, value) in cases {
+            assert_eq!(parse_number(literal), Some(value), "{}", literal);
+        }
+        for literal in ["0x", "08", "0b2", "12ab", "1000z"] {
+            assert_eq!(parse_number(literal), None, "{}", literal);
+        }
+    }
+
+    #[test]
+    fn trailing_commas_are_optional() {
+        let expected = vec![zone(true, 1, (2, 3), (4, 5)); 2];
+        assert_eq!(parse_zones("{1, 1, 2, 3, 4, 5}, {1, 1, 2, 3, 4, 5}").unwrap(), expected);
+        assert_eq!(parse_zones("{1, 1, 2, 3, 4, 5,}, {1, 1, 2, 3, 4, 5,},").unwrap(), expected);
+        let designated = "{.enabled = 1, .midi_control = 1, .cycle_count_begin = 2,\n\
+            .cycle_count_end = 3, .comp_thresh_lo = 4, .comp_thresh_hi = 5,},";
+        assert_eq!(parse_zones(designated).unwrap()[..], expected[..1]);
+    }
+
+    #[test]
+    fn comments_are_skipped_but_keep_lines() {
+        let configs = parse_zones("/* {9, 9} */ {1, 1, // 0x\n 2, 3, 4, 5}").unwrap();
+        assert_eq!(configs, [zone(true, 1, (2, 3), (4, 5))]);
+        let error = parse_zones("/* one\n two */\n{1, 1, 2, 3, 4, nope}").unwrap_err();
+        assert!(matches!(error, HeaderError::Syntax { line: 4, .. }), "{:?}", error);
+    }
+
+    #[test]
+    fn the_zone_array_is_picked_over_others() {
+        let header = "int pins[2] = {{1, 2}, {3, 4}};\nzone_config_t cfg[1] = {{1, 1, 2, 3, 4, 5}};\n";
+        assert_eq!(parse_c_header(header).unwrap(), [zone(true, 1, (2, 3), (4, 5))]);
+        // Without "zone" anywhere the first array is taken
+        let header = "config_t cfg[1] = {{1, 1, 2, 3, 4, 5}};\nconfig_t other[1] = {{0, 2, 2, 3, 4, 5}};\n";
+        assert_eq!(parse_c_header(header).unwrap(), [zone(true, 1, (2, 3), (4, 5))]);
+        assert!(matches!(parse_c_header("int x = 1;\n"), Err(HeaderError::NoArray)));
+    }
+
+    #[test]
+    fn rejections() {
+        let field_count = parse_zones("{1, 1, 2, 3}").unwrap_err();
+        assert!(matches!(field_count, HeaderError::FieldCount { zone: 0, found: 4 }), "{:?}", field_count);
+        let too_many = parse_zones("{1, 1, 2, 3, 4, 5}, {1, 1, 2, 3, 4, 5, 6}").unwrap_err();
+        assert!(matches!(too_many, HeaderError::FieldCount { zone: 1, found: 7 }), "{:?}", too_many);
+        let cases = [
+            ("{1, 0x100, 2, 3, 4, 5}", "Zone 0"),
+            ("{1, 1, 2, 3, 4, 10001}", "Zone 0"),
+            ("{1, 1, 2, 3, 4, 0x1FFFFFFFF}", "Zone 0"),
+            ("{1, 1, 2, 3, 4, UNDEFINED}", "Line 2"),
+            ("{1, 1, 2, 3, 4, 12ab}", "Line 2"),
+            ("{.nope = 1}", "Line 2"),
+            ("{.enabled = 1, 2}", "Line 2"),
+            ("{1, 1, 2, 3, 4, 5", "Line 3"),
+        ];
+        for (zones, location) in cases {
+            let error = parse_zones(zones).unwrap_err().to_string();
+            assert!(error.starts_with(location), "{}: {}", zones, error);
+        }
+    }
+}
@@ -0,0 +1,10 @@
+use serde::{Deserialize, Serialize};
+
+/// Settings of the `--http-port` API. They can only be changed in the file,
+/// not through the API itself.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Requests must carry `Authorization: Bearer <token>`; empty for no auth
+    pub token: String,
+}
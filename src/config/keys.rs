@@ -0,0 +1,75 @@
+use super::zones::NUM_ZONES;
+use serde::{Deserialize, Serialize};
+
+/// Key names accepted besides single characters
+pub const NAMED_KEYS: &[&str] = &[
+    "Space", "Return", "Tab", "Escape", "Backspace", "Delete", "Up", "Down", "Left", "Right",
+    "PageUp", "PageDown", "Home", "End", "F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9",
+    "F10", "F11", "F12",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum KeyGesture {
+    /// Key held down from the zone's hold gesture until it is released
+    Hold,
+    /// Key pressed and released once
+    Tap,
+    DoubleTap,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyBinding {
+    pub zone: usize,
+    pub gesture: KeyGesture,
+    /// A name from `NAMED_KEYS` or a single character
+    pub key: String,
+}
+
+impl KeyBinding {
+    pub fn is_valid_key(key: &str) -> bool {
+        NAMED_KEYS.contains(&key) || key.chars().count() == 1
+    }
+}
+
+/// Synthetic key presses driven by zone gestures, used by builds with the
+/// `keys` feature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeysConfig {
+    pub enabled: bool,
+    pub bindings: Vec<KeyBinding>,
+}
+
+impl KeysConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        for binding in &self.bindings {
+            if binding.zone >= NUM_ZONES {
+                return Err(format!("Key binding for zone {} is out of range", binding.zone));
+            }
+            if !KeyBinding::is_valid_key(&binding.key) {
+                return Err(format!("Unknown key `{}` in key bindings", binding.key));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bindings: vec![
+                KeyBinding {
+                    zone: 0,
+                    gesture: KeyGesture::Hold,
+                    key: "Space".to_string(),
+                },
+                KeyBinding {
+                    zone: 3,
+                    gesture: KeyGesture::DoubleTap,
+                    key: "n".to_string(),
+                },
+            ],
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// Language of the GUI, see `gui::i18n`
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+}
+
+impl Language {
+    pub fn all() -> &'static [Language] {
+        &[Language::English, Language::German]
+    }
+
+    /// The language's own name for itself, as offered in the selector
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::German => "Deutsch",
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use super::midi::PatchBinding;
+use super::zones::NUM_ZONES;
+use crate::markers::{DEFAULT_MARKER_NAME, MAX_MARKER_NAME_CHARS};
+use serde::{Deserialize, Serialize};
+
+/// Markers dropped into the session's timeline by a zone gesture, like the
+/// hotkey and the button of the Plot tab
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MarkerConfig {
+    /// What markers the gesture drops are called
+    pub name: String,
+    /// Gesture that drops a marker
+    pub binding: Option<PatchBinding>,
+}
+
+impl MarkerConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.chars().count() > MAX_MARKER_NAME_CHARS {
+            return Err(format!("markers.name must be at most {} characters", MAX_MARKER_NAME_CHARS));
+        }
+        if self.binding.is_some_and(|binding| binding.zone >= NUM_ZONES) {
+            return Err("markers.binding zone is out of range".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for MarkerConfig {
+    fn default() -> Self {
+        Self {
+            name: DEFAULT_MARKER_NAME.to_string(),
+            binding: None,
+        }
+    }
+}
@@ -1,4 +1,6 @@
+use super::zones::NUM_ZONES;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum MidiOutputMethod {
@@ -6,6 +8,28 @@ pub enum MidiOutputMethod {
     Notes,
 }
 
+/// When continuous values (Control Change, key pressure) are sent. Note On
+/// and Off are always sent as soon as a sample crosses the threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MidiUpdateMode {
+    /// One message per sample, as samples arrive
+    PerSample,
+    /// The latest value of each zone on a fixed clock, see `MidiUpdateConfig::rate_hz`
+    FixedRate,
+}
+
+/// What happens to a held note when the note its zone maps to changes, e.g.
+/// with the scale, base note or octave offset
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum RequantizeMode {
+    /// The held note sounds on; the zone plays the new note when touched again
+    #[default]
+    Retrigger,
+    /// The held note is ended and the new one started at once, with the
+    /// velocity last sent for the zone
+    Immediate,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum MusicalScale {
     Chromatic,
@@ -95,36 +119,891 @@ pub struct MidiConfig {
     pub method: MidiOutputMethod,
     pub control_change_config: ControlChangeConfig,
     pub note_config: NoteConfig,
+    /// Name of the MIDI output port to open; `None` picks one at startup
+    #[serde(default)]
+    pub output_port: Option<String>,
+    /// Create our own virtual output port instead of connecting to `output_port`
+    #[serde(default)]
+    pub virtual_port: bool,
+    #[serde(default)]
+    pub looper: LooperConfig,
+    #[serde(default)]
+    pub lfo: LfoConfig,
+    #[serde(default)]
+    pub update: MidiUpdateConfig,
+    #[serde(default)]
+    pub cc_ramp: CcRampConfig,
+    #[serde(default)]
+    pub patches: PatchConfig,
+    #[serde(default)]
+    pub morph: MorphConfig,
+    #[serde(default)]
+    pub peak: PeakHoldConfig,
+    #[serde(default)]
+    pub requantize: RequantizeMode,
+    #[serde(default)]
+    pub pressure: KeyPressureConfig,
+    #[serde(default)]
+    pub mpe: MpeConfig,
+    /// Send Note On again when a zone strikes a note another zone already
+    /// sounds; it ends with the last of them either way
+    #[serde(default)]
+    pub retrigger_shared_notes: bool,
+    /// Zone gesture that presses or releases the sustain pedal
+    #[serde(default)]
+    pub sustain_toggle: Option<PatchBinding>,
+    #[serde(default)]
+    pub metronome: MetronomeConfig,
+}
+
+impl MidiConfig {
+    /// The mapping fields set above
+    pub fn mapping(&self) -> MidiMapping {
+        MidiMapping {
+            method: self.method,
+            control_change_config: self.control_change_config,
+            note_config: self.note_config.clone(),
+        }
+    }
+
+    /// The mapping `MidiProcessor` uses: the morph between the snapshots if
+    /// enabled, at the position given by `pressure` for `MorphSource::Pressure`
+    pub fn active_mapping(&self, pressure: f64) -> MidiMapping {
+        if self.morph.enabled {
+            self.morph.a.morph(&self.morph.b, self.morph.position(pressure))
+        } else {
+            self.mapping()
+        }
+    }
+}
+
+/// How values are turned into MIDI messages, the part of `MidiConfig` the
+/// morph snapshots store
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MidiMapping {
+    pub method: MidiOutputMethod,
+    pub control_change_config: ControlChangeConfig,
+    pub note_config: NoteConfig,
+}
+
+impl MidiMapping {
+    /// Interpolates every numeric field from `self` at `t` = 0 to `other` at
+    /// `t` = 1, rounding integers. The method and scale can't be mixed, so
+    /// they switch to `other`'s at 0.5.
+    pub fn morph(&self, other: &MidiMapping, t: f64) -> MidiMapping {
+        let t = t.clamp(0.0, 1.0);
+        // Exact at both ends, unlike a + (b - a) * t
+        let lerp = |a: f64, b: f64| a * (1.0 - t) + b * t;
+        let lerp_u8 = |a: u8, b: u8| lerp(f64::from(a), f64::from(b)).round() as u8;
+        let snapped = if t < 0.5 { self } else { other };
+        let (cc_a, cc_b) = (&self.control_change_config, &other.control_change_config);
+        let (note_a, note_b) = (&self.note_config, &other.note_config);
+        MidiMapping {
+            method: snapped.method,
+            control_change_config: ControlChangeConfig {
+                base_control_number: lerp_u8(cc_a.base_control_number, cc_b.base_control_number),
+                control_slope: lerp(cc_a.control_slope, cc_b.control_slope),
+            },
+            note_config: NoteConfig {
+                base_note: lerp_u8(note_a.base_note, note_b.base_note),
+                threshold: lerp(note_a.threshold, note_b.threshold),
+                velocity_slope: lerp(note_a.velocity_slope, note_b.velocity_slope),
+                scale: snapped.note_config.scale,
+                octave_offsets: snapped.note_config.octave_offsets,
+                channel_rotation: snapped.note_config.channel_rotation.clone(),
+            },
+        }
+    }
+}
+
+impl Default for MidiMapping {
+    fn default() -> Self {
+        Self {
+            method: MidiOutputMethod::ControlChange,
+            control_change_config: ControlChangeConfig {
+                base_control_number: 41,
+                control_slope: 20.0,
+            },
+            note_config: NoteConfig {
+                base_note: 60, // Middle C
+                threshold: 0.1,
+                velocity_slope: 100.0,
+                scale: MusicalScale::Chromatic,
+                octave_offsets: [0; NUM_ZONES],
+                channel_rotation: None,
+            },
+        }
+    }
+}
+
+/// What moves the morph between the snapshots
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MorphSource {
+    /// `MorphConfig::position`, set in the MIDI tab
+    Manual,
+    /// The summed magnitude of all zones, scaled by `pressure_slope`
+    Pressure,
+}
+
+/// Crossfade between two stored mappings, e.g. a gentle and an aggressive one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MorphConfig {
+    /// Use the morph instead of the mapping fields of `MidiConfig`
+    pub enabled: bool,
+    pub a: MidiMapping,
+    pub b: MidiMapping,
+    pub source: MorphSource,
+    /// 0 is snapshot A, 1 is B
+    pub position: f64,
+    pub pressure_slope: f64,
+}
+
+impl MorphConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.position) {
+            return Err("morph position must be between 0 and 1".to_string());
+        }
+        if self.pressure_slope <= 0.0 {
+            return Err("morph pressure_slope must be positive".to_string());
+        }
+        self.a.note_config.validate().map_err(|e| format!("morph snapshot a: {}", e))?;
+        self.b.note_config.validate().map_err(|e| format!("morph snapshot b: {}", e))?;
+        Ok(())
+    }
+
+    /// Position of the morph with the zones' summed magnitude at `pressure`
+    pub fn position(&self, pressure: f64) -> f64 {
+        match self.source {
+            MorphSource::Manual => self.position,
+            MorphSource::Pressure => (pressure * self.pressure_slope).min(1.0),
+        }
+    }
+}
+
+impl Default for MorphConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            a: MidiMapping::default(),
+            b: MidiMapping::default(),
+            source: MorphSource::Manual,
+            position: 0.0,
+            pressure_slope: 5.0,
+        }
+    }
+}
+
+/// Highest bank number, sent as two 7-bit Bank Select values
+pub const MAX_BANK: u16 = 16383;
+
+/// Gesture that triggers a patch binding
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum PatchGesture {
+    Tap,
+    DoubleTap,
+    /// Once the touch has lasted `gesture.hold_ms`
+    Hold,
+}
+
+impl PatchGesture {
+    pub fn all() -> &'static [PatchGesture] {
+        &[PatchGesture::Tap, PatchGesture::DoubleTap, PatchGesture::Hold]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PatchGesture::Tap => "Tap",
+            PatchGesture::DoubleTap => "Double tap",
+            PatchGesture::Hold => "Hold",
+        }
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PatchBinding {
+    pub zone: usize,
+    pub gesture: PatchGesture,
+}
+
+/// A named Program Change, sent from the MIDI tab or a zone gesture
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatchSlot {
+    pub name: String,
+    /// Sent as Bank Select (CC 0 and 32) before the Program Change; `None`
+    /// leaves the synth's bank alone
+    #[serde(default)]
+    pub bank: Option<u16>,
+    pub program: u8,
+    #[serde(default)]
+    pub binding: Option<PatchBinding>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PatchConfig {
+    /// MIDI channel of the patch messages, 0-15
+    pub channel: u8,
+    pub slots: Vec<PatchSlot>,
+    /// Sends the slot after the one sent last, wrapping around
+    pub next: Option<PatchBinding>,
+}
+
+impl PatchConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.channel > 15 {
+            return Err("patch channel must be between 0 and 15".to_string());
+        }
+        for slot in &self.slots {
+            if slot.program > 127 {
+                return Err(format!("Program {} of patch `{}` is out of range", slot.program, slot.name));
+            }
+            if slot.bank.is_some_and(|bank| bank > MAX_BANK) {
+                return Err(format!("Bank of patch `{}` is above {}", slot.name, MAX_BANK));
+            }
+        }
+        let bindings = self.slots.iter().filter_map(|slot| slot.binding).chain(self.next);
+        for binding in bindings {
+            if binding.zone >= NUM_ZONES {
+                return Err(format!("Patch binding for zone {} is out of range", binding.zone));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MidiUpdateConfig {
+    pub mode: MidiUpdateMode,
+    /// Ticks per second in `FixedRate` mode; each tick sends a zone's
+    /// latest value if it has a new one
+    pub rate_hz: f64,
+}
+
+impl MidiUpdateConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.rate_hz > 0.0 && self.rate_hz <= 1000.0) {
+            return Err("midi update rate_hz must be between 0 and 1000".to_string());
+        }
+        Ok(())
+    }
+
+    /// Time between ticks in `FixedRate` mode
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.rate_hz)
+    }
+}
+
+impl Default for MidiUpdateConfig {
+    fn default() -> Self {
+        Self {
+            mode: MidiUpdateMode::PerSample,
+            rate_hz: 100.0,
+        }
+    }
+}
+
+/// Easing in the Control Change jumps that come from the mapping changing,
+/// e.g. a morph or an edit, rather than from the sensors
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CcRampConfig {
+    pub enabled: bool,
+    /// Jumps of more than this many CC steps are ramped, smaller ones sent
+    /// as they are
+    pub min_step: u8,
+    /// How long a ramp takes
+    pub ramp_ms: f64,
+}
+
+impl CcRampConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_step > 127 {
+            return Err("midi.cc_ramp.min_step must be at most 127".to_string());
+        }
+        if !(self.ramp_ms > 0.0 && self.ramp_ms <= 10_000.0) {
+            return Err("midi.cc_ramp.ramp_ms must be between 0 and 10000".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn ramp(&self) -> Duration {
+        Duration::from_secs_f64(self.ramp_ms / 1000.0)
+    }
+}
+
+impl Default for CcRampConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_step: 8,
+            ramp_ms: 250.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LfoWaveform {
+    Sine,
+    Triangle,
+    /// A new random level every cycle
+    Random,
+}
+
+impl LfoWaveform {
+    pub fn all() -> &'static [LfoWaveform] {
+        &[LfoWaveform::Sine, LfoWaveform::Triangle, LfoWaveform::Random]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LfoWaveform::Sine => "Sine",
+            LfoWaveform::Triangle => "Triangle",
+            LfoWaveform::Random => "Random",
+        }
+    }
+}
+
+/// How the LFO is combined with a zone's Control Change level
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LfoMix {
+    /// Raises the level by up to `depth`, so it moves even at rest
+    Add,
+    /// Lowers the level by up to `depth` of itself, like a tremolo
+    Multiply,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LfoZoneConfig {
+    pub enabled: bool,
+    /// 0..=1, as a fraction of the full Control Change range
+    pub depth: f64,
+}
+
+impl Default for LfoZoneConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            depth: 0.25,
+        }
+    }
+}
+
+/// A host-side LFO mixed into the Control Change output of each zone
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LfoConfig {
+    pub enabled: bool,
+    pub waveform: LfoWaveform,
+    pub mix: LfoMix,
+    /// Cycles per second, unless `sync` is set
+    pub rate_hz: f64,
+    /// Follow `looper.tempo_bpm` instead of `rate_hz`
+    pub sync: bool,
+    /// Beats per cycle while synced
+    pub beats_per_cycle: f64,
+    /// Indexed by output zone
+    pub zones: [LfoZoneConfig; NUM_ZONES],
+}
+
+impl LfoConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.rate_hz > 0.0 && self.rate_hz <= 100.0) {
+            return Err("lfo rate_hz must be between 0 and 100".to_string());
+        }
+        if self.beats_per_cycle <= 0.0 {
+            return Err("lfo beats_per_cycle must be positive".to_string());
+        }
+        if self.zones.iter().any(|zone| !(0.0..=1.0).contains(&zone.depth)) {
+            return Err("lfo depth must be between 0 and 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// Cycles per second at `tempo_bpm`
+    pub fn rate(&self, tempo_bpm: f64) -> f64 {
+        if self.sync {
+            tempo_bpm / 60.0 / self.beats_per_cycle
+        } else {
+            self.rate_hz
+        }
+    }
+}
+
+impl Default for LfoConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            waveform: LfoWaveform::Sine,
+            mix: LfoMix::Add,
+            rate_hz: 0.5,
+            sync: false,
+            beats_per_cycle: 4.0,
+            zones: [LfoZoneConfig::default(); NUM_ZONES],
+        }
+    }
+}
+
+/// Peak hold of every zone's magnitude, shown as the tick on the meters and
+/// optionally sent as Control Change next to the zone's own controller
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeakHoldConfig {
+    pub enabled: bool,
+    /// How long a peak stays before it starts to fall
+    pub hold_ms: f64,
+    /// Fall rate in magnitude per second after the hold
+    pub decay_per_sec: f64,
+    /// Also send the peak in Control Change mode
+    pub send_cc: bool,
+    /// Controller of zone N's peak is the zone's controller plus this
+    pub peak_cc_offset: u8,
+}
+
+impl PeakHoldConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.hold_ms.is_finite() || self.hold_ms < 0.0 {
+            return Err("midi.peak.hold_ms must not be negative".to_string());
+        }
+        if !self.decay_per_sec.is_finite() || self.decay_per_sec <= 0.0 {
+            return Err("midi.peak.decay_per_sec must be positive".to_string());
+        }
+        if self.peak_cc_offset == 0 || self.peak_cc_offset > 127 {
+            return Err("midi.peak.peak_cc_offset must be between 1 and 127".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn hold(&self) -> Duration {
+        Duration::from_secs_f64(self.hold_ms / 1000.0)
+    }
+}
+
+impl Default for PeakHoldConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            hold_ms: 500.0,
+            decay_per_sec: 0.5,
+            send_cc: false,
+            peak_cc_offset: 8,
+        }
+    }
+}
+
+/// How often Key Pressure is sent for a held note in Notes mode. The
+/// defaults send it for every sample, which floods slower synths.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyPressureConfig {
+    /// Least time between two Key Pressure messages of a zone
+    pub min_interval_ms: f64,
+    /// Least change from the pressure last sent, Note On velocity included
+    pub min_delta: u8,
+    /// Only send pressure above the highest sent since Note On, following
+    /// the peak for percussive patches
+    pub increase_only: bool,
+}
+
+impl KeyPressureConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.min_interval_ms.is_finite() || self.min_interval_ms < 0.0 {
+            return Err("midi.pressure.min_interval_ms must not be negative".to_string());
+        }
+        if self.min_delta > 127 {
+            return Err("midi.pressure.min_delta must be at most 127".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn min_interval(&self) -> Duration {
+        Duration::from_secs_f64(self.min_interval_ms / 1000.0)
+    }
+}
+
+impl Default for KeyPressureConfig {
+    fn default() -> Self {
+        Self {
+            min_interval_ms: 0.0,
+            min_delta: 0,
+            increase_only: false,
+        }
+    }
+}
+
+/// What a new MPE note does when every member channel still sounds a note
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum VoicePolicy {
+    /// Ends the note that started first and takes its channel
+    #[default]
+    StealOldest,
+    /// Ends the note with the lowest pressure, the oldest of equals, and
+    /// takes its channel
+    StealQuietest,
+    /// Drops the new note; its zone tries again on its next sample
+    RefuseNew,
+}
+
+/// MPE output in Notes mode: every note gets a member channel of the lower
+/// zone to itself and its pressure is sent as channel pressure there. The
+/// sustain pedal and `midi.requantize` leave MPE notes alone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MpeConfig {
+    pub enabled: bool,
+    /// Member channels of the lower zone, from the second channel up
+    pub member_channels: u8,
+    pub voice_policy: VoicePolicy,
+}
+
+impl MpeConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(1..=14).contains(&self.member_channels) {
+            return Err("midi.mpe.member_channels must be between 1 and 14".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for MpeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            member_channels: 14,
+            voice_policy: VoicePolicy::default(),
+        }
+    }
+}
+
+/// Length and tempo of the phrase looper. The recorded phrase itself is
+/// never saved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LooperConfig {
+    /// Length of a recording in bars; 0 records until Record is pressed again
+    pub bars: u32,
+    pub beats_per_bar: u32,
+    pub tempo_bpm: f64,
+}
+
+impl LooperConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.beats_per_bar == 0 {
+            return Err("looper beats_per_bar must be positive".to_string());
+        }
+        if !(self.tempo_bpm > 0.0 && self.tempo_bpm <= 1000.0) {
+            return Err("looper tempo_bpm must be between 0 and 1000".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn beat_length(&self) -> Duration {
+        Duration::from_secs_f64(60.0 / self.tempo_bpm)
+    }
+
+    /// Length of a recording, `None` when it runs until stopped
+    pub fn loop_length(&self) -> Option<Duration> {
+        (self.bars > 0).then(|| self.beat_length() * self.bars * self.beats_per_bar)
+    }
+}
+
+impl Default for LooperConfig {
+    fn default() -> Self {
+        Self {
+            bars: 2,
+            beats_per_bar: 4,
+            tempo_bpm: 120.0,
+        }
+    }
+}
+
+/// What a metronome click sends
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum MetronomeClick {
+    /// Note On at the click's velocity, Note Off after `click_ms`
+    #[default]
+    Note,
+    /// The controller at the click's velocity, back to 0 after `click_ms`
+    ControlChange,
+}
+
+impl MetronomeClick {
+    pub fn all() -> &'static [MetronomeClick] {
+        &[MetronomeClick::Note, MetronomeClick::ControlChange]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MetronomeClick::Note => "Note",
+            MetronomeClick::ControlChange => "ControlChange",
+        }
+    }
+}
+
+/// A click track for practicing, on a channel of its own; started and
+/// stopped from the MIDI tab, never on startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetronomeConfig {
+    pub tempo_bpm: f64,
+    /// The first beat of every bar is accented
+    pub beats_per_bar: u32,
+    /// MIDI channel of the clicks, 0-15; 9 is General MIDI percussion
+    pub channel: u8,
+    pub click: MetronomeClick,
+    /// Note, or controller, of the clicks
+    pub number: u8,
+    pub velocity: u8,
+    /// Velocity of the first beat of a bar
+    pub accent_velocity: u8,
+    /// How long a click's note or controller stays on
+    pub click_ms: f64,
+}
+
+impl MetronomeConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(20.0..=400.0).contains(&self.tempo_bpm) {
+            return Err("metronome tempo_bpm must be between 20 and 400".to_string());
+        }
+        if !(1..=16).contains(&self.beats_per_bar) {
+            return Err("metronome beats_per_bar must be between 1 and 16".to_string());
+        }
+        if self.channel > 15 {
+            return Err("metronome channel must be between 0 and 15".to_string());
+        }
+        if self.number > 127 || self.velocity > 127 || self.accent_velocity > 127 {
+            return Err("metronome number and velocities must be at most 127".to_string());
+        }
+        if !(1.0..=1000.0).contains(&self.click_ms) {
+            return Err("metronome click_ms must be between 1 and 1000".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn beat_length(&self) -> Duration {
+        Duration::from_secs_f64(60.0 / self.tempo_bpm)
+    }
+
+    /// A click stays on for at most half a beat, so the next one is heard
+    pub fn click_length(&self) -> Duration {
+        Duration::from_secs_f64(self.click_ms / 1000.0).min(self.beat_length() / 2)
+    }
+}
+
+impl Default for MetronomeConfig {
+    fn default() -> Self {
+        Self {
+            tempo_bpm: 120.0,
+            beats_per_bar: 4,
+            channel: 9,
+            click: MetronomeClick::Note,
+            // General MIDI Hi Wood Block
+            number: 76,
+            velocity: 90,
+            accent_velocity: 127,
+            click_ms: 30.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct ControlChangeConfig {
     pub base_control_number: u8,
     pub control_slope: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NoteConfig {
     pub base_note: u8,
     pub threshold: f64,
     pub velocity_slope: f64,
     pub scale: MusicalScale,
+    /// Octaves added to the scale note of every zone, e.g. -1 for the lower
+    /// half of a bass/lead split
+    #[serde(default)]
+    pub octave_offsets: [i8; NUM_ZONES],
+    /// MIDI channels, 0-15, that Note Ons take in turn, skipping those still
+    /// sounding a note while there is a free one, e.g. for a stack of mono
+    /// synths; a note's pressure and Note Off follow it to its channel.
+    /// Everything on channel 0 if `None`.
+    #[serde(default)]
+    pub channel_rotation: Option<Vec<u8>>,
+}
+
+impl NoteConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let Some(channels) = &self.channel_rotation else {
+            return Ok(());
+        };
+        if channels.is_empty() {
+            return Err("note channel_rotation must list at least one channel".to_string());
+        }
+        for (index, &channel) in channels.iter().enumerate() {
+            if channel > 15 {
+                return Err("note channel_rotation channels must be between 0 and 15".to_string());
+            }
+            if channels[..index].contains(&channel) {
+                return Err(format!("note channel_rotation lists channel {} twice", channel));
+            }
+        }
+        Ok(())
+    }
+
+    /// The note `zone` plays: its scale note shifted by its octave offset,
+    /// clamped to the MIDI range
+    pub fn zone_note(&self, zone: usize) -> u8 {
+        let note = i16::from(self.scale.map_zone_to_note(self.base_note, zone));
+        let offset = i16::from(self.octave_offsets.get(zone).copied().unwrap_or(0));
+        (note + 12 * offset).clamp(0, 127) as u8
+    }
 }
 
 impl Default for MidiConfig {
     fn default() -> Self {
+        let mapping = MidiMapping::default();
         Self {
+            method: mapping.method,
+            control_change_config: mapping.control_change_config,
+            note_config: mapping.note_config,
+            output_port: None,
+            virtual_port: false,
+            looper: LooperConfig::default(),
+            lfo: LfoConfig::default(),
+            update: MidiUpdateConfig::default(),
+            cc_ramp: CcRampConfig::default(),
+            patches: PatchConfig::default(),
+            morph: MorphConfig::default(),
+            peak: PeakHoldConfig::default(),
+            requantize: RequantizeMode::default(),
+            pressure: KeyPressureConfig::default(),
+            mpe: MpeConfig::default(),
+            retrigger_shared_notes: false,
+            sustain_toggle: None,
+            metronome: MetronomeConfig::default(),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Low slopes and a high threshold
+    fn gentle() -> MidiMapping {
+        MidiMapping {
             method: MidiOutputMethod::ControlChange,
             control_change_config: ControlChangeConfig {
-                base_control_number: 41,
-                control_slope: 20.0,
+                base_control_number: 20,
+                control_slope: 2.0,
             },
             note_config: NoteConfig {
-                base_note: 60, // Middle C
-                threshold: 0.1,
+                base_note: 48,
+                threshold: 0.2,
                 velocity_slope: 100.0,
-                scale: MusicalScale::Chromatic,
+                scale: MusicalScale::Major,
+                octave_offsets: [0; NUM_ZONES],
+                channel_rotation: None,
+            },
+        }
+    }
+
+    /// High slopes and a low threshold, with everything that snaps changed
+    fn aggressive() -> MidiMapping {
+        MidiMapping {
+            method: MidiOutputMethod::Notes,
+            control_change_config: ControlChangeConfig {
+                base_control_number: 31,
+                control_slope: 10.0,
+            },
+            note_config: NoteConfig {
+                base_note: 60,
+                threshold: 0.05,
+                velocity_slope: 900.0,
+                scale: MusicalScale::Blues,
+                octave_offsets: [-1; NUM_ZONES],
+                channel_rotation: Some(vec![1, 2]),
             },
         }
     }
-}
\ No newline at end of file
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn morph_ends_are_the_snapshots() {
+        assert_eq!(gentle().morph(&aggressive(), 0.0), gentle());
+        assert_eq!(gentle().morph(&aggressive(), 1.0), aggressive());
+        // Positions outside 0..1 clamp
+        assert_eq!(gentle().morph(&aggressive(), -1.0), gentle());
+        assert_eq!(gentle().morph(&aggressive(), 2.0), aggressive());
+    }
+
+    #[test]
+    fn numbers_interpolate_and_integers_round() {
+        let quarter = gentle().morph(&aggressive(), 0.25);
+        assert_close(quarter.control_change_config.control_slope, 4.0);
+        assert_close(quarter.note_config.threshold, 0.1625);
+        assert_close(quarter.note_config.velocity_slope, 300.0);
+        // 20 + 11 / 4 = 22.75 and 48 + 12 / 4 = 51
+        assert_eq!(quarter.control_change_config.base_control_number, 23);
+        assert_eq!(quarter.note_config.base_note, 51);
+    }
+
+    #[test]
+    fn method_and_scale_snap_at_the_middle() {
+        let before = gentle().morph(&aggressive(), 0.49);
+        assert_eq!(before.method, MidiOutputMethod::ControlChange);
+        assert_eq!(before.note_config.scale, MusicalScale::Major);
+        assert_eq!(before.note_config.octave_offsets, [0; NUM_ZONES]);
+        assert_eq!(before.note_config.channel_rotation, None);
+        let middle = gentle().morph(&aggressive(), 0.5);
+        assert_eq!(middle.method, MidiOutputMethod::Notes);
+        assert_eq!(middle.note_config.scale, MusicalScale::Blues);
+        assert_eq!(middle.note_config.octave_offsets, [-1; NUM_ZONES]);
+        assert_eq!(middle.note_config.channel_rotation, Some(vec![1, 2]));
+    }
+
+    #[test]
+    fn position_follows_the_source() {
+        let manual = MorphConfig {
+            position: 0.3,
+            ..Default::default()
+        };
+        assert_close(manual.position(10.0), 0.3);
+        let pressure = MorphConfig {
+            source: MorphSource::Pressure,
+            pressure_slope: 4.0,
+            ..Default::default()
+        };
+        assert_close(pressure.position(0.1), 0.4);
+        assert_close(pressure.position(1.0), 1.0);
+    }
+
+    #[test]
+    fn active_mapping_uses_the_morph_only_when_enabled() {
+        let mut config = MidiConfig::default();
+        config.morph.a = gentle();
+        config.morph.b = aggressive();
+        config.morph.position = 1.0;
+        assert_eq!(config.active_mapping(0.0), config.mapping());
+        config.morph.enabled = true;
+        assert_eq!(config.active_mapping(0.0), aggressive());
+    }
+
+    #[test]
+    fn channel_rotations_list_distinct_channels() {
+        let rotation = |channels: Option<Vec<u8>>| NoteConfig {
+            channel_rotation: channels,
+            ..gentle().note_config
+        };
+        assert!(rotation(None).validate().is_ok());
+        assert!(rotation(Some(vec![0])).validate().is_ok());
+        assert!(rotation(Some((0..16).collect())).validate().is_ok());
+        assert!(rotation(Some(vec![])).validate().is_err());
+        assert!(rotation(Some(vec![3, 16])).validate().is_err());
+        let twice = rotation(Some(vec![1, 2, 1])).validate().unwrap_err();
+        assert!(twice.contains("channel 1 twice"), "{}", twice);
+    }
+}
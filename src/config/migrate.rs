@@ -0,0 +1,148 @@
+use super::zones::NUM_ZONES;
+use serde_json::{Map, Value};
+use thiserror::Error;
+use tracing::info;
+
+/// Schema version written by this build
+pub const CURRENT_VERSION: u32 = 2;
+
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error("Config file has version {0}, but this build only understands up to version {CURRENT_VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("Config file is not a JSON object")]
+    NotAnObject,
+}
+
+type Migration = fn(Map<String, Value>) -> Map<String, Value>;
+
+/// `MIGRATIONS[n]` turns a version `n` document into a version `n + 1` one.
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1, migrate_v1_to_v2];
+
+/// Brings a config document of any known version up to `CURRENT_VERSION`.
+pub fn migrate(value: Value) -> Result<Value, MigrationError> {
+    let Value::Object(mut object) = value else {
+        return Err(MigrationError::NotAnObject);
+    };
+
+    let version = version_of(&object);
+    if version > CURRENT_VERSION {
+        return Err(MigrationError::UnsupportedVersion(version));
+    }
+
+    for (from_version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        object = migration(object);
+        info!("Migrated app config from version {} to {}", from_version, from_version + 1);
+    }
+    object.insert("version".to_string(), Value::from(CURRENT_VERSION));
+    Ok(Value::Object(object))
+}
+
+/// Documents without a `version` field predate versioning and count as version 0.
+fn version_of(object: &Map<String, Value>) -> u32 {
+    object
+        .get("version")
+        .and_then(Value::as_u64)
+        .map_or(0, |v| v.min(u32::MAX as u64) as u32)
+}
+
+/// Version 0 covers two shapes: the legacy `dildonica_midi_config.json` which
+/// held only a `MidiConfig`, and unversioned `AppConfig` files. The former gets
+/// wrapped into the `midi` section; missing fields are filled from defaults.
+fn migrate_v0_to_v1(mut object: Map<String, Value>) -> Map<String, Value> {
+    if !object.contains_key("midi") && object.contains_key("method") {
+        let midi = std::mem::take(&mut object);
+        object.insert("midi".to_string(), Value::Object(midi));
+    }
+    object
+}
+
+/// Version 1 had a single `plot_raw` switch for every zone; version 2 picks
+/// a source per zone in `plot_sources`, all of them raw if the switch was on.
+fn migrate_v1_to_v2(mut object: Map<String, Value>) -> Map<String, Value> {
+    let plot_raw = object.remove("plot_raw").and_then(|value| value.as_bool()).unwrap_or(false);
+    if plot_raw && !object.contains_key("plot_sources") {
+        object.insert("plot_sources".to_string(), Value::Array(vec![Value::from("Raw"); NUM_ZONES]));
+    }
+    object
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::app::AppConfig;
+    use crate::config::midi::{MidiOutputMethod, MusicalScale};
+    use crate::config::plot::PlotSource;
+    use serde_json::json;
+
+    fn fixture(contents: &str) -> Value {
+        serde_json::from_str(contents).unwrap()
+    }
+
+    fn migrated_config(contents: &str) -> AppConfig {
+        let config: AppConfig = serde_json::from_value(migrate(fixture(contents)).unwrap()).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    #[test]
+    fn legacy_midi_file_becomes_the_midi_section() {
+        let value = migrate(fixture(include_str!("../../tests/fixtures/config/v0_midi.json"))).unwrap();
+        assert_eq!(value["version"], json!(CURRENT_VERSION));
+        assert_eq!(value["midi"]["note_config"]["scale"], json!("Pentatonic"));
+        assert!(value.get("method").is_none());
+
+        let config = migrated_config(include_str!("../../tests/fixtures/config/v0_midi.json"));
+        assert_eq!(config.midi.method, MidiOutputMethod::Notes);
+        assert_eq!(config.midi.note_config.base_note, 48);
+        assert_eq!(config.plot_sources, [PlotSource::Normalized; NUM_ZONES]);
+    }
+
+    #[test]
+    fn unversioned_app_config_keeps_its_fields() {
+        let value = migrate(fixture(include_str!("../../tests/fixtures/config/v0.json"))).unwrap();
+        assert_eq!(value["version"], json!(CURRENT_VERSION));
+        assert!(value.get("plot_raw").is_none());
+        assert_eq!(value["plot_sources"], json!(vec!["Raw"; NUM_ZONES]));
+
+        let config = migrated_config(include_str!("../../tests/fixtures/config/v0.json"));
+        assert_eq!(config.midi.control_change_config.base_control_number, 20);
+        assert_eq!(config.zone_map, vec![7, 6, 5, 4, 3, 2, 1, 0]);
+        assert_eq!(config.exponential_alpha, 0.002);
+        assert_eq!(config.plot_duration_secs, 8.0);
+        assert_eq!(config.plot_sources, [PlotSource::Raw; NUM_ZONES]);
+    }
+
+    #[test]
+    fn version_1_plot_raw_off_keeps_the_default_sources() {
+        let value = migrate(fixture(include_str!("../../tests/fixtures/config/v1.json"))).unwrap();
+        assert_eq!(value["version"], json!(CURRENT_VERSION));
+        assert!(value.get("plot_raw").is_none());
+        assert!(value.get("plot_sources").is_none());
+
+        let config = migrated_config(include_str!("../../tests/fixtures/config/v1.json"));
+        assert_eq!(config.midi.note_config.scale, MusicalScale::Dorian);
+        assert_eq!(config.midi.note_config.velocity_slope, 80.0);
+        assert_eq!(config.plot_sources, [PlotSource::Normalized; NUM_ZONES]);
+    }
+
+    #[test]
+    fn current_version_is_left_alone() {
+        let original = fixture(include_str!("../../tests/fixtures/config/v2.json"));
+        assert_eq!(migrate(original.clone()).unwrap(), original);
+
+        let config = migrated_config(include_str!("../../tests/fixtures/config/v2.json"));
+        assert_eq!(config.plot_sources[3], PlotSource::Peak);
+        assert_eq!(config.zone_map, vec![1, 0, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn newer_versions_and_non_objects_are_rejected() {
+        let newer = json!({ "version": CURRENT_VERSION + 1 });
+        assert!(matches!(
+            migrate(newer),
+            Err(MigrationError::UnsupportedVersion(v)) if v == CURRENT_VERSION + 1
+        ));
+        assert!(matches!(migrate(json!([1, 2, 3])), Err(MigrationError::NotAnObject)));
+    }
+}
@@ -1,9 +1,57 @@
 pub mod app;
+pub mod artnet;
+pub mod batching;
+pub mod ble;
 pub mod device;
+pub mod drift;
+pub mod gamepad;
+pub mod gain;
+pub mod gesture;
+pub mod header;
+pub mod http;
+pub mod keys;
+pub mod language;
+pub mod markers;
 pub mod midi;
+pub mod migrate;
+pub mod normalization;
+pub mod osc;
+pub mod overrides;
+pub mod plot;
+pub mod selftest;
+pub mod startup;
+pub mod store;
+pub mod synth;
+pub mod takes;
+pub mod templates;
+pub mod watchdog;
 pub mod zones;
 
 // Re-export commonly used types for convenience
-pub use app::AppConfig;
-pub use device::{DeviceConfigError, DildonicaZoneConfig, read_zone_configs, write_zone_configs};
-pub use midi::{ControlChangeConfig, MidiConfig, MidiOutputMethod, MusicalScale, NoteConfig};
\ No newline at end of file
+pub use app::{AppConfig, ConfigError, LoadFailure};
+pub use batching::{BatchingConfig, MAX_BATCH_SAMPLES};
+pub use ble::BleConfig;
+pub use device::{
+    cycles_to_micros, diff_zone_configs, micros_to_cycles, parse_zone_configs, read_config_blob, write_zone_configs,
+    ConfigLayout, DeviceConfigError, DeviceOscillator, DildonicaZoneConfig, SizeMismatch, ZoneConfigChange, ZoneLimits,
+    DEFAULT_OSCILLATOR_HZ, FIRMWARE_LIMITS,
+};
+pub use drift::DriftConfig;
+pub use gain::AutoGainConfig;
+pub use header::{import_c_header, parse_c_header, HeaderError};
+pub use language::Language;
+pub use markers::MarkerConfig;
+pub use midi::{CcRampConfig, ControlChangeConfig, KeyPressureConfig, LfoConfig, LfoMix, LfoWaveform, LooperConfig, MetronomeClick, MetronomeConfig, MidiConfig, MidiMapping, MidiOutputMethod, MidiUpdateConfig, MidiUpdateMode, MorphConfig, MorphSource, MpeConfig, MusicalScale, NoteConfig, PatchBinding, PatchConfig, PatchGesture, PatchSlot, PeakHoldConfig, RequantizeMode, VoicePolicy};
+pub use normalization::{NormalizationConfig, NormalizationMode};
+pub use osc::OscConfig;
+pub use plot::{PlotSource, MAX_PLOT_DURATION_SECS, MIN_PLOT_DURATION_SECS, ZONE_PALETTE};
+pub use selftest::SelfTestConfig;
+pub use startup::{StartupAction, StartupConfig};
+pub use store::ConfigStore;
+pub use takes::TakeBufferConfig;
+pub use templates::{hardware_template, DeviceHardware, HardwareTemplate, HARDWARE_TEMPLATES};
+pub use watchdog::WatchdogConfig;
+pub use zones::{
+    mirror_zone_map, validate_zone_groups, validate_zone_map, LogicalZones, ZoneGroupReduction, ZoneMapPreset, ZoneMapScope,
+    NUM_ZONES,
+};
\ No newline at end of file
@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Longest `baseline_warmup_samples` allowed
+pub const MAX_BASELINE_WARMUP_SAMPLES: u64 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NormalizationMode {
+    /// `(raw - baseline) / baseline`, signed
+    RelativeToBaseline,
+    /// Position of the raw value between two percentiles of the recent
+    /// values, from 0 to 1; rest sits at 0 since touching raises the value
+    PercentileRange,
+    /// Position of the raw count in the zone's cycle count window from the
+    /// device config, `(raw - begin) / (end - begin)`, so zones with other
+    /// windows compare; zero until the device config is known
+    CycleWindow,
+}
+
+impl NormalizationMode {
+    pub fn all() -> &'static [NormalizationMode] {
+        &[
+            NormalizationMode::RelativeToBaseline,
+            NormalizationMode::PercentileRange,
+            NormalizationMode::CycleWindow,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NormalizationMode::RelativeToBaseline => "Relative to baseline",
+            NormalizationMode::PercentileRange => "Percentile range",
+            NormalizationMode::CycleWindow => "Cycle count window",
+        }
+    }
+}
+
+/// How raw values become normalized values. The baseline of
+/// `exponential_alpha` is kept in either mode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NormalizationConfig {
+    pub mode: NormalizationMode,
+    /// `RelativeToBaseline`: a baseline or `PercentileRange` band smaller
+    /// than this gives zero instead of dividing by it
+    pub epsilon: f64,
+    /// `PercentileRange`: how far back the percentiles reach
+    pub window_secs: f64,
+    /// `PercentileRange`: the band's low and high end, in percent
+    pub low_percentile: f64,
+    pub high_percentile: f64,
+    /// Scale raw counts to their zone's cycle count window like
+    /// `CycleWindow` before anything else, so the baseline, the plot and the
+    /// taps all see the scaled value, `epsilon` included; bare counts until
+    /// the device config is known
+    pub window_relative_raw: bool,
+    /// A new baseline is the plain mean of this many samples before it
+    /// averages at `exponential_alpha`, so a spike while connecting doesn't
+    /// set it; 0 starts it at the first sample
+    pub baseline_warmup_samples: u64,
+}
+
+impl NormalizationConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.epsilon.is_finite() || self.epsilon < 0.0 {
+            return Err("normalization.epsilon must not be negative".to_string());
+        }
+        if !(0.1..=600.0).contains(&self.window_secs) {
+            return Err("normalization.window_secs must be between 0.1 and 600".to_string());
+        }
+        if !(0.0..=100.0).contains(&self.low_percentile)
+            || !(0.0..=100.0).contains(&self.high_percentile)
+            || self.low_percentile >= self.high_percentile
+        {
+            return Err(
+                "normalization.low_percentile must be below high_percentile, both between 0 and 100".to_string(),
+            );
+        }
+        if self.baseline_warmup_samples > MAX_BASELINE_WARMUP_SAMPLES {
+            return Err(format!(
+                "normalization.baseline_warmup_samples must be at most {}",
+                MAX_BASELINE_WARMUP_SAMPLES
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn window(&self) -> Duration {
+        Duration::from_secs_f64(self.window_secs)
+    }
+}
+
+impl Default for NormalizationConfig {
+    fn default() -> Self {
+        Self {
+            mode: NormalizationMode::RelativeToBaseline,
+            epsilon: 1.0,
+            window_secs: 10.0,
+            low_percentile: 5.0,
+            high_percentile: 95.0,
+            window_relative_raw: false,
+            baseline_warmup_samples: 50,
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+/// Placeholder in OSC addresses that is replaced by the output zone number
+pub const ZONE_PLACEHOLDER: &str = "{zone}";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OscConfig {
+    pub enabled: bool,
+    /// Destination host name or IP address
+    pub host: String,
+    pub port: u16,
+    /// Address of each zone's normalized value as a float, e.g. `/dildonica/zone/{zone}`
+    pub value_address: String,
+    /// Address of gate events, an int 1 when a zone rises above
+    /// `gate_threshold` and 0 when it falls below again
+    pub gate_address: String,
+    pub gate_threshold: f64,
+    /// Value messages per second and zone, 0 for no limit. Gate events are
+    /// never held back.
+    pub max_rate_hz: f64,
+}
+
+impl OscConfig {
+    /// `template` with the zone placeholder filled in.
+    pub fn address(template: &str, zone: usize) -> String {
+        template.replace(ZONE_PLACEHOLDER, &zone.to_string())
+    }
+
+    /// OSC addresses must start with a slash, receivers drop anything else.
+    pub fn is_valid_address(template: &str) -> bool {
+        template.starts_with('/')
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_rate_hz < 0.0 {
+            return Err("osc.max_rate_hz must not be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "127.0.0.1".to_string(),
+            // SuperCollider's language port
+            port: 57120,
+            value_address: "/dildonica/zone/{zone}".to_string(),
+            gate_address: "/dildonica/note/{zone}".to_string(),
+            gate_threshold: 0.1,
+            max_rate_hz: 100.0,
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use serde_json::Value;
+use thiserror::Error;
+
+/// Prefix of environment variables that override config fields; other
+/// `DILDONICA_*` variables are left to whatever else reads them
+const ENV_PREFIX: &str = "DILDONICA_SET_";
+/// Config keys holding secrets, matched against the end of the key,
+/// case-insensitively
+const SENSITIVE_KEYS: &[&str] = &["token", "password", "secret", "credentials", "api_key"];
+
+#[derive(Error, Debug)]
+pub enum OverrideError {
+    #[error("Invalid override `{0}`, expected KEY=VALUE")]
+    InvalidSyntax(String),
+    #[error("Unknown config key `{0}`")]
+    UnknownKey(String),
+    #[error("Config key `{key}` expects {expected}, got {found}")]
+    TypeMismatch {
+        key: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+/// A single `key.path=value` override applied on top of the loaded config.
+/// Overridden fields keep their file value when the config is saved.
+#[derive(Debug, Clone)]
+pub struct ConfigOverride {
+    /// Dotted path into the config, e.g. `midi.note_config.threshold`
+    pub key: String,
+    pub value: Value,
+    /// Value the field had before the override, written back on save
+    pub original: Value,
+}
+
+impl ConfigOverride {
+    /// Parses `key.path=value`. The value is read as JSON when possible
+    /// (numbers, booleans, arrays) and as a plain string otherwise.
+    pub fn parse(s: &str) -> Result<Self, OverrideError> {
+        let (key, raw_value) = s
+            .split_once('=')
+            .filter(|(key, _)| !key.trim().is_empty())
+            .ok_or_else(|| OverrideError::InvalidSyntax(s.to_string()))?;
+        Ok(Self::new(key.trim(), raw_value.trim()))
+    }
+
+    fn new(key: &str, raw_value: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            value: serde_json::from_str(raw_value)
+                .unwrap_or_else(|_| Value::String(raw_value.to_string())),
+            original: Value::Null,
+        }
+    }
+}
+
+/// Whether the config key `key`, a field name or dotted path, holds a secret
+/// that is never logged or exported
+pub fn is_sensitive(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SENSITIVE_KEYS.iter().any(|sensitive| key.ends_with(sensitive))
+}
+
+/// Collects overrides from `DILDONICA_SET_SECTION__FIELD=value` environment
+/// variables, where `__` separates path segments.
+pub fn overrides_from_env() -> Vec<ConfigOverride> {
+    overrides_from_vars(std::env::vars())
+}
+
+fn overrides_from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Vec<ConfigOverride> {
+    vars.into_iter()
+        .filter_map(|(name, value)| {
+            let path = name.strip_prefix(ENV_PREFIX)?;
+            let key = path
+                .split("__")
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>()
+                .join(".");
+            Some(ConfigOverride::new(&key, &value))
+        })
+        .collect()
+}
+
+/// Applies `config_override` to `config`, recording the previous value.
+pub fn apply_override(
+    config: &mut Value,
+    config_override: &mut ConfigOverride,
+) -> Result<(), OverrideError> {
+    let unknown_key = || OverrideError::UnknownKey(config_override.key.clone());
+
+    let mut field = &mut *config;
+    for segment in config_override.key.split('.') {
+        field = field.get_mut(segment).ok_or_else(unknown_key)?;
+    }
+
+    // Strings like `41` would parse as numbers, so turn them back into the string the field wants
+    let mut value = config_override.value.clone();
+    if field.is_string() && !value.is_string() {
+        value = Value::String(value.to_string());
+    }
+    if !field.is_null() && type_name(field) != type_name(&value) {
+        return Err(OverrideError::TypeMismatch {
+            key: config_override.key.clone(),
+            expected: type_name(field),
+            found: type_name(&value),
+        });
+    }
+
+    config_override.original = std::mem::replace(field, value);
+    Ok(())
+}
+
+/// Puts the pre-override value back, so saving doesn't persist temporary values.
+pub fn restore_original(config: &mut Value, config_override: &ConfigOverride) {
+    let mut field = &mut *config;
+    for segment in config_override.key.split('.') {
+        match field.get_mut(segment) {
+            Some(next) => field = next,
+            None => return,
+        }
+    }
+    *field = config_override.original.clone();
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "a section",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn parse_reads_json_values_and_falls_back_to_strings() {
+        let parsed = ConfigOverride::parse("midi.note_config.threshold = 0.05").unwrap();
+        assert_eq!(parsed.key, "midi.note_config.threshold");
+        assert_eq!(parsed.value, json!(0.05));
+        assert_eq!(ConfigOverride::parse("zone_map=[1,0]").unwrap().value, json!([1, 0]));
+        assert_eq!(ConfigOverride::parse("midi.method=Notes").unwrap().value, json!("Notes"));
+        assert_eq!(ConfigOverride::parse("a.b=x=y").unwrap().value, json!("x=y"));
+    }
+
+    #[test]
+    fn parse_rejects_a_missing_key_or_value() {
+        for s in ["midi.method", "=Notes", "  =1", ""] {
+            assert!(matches!(ConfigOverride::parse(s), Err(OverrideError::InvalidSyntax(_))), "{}", s);
+        }
+    }
+
+    #[test]
+    fn only_prefixed_variables_are_overrides() {
+        let overrides = overrides_from_vars(vars(&[
+            ("DILDONICA_SET_MIDI__NOTE_CONFIG__BASE_NOTE", "48"),
+            ("DILDONICA_SET_EXPONENTIAL_ALPHA", "0.01"),
+            ("DILDONICA_LOG", "debug"),
+            ("DILDONICA_HTTP_PORT", "8080"),
+            ("PATH", "/usr/bin"),
+        ]));
+        let keys: Vec<_> = overrides.iter().map(|o| (o.key.as_str(), o.value.clone())).collect();
+        assert_eq!(
+            keys,
+            [("midi.note_config.base_note", json!(48)), ("exponential_alpha", json!(0.01))]
+        );
+    }
+
+    #[test]
+    fn apply_and_restore_round_trip() {
+        let mut config = json!({ "midi": { "output_port": "Synth", "note_config": { "base_note": 60 } } });
+        let original = config.clone();
+        let mut base_note = ConfigOverride::parse("midi.note_config.base_note=48").unwrap();
+        // A number for a string field is taken as that string
+        let mut port = ConfigOverride::parse("midi.output_port=41").unwrap();
+        apply_override(&mut config, &mut base_note).unwrap();
+        apply_override(&mut config, &mut port).unwrap();
+        assert_eq!(config["midi"]["note_config"]["base_note"], json!(48));
+        assert_eq!(config["midi"]["output_port"], json!("41"));
+
+        restore_original(&mut config, &port);
+        restore_original(&mut config, &base_note);
+        assert_eq!(config, original);
+    }
+
+    #[test]
+    fn apply_rejects_unknown_keys_and_wrong_types() {
+        let mut config = json!({ "midi": { "virtual_port": false } });
+        let mut unknown = ConfigOverride::parse("midi.nope=1").unwrap();
+        assert!(matches!(apply_override(&mut config, &mut unknown), Err(OverrideError::UnknownKey(key)) if key == "midi.nope"));
+        let mut mismatch = ConfigOverride::parse("midi.virtual_port=3").unwrap();
+        assert!(matches!(
+            apply_override(&mut config, &mut mismatch),
+            Err(OverrideError::TypeMismatch { expected: "a boolean", found: "a number", .. })
+        ));
+        assert_eq!(config, json!({ "midi": { "virtual_port": false } }));
+    }
+
+    #[test]
+    fn secrets_are_matched_by_their_key_suffix() {
+        assert!(is_sensitive("http.token"));
+        assert!(is_sensitive("OSC.API_KEY"));
+        assert!(!is_sensitive("midi.note_config.threshold"));
+    }
+}
@@ -0,0 +1,61 @@
+use super::zones::NUM_ZONES;
+use serde::{Deserialize, Serialize};
+
+/// Narrowest window the plot zooms to, in seconds
+pub const MIN_PLOT_DURATION_SECS: f64 = 0.25;
+/// Widest window the plot zooms to, in seconds; the plot history keeps this
+/// much whatever the window, so zooming out and scrolling back show it
+pub const MAX_PLOT_DURATION_SECS: f64 = 120.0;
+
+/// Default line colors of the zones, the Okabe-Ito palette that stays
+/// distinguishable with the common color vision deficiencies; its black is
+/// a grey, to show on the dark theme
+pub const ZONE_PALETTE: [[u8; 3]; NUM_ZONES] = [
+    [230, 159, 0],
+    [86, 180, 233],
+    [0, 158, 115],
+    [240, 228, 66],
+    [0, 114, 178],
+    [213, 94, 0],
+    [204, 121, 167],
+    [153, 153, 153],
+];
+
+/// Which of a zone's values its plot line shows. Every point keeps them
+/// all, so switching loses no history.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum PlotSource {
+    /// The sensor value as the device sent it
+    Raw,
+    #[default]
+    Normalized,
+    /// Rate of change of the normalized value, per second
+    Derivative,
+    /// Held peak of the normalized magnitude
+    Peak,
+}
+
+impl PlotSource {
+    pub fn all() -> &'static [PlotSource] {
+        &[PlotSource::Raw, PlotSource::Normalized, PlotSource::Derivative, PlotSource::Peak]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlotSource::Raw => "Raw",
+            PlotSource::Normalized => "Normalized",
+            PlotSource::Derivative => "Derivative",
+            PlotSource::Peak => "Peak",
+        }
+    }
+
+    /// Short form for the plot legend
+    pub fn abbreviation(&self) -> &'static str {
+        match self {
+            PlotSource::Raw => "raw",
+            PlotSource::Normalized => "norm",
+            PlotSource::Derivative => "deriv",
+            PlotSource::Peak => "peak",
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Limits of the zone self-test, run from the Configuration tab or by the
+/// `selftest` command. Noise is the standard deviation of a zone's raw
+/// value relative to its mean while nothing touches the instrument.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelfTestConfig {
+    /// How long samples are collected, from the first one
+    pub duration_secs: f64,
+    /// An enabled zone sending fewer samples per second fails
+    pub min_rate_hz: f64,
+    /// A zone with more of its samples missing a reading than this fraction warns
+    pub max_missing_fraction: f64,
+    pub noise_warn: f64,
+    pub noise_fail: f64,
+}
+
+impl SelfTestConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.duration_secs > 0.0 && self.duration_secs <= 600.0) {
+            return Err("selftest.duration_secs must be between 0 and 600".to_string());
+        }
+        if self.min_rate_hz < 0.0 {
+            return Err("selftest.min_rate_hz must not be negative".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.max_missing_fraction) {
+            return Err("selftest.max_missing_fraction must be between 0 and 1".to_string());
+        }
+        if self.noise_warn < 0.0 || self.noise_fail < self.noise_warn {
+            return Err("selftest.noise_warn must not be negative and noise_fail at least noise_warn".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs_f64(self.duration_secs)
+    }
+}
+
+impl Default for SelfTestConfig {
+    fn default() -> Self {
+        Self {
+            duration_secs: 5.0,
+            min_rate_hz: 20.0,
+            max_missing_fraction: 0.1,
+            noise_warn: 0.005,
+            noise_fail: 0.02,
+        }
+    }
+}
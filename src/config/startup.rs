@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Longest `StartupAction::Calibrate` allowed
+pub const MAX_STARTUP_CALIBRATION_SECS: f64 = 600.0;
+
+/// One step of the startup sequence
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum StartupAction {
+    /// Connects to the device at this address instead of `--device`; without
+    /// this step the device is connected at launch. Done once samples arrive.
+    ConnectTo(String),
+    /// Waits for the next sample from the device
+    WaitForSamples,
+    /// Calibrates the baselines for this many seconds, don't touch meanwhile
+    Calibrate(f64),
+    /// Switches to the zone map preset of this name
+    LoadPreset(String),
+    /// Writes the zone configs last written and verified, from the config
+    /// history, to the device again
+    WriteDeviceConfig,
+    /// Unmutes or mutes MIDI output
+    SetMidi(bool),
+}
+
+impl fmt::Display for StartupAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StartupAction::ConnectTo(address) => write!(f, "connect to {}", address),
+            StartupAction::WaitForSamples => write!(f, "wait for samples"),
+            StartupAction::Calibrate(secs) => write!(f, "calibrate for {} s", secs),
+            StartupAction::LoadPreset(name) => write!(f, "load zone map preset `{}`", name),
+            StartupAction::WriteDeviceConfig => write!(f, "write the device config"),
+            StartupAction::SetMidi(true) => write!(f, "enable MIDI"),
+            StartupAction::SetMidi(false) => write!(f, "disable MIDI"),
+        }
+    }
+}
+
+/// Steps run in order after launch when connected to a device, see
+/// `startup::run`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StartupConfig {
+    pub enabled: bool,
+    pub actions: Vec<StartupAction>,
+}
+
+impl StartupConfig {
+    /// Presets are looked up when the step runs, so deleting one doesn't
+    /// make the config unreadable
+    pub fn validate(&self) -> Result<(), String> {
+        let mut connects = 0;
+        for action in &self.actions {
+            match action {
+                StartupAction::ConnectTo(address) => {
+                    if address.trim().is_empty() {
+                        return Err("startup ConnectTo needs an address".to_string());
+                    }
+                    connects += 1;
+                }
+                StartupAction::Calibrate(secs) => {
+                    if !(*secs > 0.0 && *secs <= MAX_STARTUP_CALIBRATION_SECS) {
+                        return Err(format!(
+                            "startup Calibrate must be longer than 0 and at most {} seconds",
+                            MAX_STARTUP_CALIBRATION_SECS
+                        ));
+                    }
+                }
+                StartupAction::LoadPreset(name) => {
+                    if name.trim().is_empty() {
+                        return Err("startup LoadPreset needs a preset name".to_string());
+                    }
+                }
+                StartupAction::WaitForSamples | StartupAction::WriteDeviceConfig | StartupAction::SetMidi(_) => {}
+            }
+        }
+        if connects > 1 {
+            return Err("startup may connect only once".to_string());
+        }
+        Ok(())
+    }
+
+    /// The address of the `ConnectTo` step, if the sequence has one
+    pub fn connect_to(&self) -> Option<&str> {
+        self.actions.iter().find_map(|action| match action {
+            StartupAction::ConnectTo(address) => Some(address.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Whether the sequence runs at all
+    pub fn is_active(&self) -> bool {
+        self.enabled && !self.actions.is_empty()
+    }
+}
+
+impl Default for StartupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            actions: Vec::new(),
+        }
+    }
+}
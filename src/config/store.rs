@@ -0,0 +1,443 @@
+use super::app::{AppConfig, ConfigError, LoadFailure};
+use std::fs;
+use std::path::Path;
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{error, info, info_span, warn};
+
+/// Minimum time between two saves of the config file
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Wraps the shared `AppConfig` and debounces writes to disk: widgets call
+/// `mark_dirty()` on every change, and `flush_if_due()` writes the file at
+/// most once per `SAVE_INTERVAL`.
+///
+/// The store also notices when the file is edited externally. Such edits are
+/// applied immediately unless there are unsaved in-app changes, in which case
+/// they are held in `pending_external` until the user picks a side.
+///
+/// A file that failed to load puts the store in safe mode: it runs on the
+/// defaults and saves nothing, until the file loads (fixed externally or by
+/// `retry_load`) or the user accepts the defaults.
+#[derive(Debug)]
+pub struct ConfigStore {
+    config: AppConfig,
+    dirty: bool,
+    last_save: Option<Instant>,
+    /// Modification time of the file as last loaded or saved by us
+    file_mtime: Option<SystemTime>,
+    pending_external: Option<AppConfig>,
+    /// Incremented every time an external edit replaces the config
+    generation: u64,
+    safe_mode: Option<LoadFailure>,
+}
+
+impl ConfigStore {
+    pub fn new(mut config: AppConfig) -> Self {
+        let file_mtime = file_mtime(&config);
+        let safe_mode = config.load_failure.take();
+        Self {
+            config,
+            dirty: false,
+            last_save: None,
+            file_mtime,
+            pending_external: None,
+            generation: 0,
+            safe_mode,
+        }
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Changes whenever the config was replaced by an external edit, so
+    /// consumers can reset state derived from the old values.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Saves if there are unsaved changes and the last save was long enough ago.
+    pub fn flush_if_due(&mut self) {
+        let due = self.last_save.is_none_or(|t| t.elapsed() >= SAVE_INTERVAL);
+        if self.dirty && due {
+            self.flush();
+        }
+    }
+
+    /// Saves immediately if there are unsaved changes.
+    pub fn flush(&mut self) {
+        // Never clobber an external edit the user hasn't decided on yet, nor
+        // a file being fixed in safe mode
+        if !self.dirty || self.pending_external.is_some() || self.safe_mode.is_some() {
+            return;
+        }
+        // A failed save stays dirty and is retried after the next interval
+        match self.config.save_to_file() {
+            Ok(()) => {
+                self.dirty = false;
+                self.file_mtime = file_mtime(&self.config);
+            }
+            Err(e) => error!("Failed to save app config: {}", e),
+        }
+        self.last_save = Some(Instant::now());
+    }
+
+    /// Reloads the config file if it was modified by someone else since we
+    /// last loaded or saved it.
+    pub fn check_external_change(&mut self) {
+        let mtime = file_mtime(&self.config);
+        if mtime.is_none() || mtime == self.file_mtime {
+            return;
+        }
+        self.file_mtime = mtime;
+
+        let path = self.config.config_path.clone();
+        let _span = info_span!("config_reload", path = %path.display()).entered();
+        let external = match self.load(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Ignoring external edit of {}: {}", path.display(), e);
+                if let Some(safe_mode) = &mut self.safe_mode {
+                    *safe_mode = LoadFailure::new(&path, &e, safe_mode.preserved.take());
+                }
+                return;
+            }
+        };
+
+        if self.dirty {
+            warn!("Config file changed on disk while there are unsaved changes");
+            self.pending_external = Some(external);
+        } else {
+            info!("Reloaded app config from {}", path.display());
+            self.leave_safe_mode();
+            self.replace(external);
+        }
+    }
+
+    /// Why the config file failed to load, while in safe mode
+    pub fn safe_mode(&self) -> Option<&LoadFailure> {
+        self.safe_mode.as_ref()
+    }
+
+    /// Tries loading the config file again in safe mode, or the preserved
+    /// broken file while there is none; that one is then saved as the
+    /// config file. Unsaved in-app changes are dropped.
+    pub fn retry_load(&mut self) -> Result<(), String> {
+        let Some(safe_mode) = &self.safe_mode else {
+            return Ok(());
+        };
+        let path = self.config.config_path.clone();
+        let from_preserved = !path.exists();
+        let source = match &safe_mode.preserved {
+            Some(preserved) if from_preserved => preserved.clone(),
+            _ => path.clone(),
+        };
+        match self.load(&source) {
+            Ok(config) => {
+                info!("Loaded app config from {}", source.display());
+                self.pending_external = None;
+                self.leave_safe_mode();
+                self.replace(config);
+                self.file_mtime = file_mtime(&self.config);
+                self.dirty = from_preserved;
+                self.flush();
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Still failing to load {}: {}", source.display(), e);
+                let failure = LoadFailure::new(&source, &e, safe_mode.preserved.clone());
+                let error = failure.error.clone();
+                self.safe_mode = Some(failure);
+                Err(error)
+            }
+        }
+    }
+
+    /// Leaves safe mode for good with the config as it is, saving it.
+    pub fn accept_defaults(&mut self) {
+        if self.safe_mode.is_some() {
+            self.leave_safe_mode();
+            self.dirty = true;
+            self.flush();
+        }
+    }
+
+    /// `path` parsed as the config of this store, with the same overrides
+    fn load(&self, path: &Path) -> Result<AppConfig, ConfigError> {
+        let mut config = AppConfig::parse_file(path)?;
+        config.config_path = self.config.config_path.clone();
+        config.profile = self.config.profile.clone();
+        config.apply_overrides(self.config.overrides.clone())?;
+        Ok(config)
+    }
+
+    fn leave_safe_mode(&mut self) {
+        if self.safe_mode.take().is_some() {
+            info!("Leaving safe mode, saving the config again");
+        }
+    }
+
+    /// Whether an external edit conflicts with unsaved in-app changes.
+    pub fn has_conflict(&self) -> bool {
+        self.pending_external.is_some()
+    }
+
+    /// Replaces the whole config with an already validated one, e.g. from the
+    /// HTTP API, and saves it like an in-app change.
+    pub fn update(&mut self, config: AppConfig) {
+        self.replace(config);
+        self.dirty = true;
+    }
+
+    /// Resolves a conflict in favour of the file on disk.
+    pub fn accept_external(&mut self) {
+        if let Some(external) = self.pending_external.take() {
+            self.dirty = false;
+            self.leave_safe_mode();
+            self.replace(external);
+        }
+    }
+
+    /// Resolves a conflict in favour of the in-app changes, overwriting the file.
+    pub fn keep_local(&mut self) {
+        self.pending_external = None;
+        self.leave_safe_mode();
+        self.flush();
+    }
+
+    fn replace(&mut self, config: AppConfig) {
+        self.config = config;
+        self.generation += 1;
+    }
+}
+
+fn file_mtime(config: &AppConfig) -> Option<SystemTime> {
+    fs::metadata(&config.config_path)
+        .and_then(|m| m.modified())
+        .ok()
+}
+
+impl Deref for ConfigStore {
+    type Target = AppConfig;
+
+    fn deref(&self) -> &AppConfig {
+        &self.config
+    }
+}
+
+impl DerefMut for ConfigStore {
+    fn deref_mut(&mut self) -> &mut AppConfig {
+        &mut self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    fn store_in(dir: &TempDir) -> ConfigStore {
+        let config = AppConfig {
+            config_path: dir.path().join("config.json"),
+            ..AppConfig::default()
+        };
+        ConfigStore::new(config)
+    }
+
+    fn tmp_path(store: &ConfigStore) -> PathBuf {
+        let mut tmp_path = store.config_path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        PathBuf::from(tmp_path)
+    }
+
+    fn saved_alpha(store: &ConfigStore) -> f64 {
+        AppConfig::parse_file(&store.config_path).unwrap().exponential_alpha
+    }
+
+    #[test]
+    fn crash_before_rename_leaves_the_old_file() {
+        let dir = TempDir::new().unwrap();
+        let mut store = store_in(&dir);
+        store.exponential_alpha = 0.25;
+        store.mark_dirty();
+        store.flush();
+
+        // A save that died after writing the temp file, before renaming it
+        let mut unsaved = store.config.clone();
+        unsaved.exponential_alpha = 0.5;
+        fs::write(tmp_path(&store), serde_json::to_string_pretty(&unsaved).unwrap()).unwrap();
+
+        assert_eq!(saved_alpha(&store), 0.25);
+        let loaded = AppConfig::load_from_file(&store.config_path, None);
+        assert_eq!(loaded.exponential_alpha, 0.25);
+        assert!(loaded.load_failure.is_none());
+    }
+
+    #[test]
+    fn interrupted_temp_file_never_replaces_the_config() {
+        let dir = TempDir::new().unwrap();
+        let mut store = store_in(&dir);
+        store.exponential_alpha = 0.25;
+        store.mark_dirty();
+        store.flush();
+
+        // Half a file, as left by a write cut short
+        fs::write(tmp_path(&store), "{\n  \"version\": 1,\n  \"midi\": {").unwrap();
+        store.check_external_change();
+        assert_eq!(store.exponential_alpha, 0.25);
+        assert_eq!(saved_alpha(&store), 0.25);
+
+        // The next save writes the temp file afresh and renames it in place
+        store.last_save = None;
+        store.exponential_alpha = 0.5;
+        store.mark_dirty();
+        store.flush();
+        assert_eq!(saved_alpha(&store), 0.5);
+        assert!(!tmp_path(&store).exists());
+    }
+
+    #[test]
+    fn failed_temp_write_keeps_the_old_file_and_stays_dirty() {
+        let dir = TempDir::new().unwrap();
+        let mut store = store_in(&dir);
+        store.exponential_alpha = 0.25;
+        store.mark_dirty();
+        store.flush();
+
+        // A directory in the way makes writing the temp file fail
+        fs::create_dir(tmp_path(&store)).unwrap();
+        store.exponential_alpha = 0.5;
+        store.mark_dirty();
+        store.flush();
+        assert!(store.dirty);
+        assert_eq!(saved_alpha(&store), 0.25);
+    }
+
+    #[test]
+    fn edits_within_the_interval_are_written_once() {
+        let dir = TempDir::new().unwrap();
+        let mut store = store_in(&dir);
+        store.exponential_alpha = 0.1;
+        store.mark_dirty();
+        store.flush_if_due();
+        assert_eq!(saved_alpha(&store), 0.1);
+
+        for alpha in [0.2, 0.3, 0.4] {
+            store.exponential_alpha = alpha;
+            store.mark_dirty();
+            store.flush_if_due();
+        }
+        assert!(store.dirty);
+        assert_eq!(saved_alpha(&store), 0.1);
+
+        // Once the interval is over, the last edit is written in one go
+        store.last_save = Some(Instant::now() - SAVE_INTERVAL);
+        store.flush_if_due();
+        assert!(!store.dirty);
+        assert_eq!(saved_alpha(&store), 0.4);
+    }
+
+    /// A store loaded from `name` in `dir` holding `contents`
+    fn load_in(dir: &TempDir, name: &str, contents: &str) -> ConfigStore {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        ConfigStore::new(AppConfig::load_from_file(&path, None))
+    }
+
+    fn valid_json(alpha: f64) -> String {
+        let config = AppConfig {
+            exponential_alpha: alpha,
+            ..AppConfig::default()
+        };
+        serde_json::to_string_pretty(&config).unwrap()
+    }
+
+    const BROKEN_JSON: &str = "{\n  \"version\": 1,\n  \"exponential_alpha\": 0.5,,\n}";
+
+    #[test]
+    fn a_broken_file_is_moved_aside_and_never_saved_over() {
+        let dir = TempDir::new().unwrap();
+        let mut store = load_in(&dir, "config.json", BROKEN_JSON);
+        let failure = store.safe_mode().unwrap().clone();
+        assert_eq!(failure.location, Some((3, 28)));
+        let preserved = failure.preserved.unwrap();
+        let name = preserved.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("config.json.broken-"), "{}", name);
+        assert_eq!(fs::read_to_string(&preserved).unwrap(), BROKEN_JSON);
+        assert!(!store.config_path.exists());
+        assert_eq!(store.exponential_alpha, AppConfig::default().exponential_alpha);
+
+        store.exponential_alpha = 0.25;
+        store.mark_dirty();
+        store.flush();
+        assert!(!store.config_path.exists());
+        assert_eq!(fs::read_to_string(&preserved).unwrap(), BROKEN_JSON);
+    }
+
+    #[test]
+    fn an_invalid_value_is_safe_mode_without_a_location() {
+        let dir = TempDir::new().unwrap();
+        let store = load_in(&dir, "config.json", &valid_json(2.0));
+        let failure = store.safe_mode().unwrap();
+        assert!(failure.error.contains("exponential_alpha"), "{}", failure.error);
+        assert_eq!(failure.location, None);
+    }
+
+    #[test]
+    fn retrying_reads_the_preserved_file_once_fixed() {
+        let dir = TempDir::new().unwrap();
+        let mut store = load_in(&dir, "config.json", BROKEN_JSON);
+        assert!(store.retry_load().is_err());
+        assert!(store.safe_mode().is_some());
+
+        let preserved = store.safe_mode().unwrap().preserved.clone().unwrap();
+        fs::write(&preserved, valid_json(0.5)).unwrap();
+        store.retry_load().unwrap();
+        assert!(store.safe_mode().is_none());
+        assert_eq!(store.exponential_alpha, 0.5);
+        // Saved back as the config file, the moved one kept
+        assert_eq!(saved_alpha(&store), 0.5);
+        assert!(preserved.exists());
+    }
+
+    #[test]
+    fn a_file_fixed_externally_ends_safe_mode() {
+        let dir = TempDir::new().unwrap();
+        let mut store = load_in(&dir, "config.toml", "version = 1\nexponential_alpha = \n");
+        assert_eq!(store.safe_mode().unwrap().location, Some((2, 21)));
+        // Still broken, the newer error is kept
+        fs::write(&store.config_path, "version = 1\n[midi\n").unwrap();
+        store.check_external_change();
+        assert_eq!(store.safe_mode().unwrap().location, Some((2, 6)));
+
+        let config = AppConfig {
+            exponential_alpha: 0.5,
+            ..AppConfig::default()
+        };
+        fs::write(&store.config_path, toml::to_string_pretty(&config).unwrap()).unwrap();
+        // The file was rewritten within the same mtime tick
+        store.file_mtime = None;
+        store.check_external_change();
+        assert!(store.safe_mode().is_none());
+        assert_eq!(store.exponential_alpha, 0.5);
+
+        // Saving again
+        store.exponential_alpha = 0.25;
+        store.mark_dirty();
+        store.flush();
+        assert_eq!(AppConfig::parse_file(&store.config_path).unwrap().exponential_alpha, 0.25);
+    }
+
+    #[test]
+    fn accepting_the_defaults_saves_them_and_keeps_the_broken_file() {
+        let dir = TempDir::new().unwrap();
+        let mut store = load_in(&dir, "config.json", BROKEN_JSON);
+        let preserved = store.safe_mode().unwrap().preserved.clone().unwrap();
+        store.accept_defaults();
+        assert!(store.safe_mode().is_none());
+        assert_eq!(saved_alpha(&store), AppConfig::default().exponential_alpha);
+        assert_eq!(fs::read_to_string(preserved).unwrap(), BROKEN_JSON);
+    }
+}
@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SynthWaveform {
+    Sine,
+    /// Two-operator FM, see `SynthConfig::fm_ratio` and `fm_index`
+    Fm,
+}
+
+/// Built-in synthesizer, one voice per zone. Pitches follow the MIDI note
+/// mapping (`midi.note_config.base_note` and `scale`). Only used by builds with
+/// the `synth` feature, but always kept in the config so files stay portable.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SynthConfig {
+    pub enabled: bool,
+    pub waveform: SynthWaveform,
+    /// Output level, 0..=1
+    pub volume: f64,
+    /// Voice amplitude per unit of normalized magnitude, capped at 1
+    pub sensitivity: f64,
+    pub attack_ms: f64,
+    pub release_ms: f64,
+    /// Modulator frequency as a multiple of the carrier
+    pub fm_ratio: f64,
+    /// Modulation depth in radians
+    pub fm_index: f64,
+}
+
+impl SynthConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.volume) {
+            return Err("synth.volume must be between 0 and 1".to_string());
+        }
+        if self.attack_ms < 0.0 || self.release_ms < 0.0 {
+            return Err("synth.attack_ms and synth.release_ms must not be negative".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for SynthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            waveform: SynthWaveform::Sine,
+            volume: 0.5,
+            sensitivity: 5.0,
+            attack_ms: 10.0,
+            release_ms: 200.0,
+            fm_ratio: 2.0,
+            fm_index: 1.5,
+        }
+    }
+}
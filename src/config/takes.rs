@@ -0,0 +1,69 @@
+use super::midi::PatchBinding;
+use super::zones::NUM_ZONES;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Directory of the takes in the per-user data directory
+const TAKES_DIR_NAME: &str = "takes";
+
+/// The rolling buffer of the raw samples of the last minutes, kept whether
+/// or not anything is recorded, so a take can be saved after the fact
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TakeBufferConfig {
+    pub enabled: bool,
+    /// Samples older than this are dropped
+    pub minutes: f64,
+    /// Memory the buffer may take; at high sample rates it then holds less
+    /// than `minutes`
+    pub memory_mb: f64,
+    /// How much of the end of the buffer a take is
+    pub save_secs: f64,
+    /// Gesture that saves a take, like the button
+    pub save_binding: Option<PatchBinding>,
+    /// Where takes are saved, `takes` in the data directory if not set
+    pub directory: Option<PathBuf>,
+}
+
+impl TakeBufferConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.minutes > 0.0 && self.minutes <= 60.0) {
+            return Err("takes.minutes must be between 0 and 60".to_string());
+        }
+        if !(1.0..=1024.0).contains(&self.memory_mb) {
+            return Err("takes.memory_mb must be between 1 and 1024".to_string());
+        }
+        if !(self.save_secs > 0.0 && self.save_secs <= self.minutes * 60.0) {
+            return Err("takes.save_secs must be positive and at most takes.minutes".to_string());
+        }
+        if self.save_binding.is_some_and(|binding| binding.zone >= NUM_ZONES) {
+            return Err("takes.save_binding zone is out of range".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn memory_bytes(&self) -> usize {
+        (self.memory_mb * 1024.0 * 1024.0) as usize
+    }
+
+    pub fn directory(&self) -> PathBuf {
+        self.directory.clone().unwrap_or_else(|| match ProjectDirs::from("", "", "dildonica") {
+            Some(dirs) => dirs.data_dir().join(TAKES_DIR_NAME),
+            None => PathBuf::from(TAKES_DIR_NAME),
+        })
+    }
+}
+
+impl Default for TakeBufferConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            minutes: 5.0,
+            memory_mb: 32.0,
+            save_secs: 120.0,
+            save_binding: None,
+            directory: None,
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use super::device::{DildonicaZoneConfig, ZoneLimits, FIRMWARE_LIMITS};
+use super::zones::NUM_ZONES;
+use serde::{Deserialize, Serialize};
+
+/// Device zone configs that work as a starting point on one hardware
+/// revision, loaded into the editor or written with `config write
+/// --hw-template`
+#[derive(Debug, Clone, Copy)]
+pub struct HardwareTemplate {
+    /// What `--hw-template` takes
+    pub name: &'static str,
+    pub description: &'static str,
+    pub zones: [DildonicaZoneConfig; NUM_ZONES],
+    /// What the firmware on this revision stores, where it is less than
+    /// `FIRMWARE_LIMITS`
+    pub limits: ZoneLimits,
+}
+
+impl HardwareTemplate {
+    pub fn validate(&self) -> Result<(), String> {
+        self.limits
+            .validate()
+            .map_err(|e| format!("hardware template `{}`: {}", self.name, e))?;
+        for (zone, config) in self.zones.iter().enumerate() {
+            config
+                .validate_within(&self.limits)
+                .map_err(|e| format!("hardware template `{}` zone {}: {}", self.name, zone, e))?;
+        }
+        Ok(())
+    }
+}
+
+/// The hardware revision of the device at `address`, by template name,
+/// whose limits bound the zone configs edited and written for it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceHardware {
+    pub address: String,
+    pub template: String,
+}
+
+/// An enabled zone with these cycle count and comparator windows
+const fn zone(cycle_count_begin: u32, cycle_count_end: u32, comp_thresh_lo: u32, comp_thresh_hi: u32) -> DildonicaZoneConfig {
+    DildonicaZoneConfig {
+        enabled: true,
+        midi_control: 0,
+        cycle_count_begin,
+        cycle_count_end,
+        comp_thresh_lo,
+        comp_thresh_hi,
+    }
+}
+
+const fn disabled(config: DildonicaZoneConfig) -> DildonicaZoneConfig {
+    DildonicaZoneConfig {
+        enabled: false,
+        ..config
+    }
+}
+
+const REV_A: DildonicaZoneConfig = zone(1000, 10000, 100, 4000);
+/// Rev B's larger coils oscillate slower and swing further
+const REV_B: DildonicaZoneConfig = zone(2500, 24000, 250, 6500);
+const PROTOTYPE: DildonicaZoneConfig = zone(1500, 12000, 150, 4500);
+
+pub const HARDWARE_TEMPLATES: &[HardwareTemplate] = &[
+    HardwareTemplate {
+        name: "rev-a",
+        description: "Rev A, the first production boards",
+        zones: [REV_A; NUM_ZONES],
+        limits: FIRMWARE_LIMITS,
+    },
+    HardwareTemplate {
+        name: "rev-b",
+        description: "Rev B, with the larger coils",
+        zones: [REV_B; NUM_ZONES],
+        limits: FIRMWARE_LIMITS,
+    },
+    HardwareTemplate {
+        name: "prototype-6-zone",
+        description: "The six-zone prototype, the last two zones disabled",
+        zones: [
+            PROTOTYPE,
+            PROTOTYPE,
+            PROTOTYPE,
+            PROTOTYPE,
+            PROTOTYPE,
+            PROTOTYPE,
+            disabled(PROTOTYPE),
+            disabled(PROTOTYPE),
+        ],
+        limits: FIRMWARE_LIMITS,
+    },
+];
+
+/// The template called `name`, ignoring case
+pub fn hardware_template(name: &str) -> Option<&'static HardwareTemplate> {
+    HARDWARE_TEMPLATES
+        .iter()
+        .find(|template| template.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_template_passes_the_validator() {
+        for template in HARDWARE_TEMPLATES {
+            assert_eq!(template.validate(), Ok(()), "{}", template.name);
+        }
+    }
+
+    #[test]
+    fn template_names_are_unique_and_fit_the_command_line() {
+        for (index, template) in HARDWARE_TEMPLATES.iter().enumerate() {
+            assert!(!template.description.is_empty());
+            assert!(
+                template.name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-'),
+                "{}",
+                template.name
+            );
+            assert!(HARDWARE_TEMPLATES[..index]
+                .iter()
+                .all(|other| !other.name.eq_ignore_ascii_case(template.name)));
+        }
+    }
+
+    #[test]
+    fn templates_are_found_ignoring_case() {
+        assert_eq!(hardware_template("rev-b").unwrap().name, "rev-b");
+        assert_eq!(hardware_template("REV-B").unwrap().name, "rev-b");
+        assert!(hardware_template("rev-c").is_none());
+        assert!(hardware_template("").is_none());
+    }
+
+    #[test]
+    fn rev_a_is_the_defaults() {
+        let rev_a = hardware_template("rev-a").unwrap();
+        assert!(rev_a.zones.iter().all(|zone| *zone == DildonicaZoneConfig::default()));
+    }
+
+    #[test]
+    fn the_prototype_has_six_zones() {
+        let prototype = hardware_template("prototype-6-zone").unwrap();
+        let enabled: Vec<bool> = prototype.zones.iter().map(|zone| zone.enabled).collect();
+        assert_eq!(enabled, [true, true, true, true, true, true, false, false]);
+    }
+
+    #[test]
+    fn a_template_outside_its_limits_fails() {
+        let mut template = *hardware_template("rev-b").unwrap();
+        template.zones[3].cycle_count_end = template.limits.max_cycle_count + 1;
+        let error = template.validate().unwrap_err();
+        assert!(error.starts_with("hardware template `rev-b` zone 3: "), "{}", error);
+    }
+
+    #[test]
+    fn a_template_may_lower_its_limits_but_not_raise_them() {
+        let mut template = *hardware_template("rev-a").unwrap();
+        template.limits = ZoneLimits {
+            max_cycle_count: 20_000,
+            max_comp_thresh: 4000,
+        };
+        assert_eq!(template.validate(), Ok(()));
+        template.limits.max_comp_thresh = 3999;
+        let error = template.validate().unwrap_err();
+        assert_eq!(error, "hardware template `rev-a` zone 0: comparator thresholds must be at most 3999");
+        template.limits.max_cycle_count = FIRMWARE_LIMITS.max_cycle_count + 1;
+        let error = template.validate().unwrap_err();
+        assert!(error.starts_with("hardware template `rev-a`: limits must be at most"), "{}", error);
+    }
+}
@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Watching the device session for a notification stream that stopped
+/// while the connection still looks up
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    /// Without a sample for this long the connection is suspect
+    pub stall_secs: f64,
+    /// Still without one this much longer, it is dropped and made again
+    pub reconnect_secs: f64,
+}
+
+impl WatchdogConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.5..=600.0).contains(&self.stall_secs) {
+            return Err("watchdog.stall_secs must be between 0.5 and 600".to_string());
+        }
+        if !(0.0..=600.0).contains(&self.reconnect_secs) {
+            return Err("watchdog.reconnect_secs must be between 0 and 600".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn stall(&self) -> Duration {
+        Duration::from_secs_f64(self.stall_secs)
+    }
+
+    pub fn reconnect(&self) -> Duration {
+        Duration::from_secs_f64(self.reconnect_secs)
+    }
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            stall_secs: 5.0,
+            reconnect_secs: 5.0,
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Number of sensor zones on the device
+pub const NUM_ZONES: usize = 8;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ZoneMapError {
+    #[error("Zone map has {found} entries, expected {expected}")]
+    WrongLength { expected: usize, found: usize },
+    #[error("Zone {0} is out of range")]
+    OutOfRange(usize),
+    #[error("Zone {0} is mapped more than once")]
+    Duplicate(usize),
+    #[error("Zone group {0} needs at least two zones")]
+    GroupTooSmall(usize),
+    #[error("Zone {0} is in more than one group")]
+    Regrouped(usize),
+}
+
+/// How the values of a zone group become the value of its logical zone
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ZoneGroupReduction {
+    /// The member value of the largest magnitude
+    Max,
+    Mean,
+}
+
+impl ZoneGroupReduction {
+    pub fn all() -> &'static [ZoneGroupReduction] {
+        &[ZoneGroupReduction::Max, ZoneGroupReduction::Mean]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ZoneGroupReduction::Max => "Max",
+            ZoneGroupReduction::Mean => "Mean",
+        }
+    }
+
+    /// Reduces `values`, zero if there are none
+    pub fn reduce(&self, values: impl IntoIterator<Item = f64>) -> f64 {
+        match self {
+            ZoneGroupReduction::Max => values.into_iter().fold(0.0, |max, value| {
+                if value.abs() > f64::abs(max) { value } else { max }
+            }),
+            ZoneGroupReduction::Mean => {
+                let (sum, count) = values.into_iter().fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+                if count == 0 { 0.0 } else { sum / count as f64 }
+            }
+        }
+    }
+}
+
+/// What the zone map applies to
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ZoneMapScope {
+    /// Samples move to their output zone as they come in, for the plot,
+    /// the meters and MIDI alike
+    #[default]
+    Everything,
+    /// Samples keep their device zone, so the plot shows the device order;
+    /// only MIDI and the zone bindings play them as their output zone
+    MidiOnly,
+}
+
+/// The logical zones MIDI and the meters work with: one per zone group, at
+/// the position of its lowest output zone, and one per ungrouped output zone,
+/// numbered in that order
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogicalZones {
+    members: Vec<Vec<usize>>,
+    /// Logical zone of every output zone
+    logical: Vec<usize>,
+}
+
+impl LogicalZones {
+    /// `groups` as in `AppConfig::zone_groups`; zones out of range or in an
+    /// earlier group are ignored, so a group being edited still works.
+    pub fn new(groups: &[Vec<usize>], num_zones: usize) -> Self {
+        let mut group_of = vec![None; num_zones];
+        for (index, group) in groups.iter().enumerate() {
+            for &zone in group {
+                if zone < num_zones && group_of[zone].is_none() {
+                    group_of[zone] = Some(index);
+                }
+            }
+        }
+        let mut members: Vec<Vec<usize>> = Vec::new();
+        let mut logical = vec![0; num_zones];
+        let mut group_logical = vec![None; groups.len()];
+        for zone in 0..num_zones {
+            let index = match group_of[zone] {
+                Some(group) => *group_logical[group].get_or_insert_with(|| {
+                    members.push(Vec::new());
+                    members.len() - 1
+                }),
+                None => {
+                    members.push(Vec::new());
+                    members.len() - 1
+                }
+            };
+            members[index].push(zone);
+            logical[zone] = index;
+        }
+        Self { members, logical }
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// The logical zone `zone` belongs to
+    pub fn logical_zone(&self, zone: usize) -> usize {
+        self.logical[zone]
+    }
+
+    /// The output zones of logical zone `logical`
+    pub fn members(&self, logical: usize) -> &[usize] {
+        &self.members[logical]
+    }
+}
+
+/// A named zone map, e.g. for one way of mounting the instrument
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ZoneMapPreset {
+    pub name: String,
+    pub zone_map: Vec<usize>,
+}
+
+pub fn create_default_zone_map(num_zones: usize) -> Vec<usize> {
+    (0..num_zones).collect()
+}
+
+/// Checks that `zone_map` is a permutation of `0..num_zones`.
+pub fn validate_zone_map(zone_map: &[usize], num_zones: usize) -> Result<(), ZoneMapError> {
+    if zone_map.len() != num_zones {
+        return Err(ZoneMapError::WrongLength {
+            expected: num_zones,
+            found: zone_map.len(),
+        });
+    }
+
+    let mut used_zones = vec![false; num_zones];
+    for &zone in zone_map {
+        if zone >= num_zones {
+            return Err(ZoneMapError::OutOfRange(zone));
+        }
+        if used_zones[zone] {
+            return Err(ZoneMapError::Duplicate(zone));
+        }
+        used_zones[zone] = true;
+    }
+    Ok(())
+}
+
+/// The map for the instrument mounted the other way round: every output
+/// zone keeps its physical position, which is now sensed by the device zone
+/// at the opposite end. Unlike reversing the map, this flips the device side.
+pub fn mirror_zone_map(zone_map: &[usize], num_zones: usize) -> Vec<usize> {
+    zone_map
+        .iter()
+        .map(|&zone| num_zones.saturating_sub(1).saturating_sub(zone))
+        .collect()
+}
+
+/// Checks that every zone group has at least two zones in range and that no
+/// zone is in two groups.
+pub fn validate_zone_groups(groups: &[Vec<usize>], num_zones: usize) -> Result<(), ZoneMapError> {
+    let mut grouped = vec![false; num_zones];
+    for (index, group) in groups.iter().enumerate() {
+        if group.len() < 2 {
+            return Err(ZoneMapError::GroupTooSmall(index));
+        }
+        for &zone in group {
+            if zone >= num_zones {
+                return Err(ZoneMapError::OutOfRange(zone));
+            }
+            if grouped[zone] {
+                return Err(ZoneMapError::Regrouped(zone));
+            }
+            grouped[zone] = true;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn groups_take_the_place_of_their_lowest_zone() {
+        let zones = LogicalZones::new(&[vec![5, 2], vec![3, 7]], NUM_ZONES);
+        assert_eq!(zones.len(), 6);
+        let logical: Vec<usize> = (0..NUM_ZONES).map(|zone| zones.logical_zone(zone)).collect();
+        assert_eq!(logical, [0, 1, 2, 3, 4, 2, 5, 3]);
+        assert_eq!(zones.members(2), [2, 5]);
+        assert_eq!(zones.members(3), [3, 7]);
+        assert_eq!(zones.members(5), [6]);
+    }
+
+    #[test]
+    fn no_groups_keeps_every_zone() {
+        let zones = LogicalZones::new(&[], NUM_ZONES);
+        assert_eq!(zones.len(), NUM_ZONES);
+        assert!((0..NUM_ZONES).all(|zone| zones.logical_zone(zone) == zone && zones.members(zone) == [zone]));
+    }
+
+    #[test]
+    fn a_group_being_edited_ignores_bad_zones() {
+        let zones = LogicalZones::new(&[vec![0, 1, 99], vec![1, 2]], NUM_ZONES);
+        assert_eq!(zones.members(0), [0, 1]);
+        assert_eq!(zones.logical_zone(2), 1, "zone 1 stays in its first group");
+        assert_eq!(zones.members(1), [2]);
+    }
+
+    #[test]
+    fn reductions() {
+        assert_eq!(ZoneGroupReduction::Max.reduce([0.2, -0.5, 0.4]), -0.5);
+        assert_eq!(ZoneGroupReduction::Max.reduce([]), 0.0);
+        assert_eq!(ZoneGroupReduction::Mean.reduce([0.2, 0.4]), 0.30000000000000004);
+        assert_eq!(ZoneGroupReduction::Mean.reduce([]), 0.0);
+    }
+
+    #[test]
+    fn validating_groups() {
+        assert_eq!(validate_zone_groups(&[vec![0, 1], vec![2, 3, 4]], NUM_ZONES), Ok(()));
+        assert_eq!(validate_zone_groups(&[vec![0, 1], vec![2]], NUM_ZONES), Err(ZoneMapError::GroupTooSmall(1)));
+        assert_eq!(validate_zone_groups(&[vec![0, 8]], NUM_ZONES), Err(ZoneMapError::OutOfRange(8)));
+        assert_eq!(validate_zone_groups(&[vec![0, 1], vec![1, 2]], NUM_ZONES), Err(ZoneMapError::Regrouped(1)));
+    }
+
+    proptest! {
+        #[test]
+        fn exactly_the_permutations_are_valid_maps(zone_map in proptest::collection::vec(0..NUM_ZONES + 2, 0..NUM_ZONES + 2)) {
+            let mut sorted = zone_map.clone();
+            sorted.sort();
+            let permutation = sorted == create_default_zone_map(NUM_ZONES);
+            prop_assert_eq!(validate_zone_map(&zone_map, NUM_ZONES).is_ok(), permutation);
+        }
+
+        #[test]
+        fn shuffled_and_mirrored_maps_stay_valid(zone_map in Just(create_default_zone_map(NUM_ZONES)).prop_shuffle()) {
+            prop_assert_eq!(validate_zone_map(&zone_map, NUM_ZONES), Ok(()));
+            let mirrored = mirror_zone_map(&zone_map, NUM_ZONES);
+            prop_assert_eq!(validate_zone_map(&mirrored, NUM_ZONES), Ok(()));
+            prop_assert_eq!(mirror_zone_map(&mirrored, NUM_ZONES), zone_map);
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use crate::config::{DildonicaZoneConfig, NUM_ZONES};
+use crate::sample::Sample;
+use crate::selftest::ZoneSamples;
+use crate::sync::LockExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long the raw values are collected before and after the write
+pub const COMPARE_DURATION: Duration = Duration::from_secs(5);
+/// Changes of at least this fraction are highlighted
+pub const NOTABLE_CHANGE: f64 = 0.05;
+
+/// Raw value statistics of one device zone over a capture
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawStats {
+    pub mean: f64,
+    pub std_dev: f64,
+    pub peak_to_peak: f64,
+}
+
+impl RawStats {
+    /// `None` for a zone with fewer than two readings
+    pub fn from_samples(samples: &ZoneSamples) -> Option<Self> {
+        let (Some(mean), Some(std_dev), Some(min), Some(max)) =
+            (samples.mean(), samples.std_dev(), samples.min, samples.max)
+        else {
+            return None;
+        };
+        Some(Self {
+            mean,
+            std_dev,
+            peak_to_peak: f64::from(max - min),
+        })
+    }
+}
+
+/// One statistic before and after the write
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compared {
+    pub before: f64,
+    pub after: f64,
+}
+
+impl Compared {
+    /// Change relative to before, `None` from zero
+    pub fn change(&self) -> Option<f64> {
+        (self.before.abs() > f64::EPSILON).then(|| (self.after - self.before) / self.before.abs())
+    }
+
+    /// Whether the change reaches `NOTABLE_CHANGE`
+    pub fn notable(&self) -> bool {
+        self.change().is_some_and(|change| change.abs() >= NOTABLE_CHANGE)
+    }
+}
+
+/// A device zone's raw statistics before and after the write; `None` where
+/// either capture had too few readings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ZoneComparison {
+    pub zone: usize,
+    pub before: Option<RawStats>,
+    pub after: Option<RawStats>,
+}
+
+impl ZoneComparison {
+    fn compared(&self, statistic: impl Fn(&RawStats) -> f64) -> Option<Compared> {
+        let (before, after) = self.before.as_ref().zip(self.after.as_ref())?;
+        Some(Compared {
+            before: statistic(before),
+            after: statistic(after),
+        })
+    }
+
+    pub fn mean(&self) -> Option<Compared> {
+        self.compared(|stats| stats.mean)
+    }
+
+    pub fn std_dev(&self) -> Option<Compared> {
+        self.compared(|stats| stats.std_dev)
+    }
+
+    pub fn peak_to_peak(&self) -> Option<Compared> {
+        self.compared(|stats| stats.peak_to_peak)
+    }
+}
+
+/// The raw values of every zone before and after a config write
+#[derive(Debug, Clone)]
+pub struct CompareReport {
+    pub zones: Vec<ZoneComparison>,
+}
+
+/// Compares what every zone sent in the captures before and after a write.
+pub fn compare(before: &[ZoneSamples; NUM_ZONES], after: &[ZoneSamples; NUM_ZONES]) -> CompareReport {
+    let zones = (0..NUM_ZONES)
+        .map(|zone| ZoneComparison {
+            zone,
+            before: RawStats::from_samples(&before[zone]),
+            after: RawStats::from_samples(&after[zone]),
+        })
+        .collect();
+    CompareReport { zones }
+}
+
+/// Where a comparison is, for the GUI
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ComparePhase {
+    /// Capturing with the old configs, for the time since the first sample
+    Before(Duration),
+    /// The new configs are being written
+    Writing,
+    /// Capturing with the new configs, for the time since the first sample
+    After(Duration),
+}
+
+/// A capture, timed from its first sample
+#[derive(Debug, Clone, Default)]
+struct Capture {
+    started: Option<Instant>,
+    /// Boxed, the comparison's states carry up to two
+    samples: Box<[ZoneSamples; NUM_ZONES]>,
+}
+
+impl Capture {
+    fn elapsed(&self) -> Duration {
+        self.started.map_or(Duration::ZERO, |started| started.elapsed())
+    }
+
+    /// Adds `sample`, and returns whether the capture ran its time.
+    fn add(&mut self, sample: &Sample, now: Instant) -> bool {
+        let started = *self.started.get_or_insert(now);
+        self.samples[sample.zone].add(sample);
+        now.saturating_duration_since(started) >= COMPARE_DURATION
+    }
+}
+
+#[derive(Debug, Default)]
+enum CompareState {
+    #[default]
+    Idle,
+    Before {
+        configs: [DildonicaZoneConfig; NUM_ZONES],
+        capture: Capture,
+    },
+    Writing {
+        before: Capture,
+    },
+    After {
+        before: Capture,
+        capture: Capture,
+    },
+}
+
+#[derive(Debug, Default)]
+struct Comparison {
+    state: CompareState,
+    /// The last finished comparison, until taken
+    report: Option<CompareReport>,
+}
+
+/// "Write & Compare": captures the raw values of every zone for
+/// `COMPARE_DURATION`, writes new configs, and captures again once the
+/// write is done. The GUI starts it with `begin`; the device session feeds
+/// it every sample with `add`, writes the configs it hands back and reports
+/// how that went with `written`. Captures only advance with samples.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigCompare {
+    comparison: Arc<Mutex<Comparison>>,
+}
+
+impl ConfigCompare {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts comparing the current configs with `configs`, discarding a
+    /// running comparison.
+    pub fn begin(&self, configs: [DildonicaZoneConfig; NUM_ZONES]) {
+        self.comparison.lock_or_recover().state = CompareState::Before {
+            configs,
+            capture: Capture::default(),
+        };
+        info!("Comparing raw values before and after a config write, don't touch the instrument");
+    }
+
+    /// `None` while no comparison runs
+    pub fn phase(&self) -> Option<ComparePhase> {
+        match &self.comparison.lock_or_recover().state {
+            CompareState::Idle => None,
+            CompareState::Before { capture, .. } => Some(ComparePhase::Before(capture.elapsed())),
+            CompareState::Writing { .. } => Some(ComparePhase::Writing),
+            CompareState::After { capture, .. } => Some(ComparePhase::After(capture.elapsed())),
+        }
+    }
+
+    /// Stops a running comparison, also one whose configs are being written.
+    pub fn cancel(&self) {
+        self.comparison.lock_or_recover().state = CompareState::Idle;
+        info!("Config comparison cancelled");
+    }
+
+    /// The comparison finished since the last call
+    pub fn take_report(&self) -> Option<CompareReport> {
+        self.comparison.lock_or_recover().report.take()
+    }
+
+    /// Adds a sample from the device at `now` to the running capture.
+    /// Returns the configs to write once the capture before the write is
+    /// complete.
+    pub fn add(&self, sample: &Sample, now: Instant) -> Option<[DildonicaZoneConfig; NUM_ZONES]> {
+        let mut comparison = self.comparison.lock_or_recover();
+        let comparison = &mut *comparison;
+        match &mut comparison.state {
+            CompareState::Idle | CompareState::Writing { .. } => None,
+            CompareState::Before { configs, capture } => {
+                if !capture.add(sample, now) {
+                    return None;
+                }
+                let configs = *configs;
+                let before = std::mem::take(capture);
+                comparison.state = CompareState::Writing { before };
+                Some(configs)
+            }
+            CompareState::After { before, capture } => {
+                if !capture.add(sample, now) {
+                    return None;
+                }
+                let report = compare(&before.samples, &capture.samples);
+                info!("Config comparison finished");
+                comparison.report = Some(report);
+                comparison.state = CompareState::Idle;
+                None
+            }
+        }
+    }
+
+    /// Starts the capture after the write if it succeeded, or gives up.
+    pub fn written(&self, succeeded: bool) {
+        let mut comparison = self.comparison.lock_or_recover();
+        let CompareState::Writing { before } = std::mem::take(&mut comparison.state) else {
+            // Cancelled meanwhile
+            return;
+        };
+        if succeeded {
+            comparison.state = CompareState::After {
+                before,
+                capture: Capture::default(),
+            };
+        } else {
+            warn!("Config comparison stopped, the write failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HARDWARE_TEMPLATES;
+
+    /// Feeds `comparison` a sample every ms from `start` for `secs` seconds,
+    /// zones in turn alternating `noise` around 5000, and returns what the
+    /// last one that handed back configs handed back
+    fn feed(
+        comparison: &ConfigCompare,
+        start: Instant,
+        secs: u64,
+        noise: i32,
+    ) -> Option<[DildonicaZoneConfig; NUM_ZONES]> {
+        let mut handed = None;
+        for index in 0..secs * 1000 {
+            let sign = if (index / NUM_ZONES as u64).is_multiple_of(2) { 1 } else { -1 };
+            let sample = Sample {
+                timestamp: index as i32,
+                zone: index as usize % NUM_ZONES,
+                value: Some(5000 + sign * noise),
+                flags: None,
+                sequence: None,
+            };
+            if let Some(configs) = comparison.add(&sample, start + Duration::from_millis(index)) {
+                assert!(handed.is_none(), "configs handed back twice");
+                handed = Some(configs);
+            }
+        }
+        handed
+    }
+
+    fn new_configs() -> [DildonicaZoneConfig; NUM_ZONES] {
+        let mut configs = HARDWARE_TEMPLATES[0].zones;
+        configs[2].cycle_count_end += 100;
+        configs
+    }
+
+    #[test]
+    fn halving_the_noise_shows_as_a_notable_drop() {
+        let comparison = ConfigCompare::new();
+        let start = Instant::now();
+        comparison.begin(new_configs());
+        assert!(matches!(comparison.phase(), Some(ComparePhase::Before(_))));
+        assert_eq!(feed(&comparison, start, 6, 40), Some(new_configs()));
+        assert_eq!(comparison.phase(), Some(ComparePhase::Writing));
+        // Samples while writing count for neither capture
+        assert_eq!(feed(&comparison, start, 1, 1000), None);
+
+        comparison.written(true);
+        assert!(matches!(comparison.phase(), Some(ComparePhase::After(_))));
+        assert!(comparison.take_report().is_none());
+        feed(&comparison, start, 6, 20);
+        assert_eq!(comparison.phase(), None);
+        let report = comparison.take_report().unwrap();
+        assert!(comparison.take_report().is_none(), "taken once");
+        for zone in &report.zones {
+            let std_dev = zone.std_dev().unwrap();
+            assert!((std_dev.change().unwrap() + 0.5).abs() < 1e-3, "zone {}: {:?}", zone.zone, std_dev);
+            assert!(std_dev.notable());
+            assert_eq!(zone.peak_to_peak().unwrap(), Compared { before: 80.0, after: 40.0 });
+            let mean = zone.mean().unwrap();
+            assert!(mean.change().unwrap().abs() < 1e-4, "{:?}", mean);
+            assert!(!mean.notable());
+        }
+    }
+
+    #[test]
+    fn a_failed_write_ends_the_comparison_without_a_report() {
+        let comparison = ConfigCompare::new();
+        comparison.begin(new_configs());
+        assert!(feed(&comparison, Instant::now(), 6, 40).is_some());
+        comparison.written(false);
+        assert_eq!(comparison.phase(), None);
+        assert!(comparison.take_report().is_none());
+    }
+
+    #[test]
+    fn a_cancelled_comparison_ignores_its_write() {
+        let comparison = ConfigCompare::new();
+        comparison.begin(new_configs());
+        assert!(feed(&comparison, Instant::now(), 6, 40).is_some());
+        comparison.cancel();
+        comparison.written(true);
+        assert_eq!(comparison.phase(), None);
+        assert_eq!(feed(&comparison, Instant::now(), 6, 40), None);
+        assert!(comparison.take_report().is_none());
+    }
+
+    #[test]
+    fn captures_are_timed_from_their_first_sample() {
+        let comparison = ConfigCompare::new();
+        comparison.begin(new_configs());
+        // Five seconds without samples don't count
+        let late = Instant::now() + Duration::from_secs(5);
+        assert_eq!(feed(&comparison, late, 4, 40), None);
+        assert!(matches!(comparison.phase(), Some(ComparePhase::Before(_))));
+    }
+
+    #[test]
+    fn changes_are_relative_to_before() {
+        let compared = Compared {
+            before: -200.0,
+            after: -190.0,
+        };
+        assert_eq!(compared.change(), Some(0.05));
+        assert!(compared.notable());
+        let from_zero = Compared { before: 0.0, after: 5.0 };
+        assert_eq!(from_zero.change(), None);
+        assert!(!from_zero.notable());
+    }
+
+    #[test]
+    fn zones_with_too_few_readings_have_no_comparison() {
+        let mut before: [ZoneSamples; NUM_ZONES] = Default::default();
+        let after: [ZoneSamples; NUM_ZONES] = Default::default();
+        for value in [5000, 5010] {
+            before[0].add(&Sample {
+                timestamp: 0,
+                zone: 0,
+                value: Some(value),
+                flags: None,
+                sequence: None,
+            });
+        }
+        let report = compare(&before, &after);
+        assert!(report.zones[0].before.is_some());
+        assert_eq!(report.zones[0].std_dev(), None);
+        assert_eq!(report.zones[1].before, None);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::config::{DildonicaZoneConfig, NUM_ZONES};
+use crate::recorder::unix_time;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use thiserror::Error;
+use tracing::{info, warn};
+
+/// File name of the history, beside the config file
+pub const HISTORY_FILE_NAME: &str = "config_history.log";
+/// Size at which the history is moved to `config_history.log.1`, replacing
+/// the one moved there before; about a thousand writes
+const MAX_HISTORY_SIZE: u64 = 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum ConfigHistoryError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// One attempt to write zone configs to the device, a line of the history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch
+    pub time: f64,
+    pub configs: [DildonicaZoneConfig; NUM_ZONES],
+    /// Whether the configs read back after the write matched
+    pub verified: bool,
+    /// Why the write or reading it back failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(configs: [DildonicaZoneConfig; NUM_ZONES], verified: bool, error: Option<String>) -> Self {
+        Self {
+            time: unix_time(SystemTime::now()),
+            configs,
+            verified,
+            error,
+        }
+    }
+}
+
+/// The history of every config written to the device, as JSON lines, for
+/// tracking down firmware regressions. Kept forever, but rotated once it
+/// grows past `MAX_HISTORY_SIZE`.
+#[derive(Debug, Clone)]
+pub struct ConfigHistory {
+    path: PathBuf,
+}
+
+impl ConfigHistory {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// The history beside the config file at `config_path`
+    pub fn beside(config_path: &Path) -> Self {
+        Self::new(config_path.with_file_name(HISTORY_FILE_NAME))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn rotated_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".1");
+        path.into()
+    }
+
+    /// Adds `entry` at the end, rotating the history first if it's full.
+    pub fn append(&self, entry: &HistoryEntry) -> Result<(), ConfigHistoryError> {
+        if fs::metadata(&self.path).is_ok_and(|metadata| metadata.len() >= MAX_HISTORY_SIZE) {
+            fs::rename(&self.path, self.rotated_path())?;
+            info!("Config history rotated to {}", self.rotated_path().display());
+        }
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        // One write, so a crash leaves no half line behind
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /// The last `count` entries, oldest first, also from the rotated
+    /// history if needed. Empty without any history; lines that don't parse
+    /// are skipped.
+    pub fn last(&self, count: usize) -> Result<Vec<HistoryEntry>, ConfigHistoryError> {
+        let mut entries = read_entries(&self.path)?;
+        if entries.len() < count {
+            let mut older = read_entries(&self.rotated_path())?;
+            older.append(&mut entries);
+            entries = older;
+        }
+        let skip = entries.len().saturating_sub(count);
+        Ok(entries.split_off(skip))
+    }
+
+    /// The last write the device read back as written, also from the
+    /// rotated history
+    pub fn last_verified(&self) -> Result<Option<HistoryEntry>, ConfigHistoryError> {
+        let find = |entries: Vec<HistoryEntry>| entries.into_iter().rev().find(|entry| entry.verified);
+        match find(read_entries(&self.path)?) {
+            Some(entry) => Ok(Some(entry)),
+            None => Ok(find(read_entries(&self.rotated_path())?)),
+        }
+    }
+}
+
+fn read_entries(path: &Path) -> Result<Vec<HistoryEntry>, ConfigHistoryError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("Skipping line {} of {}: {}", index + 1, path.display(), e),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HARDWARE_TEMPLATES;
+
+    /// Entry `index`, told apart by zone 0's cycle count end
+    fn entry(index: u32, verified: bool) -> HistoryEntry {
+        let mut configs = HARDWARE_TEMPLATES[0].zones;
+        configs[0].cycle_count_end = 5000 + index;
+        let error = (!verified).then(|| format!("write {} not verified", index));
+        HistoryEntry::new(configs, verified, error)
+    }
+
+    fn ends(entries: &[HistoryEntry]) -> Vec<u32> {
+        entries.iter().map(|entry| entry.configs[0].cycle_count_end - 5000).collect()
+    }
+
+    #[test]
+    fn appended_entries_parse_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::beside(&dir.path().join("config.json"));
+        assert_eq!(history.path(), dir.path().join(HISTORY_FILE_NAME));
+        assert!(history.last(20).unwrap().is_empty());
+
+        let written = [entry(0, true), entry(1, false)];
+        for entry in &written {
+            history.append(entry).unwrap();
+        }
+        let read = history.last(20).unwrap();
+        assert_eq!(read.len(), 2);
+        for (read, written) in read.iter().zip(&written) {
+            // serde_json parses floats to the nearest few ULPs only
+            assert!((read.time - written.time).abs() < 1e-6, "{} vs {}", read.time, written.time);
+            assert_eq!(read.configs, written.configs);
+            assert_eq!((read.verified, &read.error), (written.verified, &written.error));
+        }
+        let contents = fs::read_to_string(history.path()).unwrap();
+        assert_eq!(contents.lines().count(), 2, "one line per entry");
+        assert!(!contents.lines().next().unwrap().contains("error"), "no error, no field");
+    }
+
+    #[test]
+    fn last_keeps_the_newest() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::new(dir.path().join(HISTORY_FILE_NAME));
+        for index in 0..5 {
+            history.append(&entry(index, true)).unwrap();
+        }
+        assert_eq!(ends(&history.last(3).unwrap()), [2, 3, 4]);
+    }
+
+    #[test]
+    fn lines_that_dont_parse_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::new(dir.path().join(HISTORY_FILE_NAME));
+        history.append(&entry(0, true)).unwrap();
+        let mut file = OpenOptions::new().append(true).open(history.path()).unwrap();
+        file.write_all(b"{\"time\": garbage\n\n").unwrap();
+        history.append(&entry(1, true)).unwrap();
+        assert_eq!(ends(&history.last(20).unwrap()), [0, 1]);
+    }
+
+    #[test]
+    fn a_full_history_rotates_and_is_still_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::new(dir.path().join(HISTORY_FILE_NAME));
+        let mut appended = 0;
+        while !history.rotated_path().exists() {
+            history.append(&entry(appended, appended % 2 == 0)).unwrap();
+            appended += 1;
+        }
+        assert!(fs::metadata(history.rotated_path()).unwrap().len() >= MAX_HISTORY_SIZE);
+        assert_eq!(history.last(1).unwrap().len(), 1, "the new history holds the last");
+        // Reaching back into the rotated history
+        let last = history.last(3).unwrap();
+        assert_eq!(ends(&last), [appended - 3, appended - 2, appended - 1]);
+        let verified = history.last_verified().unwrap().unwrap();
+        assert_eq!(ends(&[verified]), [if appended % 2 == 0 { appended - 2 } else { appended - 1 }]);
+    }
+
+    #[test]
+    fn the_last_verified_write_is_found_in_the_rotated_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = ConfigHistory::new(dir.path().join(HISTORY_FILE_NAME));
+        history.append(&entry(0, true)).unwrap();
+        fs::rename(history.path(), history.rotated_path()).unwrap();
+        history.append(&entry(1, false)).unwrap();
+        assert_eq!(ends(&[history.last_verified().unwrap().unwrap()]), [0]);
+        assert_eq!(ends(&history.last(20).unwrap()), [0, 1]);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::diagnostics::SampleHealth;
+use crate::midi::{MidiOutputState, MidiStatus};
+use crate::sd_notify::{self, Notifier};
+use crate::sync::LockExt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// How often the service state is checked and its status updated, more
+/// often for a short watchdog timeout
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Error, Debug)]
+pub enum DaemonError {
+    #[error("Not ready within {} s ({status})", timeout.as_secs_f64())]
+    NotReady { timeout: Duration, status: String },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DaemonOptions {
+    /// Samples flowing and the MIDI port up by then, or the service fails
+    pub ready_timeout: Duration,
+    /// The watchdog is only fed while the newest sample is more recent
+    pub stall_timeout: Duration,
+}
+
+/// Tells systemd how the service is doing until `shutdown`: READY=1 once
+/// samples arrive and the MIDI port is up (or disabled), WATCHDOG=1 while
+/// samples keep arriving within `stall_timeout`, and STATUS= whenever the
+/// state changes. Not ready within `ready_timeout`, it cancels `shutdown`
+/// and fails, so the service exits with an error and systemd can restart it.
+/// Without `$NOTIFY_SOCKET` the checks still run, there's just nobody to tell.
+pub async fn supervise(
+    health: SampleHealth,
+    midi_output: Arc<Mutex<MidiOutputState>>,
+    options: DaemonOptions,
+    shutdown: CancellationToken,
+) -> Result<(), DaemonError> {
+    let notifier = match Notifier::connect() {
+        Ok(Some(notifier)) => Some(notifier),
+        Ok(None) => {
+            warn!("No $NOTIFY_SOCKET, not running as a systemd notify service");
+            None
+        }
+        Err(e) => {
+            warn!("Failed to open the systemd notify socket: {}", e);
+            None
+        }
+    };
+    let notify = |state: &str| {
+        if let Some(notifier) = &notifier {
+            if let Err(e) = notifier.notify(state) {
+                warn!("Failed to notify systemd: {}", e);
+            }
+        }
+    };
+    let watchdog_interval = sd_notify::watchdog_timeout().map(|timeout| timeout / 2);
+    if let Some(interval) = watchdog_interval {
+        info!("Feeding the systemd watchdog every {:.1} s", interval.as_secs_f64());
+    }
+
+    let started = Instant::now();
+    let mut ready = false;
+    let mut last_status = String::new();
+    let mut last_ping: Option<Instant> = None;
+    // Samples counted at the previous check, for the rate in the status
+    let mut counted = (started, health.samples());
+    let mut samples_per_sec = None;
+    // A ping is late by up to one check, a quarter of the timeout at most
+    let check_interval = watchdog_interval.map_or(CHECK_INTERVAL, |interval| CHECK_INTERVAL.min(interval / 2));
+    let mut interval = tokio::time::interval(check_interval);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => break,
+        }
+        let now = Instant::now();
+        let samples = health.samples();
+        let elapsed = now.duration_since(counted.0).as_secs_f64();
+        if elapsed >= 1.0 {
+            samples_per_sec = Some((samples - counted.1) as f64 / elapsed);
+            counted = (now, samples);
+        }
+
+        let receiving = health.receiving(options.stall_timeout);
+        let midi_status = midi_output.lock_or_recover().status().clone();
+        let midi_up = !matches!(midi_status, MidiStatus::Waiting(_));
+        let source = match health.received_at() {
+            None => "connecting, no samples yet".to_string(),
+            Some(_) if receiving => match samples_per_sec {
+                Some(rate) => format!("receiving {:.0} samples/s", rate),
+                None => "receiving".to_string(),
+            },
+            Some(at) => format!("no samples for {:.0} s", at.elapsed().as_secs_f64()),
+        };
+        let status = format!("Device {}; MIDI {}", source, midi_status);
+
+        if !ready {
+            if receiving && midi_up {
+                ready = true;
+                info!("Ready: {}", status);
+                notify("READY=1");
+            } else if now.duration_since(started) >= options.ready_timeout {
+                shutdown.cancel();
+                notify(&format!("STATUS=Not ready: {}", status));
+                return Err(DaemonError::NotReady {
+                    timeout: options.ready_timeout,
+                    status,
+                });
+            }
+        }
+        if status != last_status {
+            notify(&format!("STATUS={}", status));
+            last_status = status;
+        }
+        // A stalled source stops the pings, and systemd restarts the service
+        if let Some(watchdog_interval) = watchdog_interval {
+            let due = last_ping.is_none_or(|ping| now.duration_since(ping) >= watchdog_interval);
+            if ready && receiving && due {
+                notify("WATCHDOG=1");
+                last_ping = Some(now);
+            }
+        }
+    }
+    notify("STOPPING=1");
+    Ok(())
+}
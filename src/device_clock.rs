@@ -0,0 +1,206 @@
+use std::time::{Duration, Instant};
+
+/// Time constant of following a host/device offset that grows, e.g. a device
+/// crystal running slow
+const TRACKING_TIME: f64 = 10.0;
+/// A sample this much later than the estimate is a BLE stall, not drift
+const OUTLIER_SECS: f64 = 0.5;
+/// How long samples must keep arriving as outliers before the estimate
+/// restarts on them, e.g. after the device clock jumped forward
+const RESYNC_TIME: Duration = Duration::from_secs(2);
+
+/// Maps the device's millisecond counter to host time. The counter's crystal
+/// drifts against the host clock over a session, and BLE delivers samples
+/// with a variable delay, so neither a fixed offset nor the offset of the
+/// newest sample lines the two up.
+///
+/// Delivery can only delay a sample, never make it early, so the estimate
+/// takes any sample that arrives earlier than predicted as the new offset
+/// right away, and follows later arrivals slowly over `TRACKING_TIME`. That
+/// tracks drift either way while ignoring jitter. Outliers are dropped,
+/// and the estimate restarts when the device clock runs backwards.
+#[derive(Debug)]
+pub struct DeviceClock {
+    epoch: Instant,
+    /// Host minus device time in seconds
+    offset: Option<f64>,
+    /// Device time and arrival of the newest sample used
+    last: Option<(f64, Instant)>,
+    /// Arrival of the first sample of the current run of outliers
+    outliers_since: Option<Instant>,
+}
+
+impl Default for DeviceClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceClock {
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            offset: None,
+            last: None,
+            outliers_since: None,
+        }
+    }
+
+    /// Feeds a sample stamped `device_time` seconds that arrived at `received`.
+    pub fn update(&mut self, device_time: f64, received: Instant) {
+        let measured = self.host_secs(received) - device_time;
+        let (Some(offset), Some((last_time, last_received))) = (self.offset, self.last) else {
+            self.restart(device_time, received, measured);
+            return;
+        };
+        if device_time < last_time {
+            // The device rebooted or reconnected with a fresh counter
+            self.restart(device_time, received, measured);
+            return;
+        }
+        let error = measured - offset;
+        if error > OUTLIER_SECS {
+            let since = *self.outliers_since.get_or_insert(received);
+            if received.saturating_duration_since(since) >= RESYNC_TIME {
+                self.restart(device_time, received, measured);
+            }
+            return;
+        }
+        self.outliers_since = None;
+        let offset = if error < 0.0 {
+            measured
+        } else {
+            let elapsed = received.saturating_duration_since(last_received).as_secs_f64();
+            offset + error * (elapsed / TRACKING_TIME).min(1.0)
+        };
+        self.offset = Some(offset);
+        self.last = Some((device_time, received));
+    }
+
+    /// The device time at host time `at`, `None` before the first sample
+    pub fn device_time(&self, at: Instant) -> Option<f64> {
+        self.offset.map(|offset| self.host_secs(at) - offset)
+    }
+
+    fn restart(&mut self, device_time: f64, received: Instant, measured: f64) {
+        self.offset = Some(measured);
+        self.last = Some((device_time, received));
+        self.outliers_since = None;
+    }
+
+    /// `at` in seconds since the clock was created; earlier instants count
+    /// as negative so the offset stays exact
+    fn host_secs(&self, at: Instant) -> f64 {
+        match at.checked_duration_since(self.epoch) {
+            Some(since) => since.as_secs_f64(),
+            None => -self.epoch.duration_since(at).as_secs_f64(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Samples per second of the simulated device
+    const RATE: u64 = 100;
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    /// Runs an hour of samples from a device whose crystal is `ppm` off,
+    /// rebooted at 50 minutes, with 5-45 ms of delivery jitter and a 300 ms
+    /// stall every ten minutes. Returns the largest error of the estimated
+    /// device time at the samples' arrivals, outside the first second and
+    /// the second after the reboot.
+    fn worst_error(ppm: f64) -> f64 {
+        let mut clock = DeviceClock::new();
+        let start = clock.epoch;
+        let reboot = 3000.0;
+        // A fixed linear congruential generator, so every run jitters alike
+        let mut seed: u64 = 1;
+        let mut jitter = || {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            0.005 + 0.04 * (seed >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let device_at = |host: f64| {
+            let since_boot = if host >= reboot { host - reboot } else { host };
+            since_boot * (1.0 + ppm * 1e-6)
+        };
+        let mut worst: f64 = 0.0;
+        for index in 0..3600 * RATE {
+            let sent = index as f64 / RATE as f64;
+            let mut arrival = sent + jitter();
+            let stall_start = (sent / 600.0).floor() * 600.0 + 300.0;
+            if (stall_start..stall_start + 0.3).contains(&sent) {
+                arrival = arrival.max(stall_start + 0.3);
+            }
+            clock.update(device_at(sent), start + secs(arrival));
+            let settling = arrival < 1.0 || (reboot..reboot + 1.0).contains(&arrival);
+            if !settling {
+                let estimate = clock.device_time(start + secs(arrival)).unwrap();
+                worst = worst.max((estimate - device_at(arrival)).abs());
+            }
+        }
+        worst
+    }
+
+    #[test]
+    fn follows_50_ppm_of_drift_and_a_reboot() {
+        for ppm in [50.0, -50.0] {
+            let worst = worst_error(ppm);
+            assert!(worst < 0.02, "{} ppm: {:.1} ms off", ppm, worst * 1000.0);
+        }
+    }
+
+    #[test]
+    fn knows_nothing_before_the_first_sample() {
+        let clock = DeviceClock::new();
+        assert_eq!(clock.device_time(Instant::now()), None);
+    }
+
+    #[test]
+    fn an_early_arrival_is_taken_at_once() {
+        let mut clock = DeviceClock::new();
+        let start = clock.epoch;
+        clock.update(10.0, start + secs(0.1));
+        // 50 ms earlier than the first sample predicted
+        clock.update(11.0, start + secs(1.05));
+        assert!((clock.device_time(start + secs(2.05)).unwrap() - 12.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn a_single_outlier_is_ignored() {
+        let mut clock = DeviceClock::new();
+        let start = clock.epoch;
+        clock.update(10.0, start);
+        clock.update(10.1, start + secs(1.1));
+        assert!((clock.device_time(start + secs(2.0)).unwrap() - 12.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn lasting_outliers_resync() {
+        let mut clock = DeviceClock::new();
+        let start = clock.epoch;
+        clock.update(10.0, start);
+        // From here on every sample arrives a second late
+        for tenth in 11..=35 {
+            let elapsed = f64::from(tenth) / 10.0;
+            clock.update(10.0 + elapsed - 1.0, start + secs(elapsed));
+            if elapsed < 3.1 {
+                assert!((clock.device_time(start + secs(elapsed)).unwrap() - (10.0 + elapsed)).abs() < 1e-9);
+            }
+        }
+        assert!((clock.device_time(start + secs(4.0)).unwrap() - 13.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn a_clock_running_backwards_restarts() {
+        let mut clock = DeviceClock::new();
+        let start = clock.epoch;
+        clock.update(500.0, start);
+        clock.update(0.5, start + secs(1.0));
+        assert!((clock.device_time(start + secs(2.0)).unwrap() - 1.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,769 @@
+// Packages are picked and progress is shown in the GUI
+#![cfg_attr(not(feature = "gui"), allow(dead_code))]
+
+use crate::ble::{self, BleError, DeviceConnection};
+use crate::config::BleConfig;
+use crate::sync::LockExt;
+use btleplug::api::{Central, Characteristic, Peripheral as _, ScanFilter, WriteType};
+use btleplug::platform::Peripheral;
+use futures::stream::{Stream, StreamExt};
+use serde::Deserialize;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Nordic Secure DFU service, on the bootloader and (for the buttonless
+/// trigger) on the application
+pub const DFU_SERVICE_UUID: Uuid = Uuid::from_u128(0x0000fe59_0000_1000_8000_00805f9b34fb);
+pub const CONTROL_POINT_UUID: Uuid = Uuid::from_u128(0x8ec90001_f315_4f60_9fb8_838830daea50);
+pub const PACKET_UUID: Uuid = Uuid::from_u128(0x8ec90002_f315_4f60_9fb8_838830daea50);
+/// Buttonless DFU without bonds: the application reboots into the
+/// bootloader, which then advertises at the device address plus one
+pub const BUTTONLESS_UUID: Uuid = Uuid::from_u128(0x8ec90003_f315_4f60_9fb8_838830daea50);
+
+/// Bytes per packet write; the default ATT MTU of 23 less the 3 byte header,
+/// since the negotiated MTU isn't available
+pub const PACKET_SIZE: usize = 20;
+/// The bootloader sends a checksum every this many packets, so a corrupted
+/// transfer is noticed before the object is complete
+const RECEIPT_INTERVAL: u16 = 12;
+/// Longest wait for a control point response; creating an object erases flash
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long the application has to reboot into the bootloader or back
+const REBOOT_TIMEOUT: Duration = Duration::from_secs(15);
+/// Attempts at reconnecting to the application after an update
+const RECONNECT_ATTEMPTS: u32 = 5;
+
+const OP_CREATE: u8 = 0x01;
+const OP_SET_RECEIPT: u8 = 0x02;
+const OP_CALCULATE_CHECKSUM: u8 = 0x03;
+const OP_EXECUTE: u8 = 0x04;
+const OP_SELECT: u8 = 0x06;
+const OP_RESPONSE: u8 = 0x60;
+const BUTTONLESS_ENTER_BOOTLOADER: u8 = 0x01;
+const BUTTONLESS_RESPONSE: u8 = 0x20;
+const RESULT_SUCCESS: u8 = 0x01;
+const RESULT_EXTENDED_ERROR: u8 = 0x0B;
+
+#[derive(Error, Debug)]
+pub enum DfuError {
+    #[error("Failed to read firmware package: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid firmware package: {0}")]
+    Package(#[from] zip::result::ZipError),
+    #[error("Invalid firmware package manifest: {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("Firmware package has no image")]
+    NoImage,
+    #[error("Firmware package has {0} images, only packages with one are supported")]
+    MultipleImages(usize),
+    #[error("Init packet of {size} bytes is larger than the bootloader accepts ({max})")]
+    InitPacketTooLarge { size: usize, max: u32 },
+    #[error("Device has no buttonless DFU characteristic, start its bootloader by hand")]
+    NoButtonless,
+    #[error("DFU bootloader of {0} not found")]
+    BootloaderNotFound(String),
+    #[error("DFU {0} characteristic not found")]
+    CharacteristicNotFound(&'static str),
+    #[error("No response to DFU {0} request")]
+    Timeout(&'static str),
+    #[error("DFU target rejected {request}: {reason}")]
+    Rejected { request: &'static str, reason: String },
+    #[error("Malformed response to DFU {0} request")]
+    InvalidResponse(&'static str),
+    #[error("Checksum mismatch at offset {offset}: device has {actual:08x}, expected {expected:08x}")]
+    Checksum { offset: u32, expected: u32, actual: u32 },
+    #[error("Device received {actual} bytes, expected {expected}")]
+    Offset { expected: u32, actual: u32 },
+    #[error("BLE error: {0}")]
+    Btle(#[from] btleplug::Error),
+    #[error(transparent)]
+    Ble(#[from] BleError),
+    #[error("Cancelled")]
+    Cancelled,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    manifest: std::collections::BTreeMap<String, ManifestImage>,
+}
+
+#[derive(Deserialize)]
+struct ManifestImage {
+    bin_file: String,
+    dat_file: String,
+}
+
+/// One image of an `nrfutil` DFU package: the signed init packet and the
+/// firmware it describes
+#[derive(Debug, Clone)]
+pub struct DfuPackage {
+    /// Kind of image from the manifest, e.g. `application`
+    pub kind: String,
+    pub init_packet: Vec<u8>,
+    pub firmware: Vec<u8>,
+}
+
+impl DfuPackage {
+    /// Reads a `.zip` package as written by `nrfutil pkg generate`.
+    pub fn read(path: &Path) -> Result<Self, DfuError> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        let manifest: Manifest = serde_json::from_slice(&read_entry(&mut archive, "manifest.json")?)?;
+        let mut images = manifest.manifest.into_iter();
+        let (kind, image) = match (images.next(), images.len()) {
+            (None, _) => return Err(DfuError::NoImage),
+            (Some(image), 0) => image,
+            (Some(_), more) => return Err(DfuError::MultipleImages(more + 1)),
+        };
+        Ok(Self {
+            kind,
+            init_packet: read_entry(&mut archive, &image.dat_file)?,
+            firmware: read_entry(&mut archive, &image.bin_file)?,
+        })
+    }
+}
+
+fn read_entry(archive: &mut zip::ZipArchive<File>, name: &str) -> Result<Vec<u8>, DfuError> {
+    let mut entry = archive.by_name(name)?;
+    let mut data = Vec::with_capacity(entry.size() as usize);
+    entry.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// The firmware in objects of the bootloader's `max_size`, each sent in
+/// packets of `PACKET_SIZE`
+pub fn objects(data: &[u8], max_size: u32) -> impl Iterator<Item = &[u8]> {
+    data.chunks(max_size.max(1) as usize)
+}
+
+/// The packets of an object, each with whether the bootloader sends a
+/// receipt once it has it; creating the object restarts its packet count
+fn packets(data: &[u8]) -> impl Iterator<Item = (&[u8], bool)> {
+    let interval = usize::from(RECEIPT_INTERVAL);
+    data.chunks(PACKET_SIZE)
+        .enumerate()
+        .map(move |(index, packet)| (packet, (index + 1).is_multiple_of(interval)))
+}
+
+/// CRC-32 as the bootloader computes it over everything received so far
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ObjectType {
+    /// The init packet
+    Command = 0x01,
+    Data = 0x02,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Request {
+    Create { object: ObjectType, size: u32 },
+    SetReceipt(u16),
+    CalculateChecksum,
+    Execute,
+    Select(ObjectType),
+}
+
+impl Request {
+    fn opcode(self) -> u8 {
+        match self {
+            Request::Create { .. } => OP_CREATE,
+            Request::SetReceipt(_) => OP_SET_RECEIPT,
+            Request::CalculateChecksum => OP_CALCULATE_CHECKSUM,
+            Request::Execute => OP_EXECUTE,
+            Request::Select(_) => OP_SELECT,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Request::Create { .. } => "create",
+            Request::SetReceipt(_) => "receipt",
+            Request::CalculateChecksum => "checksum",
+            Request::Execute => "execute",
+            Request::Select(_) => "select",
+        }
+    }
+
+    /// Control point message: the opcode and its little-endian parameters
+    fn encode(self) -> Vec<u8> {
+        let mut message = vec![self.opcode()];
+        match self {
+            Request::Create { object, size } => {
+                message.push(object as u8);
+                message.extend_from_slice(&size.to_le_bytes());
+            }
+            Request::SetReceipt(interval) => message.extend_from_slice(&interval.to_le_bytes()),
+            Request::Select(object) => message.push(object as u8),
+            Request::CalculateChecksum | Request::Execute => {}
+        }
+        message
+    }
+}
+
+fn describe_result(result: u8, extended: Option<u8>) -> String {
+    match result {
+        0x02 => "operation not supported".to_string(),
+        0x03 => "invalid parameter".to_string(),
+        0x04 => "insufficient resources".to_string(),
+        0x05 => "invalid object".to_string(),
+        0x07 => "unsupported object type".to_string(),
+        0x08 => "operation not permitted".to_string(),
+        0x0A => "operation failed".to_string(),
+        RESULT_EXTENDED_ERROR => match extended {
+            Some(0x07) => "firmware version too low".to_string(),
+            Some(0x08) => "hardware version mismatch".to_string(),
+            Some(0x0C) => "signature missing".to_string(),
+            Some(0x0D) => "wrong hash type".to_string(),
+            Some(0x0E) => "hash verification failed".to_string(),
+            Some(0x10) => "signature verification failed".to_string(),
+            Some(0x11) => "not enough space for the image".to_string(),
+            Some(code) => format!("extended error {:#04x}", code),
+            None => "extended error".to_string(),
+        },
+        code => format!("result {:#04x}", code),
+    }
+}
+
+/// Checks a control point notification answers `request` successfully and
+/// returns the parameters after the status.
+fn parse_response(request: Request, data: &[u8]) -> Result<&[u8], DfuError> {
+    match *data {
+        [OP_RESPONSE, opcode, RESULT_SUCCESS, ref rest @ ..] if opcode == request.opcode() => Ok(rest),
+        [OP_RESPONSE, opcode, result, ref rest @ ..] if opcode == request.opcode() => Err(DfuError::Rejected {
+            request: request.name(),
+            reason: describe_result(result, rest.first().copied()),
+        }),
+        _ => Err(DfuError::InvalidResponse(request.name())),
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    data.get(at..at + 4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// Offset and CRC-32 of the data the bootloader has received
+fn parse_checksum(request: Request, data: &[u8]) -> Result<(u32, u32), DfuError> {
+    let rest = parse_response(request, data)?;
+    read_u32(rest, 0)
+        .zip(read_u32(rest, 4))
+        .ok_or(DfuError::InvalidResponse(request.name()))
+}
+
+/// The bootloader's address: buttonless DFU without bonds adds one to the
+/// 48-bit device address so hosts don't mix up their cached attributes.
+pub fn bootloader_address(address: &str) -> Option<String> {
+    let bytes: Vec<u8> = address
+        .split(':')
+        .map(|part| u8::from_str_radix(part, 16).ok())
+        .collect::<Option<_>>()?;
+    let bytes: [u8; 6] = bytes.try_into().ok()?;
+    let mut value = [0u8; 8];
+    value[2..].copy_from_slice(&bytes);
+    let next = (u64::from_be_bytes(value) + 1) & 0xFFFF_FFFF_FFFF;
+    let next = next.to_be_bytes();
+    Some(
+        next[2..]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(":"),
+    )
+}
+
+/// Where a firmware update is, shown by the GUI
+#[derive(Debug, Clone, PartialEq)]
+pub enum DfuState {
+    Idle,
+    EnteringBootloader,
+    Uploading { sent: usize, total: usize },
+    Reconnecting,
+    Finished,
+    Failed(String),
+}
+
+impl fmt::Display for DfuState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DfuState::Idle => write!(f, "idle"),
+            DfuState::EnteringBootloader => write!(f, "starting the bootloader"),
+            DfuState::Uploading { sent, total } => write!(f, "uploading {} of {} bytes", sent, total),
+            DfuState::Reconnecting => write!(f, "reconnecting"),
+            DfuState::Finished => write!(f, "finished"),
+            DfuState::Failed(reason) => write!(f, "failed: {}", reason),
+        }
+    }
+}
+
+/// Progress of the running update, shared between the GUI and the session
+#[derive(Debug, Clone)]
+pub struct DfuProgress {
+    state: Arc<Mutex<DfuState>>,
+}
+
+impl Default for DfuProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DfuProgress {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(DfuState::Idle)),
+        }
+    }
+
+    pub fn state(&self) -> DfuState {
+        self.state.lock_or_recover().clone()
+    }
+
+    /// An update is between being requested and finishing or failing
+    pub fn is_running(&self) -> bool {
+        matches!(
+            self.state(),
+            DfuState::EnteringBootloader | DfuState::Uploading { .. } | DfuState::Reconnecting
+        )
+    }
+
+    pub fn set(&self, state: DfuState) {
+        *self.state.lock_or_recover() = state;
+    }
+
+    fn fail(&self, e: &DfuError) {
+        error!("Firmware update failed: {}", e);
+        self.set(DfuState::Failed(e.to_string()));
+    }
+}
+
+/// An update asked for by the GUI, handled by the BLE session
+#[derive(Debug, Clone)]
+pub struct DfuRequest {
+    pub package: DfuPackage,
+    pub progress: DfuProgress,
+}
+
+/// Asks the application on `device` to reboot into its bootloader through
+/// the buttonless DFU characteristic. The device disconnects afterwards.
+pub async fn enter_bootloader(device: &Peripheral) -> Result<(), DfuError> {
+    let buttonless = device
+        .characteristics()
+        .into_iter()
+        .find(|c| c.uuid == BUTTONLESS_UUID)
+        .ok_or(DfuError::NoButtonless)?;
+    device.subscribe(&buttonless).await?;
+    let mut notifications = device.notifications().await?;
+    device
+        .write(&buttonless, &[BUTTONLESS_ENTER_BOOTLOADER], WriteType::WithResponse)
+        .await?;
+    let response = next_response(&mut notifications, BUTTONLESS_UUID, "enter bootloader").await?;
+    match *response {
+        [BUTTONLESS_RESPONSE, BUTTONLESS_ENTER_BOOTLOADER, RESULT_SUCCESS, ..] => Ok(()),
+        [BUTTONLESS_RESPONSE, BUTTONLESS_ENTER_BOOTLOADER, result, ..] => Err(DfuError::Rejected {
+            request: "enter bootloader",
+            reason: describe_result(result, None),
+        }),
+        _ => Err(DfuError::InvalidResponse("enter bootloader")),
+    }
+}
+
+async fn next_response(
+    notifications: &mut (impl Stream<Item = btleplug::api::ValueNotification> + Unpin),
+    uuid: Uuid,
+    request: &'static str,
+) -> Result<Vec<u8>, DfuError> {
+    let wait = async {
+        while let Some(notification) = notifications.next().await {
+            if notification.uuid == uuid {
+                return Some(notification.value);
+            }
+        }
+        None
+    };
+    tokio::time::timeout(RESPONSE_TIMEOUT, wait)
+        .await
+        .ok()
+        .flatten()
+        .ok_or(DfuError::Timeout(request))
+}
+
+/// Scans until the bootloader of the device at `address` advertises the DFU
+/// service, at its own address or at `bootloader_address`.
+async fn find_bootloader(adapter_index: Option<usize>, address: &str) -> Result<Peripheral, DfuError> {
+    let central = ble::find_adapter(adapter_index).await?;
+    let addresses = [Some(address.to_string()), bootloader_address(address)];
+    central
+        .start_scan(ScanFilter {
+            services: vec![DFU_SERVICE_UUID],
+        })
+        .await?;
+    let search = async {
+        loop {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            for peripheral in central.peripherals().await? {
+                let found = peripheral.address().to_string();
+                let advertises = peripheral
+                    .properties()
+                    .await?
+                    .is_some_and(|properties| properties.services.contains(&DFU_SERVICE_UUID));
+                if advertises && addresses.iter().flatten().any(|a| a.eq_ignore_ascii_case(&found)) {
+                    return Ok::<_, DfuError>(peripheral);
+                }
+            }
+        }
+    };
+    let result = tokio::time::timeout(REBOOT_TIMEOUT, search).await;
+    central.stop_scan().await?;
+    result.map_err(|_| DfuError::BootloaderNotFound(address.to_string()))?
+}
+
+/// A connection to the bootloader's control point and packet characteristics
+struct DfuTarget<S> {
+    device: Peripheral,
+    control_point: Characteristic,
+    packet: Characteristic,
+    notifications: S,
+}
+
+impl<S: Stream<Item = btleplug::api::ValueNotification> + Unpin> DfuTarget<S> {
+    async fn request(&mut self, request: Request) -> Result<Vec<u8>, DfuError> {
+        self.device
+            .write(&self.control_point, &request.encode(), WriteType::WithResponse)
+            .await?;
+        let response = next_response(&mut self.notifications, CONTROL_POINT_UUID, request.name()).await?;
+        parse_response(request, &response)?;
+        Ok(response)
+    }
+
+    /// Max object size given by the bootloader for `object`
+    async fn select(&mut self, object: ObjectType) -> Result<u32, DfuError> {
+        let request = Request::Select(object);
+        let response = self.request(request).await?;
+        let rest = parse_response(request, &response)?;
+        read_u32(rest, 0).ok_or(DfuError::InvalidResponse(request.name()))
+    }
+
+    /// Sends `data`, the part of `image` starting at `start`, as one object,
+    /// verifies the bootloader's checksum of the image so far and executes it.
+    async fn send_object(
+        &mut self,
+        object: ObjectType,
+        image: &[u8],
+        start: usize,
+        data: &[u8],
+        on_packet: &mut impl FnMut(usize),
+    ) -> Result<(), DfuError> {
+        self.request(Request::Create {
+            object,
+            size: data.len() as u32,
+        })
+        .await?;
+        let mut sent = start;
+        for (packet, receipt) in packets(data) {
+            self.device.write(&self.packet, packet, WriteType::WithoutResponse).await?;
+            sent += packet.len();
+            on_packet(sent);
+            if receipt {
+                let response =
+                    next_response(&mut self.notifications, CONTROL_POINT_UUID, Request::CalculateChecksum.name())
+                        .await?;
+                verify(parse_checksum(Request::CalculateChecksum, &response)?, &image[..sent])?;
+            }
+        }
+        let response = self.request(Request::CalculateChecksum).await?;
+        verify(parse_checksum(Request::CalculateChecksum, &response)?, &image[..sent])?;
+        self.request(Request::Execute).await?;
+        Ok(())
+    }
+}
+
+/// Compares the bootloader's offset and checksum with what was sent.
+fn verify((offset, crc): (u32, u32), sent: &[u8]) -> Result<(), DfuError> {
+    if offset as usize != sent.len() {
+        return Err(DfuError::Offset {
+            expected: sent.len() as u32,
+            actual: offset,
+        });
+    }
+    let expected = crc32(sent);
+    if crc != expected {
+        return Err(DfuError::Checksum {
+            offset,
+            expected,
+            actual: crc,
+        });
+    }
+    Ok(())
+}
+
+/// Transfers `package` to the bootloader of the device at `address`, which
+/// must already have been asked to start it with `enter_bootloader`. The
+/// bootloader validates the image and boots it once the last object runs.
+pub async fn upload(adapter_index: Option<usize>, address: &str, package: &DfuPackage, progress: &DfuProgress) -> Result<(), DfuError> {
+    let device = find_bootloader(adapter_index, address).await?;
+    info!("Connecting to DFU bootloader {}...", device.address());
+    device.connect().await?;
+    device.discover_services().await?;
+    let chars = device.characteristics();
+    let find = |uuid, name| {
+        chars
+            .iter()
+            .find(|c| c.uuid == uuid)
+            .cloned()
+            .ok_or(DfuError::CharacteristicNotFound(name))
+    };
+    let control_point = find(CONTROL_POINT_UUID, "control point")?;
+    let packet = find(PACKET_UUID, "packet")?;
+    device.subscribe(&control_point).await?;
+    let notifications = device.notifications().await?;
+    let mut target = DfuTarget {
+        device: device.clone(),
+        control_point,
+        packet,
+        notifications,
+    };
+    let result = transfer(&mut target, package, progress).await;
+    // The bootloader resets into the new firmware and drops the link itself
+    let _ = device.disconnect().await;
+    result
+}
+
+async fn transfer<S: Stream<Item = btleplug::api::ValueNotification> + Unpin>(
+    target: &mut DfuTarget<S>,
+    package: &DfuPackage,
+    progress: &DfuProgress,
+) -> Result<(), DfuError> {
+    let total = package.firmware.len();
+    target.request(Request::SetReceipt(RECEIPT_INTERVAL)).await?;
+
+    let max = target.select(ObjectType::Command).await?;
+    if package.init_packet.len() > max as usize {
+        return Err(DfuError::InitPacketTooLarge {
+            size: package.init_packet.len(),
+            max,
+        });
+    }
+    info!("Sending {} init packet ({} bytes)", package.kind, package.init_packet.len());
+    target
+        .send_object(ObjectType::Command, &package.init_packet, 0, &package.init_packet, &mut |_| {})
+        .await?;
+
+    let max = target.select(ObjectType::Data).await?;
+    info!("Sending {} bytes of firmware in objects of {} bytes", total, max);
+    let mut start = 0;
+    for object in objects(&package.firmware, max) {
+        target
+            .send_object(ObjectType::Data, &package.firmware, start, object, &mut |sent| {
+                progress.set(DfuState::Uploading { sent, total });
+            })
+            .await?;
+        start += object.len();
+    }
+    info!("Firmware sent and verified");
+    Ok(())
+}
+
+/// Follows a session that ended for `request`: uploads the package, then
+/// reconnects to the application, retrying while it boots. The update's
+/// outcome goes to the request's progress; an error is only returned when
+/// the device can't be reached again with `uuids`.
+pub async fn update_and_reconnect(
+    adapter_index: Option<usize>,
+    address: &str,
+    uuids: &BleConfig,
+    request: &DfuRequest,
+    shutdown: &CancellationToken,
+) -> Result<DeviceConnection, DfuError> {
+    let progress = &request.progress;
+    let result = tokio::select! {
+        result = upload(adapter_index, address, &request.package, progress) => result,
+        _ = shutdown.cancelled() => return Err(DfuError::Cancelled),
+    };
+    let updated = match result {
+        Ok(()) => true,
+        Err(e) => {
+            progress.fail(&e);
+            false
+        }
+    };
+    if updated {
+        progress.set(DfuState::Reconnecting);
+    }
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let connection = tokio::select! {
+            connection = ble::connect(adapter_index, address, uuids) => connection,
+            _ = shutdown.cancelled() => return Err(DfuError::Cancelled),
+        };
+        match connection {
+            Ok(connection) => {
+                if updated {
+                    info!("Firmware update finished, reconnected");
+                    progress.set(DfuState::Finished);
+                }
+                return Ok(connection);
+            }
+            Err(e) if attempt < RECONNECT_ATTEMPTS => warn!("Reconnecting after firmware update failed: {}", e),
+            Err(e) => {
+                let e = DfuError::from(e);
+                if updated {
+                    progress.fail(&e);
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Starts an update from the session: puts the device into its bootloader,
+/// or records why it couldn't be.
+pub async fn start(device: &Peripheral, request: &DfuRequest) -> bool {
+    info!(
+        "Starting firmware update with a {} image of {} bytes",
+        request.package.kind,
+        request.package.firmware.len()
+    );
+    request.progress.set(DfuState::EnteringBootloader);
+    match enter_bootloader(device).await {
+        Ok(()) => {
+            request.progress.set(DfuState::Uploading {
+                sent: 0,
+                total: request.package.firmware.len(),
+            });
+            true
+        }
+        Err(e) => {
+            request.progress.fail(&e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A made-up init packet and 250 bytes of firmware, and the package
+    /// `nrfutil` would make of them; nothing a board would boot
+    const INIT_PACKET: &[u8] = include_bytes!("../tests/fixtures/dfu/app.dat");
+    const FIRMWARE: &[u8] = include_bytes!("../tests/fixtures/dfu/app.bin");
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/dfu").join(name)
+    }
+
+    #[test]
+    fn the_package_holds_the_fixture_image() {
+        let package = DfuPackage::read(&fixture("app.zip")).unwrap();
+        assert_eq!(package.kind, "application");
+        assert_eq!(package.init_packet, INIT_PACKET);
+        assert_eq!(package.firmware, FIRMWARE);
+        assert!(matches!(DfuPackage::read(&fixture("missing.zip")), Err(DfuError::Io(_))));
+        assert!(matches!(DfuPackage::read(&fixture("app.bin")), Err(DfuError::Package(_))));
+    }
+
+    #[test]
+    fn crc32_matches_known_values() {
+        assert_eq!(crc32(b""), 0);
+        // The CRC-32 check value
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(INIT_PACKET), 0xAA01_139D);
+        assert_eq!(crc32(FIRMWARE), 0xA99C_FB52);
+        assert_eq!(crc32(&FIRMWARE[..240]), 0x4D7B_2E1C);
+    }
+
+    #[test]
+    fn objects_are_split_at_the_bootloaders_size() {
+        let sizes: Vec<usize> = objects(FIRMWARE, 100).map(<[u8]>::len).collect();
+        assert_eq!(sizes, [100, 100, 50]);
+        assert_eq!(objects(FIRMWARE, 4096).count(), 1);
+        assert_eq!(objects(FIRMWARE, 0).count(), FIRMWARE.len(), "a size of 0 sends a byte at a time");
+    }
+
+    #[test]
+    fn packets_are_split_at_packet_size_with_a_receipt_every_12() {
+        let sent: Vec<(usize, bool)> = packets(FIRMWARE).map(|(packet, receipt)| (packet.len(), receipt)).collect();
+        assert_eq!(sent.len(), 13);
+        assert!(sent[..12].iter().all(|&(len, _)| len == PACKET_SIZE));
+        assert_eq!(sent[12].0, FIRMWARE.len() - 12 * PACKET_SIZE);
+        let receipts: Vec<usize> = (0..sent.len()).filter(|&index| sent[index].1).collect();
+        assert_eq!(receipts, [11]);
+        assert_eq!(packets(FIRMWARE).flat_map(|(packet, _)| packet.to_vec()).collect::<Vec<_>>(), FIRMWARE);
+
+        let receipts = |data: &[u8]| packets(data).filter(|(_, receipt)| *receipt).count();
+        // A short twelfth packet is still the twelfth
+        assert_eq!(receipts(&FIRMWARE[..11 * PACKET_SIZE]), 0);
+        assert_eq!(receipts(&FIRMWARE[..11 * PACKET_SIZE + 1]), 1);
+        assert_eq!(receipts(&[0; 24 * PACKET_SIZE]), 2);
+        assert_eq!(receipts(INIT_PACKET), 0);
+    }
+
+    #[test]
+    fn every_receipt_of_a_transfer_checks_out() {
+        // As the bootloader reports them, over objects of 240 bytes
+        let mut checkpoints = Vec::new();
+        let mut sent = 0;
+        for object in objects(FIRMWARE, 240) {
+            for (packet, receipt) in packets(object) {
+                sent += packet.len();
+                if receipt {
+                    checkpoints.push(sent);
+                }
+            }
+            checkpoints.push(sent);
+        }
+        assert_eq!(checkpoints, [240, 240, 250]);
+        for sent in checkpoints {
+            verify((sent as u32, crc32(&FIRMWARE[..sent])), &FIRMWARE[..sent]).unwrap();
+        }
+    }
+
+    #[test]
+    fn a_wrong_receipt_stops_the_transfer() {
+        let sent = &FIRMWARE[..240];
+        assert!(verify((240, 0x4D7B_2E1C), sent).is_ok());
+        assert!(matches!(
+            verify((240, 0x4D7B_2E1D), sent),
+            Err(DfuError::Checksum {
+                offset: 240,
+                expected: 0x4D7B_2E1C,
+                actual: 0x4D7B_2E1D
+            })
+        ));
+        assert!(matches!(
+            verify((220, 0x4D7B_2E1C), sent),
+            Err(DfuError::Offset {
+                expected: 240,
+                actual: 220
+            })
+        ));
+    }
+
+    #[test]
+    fn checksum_responses_are_parsed() {
+        // Offset 240 and its CRC, little-endian
+        let response = [OP_RESPONSE, OP_CALCULATE_CHECKSUM, RESULT_SUCCESS, 0xF0, 0, 0, 0, 0x1C, 0x2E, 0x7B, 0x4D];
+        assert_eq!(parse_checksum(Request::CalculateChecksum, &response).unwrap(), (240, 0x4D7B_2E1C));
+        assert!(matches!(
+            parse_checksum(Request::CalculateChecksum, &response[..10]),
+            Err(DfuError::InvalidResponse("checksum"))
+        ));
+        let rejected = [OP_RESPONSE, OP_CALCULATE_CHECKSUM, RESULT_EXTENDED_ERROR, 0x0E];
+        match parse_checksum(Request::CalculateChecksum, &rejected) {
+            Err(DfuError::Rejected { request, reason }) => {
+                assert_eq!((request, reason.as_str()), ("checksum", "hash verification failed"))
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use crate::config::NUM_ZONES;
+use crate::plot_history::PlotMemory;
+use crate::sync::LockExt;
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Latencies above this all land in the last bucket
+const MAX_LATENCY_MICROS: usize = 100_000;
+
+/// Histogram of durations with one-microsecond buckets up to 100 ms, cheap
+/// enough to update for every sample.
+pub struct LatencyHistogram {
+    buckets: Vec<u64>,
+    count: u64,
+    max: Duration,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        Self {
+            buckets: vec![0; MAX_LATENCY_MICROS + 1],
+            count: 0,
+            max: Duration::ZERO,
+        }
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let micros = (latency.as_micros() as usize).min(MAX_LATENCY_MICROS);
+        self.buckets[micros] += 1;
+        self.count += 1;
+        self.max = self.max.max(latency);
+    }
+
+    /// Smallest latency that at least `fraction` (0..=1) of the recorded values don't exceed.
+    pub fn percentile(&self, fraction: f64) -> Duration {
+        let target = (self.count as f64 * fraction).ceil() as u64;
+        let mut seen = 0;
+        for (micros, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= target.max(1) {
+                return Duration::from_micros(micros as u64);
+            }
+        }
+        self.max
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+}
+
+/// Counters the pipeline keeps about its own work
+pub struct PipelineStats {
+    pub processed: u64,
+    /// Processed samples per output zone
+    pub per_zone: [u64; NUM_ZONES],
+    /// Packets from the source that couldn't be parsed
+    pub parse_errors: u64,
+    /// Samples of device zones disabled in the device config, not processed
+    pub disabled_zone_samples: u64,
+    /// Failed sends that dropped the MIDI connection
+    pub midi_errors: u64,
+    /// Times the device session's watchdog found samples had stopped arriving
+    pub stalls: u64,
+    /// Events dropped by taps whose consumer was behind, per output zone. A
+    /// sample dropped by two taps counts twice.
+    pub tap_drops: [u64; NUM_ZONES],
+    /// How far the long-term baseline of every output zone drifted from
+    /// calibration, while past `drift.threshold_percent`
+    pub baseline_drift: [Option<f64>; NUM_ZONES],
+    /// Time from a sample entering the pipeline until MIDI was sent, taps were
+    /// fed and the plot history was updated
+    pub latency: LatencyHistogram,
+}
+
+impl Default for PipelineStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PipelineStats {
+    pub fn new() -> Self {
+        Self {
+            processed: 0,
+            per_zone: [0; NUM_ZONES],
+            parse_errors: 0,
+            disabled_zone_samples: 0,
+            midi_errors: 0,
+            stalls: 0,
+            tap_drops: [0; NUM_ZONES],
+            baseline_drift: [None; NUM_ZONES],
+            latency: LatencyHistogram::new(),
+        }
+    }
+}
+
+impl PipelineStats {
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            processed: self.processed,
+            per_zone: self.per_zone,
+            parse_errors: self.parse_errors,
+            disabled_zone_samples: self.disabled_zone_samples,
+            midi_errors: self.midi_errors,
+            stalls: self.stalls,
+            tap_drops: self.tap_drops,
+            baseline_drift: self.baseline_drift,
+            latency_p50_micros: self.latency.percentile(0.5).as_micros() as u64,
+            latency_p99_micros: self.latency.percentile(0.99).as_micros() as u64,
+            latency_max_micros: self.latency.max().as_micros() as u64,
+            plot_memory: None,
+        }
+    }
+}
+
+/// The counters of `PipelineStats` at one point in time
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct StatsSnapshot {
+    pub processed: u64,
+    pub per_zone: [u64; NUM_ZONES],
+    pub parse_errors: u64,
+    pub disabled_zone_samples: u64,
+    pub midi_errors: u64,
+    pub stalls: u64,
+    pub tap_drops: [u64; NUM_ZONES],
+    pub baseline_drift: [Option<f64>; NUM_ZONES],
+    pub latency_p50_micros: u64,
+    pub latency_p99_micros: u64,
+    pub latency_max_micros: u64,
+    /// Set by the pipeline when it keeps a plot history
+    pub plot_memory: Option<PlotMemory>,
+}
+
+/// How often the pipeline publishes its counters to a `SharedStats`
+pub const STATS_PUBLISH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The pipeline's counters as last published, for whoever runs beside the
+/// source task, like the GUI's diagnostics bundle
+#[derive(Clone, Default)]
+pub struct SharedStats {
+    latest: Arc<Mutex<Option<StatsSnapshot>>>,
+}
+
+impl SharedStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn publish(&self, stats: StatsSnapshot) {
+        *self.latest.lock_or_recover() = Some(stats);
+    }
+
+    /// `None` until the pipeline published once
+    pub fn latest(&self) -> Option<StatsSnapshot> {
+        *self.latest.lock_or_recover()
+    }
+}
+
+/// One-line summary for the end of a session
+impl fmt::Display for PipelineStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let per_zone: Vec<String> = self.per_zone.iter().map(u64::to_string).collect();
+        write!(
+            f,
+            "{} samples (per zone: {}), {} parse errors, {} MIDI errors, {} dropped by slow outputs",
+            self.processed,
+            per_zone.join(" "),
+            self.parse_errors,
+            self.midi_errors,
+            self.tap_drops.iter().sum::<u64>()
+        )?;
+        if self.disabled_zone_samples > 0 {
+            write!(f, ", {} from disabled zones ignored", self.disabled_zone_samples)?;
+        }
+        if self.stalls > 0 {
+            write!(f, ", {} stalls of the device's samples", self.stalls)?;
+        }
+        let drifted: Vec<String> = (0..NUM_ZONES)
+            .filter(|&zone| self.baseline_drift[zone].is_some())
+            .map(|zone| zone.to_string())
+            .collect();
+        if !drifted.is_empty() {
+            write!(f, ", baseline drifted in zones {}", drifted.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Longer than this without a sample, the source counts as not sending; the
+/// GUI, TUI and `--daemon` all judge by it
+pub const SAMPLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether samples are flowing, given when the newest one arrived
+pub fn receiving(received_at: Option<Instant>, timeout: Duration) -> bool {
+    received_at.is_some_and(|at| at.elapsed() < timeout)
+}
+
+/// When the pipeline handled its newest sample and how many it handled,
+/// shared with whoever checks that samples keep flowing without a plot to
+/// look at
+#[derive(Clone)]
+pub struct SampleHealth {
+    epoch: Instant,
+    /// Microseconds since `epoch` of the newest sample plus one, 0 before the first
+    last: Arc<AtomicU64>,
+    samples: Arc<AtomicU64>,
+}
+
+impl Default for SampleHealth {
+    fn default() -> Self {
+        Self {
+            epoch: Instant::now(),
+            last: Arc::new(AtomicU64::new(0)),
+            samples: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl SampleHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a sample handled at `now`.
+    pub fn record(&self, now: Instant) {
+        let micros = now.saturating_duration_since(self.epoch).as_micros() as u64;
+        self.last.store(micros + 1, Ordering::Relaxed);
+        self.samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn received_at(&self) -> Option<Instant> {
+        match self.last.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(self.epoch + Duration::from_micros(micros - 1)),
+        }
+    }
+
+    /// Samples handled since the pipeline started
+    pub fn samples(&self) -> u64 {
+        self.samples.load(Ordering::Relaxed)
+    }
+
+    pub fn receiving(&self, timeout: Duration) -> bool {
+        receiving(self.received_at(), timeout)
+    }
+}
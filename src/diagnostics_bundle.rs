@@ -0,0 +1,307 @@
+use crate::bundle::{BundleHeader, BundleRecord, BundleSample};
+use crate::config::overrides;
+use crate::config::{AppConfig, ConfigLayout, DildonicaZoneConfig, NUM_ZONES};
+use crate::diagnostics::StatsSnapshot;
+use crate::markers::SessionMarker;
+use crate::pipeline::SampleTap;
+use crate::recorder::{unix_time, write_markers, RecordFormat};
+use crate::sync::LockExt;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use thiserror::Error;
+use tracing::{info, warn};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Length of the sample capture a bundle can include
+pub const CAPTURE_DURATION: Duration = Duration::from_secs(10);
+/// Samples buffered for the capture, enough for a burst at a high rate
+const CAPTURE_QUEUE_SIZE: usize = 16384;
+const REDACTED: &str = "<redacted>";
+
+#[derive(Error, Debug)]
+pub enum DiagnosticsBundleError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+}
+
+/// The app build and the machine it runs on
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemInfo {
+    pub app_version: &'static str,
+    pub os: &'static str,
+    pub arch: &'static str,
+    /// Optional features built in
+    pub features: Vec<&'static str>,
+    /// Seconds since the Unix epoch
+    pub created: f64,
+}
+
+impl SystemInfo {
+    pub fn current() -> Self {
+        let features = [
+            ("gui", cfg!(feature = "gui")),
+            ("synth", cfg!(feature = "synth")),
+            ("gamepad", cfg!(feature = "gamepad")),
+            ("keys", cfg!(feature = "keys")),
+            ("sqlite", cfg!(feature = "sqlite")),
+            ("systemd", cfg!(feature = "systemd")),
+        ];
+        Self {
+            app_version: env!("CARGO_PKG_VERSION"),
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+            features: features.into_iter().filter(|&(_, on)| on).map(|(name, _)| name).collect(),
+            created: unix_time(SystemTime::now()),
+        }
+    }
+}
+
+/// The device the app was told to use and what it knows of it
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceInfo {
+    pub address: String,
+    pub adapter: Option<usize>,
+    /// Why the device couldn't be reached, if it couldn't
+    pub error: Option<String>,
+    /// Raw config blob the device sent last, as hex; also one that couldn't
+    /// be parsed
+    pub config_blob: Option<String>,
+    /// The zone configs last read or written
+    pub zone_configs: Option<[DildonicaZoneConfig; NUM_ZONES]>,
+}
+
+impl DeviceInfo {
+    /// The device at `address`, with the configs parsed from `blob` if it
+    /// was read
+    pub fn new(
+        address: String,
+        adapter: Option<usize>,
+        error: Option<String>,
+        blob: Option<&[u8]>,
+        zone_configs: [DildonicaZoneConfig; NUM_ZONES],
+    ) -> Self {
+        Self {
+            address,
+            adapter,
+            error,
+            // One zone per line where the layout is known
+            config_blob: blob.map(|blob| {
+                let row = ConfigLayout::detect(blob.len(), NUM_ZONES).map_or(DildonicaZoneConfig::SIZE, |layout| layout.size());
+                hex_blob(blob, row)
+            }),
+            zone_configs: blob.is_some().then_some(zone_configs),
+        }
+    }
+}
+
+/// Everything a diagnostics bundle holds, gathered by the caller so the
+/// bundle can be built from any snapshot
+pub struct DiagnosticsSnapshot {
+    pub system: SystemInfo,
+    pub app_config: AppConfig,
+    pub device: DeviceInfo,
+    pub stats: Option<StatsSnapshot>,
+    pub log_lines: Vec<String>,
+    /// Samples as received, if a capture was made
+    pub capture: Option<Vec<BundleSample>>,
+    /// The markers of the session so far
+    pub markers: Vec<SessionMarker>,
+}
+
+/// Replaces the value of every sensitive key in `value`, at any depth, with
+/// a placeholder, see `overrides::is_sensitive`. Empty strings stay, so it
+/// shows that nothing was set.
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                if overrides::is_sensitive(key) && !matches!(field, Value::Null) && field.as_str() != Some("") {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// Writes `snapshot` as a zip archive:
+///
+/// - `system.json`: app version, platform and features
+/// - `config.json`: the app config in effect, redacted
+/// - `device.json`: device address, connection error and zone configs
+/// - `stats.json`: pipeline counters, if the pipeline ran
+/// - `log.txt`: the recent log lines
+/// - `markers.json`: the markers of the session, if any
+/// - `capture.jsonl`: the captured samples and the markers among them as a
+///   session bundle with the redacted config, replayable with
+///   `replay --with-config`
+pub fn write_bundle<W: Write + Seek>(writer: W, snapshot: &DiagnosticsSnapshot) -> Result<W, DiagnosticsBundleError> {
+    let mut zip = ZipWriter::new(writer);
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    zip.start_file("system.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, &snapshot.system)?;
+
+    let mut app_config = serde_json::to_value(&snapshot.app_config)?;
+    redact(&mut app_config);
+    zip.start_file("config.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, &app_config)?;
+
+    zip.start_file("device.json", options)?;
+    serde_json::to_writer_pretty(&mut zip, &snapshot.device)?;
+
+    if let Some(stats) = &snapshot.stats {
+        zip.start_file("stats.json", options)?;
+        serde_json::to_writer_pretty(&mut zip, stats)?;
+    }
+
+    zip.start_file("log.txt", options)?;
+    for line in &snapshot.log_lines {
+        writeln!(zip, "{}", line)?;
+    }
+
+    if !snapshot.markers.is_empty() {
+        zip.start_file("markers.json", options)?;
+        serde_json::to_writer_pretty(&mut zip, &snapshot.markers)?;
+    }
+
+    if let Some(capture) = &snapshot.capture {
+        let started = capture.first().map_or(snapshot.system.created, |sample| sample.host_time);
+        let zone_configs = snapshot.device.zone_configs.unwrap_or_default();
+        let mut header = BundleHeader::new(started, &snapshot.app_config, &zone_configs);
+        header.app_config = app_config;
+        zip.start_file("capture.jsonl", options)?;
+        serde_json::to_writer(&mut zip, &BundleRecord::Header(header))?;
+        writeln!(zip)?;
+        for &sample in capture {
+            serde_json::to_writer(&mut zip, &BundleRecord::Sample(sample))?;
+            writeln!(zip)?;
+        }
+        let ended = capture.last().map_or(started, |sample| sample.host_time);
+        let markers: Vec<SessionMarker> = snapshot
+            .markers
+            .iter()
+            .filter(|marker| (started..=ended).contains(&marker.host_time))
+            .cloned()
+            .collect();
+        write_markers(&mut zip, RecordFormat::Bundle, &markers)?;
+    }
+
+    Ok(zip.finish()?)
+}
+
+/// Writes `snapshot` to a new file at `path`, see `write_bundle`.
+pub fn save_bundle(path: &Path, snapshot: &DiagnosticsSnapshot) -> Result<(), DiagnosticsBundleError> {
+    let file = File::create_new(path)?;
+    write_bundle(BufWriter::new(file), snapshot)?.flush()?;
+    info!("Diagnostics bundle saved to {}", path.display());
+    Ok(())
+}
+
+/// File name for a bundle made at `time`, e.g.
+/// `dildonica-diagnostics-20261014-092100.zip`
+pub fn file_name(time: DateTime<Local>) -> String {
+    format!("dildonica-diagnostics-{}.zip", time.format("%Y%m%d-%H%M%S"))
+}
+
+/// Hex dump of a config blob, one line per `row` bytes
+fn hex_blob(blob: &[u8], row: usize) -> String {
+    blob.chunks(row.max(1))
+        .map(|chunk| chunk.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" "))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[derive(Default)]
+struct Capture {
+    /// Until when samples are kept, from the first one on; `None` while idle
+    duration: Option<Duration>,
+    started: Option<Instant>,
+    samples: Vec<BundleSample>,
+    /// A capture that ran its duration, until taken
+    finished: Option<Vec<BundleSample>>,
+}
+
+/// Captures the samples from a pipeline tap for a diagnostics bundle, on
+/// demand: `begin` starts a capture, `take_finished` returns it once it ran
+/// its duration.
+#[derive(Clone)]
+pub struct SampleCapture {
+    capture: Arc<Mutex<Capture>>,
+}
+
+impl SampleCapture {
+    /// Starts the collecting thread, which ends with the pipeline.
+    pub fn start() -> (Self, SampleTap) {
+        let (tap, mut rx, dropped) = SampleTap::new(CAPTURE_QUEUE_SIZE);
+        let capture = Arc::new(Mutex::new(Capture::default()));
+        let thread_capture = capture.clone();
+        std::thread::spawn(move || {
+            while let Some(event) = rx.blocking_recv() {
+                let mut capture = thread_capture.lock_or_recover();
+                let Some(duration) = capture.duration else {
+                    continue;
+                };
+                let now = Instant::now();
+                let started = *capture.started.get_or_insert(now);
+                if now.duration_since(started) >= duration {
+                    capture.duration = None;
+                    capture.finished = Some(std::mem::take(&mut capture.samples));
+                    continue;
+                }
+                capture.samples.push(BundleSample::new(unix_time(event.received), &event.sample));
+            }
+            let dropped = dropped.load(Ordering::Relaxed);
+            if dropped > 0 {
+                warn!("Sample capture fell behind, dropped {} samples", dropped);
+            }
+        });
+        (Self { capture }, tap)
+    }
+
+    /// Starts capturing for `duration` from the next sample, discarding a
+    /// running capture.
+    pub fn begin(&self, duration: Duration) {
+        *self.capture.lock_or_recover() = Capture {
+            duration: Some(duration),
+            ..Capture::default()
+        };
+        info!("Capturing samples for {:.0} s", duration.as_secs_f64());
+    }
+
+    /// Time since the running capture's first sample and its duration,
+    /// `None` while idle
+    pub fn progress(&self) -> Option<(Duration, Duration)> {
+        let capture = self.capture.lock_or_recover();
+        let duration = capture.duration?;
+        Some((capture.started.map_or(Duration::ZERO, |started| started.elapsed()), duration))
+    }
+
+    /// The capture that ran its duration since the last call
+    pub fn take_finished(&self) -> Option<Vec<BundleSample>> {
+        self.capture.lock_or_recover().finished.take()
+    }
+
+    /// Ends the running capture early and returns what it has, e.g. when the
+    /// samples stopped coming.
+    pub fn stop(&self) -> Vec<BundleSample> {
+        let mut capture = self.capture.lock_or_recover();
+        capture.duration = None;
+        capture.finished.take().unwrap_or_else(|| std::mem::take(&mut capture.samples))
+    }
+}
@@ -0,0 +1,187 @@
+use crate::config::DriftConfig;
+use std::time::Instant;
+
+/// Readings of the baseline kept per window; the long-term baseline is
+/// their median
+const WINDOW_READINGS: usize = 30;
+/// A drifted zone counts as back once its drift falls below this fraction
+/// of the threshold, so it doesn't flap around it
+const RECOVERY_FRACTION: f64 = 0.8;
+
+/// A zone crossing the drift threshold, one way or the other
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DriftChange {
+    /// The long-term baseline moved this fraction away from the reference
+    Drifted(f64),
+    Recovered,
+}
+
+/// Watches one zone's baseline for slow drift away from its reference, the
+/// baseline at calibration time. The baseline is read at even intervals and
+/// the median of a window of readings compared, so a touch held for less
+/// than half the window doesn't count.
+#[derive(Debug, Clone, Default)]
+pub struct DriftDetector {
+    /// Without a calibration, the first long-term baseline
+    reference: Option<f64>,
+    /// Oldest first
+    readings: Vec<f64>,
+    last_reading: Option<Instant>,
+    /// Drift of the last long-term baseline, `None` until the window filled
+    drift: Option<f64>,
+    drifted: bool,
+}
+
+impl DriftDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fraction the long-term baseline is away from the reference, while
+    /// past the threshold
+    pub fn drifted(&self) -> Option<f64> {
+        self.drift.filter(|_| self.drifted)
+    }
+
+    /// Forgets the reference and the readings, as when the baseline starts
+    /// over.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Takes `baseline` as the reference, as at the end of a calibration,
+    /// and starts a new window.
+    pub fn set_reference(&mut self, baseline: f64) {
+        self.reset();
+        self.reference = Some(baseline);
+    }
+
+    /// Feeds the zone's `baseline` at `now`. Returns a change when the
+    /// long-term baseline just crossed `threshold_percent` away from the
+    /// reference, or came back within `RECOVERY_FRACTION` of it.
+    pub fn update(&mut self, baseline: f64, now: Instant, config: &DriftConfig) -> Option<DriftChange> {
+        let interval = config.window() / WINDOW_READINGS as u32;
+        if self.last_reading.is_some_and(|last| now.saturating_duration_since(last) < interval) {
+            return None;
+        }
+        self.last_reading = Some(now);
+        self.readings.push(baseline);
+        if self.readings.len() > WINDOW_READINGS {
+            self.readings.remove(0);
+        }
+        if self.readings.len() < WINDOW_READINGS {
+            return None;
+        }
+
+        let mut sorted = self.readings.clone();
+        sorted.sort_by(f64::total_cmp);
+        let median = sorted[WINDOW_READINGS / 2];
+        let reference = *self.reference.get_or_insert(median);
+        if reference.abs() < f64::EPSILON {
+            return None;
+        }
+        let drift = (median - reference) / reference;
+        self.drift = Some(drift);
+        let threshold = config.threshold_percent / 100.0;
+        if !self.drifted && drift.abs() > threshold {
+            self.drifted = true;
+            Some(DriftChange::Drifted(drift))
+        } else if self.drifted && drift.abs() < threshold * RECOVERY_FRACTION {
+            self.drifted = false;
+            Some(DriftChange::Recovered)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const BASELINE: f64 = 5000.0;
+
+    /// The changes reported feeding `baseline_at` every second for `secs`,
+    /// each with the second it came at
+    fn run(detector: &mut DriftDetector, secs: u64, baseline_at: impl Fn(u64) -> f64) -> Vec<(u64, DriftChange)> {
+        let config = DriftConfig::default();
+        let start = Instant::now();
+        (0..secs)
+            .filter_map(|sec| {
+                let change = detector.update(baseline_at(sec), start + Duration::from_secs(sec), &config);
+                change.map(|change| (sec, change))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn slow_drift_warns_once_past_the_threshold() {
+        let mut detector = DriftDetector::new();
+        // +20 % over an hour
+        let changes = run(&mut detector, 3600, |sec| BASELINE * (1.0 + 0.2 * sec as f64 / 3600.0));
+        assert_eq!(changes.len(), 1, "{:?}", changes);
+        let (sec, DriftChange::Drifted(drift)) = changes[0] else {
+            panic!("{:?}", changes);
+        };
+        // 10 % above the first window's median, itself 2.5 minutes in, is
+        // reached at about 33 minutes, and the median lags half a window
+        assert!((34 * 60..37 * 60).contains(&sec), "warned at {} s", sec);
+        assert!(drift > 0.1 && drift < 0.11, "{}", drift);
+        assert!(detector.drifted().is_some());
+    }
+
+    #[test]
+    fn momentary_touches_never_warn() {
+        let mut detector = DriftDetector::new();
+        // A minute at +50 % every ten minutes
+        let changes = run(&mut detector, 3600, |sec| if sec % 600 < 60 { BASELINE * 1.5 } else { BASELINE });
+        assert!(changes.is_empty(), "{:?}", changes);
+        assert_eq!(detector.drifted(), None);
+    }
+
+    #[test]
+    fn a_step_warns_and_going_back_recovers() {
+        let mut detector = DriftDetector::new();
+        let changes = run(&mut detector, 1800, |sec| match sec {
+            0..600 => BASELINE,
+            600..1200 => BASELINE * 1.2,
+            _ => BASELINE,
+        });
+        assert_eq!(changes.len(), 2, "{:?}", changes);
+        assert!(matches!(changes[0], (600..900, DriftChange::Drifted(drift)) if (drift - 0.2).abs() < 1e-9));
+        assert!(matches!(changes[1], (1200..1500, DriftChange::Recovered)));
+        assert_eq!(detector.drifted(), None);
+    }
+
+    #[test]
+    fn the_calibrated_reference_counts_from_the_start() {
+        let mut detector = DriftDetector::new();
+        detector.set_reference(BASELINE);
+        // Already 15 % off when the first window fills
+        let changes = run(&mut detector, 600, |_| BASELINE * 1.15);
+        assert!(matches!(changes[..], [(290..310, DriftChange::Drifted(_))]), "{:?}", changes);
+
+        // Without one the first window is the reference
+        let mut detector = DriftDetector::new();
+        assert!(run(&mut detector, 600, |_| BASELINE * 1.15).is_empty());
+    }
+
+    #[test]
+    fn reset_forgets_the_drift() {
+        let mut detector = DriftDetector::new();
+        detector.set_reference(BASELINE);
+        run(&mut detector, 600, |_| BASELINE * 1.5);
+        assert!(detector.drifted().is_some());
+        detector.reset();
+        assert_eq!(detector.drifted(), None);
+        assert!(run(&mut detector, 600, |_| BASELINE * 1.5).is_empty());
+    }
+
+    #[test]
+    fn a_zero_reference_warns_of_nothing() {
+        let mut detector = DriftDetector::new();
+        detector.set_reference(0.0);
+        assert!(run(&mut detector, 600, |_| BASELINE).is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use crate::bench::BenchError;
+use crate::ble::BleError;
+use crate::check::CheckError;
+use crate::config::{ConfigError, DeviceConfigError, HeaderError};
+#[cfg(all(unix, feature = "systemd"))]
+use crate::daemon::DaemonError;
+use crate::dfu::DfuError;
+use crate::diagnostics_bundle::DiagnosticsBundleError;
+use crate::instance_lock::InstanceLockError;
+use crate::relay::RelayError;
+use crate::replay::ReplayError;
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Why a subcommand failed, logged by `main` before exiting with status 1
+#[derive(Error, Debug)]
+pub enum AppError {
+    /// Options that are accepted by clap but don't make sense together
+    #[error("{0}")]
+    InvalidArgs(&'static str),
+    #[error("Expected {expected} zone configs, found {found}")]
+    ZoneCount { expected: usize, found: usize },
+    #[error("No hardware template `{name}`, known are: {known}")]
+    UnknownTemplate { name: String, known: String },
+    #[error("Zone {zone}: {message}")]
+    InvalidZoneConfig { zone: usize, message: String },
+    #[error("Failed to access {}: {source}", path.display())]
+    File { path: PathBuf, source: io::Error },
+    #[error("Failed to start {server} on port {port}: {source}")]
+    Server {
+        server: &'static str,
+        port: u16,
+        source: io::Error,
+    },
+    #[cfg(feature = "sqlite")]
+    #[error("Failed to open {}: {source}", path.display())]
+    SessionDb { path: PathBuf, source: rusqlite::Error },
+    #[error("Failed to save {}: {source}", path.display())]
+    DiagnosticsBundle {
+        path: PathBuf,
+        source: DiagnosticsBundleError,
+    },
+    #[error("Terminal failed: {0}")]
+    Terminal(io::Error),
+    #[cfg(feature = "gui")]
+    #[error("GUI failed: {0}")]
+    Gui(#[from] eframe::Error),
+    #[error("Failed to save the app config: {0}")]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Ble(#[from] BleError),
+    #[error(transparent)]
+    InstanceLock(#[from] InstanceLockError),
+    #[error(transparent)]
+    DeviceConfig(#[from] DeviceConfigError),
+    #[error(transparent)]
+    Header(#[from] HeaderError),
+    #[error(transparent)]
+    Dfu(#[from] DfuError),
+    #[error(transparent)]
+    Replay(#[from] ReplayError),
+    #[error(transparent)]
+    Relay(#[from] RelayError),
+    #[error(transparent)]
+    Bench(#[from] BenchError),
+    #[error(transparent)]
+    Check(#[from] CheckError),
+    #[cfg(all(unix, feature = "systemd"))]
+    #[error(transparent)]
+    Daemon(#[from] DaemonError),
+    /// The report is already printed, this only sets the exit status
+    #[error("Self-test failed")]
+    SelfTestFailed,
+    #[error("Invalid zone config JSON: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl AppError {
+    pub fn file(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| AppError::File { path, source }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instance_lock::LockHolder;
+
+    #[test]
+    fn file_errors_name_the_file() {
+        let error = AppError::file("/tmp/zones.json")(io::Error::new(io::ErrorKind::NotFound, "not found"));
+        assert!(matches!(&error, AppError::File { path, .. } if path.ends_with("zones.json")));
+        assert_eq!(error.to_string(), "Failed to access /tmp/zones.json: not found");
+    }
+
+    #[test]
+    fn server_errors_name_the_port() {
+        let error = AppError::Server {
+            server: "OSC",
+            port: 9000,
+            source: io::Error::new(io::ErrorKind::AddrInUse, "in use"),
+        };
+        assert_eq!(error.to_string(), "Failed to start OSC on port 9000: in use");
+    }
+
+    #[test]
+    fn config_errors_convert() {
+        let error = AppError::from(ConfigError::Invalid("alpha out of range".to_string()));
+        assert!(matches!(error, AppError::Config(ConfigError::Invalid(_))));
+        assert_eq!(error.to_string(), "Failed to save the app config: alpha out of range");
+    }
+
+    #[test]
+    fn json_errors_convert() {
+        let error: AppError = serde_json::from_str::<Vec<u8>>("[").unwrap_err().into();
+        assert!(matches!(error, AppError::Json(_)));
+        assert!(error.to_string().starts_with("Invalid zone config JSON: "), "{}", error);
+    }
+
+    #[test]
+    fn library_errors_keep_their_message() {
+        let error = AppError::from(InstanceLockError::Held {
+            address: "AA:BB".to_string(),
+            holder: LockHolder { pid: 42, since: 0.0 },
+        });
+        assert!(error.to_string().starts_with("Device AA:BB is in use by another instance (PID 42"), "{}", error);
+
+        let error = AppError::from(HeaderError::NoArray);
+        assert_eq!(error.to_string(), "No array of zone config initializers found");
+
+        let error = AppError::from(ReplayError::MissingColumn("zone"));
+        assert_eq!(error.to_string(), "Recording has no `zone` column");
+    }
+}
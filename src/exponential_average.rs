@@ -2,6 +2,10 @@
 pub struct ExponentialAverage {
     alpha: f64,
     current_average: Option<f64>,
+    /// Values averaged plainly before `alpha` takes over
+    warmup: u64,
+    /// Values averaged since the last reset
+    count: u64,
 }
 
 impl ExponentialAverage {
@@ -13,13 +17,45 @@ impl ExponentialAverage {
         ExponentialAverage {
             alpha,
             current_average: None,
+            warmup: 0,
+            count: 0,
         }
     }
 
+    /// Changes the smoothing factor while keeping the current average.
+    pub fn set_alpha(&mut self, alpha: f64) {
+        assert!(
+            (0.0..=1.0).contains(&alpha),
+            "Alpha must be between 0 and 1"
+        );
+        self.alpha = alpha;
+    }
+
+    /// Takes the plain mean of the first `warmup` values before averaging at
+    /// alpha, so an outlier among them weighs no more than any other instead
+    /// of starting the average. Without it the first value is the average.
+    /// An average already past a new, shorter warm-up goes on at alpha.
+    pub fn set_warmup(&mut self, warmup: u64) {
+        self.warmup = warmup;
+    }
+
+    /// Forgets the average, the next value starts a new one.
+    pub fn reset(&mut self) {
+        self.current_average = None;
+        self.count = 0;
+    }
+
     pub fn update(&mut self, new_value: f64) {
+        self.count = self.count.saturating_add(1);
+        // The mean's weight falls to alpha, it never weighs a value less
+        let weight = if self.count <= self.warmup {
+            (1.0 / self.count as f64).max(self.alpha)
+        } else {
+            self.alpha
+        };
         self.current_average = Some(match self.current_average {
             None => new_value,
-            Some(avg) => avg * (1.0 - self.alpha) + new_value * self.alpha,
+            Some(avg) => avg + (new_value - avg) * weight,
         });
     }
 
@@ -27,3 +63,95 @@ impl ExponentialAverage {
         self.current_average
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALPHA: f64 = 0.001;
+
+    fn warmed_up(alpha: f64, warmup: u64) -> ExponentialAverage {
+        let mut average = ExponentialAverage::new(alpha);
+        average.set_warmup(warmup);
+        average
+    }
+
+    /// The average after a spike of 20000 and then `count - 1` values
+    /// around 10000
+    fn after_spike(mut average: ExponentialAverage, count: usize) -> f64 {
+        average.update(20000.0);
+        for index in 1..count {
+            average.update(10000.0 + if index.is_multiple_of(2) { 50.0 } else { -50.0 });
+        }
+        average.get_average().unwrap()
+    }
+
+    #[test]
+    fn without_warmup_the_first_value_starts_the_average() {
+        let mut average = ExponentialAverage::new(0.5);
+        assert_eq!(average.get_average(), None);
+        average.update(10.0);
+        assert_eq!(average.get_average(), Some(10.0));
+        average.update(20.0);
+        assert_eq!(average.get_average(), Some(15.0));
+    }
+
+    #[test]
+    fn a_spike_among_the_first_values_no_longer_dominates() {
+        let plain = ExponentialAverage::new(ALPHA);
+        let warmed = warmed_up(ALPHA, 50);
+        // Without warm-up the spike is still most of the baseline
+        assert!(after_spike(plain, 50) > 19000.0);
+        assert!(after_spike(plain, 1000) > 13000.0);
+        // With it the spike weighs a fiftieth, and wears off at alpha after
+        assert!((after_spike(warmed, 50) - 10200.0).abs() < 10.0);
+        assert!(after_spike(warmed, 1000) < 10100.0);
+    }
+
+    #[test]
+    fn the_warmup_is_a_plain_mean() {
+        let mut average = warmed_up(ALPHA, 4);
+        for value in [1.0, 2.0, 3.0, 6.0] {
+            average.update(value);
+        }
+        assert_eq!(average.get_average(), Some(3.0));
+        // Then alpha takes over
+        average.update(1003.0);
+        assert!((average.get_average().unwrap() - 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn a_large_alpha_reacts_as_fast_during_the_warmup() {
+        let mut plain = ExponentialAverage::new(0.5);
+        let mut warmed = warmed_up(0.5, 50);
+        for value in [0.0, 100.0, 100.0, 100.0, 100.0] {
+            plain.update(value);
+            warmed.update(value);
+        }
+        assert_eq!(warmed.get_average(), plain.get_average());
+    }
+
+    #[test]
+    fn a_reset_starts_the_warmup_over() {
+        let mut average = warmed_up(ALPHA, 2);
+        for value in [5.0, 7.0, 100.0] {
+            average.update(value);
+        }
+        average.reset();
+        assert_eq!(average.get_average(), None);
+        average.update(1.0);
+        average.update(3.0);
+        assert_eq!(average.get_average(), Some(2.0));
+    }
+
+    #[test]
+    fn a_shorter_warmup_set_later_goes_on_at_alpha() {
+        let mut average = warmed_up(ALPHA, 100);
+        for value in [1.0, 3.0] {
+            average.update(value);
+        }
+        average.set_warmup(1);
+        average.update(1002.0);
+        assert!((average.get_average().unwrap() - 3.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,222 @@
+use crate::config::gamepad::{GamepadAxis, GamepadZone};
+use crate::config::{ConfigStore, NUM_ZONES};
+use crate::output::{SinkMessage, ThreadedSink};
+use crate::pipeline::SampleEvent;
+use crate::sync::{LockExt, TrackedMutex};
+use evdev::uinput::VirtualDevice;
+use evdev::{AbsInfo, AbsoluteAxisCode, AbsoluteAxisEvent, AttributeSet, InputEvent, KeyCode, KeyEvent, UinputAbsSetup};
+use std::io;
+use std::sync::Arc;
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+const STICK_MAX: i32 = 32767;
+const TRIGGER_MAX: i32 = 255;
+/// Button pressed by each zone
+const BUTTONS: [KeyCode; NUM_ZONES] = [
+    KeyCode::BTN_SOUTH,
+    KeyCode::BTN_EAST,
+    KeyCode::BTN_NORTH,
+    KeyCode::BTN_WEST,
+    KeyCode::BTN_TL,
+    KeyCode::BTN_TR,
+    KeyCode::BTN_SELECT,
+    KeyCode::BTN_START,
+];
+
+#[derive(Error, Debug)]
+pub enum GamepadError {
+    #[error("/dev/uinput does not exist, load the kernel module with `sudo modprobe uinput`")]
+    NoUinput,
+    #[error(
+        "No permission to open /dev/uinput. Add a udev rule like \
+         `KERNEL==\"uinput\", GROUP=\"input\", MODE=\"0660\"` and add your user to the input group"
+    )]
+    PermissionDenied,
+    #[error("Failed to create the virtual gamepad: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Starts the virtual gamepad thread. The device exists while
+/// `gamepad.enabled` is set and is removed again when it is cleared.
+pub fn start(app_config: Arc<TrackedMutex<ConfigStore>>) -> ThreadedSink {
+    ThreadedSink::spawn("Gamepad", |config| config.gamepad.enabled, move |rx, sink_errors| {
+        let mut gamepad = None;
+        // Don't retry for every sample, only after toggling
+        let mut failed = false;
+        while let Ok(message) = rx.recv() {
+            let (config, threshold) = {
+                let config = app_config.lock_or_recover();
+                (config.gamepad.clone(), config.midi.note_config.threshold)
+            };
+            if !config.enabled {
+                if let Some(gamepad) = gamepad.take() {
+                    Gamepad::close(gamepad);
+                }
+                failed = false;
+                continue;
+            }
+            let event = match message {
+                SinkMessage::Sample(event) => event,
+                SinkMessage::Flush => {
+                    if let Some(Err(e)) = gamepad.as_mut().map(Gamepad::reset) {
+                        warn!("Failed to release virtual gamepad buttons: {}", e);
+                        sink_errors.report(e);
+                    }
+                    continue;
+                }
+            };
+
+            if gamepad.is_none() && !failed {
+                match Gamepad::open() {
+                    Ok(opened) => gamepad = Some(opened),
+                    Err(e) => {
+                        error!("{}", e);
+                        sink_errors.report(e);
+                        failed = true;
+                    }
+                }
+            }
+            if let Some(device) = &mut gamepad {
+                if let Err(e) = device.update(&config.zones, threshold, &event) {
+                    error!("Virtual gamepad failed: {}", e);
+                    sink_errors.report(e);
+                    gamepad = None;
+                    failed = true;
+                }
+            }
+        }
+
+        if let Some(gamepad) = gamepad {
+            Gamepad::close(gamepad);
+        }
+    })
+}
+
+struct Gamepad {
+    device: VirtualDevice,
+    /// Mapping the current axis and button state was produced with
+    zones: Vec<GamepadZone>,
+    axis_value: [i32; NUM_ZONES],
+    pressed: [bool; NUM_ZONES],
+}
+
+impl Gamepad {
+    /// Creates the device with every axis, so remapping zones at runtime
+    /// doesn't need a new device.
+    fn open() -> Result<Self, GamepadError> {
+        let builder = VirtualDevice::builder().map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => GamepadError::NoUinput,
+            io::ErrorKind::PermissionDenied => GamepadError::PermissionDenied,
+            _ => GamepadError::Io(e),
+        })?;
+        let buttons: AttributeSet<KeyCode> = BUTTONS.iter().collect();
+        let mut builder = builder.name("Dildonica Gamepad").with_keys(&buttons)?;
+        for axis in [
+            GamepadAxis::LeftX,
+            GamepadAxis::LeftY,
+            GamepadAxis::RightX,
+            GamepadAxis::RightY,
+            GamepadAxis::LeftTrigger,
+            GamepadAxis::RightTrigger,
+        ] {
+            let Some(code) = axis_code(axis) else { continue };
+            let info = if is_trigger(axis) {
+                AbsInfo::new(0, 0, TRIGGER_MAX, 0, 0, 0)
+            } else {
+                AbsInfo::new(0, -STICK_MAX, STICK_MAX, 16, 128, 0)
+            };
+            builder = builder.with_absolute_axis(&UinputAbsSetup::new(code, info))?;
+        }
+        let device = builder.build()?;
+        info!("Virtual gamepad created");
+        Ok(Self {
+            device,
+            zones: Vec::new(),
+            axis_value: [0; NUM_ZONES],
+            pressed: [false; NUM_ZONES],
+        })
+    }
+
+    fn update(&mut self, zones: &[GamepadZone], threshold: f64, event: &SampleEvent) -> io::Result<()> {
+        if zones != self.zones {
+            // Don't leave axes or buttons behind that are no longer mapped
+            self.reset()?;
+            self.zones = zones.to_vec();
+        }
+        let zone = event.processed.zone;
+        let Some(mapping) = self.zones.get(zone).copied() else {
+            return Ok(());
+        };
+        let magnitude = event.processed.value_normalized.abs();
+        let mut events = Vec::with_capacity(2);
+
+        if let Some(code) = axis_code(mapping.axis) {
+            let deflection = (magnitude * mapping.scale).min(1.0);
+            let value = match (is_trigger(mapping.axis), mapping.invert) {
+                (true, false) => deflection * TRIGGER_MAX as f64,
+                (true, true) => (1.0 - deflection) * TRIGGER_MAX as f64,
+                (false, false) => deflection * STICK_MAX as f64,
+                (false, true) => -deflection * STICK_MAX as f64,
+            }
+            .round() as i32;
+            if value != self.axis_value[zone] {
+                self.axis_value[zone] = value;
+                events.push(*AbsoluteAxisEvent::new(code, value));
+            }
+        }
+
+        let pressed = mapping.button && magnitude > threshold;
+        if pressed != self.pressed[zone] {
+            self.pressed[zone] = pressed;
+            events.push(*KeyEvent::new(BUTTONS[zone], pressed as i32));
+        }
+
+        if events.is_empty() {
+            return Ok(());
+        }
+        self.device.emit(&events)
+    }
+
+    /// Centers every mapped axis and releases every button.
+    fn reset(&mut self) -> io::Result<()> {
+        let mut events: Vec<InputEvent> = Vec::new();
+        for (zone, mapping) in self.zones.iter().enumerate() {
+            if let Some(code) = axis_code(mapping.axis) {
+                events.push(*AbsoluteAxisEvent::new(code, 0));
+            }
+            if self.pressed[zone] {
+                events.push(*KeyEvent::new(BUTTONS[zone], 0));
+            }
+        }
+        self.axis_value = [0; NUM_ZONES];
+        self.pressed = [false; NUM_ZONES];
+        if events.is_empty() {
+            return Ok(());
+        }
+        self.device.emit(&events)
+    }
+
+    fn close(mut self) {
+        if let Err(e) = self.reset() {
+            warn!("Failed to release virtual gamepad buttons: {}", e);
+        }
+        info!("Virtual gamepad removed");
+    }
+}
+
+fn axis_code(axis: GamepadAxis) -> Option<AbsoluteAxisCode> {
+    match axis {
+        GamepadAxis::None => None,
+        GamepadAxis::LeftX => Some(AbsoluteAxisCode::ABS_X),
+        GamepadAxis::LeftY => Some(AbsoluteAxisCode::ABS_Y),
+        GamepadAxis::RightX => Some(AbsoluteAxisCode::ABS_RX),
+        GamepadAxis::RightY => Some(AbsoluteAxisCode::ABS_RY),
+        GamepadAxis::LeftTrigger => Some(AbsoluteAxisCode::ABS_Z),
+        GamepadAxis::RightTrigger => Some(AbsoluteAxisCode::ABS_RZ),
+    }
+}
+
+fn is_trigger(axis: GamepadAxis) -> bool {
+    matches!(axis, GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger)
+}
@@ -0,0 +1,140 @@
+use crate::config::gesture::GestureConfig;
+use crate::config::NUM_ZONES;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Gesture {
+    TouchStart,
+    TouchEnd,
+    /// The touch has lasted `hold_ms`, sent once while still touching
+    Hold,
+    /// Sent with the `TouchEnd` of a short touch
+    Tap,
+    /// Sent with the `Tap` of the second of two quick taps
+    DoubleTap,
+}
+
+const ALL_GESTURES: [Gesture; 5] = [
+    Gesture::TouchStart,
+    Gesture::TouchEnd,
+    Gesture::Hold,
+    Gesture::Tap,
+    Gesture::DoubleTap,
+];
+
+impl fmt::Display for Gesture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Gesture::TouchStart => "touch_start",
+            Gesture::TouchEnd => "touch_end",
+            Gesture::Hold => "hold",
+            Gesture::Tap => "tap",
+            Gesture::DoubleTap => "double_tap",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The gestures one sample completed; a release can end a touch, a tap and a
+/// double tap at once.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Gestures(u8);
+
+impl Gestures {
+    pub fn insert(&mut self, gesture: Gesture) {
+        self.0 |= 1 << gesture as u8;
+    }
+
+    pub fn contains(self, gesture: Gesture) -> bool {
+        self.0 & (1 << gesture as u8) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Gesture> {
+        ALL_GESTURES.into_iter().filter(move |&gesture| self.contains(gesture))
+    }
+}
+
+/// Serialized as a list of gesture names
+impl Serialize for Gestures {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct ZoneTouch {
+    touch_start: Option<Instant>,
+    hold_sent: bool,
+    /// End of the last tap, while a second one would still be a double tap
+    last_tap: Option<Instant>,
+}
+
+/// Turns each zone's magnitude into touch gestures.
+pub struct GestureDetector {
+    zones: [ZoneTouch; NUM_ZONES],
+}
+
+impl Default for GestureDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GestureDetector {
+    pub fn new() -> Self {
+        Self {
+            zones: [ZoneTouch::default(); NUM_ZONES],
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        zone: usize,
+        magnitude: f64,
+        threshold: f64,
+        config: &GestureConfig,
+        now: Instant,
+    ) -> Gestures {
+        let ms = |ms: f64| Duration::from_secs_f64(ms / 1000.0);
+        let state = &mut self.zones[zone];
+        let mut gestures = Gestures::default();
+
+        match state.touch_start {
+            None if magnitude > threshold => {
+                state.touch_start = Some(now);
+                state.hold_sent = false;
+                gestures.insert(Gesture::TouchStart);
+            }
+            Some(start) if magnitude <= threshold => {
+                state.touch_start = None;
+                gestures.insert(Gesture::TouchEnd);
+                if now.duration_since(start) <= ms(config.tap_max_ms) {
+                    gestures.insert(Gesture::Tap);
+                    let double = state
+                        .last_tap
+                        .is_some_and(|last| start.duration_since(last) <= ms(config.double_tap_ms));
+                    if double {
+                        gestures.insert(Gesture::DoubleTap);
+                        // A third tap starts a new pair
+                        state.last_tap = None;
+                    } else {
+                        state.last_tap = Some(now);
+                    }
+                }
+            }
+            Some(start) if !state.hold_sent && now.duration_since(start) >= ms(config.hold_ms) => {
+                state.hold_sent = true;
+                gestures.insert(Gesture::Hold);
+            }
+            _ => {}
+        }
+        gestures
+    }
+}
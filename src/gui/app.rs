@@ -1,107 +1,817 @@
-use crate::config::{AppConfig, DildonicaZoneConfig};
+use super::focus::ZoneFocus;
+use super::i18n::{self, t, tf};
+use super::plot::{PausedPlot, PlotCache};
+use super::toast::Toasts;
+use super::widgets::lockable;
+use crate::auto_gain::AutoGain;
+use crate::batching::BatchingStatus;
+use crate::ble::{ConfigActivity, ConfigReadReport};
+use crate::bundle::BundleSample;
+use crate::config::{AppConfig, BatchingConfig, BleConfig, ConfigStore, DildonicaZoneConfig, StartupAction, NUM_ZONES};
+use crate::config_compare::{CompareReport, Compared, ConfigCompare};
+use crate::config_history::HistoryEntry;
+use crate::diagnostics::{self, SharedStats, SAMPLE_TIMEOUT};
+use crate::diagnostics_bundle::{self, DeviceInfo, DiagnosticsSnapshot, SampleCapture, SystemInfo};
+use crate::dfu::{DfuProgress, DfuRequest};
+use crate::instance_lock::LockHolder;
+use crate::lfo::TapTempo;
+use crate::logging;
+use crate::markers::{marker_name, SessionMarkers};
+use crate::looper::LooperHandle;
+use crate::metronome::Metronome;
+use crate::patch::PatchSelector;
+use crate::midi::{MidiOutputState, SustainPedal, ZoneMutes};
+use crate::midi_histogram::MidiHistograms;
+use crate::output::OutputStatus;
+use crate::plot_history::PlotFeed;
+use crate::selftest::{SelfTest, SelfTestReport, SelfTestStatus};
+use crate::startup::{StartupProgress, StartupStatus};
+use crate::sync::{enter_side, LockExt, LockSide, TrackedMutex};
+use crate::take_buffer::Takes;
+use crate::usage::UsageStats;
+use crate::watchdog::LinkHealth;
 use eframe::egui;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
-const NUM_ZONES: usize = 8;
-const PLOT_DURATION_SECS: f64 = 4.0;
+/// How long the changes a config read found stay on screen when there are no
+/// edits to decide about
+const READ_REPORT_DURATION: Duration = Duration::from_secs(10);
 
-#[derive(Clone, Copy)]
-pub struct ProcessedSample {
-    pub timestamp: i32,
-    pub zone: usize,
-    pub value_raw: f64,
-    pub value_normalized: f64,
-}
+/// Time between repaints while samples are arriving
+const LIVE_REPAINT_INTERVAL: Duration = Duration::from_millis(33);
+/// Time between repaints without samples or input, so the window still
+/// notices the source starting, MIDI reconnects and Ctrl+C
+const IDLE_REPAINT_INTERVAL: Duration = Duration::from_millis(500);
 
-#[derive(PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Tab {
     Plot,
     Config,
     Midi,
+    Outputs,
+    Stats,
 }
 
 pub struct PlotApp {
-    pub sensor_data: Arc<Mutex<[Vec<[f64; 2]>; NUM_ZONES]>>,
-    pub rx: mpsc::Receiver<ProcessedSample>,
-    pub time_begin: Instant,
-    pub time_delta: Option<i32>,
-    pub zone_configs: Arc<Mutex<[DildonicaZoneConfig; NUM_ZONES]>>,
+    /// Plot history published by the pipeline
+    pub plot: PlotFeed,
+    pub plot_cache: PlotCache,
+    /// The plot frozen for scrolling back, `None` while live
+    pub plot_paused: Option<PausedPlot>,
+    pub zone_configs: Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>,
     pub config_tx: Option<mpsc::Sender<[DildonicaZoneConfig; NUM_ZONES]>>,
     pub config_read_tx: Option<mpsc::Sender<()>>,
-    pub app_config: Arc<Mutex<AppConfig>>,
+    /// The config read or write the device session is running
+    pub config_activity: ConfigActivity,
+    pub app_config: Arc<TrackedMutex<ConfigStore>>,
+    pub midi_output: Arc<Mutex<MidiOutputState>>,
+    /// Output ports offered in the MIDI tab, listed when the tab is first shown
+    pub midi_ports: Option<Vec<String>>,
     pub selected_tab: Tab,
+    /// Performance lock: disables every widget that changes settings. Never persisted.
+    pub locked: bool,
+    pub toasts: Toasts,
+    /// Closes the window once cancelled, e.g. by Ctrl+C in the terminal
+    pub shutdown: Option<CancellationToken>,
+    /// The phrase looper driven by the transport in the MIDI tab
+    pub looper: Option<LooperHandle>,
+    /// The click track started from the MIDI tab or with Ctrl+M
+    pub metronome: Option<Metronome>,
+    /// Saves the end of the pipeline's take buffer, from the top bar
+    pub takes: Option<Takes>,
+    /// The pipeline's session markers, dropped from the Plot tab or with Ctrl+B
+    pub markers: SessionMarkers,
+    /// Name typed for the next marker, `markers.name` if empty
+    pub marker_name: String,
+    /// Taps of the LFO tempo button
+    pub tap_tempo: TapTempo,
+    /// The patch sent last, shared with the pipeline's gesture bindings
+    pub patches: PatchSelector,
+    /// Sustain pedal toggled in the MIDI tab, shared with the pipeline
+    pub sustain: SustainPedal,
+    /// Zones whose MIDI the pipeline mutes, shared with it
+    pub zone_mutes: ZoneMutes,
+    /// The zone focused for tuning, `None` outside focus mode
+    pub focus: Option<ZoneFocus>,
+    /// The pipeline's learned zone gains, shown in the Configuration tab
+    pub auto_gain: Option<AutoGain>,
+    /// Touch counts of the pipeline, shown in the Stats tab
+    pub usage: Option<UsageStats>,
+    /// Name typed for saving the zone map as a preset
+    pub zone_map_preset_name: String,
+    /// Starts a firmware update in the device session, `None` without a device
+    pub dfu_tx: Option<mpsc::Sender<DfuRequest>>,
+    pub dfu_progress: DfuProgress,
+    /// Package path typed into the Firmware Update section
+    pub dfu_path: String,
+    /// Firmware header path typed next to the template picker
+    pub header_path: String,
+    /// Velocities and pressures sent recently, shown in the MIDI tab
+    pub midi_histograms: Option<MidiHistograms>,
+    /// Zone shown by the histograms, all zones if `None`
+    pub histogram_zone: Option<usize>,
+    /// Zone self-test started from the Configuration tab
+    pub selftest: Option<SelfTest>,
+    /// Report of the last self-test, shown until closed
+    pub selftest_report: Option<SelfTestReport>,
+    /// Address and adapter of the device, for diagnostics bundles
+    pub device: (String, Option<usize>),
+    /// The pipeline's counters, for diagnostics bundles and drift warnings
+    pub shared_stats: Option<SharedStats>,
+    /// Output zones whose baseline drift was warned about, until they recover
+    pub drift_warned: [bool; NUM_ZONES],
+    /// Device config writes shown in the Configuration tab, or why they
+    /// couldn't be read; `None` until shown, and after every write
+    pub config_history: Option<Result<Vec<HistoryEntry>, String>>,
+    /// The other instance holding the device when this one only views the
+    /// config, without BLE and MIDI
+    pub view_only: Option<LockHolder>,
+    /// Whether the device session's watchdog suspects the connection, for
+    /// the status bar; `None` without a device
+    pub link_health: Option<LinkHealth>,
+    /// Sends the batching settings to the device session, `None` without a device
+    pub batching_tx: Option<mpsc::Sender<BatchingConfig>>,
+    /// The batching hint the device session wrote, `None` without a device
+    pub batching_status: Option<BatchingStatus>,
+    /// The startup sequence, shown until it ended and was closed; `None`
+    /// without one
+    pub startup: Option<StartupProgress>,
+    /// Edit the device's cycle counts in microseconds instead of cycles
+    pub cycle_counts_in_micros: bool,
+    /// The safe mode window was put away; the banner stays
+    pub safe_mode_hidden: bool,
+    /// The BLE UUID fields as typed and the UUIDs they were filled from;
+    /// `None` until shown
+    pub ble_uuid_texts: Option<(BleConfig, [String; 4])>,
+    /// "Write & Compare" run by the device session, `None` without a device
+    pub config_compare: Option<ConfigCompare>,
+    /// Report of the last comparison, shown until closed
+    pub compare_report: Option<CompareReport>,
+    /// What the last config read changed and when it was shown, until closed
+    pub config_read_report: Option<(ConfigReadReport, Instant)>,
+    /// How the pipeline's output sinks are doing, shown in the Outputs tab
+    pub outputs: Option<OutputStatus>,
+    /// Sample capture for a diagnostics bundle, started from the
+    /// Configuration tab
+    pub sample_capture: Option<SampleCapture>,
+    /// Session recording toggled by the record button, if built in
+    #[cfg(feature = "sqlite")]
+    pub db_recording: Option<crate::session_db::DbRecording>,
 }
 
 impl PlotApp {
     pub fn new(
-        sensor_data: Arc<Mutex<[Vec<[f64; 2]>; NUM_ZONES]>>,
-        rx: mpsc::Receiver<ProcessedSample>,
-        zone_configs: Arc<Mutex<[DildonicaZoneConfig; NUM_ZONES]>>,
+        plot: PlotFeed,
+        zone_configs: Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>,
         config_tx: mpsc::Sender<[DildonicaZoneConfig; NUM_ZONES]>,
         config_read_tx: mpsc::Sender<()>,
-        app_config: Arc<Mutex<AppConfig>>,
+        app_config: Arc<TrackedMutex<ConfigStore>>,
+        midi_output: Arc<Mutex<MidiOutputState>>,
     ) -> Self {
         Self {
-            sensor_data,
-            rx,
-            time_begin: Instant::now(),
-            time_delta: None,
+            plot,
+            plot_cache: PlotCache::default(),
+            plot_paused: None,
             zone_configs,
             config_tx: Some(config_tx),
             config_read_tx: Some(config_read_tx),
+            config_activity: ConfigActivity::new(),
             app_config,
+            midi_output,
+            midi_ports: None,
             selected_tab: Tab::Plot,
+            locked: false,
+            toasts: Toasts::default(),
+            shutdown: None,
+            looper: None,
+            metronome: None,
+            takes: None,
+            markers: SessionMarkers::new(),
+            marker_name: String::new(),
+            tap_tempo: TapTempo::default(),
+            patches: PatchSelector::new(),
+            auto_gain: None,
+            usage: None,
+            sustain: SustainPedal::new(),
+            zone_mutes: ZoneMutes::new(),
+            focus: None,
+            zone_map_preset_name: String::new(),
+            dfu_tx: None,
+            dfu_progress: DfuProgress::new(),
+            dfu_path: String::new(),
+            header_path: String::new(),
+            midi_histograms: None,
+            histogram_zone: None,
+            selftest: None,
+            selftest_report: None,
+            device: (String::new(), None),
+            shared_stats: None,
+            drift_warned: [false; NUM_ZONES],
+            config_history: None,
+            view_only: None,
+            link_health: None,
+            batching_tx: None,
+            batching_status: None,
+            startup: None,
+            cycle_counts_in_micros: false,
+            safe_mode_hidden: false,
+            ble_uuid_texts: None,
+            config_compare: None,
+            compare_report: None,
+            config_read_report: None,
+            outputs: None,
+            sample_capture: None,
+            #[cfg(feature = "sqlite")]
+            db_recording: None,
+        }
+    }
+}
+
+impl PlotApp {
+    /// Saves a diagnostics bundle of what the app knows now, with `capture`
+    /// if one was made, next to the config file and tells where.
+    /// `zone_configs` are passed in, the caller may hold their lock.
+    pub fn save_diagnostics(&mut self, zone_configs: &[DildonicaZoneConfig; NUM_ZONES], capture: Option<Vec<BundleSample>>) {
+        let app_config = AppConfig::clone(&self.app_config.lock_or_recover());
+        let blob = self.config_activity.config_blob();
+        let (address, adapter) = self.device.clone();
+        let snapshot = DiagnosticsSnapshot {
+            system: SystemInfo::current(),
+            device: DeviceInfo::new(address, adapter, None, blob.as_deref(), *zone_configs),
+            stats: self.shared_stats.as_ref().and_then(SharedStats::latest),
+            log_lines: logging::recent_lines(),
+            capture,
+            markers: self.markers.all(),
+            app_config,
+        };
+        let directory = snapshot.app_config.config_path.parent().map(PathBuf::from).unwrap_or_default();
+        let path = directory.join(diagnostics_bundle::file_name(chrono::Local::now()));
+        match diagnostics_bundle::save_bundle(&path, &snapshot) {
+            Ok(()) => self.toasts.push(tf("diagnostics.saved", &[("path", &path.display())])),
+            Err(e) => self.toasts.push(tf("diagnostics.failed", &[("error", &e)])),
         }
     }
 
-    pub fn current_dildonica_time(&self) -> f64 {
-        let cur_machine_time = self.time_begin.elapsed().as_millis() as i32;
-        (cur_machine_time - self.time_delta.unwrap_or(0)) as f64 / 1000.0
+    /// Drops a marker called as typed in the Plot tab at the next sample,
+    /// returning its name.
+    pub fn drop_marker(&mut self) -> String {
+        let name = match self.marker_name.trim() {
+            "" => self.app_config.lock_or_recover().markers.name.clone(),
+            name => name.to_string(),
+        };
+        self.markers.request(&name);
+        marker_name(&name)
+    }
+
+    /// Tells how the last take save went.
+    fn poll_takes(&mut self) {
+        match self.takes.as_ref().and_then(Takes::take_finished) {
+            Some(Ok(path)) => self.toasts.push(tf("takes.saved", &[("path", &path.display())])),
+            Some(Err(e)) => self.toasts.push(tf("takes.failed", &[("error", &e)])),
+            None => {}
+        }
     }
 
-    pub fn process_incoming_samples(&mut self) {
-        let cur_machine_time = self.time_begin.elapsed().as_millis() as i32;
-        let cur_dildonica_time = self.current_dildonica_time();
+    /// Saves the bundle of a sample capture once it ran its time.
+    fn poll_sample_capture(&mut self, ctx: &egui::Context) {
+        let Some(capture) = &self.sample_capture else {
+            return;
+        };
+        if let Some(samples) = capture.take_finished() {
+            let zone_configs = *self.zone_configs.lock_or_recover();
+            self.save_diagnostics(&zone_configs, Some(samples));
+        } else if capture.progress().is_some() {
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+    }
 
-        while let Ok(processed_sample) = self.rx.try_recv() {
-            let timestamp = processed_sample.timestamp;
-            if self.time_delta.is_none() {
-                self.time_delta = Some(cur_machine_time - timestamp);
+    /// Shows what a config read found changed on the device, and asks per
+    /// zone whether unsaved edits the read kept should stay or give way to
+    /// the device's values.
+    fn render_config_read_report(&mut self, ctx: &egui::Context) {
+        if let Some(report) = self.config_activity.take_read_report() {
+            self.config_read_report = Some((report, Instant::now()));
+        }
+        let Some((report, shown)) = &mut self.config_read_report else {
+            return;
+        };
+        if report.kept_zones.is_empty() {
+            let remaining = READ_REPORT_DURATION.saturating_sub(shown.elapsed());
+            if remaining.is_zero() {
+                self.config_read_report = None;
+                return;
             }
-            let mut sensor_data = self.sensor_data.lock().unwrap();
+            ctx.request_repaint_after(remaining);
+        }
 
-            let app_config = self.app_config.lock().unwrap();
-            let plot_value = if app_config.plot_raw {
-                processed_sample.value_raw
-            } else {
-                processed_sample.value_normalized
-            };
-            drop(app_config);
+        let mut open = true;
+        let mut resolved = None;
+        egui::Window::new(t("config.diff.title"))
+            .collapsible(false)
+            .resizable(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                if report.changes.is_empty() {
+                    ui.label(t("config.diff.unchanged"));
+                }
+                for change in &report.changes {
+                    ui.label(change.to_string());
+                }
+                if report.kept_zones.is_empty() {
+                    return;
+                }
+                ui.separator();
+                ui.label(t("config.diff.kept"));
+                egui::Grid::new("config_read_kept").show(ui, |ui| {
+                    for &zone in &report.kept_zones {
+                        ui.label(tf("config.diff.zone", &[("zone", &zone)]));
+                        if ui.button(t("config.diff.keep_mine")).clicked() {
+                            resolved = Some(zone);
+                        }
+                        let use_device = lockable(ui, self.locked, &mut self.toasts, |ui| {
+                            ui.button(t("config.diff.use_device")).clicked()
+                        });
+                        if use_device {
+                            self.zone_configs.lock_or_recover()[zone] = report.device[zone];
+                            resolved = Some(zone);
+                        }
+                        ui.end_row();
+                    }
+                });
+            });
+        if let Some(zone) = resolved {
+            report.kept_zones.retain(|&kept| kept != zone);
+            // Counted from here, so it doesn't vanish as the last choice is made
+            *shown = Instant::now();
+        }
+        if !open {
+            self.config_read_report = None;
+        }
+    }
+
+    /// Warns once about every zone whose baseline drifted since calibration.
+    fn poll_baseline_drift(&mut self) {
+        let Some(stats) = self.shared_stats.as_ref().and_then(SharedStats::latest) else {
+            return;
+        };
+        for (zone, drift) in stats.baseline_drift.iter().enumerate() {
+            match drift {
+                Some(drift) if !self.drift_warned[zone] => {
+                    let percent = format!("{:+.1}", drift * 100.0);
+                    self.toasts.push(tf("drift.warning", &[("zone", &zone), ("percent", &percent)]));
+                    self.drift_warned[zone] = true;
+                }
+                None => self.drift_warned[zone] = false,
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Shows the raw values of every zone before and after the last
+    /// "Write & Compare" side by side.
+    fn render_compare_report(&mut self, ctx: &egui::Context) {
+        if let Some(report) = self.config_compare.as_ref().and_then(ConfigCompare::take_report) {
+            self.compare_report = Some(report);
+        }
+        let Some(report) = &self.compare_report else {
+            return;
+        };
+        let mut open = true;
+        egui::Window::new(t("compare.title"))
+            .collapsible(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                egui::Grid::new("compare_report").striped(true).show(ui, |ui| {
+                    ui.label("");
+                    for header in ["compare.mean", "compare.std_dev", "compare.peak_to_peak"] {
+                        ui.strong(t(header));
+                        ui.label("");
+                        ui.label("");
+                    }
+                    ui.end_row();
+                    ui.strong(t("zone"));
+                    for _ in 0..3 {
+                        ui.strong(t("compare.before"));
+                        ui.strong(t("compare.after"));
+                        ui.strong(t("compare.change"));
+                    }
+                    ui.end_row();
+                    for zone in &report.zones {
+                        ui.label(zone.zone.to_string());
+                        compared_cells(ui, zone.mean(), 0, false);
+                        compared_cells(ui, zone.std_dev(), 1, true);
+                        compared_cells(ui, zone.peak_to_peak(), 0, true);
+                        ui.end_row();
+                    }
+                });
+                ui.label(t("compare.note"));
+            });
+        if !open {
+            self.compare_report = None;
+        }
+    }
+
+    /// Shows the steps of the startup sequence and where it is, with a
+    /// button to abort it.
+    fn render_startup(&mut self, ctx: &egui::Context) {
+        let Some(progress) = &self.startup else {
+            return;
+        };
+        let Some((actions, status)) = progress.status() else {
+            return;
+        };
+        let running = matches!(status, StartupStatus::Running(_));
+        let mut open = true;
+        let mut window = egui::Window::new(t("startup.title"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::LEFT_BOTTOM, [10.0, -40.0]);
+        // Closed only once it ended
+        if !running {
+            window = window.open(&mut open);
+        }
+        window.show(ctx, |ui| {
+            for (index, action) in actions.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let label = startup_action_label(action);
+                    match &status {
+                        StartupStatus::Running(current) if index == *current => {
+                            ui.spinner();
+                            ui.strong(label);
+                        }
+                        StartupStatus::Failed(current, error) if index == *current => {
+                            ui.colored_label(egui::Color32::RED, "✖");
+                            ui.label(label).on_hover_text(error);
+                        }
+                        StartupStatus::Aborted(current) if index == *current => {
+                            ui.colored_label(egui::Color32::YELLOW, "✖");
+                            ui.label(label);
+                        }
+                        StartupStatus::Running(current) | StartupStatus::Failed(current, _) | StartupStatus::Aborted(current)
+                            if index > *current =>
+                        {
+                            ui.weak("○");
+                            ui.weak(label);
+                        }
+                        _ => {
+                            ui.colored_label(egui::Color32::GREEN, "✔");
+                            ui.label(label);
+                        }
+                    }
+                });
+            }
+            ui.separator();
+            match &status {
+                StartupStatus::Running(_) => {
+                    if ui.button(t("startup.abort")).on_hover_text(t("startup.abort.hover")).clicked() {
+                        progress.abort();
+                    }
+                }
+                StartupStatus::Finished => {
+                    ui.colored_label(egui::Color32::GREEN, t("startup.finished"));
+                }
+                StartupStatus::Failed(_, error) => {
+                    ui.colored_label(egui::Color32::RED, tf("startup.failed", &[("error", error)]));
+                }
+                StartupStatus::Aborted(_) => {
+                    ui.colored_label(egui::Color32::YELLOW, t("startup.aborted"));
+                }
+            }
+        });
+        if !open {
+            progress.dismiss();
+        }
+        if running {
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+    }
+
+    /// Asks which side wins when the config file was edited externally while
+    /// there were unsaved in-app changes.
+    /// A banner while the config file failed to load, and a window telling
+    /// why with the ways out of safe mode.
+    fn render_safe_mode(&mut self, ctx: &egui::Context) {
+        let Some(failure) = self.app_config.lock_or_recover().safe_mode().cloned() else {
+            return;
+        };
+        egui::TopBottomPanel::top("safe_mode").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.colored_label(egui::Color32::YELLOW, t("safe_mode.banner"));
+                if self.safe_mode_hidden && ui.button(t("safe_mode.details")).clicked() {
+                    self.safe_mode_hidden = false;
+                }
+            });
+        });
+        if self.safe_mode_hidden {
+            return;
+        }
+
+        egui::Window::new(t("safe_mode.title"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 60.0])
+            .show(ctx, |ui| {
+                ui.label(t("safe_mode.text"));
+                ui.colored_label(egui::Color32::RED, &failure.error);
+                if let Some((line, column)) = failure.location {
+                    ui.label(tf("safe_mode.location", &[("line", &line), ("column", &column)]));
+                }
+                match &failure.preserved {
+                    Some(path) => ui.label(tf("safe_mode.preserved", &[("path", &path.display())])),
+                    None => ui.colored_label(egui::Color32::YELLOW, t("safe_mode.not_preserved")),
+                };
+                ui.horizontal(|ui| {
+                    if ui.button(t("safe_mode.retry")).on_hover_text(t("safe_mode.retry.hover")).clicked() {
+                        match self.app_config.lock_or_recover().retry_load() {
+                            Ok(()) => self.toasts.push(t("safe_mode.loaded").to_string()),
+                            Err(e) => self.toasts.push(tf("safe_mode.retry_failed", &[("error", &e)])),
+                        }
+                    }
+                    let config_path = self.app_config.lock_or_recover().config_path.clone();
+                    if let Some(directory) = config_path.parent() {
+                        if ui.button(t("safe_mode.open_folder")).clicked() {
+                            if let Err(e) = open_folder(directory) {
+                                self.toasts.push(tf("safe_mode.open_failed", &[("error", &e)]));
+                            }
+                        }
+                    }
+                    if ui
+                        .button(t("safe_mode.accept"))
+                        .on_hover_text(t("safe_mode.accept.hover"))
+                        .clicked()
+                    {
+                        self.app_config.lock_or_recover().accept_defaults();
+                    }
+                    if ui.button(t("safe_mode.later")).clicked() {
+                        self.safe_mode_hidden = true;
+                    }
+                });
+            });
+    }
+
+    fn render_config_conflict(&mut self, ctx: &egui::Context) {
+        let mut app_config = self.app_config.lock_or_recover();
+        if !app_config.has_conflict() {
+            return;
+        }
+
+        egui::Window::new(t("conflict.title"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -40.0])
+            .show(ctx, |ui| {
+                ui.label(t("conflict.text"));
+                ui.horizontal(|ui| {
+                    if ui.button(t("conflict.load_file")).clicked() {
+                        app_config.accept_external();
+                    }
+                    if ui.button(t("conflict.keep_mine")).clicked() {
+                        app_config.keep_local();
+                    }
+                });
+            });
+    }
+}
 
-            let zone_data = &mut sensor_data[processed_sample.zone];
-            zone_data.push([timestamp as f64 / 1000.0, plot_value]);
+/// Shows `directory` in the system's file manager.
+fn open_folder(directory: &Path) -> std::io::Result<()> {
+    #[cfg(target_os = "windows")]
+    let program = "explorer";
+    #[cfg(target_os = "macos")]
+    let program = "open";
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let program = "xdg-open";
+    let mut child = std::process::Command::new(program).arg(directory).spawn()?;
+    // Reaped in the background, the file manager may keep running
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+fn startup_action_label(action: &StartupAction) -> String {
+    match action {
+        StartupAction::ConnectTo(address) => tf("startup.connect_to", &[("address", address)]),
+        StartupAction::WaitForSamples => t("startup.wait_for_samples").to_string(),
+        StartupAction::Calibrate(secs) => tf("startup.calibrate", &[("secs", secs)]),
+        StartupAction::LoadPreset(name) => tf("startup.load_preset", &[("name", name)]),
+        StartupAction::WriteDeviceConfig => t("startup.write_device_config").to_string(),
+        StartupAction::SetMidi(true) => t("startup.enable_midi").to_string(),
+        StartupAction::SetMidi(false) => t("startup.disable_midi").to_string(),
+    }
+}
+
+/// One statistic of the comparison report: before, after and the change,
+/// highlighted when notable. For statistics where `lower_is_better`
+/// a notable drop is green and a rise red, otherwise yellow.
+fn compared_cells(ui: &mut egui::Ui, compared: Option<Compared>, decimals: usize, lower_is_better: bool) {
+    let Some(compared) = compared else {
+        for _ in 0..3 {
+            ui.label("-");
+        }
+        return;
+    };
+    ui.label(format!("{:.*}", decimals, compared.before));
+    ui.label(format!("{:.*}", decimals, compared.after));
+    let Some(change) = compared.change() else {
+        ui.label("-");
+        return;
+    };
+    let text = format!("{:+.1}%", change * 100.0);
+    if !compared.notable() {
+        ui.label(text);
+    } else if !lower_is_better {
+        ui.colored_label(egui::Color32::YELLOW, text);
+    } else if change < 0.0 {
+        ui.colored_label(egui::Color32::GREEN, text);
+    } else {
+        ui.colored_label(egui::Color32::RED, text);
+    }
+}
+
+/// A self-test result as shown in the report
+fn status_name(status: SelfTestStatus) -> &'static str {
+    match status {
+        SelfTestStatus::Disabled => t("selftest.off"),
+        SelfTestStatus::Pass => t("selftest.pass"),
+        SelfTestStatus::Warn => t("selftest.warn"),
+        SelfTestStatus::Fail => t("selftest.fail"),
+    }
+}
 
-            while !zone_data.is_empty()
-                && zone_data[0][0] < cur_dildonica_time - PLOT_DURATION_SECS
-            {
-                zone_data.remove(0);
+impl PlotApp {
+    /// Shows the running self-test, finishing it once its duration is over,
+    /// and then its report.
+    fn render_selftest(&mut self, ctx: &egui::Context) {
+        if let Some(selftest) = self.selftest.as_ref().filter(|selftest| selftest.is_running()) {
+            let config = self.app_config.lock_or_recover().selftest.clone();
+            let elapsed = selftest.elapsed();
+            if elapsed >= config.duration() {
+                self.selftest_report = Some(selftest.finish(&self.zone_configs.lock_or_recover(), &config));
+            } else {
+                egui::Window::new(t("selftest.title"))
+                    .collapsible(false)
+                    .resizable(false)
+                    .show(ctx, |ui| {
+                        ui.label(t("selftest.running"));
+                        let progress = elapsed.as_secs_f64() / config.duration_secs;
+                        let text = if elapsed.is_zero() {
+                            t("waiting_for_samples").to_string()
+                        } else {
+                            format!("{:.1} / {:.1} s", elapsed.as_secs_f64(), config.duration_secs)
+                        };
+                        ui.add(egui::ProgressBar::new(progress as f32).text(text));
+                        if ui.button(t("cancel")).clicked() {
+                            selftest.cancel();
+                        }
+                    });
+                ctx.request_repaint_after(Duration::from_millis(100));
             }
         }
+
+        let Some(report) = &self.selftest_report else {
+            return;
+        };
+        let mut open = true;
+        egui::Window::new(t("selftest.report"))
+            .collapsible(false)
+            .open(&mut open)
+            .show(ctx, |ui| {
+                egui::Grid::new("selftest_report").striped(true).show(ui, |ui| {
+                    for header in ["zone", "selftest.result", "selftest.rate", "selftest.mean", "selftest.noise", "selftest.notes"] {
+                        ui.strong(t(header));
+                    }
+                    ui.end_row();
+                    for zone in &report.zones {
+                        ui.label(zone.zone.to_string());
+                        let color = match zone.status {
+                            SelfTestStatus::Disabled => egui::Color32::GRAY,
+                            SelfTestStatus::Pass => egui::Color32::GREEN,
+                            SelfTestStatus::Warn => egui::Color32::YELLOW,
+                            SelfTestStatus::Fail => egui::Color32::RED,
+                        };
+                        ui.colored_label(color, status_name(zone.status));
+                        ui.label(format!("{:.1}", zone.rate_hz));
+                        ui.label(zone.mean.map_or("-".to_string(), |mean| format!("{:.0}", mean)));
+                        ui.label(zone.noise.map_or("-".to_string(), |noise| format!("{:.2}%", noise * 100.0)));
+                        ui.label(zone.notes.join("; "));
+                        ui.end_row();
+                    }
+                });
+                ui.label(tf(
+                    "selftest.summary",
+                    &[
+                        ("status", &status_name(report.status())),
+                        ("secs", &format!("{:.1}", report.duration.as_secs_f64())),
+                    ],
+                ));
+            });
+        if !open {
+            self.selftest_report = None;
+        }
     }
 }
 
 impl eframe::App for PlotApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        self.process_incoming_samples();
+        let _side = enter_side(LockSide::Gui);
+        if self.shutdown.as_ref().is_some_and(CancellationToken::is_cancelled) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+        i18n::set_language(self.app_config.lock_or_recover().language);
+        // From any tab, also while locked: it changes no settings
+        if let Some(metronome) = &self.metronome {
+            if ctx.input_mut(|input| input.consume_key(egui::Modifiers::CTRL, egui::Key::M)) {
+                metronome.toggle();
+            }
+        }
+        if ctx.input_mut(|input| input.consume_key(egui::Modifiers::CTRL, egui::Key::B)) {
+            let name = self.drop_marker();
+            self.toasts.push(tf("markers.dropped", &[("name", &name)]));
+        }
 
         // Tab bar
         egui::TopBottomPanel::top("tab_bar").show(ctx, |ui| {
             ui.horizontal(|ui| {
-                ui.selectable_value(&mut self.selected_tab, Tab::Plot, "Plot");
-                ui.selectable_value(&mut self.selected_tab, Tab::Config, "Configuration");
-                ui.selectable_value(&mut self.selected_tab, Tab::Midi, "MIDI");
+                ui.selectable_value(&mut self.selected_tab, Tab::Plot, t("tab.plot"));
+                ui.selectable_value(&mut self.selected_tab, Tab::Config, t("tab.config"));
+                ui.selectable_value(&mut self.selected_tab, Tab::Midi, t("tab.midi"));
+                ui.selectable_value(&mut self.selected_tab, Tab::Outputs, t("tab.outputs"));
+                ui.selectable_value(&mut self.selected_tab, Tab::Stats, t("tab.stats"));
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    ui.toggle_value(&mut self.locked, t("lock"))
+                        .on_hover_text(t("lock.hover"));
+                    // Told on every tab, the other zones play no MIDI meanwhile
+                    if let Some(focus) = self.focus {
+                        if ui.button(t("focus.leave")).on_hover_text(t("focus.leave.hover")).clicked() {
+                            self.leave_focus();
+                        }
+                        ui.colored_label(egui::Color32::YELLOW, tf("focus.status", &[("zone", &focus.zone)]));
+                    }
+                    if let Some(holder) = &self.view_only {
+                        ui.colored_label(egui::Color32::YELLOW, t("view_only"))
+                            .on_hover_text(tf("view_only.hover", &[("pid", &holder.pid)]));
+                    }
+                    #[cfg(feature = "sqlite")]
+                    if let Some(recording) = &self.db_recording {
+                        let mut recording_on = recording.is_requested();
+                        let response = ui
+                            .toggle_value(&mut recording_on, t("record"))
+                            .on_hover_text(tf("record.hover", &[("path", &recording.path().display())]));
+                        if response.changed() {
+                            if recording_on {
+                                recording.start();
+                            } else {
+                                recording.stop();
+                            }
+                        }
+                    }
+                    if let Some(takes) = &self.takes {
+                        let config = self.app_config.lock_or_recover().takes.clone();
+                        if config.enabled {
+                            let secs = format!("{:.0}", config.save_secs);
+                            let response = ui
+                                .add_enabled(!takes.is_saving(), egui::Button::new(tf("takes.save", &[("secs", &secs)])))
+                                .on_hover_text(tf("takes.save.hover", &[("path", &config.directory().display())]));
+                            if response.clicked() {
+                                takes.request_save();
+                            }
+                        }
+                    }
+                });
+            });
+        });
+
+        self.render_safe_mode(ctx);
+
+        // Status bar
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let app_config = self.app_config.lock_or_recover();
+                if let Some(profile) = &app_config.profile {
+                    ui.label(tf("status.profile", &[("profile", profile)]));
+                    ui.separator();
+                }
+                ui.label(tf("status.config", &[("path", &app_config.config_path.display())]));
+                ui.separator();
+                ui.label(tf("status.midi", &[("status", &self.midi_output.lock_or_recover().status())]));
+                if self.link_health.as_ref().is_some_and(LinkHealth::is_suspect) {
+                    ui.separator();
+                    ui.colored_label(egui::Color32::YELLOW, t("status.link_suspect"))
+                        .on_hover_text(t("status.link_suspect.hover"));
+                }
+                #[cfg(feature = "sqlite")]
+                if let Some(recording) = &self.db_recording {
+                    if let Some(samples) = recording.samples() {
+                        ui.separator();
+                        ui.label(tf("status.recording", &[("samples", &samples)]));
+                    } else if recording.has_failed() {
+                        ui.separator();
+                        ui.colored_label(egui::Color32::RED, t("status.recording_failed"));
+                    }
+                }
             });
         });
 
@@ -116,8 +826,31 @@ impl eframe::App for PlotApp {
             Tab::Midi => {
                 super::midi_ui::render_midi_tab(self, ui, ctx);
             }
+            Tab::Outputs => {
+                super::outputs_ui::render_outputs_tab(self, ui);
+            }
+            Tab::Stats => {
+                super::stats_ui::render_stats_tab(self, ui);
+            }
         });
 
-        ctx.request_repaint();
+        self.render_config_conflict(ctx);
+        self.render_startup(ctx);
+        self.render_config_read_report(ctx);
+        self.render_compare_report(ctx);
+        self.render_selftest(ctx);
+        self.poll_sample_capture(ctx);
+        self.poll_takes();
+        self.poll_baseline_drift();
+        self.toasts.show(ctx);
+        self.app_config.lock_or_recover().flush_if_due();
+
+        // Input repaints by itself; the plot only needs redrawing while it moves
+        let receiving = diagnostics::receiving(self.plot.load().received_at(), SAMPLE_TIMEOUT);
+        ctx.request_repaint_after(if receiving { LIVE_REPAINT_INTERVAL } else { IDLE_REPAINT_INTERVAL });
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.app_config.lock_or_recover().flush();
     }
 }
\ No newline at end of file
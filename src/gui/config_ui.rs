@@ -1,174 +1,1401 @@
 use super::app::PlotApp;
+use super::midi_ui::render_patch_binding;
+use super::i18n::{t, t_name, tf};
+use super::toast::Toasts;
+use super::widgets::{accessible_name, config_label, lockable};
+use crate::config::device::{MAX_OSCILLATOR_HZ, MIN_OSCILLATOR_HZ};
+use crate::config::normalization::MAX_BASELINE_WARMUP_SAMPLES;
+use crate::config::zones::create_default_zone_map;
+use crate::auto_gain::AutoGain;
+use crate::batching::BatchingStatus;
+use crate::ble::ConfigOperation;
+use crate::config_compare::{ComparePhase, COMPARE_DURATION};
+use crate::config_history::{ConfigHistory, HistoryEntry};
+use crate::config::{
+    cycles_to_micros, import_c_header, micros_to_cycles, mirror_zone_map, validate_zone_groups, validate_zone_map, BatchingConfig, BleConfig, ConfigLayout, ConfigStore, DildonicaZoneConfig,
+    Language, LogicalZones, NormalizationMode, SizeMismatch, ZoneGroupReduction, ZoneMapPreset, ZoneMapScope,
+    HARDWARE_TEMPLATES, MAX_BATCH_SAMPLES, MAX_PLOT_DURATION_SECS, MIN_PLOT_DURATION_SECS, NUM_ZONES,
+};
+use crate::diagnostics_bundle::{SampleCapture, CAPTURE_DURATION};
+use crate::dfu::{DfuPackage, DfuProgress, DfuRequest, DfuState};
+use crate::markers::MAX_MARKER_NAME_CHARS;
+use crate::plot_history::PlotMemory;
+use crate::sync::{lock_reports, LockExt, LockSide, TrackedMutex};
 use eframe::egui;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// Writes listed in the write history
+const HISTORY_SHOWN: usize = 20;
 
 pub fn render_config_tab(app: &mut PlotApp, ui: &mut egui::Ui, ctx: &egui::Context) {
     egui::ScrollArea::vertical().show(ui, |ui| {
-        let mut configs = app.zone_configs.lock().unwrap();
+        // Not borrowed from `app`, which the sections below need mutably
+        let zone_configs = app.zone_configs.clone();
+        let mut configs = zone_configs.lock_or_recover();
         let mut config_changed = false;
+        let locked = app.locked;
+
+        ui.heading(t("config.appearance"));
+        lockable(ui, locked, &mut app.toasts, |ui| {
+            ui.group(|ui| {
+                let mut app_config = app.app_config.lock_or_recover();
+                ui.horizontal(|ui| {
+                    config_label(ui, &app_config, t("config.language"), "language");
+                    let mut changed = false;
+                    for language in Language::all() {
+                        changed |= ui.radio_value(&mut app_config.language, *language, language.name()).changed();
+                    }
+                    if changed {
+                        app_config.mark_dirty();
+                    }
+                });
+            });
+        });
 
         // Zone Mapping Configuration
-        ui.heading("Zone Mapping");
-        ui.label("Map device zones to output zones (changes how data appears in plot and MIDI output):");
+        ui.separator();
+        ui.heading(t("config.zone_mapping"));
+        ui.label(t("config.zone_mapping.text"));
+
+        lockable(ui, locked, &mut app.toasts, |ui| {
+            ui.group(|ui| {
+                let mut app_config = app.app_config.lock_or_recover();
+                let mut zone_map_changed = false;
+                if app_config.is_overridden("zone_map") {
+                    config_label(ui, &app_config, t("config.zone_map"), "zone_map");
+                }
+
+                ui.horizontal(|ui| {
+                    config_label(ui, &app_config, t("config.zone_map_scope"), "zone_map_scope");
+                    zone_map_changed |= ui
+                        .radio_value(&mut app_config.zone_map_scope, ZoneMapScope::Everything, t("config.zone_map_scope.everything"))
+                        .changed();
+                    zone_map_changed |= ui
+                        .radio_value(&mut app_config.zone_map_scope, ZoneMapScope::MidiOnly, t("config.zone_map_scope.midi_only"))
+                        .changed();
+                });
+                ui.weak(match app_config.zone_map_scope {
+                    ZoneMapScope::Everything => t("config.zone_map_scope.everything.text"),
+                    ZoneMapScope::MidiOnly => t("config.zone_map_scope.midi_only.text"),
+                });
 
-        ui.group(|ui| {
-            let mut app_config = app.app_config.lock().unwrap();
-            let mut zone_map_changed = false;
+                ui.horizontal_wrapped(|ui| {
+                    for (output_zone, device_zone) in app_config.zone_map.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            let label = ui.label(tf("config.zone_map.out", &[("zone", &output_zone)]));
+                            zone_map_changed |= ui
+                                .add(egui::DragValue::new(device_zone).range(0..=NUM_ZONES - 1))
+                                .on_hover_text(tf("config.zone_map.out.hover", &[("zone", &output_zone)]))
+                                .labelled_by(label.id)
+                                .changed();
+                        });
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.button(t("config.zone_map.reset")).clicked() {
+                        app_config.zone_map = create_default_zone_map(NUM_ZONES);
+                        zone_map_changed = true;
+                    }
+
+                    if ui.button(t("config.zone_map.reverse")).clicked() {
+                        app_config.zone_map = app_config.zone_map.clone().into_iter().rev().collect();
+                        zone_map_changed = true;
+                    }
+
+                    if ui
+                        .button(t("config.zone_map.mirror"))
+                        .on_hover_text(t("config.zone_map.mirror.hover"))
+                        .clicked()
+                    {
+                        app_config.zone_map = mirror_zone_map(&app_config.zone_map, NUM_ZONES);
+                        zone_map_changed = true;
+                    }
+                });
 
-            ui.horizontal_wrapped(|ui| {
-                for (output_zone, device_zone) in app_config.zone_map.iter_mut().enumerate() {
+                ui.horizontal_wrapped(|ui| {
+                    ui.label(t("config.presets"));
+                    if app_config.zone_map_presets.is_empty() {
+                        ui.weak(t("config.presets.none"));
+                    }
+                    let mut selected = None;
+                    let mut removed = None;
+                    for (index, preset) in app_config.zone_map_presets.iter().enumerate() {
+                        let active = preset.zone_map == app_config.zone_map;
+                        if ui
+                            .selectable_label(active, &preset.name)
+                            .on_hover_text(format!("{:?}", preset.zone_map))
+                            .clicked()
+                        {
+                            selected = Some(preset.zone_map.clone());
+                        }
+                        if ui.small_button("🗑").on_hover_text(t("config.presets.delete")).clicked() {
+                            removed = Some(index);
+                        }
+                    }
+                    if let Some(zone_map) = selected {
+                        app_config.zone_map = zone_map;
+                        zone_map_changed = true;
+                    }
+                    if let Some(index) = removed {
+                        app_config.zone_map_presets.remove(index);
+                        app_config.mark_dirty();
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    accessible_name(
+                        ui.add(
+                            egui::TextEdit::singleline(&mut app.zone_map_preset_name)
+                                .hint_text(t("config.presets.name"))
+                                .desired_width(150.0),
+                        ),
+                        t("config.presets.name.accessible"),
+                    );
+                    let name = app.zone_map_preset_name.trim().to_string();
+                    let valid = validate_zone_map(&app_config.zone_map, NUM_ZONES).is_ok();
+                    if ui
+                        .add_enabled(!name.is_empty() && valid, egui::Button::new(t("config.presets.save")))
+                        .on_hover_text(t("config.presets.save.hover"))
+                        .clicked()
+                    {
+                        let zone_map = app_config.zone_map.clone();
+                        match app_config.zone_map_presets.iter_mut().find(|preset| preset.name == name) {
+                            Some(preset) => preset.zone_map = zone_map,
+                            None => app_config.zone_map_presets.push(ZoneMapPreset { name, zone_map }),
+                        }
+                        app_config.mark_dirty();
+                        app.zone_map_preset_name.clear();
+                    }
+                });
+
+                // Validation
+                match validate_zone_map(&app_config.zone_map, NUM_ZONES) {
+                    Ok(()) => {
+                        ui.colored_label(egui::Color32::GREEN, t("config.zone_map.valid"));
+                    }
+                    Err(e) => {
+                        ui.colored_label(egui::Color32::RED, tf("warning", &[("error", &e)]));
+                    }
+                }
+
+                if zone_map_changed {
+                    app_config.mark_dirty();
+                    ctx.request_repaint();
+                }
+            });
+        });
+
+        // Zone Groups
+        ui.separator();
+        ui.heading(t("config.zone_groups"));
+        ui.label(t("config.zone_groups.text"));
+        lockable(ui, locked, &mut app.toasts, |ui| {
+            ui.group(|ui| {
+                let mut app_config = app.app_config.lock_or_recover();
+                render_zone_groups(ui, &mut app_config);
+            });
+        });
+
+        // Application Settings
+        ui.separator();
+        ui.heading(t("config.app_settings"));
+        lockable(ui, locked, &mut app.toasts, |ui| {
+            ui.group(|ui| {
+                let mut app_config = app.app_config.lock_or_recover();
+                let mut app_settings_changed = false;
+
+                ui.horizontal(|ui| {
+                    let label = config_label(ui, &app_config, t("config.exponential_alpha"), "exponential_alpha");
+                    app_settings_changed |= ui
+                        .add(egui::DragValue::new(&mut app_config.exponential_alpha)
+                            .range(0.0001..=1.0)
+                            .speed(0.0001)
+                            .fixed_decimals(4))
+                        .on_hover_text(t("config.exponential_alpha.hover"))
+                        .labelled_by(label.id)
+                        .changed();
+                    let label = config_label(ui, &app_config, t("config.baseline_warmup"), "normalization.baseline_warmup_samples");
+                    app_settings_changed |= ui
+                        .add(egui::DragValue::new(&mut app_config.normalization.baseline_warmup_samples)
+                            .range(0..=MAX_BASELINE_WARMUP_SAMPLES))
+                        .on_hover_text(t("config.baseline_warmup.hover"))
+                        .labelled_by(label.id)
+                        .changed();
+                });
+
+                ui.horizontal(|ui| {
+                    let label = config_label(ui, &app_config, t("config.normalization"), "normalization.mode");
+                    egui::ComboBox::from_id_source("normalization_mode")
+                        .selected_text(t_name("normalization_mode", app_config.normalization.mode.name()))
+                        .show_ui(ui, |ui| {
+                            for mode in NormalizationMode::all() {
+                                app_settings_changed |= ui
+                                    .selectable_value(&mut app_config.normalization.mode, *mode, t_name("normalization_mode", mode.name()))
+                                    .changed();
+                            }
+                        })
+                        .response
+                        .labelled_by(label.id);
+                    let label = config_label(ui, &app_config, t("config.epsilon"), "normalization.epsilon");
+                    app_settings_changed |= ui
+                        .add(egui::DragValue::new(&mut app_config.normalization.epsilon).range(0.0..=1000.0).speed(0.1))
+                        .on_hover_text(t("config.epsilon.hover"))
+                        .labelled_by(label.id)
+                        .changed();
+                });
+
+                ui.horizontal(|ui| {
+                    config_label(ui, &app_config, t("config.raw_values"), "normalization.window_relative_raw");
+                    app_settings_changed |= ui
+                        .checkbox(&mut app_config.normalization.window_relative_raw, t("config.window_relative_raw"))
+                        .on_hover_text(t("config.window_relative_raw.hover"))
+                        .changed();
+                });
+
+                if app_config.normalization.mode == NormalizationMode::PercentileRange {
                     ui.horizontal(|ui| {
-                        ui.label(format!("Out {}:", output_zone));
-                        zone_map_changed |= ui
-                            .add(egui::DragValue::new(device_zone).range(0..=7))
-                            .on_hover_text(format!("Device zone that maps to output zone {}", output_zone))
+                        let label = config_label(ui, &app_config, t("config.window_secs"), "normalization.window_secs");
+                        app_settings_changed |= ui
+                            .add(
+                                egui::DragValue::new(&mut app_config.normalization.window_secs)
+                                    .range(0.1..=600.0)
+                                    .speed(0.1),
+                            )
+                            .labelled_by(label.id)
                             .changed();
+                        let high = app_config.normalization.high_percentile;
+                        let label = config_label(ui, &app_config, t("config.percentiles"), "normalization.low_percentile");
+                        app_settings_changed |= ui
+                            .add(
+                                egui::DragValue::new(&mut app_config.normalization.low_percentile)
+                                    .range(0.0..=(high - 1.0).max(0.0))
+                                    .speed(0.5),
+                            )
+                            .labelled_by(label.id)
+                            .changed();
+                        let low = app_config.normalization.low_percentile;
+                        ui.label(t("config.percentiles.to"));
+                        let high = ui.add(
+                            egui::DragValue::new(&mut app_config.normalization.high_percentile)
+                                .range((low + 1.0).min(100.0)..=100.0)
+                                .speed(0.5),
+                        );
+                        app_settings_changed |= accessible_name(high, t("config.percentiles.high")).changed();
                     });
                 }
+
+                ui.horizontal(|ui| {
+                    let label = config_label(ui, &app_config, t("config.plot_duration"), "plot_duration_secs");
+                    app_settings_changed |= ui
+                        .add(egui::DragValue::new(&mut app_config.plot_duration_secs)
+                            .range(MIN_PLOT_DURATION_SECS..=MAX_PLOT_DURATION_SECS)
+                            .speed(0.1)
+                            .fixed_decimals(2))
+                        .on_hover_text(t("config.plot_duration.hover"))
+                        .labelled_by(label.id)
+                        .changed();
+                });
+
+                ui.horizontal(|ui| {
+                    let label = config_label(ui, &app_config, t("config.plot_memory"), "plot_memory_mb");
+                    app_settings_changed |= ui
+                        .add(egui::DragValue::new(&mut app_config.plot_memory_mb)
+                            .range(1.0..=4096.0)
+                            .speed(1.0)
+                            .fixed_decimals(0))
+                        .on_hover_text(t("config.plot_memory.hover"))
+                        .labelled_by(label.id)
+                        .changed();
+                });
+
+                if app_settings_changed {
+                    app_config.mark_dirty();
+                    ctx.request_repaint();
+                }
             });
+        });
 
-            ui.horizontal(|ui| {
-                if ui.button("Reset to Default").clicked() {
-                    app_config.zone_map = (0..8).collect();
-                    zone_map_changed = true;
+        ui.separator();
+        ui.heading(t("config.auto_gain"));
+        lockable(ui, locked, &mut app.toasts, |ui| {
+            render_auto_gain(ui, &app.app_config, app.auto_gain.as_ref());
+        });
+        ctx.request_repaint_after(Duration::from_millis(250));
+
+        ui.separator();
+        ui.heading(t("config.drift"));
+        ui.label(t("config.drift.text"));
+        lockable(ui, locked, &mut app.toasts, |ui| {
+            render_drift(ui, &mut app.app_config.lock_or_recover());
+        });
+
+        ui.separator();
+        ui.heading(t("config.takes"));
+        ui.label(t("config.takes.text"));
+        lockable(ui, locked, &mut app.toasts, |ui| {
+            render_takes(ui, &mut app.app_config.lock_or_recover());
+        });
+
+        ui.separator();
+        ui.heading(t("config.markers"));
+        ui.label(t("config.markers.text"));
+        lockable(ui, locked, &mut app.toasts, |ui| {
+            render_markers(ui, &mut app.app_config.lock_or_recover());
+        });
+
+        ui.separator();
+        ui.heading(t("config.device"));
+        let address = app.device.0.clone();
+        let (oscillator_hz, limits) = {
+            let app_config = app.app_config.lock_or_recover();
+            (app_config.oscillator_hz(&address), app_config.zone_limits(&address))
+        };
+        ui.horizontal(|ui| {
+            ui.label(tf("config.device.oscillator", &[("address", &address)]));
+            lockable(ui, locked, &mut app.toasts, |ui| {
+                let mut mhz = oscillator_hz / 1_000_000.0;
+                let response = ui
+                    .add(
+                        egui::DragValue::new(&mut mhz)
+                            .range(MIN_OSCILLATOR_HZ / 1_000_000.0..=MAX_OSCILLATOR_HZ / 1_000_000.0)
+                            .speed(0.01)
+                            .max_decimals(6)
+                            .suffix(" MHz"),
+                    )
+                    .on_hover_text(t("config.device.oscillator.hover"));
+                if accessible_name(response, t("config.device.oscillator.accessible")).changed() {
+                    let mut app_config = app.app_config.lock_or_recover();
+                    app_config.set_oscillator_hz(&address, mhz * 1_000_000.0);
+                    app_config.mark_dirty();
+                }
+            });
+            ui.checkbox(&mut app.cycle_counts_in_micros, t("config.device.in_micros"))
+                .on_hover_text(t("config.device.in_micros.hover"));
+            let loaded = lockable(ui, locked, &mut app.toasts, |ui| {
+                let mut loaded = None;
+                let picker = egui::ComboBox::from_id_source("hw_template")
+                    .selected_text(t("config.device.template"))
+                    .show_ui(ui, |ui| {
+                        for template in HARDWARE_TEMPLATES {
+                            if ui
+                                .selectable_label(false, template.name)
+                                .on_hover_text(t_name("hw_template", template.description))
+                                .clicked()
+                            {
+                                loaded = Some(template);
+                            }
+                        }
+                    });
+                picker.response.on_hover_text(t("config.device.template.hover"));
+                loaded
+            });
+            if let Some(template) = loaded {
+                *configs = template.zones;
+                config_changed = true;
+                let mut app_config = app.app_config.lock_or_recover();
+                app_config.set_hardware_template(&address, template);
+                app_config.mark_dirty();
+                app.toasts.push(tf("config.device.template.loaded", &[("name", &template.name)]));
+            }
+        });
+        ui.horizontal(|ui| {
+            let label = ui.label(t("config.device.header"));
+            ui.add(
+                egui::TextEdit::singleline(&mut app.header_path)
+                    .hint_text("zone_config.h")
+                    .desired_width(300.0),
+            )
+            .labelled_by(label.id);
+            let clicked = lockable(ui, locked, &mut app.toasts, |ui| {
+                ui.add_enabled(!app.header_path.trim().is_empty(), egui::Button::new(t("config.device.header.import")))
+                    .on_hover_text(t("config.device.header.hover"))
+                    .clicked()
+            });
+            if clicked {
+                let path = app.header_path.trim();
+                match import_c_header(Path::new(path)) {
+                    Ok(imported) => match <[DildonicaZoneConfig; NUM_ZONES]>::try_from(imported) {
+                        Ok(imported) => {
+                            *configs = imported;
+                            config_changed = true;
+                            app.toasts.push(tf("config.device.header.loaded", &[("path", &path)]));
+                        }
+                        Err(imported) => app.toasts.push(tf(
+                            "config.device.header.zone_count",
+                            &[("found", &imported.len()), ("expected", &NUM_ZONES)],
+                        )),
+                    },
+                    Err(e) => app.toasts.push(tf("config.device.header.failed", &[("error", &e)])),
+                }
+            }
+        });
+        let in_micros = app.cycle_counts_in_micros;
+        lockable(ui, locked, &mut app.toasts, |ui| {
+            for (zone, config) in configs.iter_mut().enumerate() {
+                ui.group(|ui| {
+                    ui.label(tf("zone_n", &[("zone", &zone)]));
+
+                    config_changed |= ui.checkbox(&mut config.enabled, t("config.device.enabled")).changed();
+
+                    // ui.horizontal(|ui| {
+                    //     ui.label("MIDI CC:");
+                    //     config_changed |= ui
+                    //         .add(egui::Slider::new(&mut config.midi_control, 0..=127))
+                    //         .changed();
+                    // });
+
+                    ui.horizontal(|ui| {
+                        ui.label(t("config.device.cycle_count_begin"));
+                        config_changed |= cycle_count_field(
+                            ui,
+                            &mut config.cycle_count_begin,
+                            limits.max_cycle_count,
+                            oscillator_hz,
+                            in_micros,
+                            tf("config.device.cycle_count_begin.accessible", &[("zone", &zone)]),
+                        );
+                        ui.label(t("config.device.cycle_count_end"));
+                        config_changed |= cycle_count_field(
+                            ui,
+                            &mut config.cycle_count_end,
+                            limits.max_cycle_count,
+                            oscillator_hz,
+                            in_micros,
+                            tf("config.device.cycle_count_end.accessible", &[("zone", &zone)]),
+                        );
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label(t("config.device.comp_thresh_lo"));
+                        let value = ui.add(egui::DragValue::new(&mut config.comp_thresh_lo).range(0..=limits.max_comp_thresh));
+                        config_changed |= accessible_name(value, tf("config.device.comp_thresh_lo.accessible", &[("zone", &zone)])).changed();
+                        ui.label(t("config.device.comp_thresh_hi"));
+                        let value = ui.add(egui::DragValue::new(&mut config.comp_thresh_hi).range(0..=limits.max_comp_thresh));
+                        config_changed |= accessible_name(value, tf("config.device.comp_thresh_hi.accessible", &[("zone", &zone)])).changed();
+                    });
+                });
+            }
+        });
+
+        if let Some((operation, result)) = app.config_activity.take_finished() {
+            if operation == ConfigOperation::Write {
+                // Read again the next time it is shown
+                app.config_history = None;
+            }
+            match result {
+                Ok(()) => app.toasts.push(match operation {
+                    ConfigOperation::Read => t("config.read.done").to_string(),
+                    ConfigOperation::Write => t("config.write.done").to_string(),
+                }),
+                Err(e) => app.toasts.push(match operation {
+                    ConfigOperation::Read => tf("config.read.failed", &[("error", &e)]),
+                    ConfigOperation::Write => tf("config.write.failed", &[("error", &e)]),
+                }),
+            }
+        }
+        let busy = app.config_activity.running();
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(busy.is_none(), egui::Button::new(t("config.read")))
+                .clicked()
+            {
+                if let Some(ref tx) = app.config_read_tx {
+                    let _ = tx.try_send(());
                 }
+            }
 
-                if ui.button("Reverse Order").clicked() {
-                    app_config.zone_map = app_config.zone_map.clone().into_iter().rev().collect();
-                    zone_map_changed = true;
+            if let Some(selftest) = &app.selftest {
+                let running = selftest.is_running();
+                if ui
+                    .add_enabled(!running, egui::Button::new(t("config.selftest")))
+                    .on_hover_text(t("config.selftest.hover"))
+                    .clicked()
+                {
+                    app.selftest_report = None;
+                    selftest.begin();
                 }
+            }
+
+            let write_clicked = lockable(ui, locked, &mut app.toasts, |ui| {
+                ui.add_enabled(busy.is_none(), egui::Button::new(t("config.write"))).clicked()
             });
+            if write_clicked {
+                if let Some(ref tx) = app.config_tx {
+                    let _ = tx.try_send(*configs);
+                }
+            }
 
-            // Validation
-            let mut used_zones = [false; 8];
-            let mut has_duplicates = false;
-            for &zone in &app_config.zone_map {
-                if zone < 8 {
-                    if used_zones[zone] {
-                        has_duplicates = true;
-                        break;
+            if let Some(compare) = &app.config_compare {
+                let comparing = compare.phase();
+                let compare_clicked = lockable(ui, locked, &mut app.toasts, |ui| {
+                    ui.add_enabled(busy.is_none() && comparing.is_none(), egui::Button::new(t("config.compare")))
+                        .on_hover_text(tf("config.compare.hover", &[("secs", &COMPARE_DURATION.as_secs())]))
+                        .clicked()
+                });
+                if compare_clicked {
+                    app.compare_report = None;
+                    compare.begin(*configs);
+                }
+                if let Some(phase) = comparing {
+                    ui.spinner();
+                    let secs = |elapsed: Duration| format!("{:.1}", elapsed.as_secs_f64());
+                    ui.label(match phase {
+                        ComparePhase::Before(elapsed) => tf("config.compare.before", &[("secs", &secs(elapsed))]),
+                        ComparePhase::Writing => t("config.write.running").to_string(),
+                        ComparePhase::After(elapsed) => tf("config.compare.after", &[("secs", &secs(elapsed))]),
+                    });
+                    if ui.button(t("cancel")).clicked() {
+                        compare.cancel();
                     }
-                    used_zones[zone] = true;
+                    ctx.request_repaint_after(Duration::from_millis(100));
                 }
             }
 
-            if has_duplicates {
-                ui.colored_label(egui::Color32::RED, "⚠ Warning: Duplicate zones detected!");
-            } else if app_config.zone_map.len() == 8 && used_zones.iter().all(|&x| x) {
-                ui.colored_label(egui::Color32::GREEN, "✓ Valid zone mapping");
+            if let Some(operation) = busy {
+                ui.spinner();
+                ui.label(match operation {
+                    ConfigOperation::Read => t("config.read.running"),
+                    ConfigOperation::Write => t("config.write.running"),
+                });
+                ctx.request_repaint_after(Duration::from_millis(100));
             }
+        });
 
-            if zone_map_changed {
-                if let Err(e) = app_config.save_to_file() {
-                    eprintln!("Failed to save app config: {}", e);
-                }
-                ctx.request_repaint();
+        egui::CollapsingHeader::new(t("config.batching")).show(ui, |ui| {
+            lockable(ui, locked, &mut app.toasts, |ui| {
+                render_batching(
+                    ui,
+                    &mut app.app_config.lock_or_recover(),
+                    app.batching_status.as_ref(),
+                    app.batching_tx.as_ref(),
+                );
+            });
+            ctx.request_repaint_after(Duration::from_millis(500));
+        });
+
+        egui::CollapsingHeader::new(t("config.history")).show(ui, |ui| {
+            config_changed |= render_config_history(app, &mut configs, ui);
+        });
+
+        egui::CollapsingHeader::new(t("config.diagnostics")).show(ui, |ui| {
+            render_diagnostics_bundle(app, &configs, ui);
+            ui.separator();
+            render_plot_memory(ui, app.plot.load().memory);
+            ui.separator();
+            render_config_diagnostics(ui, app.config_activity.config_blob().as_deref());
+            if cfg!(feature = "lock-stats") {
+                ui.separator();
+                render_lock_stats(ui);
+                ctx.request_repaint_after(Duration::from_millis(500));
             }
         });
 
-        // Application Settings
+        egui::CollapsingHeader::new(t("config.ble")).show(ui, |ui| {
+            lockable(ui, locked, &mut app.toasts, |ui| {
+                render_ble_uuids(ui, &mut app.app_config.lock_or_recover(), &mut app.ble_uuid_texts);
+            });
+        });
+
         ui.separator();
-        ui.heading("Application Settings");
-        ui.group(|ui| {
-            let mut app_config = app.app_config.lock().unwrap();
-            let mut app_settings_changed = false;
+        ui.heading(t("config.firmware"));
+        render_firmware_update(
+            ui,
+            locked,
+            &mut app.toasts,
+            &mut app.dfu_path,
+            &app.dfu_progress,
+            app.dfu_tx.as_ref(),
+        );
 
-            ui.horizontal(|ui| {
-                ui.label("Exponential Alpha:");
-                app_settings_changed |= ui
-                    .add(egui::DragValue::new(&mut app_config.exponential_alpha)
-                        .range(0.0001..=1.0)
-                        .speed(0.0001)
-                        .fixed_decimals(4))
-                    .on_hover_text("Smoothing factor for exponential averaging (lower = more smoothing)")
-                    .changed();
-            });
+        if config_changed {
+            ctx.request_repaint();
+        }
+    });
+}
 
-            ui.horizontal(|ui| {
-                ui.label("Plot Duration (seconds):");
-                app_settings_changed |= ui
-                    .add(egui::DragValue::new(&mut app_config.plot_duration_secs)
-                        .range(1.0..=30.0)
-                        .speed(0.1)
-                        .fixed_decimals(1))
-                    .on_hover_text("Time window shown in the plot")
-                    .changed();
-            });
+/// Zone group editor, a row of output zone checkboxes per group; a zone in
+/// another group can't be picked
+fn render_zone_groups(ui: &mut egui::Ui, app_config: &mut ConfigStore) {
+    let mut changed = false;
+    if app_config.is_overridden("zone_groups") {
+        config_label(ui, app_config, t("config.zone_groups.overridden"), "zone_groups");
+    }
 
-            if app_settings_changed {
-                if let Err(e) = app_config.save_to_file() {
-                    eprintln!("Failed to save app config: {}", e);
+    ui.horizontal(|ui| {
+        let label = config_label(ui, app_config, t("config.zone_groups.combine"), "zone_group_reduction");
+        egui::ComboBox::from_id_source("zone_group_reduction")
+            .selected_text(t_name("zone_group_reduction", app_config.zone_group_reduction.name()))
+            .show_ui(ui, |ui| {
+                for reduction in ZoneGroupReduction::all() {
+                    changed |= ui
+                        .selectable_value(&mut app_config.zone_group_reduction, *reduction, t_name("zone_group_reduction", reduction.name()))
+                        .changed();
+                }
+            })
+            .response
+            .labelled_by(label.id);
+    });
+
+    let groups = app_config.zone_groups.clone();
+    let mut removed = None;
+    for (index, group) in app_config.zone_groups.iter_mut().enumerate() {
+        ui.horizontal_wrapped(|ui| {
+            ui.label(tf("config.zone_groups.group", &[("group", &index)]));
+            for zone in 0..NUM_ZONES {
+                let elsewhere = groups
+                    .iter()
+                    .enumerate()
+                    .any(|(other, other_group)| other != index && other_group.contains(&zone));
+                let mut member = group.contains(&zone);
+                if ui
+                    .add_enabled(!elsewhere, egui::Checkbox::new(&mut member, zone.to_string()))
+                    .changed()
+                {
+                    if member {
+                        group.push(zone);
+                        group.sort_unstable();
+                    } else {
+                        group.retain(|&grouped| grouped != zone);
+                    }
+                    changed = true;
                 }
-                ctx.request_repaint();
+            }
+            if ui.small_button("🗑").on_hover_text(t("config.zone_groups.delete")).clicked() {
+                removed = Some(index);
             }
         });
+    }
+    if let Some(index) = removed {
+        app_config.zone_groups.remove(index);
+        changed = true;
+    }
 
-        ui.separator();
-        ui.heading("Device Configuration");
-        for (zone, config) in configs.iter_mut().enumerate() {
-            ui.group(|ui| {
-                ui.label(format!("Zone {}", zone));
+    // A new group starts with the first two ungrouped zones, so it is valid
+    let ungrouped: Vec<usize> = (0..NUM_ZONES)
+        .filter(|zone| !app_config.zone_groups.iter().any(|group| group.contains(zone)))
+        .take(2)
+        .collect();
+    ui.horizontal(|ui| {
+        if ui
+            .add_enabled(ungrouped.len() == 2, egui::Button::new(t("config.zone_groups.add")))
+            .on_hover_text(t("config.zone_groups.add.hover"))
+            .clicked()
+        {
+            app_config.zone_groups.push(ungrouped);
+            changed = true;
+        }
+        if ui
+            .add_enabled(!app_config.zone_groups.is_empty(), egui::Button::new(t("config.zone_groups.ungroup")))
+            .clicked()
+        {
+            app_config.zone_groups.clear();
+            changed = true;
+        }
+    });
 
-                config_changed |= ui.checkbox(&mut config.enabled, "Enabled").changed();
+    match validate_zone_groups(&app_config.zone_groups, NUM_ZONES) {
+        Ok(()) => {
+            let logical_zones = LogicalZones::new(&app_config.zone_groups, NUM_ZONES);
+            ui.colored_label(
+                egui::Color32::GREEN,
+                tf("config.zone_groups.valid", &[("zones", &logical_zones.len())]),
+            );
+        }
+        Err(e) => {
+            ui.colored_label(egui::Color32::RED, tf("warning", &[("error", &e)]));
+        }
+    }
 
-                // ui.horizontal(|ui| {
-                //     ui.label("MIDI CC:");
-                //     config_changed |= ui
-                //         .add(egui::Slider::new(&mut config.midi_control, 0..=127))
-                //         .changed();
-                // });
+    if changed {
+        app_config.mark_dirty();
+    }
+}
 
-                ui.horizontal(|ui| {
-                    ui.label("Cycle Count Begin:");
-                    config_changed |= ui
-                        .add(
-                            egui::DragValue::new(&mut config.cycle_count_begin)
-                                .range(0..=100000),
-                        )
-                        .changed();
-                    ui.label("End:");
-                    config_changed |= ui
-                        .add(
-                            egui::DragValue::new(&mut config.cycle_count_end)
-                                .range(0..=100000),
-                        )
-                        .changed();
+/// Buttons saving a diagnostics bundle for a bug report, right away or after
+/// a sample capture, whose progress they show meanwhile. Never locked, they
+/// change no settings.
+fn render_diagnostics_bundle(app: &mut PlotApp, configs: &[DildonicaZoneConfig; NUM_ZONES], ui: &mut egui::Ui) {
+    let progress = app.sample_capture.as_ref().and_then(SampleCapture::progress);
+    ui.horizontal(|ui| {
+        if ui
+            .button(t("config.diagnostics.save"))
+            .on_hover_text(t("config.diagnostics.save.hover"))
+            .clicked()
+        {
+            app.save_diagnostics(configs, None);
+        }
+        let Some(capture) = app.sample_capture.clone() else {
+            return;
+        };
+        match progress {
+            None => {
+                let label = tf("config.diagnostics.capture", &[("secs", &CAPTURE_DURATION.as_secs())]);
+                if ui.button(label).on_hover_text(t("config.diagnostics.capture.hover")).clicked() {
+                    capture.begin(CAPTURE_DURATION);
+                }
+            }
+            Some((elapsed, duration)) => {
+                let text = if elapsed.is_zero() {
+                    t("waiting_for_samples").to_string()
+                } else {
+                    format!("{:.1} / {:.0} s", elapsed.as_secs_f64(), duration.as_secs_f64())
+                };
+                let fraction = elapsed.as_secs_f64() / duration.as_secs_f64();
+                ui.add(egui::ProgressBar::new(fraction as f32).text(text).desired_width(160.0));
+                if ui.button(t("config.diagnostics.save_now")).on_hover_text(t("config.diagnostics.save_now.hover")).clicked() {
+                    let samples = capture.stop();
+                    app.save_diagnostics(configs, Some(samples));
+                }
+            }
+        }
+    });
+}
+
+/// The last writes to the device, newest first, each with a button loading
+/// its configs into the editor. Returns whether one was loaded.
+fn render_config_history(app: &mut PlotApp, configs: &mut [DildonicaZoneConfig; NUM_ZONES], ui: &mut egui::Ui) -> bool {
+    let history = ConfigHistory::beside(&app.app_config.lock_or_recover().config_path);
+    ui.horizontal(|ui| {
+        ui.label(tf("config.history.text", &[("path", &history.path().display())]));
+        if ui.button(t("config.history.reload")).clicked() {
+            app.config_history = None;
+        }
+    });
+    let entries = app
+        .config_history
+        .get_or_insert_with(|| history.last(HISTORY_SHOWN).map_err(|e| e.to_string()));
+    let entries = match entries {
+        Ok(entries) if entries.is_empty() => {
+            ui.weak(t("config.history.empty"));
+            return false;
+        }
+        Ok(entries) => entries.clone(),
+        Err(e) => {
+            ui.colored_label(egui::Color32::RED, tf("config.history.failed", &[("error", e)]));
+            return false;
+        }
+    };
+
+    let mut restored = None;
+    egui::Grid::new("config_history").striped(true).show(ui, |ui| {
+        for (index, entry) in entries.iter().enumerate().rev() {
+            ui.label(format_time(entry.time)).on_hover_text(zone_summary(entry));
+            match &entry.error {
+                None if entry.verified => ui.label(t("config.history.verified")),
+                None => ui.label(t("config.history.unverified")),
+                Some(e) => ui.colored_label(egui::Color32::YELLOW, tf("config.history.error", &[("error", e)])),
+            };
+            let clicked = lockable(ui, app.locked, &mut app.toasts, |ui| {
+                ui.button(t("config.history.restore"))
+                    .on_hover_text(t("config.history.restore.hover"))
+                    .clicked()
+            });
+            if clicked {
+                restored = Some(index);
+            }
+            ui.end_row();
+        }
+    });
+    let Some(index) = restored else {
+        return false;
+    };
+    *configs = entries[index].configs;
+    app.toasts.push(tf("config.history.restored", &[("time", &format_time(entries[index].time))]));
+    true
+}
+
+/// `time` in seconds since the Unix epoch, as local time
+fn format_time(time: f64) -> String {
+    chrono::DateTime::from_timestamp(time as i64, 0)
+        .map_or_else(String::new, |time| time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+/// The values of every zone in `entry`, one line per zone
+fn zone_summary(entry: &HistoryEntry) -> String {
+    entry
+        .configs
+        .iter()
+        .enumerate()
+        .map(|(zone, config)| {
+            tf(
+                "config.history.zone",
+                &[
+                    ("zone", &zone),
+                    ("state", &if config.enabled { t("on") } else { t("off") }),
+                    ("control", &config.midi_control),
+                    ("begin", &config.cycle_count_begin),
+                    ("end", &config.cycle_count_end),
+                    ("lo", &config.comp_thresh_lo),
+                    ("hi", &config.comp_thresh_hi),
+                ],
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Memory held by the plot history against its budget
+fn render_plot_memory(ui: &mut egui::Ui, memory: PlotMemory) {
+    const MB: f64 = 1024.0 * 1024.0;
+    if memory.budget == 0 {
+        ui.weak(t("config.plot_memory.empty"));
+        return;
+    }
+    let fraction = memory.bytes as f64 / memory.budget as f64;
+    ui.add(egui::ProgressBar::new(fraction as f32).text(tf(
+        "config.plot_memory.used",
+        &[
+            ("used", &format!("{:.1}", memory.bytes as f64 / MB)),
+            ("budget", &format!("{:.0}", memory.budget as f64 / MB)),
+        ],
+    )));
+    ui.label(tf(
+        "config.plot_memory.chunks",
+        &[("compacted", &memory.compacted_chunks), ("dropped", &memory.dropped_chunks)],
+    ));
+}
+
+/// How often the GUI and the pipeline took the shared mutexes and waited for
+/// them, with the `lock-stats` feature
+fn render_lock_stats(ui: &mut egui::Ui) {
+    ui.label(t("config.lock_stats"));
+    egui::Grid::new("lock_stats").striped(true).show(ui, |ui| {
+        ui.label(t("config.lock_stats.lock"));
+        ui.label(t("config.lock_stats.side"));
+        ui.label(t("config.lock_stats.taken"));
+        ui.label(t("config.lock_stats.contended"));
+        ui.label(t("config.lock_stats.waited"));
+        ui.end_row();
+        for report in lock_reports() {
+            for (side, counts) in LockSide::ALL.iter().zip(&report.sides) {
+                ui.label(report.name);
+                ui.label(t_name("lock_side", side.name()));
+                ui.label(counts.acquisitions.to_string());
+                ui.label(counts.contended.to_string());
+                ui.label(format!("{:.1} ms", counts.wait_nanos as f64 / 1e6));
+                ui.end_row();
+            }
+        }
+    });
+}
+
+/// What the device sent on the last config read, the layout it was read in
+/// and a read-only hex dump, one zone per row
+fn render_config_diagnostics(ui: &mut egui::Ui, blob: Option<&[u8]>) {
+    let Some(blob) = blob else {
+        ui.weak(t("config.blob.none"));
+        return;
+    };
+    let row_size = match ConfigLayout::detect(blob.len(), NUM_ZONES) {
+        Ok(ConfigLayout::Current) => {
+            ui.label(tf(
+                "config.blob.current",
+                &[("bytes", &blob.len()), ("zones", &NUM_ZONES), ("size", &DildonicaZoneConfig::SIZE)],
+            ));
+            DildonicaZoneConfig::SIZE
+        }
+        Ok(layout) => {
+            let mismatch = SizeMismatch {
+                received: blob.len(),
+                num_zones: NUM_ZONES,
+            };
+            ui.colored_label(
+                egui::Color32::YELLOW,
+                tf(
+                    "config.blob.other_layout",
+                    &[
+                        ("mismatch", &mismatch),
+                        ("layout", &t_name("config_layout", layout.name()).to_lowercase()),
+                    ],
+                ),
+            );
+            layout.size()
+        }
+        Err(mismatch) => {
+            ui.colored_label(egui::Color32::RED, format!("⚠ {}", mismatch));
+            mismatch.guess().map_or(16, |(_, size)| size)
+        }
+    };
+    let hex = blob
+        .chunks(row_size)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            format!("{:04X}  {}", row * row_size, bytes.join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    accessible_name(
+        ui.add(
+            egui::TextEdit::multiline(&mut hex.as_str())
+                .font(egui::TextStyle::Monospace)
+                .desired_width(f32::INFINITY),
+        ),
+        t("config.blob.accessible"),
+    );
+}
+
+/// Auto gain settings and the gains learned so far, with freeze and reset
+fn render_auto_gain(ui: &mut egui::Ui, app_config: &Arc<TrackedMutex<ConfigStore>>, auto_gain: Option<&AutoGain>) {
+    ui.group(|ui| {
+        let mut app_config = app_config.lock_or_recover();
+        let mut changed = false;
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, &app_config, t("enabled"), "auto_gain.enabled");
+            changed |= ui.checkbox(&mut app_config.auto_gain.enabled, "").labelled_by(label.id).changed();
+        });
+
+        ui.add_enabled_ui(app_config.auto_gain.enabled, |ui| {
+            ui.horizontal(|ui| {
+                let label = config_label(ui, &app_config, t("config.auto_gain.target"), "auto_gain.target");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.auto_gain.target).range(0.05..=2.0).speed(0.01))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, &app_config, t("config.window_secs"), "auto_gain.window_secs");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.auto_gain.window_secs).range(0.1..=600.0).speed(0.1))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, &app_config, t("config.auto_gain.adaptation"), "auto_gain.adaptation_per_sec");
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut app_config.auto_gain.adaptation_per_sec)
+                            .range(0.001..=10.0)
+                            .speed(0.01),
+                    )
+                    .on_hover_text(t("config.auto_gain.adaptation.hover"))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, &app_config, t("config.auto_gain.min_peak"), "auto_gain.min_peak");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.auto_gain.min_peak).range(0.0..=0.99).speed(0.005))
+                    .on_hover_text(t("config.auto_gain.min_peak.hover"))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                let max_gain = app_config.auto_gain.max_gain;
+                let label = config_label(ui, &app_config, t("config.auto_gain.min_gain"), "auto_gain.min_gain");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.auto_gain.min_gain).range(0.01..=max_gain).speed(0.01))
+                    .labelled_by(label.id)
+                    .changed();
+                let min_gain = app_config.auto_gain.min_gain;
+                let label = config_label(ui, &app_config, t("config.auto_gain.max_gain"), "auto_gain.max_gain");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.auto_gain.max_gain).range(min_gain..=100.0).speed(0.01))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            if let Some(auto_gain) = auto_gain {
+                let gains = auto_gain.gains();
+                ui.horizontal_wrapped(|ui| {
+                    for (zone, gain) in gains.iter().enumerate() {
+                        ui.label(tf("config.auto_gain.gain", &[("zone", &zone), ("gain", &format!("{:.2}", gain))]));
+                    }
                 });
 
                 ui.horizontal(|ui| {
-                    ui.label("Comparator Threshold Low:");
-                    config_changed |= ui
-                        .add(
-                            egui::DragValue::new(&mut config.comp_thresh_lo)
-                                .range(0..=10000),
-                        )
-                        .changed();
-                    ui.label("High:");
-                    config_changed |= ui
-                        .add(
-                            egui::DragValue::new(&mut config.comp_thresh_hi)
-                                .range(0..=10000),
-                        )
+                    let label = config_label(ui, &app_config, t("config.auto_gain.freeze"), "auto_gain.frozen");
+                    if ui.checkbox(&mut app_config.auto_gain.frozen, "").labelled_by(label.id).changed() {
+                        if app_config.auto_gain.frozen && app_config.auto_gain.persist {
+                            app_config.auto_gain.gains = gains.to_vec();
+                        }
+                        changed = true;
+                    }
+                    let label = config_label(ui, &app_config, t("config.auto_gain.persist"), "auto_gain.persist");
+                    changed |= ui
+                        .checkbox(&mut app_config.auto_gain.persist, "")
+                        .on_hover_text(t("config.auto_gain.persist.hover"))
+                        .labelled_by(label.id)
                         .changed();
+                    if ui.button(t("config.auto_gain.reset")).clicked() {
+                        auto_gain.reset();
+                        app_config.auto_gain.gains.clear();
+                        changed = true;
+                    }
                 });
+            }
+        });
+
+        if changed {
+            app_config.mark_dirty();
+        }
+    });
+}
+
+/// Settings of the baseline drift warning
+fn render_drift(ui: &mut egui::Ui, app_config: &mut ConfigStore) {
+    ui.group(|ui| {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("enabled"), "drift.enabled");
+            changed |= ui.checkbox(&mut app_config.drift.enabled, "").labelled_by(label.id).changed();
+        });
+        ui.add_enabled_ui(app_config.drift.enabled, |ui| {
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("config.drift.threshold"), "drift.threshold_percent");
+                changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut app_config.drift.threshold_percent)
+                            .range(0.5..=100.0)
+                            .speed(0.1)
+                            .suffix(" %"),
+                    )
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("config.window_secs"), "drift.window_secs");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.drift.window_secs).range(10.0..=3600.0).speed(1.0))
+                    .on_hover_text(t("config.drift.window.hover"))
+                    .labelled_by(label.id)
+                    .changed();
             });
+        });
+        if changed {
+            app_config.mark_dirty();
         }
+    });
+}
 
+/// Settings of the take buffer
+fn render_takes(ui: &mut egui::Ui, app_config: &mut ConfigStore) {
+    ui.group(|ui| {
+        let mut changed = false;
         ui.horizontal(|ui| {
-            if ui.button("Read Config from Device").clicked() {
-                if let Some(ref tx) = app.config_read_tx {
-                    let _ = tx.try_send(());
+            let label = config_label(ui, app_config, t("enabled"), "takes.enabled");
+            changed |= ui.checkbox(&mut app_config.takes.enabled, "").labelled_by(label.id).changed();
+        });
+        ui.add_enabled_ui(app_config.takes.enabled, |ui| {
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("config.takes.minutes"), "takes.minutes");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.takes.minutes).range(0.5..=60.0).speed(0.1).suffix(" min"))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("config.takes.memory"), "takes.memory_mb");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.takes.memory_mb).range(1.0..=1024.0).speed(1.0).suffix(" MB"))
+                    .on_hover_text(t("config.takes.memory.hover"))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+            ui.horizontal(|ui| {
+                let max_secs = app_config.takes.minutes * 60.0;
+                app_config.takes.save_secs = app_config.takes.save_secs.min(max_secs);
+                let label = config_label(ui, app_config, t("config.takes.save_secs"), "takes.save_secs");
+                changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.takes.save_secs).range(1.0..=max_secs).speed(1.0).suffix(" s"))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+            ui.horizontal(|ui| {
+                config_label(ui, app_config, t("config.takes.binding"), "takes.save_binding");
+                changed |= render_patch_binding(ui, "takes_save_binding", t("config.takes.binding"), &mut app_config.takes.save_binding);
+            });
+            ui.label(tf("config.takes.directory", &[("path", &app_config.takes.directory().display())]));
+        });
+        if changed {
+            app_config.mark_dirty();
+        }
+    });
+}
+
+/// The name and gesture of the markers dropped by a zone
+fn render_markers(ui: &mut egui::Ui, app_config: &mut ConfigStore) {
+    ui.group(|ui| {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("config.markers.name"), "markers.name");
+            changed |= ui
+                .add(egui::TextEdit::singleline(&mut app_config.markers.name).char_limit(MAX_MARKER_NAME_CHARS).desired_width(160.0))
+                .labelled_by(label.id)
+                .changed();
+        });
+        ui.horizontal(|ui| {
+            config_label(ui, app_config, t("config.markers.binding"), "markers.binding");
+            changed |= render_patch_binding(ui, "markers_binding", t("config.markers.binding"), &mut app_config.markers.binding);
+        });
+        if changed {
+            app_config.mark_dirty();
+        }
+    });
+}
+
+/// The samples the device batches per notification, the bounds they are
+/// adapted within and a manual override, sent to the device session as soon
+/// as they change.
+fn render_batching(
+    ui: &mut egui::Ui,
+    app_config: &mut ConfigStore,
+    status: Option<&BatchingStatus>,
+    batching_tx: Option<&mpsc::Sender<BatchingConfig>>,
+) {
+    ui.label(t("config.batching.text"));
+    let state = status.map(BatchingStatus::get).unwrap_or_default();
+    match state.supported {
+        None => {
+            ui.label(t("config.batching.not_connected"));
+        }
+        Some(false) => {
+            ui.label(t("config.batching.unsupported"));
+        }
+        Some(true) => {
+            ui.horizontal(|ui| {
+                let hint = state.hint.map_or_else(|| "-".to_string(), |hint| hint.to_string());
+                ui.label(tf("config.batching.hint", &[("samples", &hint)]));
+                if let Some(load) = state.load {
+                    let busy = format!("{:.0}", load.busy * 100.0);
+                    ui.label(tf("config.batching.load", &[("busy", &busy), ("drops", &load.drops)]));
                 }
+            });
+            if let Some(error) = &state.error {
+                ui.colored_label(egui::Color32::RED, tf("config.batching.error", &[("error", error)]));
             }
+        }
+    }
 
-            if ui.button("Write Config to Device").clicked() {
-                if let Some(ref tx) = app.config_tx {
-                    let _ = tx.try_send(*configs);
+    let mut changed = false;
+    ui.horizontal(|ui| {
+        let label = config_label(ui, app_config, t("config.batching.adaptive"), "batching.adaptive");
+        changed |= ui
+            .checkbox(&mut app_config.batching.adaptive, "")
+            .on_hover_text(t("config.batching.adaptive.hover"))
+            .labelled_by(label.id)
+            .changed();
+    });
+    ui.add_enabled_ui(app_config.batching.adaptive && app_config.batching.manual_samples.is_none(), |ui| {
+        ui.horizontal(|ui| {
+            let max = app_config.batching.max_samples;
+            let label = config_label(ui, app_config, t("config.batching.min"), "batching.min_samples");
+            changed |= ui
+                .add(egui::DragValue::new(&mut app_config.batching.min_samples).range(1..=max))
+                .labelled_by(label.id)
+                .changed();
+            let min = app_config.batching.min_samples;
+            let label = config_label(ui, app_config, t("config.batching.max"), "batching.max_samples");
+            changed |= ui
+                .add(egui::DragValue::new(&mut app_config.batching.max_samples).range(min..=MAX_BATCH_SAMPLES))
+                .labelled_by(label.id)
+                .changed();
+        });
+    });
+    ui.horizontal(|ui| {
+        let label = config_label(ui, app_config, t("config.batching.manual"), "batching.manual_samples");
+        let mut manual = app_config.batching.manual_samples.is_some();
+        if ui
+            .checkbox(&mut manual, "")
+            .on_hover_text(t("config.batching.manual.hover"))
+            .labelled_by(label.id)
+            .changed()
+        {
+            let samples = state.hint.unwrap_or(app_config.batching.min_samples).clamp(1, MAX_BATCH_SAMPLES);
+            app_config.batching.manual_samples = manual.then_some(samples);
+            changed = true;
+        }
+        if let Some(samples) = &mut app_config.batching.manual_samples {
+            changed |= ui
+                .add(egui::DragValue::new(samples).range(1..=MAX_BATCH_SAMPLES))
+                .labelled_by(label.id)
+                .changed();
+        }
+    });
+
+    if changed {
+        app_config.mark_dirty();
+        if let Some(tx) = batching_tx {
+            let _ = tx.try_send(app_config.batching.clone());
+        }
+    }
+}
+
+/// The UUIDs the device is connected with. They are edited as text so a
+/// half-typed one stays in its field, and only stored once all are valid.
+fn render_ble_uuids(ui: &mut egui::Ui, app_config: &mut ConfigStore, texts: &mut Option<(BleConfig, [String; 4])>) {
+    const FIELDS: [(&str, &str); 4] = [
+        ("config.ble.service", "ble.service_uuid"),
+        ("config.ble.characteristic", "ble.characteristic_uuid"),
+        ("config.ble.config_characteristic", "ble.config_characteristic_uuid"),
+        ("config.ble.batching_characteristic", "ble.batching_characteristic_uuid"),
+    ];
+    let stored = app_config.ble;
+    let uuids = |ble: BleConfig| {
+        [
+            ble.service_uuid,
+            ble.characteristic_uuid,
+            ble.config_characteristic_uuid,
+            ble.batching_characteristic_uuid,
+        ]
+    };
+    // Filled again when the UUIDs change elsewhere: reset, reloaded or overridden
+    let (filled_from, texts) = texts.get_or_insert_with(|| (stored, uuids(stored).map(|uuid| uuid.to_string())));
+    if *filled_from != stored {
+        *filled_from = stored;
+        *texts = uuids(stored).map(|uuid| uuid.to_string());
+    }
+
+    ui.label(t("config.ble.text"));
+    let mut parsed = [None; 4];
+    egui::Grid::new("ble_uuids").num_columns(3).show(ui, |ui| {
+        for (index, (text_key, key)) in FIELDS.iter().enumerate() {
+            let label = config_label(ui, app_config, t(text_key), key);
+            ui.add(
+                egui::TextEdit::singleline(&mut texts[index])
+                    .font(egui::TextStyle::Monospace)
+                    .desired_width(300.0),
+            )
+            .labelled_by(label.id);
+            match Uuid::parse_str(texts[index].trim()) {
+                Ok(uuid) => {
+                    parsed[index] = Some(uuid);
+                    ui.label("");
+                }
+                Err(e) => {
+                    ui.colored_label(egui::Color32::RED, tf("config.ble.invalid", &[("error", &e)]));
                 }
             }
-        });
+            ui.end_row();
+        }
+    });
 
-        if config_changed {
-            ctx.request_repaint();
+    if let [Some(service_uuid), Some(characteristic_uuid), Some(config_characteristic_uuid), Some(batching_characteristic_uuid)] = parsed {
+        let edited = BleConfig {
+            service_uuid,
+            characteristic_uuid,
+            config_characteristic_uuid,
+            batching_characteristic_uuid,
+        };
+        if edited.validate().is_err() {
+            ui.colored_label(egui::Color32::RED, t("config.ble.same_characteristic"));
+        } else if edited != stored {
+            app_config.ble = edited;
+            *filled_from = edited;
+            app_config.mark_dirty();
+        }
+    }
+
+    if ui
+        .add_enabled(!stored.is_stock(), egui::Button::new(t("config.ble.reset")))
+        .on_hover_text(t("config.ble.reset.hover"))
+        .clicked()
+    {
+        app_config.ble = BleConfig::default();
+        app_config.mark_dirty();
+    }
+}
+
+/// Package path and progress of a Nordic DFU update over BLE
+fn render_firmware_update(
+    ui: &mut egui::Ui,
+    locked: bool,
+    toasts: &mut Toasts,
+    path: &mut String,
+    progress: &DfuProgress,
+    dfu_tx: Option<&mpsc::Sender<DfuRequest>>,
+) {
+    ui.label(t("config.firmware.text"));
+    let running = progress.is_running();
+    ui.horizontal(|ui| {
+        let label = ui.label(t("config.firmware.package"));
+        ui.add_enabled(
+            !running,
+            egui::TextEdit::singleline(path)
+                .hint_text("firmware.zip")
+                .desired_width(300.0),
+        )
+        .labelled_by(label.id);
+        let clicked = lockable(ui, locked, toasts, |ui| {
+            ui.add_enabled(
+                !running && dfu_tx.is_some() && !path.trim().is_empty(),
+                egui::Button::new(t("config.firmware.update")),
+            )
+            .on_disabled_hover_text(t("config.firmware.update.disabled"))
+            .clicked()
+        });
+        let Some(dfu_tx) = dfu_tx.filter(|_| clicked) else {
+            return;
+        };
+        match DfuPackage::read(Path::new(path.trim())) {
+            Ok(package) => {
+                progress.set(DfuState::EnteringBootloader);
+                let request = DfuRequest {
+                    package,
+                    progress: progress.clone(),
+                };
+                if dfu_tx.try_send(request).is_err() {
+                    progress.set(DfuState::Failed(t("config.firmware.session_ended").to_string()));
+                }
+            }
+            Err(e) => toasts.push(e.to_string()),
         }
     });
-}
\ No newline at end of file
+
+    match progress.state() {
+        DfuState::Idle => {}
+        DfuState::Uploading { sent, total } => {
+            let fraction = if total == 0 { 0.0 } else { sent as f32 / total as f32 };
+            ui.add(egui::ProgressBar::new(fraction).text(format!("{} / {} KiB", sent / 1024, total / 1024)));
+        }
+        DfuState::EnteringBootloader => {
+            ui.label(t("config.firmware.bootloader"));
+        }
+        DfuState::Reconnecting => {
+            ui.label(t("config.firmware.reconnecting"));
+        }
+        DfuState::Finished => {
+            ui.label(t("config.firmware.finished"));
+        }
+        DfuState::Failed(reason) => {
+            ui.colored_label(egui::Color32::RED, tf("config.firmware.failed", &[("reason", &reason)]));
+        }
+    }
+    if running {
+        ui.ctx().request_repaint_after(Duration::from_millis(200));
+    }
+}
+/// A cycle count of the device, edited in cycles or in microseconds at
+/// `oscillator_hz` with the other unit beside it. Microseconds are rounded
+/// to the nearest cycle, which is what is written. Returns whether it
+/// changed.
+fn cycle_count_field(
+    ui: &mut egui::Ui,
+    cycles: &mut u32,
+    max_cycles: u32,
+    oscillator_hz: f64,
+    in_micros: bool,
+    accessible: String,
+) -> bool {
+    if !in_micros {
+        let response = ui.add(egui::DragValue::new(cycles).range(0..=max_cycles));
+        let changed = accessible_name(response, accessible).changed();
+        ui.weak(tf("config.device.micros", &[("micros", &format!("{:.2}", cycles_to_micros(*cycles, oscillator_hz)))]));
+        return changed;
+    }
+    let mut micros = cycles_to_micros(*cycles, oscillator_hz);
+    // A cycle per point dragged, as when editing cycles
+    let response = ui.add(
+        egui::DragValue::new(&mut micros)
+            .range(0.0..=cycles_to_micros(max_cycles, oscillator_hz))
+            .speed(cycles_to_micros(1, oscillator_hz))
+            .fixed_decimals(2)
+            .suffix(" µs"),
+    );
+    let changed = accessible_name(response, accessible).changed();
+    if changed {
+        *cycles = micros_to_cycles(micros, oscillator_hz).min(max_cycles);
+    }
+    ui.weak(tf("config.device.cycles", &[("cycles", cycles)]));
+    changed
+}
@@ -0,0 +1,259 @@
+use super::app::{PlotApp, Tab};
+use super::i18n::{t, tf};
+use super::plot::{device_zone, draw_session_markers, label_session_markers, set_window};
+use crate::config::{PlotSource, NUM_ZONES};
+use crate::midi::ZoneMutes;
+use crate::sync::LockExt;
+use eframe::egui;
+use egui_plot::{Line, Plot, Points};
+
+/// Every zone's bit of a `ZoneMutes` mask
+const ALL_ZONES: u32 = (1 << NUM_ZONES) - 1;
+
+/// The zone picked to tune its coil while the rest keeps quiet, and what
+/// focusing changed, put back as it was when leaving
+#[derive(Debug, Clone, Copy)]
+pub struct ZoneFocus {
+    pub zone: usize,
+    /// Tab shown before focusing
+    tab: Tab,
+    /// Zones muted before focusing
+    mutes: u32,
+}
+
+impl ZoneFocus {
+    /// The zones muted while focused: every other zone, and the focused one
+    /// if it already was
+    fn mutes(&self) -> u32 {
+        self.mutes | (ALL_ZONES & !(1 << self.zone))
+    }
+
+    /// Focuses `zone` over the `tab` shown and the zones `mutes` has muted,
+    /// keeping what `focus` puts back if it is already set
+    fn enter(focus: &mut Option<Self>, tab: &mut Tab, mutes: &ZoneMutes, zone: usize) {
+        let entered = match *focus {
+            Some(focus) => ZoneFocus { zone, ..focus },
+            None => ZoneFocus {
+                zone,
+                tab: *tab,
+                mutes: mutes.mask(),
+            },
+        };
+        mutes.set_mask(entered.mutes());
+        *tab = Tab::Plot;
+        *focus = Some(entered);
+    }
+
+    /// Puts `tab` and `mutes` back as `focus` found them, and clears it
+    fn leave(focus: &mut Option<Self>, tab: &mut Tab, mutes: &ZoneMutes) {
+        if let Some(focus) = focus.take() {
+            mutes.set_mask(focus.mutes);
+            *tab = focus.tab;
+        }
+    }
+}
+
+impl PlotApp {
+    /// Shows only `zone` in the Plot tab and mutes the MIDI of every other
+    /// zone. Focusing another zone while focused keeps what is put back on
+    /// leaving.
+    pub fn focus_zone(&mut self, zone: usize) {
+        ZoneFocus::enter(&mut self.focus, &mut self.selected_tab, &self.zone_mutes, zone);
+    }
+
+    /// Puts the tab and the muted zones back as they were before focusing.
+    pub fn leave_focus(&mut self) {
+        ZoneFocus::leave(&mut self.focus, &mut self.selected_tab, &self.zone_mutes);
+    }
+}
+
+/// The focused zone's raw and normalized lines stacked, its meter, device
+/// config and live statistics, in place of the Plot tab
+pub fn render_focus(app: &mut PlotApp, ui: &mut egui::Ui, focus: ZoneFocus) {
+    let zone = focus.zone;
+    let (color, plot_duration, window_relative_raw, threshold, zone_map, scope) = {
+        let config = app.app_config.lock_or_recover();
+        let [r, g, b] = config.plot_colors[zone];
+        (
+            egui::Color32::from_rgb(r, g, b),
+            config.plot_duration_secs,
+            config.normalization.window_relative_raw,
+            config.midi.note_config.threshold,
+            config.zone_map.clone(),
+            config.zone_map_scope,
+        )
+    };
+
+    ui.horizontal(|ui| {
+        ui.colored_label(color, egui::RichText::new(tf("focus.heading", &[("zone", &zone)])).heading());
+        ui.label(t("focus.muted"));
+        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+            // Leaving only puts things back, so never locked
+            let leave = ui.button(t("focus.leave")).on_hover_text(t("focus.leave.hover"));
+            if leave.clicked() || ui.input_mut(|input| input.consume_key(egui::Modifiers::NONE, egui::Key::Escape)) {
+                app.leave_focus();
+            }
+        });
+    });
+    ui.separator();
+
+    let frame = match &app.plot_paused {
+        Some(paused) => paused.frame(),
+        None => app.plot.load(),
+    };
+    let window_end = match &app.plot_paused {
+        Some(paused) => paused.end(),
+        None => frame.now().unwrap_or(0.0),
+    };
+    let window = window_end - plot_duration..=window_end;
+    let session_markers = app.markers.within(window_end - plot_duration, window_end);
+    let last = frame.last(zone).copied();
+
+    egui::TopBottomPanel::bottom("focus_details").show_inside(ui, |ui| {
+        // The meter, larger than anywhere else to read from a distance
+        let magnitude = last.map_or(0.0, |point| point.normalized.abs());
+        let meter_color = if magnitude >= threshold { color } else { color.gamma_multiply(0.5) };
+        ui.add(
+            egui::ProgressBar::new(magnitude.clamp(0.0, 1.0) as f32)
+                .desired_height(40.0)
+                .fill(meter_color)
+                .text(tf("focus.meter", &[("value", &format!("{:.2}", magnitude)), ("threshold", &format!("{:.2}", threshold))])),
+        );
+        ui.add_space(4.0);
+        ui.columns(2, |columns| {
+            columns[0].strong(t("focus.device_config"));
+            let device_zone = device_zone(&zone_map, scope, zone);
+            let config = device_zone.and_then(|device_zone| app.zone_configs.lock_or_recover().get(device_zone).copied());
+            egui::Grid::new("focus_device_config").striped(true).show(&mut columns[0], |ui| match (device_zone, config) {
+                (Some(device_zone), Some(config)) => {
+                    let rows = [
+                        (t("focus.device_zone"), device_zone.to_string()),
+                        (t("enabled"), if config.enabled { t("focus.on") } else { t("focus.off") }.to_string()),
+                        (t("focus.cycle_counts"), format!("{} – {}", config.cycle_count_begin, config.cycle_count_end)),
+                        (t("focus.comp_thresholds"), format!("{} – {}", config.comp_thresh_lo, config.comp_thresh_hi)),
+                        (t("focus.midi_control"), config.midi_control.to_string()),
+                    ];
+                    for (label, value) in rows {
+                        ui.label(label);
+                        ui.monospace(value);
+                        ui.end_row();
+                    }
+                }
+                _ => {
+                    ui.label(t("focus.no_device_zone"));
+                    ui.end_row();
+                }
+            });
+
+            columns[1].strong(t("focus.statistics"));
+            let raw: Vec<f64> = frame
+                .points(zone)
+                .filter(|point| window.contains(&point.time))
+                .map(|point| point.raw)
+                .collect();
+            let samples = app
+                .shared_stats
+                .as_ref()
+                .and_then(|stats| stats.latest())
+                .map(|stats| stats.per_zone[zone]);
+            let usage = app.usage.as_ref().map(|usage| usage.session().0.zones[zone]);
+            egui::Grid::new("focus_statistics").striped(true).show(&mut columns[1], |ui| {
+                let unknown = || "-".to_string();
+                let min = raw.iter().copied().reduce(f64::min);
+                let max = raw.iter().copied().reduce(f64::max);
+                let mean = (!raw.is_empty()).then(|| raw.iter().sum::<f64>() / raw.len() as f64);
+                let raw_format = |value: f64| if window_relative_raw { format!("{:.3}", value) } else { format!("{:.0}", value) };
+                let rows = [
+                    (t("focus.raw"), last.map_or_else(unknown, |point| raw_format(point.raw))),
+                    (t("focus.normalized"), last.map_or_else(unknown, |point| format!("{:.3}", point.normalized))),
+                    (t("focus.raw_range"), match (min, max) {
+                        (Some(min), Some(max)) => format!("{} – {} ({})", raw_format(min), raw_format(max), raw_format(max - min)),
+                        _ => unknown(),
+                    }),
+                    (t("focus.raw_mean"), mean.map_or_else(unknown, raw_format)),
+                    (t("focus.samples"), samples.map_or_else(unknown, |samples| samples.to_string())),
+                    (t("stats.touches"), usage.map_or_else(unknown, |usage| usage.touches.to_string())),
+                ];
+                for (label, value) in rows {
+                    ui.label(label);
+                    ui.monospace(value);
+                    ui.end_row();
+                }
+            });
+        });
+    });
+
+    // Own plot ids, so the legend of the Plot tab keeps the zones it hides
+    let bucket_secs = plot_duration / f64::from(ui.available_width().max(1.0));
+    let height = (ui.available_height() / 2.0 - ui.spacing().item_spacing.y).max(80.0);
+    let raw_name = if window_relative_raw { "raw/window" } else { PlotSource::Raw.abbreviation() };
+    let stacked = [
+        ("focus_raw", PlotSource::Raw, raw_name),
+        ("focus_normalized", PlotSource::Normalized, PlotSource::Normalized.abbreviation()),
+    ];
+    for (id, source, abbreviation) in stacked {
+        let line = match source {
+            PlotSource::Raw => app.plot_cache.focus_line(&frame, zone, bucket_secs, source),
+            _ => app.plot_cache.line(&frame, zone, bucket_secs, source),
+        };
+        let name = tf("plot.line", &[("zone", &zone), ("source", &abbreviation)]);
+        let response = Plot::new(id)
+            .height(height)
+            .allow_scroll(false)
+            .allow_zoom(false)
+            .allow_drag(false)
+            .y_axis_label(abbreviation)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(line.points()).name(&name).color(color));
+                if let Some(saturated) = line.saturated() {
+                    plot_ui.points(Points::new(saturated).color(egui::Color32::RED).radius(3.0).name(t("plot.saturated")));
+                }
+                draw_session_markers(plot_ui, &session_markers);
+                set_window(plot_ui, window_end, plot_duration);
+            });
+        label_session_markers(ui, &response.transform, &session_markers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn focusing_mutes_every_other_zone_and_leaving_restores() {
+        let (mut focus, mut tab, mutes) = (None, Tab::Midi, ZoneMutes::new());
+        ZoneFocus::enter(&mut focus, &mut tab, &mutes, 3);
+        assert_eq!((tab, mutes.mask()), (Tab::Plot, 0b1111_0111));
+        assert!(!mutes.is_muted(3) && mutes.is_muted(0));
+        ZoneFocus::leave(&mut focus, &mut tab, &mutes);
+        assert_eq!((focus.map(|focus| focus.zone), tab, mutes.mask()), (None, Tab::Midi, 0));
+        // Leaving again changes nothing
+        ZoneFocus::leave(&mut focus, &mut tab, &mutes);
+        assert_eq!((tab, mutes.mask()), (Tab::Midi, 0));
+    }
+
+    #[test]
+    fn a_zone_muted_before_focusing_stays_muted() {
+        let (mut focus, mut tab, mutes) = (None, Tab::Stats, ZoneMutes::new());
+        mutes.set_mask(0b0000_0100);
+        ZoneFocus::enter(&mut focus, &mut tab, &mutes, 2);
+        assert_eq!(mutes.mask(), ALL_ZONES);
+        ZoneFocus::leave(&mut focus, &mut tab, &mutes);
+        assert_eq!((tab, mutes.mask()), (Tab::Stats, 0b0000_0100));
+    }
+
+    #[test]
+    fn switching_zones_keeps_what_leaving_puts_back() {
+        let (mut focus, mut tab, mutes) = (None, Tab::Config, ZoneMutes::new());
+        mutes.set_mask(0b0000_0100);
+        ZoneFocus::enter(&mut focus, &mut tab, &mutes, 2);
+        ZoneFocus::enter(&mut focus, &mut tab, &mutes, 5);
+        assert_eq!(focus.map(|focus| focus.zone), Some(5));
+        assert_eq!(mutes.mask(), 0b1101_1111);
+        // Another tab picked while focused is left for the one before
+        tab = Tab::Outputs;
+        ZoneFocus::enter(&mut focus, &mut tab, &mutes, 5);
+        ZoneFocus::leave(&mut focus, &mut tab, &mutes);
+        assert_eq!((tab, mutes.mask()), (Tab::Config, 0b0000_0100));
+    }
+}
@@ -0,0 +1,62 @@
+use crate::config::Language;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use tracing::debug;
+
+/// Index of the language in `Language::all()`, set by the app every frame
+static LANGUAGE: AtomicUsize = AtomicUsize::new(0);
+
+type Catalog = HashMap<String, String>;
+
+/// The strings of `language` by key, parsed from `locales/` on first use
+fn catalog(language: Language) -> &'static Catalog {
+    static ENGLISH: OnceLock<Catalog> = OnceLock::new();
+    static GERMAN: OnceLock<Catalog> = OnceLock::new();
+    let (cell, source) = match language {
+        Language::English => (&ENGLISH, include_str!("../../locales/en.json")),
+        Language::German => (&GERMAN, include_str!("../../locales/de.json")),
+    };
+    cell.get_or_init(|| serde_json::from_str(source).expect("built-in locale is valid JSON"))
+}
+
+/// Shows the GUI strings in `language` from the next lookup on.
+pub fn set_language(language: Language) {
+    let index = Language::all().iter().position(|&l| l == language).unwrap_or(0);
+    LANGUAGE.store(index, Ordering::Relaxed);
+}
+
+pub fn language() -> Language {
+    Language::all().get(LANGUAGE.load(Ordering::Relaxed)).copied().unwrap_or_default()
+}
+
+/// The string for `key` in the current language, in English if it has none
+/// and the key itself if English has none either.
+pub fn t(key: &'static str) -> &'static str {
+    let language = language();
+    if let Some(text) = catalog(language).get(key) {
+        return text;
+    }
+    if language != Language::English {
+        debug!("No {:?} string for {}", language, key);
+    }
+    catalog(Language::English).get(key).map_or(key, String::as_str)
+}
+
+/// `t(key)` with every `{name}` in it replaced by the value of `name` in
+/// `args`. Numbers are formatted by the caller.
+pub fn tf(key: &'static str, args: &[(&str, &dyn Display)]) -> String {
+    args.iter()
+        .fold(t(key).to_string(), |text, (name, value)| text.replace(&format!("{{{}}}", name), &value.to_string()))
+}
+
+/// Translation of an English enum `name()` shown in the GUI, looked up as
+/// `prefix.name`. English has none of these, it keeps `name`.
+pub fn t_name(prefix: &str, name: &'static str) -> &'static str {
+    let language = language();
+    if language == Language::English {
+        return name;
+    }
+    catalog(language).get(&format!("{}.{}", prefix, name)).map_or(name, String::as_str)
+}
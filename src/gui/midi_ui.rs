@@ -1,137 +1,568 @@
 use super::app::PlotApp;
-use crate::config::{MidiOutputMethod, MusicalScale};
+use super::i18n::{t, t_name, tf};
+use super::toast::Toasts;
+use super::widgets::{accessible_name, config_label, lockable};
+use crate::config::midi::MAX_BANK;
+use crate::config::{
+    AppConfig, LfoMix, LfoWaveform, MetronomeClick, MidiMapping, MidiOutputMethod, MidiUpdateMode, MorphSource, MusicalScale,
+    OscConfig, PatchBinding, PatchGesture, PatchSlot, RequantizeMode, VoicePolicy, NUM_ZONES,
+};
+use crate::lfo::TapTempo;
+use crate::metronome::Metronome;
+use crate::looper::{LooperHandle, LooperState};
+use crate::midi::{list_output_ports, MidiOutputState, SustainPedal};
+use crate::midi_histogram::{HistogramKind, HistogramSummary, MidiHistograms, HISTOGRAM_WINDOW};
+use crate::patch::PatchSelector;
+use crate::sync::LockExt;
 use eframe::egui;
+use egui_plot::{Bar, BarChart, Plot};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Time between repaints while the looper position moves
+const LOOPER_REPAINT_INTERVAL: Duration = Duration::from_millis(100);
+/// How often the histograms of sent values are redrawn
+const HISTOGRAM_REPAINT_INTERVAL: Duration = Duration::from_millis(500);
+/// Height of each histogram's bar chart
+const HISTOGRAM_HEIGHT: f32 = 120.0;
 
 pub fn render_midi_tab(app: &mut PlotApp, ui: &mut egui::Ui, ctx: &egui::Context) {
-    ui.heading("MIDI Configuration");
+    ui.heading(t("midi.heading"));
 
     egui::ScrollArea::vertical().show(ui, |ui| {
-        let mut app_config = app.app_config.lock().unwrap();
+        let mut app_config = app.app_config.lock_or_recover();
         let mut config_changed = false;
 
-        ui.group(|ui| {
-            ui.label("Output Method");
-            ui.horizontal(|ui| {
-                config_changed |= ui
-                    .radio_value(
-                        &mut app_config.midi.method,
-                        MidiOutputMethod::ControlChange,
-                        "Control Change Messages",
-                    )
-                    .changed();
-                config_changed |= ui
-                    .radio_value(
-                        &mut app_config.midi.method,
-                        MidiOutputMethod::Notes,
-                        "Note On/Off Messages",
-                    )
-                    .changed();
+        let midi_ports = app.midi_ports.get_or_insert_with(list_output_ports);
+        lockable(ui, app.locked, &mut app.toasts, |ui| {
+            render_output_port_settings(&mut app_config, midi_ports, ui, &mut config_changed);
+            ui.separator();
+
+            ui.group(|ui| {
+                config_label(ui, &app_config, t("midi.method"), "midi.method");
+                ui.horizontal(|ui| {
+                    config_changed |= ui
+                        .radio_value(
+                            &mut app_config.midi.method,
+                            MidiOutputMethod::ControlChange,
+                            t("midi.method.control_change"),
+                        )
+                        .changed();
+                    config_changed |= ui
+                        .radio_value(
+                            &mut app_config.midi.method,
+                            MidiOutputMethod::Notes,
+                            t("midi.method.notes"),
+                        )
+                        .changed();
+                });
+                ui.horizontal(|ui| {
+                    config_label(ui, &app_config, t("midi.update"), "midi.update.mode");
+                    config_changed |= ui
+                        .radio_value(&mut app_config.midi.update.mode, MidiUpdateMode::PerSample, t("midi.update.per_sample"))
+                        .on_hover_text(t("midi.update.per_sample.hover"))
+                        .changed();
+                    config_changed |= ui
+                        .radio_value(&mut app_config.midi.update.mode, MidiUpdateMode::FixedRate, t("midi.update.fixed_rate"))
+                        .on_hover_text(t("midi.update.fixed_rate.hover"))
+                        .changed();
+                    if app_config.midi.update.mode == MidiUpdateMode::FixedRate {
+                        let label = config_label(ui, &app_config, t("midi.update.rate"), "midi.update.rate_hz");
+                        config_changed |= ui
+                            .add(egui::DragValue::new(&mut app_config.midi.update.rate_hz).range(1.0..=1000.0).speed(1.0))
+                            .labelled_by(label.id)
+                            .changed();
+                    }
+                });
             });
-        });
 
-        ui.separator();
+            ui.separator();
 
-        match app_config.midi.method {
-            MidiOutputMethod::ControlChange => {
-                render_control_change_settings(&mut app_config, ui, &mut config_changed);
+            match app_config.midi.method {
+                MidiOutputMethod::ControlChange => {
+                    render_control_change_settings(&mut app_config, ui, &mut config_changed);
+                    ui.separator();
+                    render_lfo_settings(&mut app_config, &mut app.tap_tempo, ui, &mut config_changed);
+                }
+                MidiOutputMethod::Notes => {
+                    render_note_settings(&mut app_config, ui, &mut config_changed);
+                }
             }
-            MidiOutputMethod::Notes => {
-                render_note_settings(&mut app_config, ui, &mut config_changed);
+
+            ui.separator();
+            render_peak_hold_settings(&mut app_config, ui, &mut config_changed);
+
+            ui.separator();
+            render_morph_settings(&mut app_config, ui, &mut config_changed);
+
+            ui.separator();
+            render_osc_settings(&mut app_config, ui, &mut config_changed);
+            ui.separator();
+            render_artnet_settings(&mut app_config, ui, &mut config_changed);
+
+            #[cfg(feature = "synth")]
+            {
+                ui.separator();
+                render_synth_settings(&mut app_config, ui, &mut config_changed);
+            }
+
+            #[cfg(all(target_os = "linux", feature = "gamepad"))]
+            {
+                ui.separator();
+                render_gamepad_settings(&mut app_config, ui, &mut config_changed);
             }
+
+            #[cfg(feature = "keys")]
+            {
+                ui.separator();
+                render_key_settings(&mut app_config, ui, &mut config_changed);
+            }
+        });
+
+        if let Some(looper) = &app.looper {
+            ui.separator();
+            render_looper(&mut app_config, looper, app.locked, &mut app.toasts, ui, &mut config_changed);
+            if matches!(
+                looper.inspect(|looper| looper.state()),
+                LooperState::Recording | LooperState::Playing | LooperState::Overdubbing
+            ) {
+                ctx.request_repaint_after(LOOPER_REPAINT_INTERVAL);
+            }
+        }
+
+        if let Some(metronome) = &app.metronome {
+            ui.separator();
+            render_metronome(&mut app_config, metronome, app.locked, &mut app.toasts, ui, &mut config_changed);
+            if metronome.is_running() {
+                // Follows the beat count
+                ctx.request_repaint_after(app_config.midi.metronome.beat_length() / 2);
+            }
+        }
+
+        ui.separator();
+        render_patches(
+            &mut app_config,
+            &app.patches,
+            &app.midi_output,
+            app.locked,
+            &mut app.toasts,
+            ui,
+            &mut config_changed,
+        );
+
+        if let Some(midi_histograms) = &app.midi_histograms {
+            ui.separator();
+            render_histograms(midi_histograms, &mut app.histogram_zone, ui);
+            ctx.request_repaint_after(HISTOGRAM_REPAINT_INTERVAL);
         }
 
+        ui.separator();
+        render_sustain(&mut app_config, &app.sustain, app.locked, &mut app.toasts, ui, &mut config_changed);
+        // A gesture can press the pedal too
+        ctx.request_repaint_after(Duration::from_millis(250));
+
+        ui.label(tf("midi.status", &[("status", &app.midi_output.lock_or_recover().status())]));
+
         // Save config if any changes were made
         if config_changed {
-            if let Err(e) = app_config.save_to_file() {
-                eprintln!("Failed to save app config: {}", e);
-            }
+            app_config.mark_dirty();
             ctx.request_repaint();
         }
     });
 }
 
+fn render_output_port_settings(
+    app_config: &mut crate::config::AppConfig,
+    midi_ports: &mut Vec<String>,
+    ui: &mut egui::Ui,
+    config_changed: &mut bool,
+) {
+    ui.group(|ui| {
+        let label = config_label(ui, app_config, t("midi.output_port"), "midi.output_port");
+
+        ui.horizontal(|ui| {
+            ui.add_enabled_ui(!app_config.midi.virtual_port, |ui| {
+                let selected = app_config.midi.output_port.as_deref().unwrap_or(t("midi.output_port.none"));
+                egui::ComboBox::from_id_source("midi_output_port")
+                    .selected_text(selected)
+                    .show_ui(ui, |ui| {
+                        for port in midi_ports.iter() {
+                            *config_changed |= ui
+                                .selectable_value(
+                                    &mut app_config.midi.output_port,
+                                    Some(port.clone()),
+                                    port,
+                                )
+                                .changed();
+                        }
+                    })
+                    .response
+                    .labelled_by(label.id);
+            });
+            if ui.button(t("midi.output_port.refresh")).clicked() {
+                *midi_ports = list_output_ports();
+            }
+        });
+
+        if cfg!(unix) {
+            *config_changed |= ui
+                .checkbox(&mut app_config.midi.virtual_port, t("midi.virtual_port"))
+                .changed();
+        }
+        ui.label(t("midi.output_port.text"));
+    });
+}
+
 fn render_control_change_settings(
     app_config: &mut crate::config::AppConfig,
     ui: &mut egui::Ui,
     config_changed: &mut bool,
 ) {
     ui.group(|ui| {
-        ui.label("Control Change Settings");
+        ui.label(t("midi.control_change"));
 
         ui.horizontal(|ui| {
-            ui.label("Base Control Number:");
+            let label = config_label(ui, app_config, t("midi.control_change_config.base_control_number"), "midi.control_change_config.base_control_number");
             *config_changed |= ui
                 .add(egui::Slider::new(
                     &mut app_config.midi.control_change_config.base_control_number,
                     0..=127,
                 ))
+                .labelled_by(label.id)
                 .changed();
         });
 
         ui.horizontal(|ui| {
-            ui.label("Control Slope:");
+            let label = config_label(ui, app_config, t("midi.control_change_config.control_slope"), "midi.control_change_config.control_slope");
             *config_changed |= ui
                 .add(
                     egui::DragValue::new(&mut app_config.midi.control_change_config.control_slope)
                         .range(0.1..=100.0)
                         .speed(0.1),
                 )
+                .labelled_by(label.id)
+                .changed();
+        });
+
+        ui.label(t("midi.control_change.text1"));
+        ui.label(t("midi.control_change.text2"));
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("midi.cc_ramp.enabled"), "midi.cc_ramp.enabled");
+            *config_changed |= ui.checkbox(&mut app_config.midi.cc_ramp.enabled, "").labelled_by(label.id).changed();
+        });
+
+        ui.add_enabled_ui(app_config.midi.cc_ramp.enabled, |ui| {
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("midi.cc_ramp.min_step"), "midi.cc_ramp.min_step");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.midi.cc_ramp.min_step).range(0..=127))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("midi.cc_ramp.ramp_ms"), "midi.cc_ramp.ramp_ms");
+                *config_changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut app_config.midi.cc_ramp.ramp_ms)
+                            .range(1.0..=10000.0)
+                            .speed(10.0)
+                            .suffix(" ms"),
+                    )
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            ui.label(t("midi.cc_ramp.text"));
+        });
+    });
+}
+
+fn render_peak_hold_settings(app_config: &mut AppConfig, ui: &mut egui::Ui, config_changed: &mut bool) {
+    ui.group(|ui| {
+        ui.label(t("midi.peak"));
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("enabled"), "midi.peak.enabled");
+            *config_changed |= ui.checkbox(&mut app_config.midi.peak.enabled, "").labelled_by(label.id).changed();
+        });
+
+        ui.add_enabled_ui(app_config.midi.peak.enabled, |ui| {
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("midi.peak.hold_ms"), "midi.peak.hold_ms");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.midi.peak.hold_ms).range(0.0..=10000.0).speed(10.0))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("midi.peak.decay_per_sec"), "midi.peak.decay_per_sec");
+                *config_changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut app_config.midi.peak.decay_per_sec)
+                            .range(0.01..=100.0)
+                            .speed(0.01),
+                    )
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("midi.peak.send_cc"), "midi.peak.send_cc");
+                *config_changed |= ui.checkbox(&mut app_config.midi.peak.send_cc, "").labelled_by(label.id).changed();
+            });
+
+            ui.add_enabled_ui(app_config.midi.peak.send_cc, |ui| {
+                ui.horizontal(|ui| {
+                    let label = config_label(ui, app_config, t("midi.peak.peak_cc_offset"), "midi.peak.peak_cc_offset");
+                    *config_changed |= ui
+                        .add(egui::Slider::new(&mut app_config.midi.peak.peak_cc_offset, 1..=127))
+                        .labelled_by(label.id)
+                        .changed();
+                });
+            });
+        });
+
+        ui.label(t("midi.peak.text1"));
+        ui.label(t("midi.peak.text2"));
+    });
+}
+
+fn render_lfo_settings(
+    app_config: &mut AppConfig,
+    tap_tempo: &mut TapTempo,
+    ui: &mut egui::Ui,
+    config_changed: &mut bool,
+) {
+    ui.group(|ui| {
+        ui.label(t("midi.lfo"));
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("enabled"), "midi.lfo.enabled");
+            *config_changed |= ui.checkbox(&mut app_config.midi.lfo.enabled, "").labelled_by(label.id).changed();
+        });
+
+        ui.add_enabled_ui(app_config.midi.lfo.enabled, |ui| {
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("midi.lfo.waveform"), "midi.lfo.waveform");
+                egui::ComboBox::from_id_source("lfo_waveform")
+                    .selected_text(t_name("lfo_waveform", app_config.midi.lfo.waveform.name()))
+                    .show_ui(ui, |ui| {
+                        for waveform in LfoWaveform::all() {
+                            *config_changed |= ui
+                                .selectable_value(&mut app_config.midi.lfo.waveform, *waveform, t_name("lfo_waveform", waveform.name()))
+                                .changed();
+                        }
+                    })
+                    .response
+                    .labelled_by(label.id);
+                config_label(ui, app_config, t("midi.lfo.mix"), "midi.lfo.mix");
+                *config_changed |= ui.radio_value(&mut app_config.midi.lfo.mix, LfoMix::Add, t("midi.lfo.mix.add")).changed();
+                *config_changed |= ui
+                    .radio_value(&mut app_config.midi.lfo.mix, LfoMix::Multiply, t("midi.lfo.mix.multiply"))
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("midi.lfo.sync"), "midi.lfo.sync");
+                *config_changed |= ui.checkbox(&mut app_config.midi.lfo.sync, "").labelled_by(label.id).changed();
+                if app_config.midi.lfo.sync {
+                    let label = config_label(ui, app_config, t("midi.lfo.beats_per_cycle"), "midi.lfo.beats_per_cycle");
+                    *config_changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut app_config.midi.lfo.beats_per_cycle)
+                                .range(0.25..=64.0)
+                                .speed(0.25),
+                        )
+                        .labelled_by(label.id)
+                        .changed();
+                    let label = config_label(ui, app_config, t("midi.looper.tempo_bpm"), "midi.looper.tempo_bpm");
+                    *config_changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut app_config.midi.looper.tempo_bpm)
+                                .range(20.0..=300.0)
+                                .speed(0.5)
+                                .fixed_decimals(1),
+                        )
+                        .on_hover_text(t("midi.looper.tempo_bpm.hover"))
+                        .labelled_by(label.id)
+                        .changed();
+                    if ui.button(t("midi.lfo.tap")).clicked() {
+                        if let Some(tempo) = tap_tempo.tap(std::time::Instant::now()) {
+                            app_config.midi.looper.tempo_bpm = tempo.clamp(20.0, 300.0);
+                            *config_changed = true;
+                        }
+                    }
+                } else {
+                    let label = config_label(ui, app_config, t("midi.lfo.rate_hz"), "midi.lfo.rate_hz");
+                    *config_changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut app_config.midi.lfo.rate_hz)
+                                .range(0.01..=100.0)
+                                .speed(0.01)
+                                .fixed_decimals(2),
+                        )
+                        .labelled_by(label.id)
+                        .changed();
+                }
+            });
+
+            egui::Grid::new("lfo_zones").show(ui, |ui| {
+                for zone in 0..NUM_ZONES {
+                    let zone_config = &mut app_config.midi.lfo.zones[zone];
+                    *config_changed |= ui.checkbox(&mut zone_config.enabled, tf("zone_n", &[("zone", &zone)])).changed();
+                    let depth = ui.add_enabled(
+                        zone_config.enabled,
+                        egui::Slider::new(&mut zone_config.depth, 0.0..=1.0).text(t("midi.lfo.depth")),
+                    );
+                    *config_changed |= accessible_name(depth, tf("midi.lfo.depth.accessible", &[("zone", &zone)])).changed();
+                    ui.end_row();
+                }
+            });
+        });
+
+        ui.label(t("midi.lfo.text1"));
+        ui.label(t("midi.lfo.text2"));
+    });
+}
+
+fn render_morph_settings(app_config: &mut AppConfig, ui: &mut egui::Ui, config_changed: &mut bool) {
+    ui.group(|ui| {
+        ui.label(t("midi.morph"));
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("enabled"), "midi.morph.enabled");
+            *config_changed |= ui
+                .checkbox(&mut app_config.midi.morph.enabled, "")
+                .on_hover_text(t("midi.morph.enabled.hover"))
+                .labelled_by(label.id)
                 .changed();
         });
 
-        ui.label("Control Change mode sends MIDI CC messages for each zone.");
-        ui.label("Zone 0 uses base control number, zone 1 uses base+1, etc.");
+        let mapping = app_config.midi.mapping();
+        for (name, snapshot) in [("A", &mut app_config.midi.morph.a), ("B", &mut app_config.midi.morph.b)] {
+            ui.horizontal(|ui| {
+                if ui.button(tf("midi.morph.store", &[("snapshot", &name)])).clicked() {
+                    *snapshot = mapping.clone();
+                    *config_changed = true;
+                }
+                ui.label(format!("{}: {}", name, describe_mapping(snapshot)));
+            });
+        }
+
+        ui.add_enabled_ui(app_config.midi.morph.enabled, |ui| {
+            ui.horizontal(|ui| {
+                config_label(ui, app_config, t("midi.morph.source"), "midi.morph.source");
+                let morph = &mut app_config.midi.morph;
+                *config_changed |= ui.radio_value(&mut morph.source, MorphSource::Manual, t("midi.morph.source.manual")).changed();
+                *config_changed |= ui
+                    .radio_value(&mut morph.source, MorphSource::Pressure, t("midi.morph.source.pressure"))
+                    .on_hover_text(t("midi.morph.source.pressure.hover"))
+                    .changed();
+            });
+            match app_config.midi.morph.source {
+                MorphSource::Manual => {
+                    ui.horizontal(|ui| {
+                        let label = config_label(ui, app_config, t("midi.morph.position"), "midi.morph.position");
+                        *config_changed |= ui
+                            .add(egui::Slider::new(&mut app_config.midi.morph.position, 0.0..=1.0).show_value(false))
+                            .labelled_by(label.id)
+                            .changed();
+                        ui.label("B");
+                    });
+                }
+                MorphSource::Pressure => {
+                    ui.horizontal(|ui| {
+                        let label = config_label(ui, app_config, t("midi.morph.pressure_slope"), "midi.morph.pressure_slope");
+                        *config_changed |= ui
+                            .add(
+                                egui::DragValue::new(&mut app_config.midi.morph.pressure_slope)
+                                    .range(0.1..=100.0)
+                                    .speed(0.1),
+                            )
+                            .on_hover_text(t("midi.morph.pressure_slope.hover"))
+                            .labelled_by(label.id)
+                            .changed();
+                    });
+                }
+            }
+        });
+        ui.label(t("midi.morph.text"));
     });
 }
 
+fn describe_mapping(mapping: &MidiMapping) -> String {
+    let cc = &mapping.control_change_config;
+    let note = &mapping.note_config;
+    let method = match mapping.method {
+        MidiOutputMethod::ControlChange => t("midi.morph.snapshot.cc"),
+        MidiOutputMethod::Notes => t("midi.morph.snapshot.notes"),
+    };
+    tf(
+        "midi.morph.snapshot",
+        &[
+            ("method", &method),
+            ("cc_slope", &format!("{:.1}", cc.control_slope)),
+            ("threshold", &format!("{:.3}", note.threshold)),
+            ("velocity_slope", &format!("{:.1}", note.velocity_slope)),
+            ("scale", &t_name("scale", note.scale.name())),
+            ("base_note", &note.base_note),
+            ("base_cc", &cc.base_control_number),
+        ],
+    )
+}
+
 fn render_note_settings(
     app_config: &mut crate::config::AppConfig,
     ui: &mut egui::Ui,
     config_changed: &mut bool,
 ) {
     ui.group(|ui| {
-        ui.label("Note Settings");
+        ui.label(t("midi.notes"));
 
         ui.horizontal(|ui| {
-            ui.label("Base Note:");
+            let label = config_label(ui, app_config, t("midi.note_config.base_note"), "midi.note_config.base_note");
             *config_changed |= ui
                 .add(egui::Slider::new(
                     &mut app_config.midi.note_config.base_note,
                     0..=127,
                 ))
+                .labelled_by(label.id)
                 .changed();
-            ui.label(format!(
-                "(MIDI note {})",
-                app_config.midi.note_config.base_note
-            ));
+            ui.label(tf("midi.note_config.base_note.value", &[("note", &app_config.midi.note_config.base_note)]));
         });
 
         ui.horizontal(|ui| {
-            ui.label("Threshold:");
+            let label = config_label(ui, app_config, t("midi.note_config.threshold"), "midi.note_config.threshold");
             *config_changed |= ui
                 .add(
                     egui::DragValue::new(&mut app_config.midi.note_config.threshold)
                         .range(0.001..=1.0)
                         .speed(0.001),
                 )
+                .labelled_by(label.id)
                 .changed();
         });
 
         ui.horizontal(|ui| {
-            ui.label("Velocity Slope:");
+            let label = config_label(ui, app_config, t("midi.note_config.velocity_slope"), "midi.note_config.velocity_slope");
             *config_changed |= ui
                 .add(
                     egui::DragValue::new(&mut app_config.midi.note_config.velocity_slope)
                         .range(1.0..=5000.0)
                         .speed(1.0),
                 )
+                .labelled_by(label.id)
                 .changed();
         });
 
         ui.horizontal(|ui| {
-            ui.label("Musical Scale:");
-            *config_changed |= egui::ComboBox::from_label("")
-                .selected_text(app_config.midi.note_config.scale.name())
+            let label = config_label(ui, app_config, t("midi.note_config.scale"), "midi.note_config.scale");
+            let scale = egui::ComboBox::from_id_source("musical_scale")
+                .selected_text(t_name("scale", app_config.midi.note_config.scale.name()))
                 .show_ui(ui, |ui| {
                     let mut scale_changed = false;
                     for scale in MusicalScale::all_scales() {
@@ -139,18 +570,1004 @@ fn render_note_settings(
                             .selectable_value(
                                 &mut app_config.midi.note_config.scale,
                                 *scale,
-                                scale.name(),
+                                t_name("scale", scale.name()),
                             )
                             .changed();
                     }
                     scale_changed
+                });
+            scale.response.labelled_by(label.id);
+            *config_changed |= scale.inner.unwrap_or(false);
+        });
+
+        ui.horizontal(|ui| {
+            config_label(ui, app_config, t("midi.note_config.octave_offsets"), "midi.note_config.octave_offsets");
+            let note_config = &mut app_config.midi.note_config;
+            for zone in 0..note_config.octave_offsets.len() {
+                let offset = ui
+                    .add(egui::DragValue::new(&mut note_config.octave_offsets[zone]).range(-10..=10))
+                    .on_hover_text(tf("midi.note_config.octave.hover", &[("zone", &zone), ("note", &note_config.zone_note(zone))]));
+                *config_changed |= accessible_name(offset, tf("midi.note_config.octave.accessible", &[("zone", &zone)])).changed();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("midi.note_config.channel_rotation"), "midi.note_config.channel_rotation");
+            let rotation = &mut app_config.midi.note_config.channel_rotation;
+            let mut enabled = rotation.is_some();
+            if ui
+                .checkbox(&mut enabled, "")
+                .on_hover_text(t("midi.note_config.channel_rotation.hover"))
+                .labelled_by(label.id)
+                .changed()
+            {
+                *rotation = enabled.then(|| (0..4).collect());
+                *config_changed = true;
+            }
+            if let Some(channels) = rotation {
+                // Channels join the rotation in the order they are picked;
+                // the last one can't be taken out
+                for channel in 0..16u8 {
+                    let mut member = channels.contains(&channel);
+                    let toggle = ui.toggle_value(&mut member, channel.to_string());
+                    let toggle = accessible_name(toggle, tf("midi.note_config.channel_rotation.accessible", &[("channel", &channel)]));
+                    if !toggle.changed() {
+                        continue;
+                    }
+                    if member {
+                        channels.push(channel);
+                        *config_changed = true;
+                    } else if channels.len() > 1 {
+                        channels.retain(|&c| c != channel);
+                        *config_changed = true;
+                    }
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            config_label(ui, app_config, t("midi.requantize"), "midi.requantize");
+            *config_changed |= ui
+                .radio_value(&mut app_config.midi.requantize, RequantizeMode::Retrigger, t("midi.requantize.retrigger"))
+                .on_hover_text(t("midi.requantize.retrigger.hover"))
+                .changed();
+            *config_changed |= ui
+                .radio_value(&mut app_config.midi.requantize, RequantizeMode::Immediate, t("midi.requantize.immediate"))
+                .on_hover_text(t("midi.requantize.immediate.hover"))
+                .changed();
+        });
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("midi.retrigger_shared_notes"), "midi.retrigger_shared_notes");
+            *config_changed |= ui
+                .checkbox(&mut app_config.midi.retrigger_shared_notes, "")
+                .on_hover_text(t("midi.retrigger_shared_notes.hover"))
+                .labelled_by(label.id)
+                .changed();
+        });
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("midi.mpe.enabled"), "midi.mpe.enabled");
+            let mpe = &mut app_config.midi.mpe;
+            *config_changed |= ui
+                .checkbox(&mut mpe.enabled, "")
+                .on_hover_text(t("midi.mpe.enabled.hover"))
+                .labelled_by(label.id)
+                .changed();
+            if !mpe.enabled {
+                return;
+            }
+            let label = ui.label(t("midi.mpe.member_channels"));
+            *config_changed |= ui
+                .add(egui::DragValue::new(&mut mpe.member_channels).range(1..=14))
+                .on_hover_text(t("midi.mpe.member_channels.hover"))
+                .labelled_by(label.id)
+                .changed();
+            ui.label(t("midi.mpe.voice_policy"));
+            let policies = [
+                (
+                    VoicePolicy::StealOldest,
+                    "midi.mpe.voice_policy.steal_oldest",
+                    "midi.mpe.voice_policy.steal_oldest.hover",
+                ),
+                (
+                    VoicePolicy::StealQuietest,
+                    "midi.mpe.voice_policy.steal_quietest",
+                    "midi.mpe.voice_policy.steal_quietest.hover",
+                ),
+                (VoicePolicy::RefuseNew, "midi.mpe.voice_policy.refuse_new", "midi.mpe.voice_policy.refuse_new.hover"),
+            ];
+            for (policy, name, hover) in policies {
+                *config_changed |= ui
+                    .radio_value(&mut mpe.voice_policy, policy, t(name))
+                    .on_hover_text(t(hover))
+                    .changed();
+            }
+        });
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("midi.pressure.min_interval_ms"), "midi.pressure.min_interval_ms");
+            let pressure = &mut app_config.midi.pressure;
+            *config_changed |= ui
+                .add(egui::DragValue::new(&mut pressure.min_interval_ms).range(0.0..=1000.0).suffix(" ms"))
+                .on_hover_text(t("midi.pressure.min_interval_ms.hover"))
+                .labelled_by(label.id)
+                .changed();
+            let label = ui.label(t("midi.pressure.min_delta"));
+            *config_changed |= ui
+                .add(egui::DragValue::new(&mut pressure.min_delta).range(0..=127))
+                .on_hover_text(t("midi.pressure.min_delta.hover"))
+                .labelled_by(label.id)
+                .changed();
+            *config_changed |= ui
+                .checkbox(&mut pressure.increase_only, t("midi.pressure.increase_only"))
+                .on_hover_text(t("midi.pressure.increase_only.hover"))
+                .changed();
+        });
+
+        ui.label(t("midi.notes.text1"));
+        ui.label(t("midi.notes.text2"));
+        ui.label(t("midi.notes.text3"));
+    });
+}
+/// Looper settings, locked like the others, and its transport, which is
+/// never locked since it changes no settings
+fn render_looper(
+    app_config: &mut AppConfig,
+    looper: &LooperHandle,
+    locked: bool,
+    toasts: &mut Toasts,
+    ui: &mut egui::Ui,
+    config_changed: &mut bool,
+) {
+    ui.group(|ui| {
+        ui.label(t("midi.looper"));
+
+        lockable(ui, locked, toasts, |ui| {
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("midi.looper.bars"), "midi.looper.bars");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.midi.looper.bars).range(0..=64))
+                    .on_hover_text(t("midi.looper.bars.hover"))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("midi.looper.beats_per_bar"), "midi.looper.beats_per_bar");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.midi.looper.beats_per_bar).range(1..=16))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("midi.looper.tempo_bpm"), "midi.looper.tempo_bpm");
+                *config_changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut app_config.midi.looper.tempo_bpm)
+                            .range(20.0..=300.0)
+                            .speed(0.5)
+                            .fixed_decimals(1),
+                    )
+                    .labelled_by(label.id)
+                    .changed();
+            });
+        });
+
+        let now = Instant::now();
+        let (state, length, position, notes) =
+            looper.inspect(|looper| (looper.state(), looper.length(), looper.position(now), looper.notes()));
+        ui.horizontal(|ui| {
+            let record_label = if state == LooperState::Recording { t("midi.looper.finish") } else { t("midi.looper.record") };
+            if ui.button(record_label).clicked() {
+                let length = app_config.midi.looper.loop_length();
+                looper.control(|looper| looper.record(now, length));
+            }
+            let playing = matches!(state, LooperState::Playing | LooperState::Overdubbing);
+            if ui
+                .add_enabled(playing || state == LooperState::Stopped, egui::Button::new(if playing { t("midi.looper.stop") } else { t("midi.looper.play") }))
+                .clicked()
+            {
+                looper.control(|looper| if playing { looper.stop() } else { looper.play(now) });
+            }
+            if ui
+                .add_enabled(playing, egui::SelectableLabel::new(state == LooperState::Overdubbing, t("midi.looper.overdub")))
+                .clicked()
+            {
+                looper.control(|looper| looper.overdub());
+            }
+            if ui.add_enabled(state != LooperState::Empty, egui::Button::new(t("midi.looper.clear"))).clicked() {
+                looper.control(|looper| looper.clear());
+            }
+        });
+
+        let length = match length {
+            Some(length) => format!("{:.1} s", length.as_secs_f64()),
+            None => t("midi.looper.open").to_string(),
+        };
+        let config = &app_config.midi.looper;
+        ui.label(tf(
+            "midi.looper.status",
+            &[
+                ("state", &looper_state_name(state)),
+                ("position", &format!("{:.1}", position.as_secs_f64())),
+                ("length", &length),
+                ("notes", &notes),
+                ("tempo", &format!("{:.1}", config.tempo_bpm)),
+                ("beats", &config.beats_per_bar),
+            ],
+        ));
+        if app_config.midi.method != MidiOutputMethod::Notes {
+            ui.label(t("midi.looper.notes_only"));
+        }
+    });
+}
+
+fn render_metronome(
+    app_config: &mut AppConfig,
+    metronome: &Metronome,
+    locked: bool,
+    toasts: &mut Toasts,
+    ui: &mut egui::Ui,
+    config_changed: &mut bool,
+) {
+    ui.group(|ui| {
+        ui.horizontal(|ui| {
+            ui.label(t("midi.metronome"));
+            // The transport changes no settings, so the lock leaves it alone
+            let running = metronome.is_running();
+            if ui
+                .button(if running { t("midi.metronome.stop") } else { t("midi.metronome.start") })
+                .on_hover_text(t("midi.metronome.start.hover"))
+                .clicked()
+            {
+                metronome.toggle();
+            }
+            if running {
+                ui.label(tf(
+                    "midi.metronome.beat",
+                    &[("beat", &(metronome.beat() + 1)), ("beats", &app_config.midi.metronome.beats_per_bar)],
+                ));
+            }
+        });
+
+        lockable(ui, locked, toasts, |ui| {
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("midi.metronome.tempo_bpm"), "midi.metronome.tempo_bpm");
+                *config_changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut app_config.midi.metronome.tempo_bpm)
+                            .range(20.0..=400.0)
+                            .speed(0.5)
+                            .fixed_decimals(1),
+                    )
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("midi.looper.beats_per_bar"), "midi.metronome.beats_per_bar");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.midi.metronome.beats_per_bar).range(1..=16))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+            ui.horizontal(|ui| {
+                config_label(ui, app_config, t("midi.metronome.click"), "midi.metronome.click");
+                for click in MetronomeClick::all() {
+                    *config_changed |= ui
+                        .radio_value(&mut app_config.midi.metronome.click, *click, t_name("metronome_click", click.name()))
+                        .changed();
+                }
+                let number_key = match app_config.midi.metronome.click {
+                    MetronomeClick::Note => "midi.metronome.note",
+                    MetronomeClick::ControlChange => "midi.metronome.control",
+                };
+                let label = config_label(ui, app_config, t(number_key), "midi.metronome.number");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.midi.metronome.number).range(0..=127))
+                    .labelled_by(label.id)
+                    .changed();
+                // Shown as MIDI channels 1-16
+                let label = config_label(ui, app_config, t("midi.metronome.channel"), "midi.metronome.channel");
+                let mut channel = app_config.midi.metronome.channel + 1;
+                if ui
+                    .add(egui::DragValue::new(&mut channel).range(1..=16))
+                    .on_hover_text(t("midi.metronome.channel.hover"))
+                    .labelled_by(label.id)
+                    .changed()
+                {
+                    app_config.midi.metronome.channel = channel - 1;
+                    *config_changed = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("midi.metronome.velocity"), "midi.metronome.velocity");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.midi.metronome.velocity).range(1..=127))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("midi.metronome.accent_velocity"), "midi.metronome.accent_velocity");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.midi.metronome.accent_velocity).range(1..=127))
+                    .on_hover_text(t("midi.metronome.accent_velocity.hover"))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("midi.metronome.click_ms"), "midi.metronome.click_ms");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.midi.metronome.click_ms).range(1.0..=1000.0).speed(1.0))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+        });
+    });
+}
+
+/// The looper state as shown in the status line
+fn looper_state_name(state: LooperState) -> &'static str {
+    match state {
+        LooperState::Empty => t("midi.looper.state.empty"),
+        LooperState::Recording => t("midi.looper.state.recording"),
+        LooperState::Playing => t("midi.looper.state.playing"),
+        LooperState::Overdubbing => t("midi.looper.state.overdubbing"),
+        LooperState::Stopped => t("midi.looper.state.stopped"),
+    }
+}
+
+/// Patch slots, locked like other settings, and their Send buttons, which
+/// are never locked since they change no settings
+fn render_patches(
+    app_config: &mut AppConfig,
+    patches: &PatchSelector,
+    midi_output: &Mutex<MidiOutputState>,
+    locked: bool,
+    toasts: &mut Toasts,
+    ui: &mut egui::Ui,
+    config_changed: &mut bool,
+) {
+    ui.group(|ui| {
+        ui.label(t("midi.patches"));
+
+        lockable(ui, locked, toasts, |ui| {
+            let config = &mut app_config.midi.patches;
+            ui.horizontal(|ui| {
+                let label = ui.label(t("midi.patches.channel"));
+                // Stored from 0 like on the wire, shown from 1 like on synths
+                let mut channel = config.channel + 1;
+                if ui.add(egui::DragValue::new(&mut channel).range(1..=16)).labelled_by(label.id).changed() {
+                    config.channel = channel - 1;
+                    *config_changed = true;
+                }
+                ui.label(t("midi.patches.next"));
+                *config_changed |= render_patch_binding(ui, "patch_next", t("midi.patches.next.accessible"), &mut config.next);
+            });
+
+            let mut remove = None;
+            egui::Grid::new("patch_slots").striped(true).show(ui, |ui| {
+                ui.label(t("midi.patches.name"));
+                ui.label(t("midi.patches.bank"));
+                ui.label(t("midi.patches.program"));
+                ui.label(t("midi.patches.send_on"));
+                ui.end_row();
+
+                for (i, slot) in config.slots.iter_mut().enumerate() {
+                    let patch = i + 1;
+                    let name = ui.add(egui::TextEdit::singleline(&mut slot.name).desired_width(120.0));
+                    *config_changed |= accessible_name(name, tf("midi.patches.name.accessible", &[("patch", &patch)])).changed();
+                    ui.horizontal(|ui| {
+                        let mut has_bank = slot.bank.is_some();
+                        let send_bank = ui.checkbox(&mut has_bank, "");
+                        if accessible_name(send_bank, tf("midi.patches.has_bank.accessible", &[("patch", &patch)])).changed() {
+                            slot.bank = has_bank.then_some(0);
+                            *config_changed = true;
+                        }
+                        if let Some(bank) = &mut slot.bank {
+                            let bank = ui.add(egui::DragValue::new(bank).range(0..=MAX_BANK));
+                            *config_changed |= accessible_name(bank, tf("midi.patches.bank.accessible", &[("patch", &patch)])).changed();
+                        }
+                    });
+                    let program = ui.add(egui::DragValue::new(&mut slot.program).range(0..=127));
+                    *config_changed |= accessible_name(program, tf("midi.patches.program.accessible", &[("patch", &patch)])).changed();
+                    ui.horizontal(|ui| {
+                        let name = tf("midi.patches.send_on.accessible", &[("patch", &patch)]);
+                        *config_changed |= render_patch_binding(ui, ("patch_binding", i), &name, &mut slot.binding);
+                    });
+                    if ui.button(t("remove")).clicked() {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+            if let Some(i) = remove {
+                config.slots.remove(i);
+                *config_changed = true;
+            }
+            if ui.button(t("midi.patches.add")).clicked() {
+                config.slots.push(PatchSlot {
+                    name: tf("midi.patches.default_name", &[("patch", &(config.slots.len() + 1))]),
+                    bank: None,
+                    program: 0,
+                    binding: None,
+                });
+                *config_changed = true;
+            }
+        });
+
+        let config = &app_config.midi.patches;
+        let current = patches.current(config);
+        let mut send = None;
+        let mut send_next = false;
+        ui.horizontal_wrapped(|ui| {
+            for (i, slot) in config.slots.iter().enumerate() {
+                if ui.selectable_label(current == Some(i), &slot.name).clicked() {
+                    send = Some(i);
+                }
+            }
+            if !config.slots.is_empty() && ui.button(t("midi.patches.send_next")).clicked() {
+                send_next = true;
+            }
+        });
+        if send.is_some() || send_next {
+            let mut midi_output = midi_output.lock_or_recover();
+            if let Some(sink) = midi_output.connection() {
+                let result = match send {
+                    Some(i) => patches.send(sink, config, i),
+                    None => patches.send_next(sink, config),
+                };
+                if let Err(e) = result {
+                    midi_output.connection_lost(&e);
+                }
+            } else {
+                toasts.push(t("midi.no_output"));
+            }
+        }
+        if config.slots.is_empty() {
+            ui.label(t("midi.patches.empty"));
+        }
+    });
+}
+
+/// Bar charts of the velocities and pressures sent in the last minute, to
+/// help choose `velocity_slope`; never locked since they change no settings
+fn render_histograms(midi_histograms: &MidiHistograms, zone: &mut Option<usize>, ui: &mut egui::Ui) {
+    ui.group(|ui| {
+        ui.horizontal(|ui| {
+            let label = ui.label(tf("midi.histograms", &[("secs", &HISTOGRAM_WINDOW.as_secs())]));
+            egui::ComboBox::from_id_source("histogram_zone")
+                .selected_text(zone.map_or(t("all_zones").to_string(), |zone| tf("zone_n", &[("zone", &zone)])))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(zone, None, t("all_zones"));
+                    for index in 0..NUM_ZONES {
+                        ui.selectable_value(zone, Some(index), tf("zone_n", &[("zone", &index)]));
+                    }
                 })
-                .inner
-                .unwrap_or(false);
+                .response
+                .labelled_by(label.id);
+            if ui.button(t("reset")).clicked() {
+                midi_histograms.reset();
+            }
+        });
+        ui.columns(2, |columns| {
+            let kinds = [(HistogramKind::Velocity, t("midi.histograms.velocity")), (HistogramKind::Pressure, t("midi.histograms.pressure"))];
+            for (ui, (kind, title)) in columns.iter_mut().zip(kinds) {
+                let counts = midi_histograms.counts(kind, *zone);
+                let summary = HistogramSummary::of(&counts);
+                ui.label(tf(
+                    "midi.histograms.summary",
+                    &[
+                        ("title", &title),
+                        ("total", &summary.total),
+                        ("clipped", &summary.clipped),
+                        ("bottomed", &summary.bottomed),
+                    ],
+                ));
+                let bars = counts
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &count)| count > 0)
+                    .map(|(value, &count)| Bar::new(value as f64, f64::from(count)).width(1.0))
+                    .collect();
+                Plot::new(("histogram", title))
+                    .height(HISTOGRAM_HEIGHT)
+                    .include_x(0.0)
+                    .include_x(127.0)
+                    .include_y(0.0)
+                    .allow_drag(false)
+                    .allow_zoom(false)
+                    .allow_scroll(false)
+                    .show(ui, |plot_ui| plot_ui.bar_chart(BarChart::new(bars)));
+            }
+        });
+    });
+}
+
+/// The sustain pedal, never locked like the patch Send buttons, and its
+/// gesture binding
+fn render_sustain(
+    app_config: &mut AppConfig,
+    sustain: &SustainPedal,
+    locked: bool,
+    toasts: &mut Toasts,
+    ui: &mut egui::Ui,
+    config_changed: &mut bool,
+) {
+    ui.group(|ui| {
+        ui.label(t("midi.sustain"));
+        ui.horizontal(|ui| {
+            let mut down = sustain.is_down();
+            if ui
+                .toggle_value(&mut down, t("midi.sustain.pedal"))
+                .on_hover_text(t("midi.sustain.pedal.hover"))
+                .changed()
+            {
+                sustain.set(down);
+            }
+            lockable(ui, locked, toasts, |ui| {
+                ui.label(t("midi.sustain.toggle"));
+                *config_changed |= render_patch_binding(ui, "sustain_toggle", t("midi.sustain.toggle.accessible"), &mut app_config.midi.sustain_toggle);
+            });
+        });
+        if app_config.midi.method != MidiOutputMethod::Notes {
+            ui.label(t("midi.sustain.notes_only"));
+        }
+    });
+}
+
+/// A gesture and zone, or none, named `name` for screen readers; returns
+/// whether it changed
+pub(super) fn render_patch_binding(
+    ui: &mut egui::Ui,
+    id: impl std::hash::Hash,
+    name: &str,
+    binding: &mut Option<PatchBinding>,
+) -> bool {
+    let mut changed = false;
+    let gesture = egui::ComboBox::from_id_source(id)
+        .selected_text(binding.map_or(t("none"), |binding| t_name("patch_gesture", binding.gesture.name())))
+        .show_ui(ui, |ui| {
+            if ui.selectable_label(binding.is_none(), t("none")).clicked() {
+                *binding = None;
+                changed = true;
+            }
+            for &gesture in PatchGesture::all() {
+                let selected = binding.is_some_and(|binding| binding.gesture == gesture);
+                if ui.selectable_label(selected, t_name("patch_gesture", gesture.name())).clicked() {
+                    let zone = binding.map_or(0, |binding| binding.zone);
+                    *binding = Some(PatchBinding { zone, gesture });
+                    changed = true;
+                }
+            }
+        });
+    accessible_name(gesture.response, name);
+    if let Some(binding) = binding {
+        ui.label(t("midi.binding.zone"));
+        let zone = ui.add(egui::DragValue::new(&mut binding.zone).range(0..=NUM_ZONES - 1));
+        changed |= accessible_name(zone, tf("midi.binding.zone.accessible", &[("name", &name)])).changed();
+    }
+    changed
+}
+
+fn render_osc_settings(
+    app_config: &mut crate::config::AppConfig,
+    ui: &mut egui::Ui,
+    config_changed: &mut bool,
+) {
+    ui.group(|ui| {
+        ui.label(t("osc"));
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("enabled"), "osc.enabled");
+            *config_changed |= ui.checkbox(&mut app_config.osc.enabled, "").labelled_by(label.id).changed();
+        });
+
+        ui.add_enabled_ui(app_config.osc.enabled, |ui| {
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("osc.host"), "osc.host");
+                *config_changed |= ui.text_edit_singleline(&mut app_config.osc.host).labelled_by(label.id).changed();
+                let label = config_label(ui, app_config, t("osc.port"), "osc.port");
+                *config_changed |= ui.add(egui::DragValue::new(&mut app_config.osc.port)).labelled_by(label.id).changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("osc.value_address"), "osc.value_address");
+                *config_changed |= ui.text_edit_singleline(&mut app_config.osc.value_address).labelled_by(label.id).changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("osc.gate_address"), "osc.gate_address");
+                *config_changed |= ui.text_edit_singleline(&mut app_config.osc.gate_address).labelled_by(label.id).changed();
+            });
+
+            if !OscConfig::is_valid_address(&app_config.osc.value_address)
+                || !OscConfig::is_valid_address(&app_config.osc.gate_address)
+            {
+                ui.colored_label(egui::Color32::RED, t("osc.invalid_address"));
+            }
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("osc.gate_threshold"), "osc.gate_threshold");
+                *config_changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut app_config.osc.gate_threshold)
+                            .range(0.001..=1.0)
+                            .speed(0.001),
+                    )
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("osc.max_rate_hz"), "osc.max_rate_hz");
+                *config_changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut app_config.osc.max_rate_hz)
+                            .range(0.0..=1000.0)
+                            .speed(1.0),
+                    )
+                    .labelled_by(label.id)
+                    .changed();
+            });
+        });
+
+        ui.label(t("osc.text1"));
+        ui.label(t("osc.text2"));
+        ui.label(t("osc.text3"));
+    });
+}
+
+fn render_artnet_settings(
+    app_config: &mut crate::config::AppConfig,
+    ui: &mut egui::Ui,
+    config_changed: &mut bool,
+) {
+    use crate::config::artnet::DMX_CHANNELS;
+
+    ui.group(|ui| {
+        ui.label(t("artnet"));
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("enabled"), "artnet.enabled");
+            *config_changed |= ui.checkbox(&mut app_config.artnet.enabled, "").labelled_by(label.id).changed();
+        });
+
+        ui.add_enabled_ui(app_config.artnet.enabled, |ui| {
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("artnet.host"), "artnet.host");
+                *config_changed |= ui.text_edit_singleline(&mut app_config.artnet.host).labelled_by(label.id).changed();
+                let label = config_label(ui, app_config, t("artnet.port"), "artnet.port");
+                *config_changed |= ui.add(egui::DragValue::new(&mut app_config.artnet.port)).labelled_by(label.id).changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("artnet.net"), "artnet.net");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.artnet.net).range(0..=127))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("artnet.subnet"), "artnet.subnet");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.artnet.subnet).range(0..=15))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("artnet.universe"), "artnet.universe");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.artnet.universe).range(0..=15))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("artnet.frame_rate_hz"), "artnet.frame_rate_hz");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.artnet.frame_rate_hz).range(30.0..=44.0))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("artnet.blackout_on_shutdown"), "artnet.blackout_on_shutdown");
+                *config_changed |= ui
+                    .checkbox(&mut app_config.artnet.blackout_on_shutdown, "")
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            egui::Grid::new("artnet_zones").striped(true).show(ui, |ui| {
+                ui.label(t("zone"));
+                ui.label(t("artnet.zones.channels"));
+                ui.label(t("artnet.zones.scale"));
+                ui.end_row();
+
+                for (zone, mapping) in app_config.artnet.zones.iter_mut().enumerate() {
+                    ui.label(zone.to_string());
+                    // Only valid lists are stored, so a half-typed one is never saved
+                    let mut text = mapping
+                        .channels
+                        .iter()
+                        .map(|channel| channel.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    let response = ui.add(egui::TextEdit::singleline(&mut text).desired_width(120.0));
+                    if accessible_name(response, tf("artnet.zones.channels.accessible", &[("zone", &zone)])).changed() {
+                        if let Some(channels) = parse_channels(&text, DMX_CHANNELS) {
+                            mapping.channels = channels;
+                            *config_changed = true;
+                        }
+                    }
+                    let scale = ui.add(egui::DragValue::new(&mut mapping.scale).range(0.0..=100.0).speed(0.1));
+                    *config_changed |= accessible_name(scale, tf("artnet.zones.scale.accessible", &[("zone", &zone)])).changed();
+                    ui.end_row();
+                }
+            });
+        });
+
+        ui.label(t("artnet.text1"));
+        ui.label(t("artnet.text2"));
+    });
+}
+
+/// A comma separated channel list, or `None` if any entry isn't a channel.
+fn parse_channels(text: &str, max: u16) -> Option<Vec<u16>> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| entry.parse().ok().filter(|channel| (1..=max).contains(channel)))
+        .collect()
+}
+
+#[cfg(feature = "synth")]
+fn render_synth_settings(
+    app_config: &mut crate::config::AppConfig,
+    ui: &mut egui::Ui,
+    config_changed: &mut bool,
+) {
+    use crate::config::synth::SynthWaveform;
+
+    ui.group(|ui| {
+        ui.label(t("synth"));
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("enabled"), "synth.enabled");
+            *config_changed |= ui.checkbox(&mut app_config.synth.enabled, "").labelled_by(label.id).changed();
+        });
+
+        ui.add_enabled_ui(app_config.synth.enabled, |ui| {
+            ui.horizontal(|ui| {
+                config_label(ui, app_config, t("synth.waveform"), "synth.waveform");
+                *config_changed |= ui
+                    .radio_value(&mut app_config.synth.waveform, SynthWaveform::Sine, t("synth.waveform.sine"))
+                    .changed();
+                *config_changed |= ui
+                    .radio_value(&mut app_config.synth.waveform, SynthWaveform::Fm, t("synth.waveform.fm"))
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("synth.volume"), "synth.volume");
+                *config_changed |= ui
+                    .add(egui::Slider::new(&mut app_config.synth.volume, 0.0..=1.0))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("synth.sensitivity"), "synth.sensitivity");
+                *config_changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut app_config.synth.sensitivity)
+                            .range(0.1..=100.0)
+                            .speed(0.1),
+                    )
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("synth.attack_ms"), "synth.attack_ms");
+                *config_changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut app_config.synth.attack_ms)
+                            .range(0.0..=2000.0)
+                            .speed(1.0),
+                    )
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("synth.release_ms"), "synth.release_ms");
+                *config_changed |= ui
+                    .add(
+                        egui::DragValue::new(&mut app_config.synth.release_ms)
+                            .range(0.0..=5000.0)
+                            .speed(1.0),
+                    )
+                    .labelled_by(label.id)
+                    .changed();
+            });
+
+            if app_config.synth.waveform == SynthWaveform::Fm {
+                ui.horizontal(|ui| {
+                    let label = config_label(ui, app_config, t("synth.fm_ratio"), "synth.fm_ratio");
+                    *config_changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut app_config.synth.fm_ratio)
+                                .range(0.1..=16.0)
+                                .speed(0.01),
+                        )
+                        .labelled_by(label.id)
+                        .changed();
+                    let label = config_label(ui, app_config, t("synth.fm_index"), "synth.fm_index");
+                    *config_changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut app_config.synth.fm_index)
+                                .range(0.0..=20.0)
+                                .speed(0.01),
+                        )
+                        .labelled_by(label.id)
+                        .changed();
+                });
+            }
+        });
+
+        ui.label(t("synth.text1"));
+        ui.label(t("synth.text2"));
+    });
+}
+
+#[cfg(all(target_os = "linux", feature = "gamepad"))]
+fn render_gamepad_settings(
+    app_config: &mut crate::config::AppConfig,
+    ui: &mut egui::Ui,
+    config_changed: &mut bool,
+) {
+    use crate::config::gamepad::GamepadAxis;
+
+    const AXES: [GamepadAxis; 7] = [
+        GamepadAxis::None,
+        GamepadAxis::LeftX,
+        GamepadAxis::LeftY,
+        GamepadAxis::RightX,
+        GamepadAxis::RightY,
+        GamepadAxis::LeftTrigger,
+        GamepadAxis::RightTrigger,
+    ];
+    const BUTTON_NAMES: [&str; crate::config::NUM_ZONES] = ["A", "B", "X", "Y", "LB", "RB", "Select", "Start"];
+
+    ui.group(|ui| {
+        ui.label(t("gamepad"));
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("enabled"), "gamepad.enabled");
+            *config_changed |= ui.checkbox(&mut app_config.gamepad.enabled, "").labelled_by(label.id).changed();
+        });
+
+        ui.add_enabled_ui(app_config.gamepad.enabled, |ui| {
+            egui::Grid::new("gamepad_zones").striped(true).show(ui, |ui| {
+                ui.label(t("zone"));
+                ui.label(t("gamepad.zones.axis"));
+                ui.label(t("gamepad.zones.scale"));
+                ui.label(t("gamepad.zones.invert"));
+                ui.label(t("gamepad.zones.button"));
+                ui.end_row();
+
+                // The zone list always has NUM_ZONES entries, see GamepadConfig::validate
+                for (zone, button_name) in BUTTON_NAMES.iter().enumerate() {
+                    ui.label(format!("{}", zone));
+                    let current = app_config.gamepad.zones[zone].axis;
+                    let axis = egui::ComboBox::from_id_source(("gamepad_axis", zone))
+                        .selected_text(t_name("gamepad_axis", current.name()))
+                        .show_ui(ui, |ui| {
+                            for axis in AXES {
+                                // Every axis can only follow one zone
+                                let taken = axis != GamepadAxis::None
+                                    && axis != current
+                                    && app_config.gamepad.zones.iter().any(|z| z.axis == axis);
+                                if !taken {
+                                    *config_changed |= ui
+                                        .selectable_value(&mut app_config.gamepad.zones[zone].axis, axis, t_name("gamepad_axis", axis.name()))
+                                        .changed();
+                                }
+                            }
+                        });
+                    accessible_name(axis.response, tf("gamepad.zones.axis.accessible", &[("zone", &zone)]));
+                    let mapping = &mut app_config.gamepad.zones[zone];
+                    let scale = ui.add(egui::DragValue::new(&mut mapping.scale).range(0.1..=100.0).speed(0.1));
+                    *config_changed |= accessible_name(scale, tf("gamepad.zones.scale.accessible", &[("zone", &zone)])).changed();
+                    let invert = ui.checkbox(&mut mapping.invert, "");
+                    *config_changed |= accessible_name(invert, tf("gamepad.zones.invert.accessible", &[("zone", &zone)])).changed();
+                    *config_changed |= ui.checkbox(&mut mapping.button, *button_name).changed();
+                    ui.end_row();
+                }
+            });
+        });
+
+        ui.label(t("gamepad.text1"));
+        ui.label(t("gamepad.text2"));
+    });
+}
+
+#[cfg(feature = "keys")]
+fn render_key_settings(
+    app_config: &mut crate::config::AppConfig,
+    ui: &mut egui::Ui,
+    config_changed: &mut bool,
+) {
+    use crate::config::keys::{KeyBinding, KeyGesture};
+    use crate::config::NUM_ZONES;
+
+    const GESTURES: [(KeyGesture, &str); 3] = [
+        (KeyGesture::Hold, "keys.gesture.hold"),
+        (KeyGesture::Tap, "keys.gesture.tap"),
+        (KeyGesture::DoubleTap, "keys.gesture.double_tap"),
+    ];
+
+    ui.group(|ui| {
+        ui.label(t("keys"));
+
+        ui.horizontal(|ui| {
+            let label = config_label(ui, app_config, t("enabled"), "keys.enabled");
+            *config_changed |= ui.checkbox(&mut app_config.keys.enabled, "").labelled_by(label.id).changed();
         });
 
-        ui.label("Note mode sends Note On when magnitude > threshold,");
-        ui.label("Key Pressure while note is on, and Note Off when magnitude < threshold.");
-        ui.label("Zones are mapped to notes according to the selected musical scale.");
+        ui.add_enabled_ui(app_config.keys.enabled, |ui| {
+            let mut remove = None;
+            egui::Grid::new("key_bindings").striped(true).show(ui, |ui| {
+                ui.label(t("zone"));
+                ui.label(t("keys.bindings.gesture"));
+                ui.label(t("keys.bindings.key"));
+                ui.end_row();
+
+                for (i, binding) in app_config.keys.bindings.iter_mut().enumerate() {
+                    let row = i + 1;
+                    let zone = ui.add(egui::DragValue::new(&mut binding.zone).range(0..=NUM_ZONES - 1));
+                    *config_changed |= accessible_name(zone, tf("keys.bindings.zone.accessible", &[("row", &row)])).changed();
+                    let selected = GESTURES
+                        .iter()
+                        .find(|(gesture, _)| *gesture == binding.gesture)
+                        .map_or("", |&(_, key)| t(key));
+                    let gesture = egui::ComboBox::from_id_source(("key_gesture", i))
+                        .selected_text(selected)
+                        .show_ui(ui, |ui| {
+                            for (gesture, key) in GESTURES {
+                                *config_changed |= ui.selectable_value(&mut binding.gesture, gesture, t(key)).changed();
+                            }
+                        });
+                    accessible_name(gesture.response, tf("keys.bindings.gesture.accessible", &[("row", &row)]));
+                    // Only valid keys are stored, so a half-typed name is never saved
+                    let mut key = binding.key.clone();
+                    let response = ui.add(egui::TextEdit::singleline(&mut key).desired_width(80.0));
+                    let response = accessible_name(response, tf("keys.bindings.key.accessible", &[("row", &row)]));
+                    if response.changed() && KeyBinding::is_valid_key(&key) {
+                        binding.key = key;
+                        *config_changed = true;
+                    }
+                    if ui.button(t("remove")).clicked() {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+            if let Some(i) = remove {
+                app_config.keys.bindings.remove(i);
+                *config_changed = true;
+            }
+            if ui.button(t("keys.bindings.add")).clicked() {
+                app_config.keys.bindings.push(KeyBinding {
+                    zone: 0,
+                    gesture: KeyGesture::Tap,
+                    key: "Space".to_string(),
+                });
+                *config_changed = true;
+            }
+
+            ui.horizontal(|ui| {
+                let label = config_label(ui, app_config, t("gesture.tap_max_ms"), "gesture.tap_max_ms");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.gesture.tap_max_ms).range(10.0..=2000.0))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("gesture.double_tap_ms"), "gesture.double_tap_ms");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.gesture.double_tap_ms).range(10.0..=2000.0))
+                    .labelled_by(label.id)
+                    .changed();
+                let label = config_label(ui, app_config, t("gesture.hold_ms"), "gesture.hold_ms");
+                *config_changed |= ui
+                    .add(egui::DragValue::new(&mut app_config.gesture.hold_ms).range(10.0..=5000.0))
+                    .labelled_by(label.id)
+                    .changed();
+            });
+        });
+
+        ui.label(t("keys.text1"));
+        ui.label(t("keys.text2"));
+        ui.label(t("keys.text3"));
     });
-}
\ No newline at end of file
+}
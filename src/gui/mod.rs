@@ -1,7 +1,13 @@
 pub mod app;
 pub mod config_ui;
+pub mod focus;
+pub mod i18n;
 pub mod midi_ui;
+pub mod outputs_ui;
 pub mod plot;
+pub mod stats_ui;
+pub mod toast;
+pub mod widgets;
 
 // Re-export commonly used types for convenience
-pub use app::{PlotApp, ProcessedSample};
\ No newline at end of file
+pub use app::PlotApp;
\ No newline at end of file
@@ -0,0 +1,62 @@
+use super::app::PlotApp;
+use super::i18n::{t, t_name};
+use crate::output::{SinkState, SinkStatus};
+use eframe::egui;
+
+fn state_name(state: SinkState) -> &'static str {
+    match state {
+        SinkState::Active => t("outputs.active"),
+        SinkState::Disabled => t("outputs.disabled"),
+        SinkState::Muted => t("outputs.muted"),
+    }
+}
+
+pub fn render_outputs_tab(app: &mut PlotApp, ui: &mut egui::Ui) {
+    ui.heading(t("outputs.heading"));
+
+    let Some(outputs) = &app.outputs else {
+        ui.label(t("outputs.unavailable"));
+        return;
+    };
+    let statuses = outputs.latest();
+    if statuses.is_empty() {
+        ui.label(t("outputs.waiting"));
+        return;
+    }
+    render_statuses(&statuses, ui);
+    ui.label(t("outputs.note"));
+}
+
+fn render_statuses(statuses: &[SinkStatus], ui: &mut egui::Ui) {
+    egui::Grid::new("output_sinks").striped(true).show(ui, |ui| {
+        ui.label(t("outputs.output"));
+        ui.label(t("outputs.state"));
+        ui.label(t("outputs.samples"));
+        ui.label(t("outputs.dropped"));
+        ui.label(t("outputs.errors"));
+        ui.label(t("outputs.last_error"));
+        ui.end_row();
+
+        for status in statuses {
+            let name = ui.label(t_name("output", status.name));
+            if let Some(detail) = &status.detail {
+                name.on_hover_text(detail);
+            }
+            let state = state_name(status.state);
+            match status.state {
+                SinkState::Active => ui.label(state),
+                SinkState::Disabled => ui.weak(state),
+                SinkState::Muted => ui.colored_label(egui::Color32::YELLOW, state),
+            };
+            ui.label(status.samples.to_string());
+            ui.label(status.dropped.to_string());
+            if status.errors > 0 {
+                ui.colored_label(egui::Color32::RED, status.errors.to_string());
+            } else {
+                ui.label("0");
+            }
+            ui.label(status.last_error.as_deref().unwrap_or("-"));
+            ui.end_row();
+        }
+    });
+}
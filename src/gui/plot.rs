@@ -1,49 +1,505 @@
 use super::app::PlotApp;
+use super::focus::render_focus;
+use super::i18n::{t, t_name, tf};
+use super::widgets::lockable;
+use crate::config::{
+    PlotSource, ZoneMapScope, MAX_PLOT_DURATION_SECS, MIN_PLOT_DURATION_SECS, NUM_ZONES, ZONE_PALETTE,
+};
+use crate::markers::SessionMarker;
+use crate::midi::MidiAction;
+use crate::plot_history::{MidiMarker, PlotFrame};
+use crate::sync::LockExt;
 use eframe::egui::{self, Vec2b};
-use egui_plot::{Corner, Legend, Line, Plot, PlotBounds, PlotPoints};
+use egui_plot::{Corner, Legend, Line, LineStyle, MarkerShape, Plot, PlotBounds, PlotPoints, PlotTransform, PlotUi, Points, VLine};
+use std::sync::Arc;
 
+/// The decimated line of every zone, kept between repaints and only rebuilt
+/// for zones with new samples or after the view changed
+#[derive(Default)]
+pub struct PlotCache {
+    zones: [ZoneLine; NUM_ZONES],
+    /// The second source of the focused zone, see `gui::focus`
+    focus: ZoneLine,
+}
+
+/// Shape marking each zone's line, and the glyph showing it in the legend,
+/// one the default fonts have. Drawn hollow, the MIDI markers are filled.
+const ZONE_MARKERS: [(MarkerShape, &str); NUM_ZONES] = [
+    (MarkerShape::Circle, "○"),
+    (MarkerShape::Square, "◻"),
+    (MarkerShape::Diamond, "◊"),
+    (MarkerShape::Up, "⏶"),
+    (MarkerShape::Down, "⏷"),
+    (MarkerShape::Cross, "×"),
+    (MarkerShape::Plus, "+"),
+    (MarkerShape::Asterisk, "*"),
+];
+/// Buckets, about pixel columns, between two zone markers on a line
+const MARKER_SPACING: f64 = 48.0;
+/// Zoom of the plot window per point of mouse wheel scrolling
+const ZOOM_PER_SCROLL: f64 = 0.005;
+/// Lines and labels of the session markers, apart from the zone palette
+const SESSION_MARKER_COLOR: egui::Color32 = egui::Color32::from_rgb(255, 170, 0);
+
+/// A plot frozen to scroll back in: the frame it shows and the device time
+/// at the right edge of its window
+pub struct PausedPlot {
+    frame: Arc<PlotFrame>,
+    end: f64,
+}
+
+impl PausedPlot {
+    pub(super) fn frame(&self) -> Arc<PlotFrame> {
+        self.frame.clone()
+    }
+
+    pub(super) fn end(&self) -> f64 {
+        self.end
+    }
+}
+
+#[derive(Default)]
+pub(super) struct ZoneLine {
+    /// Frame generation, source and bucket size the points were built for
+    built_for: Option<(u64, PlotSource, f64)>,
+    points: Vec<[f64; 2]>,
+    /// Saturated points, marked on top of the line
+    saturated: Vec<[f64; 2]>,
+    /// The points carrying the zone's marker, every `MARKER_SPACING` buckets
+    markers: Vec<[f64; 2]>,
+}
+
+impl PlotCache {
+    pub(super) fn line(&mut self, frame: &PlotFrame, zone: usize, bucket_secs: f64, source: PlotSource) -> &ZoneLine {
+        self.zones[zone].update(frame, zone, bucket_secs, source)
+    }
+
+    /// Like `line`, but kept apart from the zone's own line so showing two
+    /// sources of one zone doesn't rebuild both every repaint
+    pub(super) fn focus_line(&mut self, frame: &PlotFrame, zone: usize, bucket_secs: f64, source: PlotSource) -> &ZoneLine {
+        self.focus.update(frame, zone, bucket_secs, source)
+    }
+}
+
+impl ZoneLine {
+    fn update(&mut self, frame: &PlotFrame, zone: usize, bucket_secs: f64, source: PlotSource) -> &ZoneLine {
+        let key = (frame.generation(zone), source, bucket_secs);
+        if self.built_for != Some(key) {
+            frame.decimate_into(zone, bucket_secs, source, &mut self.points);
+            frame.saturated_into(zone, bucket_secs, source, &mut self.saturated);
+            marker_points(&self.points, zone, bucket_secs * MARKER_SPACING, &mut self.markers);
+            self.built_for = Some(key);
+        }
+        self
+    }
+
+    pub(super) fn points(&self) -> PlotPoints {
+        PlotPoints::Owned(self.points.iter().map(|&point| point.into()).collect())
+    }
+
+    /// The saturated points, `None` without any
+    pub(super) fn saturated(&self) -> Option<PlotPoints> {
+        (!self.saturated.is_empty()).then(|| PlotPoints::Owned(self.saturated.iter().map(|&point| point.into()).collect()))
+    }
+}
+
+/// Replaces `markers` with the first of `points` in every `interval` of
+/// time. The intervals are fixed in time, so the markers scroll with the
+/// line, and offset per zone, so zones with the same values don't hide each
+/// other's markers.
+fn marker_points(points: &[[f64; 2]], zone: usize, interval: f64, markers: &mut Vec<[f64; 2]>) {
+    markers.clear();
+    let offset = zone as f64 / NUM_ZONES as f64;
+    let mut last = None;
+    for &point in points {
+        let index = (point[0] / interval + offset).floor();
+        if last != Some(index) {
+            last = Some(index);
+            markers.push(point);
+        }
+    }
+}
+
+/// The device zone whose samples output zone `zone` plots, `None` if the
+/// zone map doesn't have it
+pub(super) fn device_zone(zone_map: &[usize], scope: ZoneMapScope, zone: usize) -> Option<usize> {
+    match scope {
+        ZoneMapScope::Everything => zone_map.get(zone).copied(),
+        ZoneMapScope::MidiOnly => Some(zone),
+    }
+}
+
+/// Draws the MIDI markers of a zone: up triangles for Note On, down
+/// triangles for Note Off and dots for Control Change
+fn draw_midi_markers(
+    plot_ui: &mut PlotUi,
+    markers: &[MidiMarker],
+    name: &str,
+    color: egui::Color32,
+    source: PlotSource,
+) {
+    let shapes = [(MarkerShape::Up, 5.0), (MarkerShape::Down, 5.0), (MarkerShape::Circle, 1.5)];
+    for (index, (shape, radius)) in shapes.into_iter().enumerate() {
+        let points: Vec<[f64; 2]> = markers
+            .iter()
+            .filter(|marker| match marker.action {
+                MidiAction::NoteOn { .. } => index == 0,
+                MidiAction::NoteOff { .. } => index == 1,
+                MidiAction::ControlChange { .. } => index == 2,
+                MidiAction::KeyPressure { .. } => false,
+            })
+            .map(|marker| [marker.point.time, marker.point.value(source)])
+            .collect();
+        if !points.is_empty() {
+            plot_ui.points(
+                Points::new(points)
+                    .shape(shape)
+                    .filled(true)
+                    .radius(radius)
+                    .color(color)
+                    .name(name),
+            );
+        }
+    }
+}
+
+/// Draws the session markers as dashed lines across the plot, unnamed so
+/// the legend has no entry for them
+pub(super) fn draw_session_markers(plot_ui: &mut PlotUi, markers: &[(usize, SessionMarker)]) {
+    for (_, marker) in markers {
+        plot_ui.vline(VLine::new(marker.time()).color(SESSION_MARKER_COLOR).style(LineStyle::dashed_loose()));
+    }
+}
+
+/// Labels the lines of `draw_session_markers` at the top of the plot with
+/// their numbers and names. Painted over the plot rather than plotted, as
+/// plotted text would widen the fitted bounds to fit it.
+pub(super) fn label_session_markers(ui: &egui::Ui, transform: &PlotTransform, markers: &[(usize, SessionMarker)]) {
+    let frame = *transform.frame();
+    let painter = ui.painter_at(frame);
+    for (number, marker) in markers {
+        let x = transform.position_from_point_x(marker.time());
+        painter.text(
+            egui::pos2(x + 3.0, frame.top() + 2.0),
+            egui::Align2::LEFT_TOP,
+            tf("plot.session_marker", &[("number", number), ("name", &marker.name)]),
+            egui::FontId::proportional(12.0),
+            SESSION_MARKER_COLOR,
+        );
+    }
+}
+
+/// Names the next marker and drops it; changes no settings, so never locked
+fn render_session_markers(app: &mut PlotApp, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        ui.label(t("markers"));
+        let default_name = app.app_config.lock_or_recover().markers.name.clone();
+        ui.add(egui::TextEdit::singleline(&mut app.marker_name).hint_text(default_name).desired_width(160.0))
+            .on_hover_text(t("markers.name.hover"));
+        if ui.button(t("markers.drop")).on_hover_text(t("markers.drop.hover")).clicked() {
+            app.drop_marker();
+        }
+        ui.label(tf("markers.count", &[("count", &app.markers.len())]));
+    });
+}
+
+/// The source picker of every zone, with one to set them all at once.
+/// Returns whether any changed.
+fn render_plot_sources(ui: &mut egui::Ui, label: &str, sources: &mut [PlotSource; NUM_ZONES]) -> bool {
+    let mut changed = false;
+    ui.menu_button(label, |ui| {
+        ui.horizontal(|ui| {
+            ui.label(t("plot.all_zones"));
+            for source in PlotSource::all() {
+                if ui.button(t_name("plot_source", source.name())).clicked() {
+                    changed |= sources.iter().any(|zone_source| zone_source != source);
+                    *sources = [*source; NUM_ZONES];
+                }
+            }
+        });
+        ui.separator();
+        egui::Grid::new("plot_sources").show(ui, |ui| {
+            for (zone, zone_source) in sources.iter_mut().enumerate() {
+                ui.label(tf("zone_label", &[("zone", &zone)]));
+                for source in PlotSource::all() {
+                    changed |= ui.selectable_value(zone_source, *source, t_name("plot_source", source.name())).changed();
+                }
+                ui.end_row();
+            }
+        });
+    });
+    changed
+}
+
+/// The color picker of every zone, with a reset to `ZONE_PALETTE`. Returns
+/// whether any changed.
+fn render_plot_colors(ui: &mut egui::Ui, label: &str, colors: &mut [[u8; 3]; NUM_ZONES]) -> bool {
+    let mut changed = false;
+    ui.menu_button(label, |ui| {
+        egui::Grid::new("plot_colors").show(ui, |ui| {
+            for (zone, color) in colors.iter_mut().enumerate() {
+                ui.label(tf("zone_label", &[("zone", &zone)]));
+                changed |= egui::color_picker::color_edit_button_srgb(ui, color).changed();
+                ui.end_row();
+            }
+        });
+        ui.separator();
+        if ui
+            .add_enabled(*colors != ZONE_PALETTE, egui::Button::new(t("reset")))
+            .on_hover_text(t("plot.colors.reset.hover"))
+            .clicked()
+        {
+            *colors = ZONE_PALETTE;
+            changed = true;
+        }
+    });
+    changed
+}
 
 pub fn render_plot_tab(app: &mut PlotApp, ui: &mut egui::Ui, _ctx: &egui::Context) {
+    if let Some(focus) = app.focus {
+        render_focus(app, ui, focus);
+        return;
+    }
+
     // Plot configuration controls
+    let locked = app.locked;
     ui.horizontal(|ui| {
-        let mut app_config = app.app_config.lock().unwrap();
-        let config_changed = ui
-            .checkbox(&mut app_config.plot_raw, "Show raw sensor values")
-            .changed();
-        if config_changed {
-            if let Err(e) = app_config.save_to_file() {
-                eprintln!("Failed to save app config: {}", e);
-            }
+        let mut app_config = app.app_config.lock_or_recover();
+        let sources_label = if app_config.is_overridden("plot_sources") {
+            format!("{} *", t("plot.sources"))
+        } else {
+            t("plot.sources").to_string()
+        };
+        let midi_label = if app_config.is_overridden("plot_midi_events") {
+            format!("{} *", t("plot.show_midi"))
+        } else {
+            t("plot.show_midi").to_string()
+        };
+        let colors_label = if app_config.is_overridden("plot_colors") {
+            format!("{} *", t("plot.colors"))
+        } else {
+            t("plot.colors").to_string()
+        };
+        let markers_label = if app_config.is_overridden("plot_zone_markers") {
+            format!("{} *", t("plot.zone_markers"))
+        } else {
+            t("plot.zone_markers").to_string()
+        };
+        let config_changed = lockable(ui, locked, &mut app.toasts, |ui| {
+            let sources_changed = render_plot_sources(ui, &sources_label, &mut app_config.plot_sources);
+            let colors_changed = render_plot_colors(ui, &colors_label, &mut app_config.plot_colors);
+            let markers_changed = ui
+                .checkbox(&mut app_config.plot_zone_markers, markers_label)
+                .on_hover_text(t("plot.zone_markers.hover"))
+                .changed();
+            let midi_changed = ui
+                .checkbox(&mut app_config.plot_midi_events, midi_label)
+                .on_hover_text(t("plot.show_midi.hover"))
+                .changed();
+            sources_changed || colors_changed || markers_changed || midi_changed
+        });
+        let duration_label = if app_config.is_overridden("plot_duration_secs") {
+            format!("{} *", t("plot.duration"))
+        } else {
+            t("plot.duration").to_string()
+        };
+        let duration_changed = lockable(ui, locked, &mut app.toasts, |ui| {
+            ui.label(duration_label);
+            ui.add(
+                egui::Slider::new(
+                    &mut app_config.plot_duration_secs,
+                    MIN_PLOT_DURATION_SECS..=MAX_PLOT_DURATION_SECS,
+                )
+                .logarithmic(true)
+                .suffix(" s"),
+            )
+            .on_hover_text(t("plot.duration.hover"))
+            .changed()
+        });
+        if config_changed || duration_changed {
+            app_config.mark_dirty();
         }
-        if !app_config.plot_raw {
-            ui.label("(showing normalized values)");
+        drop(app_config);
+
+        // Only what is shown, nothing to lock
+        let mut paused = app.plot_paused.is_some();
+        if ui.toggle_value(&mut paused, t("plot.pause")).on_hover_text(tf("plot.pause.hover", &[("secs", &MAX_PLOT_DURATION_SECS)])).changed() {
+            app.plot_paused = paused.then(|| {
+                let frame = app.plot.load();
+                let end = frame.now().unwrap_or(0.0);
+                PausedPlot { frame, end }
+            });
         }
     });
+    // Mutes the other zones, so locked with the settings
+    let focused = lockable(ui, locked, &mut app.toasts, |ui| {
+        ui.horizontal(|ui| {
+            ui.label(t("focus"));
+            let mut focused = None;
+            for zone in 0..NUM_ZONES {
+                if ui.button(zone.to_string()).on_hover_text(tf("focus.hover", &[("zone", &zone)])).clicked() {
+                    focused = Some(zone);
+                }
+            }
+            focused
+        })
+        .inner
+    });
+    if let Some(zone) = focused {
+        app.focus_zone(zone);
+    }
+    render_session_markers(app, ui);
 
     ui.separator();
 
-    let sensor_data = app.sensor_data.lock().unwrap();
-    let cur_dildonica_time = app.current_dildonica_time();
+    // One frame and one config read per repaint; the pipeline never waits for either
+    let frame = match &app.plot_paused {
+        Some(paused) => paused.frame.clone(),
+        None => app.plot.load(),
+    };
+    let (plot_sources, plot_colors, plot_zone_markers, plot_midi_events, plot_duration, window_relative_raw) = {
+        let config = app.app_config.lock_or_recover();
+        (
+            config.plot_sources,
+            config.plot_colors,
+            config.plot_zone_markers,
+            config.plot_midi_events,
+            config.plot_duration_secs,
+            config.normalization.window_relative_raw,
+        )
+    };
+    // Live, the window's right edge is the current time
+    let window_end = match &app.plot_paused {
+        Some(paused) => paused.end,
+        None => frame.now().unwrap_or(0.0),
+    };
+    // About two points per pixel column are all the plot can show
+    let bucket_secs = plot_duration / f64::from(ui.available_width().max(1.0));
+    // Kept apart from the frame, so pausing neither hides nor freezes them
+    let session_markers = app.markers.within(window_end - plot_duration, window_end);
+    let cache = &mut app.plot_cache;
+    // Zones disabled in the device config get no samples; grey out their lines
+    let enabled: [bool; NUM_ZONES] = {
+        let (zone_map, scope) = {
+            let config = app.app_config.lock_or_recover();
+            (config.zone_map.clone(), config.zone_map_scope)
+        };
+        let zone_configs = app.zone_configs.lock_or_recover();
+        std::array::from_fn(|zone| {
+            device_zone(&zone_map, scope, zone)
+                .and_then(|device_zone| zone_configs.get(device_zone))
+                .is_none_or(|config| config.enabled)
+        })
+    };
 
-    Plot::new("sensor_plot")
+    // The window is set every frame, so the plot's own zoom and, paused, its
+    // dragging would be undone; both are done here instead
+    let paused = app.plot_paused.is_some();
+    let response = Plot::new("sensor_plot")
         .legend(Legend::default().position(Corner::LeftTop))
         .allow_scroll(false)
-        .x_axis_label("Time (seconds)")
+        .allow_zoom(false)
+        .allow_drag(!paused)
+        .x_axis_label(t("plot.time_axis"))
         .show(ui, |plot_ui| {
-            for (zone, points) in sensor_data.iter().enumerate() {
-                let plot_points = PlotPoints::new(points.clone());
-                plot_ui.line(Line::new(plot_points).name(format!("Zone {}", zone)));
-                let mut plot_bounds = plot_ui.plot_bounds();
-                let plot_duration = {
-                    let config = app.app_config.lock().unwrap();
-                    config.plot_duration_secs
+            for (zone, &enabled) in enabled.iter().enumerate() {
+                // Line takes its points by value, so this copy is left per repaint
+                let source = plot_sources[zone];
+                let line = cache.line(&frame, zone, bucket_secs, source);
+                // The unit of raw values: cycle counts or their window
+                let abbreviation = match source {
+                    PlotSource::Raw if window_relative_raw => "raw/window",
+                    _ => source.abbreviation(),
                 };
-                plot_bounds.set_x(&PlotBounds::from_min_max(
-                    [cur_dildonica_time - plot_duration, 0.0],
-                    [cur_dildonica_time, 0.0],
-                ));
-                plot_ui.set_plot_bounds(plot_bounds);
-                plot_ui.set_auto_bounds(Vec2b::new(false, true));
+                let points = line.points();
+                let (mut name, color) = if enabled {
+                    let [r, g, b] = plot_colors[zone];
+                    (
+                        tf("plot.line", &[("zone", &zone), ("source", &abbreviation)]),
+                        egui::Color32::from_rgb(r, g, b),
+                    )
+                } else {
+                    (
+                        tf("plot.line_disabled", &[("zone", &zone), ("source", &abbreviation)]),
+                        egui::Color32::DARK_GRAY,
+                    )
+                };
+                let (shape, glyph) = ZONE_MARKERS[zone];
+                if plot_zone_markers {
+                    name = format!("{} {}", glyph, name);
+                }
+                plot_ui.line(Line::new(points).name(&name).color(color));
+                if plot_zone_markers && !line.markers.is_empty() {
+                    let markers = PlotPoints::Owned(line.markers.iter().map(|&point| point.into()).collect());
+                    plot_ui.points(
+                        Points::new(markers)
+                            .shape(shape)
+                            .filled(false)
+                            .radius(4.0)
+                            .color(color)
+                            .name(&name),
+                    );
+                }
+                if plot_midi_events {
+                    // Same name as the line, so hiding the zone in the legend hides its markers
+                    draw_midi_markers(plot_ui, frame.markers(zone), &name, color, source);
+                }
+                if let Some(saturated) = line.saturated() {
+                    plot_ui.points(
+                        Points::new(saturated)
+                            .color(egui::Color32::RED)
+                            .radius(3.0)
+                            .name(t("plot.saturated")),
+                    );
+                }
             }
+            draw_session_markers(plot_ui, &session_markers);
+            set_window(plot_ui, window_end, plot_duration);
+
+            let scroll = if plot_ui.response().hovered() {
+                plot_ui.ctx().input(|input| input.smooth_scroll_delta.y)
+            } else {
+                0.0
+            };
+            let zoom = (-f64::from(scroll) * ZOOM_PER_SCROLL).exp();
+            let dragged = if paused {
+                f64::from(plot_ui.pointer_coordinate_drag_delta().x)
+            } else {
+                0.0
+            };
+            (zoom, dragged)
         });
+    label_session_markers(ui, &response.transform, &session_markers);
+    apply_view_change(app, response.inner, plot_duration);
+}
+
+/// Shows the `duration` seconds up to `window_end`, the values fitted to
+/// the plot's height
+pub(super) fn set_window(plot_ui: &mut PlotUi, window_end: f64, duration: f64) {
+    let mut plot_bounds = plot_ui.plot_bounds();
+    plot_bounds.set_x(&PlotBounds::from_min_max([window_end - duration, 0.0], [window_end, 0.0]));
+    plot_ui.set_plot_bounds(plot_bounds);
+    plot_ui.set_auto_bounds(Vec2b::new(false, true));
+}
+
+/// Zooms the window by `zoom` and, paused, scrolls it back by `dragged`
+/// seconds. Live, zooming keeps the right edge at the current time; paused,
+/// it keeps the center.
+fn apply_view_change(app: &mut PlotApp, (zoom, dragged): (f64, f64), duration: f64) {
+    let zoomed = (duration * zoom).clamp(MIN_PLOT_DURATION_SECS, MAX_PLOT_DURATION_SECS);
+    let zoom_changed = zoomed != duration && !app.locked;
+    if let Some(paused) = &mut app.plot_paused {
+        if zoom_changed {
+            paused.end += (zoomed - duration) / 2.0;
+        }
+        // Not past the time it was paused at
+        let latest = paused.frame.now().unwrap_or(paused.end);
+        paused.end = (paused.end - dragged).min(latest);
+    }
+    if zoom_changed {
+        let mut app_config = app.app_config.lock_or_recover();
+        app_config.plot_duration_secs = zoomed;
+        app_config.mark_dirty();
+    }
 }
\ No newline at end of file
@@ -0,0 +1,64 @@
+use super::app::PlotApp;
+use super::i18n::{t, tf};
+use crate::usage::UsageStats;
+use eframe::egui;
+
+/// Formats `secs` as e.g. "2 h 05 min", "4 min 12 s" or "9.3 s"; the units
+/// read the same in every language
+fn format_active(secs: f64) -> String {
+    let whole = secs as u64;
+    match whole {
+        0..=59 => format!("{:.1} s", secs),
+        60..=3599 => format!("{} min {:02} s", whole / 60, whole % 60),
+        _ => format!("{} h {:02} min", whole / 3600, whole / 60 % 60),
+    }
+}
+
+pub fn render_stats_tab(app: &mut PlotApp, ui: &mut egui::Ui) {
+    ui.heading(t("stats.heading"));
+
+    let Some(usage) = &app.usage else {
+        ui.label(t("stats.unavailable"));
+        return;
+    };
+    render_usage(usage, ui);
+}
+
+fn render_usage(usage: &UsageStats, ui: &mut egui::Ui) {
+    let (counts, since) = usage.session();
+    ui.horizontal(|ui| {
+        ui.label(tf("stats.since", &[("time", &since.format("%Y-%m-%d %H:%M"))]));
+        // Not a setting, so never locked
+        if ui.button(t("reset")).clicked() {
+            usage.reset();
+        }
+    });
+    ui.label(tf("stats.total", &[("touches", &counts.total_touches())]));
+    match counts.busiest_hour() {
+        Some((hour, touches)) => ui.label(tf(
+            "stats.busiest_hour",
+            &[
+                ("from", &format!("{:02}:00", hour)),
+                ("to", &format!("{:02}:00", (hour + 1) % 24)),
+                ("touches", &touches),
+            ],
+        )),
+        None => ui.label(t("stats.no_busiest_hour")),
+    };
+    ui.separator();
+
+    egui::Grid::new("usage_zones").striped(true).show(ui, |ui| {
+        ui.label(t("zone"));
+        ui.label(t("stats.touches"));
+        ui.label(t("stats.active_time"));
+        ui.end_row();
+
+        for (zone, zone_usage) in counts.zones.iter().enumerate() {
+            ui.label(zone.to_string());
+            ui.label(zone_usage.touches.to_string());
+            ui.label(format_active(zone_usage.active_secs));
+            ui.end_row();
+        }
+    });
+    ui.label(t("stats.note"));
+}
@@ -0,0 +1,41 @@
+use eframe::egui;
+use std::time::{Duration, Instant};
+
+/// How long a toast stays on screen
+const TOAST_DURATION: Duration = Duration::from_secs(3);
+
+/// Short-lived notifications shown in the bottom right corner.
+#[derive(Default)]
+pub struct Toasts {
+    toasts: Vec<(String, Instant)>,
+}
+
+impl Toasts {
+    pub fn push(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        // Repeated clicks shouldn't stack the same message
+        self.toasts.retain(|(existing, _)| *existing != message);
+        self.toasts.push((message, Instant::now()));
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context) {
+        self.toasts.retain(|(_, shown)| shown.elapsed() < TOAST_DURATION);
+        if self.toasts.is_empty() {
+            return;
+        }
+
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-10.0, -40.0])
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                for (message, _) in &self.toasts {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(message);
+                    });
+                }
+            });
+        // Nothing else may repaint before the oldest one is due to go
+        let (_, oldest) = self.toasts[0];
+        ctx.request_repaint_after(TOAST_DURATION.saturating_sub(oldest.elapsed()));
+    }
+}
@@ -0,0 +1,38 @@
+use super::i18n::t;
+use super::toast::Toasts;
+use crate::config::AppConfig;
+use eframe::egui;
+
+/// Label for a config field, marked with an asterisk when the field is
+/// overridden from the command line or environment.
+pub fn config_label(ui: &mut egui::Ui, config: &AppConfig, text: &str, key: &str) -> egui::Response {
+    if config.is_overridden(key) {
+        ui.label(format!("{} *", text))
+            .on_hover_text(t("overridden"))
+    } else {
+        ui.label(text)
+    }
+}
+
+/// Shows `add_contents` disabled while the app is locked. Clicking the
+/// disabled controls explains the lock instead of silently doing nothing.
+pub fn lockable<R>(
+    ui: &mut egui::Ui,
+    locked: bool,
+    toasts: &mut Toasts,
+    add_contents: impl FnOnce(&mut egui::Ui) -> R,
+) -> R {
+    let inner = ui.add_enabled_ui(!locked, add_contents);
+    if locked && inner.response.interact(egui::Sense::click()).clicked() {
+        toasts.push(t("locked"));
+    }
+    inner.inner
+}
+
+/// Names `response` for screen readers, for controls without a label of
+/// their own to point at, e.g. one of several in a row.
+pub fn accessible_name(response: egui::Response, name: impl Into<String>) -> egui::Response {
+    let name = name.into();
+    response.ctx.accesskit_node_builder(response.id, |builder| builder.set_name(name));
+    response
+}
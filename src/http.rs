@@ -0,0 +1,224 @@
+use crate::config::{AppConfig, ConfigStore, DildonicaZoneConfig, NUM_ZONES};
+use crate::pipeline::{PipelineCommand, CALIBRATION_TIME};
+use crate::sync::{LockExt, TrackedMutex};
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Config fields the API neither shows nor changes
+const PROTECTED_FIELDS: &[&str] = &["version", "http"];
+
+/// State shared with the GUI, so API changes show up there immediately
+#[derive(Clone)]
+pub struct ApiState {
+    pub app_config: Arc<TrackedMutex<ConfigStore>>,
+    pub zone_configs: Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>,
+    /// Writes zone configs to the device; `None` when replaying
+    pub config_tx: Option<mpsc::Sender<[DildonicaZoneConfig; NUM_ZONES]>>,
+    pub commands: mpsc::Sender<PipelineCommand>,
+}
+
+/// An error reply, sent as `{"error": "..."}`
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn bad_request(message: impl ToString) -> Self {
+        Self(StatusCode::BAD_REQUEST, message.to_string())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+/// Starts the HTTP API on `bind:port`:
+///
+/// - `GET /api/config` returns the app config, `PUT` merges a partial or full
+///   config into it and returns the result
+/// - `GET /api/zones` returns the device zone configs, `PUT` writes all of
+///   them to the device
+/// - `POST /api/actions/panic`, `/reset-baselines` and `/calibrate` run the
+///   pipeline actions
+pub async fn start(bind: IpAddr, port: u16, state: ApiState, shutdown: CancellationToken) -> io::Result<()> {
+    let listener = TcpListener::bind((bind, port)).await?;
+    let addr = listener.local_addr()?;
+    if !bind.is_loopback() && state.app_config.lock_or_recover().http.token.is_empty() {
+        warn!("HTTP API on {} is reachable from the network without a token, set http.token", addr);
+    }
+    info!("HTTP API listening on http://{}", addr);
+
+    let router = Router::new()
+        .route("/api/config", get(get_config).put(put_config))
+        .route("/api/zones", get(get_zones).put(put_zones))
+        .route("/api/actions/panic", post(panic))
+        .route("/api/actions/reset-baselines", post(reset_baselines))
+        .route("/api/actions/calibrate", post(calibrate))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state);
+    tokio::spawn(async move {
+        let result = axum::serve(listener, router)
+            .with_graceful_shutdown(shutdown.cancelled_owned())
+            .await;
+        if let Err(e) = result {
+            warn!("HTTP API failed: {}", e);
+        }
+    });
+    Ok(())
+}
+
+async fn require_token(State(state): State<ApiState>, request: Request, next: Next) -> Result<Response, ApiError> {
+    let token = state.app_config.lock_or_recover().http.token.clone();
+    if !token.is_empty() {
+        let sent = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if !sent.is_some_and(|sent| tokens_match(sent, &token)) {
+            return Err(ApiError(StatusCode::UNAUTHORIZED, "Missing or wrong bearer token".to_string()));
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Compares in time independent of where they differ, so a token can't be
+/// guessed byte by byte. The relay checks its token with it too.
+pub fn tokens_match(sent: &str, token: &str) -> bool {
+    sent.len() == token.len() && sent.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+async fn get_config(State(state): State<ApiState>) -> Result<Json<Value>, ApiError> {
+    let app_config = state.app_config.lock_or_recover();
+    config_json(&app_config).map(Json)
+}
+
+async fn put_config(State(state): State<ApiState>, Json(mut patch): Json<Value>) -> Result<Json<Value>, ApiError> {
+    let Some(fields) = patch.as_object_mut() else {
+        return Err(ApiError::bad_request("Expected a JSON object"));
+    };
+    for field in PROTECTED_FIELDS {
+        fields.remove(*field);
+    }
+
+    let mut store = state.app_config.lock_or_recover();
+    let current: &AppConfig = &store;
+    let mut value = serde_json::to_value(current).map_err(ApiError::bad_request)?;
+    merge(&mut value, patch);
+    let mut config: AppConfig = serde_json::from_value(value).map_err(ApiError::bad_request)?;
+    config.validate().map_err(ApiError::bad_request)?;
+    // Overrides keep winning, as they do over edits of the file
+    config.config_path = current.config_path.clone();
+    config.profile = current.profile.clone();
+    config
+        .apply_overrides(current.overrides.clone())
+        .map_err(ApiError::bad_request)?;
+    store.update(config);
+    info!("App config changed through the HTTP API");
+    config_json(&store).map(Json)
+}
+
+/// `config` as JSON without the protected fields
+fn config_json(config: &AppConfig) -> Result<Value, ApiError> {
+    let mut value = serde_json::to_value(config)
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if let Some(fields) = value.as_object_mut() {
+        for field in PROTECTED_FIELDS {
+            fields.remove(*field);
+        }
+    }
+    Ok(value)
+}
+
+/// Merges `patch` into `target`: objects field by field, anything else is
+/// replaced as a whole.
+fn merge(target: &mut Value, patch: Value) {
+    match (target, patch) {
+        (Value::Object(target), Value::Object(patch)) => {
+            for (key, value) in patch {
+                match target.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+async fn get_zones(State(state): State<ApiState>) -> Json<[DildonicaZoneConfig; NUM_ZONES]> {
+    Json(*state.zone_configs.lock_or_recover())
+}
+
+/// Queues the configs for the device. The GUI and the next `GET` show them
+/// once the device has accepted them.
+async fn put_zones(
+    State(state): State<ApiState>,
+    Json(configs): Json<Vec<DildonicaZoneConfig>>,
+) -> Result<StatusCode, ApiError> {
+    let configs: [DildonicaZoneConfig; NUM_ZONES] = configs.try_into().map_err(|configs: Vec<_>| {
+        ApiError::bad_request(format!("Expected {} zone configs, got {}", NUM_ZONES, configs.len()))
+    })?;
+    for (zone, config) in configs.iter().enumerate() {
+        config
+            .validate()
+            .map_err(|e| ApiError::bad_request(format!("Zone {}: {}", zone, e)))?;
+    }
+    let Some(config_tx) = &state.config_tx else {
+        return Err(ApiError(StatusCode::CONFLICT, "No device, replaying a recording".to_string()));
+    };
+    config_tx
+        .try_send(configs)
+        .map_err(|_| ApiError(StatusCode::SERVICE_UNAVAILABLE, "Device is not accepting configs".to_string()))?;
+    info!("Zone configs queued for the device through the HTTP API");
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn panic(State(state): State<ApiState>) -> Result<StatusCode, ApiError> {
+    send_command(&state, PipelineCommand::Panic)
+}
+
+async fn reset_baselines(State(state): State<ApiState>) -> Result<StatusCode, ApiError> {
+    send_command(&state, PipelineCommand::ResetBaselines)
+}
+
+async fn calibrate(State(state): State<ApiState>) -> Result<StatusCode, ApiError> {
+    send_command(&state, PipelineCommand::Calibrate(CALIBRATION_TIME))
+}
+
+/// Commands run with the next sample, so they are only accepted, not done.
+fn send_command(state: &ApiState, command: PipelineCommand) -> Result<StatusCode, ApiError> {
+    state
+        .commands
+        .try_send(command)
+        .map_err(|_| ApiError(StatusCode::SERVICE_UNAVAILABLE, "Sample processing is not running".to_string()))?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_same_token_matches() {
+        let token = "show night";
+        assert!(tokens_match(token, token));
+        assert!(!tokens_match("show nighT", token));
+        assert!(!tokens_match("show", token));
+        assert!(!tokens_match("", token));
+    }
+}
@@ -0,0 +1,308 @@
+//! One instance per device: a lock file with the holder's PID, so a second
+//! copy of the app doesn't fight the first over the BLE connection and the
+//! MIDI port.
+
+use crate::recorder::unix_time;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+#[cfg(not(target_os = "linux"))]
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+use thiserror::Error;
+use tracing::{debug, warn};
+
+/// Directory of the lock files, beside the config file
+pub const LOCK_DIR_NAME: &str = "locks";
+/// Times a lock is tried, taking over a stale one in between
+const LINK_ATTEMPTS: usize = 3;
+
+#[derive(Error, Debug)]
+pub enum InstanceLockError {
+    #[error("Device {address} is in use by {holder}")]
+    Held { address: String, holder: LockHolder },
+    #[error("Failed to lock {}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+}
+
+/// The process holding a lock, as written to its file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    /// Seconds since the Unix epoch
+    pub since: f64,
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            since: unix_time(SystemTime::now()),
+        }
+    }
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "another instance (PID {}", self.pid)?;
+        if let Some(since) = chrono::DateTime::from_timestamp(self.since as i64, 0) {
+            write!(f, ", since {}", since.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Whether a process with `pid` is running
+#[cfg(target_os = "linux")]
+pub fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Whether a process with `pid` is running; signal 0 only checks it exists.
+/// If `kill` can't be run the process counts as running, so a lock is never
+/// taken from a live instance.
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn process_alive(pid: u32) -> bool {
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(Stdio::null())
+        .status()
+        .map_or(true, |status| status.success())
+}
+
+/// Whether a process with `pid` is running, by whether `tasklist` lists it.
+/// If it can't be run the process counts as running.
+#[cfg(not(unix))]
+pub fn process_alive(pid: u32) -> bool {
+    Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH", "/FO", "CSV"])
+        .stderr(Stdio::null())
+        .output()
+        .map_or(true, |output| String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", pid)))
+}
+
+/// The lock file of the device at `address` in `directory`
+pub fn lock_path(directory: &Path, address: &str) -> PathBuf {
+    let name: String = address
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '-' })
+        .collect();
+    directory.join(LOCK_DIR_NAME).join(format!("{}.lock", name))
+}
+
+/// A device locked for this process until dropped
+#[derive(Debug)]
+pub struct InstanceLock {
+    path: PathBuf,
+}
+
+impl InstanceLock {
+    /// Locks the device at `address` with a lock file in `directory`. A
+    /// lock whose process is gone, or that can't be read, is stale and
+    /// taken over; one held by a running process is an error naming it.
+    pub fn acquire(directory: &Path, address: &str) -> Result<Self, InstanceLockError> {
+        let path = lock_path(directory, address);
+        let io_error = |source| InstanceLockError::Io {
+            path: path.clone(),
+            source,
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        // Written beside it and linked into place, so no other instance ever
+        // reads a lock half written
+        let mut staged = path.clone().into_os_string();
+        staged.push(format!(".{}", std::process::id()));
+        let staged = PathBuf::from(staged);
+        let contents = serde_json::to_string(&LockHolder::current()).expect("a lock holder serializes") + "\n";
+        fs::write(&staged, contents).map_err(io_error)?;
+        let result = Self::link(&path, &staged, address);
+        let _ = fs::remove_file(&staged);
+        result.map(|()| Self { path })
+    }
+
+    /// Links `staged` to `path` unless a running process holds it, taking
+    /// over a stale lock.
+    fn link(path: &Path, staged: &Path, address: &str) -> Result<(), InstanceLockError> {
+        let io_error = |source| InstanceLockError::Io {
+            path: path.to_path_buf(),
+            source,
+        };
+        // Again after each stale lock removed or replaced meanwhile
+        for _ in 0..LINK_ATTEMPTS {
+            match fs::hard_link(staged, path) {
+                Ok(()) => {
+                    debug!("Locked device {} with {}", address, path.display());
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(io_error(e)),
+            }
+            let contents = read_contents(path);
+            match contents.as_deref().and_then(parse_holder) {
+                Some(holder) if holder.pid != std::process::id() && process_alive(holder.pid) => {
+                    return Err(InstanceLockError::Held {
+                        address: address.to_string(),
+                        holder,
+                    });
+                }
+                Some(holder) => warn!("Taking over the lock of device {} left by PID {}", address, holder.pid),
+                None => warn!("Taking over the unreadable lock {}", path.display()),
+            }
+            if !remove_if_unchanged(path, contents.as_deref()).map_err(io_error)? {
+                debug!("The stale lock {} changed before it was removed, checking it again", path.display());
+            }
+        }
+        // Another instance took it over meanwhile
+        match read_holder(path) {
+            Some(holder) => Err(InstanceLockError::Held {
+                address: address.to_string(),
+                holder,
+            }),
+            None => Err(io_error(io::Error::new(io::ErrorKind::AlreadyExists, "lock changed while taking it over"))),
+        }
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Failed to remove the lock {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// The holder written to the lock file at `path`, `None` if it can't be
+/// read
+pub fn read_holder(path: &Path) -> Option<LockHolder> {
+    parse_holder(&read_contents(path)?)
+}
+
+/// The contents of the lock file at `path`, `None` if it can't be read
+fn read_contents(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok()
+}
+
+fn parse_holder(contents: &str) -> Option<LockHolder> {
+    serde_json::from_str(contents.trim()).ok()
+}
+
+/// Removes the stale lock at `path` if it still reads `stale`, as when it
+/// was found stale. Another instance may have taken it over since, removing
+/// the stale lock and linking its own, which must not be removed in turn.
+/// Returns whether the lock is gone; a lock removed meanwhile is, and
+/// linking again decides who takes it.
+fn remove_if_unchanged(path: &Path, stale: Option<&str>) -> io::Result<bool> {
+    let contents = read_contents(path);
+    if contents.is_none() && !path.exists() {
+        return Ok(true);
+    }
+    if contents.as_deref() != stale {
+        return Ok(false);
+    }
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    const ADDRESS: &str = "aa:bb:cc:dd:ee:ff";
+
+    fn write_lock(directory: &Path, contents: &str) -> PathBuf {
+        let path = lock_path(directory, ADDRESS);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn holder_json(pid: u32) -> String {
+        serde_json::to_string(&LockHolder { pid, since: 0.0 }).unwrap() + "\n"
+    }
+
+    /// The PID of a process that ran and was reaped
+    fn dead_pid() -> u32 {
+        let mut child = Command::new("true").spawn().unwrap();
+        let pid = child.id();
+        child.wait().unwrap();
+        pid
+    }
+
+    #[test]
+    fn the_lock_file_is_named_after_the_address() {
+        let path = lock_path(Path::new("/config"), ADDRESS);
+        assert_eq!(path, Path::new("/config/locks/AA-BB-CC-DD-EE-FF.lock"));
+    }
+
+    #[test]
+    fn a_lock_is_held_until_dropped() {
+        let directory = tempfile::tempdir().unwrap();
+        let lock = InstanceLock::acquire(directory.path(), ADDRESS).unwrap();
+        assert_eq!(read_holder(&lock.path).unwrap().pid, std::process::id());
+        let path = lock.path.clone();
+        drop(lock);
+        assert!(!path.exists());
+        // Nothing staged is left beside it
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn a_lock_left_by_a_dead_process_is_taken_over() {
+        let directory = tempfile::tempdir().unwrap();
+        let pid = dead_pid();
+        assert!(!process_alive(pid));
+        write_lock(directory.path(), &holder_json(pid));
+        let lock = InstanceLock::acquire(directory.path(), ADDRESS).unwrap();
+        assert_eq!(read_holder(&lock.path).unwrap().pid, std::process::id());
+    }
+
+    #[test]
+    fn a_lock_of_a_running_process_is_refused() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let path = write_lock(directory.path(), &holder_json(child.id()));
+        let result = InstanceLock::acquire(directory.path(), ADDRESS);
+        child.kill().unwrap();
+        child.wait().unwrap();
+        match result {
+            Err(InstanceLockError::Held { address, holder }) => {
+                assert_eq!(address, ADDRESS);
+                assert_eq!(holder.pid, child.id());
+            }
+            other => panic!("expected the lock to be held, got {:?}", other),
+        }
+        // Left as it was for its holder
+        assert_eq!(fs::read_to_string(path).unwrap(), holder_json(child.id()));
+    }
+
+    #[test]
+    fn an_unreadable_lock_is_taken_over() {
+        let directory = tempfile::tempdir().unwrap();
+        write_lock(directory.path(), "{\"pid\": 12");
+        let lock = InstanceLock::acquire(directory.path(), ADDRESS).unwrap();
+        assert_eq!(read_holder(&lock.path).unwrap().pid, std::process::id());
+    }
+
+    #[test]
+    fn a_stale_lock_replaced_meanwhile_is_not_removed() {
+        let directory = tempfile::tempdir().unwrap();
+        let stale = holder_json(dead_pid());
+        let path = write_lock(directory.path(), &stale);
+        // Another instance took it over after it was found stale
+        fs::write(&path, holder_json(std::process::id())).unwrap();
+        assert!(!remove_if_unchanged(&path, Some(&stale)).unwrap());
+        assert!(path.exists());
+
+        assert!(remove_if_unchanged(&path, Some(&holder_json(std::process::id()))).unwrap());
+        assert!(!path.exists());
+        // Removed by another instance first
+        assert!(remove_if_unchanged(&path, Some(&stale)).unwrap());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::config::keys::{KeyBinding, KeyGesture};
+use crate::config::ConfigStore;
+use crate::gesture::Gesture;
+use crate::output::{SinkErrors, SinkMessage, ThreadedSink};
+use crate::pipeline::SampleEvent;
+use crate::sync::{LockExt, TrackedMutex};
+use enigo::{Direction, Enigo, Key, Keyboard, Settings};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// Starts the key emulation thread. It follows the `keys` section of the
+/// config while running, so bindings can be edited from the GUI.
+pub fn start(app_config: Arc<TrackedMutex<ConfigStore>>) -> ThreadedSink {
+    ThreadedSink::spawn("Keys", |config| config.keys.enabled, move |rx, sink_errors| {
+        let mut keyboard: Option<KeyEmulator> = None;
+        // Don't retry for every gesture, only after toggling
+        let mut failed = false;
+        while let Ok(message) = rx.recv() {
+            let event = match message {
+                SinkMessage::Sample(event) => Some(event),
+                SinkMessage::Flush => None,
+            };
+            let holding = keyboard.as_ref().is_some_and(|k| !k.held.is_empty());
+            // Most samples complete no gesture; only then do bindings matter,
+            // or while a key is held and the bindings could change under it
+            if event.is_some_and(|event| event.gestures.is_empty()) && !holding {
+                continue;
+            }
+            let config = app_config.lock_or_recover().keys.clone();
+            if !config.enabled {
+                if let Some(keyboard) = keyboard.take() {
+                    keyboard.close();
+                }
+                failed = false;
+                continue;
+            }
+            let Some(event) = event else {
+                if let Some(keyboard) = &mut keyboard {
+                    keyboard.release_all();
+                }
+                continue;
+            };
+
+            if keyboard.is_none() && !failed {
+                match KeyEmulator::open(sink_errors.clone()) {
+                    Ok(opened) => keyboard = Some(opened),
+                    Err(e) => {
+                        error!("Failed to start key emulation: {}", e);
+                        sink_errors.report(e);
+                        failed = true;
+                    }
+                }
+            }
+            if let Some(keyboard) = &mut keyboard {
+                keyboard.update(&config.bindings, &event);
+            }
+        }
+
+        if let Some(keyboard) = keyboard {
+            keyboard.close();
+        }
+    })
+}
+
+struct KeyEmulator {
+    enigo: Enigo,
+    /// Bindings the held keys were pressed with
+    bindings: Vec<KeyBinding>,
+    /// Zone and key of every key currently held down
+    held: Vec<(usize, Key)>,
+    sink_errors: SinkErrors,
+}
+
+impl KeyEmulator {
+    fn open(sink_errors: SinkErrors) -> Result<Self, enigo::NewConError> {
+        let enigo = Enigo::new(&Settings::default())?;
+        info!("Key emulation started");
+        Ok(Self {
+            enigo,
+            bindings: Vec::new(),
+            held: Vec::new(),
+            sink_errors,
+        })
+    }
+
+    fn update(&mut self, bindings: &[KeyBinding], event: &SampleEvent) {
+        if bindings != self.bindings {
+            // A held key may no longer be bound to anything that releases it
+            self.release_all();
+            self.bindings = bindings.to_vec();
+        }
+        let zone = event.processed.zone;
+        let gestures = event.gestures;
+
+        if gestures.contains(Gesture::TouchEnd) {
+            let (released, held): (Vec<_>, Vec<_>) = self.held.drain(..).partition(|&(z, _)| z == zone);
+            self.held = held;
+            for (_, key) in released {
+                self.send(key, Direction::Release);
+            }
+        }
+
+        let bound: Vec<(KeyGesture, Key)> = self
+            .bindings
+            .iter()
+            .filter(|binding| binding.zone == zone)
+            .filter_map(|binding| Some((binding.gesture, parse_key(&binding.key)?)))
+            .collect();
+        for (gesture, key) in bound {
+            match gesture {
+                KeyGesture::Hold if gestures.contains(Gesture::Hold) => {
+                    self.send(key, Direction::Press);
+                    self.held.push((zone, key));
+                }
+                KeyGesture::Tap if gestures.contains(Gesture::Tap) => self.send(key, Direction::Click),
+                KeyGesture::DoubleTap if gestures.contains(Gesture::DoubleTap) => {
+                    self.send(key, Direction::Click)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn send(&mut self, key: Key, direction: Direction) {
+        if let Err(e) = self.enigo.key(key, direction) {
+            warn!("Failed to send key {:?}: {}", key, e);
+            self.sink_errors.report(format!("key {:?}: {}", key, e));
+        }
+    }
+
+    fn release_all(&mut self) {
+        for (_, key) in std::mem::take(&mut self.held) {
+            self.send(key, Direction::Release);
+        }
+    }
+
+    fn close(mut self) {
+        self.release_all();
+        info!("Key emulation stopped");
+    }
+}
+
+/// The enigo key for a `KeyBinding::key`.
+fn parse_key(name: &str) -> Option<Key> {
+    let key = match name {
+        "Space" => Key::Space,
+        "Return" => Key::Return,
+        "Tab" => Key::Tab,
+        "Escape" => Key::Escape,
+        "Backspace" => Key::Backspace,
+        "Delete" => Key::Delete,
+        "Up" => Key::UpArrow,
+        "Down" => Key::DownArrow,
+        "Left" => Key::LeftArrow,
+        "Right" => Key::RightArrow,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "F1" => Key::F1,
+        "F2" => Key::F2,
+        "F3" => Key::F3,
+        "F4" => Key::F4,
+        "F5" => Key::F5,
+        "F6" => Key::F6,
+        "F7" => Key::F7,
+        "F8" => Key::F8,
+        "F9" => Key::F9,
+        "F10" => Key::F10,
+        "F11" => Key::F11,
+        "F12" => Key::F12,
+        _ => {
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Key::Unicode(c),
+                _ => return None,
+            }
+        }
+    };
+    Some(key)
+}
@@ -0,0 +1,189 @@
+use crate::config::{LfoMix, LfoWaveform};
+use std::collections::VecDeque;
+use std::f64::consts::TAU;
+use std::time::{Duration, Instant};
+
+/// Taps further apart than this start a new tempo
+const TAP_TIMEOUT: Duration = Duration::from_secs(2);
+/// Taps averaged into the tempo
+const MAX_TAPS: usize = 8;
+
+/// Value of `waveform` at `phase`, counted in cycles since the LFO started,
+/// between -1 and 1. Sine and triangle start at 0 going up; the random
+/// level changes at every whole cycle.
+pub fn waveform_value(waveform: LfoWaveform, phase: f64) -> f64 {
+    let fraction = phase.rem_euclid(1.0);
+    match waveform {
+        LfoWaveform::Sine => (fraction * TAU).sin(),
+        LfoWaveform::Triangle => {
+            if fraction < 0.25 {
+                4.0 * fraction
+            } else if fraction < 0.75 {
+                2.0 - 4.0 * fraction
+            } else {
+                4.0 * fraction - 4.0
+            }
+        }
+        LfoWaveform::Random => {
+            // Hashing the cycle number gives the same level for the whole cycle without any state
+            let bits = splitmix64(phase.floor() as i64 as u64) >> 11;
+            bits as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+        }
+    }
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Combines a Control Change `level` (0..=1) with an LFO `value` (-1..=1)
+/// at `depth` (0..=1). The result is clamped to 0..=1 like unmodulated CC.
+pub fn mix(level: f64, value: f64, depth: f64, mix: LfoMix) -> f64 {
+    // Unipolar, so Add never pushes a resting zone below 0 and Multiply never above its level
+    let unipolar = (value + 1.0) / 2.0;
+    let mixed = match mix {
+        LfoMix::Add => level + depth * unipolar,
+        LfoMix::Multiply => level * (1.0 - depth * unipolar),
+    };
+    mixed.clamp(0.0, 1.0)
+}
+
+/// The LFO's phase, advanced by the time between samples so rate changes
+/// don't make it jump
+#[derive(Debug, Default)]
+pub struct Lfo {
+    /// Cycles since the LFO started
+    phase: f64,
+    last: Option<Instant>,
+}
+
+impl Lfo {
+    /// Moves the phase on to `now` at `rate` cycles per second.
+    pub fn advance(&mut self, now: Instant, rate: f64) {
+        if let Some(last) = self.last {
+            self.phase += now.saturating_duration_since(last).as_secs_f64() * rate;
+        }
+        self.last = Some(now);
+    }
+
+    pub fn value(&self, waveform: LfoWaveform) -> f64 {
+        waveform_value(waveform, self.phase)
+    }
+}
+
+/// Tempo from the intervals between taps of a button
+#[derive(Debug, Default)]
+pub struct TapTempo {
+    taps: VecDeque<Instant>,
+}
+
+impl TapTempo {
+    /// Records a tap and returns the tempo in BPM once there are two taps.
+    pub fn tap(&mut self, now: Instant) -> Option<f64> {
+        if self.taps.back().is_some_and(|&last| now.saturating_duration_since(last) > TAP_TIMEOUT) {
+            self.taps.clear();
+        }
+        self.taps.push_back(now);
+        if self.taps.len() > MAX_TAPS {
+            self.taps.pop_front();
+        }
+        let first = *self.taps.front()?;
+        let intervals = self.taps.len() - 1;
+        if intervals == 0 {
+            return None;
+        }
+        let average = now.saturating_duration_since(first).as_secs_f64() / intervals as f64;
+        (average > 0.0).then(|| 60.0 / average)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LfoConfig;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn sine_and_triangle_at_known_phases() {
+        for (phase, sine, triangle) in [
+            (0.0, 0.0, 0.0),
+            (0.125, 0.5f64.sqrt(), 0.5),
+            (0.25, 1.0, 1.0),
+            (0.5, 0.0, 0.0),
+            (0.75, -1.0, -1.0),
+            (0.875, -(0.5f64.sqrt()), -0.5),
+            (1.25, 1.0, 1.0),
+            (-0.25, -1.0, -1.0),
+        ] {
+            assert_close(waveform_value(LfoWaveform::Sine, phase), sine);
+            assert_close(waveform_value(LfoWaveform::Triangle, phase), triangle);
+        }
+    }
+
+    #[test]
+    fn random_holds_its_level_for_a_cycle() {
+        let level = waveform_value(LfoWaveform::Random, 3.0);
+        assert_eq!(waveform_value(LfoWaveform::Random, 3.5), level);
+        assert_eq!(waveform_value(LfoWaveform::Random, 3.999), level);
+        assert_ne!(waveform_value(LfoWaveform::Random, 4.0), level);
+        for cycle in 0..1000 {
+            assert!((-1.0..1.0).contains(&waveform_value(LfoWaveform::Random, f64::from(cycle))));
+        }
+    }
+
+    #[test]
+    fn mixing_is_unipolar_and_clamped() {
+        // The LFO's -1..1 is 0..1 at depth 1
+        assert_close(mix(0.2, -1.0, 1.0, LfoMix::Add), 0.2);
+        assert_close(mix(0.2, 0.0, 0.5, LfoMix::Add), 0.45);
+        assert_close(mix(0.8, 1.0, 1.0, LfoMix::Add), 1.0);
+        assert_close(mix(0.8, -1.0, 1.0, LfoMix::Multiply), 0.8);
+        assert_close(mix(0.8, 0.0, 0.5, LfoMix::Multiply), 0.6);
+        assert_close(mix(0.8, 1.0, 1.0, LfoMix::Multiply), 0.0);
+        assert_close(mix(0.5, 1.0, 0.0, LfoMix::Add), 0.5);
+        assert_close(mix(1.5, 0.0, 0.0, LfoMix::Multiply), 1.0);
+    }
+
+    #[test]
+    fn phase_advances_by_the_time_between_calls() {
+        let start = Instant::now();
+        let mut lfo = Lfo::default();
+        lfo.advance(start, 2.0);
+        assert_close(lfo.value(LfoWaveform::Triangle), 0.0);
+        // Half a second at 0.5 Hz is a quarter cycle
+        lfo.advance(start + Duration::from_millis(500), 0.5);
+        assert_close(lfo.value(LfoWaveform::Triangle), 1.0);
+        // A rate change only changes how fast it goes on from there
+        lfo.advance(start + Duration::from_millis(750), 1.0);
+        assert_close(lfo.value(LfoWaveform::Triangle), 0.0);
+    }
+
+    #[test]
+    fn synced_rate_follows_the_tempo() {
+        let config = LfoConfig {
+            sync: true,
+            beats_per_cycle: 2.0,
+            ..Default::default()
+        };
+        assert_close(config.rate(120.0), 1.0);
+        let free = LfoConfig::default();
+        assert_close(free.rate(120.0), free.rate_hz);
+    }
+
+    #[test]
+    fn tap_tempo_averages_the_intervals() {
+        let start = Instant::now();
+        let mut tempo = TapTempo::default();
+        assert_eq!(tempo.tap(start), None);
+        assert_close(tempo.tap(start + Duration::from_millis(500)).unwrap(), 120.0);
+        assert_close(tempo.tap(start + Duration::from_millis(1100)).unwrap(), 60.0 / 0.55);
+        // A pause starts over
+        assert_eq!(tempo.tap(start + Duration::from_secs(5)), None);
+    }
+}
@@ -0,0 +1,85 @@
+//! The Dildonica's sample pipeline as a library: `sample` parses the
+//! device's packets, `pipeline` normalizes them against their baselines and
+//! turns them into MIDI and the other outputs, and `config` holds the app and
+//! device settings. A pipeline writes its MIDI to any `MidiSink`, e.g. a
+//! `CaptureMidiSink`, and `Simulator` makes up samples to drive it with.
+//!
+//! The app is the `dildonicaFrontend` binary on top of it: the GUI, the TUI,
+//! BLE and every other frontend live there, so using the library pulls in
+//! none of egui. The hidden modules are the rest of the core the app builds
+//! on; they aren't part of the API.
+
+pub mod config;
+pub mod pipeline;
+pub mod sample;
+
+#[doc(hidden)]
+pub mod auto_gain;
+#[doc(hidden)]
+pub mod batching;
+#[doc(hidden)]
+pub mod bundle;
+#[doc(hidden)]
+pub mod config_compare;
+#[doc(hidden)]
+pub mod config_history;
+#[doc(hidden)]
+pub mod diagnostics;
+#[doc(hidden)]
+pub mod diagnostics_bundle;
+#[doc(hidden)]
+pub mod gesture;
+#[doc(hidden)]
+pub mod instance_lock;
+#[doc(hidden)]
+pub mod lfo;
+#[doc(hidden)]
+pub mod looper;
+#[doc(hidden)]
+pub mod markers;
+#[doc(hidden)]
+pub mod metronome;
+#[doc(hidden)]
+pub mod midi;
+#[doc(hidden)]
+pub mod midi_clock;
+#[doc(hidden)]
+pub mod midi_histogram;
+#[doc(hidden)]
+pub mod midi_mpe;
+#[doc(hidden)]
+pub mod midi_output;
+#[doc(hidden)]
+pub mod output;
+#[doc(hidden)]
+pub mod patch;
+#[doc(hidden)]
+pub mod plot_history;
+#[doc(hidden)]
+pub mod recorder;
+#[doc(hidden)]
+pub mod replay;
+#[doc(hidden)]
+pub mod selftest;
+#[doc(hidden)]
+pub mod simulator;
+#[doc(hidden)]
+pub mod sync;
+#[doc(hidden)]
+pub mod take_buffer;
+#[doc(hidden)]
+pub mod usage;
+#[doc(hidden)]
+pub mod watchdog;
+
+mod device_clock;
+mod drift;
+mod exponential_average;
+mod peak_hold;
+mod percentile;
+mod zone_engine;
+
+pub use midi::{CaptureMidiSink, MidiAction, MidiError, MidiOutputState, MidiSink, PortTarget};
+pub use midi_output::MidiOutputSink;
+pub use simulator::Simulator;
+pub use sync::{tracked, LockExt, TrackedMutex};
@@ -0,0 +1,139 @@
+use crate::sync::LockExt;
+use clap::ValueEnum;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::Mutex;
+use tracing_subscriber::EnvFilter;
+
+/// Log lines kept while captured
+const CAPTURE_LINES: usize = 200;
+/// Log lines always kept for diagnostics bundles
+const RECENT_LINES: usize = 500;
+
+/// While set, log lines are kept here instead of going to stderr, where they
+/// would garble the TUI
+static CAPTURED: Mutex<Option<VecDeque<String>>> = Mutex::new(None);
+/// The last `RECENT_LINES` log lines, wherever they went
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line, for journald or log shippers
+    Json,
+}
+
+/// Installs the global tracing subscriber, which also receives `log` records
+/// from dependencies like btleplug. Logs go to stderr so stdout stays free for
+/// command output, unless a `LogCapture` is active. `RUST_LOG` takes precedence
+/// over the verbosity flags.
+pub fn init(verbose: u8, quiet: bool, format: LogFormat) {
+    let default_directives = if quiet {
+        "warn"
+    } else {
+        // Targets match by prefix, so `dildonica` covers the library and the
+        // `dildonicaFrontend` binary
+        match verbose {
+            0 => "warn,dildonica=info",
+            1 => "info,dildonica=debug",
+            _ => "debug,dildonica=trace",
+        }
+    };
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(default_directives));
+
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(|| LogWriter);
+    match format {
+        LogFormat::Text => builder.with_target(false).init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// Writes to stderr, or into `CAPTURED` while a `LogCapture` exists. The fmt
+/// layer writes each event in one call, so every call is whole lines.
+struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        {
+            let mut recent = RECENT.lock_or_recover();
+            for line in text.lines() {
+                if recent.len() == RECENT_LINES {
+                    recent.pop_front();
+                }
+                recent.push_back(strip_ansi(line));
+            }
+        }
+        let mut captured = CAPTURED.lock_or_recover();
+        let Some(lines) = captured.as_mut() else {
+            drop(captured);
+            return io::stderr().write(buf);
+        };
+        for line in text.lines() {
+            if lines.len() == CAPTURE_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(strip_ansi(line));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Keeps log lines in memory instead of writing them to stderr until dropped,
+/// for a UI that owns the terminal.
+pub struct LogCapture(());
+
+impl LogCapture {
+    pub fn start() -> Self {
+        *CAPTURED.lock_or_recover() = Some(VecDeque::new());
+        Self(())
+    }
+
+    /// The last `count` lines, oldest first
+    pub fn recent(&self, count: usize) -> Vec<String> {
+        let captured = CAPTURED.lock_or_recover();
+        let Some(lines) = captured.as_ref() else {
+            return Vec::new();
+        };
+        lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect()
+    }
+}
+
+impl Drop for LogCapture {
+    fn drop(&mut self) {
+        *CAPTURED.lock_or_recover() = None;
+    }
+}
+
+/// The last log lines of this process, oldest first, also those written to
+/// stderr
+pub fn recent_lines() -> Vec<String> {
+    RECENT.lock_or_recover().iter().cloned().collect()
+}
+
+/// `line` without terminal color codes
+fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip up to and including the final letter of the sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
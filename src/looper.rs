@@ -0,0 +1,385 @@
+use crate::midi::{self, MidiAction, MidiError, MidiOutputState, MidiSink};
+use crate::sync::LockExt;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Longest sleep of the playback task, so a recording of fixed length ends
+/// on time even if nothing wakes the task
+const MAX_SLEEP: Duration = Duration::from_millis(50);
+/// Number of MIDI notes
+const NOTES: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LooperState {
+    /// Nothing recorded yet
+    Empty,
+    /// Capturing the first pass of a phrase
+    Recording,
+    Playing,
+    /// Playing and adding new notes to the phrase
+    Overdubbing,
+    /// A phrase is recorded but not playing
+    Stopped,
+}
+
+impl fmt::Display for LooperState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LooperState::Empty => write!(f, "empty"),
+            LooperState::Recording => write!(f, "recording"),
+            LooperState::Playing => write!(f, "playing"),
+            LooperState::Overdubbing => write!(f, "overdubbing"),
+            LooperState::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum LoopMessage {
+    NoteOn { note: u8, velocity: u8 },
+    NoteOff { note: u8 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LoopEvent {
+    /// Time since the start of the pass
+    offset: Duration,
+    message: LoopMessage,
+    /// Pass it was captured in; it sounded live then, so it isn't played
+    /// again until the next pass
+    captured_in: u64,
+}
+
+/// A phrase of Note On and Off messages captured from the pipeline's
+/// output and repeated by `run`, merged with the live output. Only the
+/// loop's own notes are ever released by it, so live notes keep sounding.
+pub struct Looper {
+    state: LooperState,
+    /// Sorted by offset
+    events: Vec<LoopEvent>,
+    /// Length of the phrase once recorded; while recording the configured
+    /// length, if any
+    length: Option<Duration>,
+    /// Start of the current pass, or of the recording
+    pass_start: Instant,
+    /// Counts passes since the recording started
+    pass: u64,
+    /// Index of the next event to play in the current pass
+    next: usize,
+    /// Notes the loop has turned on and hasn't turned off yet
+    held: [bool; NOTES],
+    /// Held notes must be released at the next poll
+    release_pending: bool,
+    muted: bool,
+}
+
+impl Looper {
+    fn new() -> Self {
+        Self {
+            state: LooperState::Empty,
+            events: Vec::new(),
+            length: None,
+            pass_start: Instant::now(),
+            pass: 0,
+            next: 0,
+            held: [false; NOTES],
+            release_pending: false,
+            muted: false,
+        }
+    }
+
+    pub fn state(&self) -> LooperState {
+        self.state
+    }
+
+    /// Length of the phrase, `None` while a recording of open length runs
+    pub fn length(&self) -> Option<Duration> {
+        self.length
+    }
+
+    /// Time since the start of the current pass or recording
+    pub fn position(&self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.pass_start);
+        match (self.state, self.length) {
+            (LooperState::Playing | LooperState::Overdubbing, Some(length)) if !length.is_zero() => {
+                Duration::from_secs_f64(elapsed.as_secs_f64() % length.as_secs_f64())
+            }
+            (LooperState::Stopped | LooperState::Empty, _) => Duration::ZERO,
+            _ => elapsed,
+        }
+    }
+
+    /// Notes in the phrase
+    pub fn notes(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|event| matches!(event.message, LoopMessage::NoteOn { .. }))
+            .count()
+    }
+
+    /// Starts recording a new phrase of `length`, or of open length, or
+    /// finishes the running recording and starts playing it.
+    pub fn record(&mut self, now: Instant, length: Option<Duration>) {
+        if self.state == LooperState::Recording {
+            self.finish_recording(now);
+            return;
+        }
+        self.events.clear();
+        self.release_pending = true;
+        self.length = length;
+        self.pass_start = now;
+        self.pass = 0;
+        self.next = 0;
+        self.state = LooperState::Recording;
+        info!("Looper recording");
+    }
+
+    fn finish_recording(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.pass_start);
+        let length = *self.length.get_or_insert(elapsed);
+        if length.is_zero() || self.events.is_empty() {
+            info!("Looper recorded nothing");
+            self.clear();
+            return;
+        }
+        // Everything captured so far has sounded live in this pass
+        self.next = self.events.partition_point(|event| event.offset <= elapsed);
+        self.state = LooperState::Playing;
+        info!("Looper playing {} notes over {:.2} s", self.notes(), length.as_secs_f64());
+    }
+
+    /// Toggles overdubbing while the phrase plays.
+    pub fn overdub(&mut self) {
+        self.state = match self.state {
+            LooperState::Playing => LooperState::Overdubbing,
+            LooperState::Overdubbing => LooperState::Playing,
+            state => state,
+        };
+    }
+
+    /// Starts the phrase from the beginning.
+    pub fn play(&mut self, now: Instant) {
+        if self.state == LooperState::Stopped {
+            self.pass_start = now;
+            self.pass += 1;
+            self.next = 0;
+            self.state = LooperState::Playing;
+        }
+    }
+
+    /// Stops playback and releases the loop's notes, keeping the phrase.
+    pub fn stop(&mut self) {
+        if matches!(self.state, LooperState::Playing | LooperState::Overdubbing) {
+            self.state = LooperState::Stopped;
+            self.release_pending = true;
+        }
+    }
+
+    /// Forgets the phrase.
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.length = None;
+        self.state = LooperState::Empty;
+        self.release_pending = true;
+    }
+
+    /// Stops playback like `stop`, and ends a recording without keeping it.
+    pub fn panic(&mut self) {
+        if self.state == LooperState::Recording {
+            self.clear();
+        } else {
+            self.stop();
+        }
+    }
+
+    /// While muted the phrase keeps time but sends nothing.
+    pub fn set_muted(&mut self, muted: bool) {
+        if muted && !self.muted {
+            self.release_pending = true;
+        }
+        self.muted = muted;
+    }
+
+    /// Adds a message the pipeline just sent to the phrase, if recording or
+    /// overdubbing. Only Note On and Note Off are captured.
+    fn capture(&mut self, action: MidiAction, now: Instant) {
+        if !matches!(self.state, LooperState::Recording | LooperState::Overdubbing) {
+            return;
+        }
+        let message = match action {
+            MidiAction::NoteOn { note, velocity } => LoopMessage::NoteOn { note, velocity },
+            MidiAction::NoteOff { note } => LoopMessage::NoteOff { note },
+            MidiAction::ControlChange { .. } | MidiAction::KeyPressure { .. } => return,
+        };
+        let elapsed = now.saturating_duration_since(self.pass_start);
+        // `poll` may not have started the pass `now` falls in yet
+        let (pass, offset) = match self.length {
+            Some(length) if self.state == LooperState::Overdubbing && elapsed >= length => {
+                let passes = (elapsed.as_secs_f64() / length.as_secs_f64()).floor();
+                (self.pass + passes as u64, elapsed.saturating_sub(length.mul_f64(passes)))
+            }
+            _ => (self.pass, elapsed),
+        };
+        let index = self.events.partition_point(|event| event.offset <= offset);
+        self.events.insert(
+            index,
+            LoopEvent {
+                offset,
+                message,
+                captured_in: pass,
+            },
+        );
+        if index < self.next {
+            self.next += 1;
+        }
+    }
+
+    /// Plays the messages due at `now` and returns when the next one is due.
+    fn poll(&mut self, now: Instant, sink: Option<&mut (dyn MidiSink + 'static)>) -> Result<Option<Instant>, MidiError> {
+        let mut sink = sink;
+        if std::mem::take(&mut self.release_pending) {
+            if let Some(sink) = sink.as_deref_mut() {
+                self.release(sink)?;
+            }
+        }
+        if self.state == LooperState::Recording {
+            let end = self.length.map(|length| self.pass_start + length);
+            match end {
+                Some(end) if now >= end => self.finish_recording(now),
+                _ => return Ok(end),
+            }
+        }
+        if !matches!(self.state, LooperState::Playing | LooperState::Overdubbing) {
+            return Ok(None);
+        }
+        let Some(length) = self.length else {
+            return Ok(None);
+        };
+        loop {
+            let elapsed = now.saturating_duration_since(self.pass_start);
+            while let Some(event) = self.events.get(self.next).copied() {
+                if event.offset > elapsed {
+                    break;
+                }
+                self.next += 1;
+                if event.captured_in != self.pass {
+                    if let Some(sink) = sink.as_deref_mut() {
+                        self.send(sink, event.message)?;
+                    }
+                }
+            }
+            if elapsed < length {
+                let next_offset = self.events.get(self.next).map_or(length, |event| event.offset);
+                return Ok(Some(self.pass_start + next_offset));
+            }
+            // A note held across the end of the phrase would otherwise never stop
+            if let Some(sink) = sink.as_deref_mut() {
+                self.release(sink)?;
+            }
+            self.pass_start += length;
+            self.pass += 1;
+            self.next = 0;
+        }
+    }
+
+    fn send(&mut self, sink: &mut dyn MidiSink, message: LoopMessage) -> Result<(), MidiError> {
+        if self.muted {
+            return Ok(());
+        }
+        match message {
+            LoopMessage::NoteOn { note, velocity } => {
+                midi::send_note_on(sink, 0, note, velocity)?;
+                self.held[usize::from(note)] = true;
+            }
+            LoopMessage::NoteOff { note } if self.held[usize::from(note)] => {
+                midi::send_note_off(sink, 0, note)?;
+                self.held[usize::from(note)] = false;
+            }
+            LoopMessage::NoteOff { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn release(&mut self, sink: &mut dyn MidiSink) -> Result<(), MidiError> {
+        for note in 0..NOTES {
+            if self.held[note] {
+                midi::send_note_off(sink, 0, note as u8)?;
+                self.held[note] = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shared handle to the looper, for the pipeline, the GUI and `run`
+#[derive(Clone)]
+pub struct LooperHandle {
+    looper: Arc<Mutex<Looper>>,
+    wake: Arc<Notify>,
+}
+
+impl Default for LooperHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LooperHandle {
+    pub fn new() -> Self {
+        Self {
+            looper: Arc::new(Mutex::new(Looper::new())),
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Runs `f` on the looper and lets the playback task pick up the change.
+    pub fn control<R>(&self, f: impl FnOnce(&mut Looper) -> R) -> R {
+        let result = f(&mut self.looper.lock_or_recover());
+        self.wake.notify_one();
+        result
+    }
+
+    /// Reads the looper without waking the playback task.
+    pub fn inspect<R>(&self, f: impl FnOnce(&Looper) -> R) -> R {
+        f(&self.looper.lock_or_recover())
+    }
+
+    /// Offers a message the pipeline sent at `now` to the phrase.
+    pub fn capture(&self, action: MidiAction, now: Instant) {
+        self.looper.lock_or_recover().capture(action, now);
+    }
+}
+
+/// The playback task: sends the phrase's messages when they are due, until
+/// `shutdown` is cancelled, then releases the loop's notes.
+pub async fn run(looper: LooperHandle, midi_output: Arc<Mutex<MidiOutputState>>, shutdown: CancellationToken) {
+    loop {
+        let now = Instant::now();
+        let due = {
+            let mut midi_output = midi_output.lock_or_recover();
+            let mut state = looper.looper.lock_or_recover();
+            match state.poll(now, midi_output.connection()) {
+                Ok(due) => due,
+                Err(e) => {
+                    midi_output.connection_lost(&e);
+                    None
+                }
+            }
+        };
+        let wake_at = due.map_or(now + MAX_SLEEP, |due| due.min(now + MAX_SLEEP));
+        tokio::select! {
+            _ = tokio::time::sleep_until(wake_at.into()) => {}
+            _ = looper.wake.notified() => {}
+            _ = shutdown.cancelled() => break,
+        }
+    }
+    let mut midi_output = midi_output.lock_or_recover();
+    if let Some(sink) = midi_output.connection() {
+        // Shutting down anyway, a failed Note Off has nowhere to go
+        let _ = looper.looper.lock_or_recover().release(sink);
+    }
+}
@@ -1,275 +1,1637 @@
-mod config;
-mod exponential_average;
+mod artnet;
+mod bench;
+mod ble;
+mod check;
+#[cfg(all(unix, feature = "systemd"))]
+mod daemon;
+mod dfu;
+mod error;
+#[cfg(all(target_os = "linux", feature = "gamepad"))]
+mod gamepad;
+#[cfg(feature = "gui")]
 mod gui;
-mod midi;
-
-use btleplug::api::{Central, CharPropFlags, Manager as _, Peripheral as _, ScanFilter};
-use btleplug::platform::Manager;
-use clap::Parser;
-use config::{
-    read_zone_configs, write_zone_configs,
-    AppConfig, DeviceConfigError, DildonicaZoneConfig,
+mod http;
+#[cfg(feature = "keys")]
+mod keys;
+mod logging;
+mod osc;
+mod printer;
+mod relay;
+#[cfg(all(unix, feature = "systemd"))]
+mod sd_notify;
+#[cfg(feature = "sqlite")]
+mod session_db;
+mod shutdown;
+mod startup;
+#[cfg(feature = "synth")]
+mod synth;
+mod tui;
+mod udp;
+mod ws;
+
+use dildonica::{
+    auto_gain, batching, bundle, config, config_compare, config_history, diagnostics, diagnostics_bundle, gesture,
+    instance_lock, looper, markers, metronome, midi, midi_clock, midi_output, output, patch, pipeline, plot_history,
+    recorder, replay, sample, selftest, simulator, sync, usage, watchdog,
+};
+// Only the window uses these
+#[cfg(feature = "gui")]
+use dildonica::{lfo, midi_histogram, take_buffer};
+use clap::{Args, Parser, Subcommand};
+use crate::error::AppError;
+use crate::auto_gain::AutoGain;
+use crate::batching::{BatchingRequests, BatchingStatus};
+use crate::bench::BenchOptions;
+use crate::check::CheckOptions;
+use crate::ble::{ConfigActivity, SessionEnd, SessionRequests};
+use crate::config::overrides::{overrides_from_env, ConfigOverride};
+use crate::config::{
+    hardware_template, import_c_header, AppConfig, BatchingConfig, ConfigStore, DildonicaZoneConfig, StartupConfig, HARDWARE_TEMPLATES, NUM_ZONES,
 };
-use gui::{PlotApp, ProcessedSample};
-use futures::stream::StreamExt;
-use std::str::FromStr;
+use crate::config_compare::ConfigCompare;
+use crate::config_history::ConfigHistory;
+#[cfg(all(unix, feature = "systemd"))]
+use crate::daemon::DaemonOptions;
+#[cfg(all(unix, feature = "systemd"))]
+use crate::diagnostics::SampleHealth;
+#[cfg(feature = "gui")]
+use crate::diagnostics::SharedStats;
+use crate::diagnostics_bundle::{DeviceInfo, DiagnosticsSnapshot, SampleCapture, SystemInfo};
+use crate::dfu::{DfuError, DfuRequest};
+#[cfg(feature = "gui")]
+use crate::gui::PlotApp;
+use crate::instance_lock::{InstanceLock, InstanceLockError, LockHolder};
+use crate::logging::LogFormat;
+use crate::looper::LooperHandle;
+use crate::markers::SessionMarker;
+use crate::metronome::Metronome;
+use crate::midi_clock::MidiClock;
+#[cfg(feature = "gui")]
+use crate::midi_histogram::MidiHistograms;
+use crate::midi_output::MidiOutputSink;
+use crate::patch::PatchSelector;
+use crate::midi::{MidiOutputState, PortTarget, SustainPedal, ZoneMutes};
+#[cfg(feature = "gui")]
+use crate::output::OutputStatus;
+use crate::pipeline::Pipeline;
+use crate::plot_history::PlotFeed;
+use crate::printer::{PrintFormat, Printer};
+use crate::recorder::Recorder;
+use crate::sample::Sample;
+use crate::selftest::SelfTest;
+use crate::startup::{StartupHandles, StartupProgress};
+#[cfg(feature = "sqlite")]
+use crate::session_db::{DbRecorder, DbRecording};
+use crate::sync::{tracked, LockExt, TrackedMutex};
+use crate::tui::Tui;
+use crate::usage::{StatsFile, UsageStats};
+use crate::watchdog::LinkHealth;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use thiserror::Error;
-use tokio::sync::mpsc;
-use uuid::Uuid;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
-const SERVICE_UUID: Uuid = Uuid::from_u128(0x64696c640000100080000000cafebabe);
-const CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0x6f6e69630000100080000000cafebabe);
-const CONFIG_CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0x6f6e69620000100080000000cafebabe);
-const DEVICE_MAC: &str = "DB:96:90:70:68:A4";
+/// Command line arguments
+#[derive(Parser, Debug)]
+#[command(author, version, about = "Dildonica - BLE sensor to MIDI converter")]
+struct Cli {
+    #[command(flatten)]
+    global: GlobalArgs,
 
+    #[command(subcommand)]
+    command: Option<Command>,
 
+    /// Options for `run`, also accepted without the subcommand name
+    #[command(flatten)]
+    run: RunArgs,
+}
 
-const NUM_ZONES: usize = 8;
+/// Options shared by every subcommand
+#[derive(Args, Debug)]
+struct GlobalArgs {
+    /// Index of the Bluetooth adapter to use (default: the first one)
+    #[arg(long, global = true, value_name = "INDEX")]
+    adapter: Option<usize>,
 
-/// Command line arguments
-#[derive(Parser, Debug)]
-#[command(author, version, about = "Dildonica - BLE sensor to MIDI converter")]
-struct Args {
+    /// Bluetooth address of the device
+    #[arg(long, global = true, value_name = "ADDRESS", default_value = ble::DEVICE_MAC)]
+    device: String,
+
+    /// Config file to load and save instead of the default location
+    #[arg(long, global = true, value_name = "PATH", conflicts_with = "profile")]
+    config: Option<PathBuf>,
+
+    /// Named config profile, stored as <configdir>/profiles/<NAME>.json
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Override a config field for this run only, e.g. midi.note_config.threshold=0.05;
+    /// DILDONICA_SET_MIDI__NOTE_CONFIG__THRESHOLD=0.05 in the environment does the same
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+
+    /// Use a saved zone map preset for this run only; --set zone_map still wins
+    #[arg(long, global = true, value_name = "NAME")]
+    map_preset: Option<String>,
+
+    /// Increase log output (-v, -vv); RUST_LOG overrides this
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Only log warnings and errors
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Log output format; `json` emits one object per line, e.g. for journald
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+}
+
+/// Options of the GUI / headless frontend
+#[derive(Args, Debug)]
+struct RunArgs {
     /// Run in headless mode (no GUI, only MIDI output)
     #[arg(short = 'l', long)]
     headless: bool,
-}
 
-#[derive(Error, Debug)]
-enum SampleError {
-    #[error("Data too short")]
-    DataTooShort,
-    #[error("Invalid zone")]
-    InvalidZone,
-    #[error("BLE error: {0}")]
-    BleError(#[from] btleplug::Error),
-    #[error("Device config error: {0}")]
-    DeviceConfigError(#[from] DeviceConfigError),
-}
+    /// Show live levels and status in the terminal instead of the GUI
+    #[arg(long, conflicts_with_all = ["headless", "print"])]
+    tui: bool,
 
-#[derive(Clone, Copy)]
-struct Sample {
-    timestamp: i32,
-    zone: usize,
-    value: Option<i32>,
-}
+    /// Run headless as a systemd notify service: ready once samples arrive and
+    /// MIDI is up, feeding the watchdog while samples keep arriving
+    #[cfg(all(unix, feature = "systemd"))]
+    #[arg(long, conflicts_with = "tui")]
+    daemon: bool,
 
-impl Sample {
-    fn from_bytes(data: &[u8]) -> Result<Self, SampleError> {
-        if data.len() < 9 {
-            return Err(SampleError::DataTooShort);
-        }
+    /// Exit with an error if --daemon isn't ready within this many seconds [default: 60]
+    #[cfg(all(unix, feature = "systemd"))]
+    #[arg(long, value_name = "SECS", requires = "daemon")]
+    ready_timeout: Option<f64>,
+
+    /// Stop feeding the watchdog after this many seconds without a sample [default: 5]
+    #[cfg(all(unix, feature = "systemd"))]
+    #[arg(long, value_name = "SECS", requires = "daemon")]
+    stall_timeout: Option<f64>,
+
+    /// Don't open any MIDI output port
+    #[arg(long)]
+    no_midi: bool,
+
+    /// Skip the startup sequence of the config's `startup` steps
+    #[arg(long)]
+    no_startup: bool,
+
+    /// Start with all settings locked against accidental changes
+    #[arg(long)]
+    locked: bool,
+
+    /// Print one line per processed sample to stdout
+    #[arg(long)]
+    print: bool,
+
+    /// Line format for --print [default: text]
+    #[arg(long, value_enum, value_name = "FORMAT", requires = "print")]
+    print_format: Option<PrintFormat>,
+
+    /// Only print samples of this output zone
+    #[arg(long, value_name = "N", requires = "print",
+          value_parser = clap::value_parser!(u8).range(..NUM_ZONES as i64))]
+    print_zone: Option<u8>,
+
+    /// Record every sample to this file, replayable with `replay`: appended as
+    /// CSV, or for `.jsonl` a new session bundle that includes the settings
+    #[arg(long, value_name = "PATH")]
+    record: Option<PathBuf>,
+
+    /// Record a session of samples and MIDI events into this SQLite database
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "PATH")]
+    record_db: Option<PathBuf>,
+
+    /// Keep touch counts and active time per zone and day in this JSON file,
+    /// rewritten every minute and added to across restarts
+    #[arg(long, value_name = "PATH")]
+    stats_file: Option<PathBuf>,
+
+    /// Stream processed samples as JSON to WebSocket clients on this localhost port
+    #[arg(long, value_name = "PORT")]
+    ws_port: Option<u16>,
+
+    /// Let WebSocket clients read the config and change the MIDI threshold
+    #[arg(long, requires = "ws_port")]
+    ws_allow_control: bool,
 
-        let timestamp = i32::from_le_bytes(data[0..4].try_into().unwrap());
-        let value = i32::from_le_bytes(data[4..8].try_into().unwrap());
-        let zone = u8::from_le_bytes(data[8..9].try_into().unwrap());
+    /// Relay the raw samples to other instances started with --upstream,
+    /// on this port of every interface
+    #[arg(long, value_name = "PORT", requires = "relay_token")]
+    relay_port: Option<u16>,
 
-        if zone >= NUM_ZONES as u8 {
-            return Err(SampleError::InvalidZone);
+    /// Address the relay listens on [default: 0.0.0.0]
+    #[arg(long, value_name = "ADDR", requires = "relay_port")]
+    relay_bind: Option<IpAddr>,
+
+    /// Take the samples relayed by the instance at this `host:port` instead
+    /// of connecting to the device
+    #[arg(long, value_name = "HOST:PORT", requires = "relay_token", conflicts_with = "bench")]
+    upstream: Option<String>,
+
+    /// Token shared by the relaying instance and those taking its samples
+    #[arg(long, value_name = "TOKEN")]
+    relay_token: Option<String>,
+
+    /// Serve the HTTP API for reading and changing the config on this port
+    #[arg(long, value_name = "PORT")]
+    http_port: Option<u16>,
+
+    /// Address the HTTP API listens on [default: 127.0.0.1]
+    #[arg(long, value_name = "ADDR", requires = "http_port")]
+    http_bind: Option<IpAddr>,
+
+    /// Stop after this many seconds (headless only)
+    #[arg(long, value_name = "SECS")]
+    duration: Option<f64>,
+
+    /// Stop after processing this many samples (headless only)
+    #[arg(long, value_name = "N")]
+    max_samples: Option<u64>,
+
+    /// Instead of connecting, push simulated load through the pipeline and report
+    #[arg(long)]
+    bench: bool,
+
+    /// Samples per second for --bench, across all zones [default: 5000]
+    #[arg(long, value_name = "HZ", requires = "bench")]
+    bench_rate: Option<f64>,
+
+    /// How long --bench runs, in seconds [default: 10]
+    #[arg(long, value_name = "SECS", requires = "bench")]
+    bench_secs: Option<f64>,
+
+    /// Exit with an error if --bench drops more than this percentage of samples [default: 0.1]
+    #[arg(long, value_name = "PERCENT", requires = "bench")]
+    bench_max_drops: Option<f64>,
+}
+
+impl RunArgs {
+    /// Combines `run` options with those given before the subcommand, so
+    /// `dildonica --headless run` means the same as `dildonica run --headless`.
+    fn merged_with(&self, top_level: &RunArgs) -> RunArgs {
+        RunArgs {
+            headless: self.headless || top_level.headless,
+            tui: self.tui || top_level.tui,
+            #[cfg(all(unix, feature = "systemd"))]
+            daemon: self.daemon || top_level.daemon,
+            #[cfg(all(unix, feature = "systemd"))]
+            ready_timeout: self.ready_timeout.or(top_level.ready_timeout),
+            #[cfg(all(unix, feature = "systemd"))]
+            stall_timeout: self.stall_timeout.or(top_level.stall_timeout),
+            no_midi: self.no_midi || top_level.no_midi,
+            no_startup: self.no_startup || top_level.no_startup,
+            locked: self.locked || top_level.locked,
+            print: self.print || top_level.print,
+            print_format: self.print_format.or(top_level.print_format),
+            print_zone: self.print_zone.or(top_level.print_zone),
+            record: self.record.clone().or_else(|| top_level.record.clone()),
+            #[cfg(feature = "sqlite")]
+            record_db: self.record_db.clone().or_else(|| top_level.record_db.clone()),
+            stats_file: self.stats_file.clone().or_else(|| top_level.stats_file.clone()),
+            ws_port: self.ws_port.or(top_level.ws_port),
+            ws_allow_control: self.ws_allow_control || top_level.ws_allow_control,
+            relay_port: self.relay_port.or(top_level.relay_port),
+            relay_bind: self.relay_bind.or(top_level.relay_bind),
+            upstream: self.upstream.clone().or_else(|| top_level.upstream.clone()),
+            relay_token: self.relay_token.clone().or_else(|| top_level.relay_token.clone()),
+            http_port: self.http_port.or(top_level.http_port),
+            http_bind: self.http_bind.or(top_level.http_bind),
+            duration: self.duration.or(top_level.duration),
+            max_samples: self.max_samples.or(top_level.max_samples),
+            bench: self.bench || top_level.bench,
+            bench_rate: self.bench_rate.or(top_level.bench_rate),
+            bench_secs: self.bench_secs.or(top_level.bench_secs),
+            bench_max_drops: self.bench_max_drops.or(top_level.bench_max_drops),
         }
+    }
 
-        Ok(Sample {
-            timestamp,
-            value: if value == 0 { None } else { Some(value) },
-            zone: zone as usize,
-        })
+    /// `--daemon` runs headless too
+    fn headless(&self) -> bool {
+        #[cfg(all(unix, feature = "systemd"))]
+        let daemon = self.daemon;
+        #[cfg(not(all(unix, feature = "systemd")))]
+        let daemon = false;
+        self.headless || daemon
     }
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Connect to the device and run the GUI or headless MIDI output (default)
+    Run(RunArgs),
+    /// List Bluetooth peripherals advertising the Dildonica service
+    Scan {
+        /// Also list peripherals that don't advertise the service
+        #[arg(long)]
+        all: bool,
+    },
+    /// List MIDI output ports
+    Ports {
+        /// Ask which one to use and save it as midi.output_port
+        #[arg(long)]
+        choose: bool,
+    },
+    /// Read or write the zone configuration stored on the device
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Play back a CSV recording instead of connecting to the device
+    Replay {
+        /// CSV recording with timestamp, zone and value columns, or a session bundle
+        file: PathBuf,
 
+        /// Playback speed, 2.0 plays twice as fast
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
 
+        /// Play a session bundle with the MIDI, gesture, zone map and
+        /// averaging settings and device zone configs it was recorded with.
+        /// They aren't saved, and --set still wins.
+        #[arg(long)]
+        with_config: bool,
 
-fn process_sample(
-    sample: Sample,
-    zone_averages: &mut [exponential_average::ExponentialAverage; NUM_ZONES],
-    app_config: &Arc<Mutex<AppConfig>>,
-) -> ProcessedSample {
-    // Find which output zone this device zone maps to
-    let zone = {
-        let config = app_config.lock().unwrap();
-        config.zone_map
-            .iter()
-            .position(|&x| x == sample.zone)
-            .unwrap_or(sample.zone)
-    };
-    let (value_raw, value_normalized) = if let Some(value) = sample.value {
-        let raw = value as f64;
-        zone_averages[zone].update(raw);
-        let average = zone_averages[zone].get_average().unwrap_or(0.0);
-        let normalized = (raw - average) / average;
-        (raw, normalized)
-    } else {
-        (0.0, 0.0)
-    };
+        #[command(flatten)]
+        run: RunArgs,
+    },
+    /// Collect samples from every zone for a few seconds and report whether
+    /// each sends at the expected rate with plausible, quiet readings. Exits
+    /// with status 1 if a zone fails.
+    Selftest {
+        /// Collect for this many seconds instead of `selftest.duration_secs`
+        #[arg(long)]
+        secs: Option<f64>,
+    },
+    /// Save a zip for a bug report: the app config (secrets redacted), the
+    /// device zone configs, version, platform and the log of connecting.
+    /// Saved even if the device can't be reached.
+    Diagnostics {
+        /// Write the bundle to this file [default: dildonica-diagnostics-<time>.zip]
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+        /// Also capture the raw samples for this many seconds, 10 without a value
+        #[arg(long, value_name = "SECS", num_args = 0..=1, default_missing_value = "10")]
+        capture: Option<f64>,
+    },
+    /// Play the simulator through the processing and MIDI mapping with fixed
+    /// configs and compare the MIDI sent with the expected, then round-trip
+    /// the parsers and migrate old configs. Needs no device and reads no
+    /// config; exits with status 1 and a diff if anything differs.
+    Check {
+        /// Compare with this fixture instead of the one built in
+        #[arg(long, value_name = "FILE", conflicts_with = "write_fixture")]
+        fixture: Option<PathBuf>,
+        /// Write the MIDI this build sends to FILE instead of comparing, to
+        /// update fixtures/check.golden after an intended change
+        #[arg(long, value_name = "FILE")]
+        write_fixture: Option<PathBuf>,
+    },
+    /// Inspect sessions recorded with --record-db
+    #[cfg(feature = "sqlite")]
+    #[command(subcommand)]
+    Sessions(SessionsCommand),
+}
 
-    ProcessedSample {
-        zone,
-        timestamp: sample.timestamp,
-        value_raw,
-        value_normalized,
-    }
+#[cfg(feature = "sqlite")]
+#[derive(Subcommand, Debug)]
+enum SessionsCommand {
+    /// List the sessions in a database with their start time, length and size
+    List {
+        file: PathBuf,
+    },
 }
 
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the device zone configuration as JSON
+    Read {
+        /// Write to this file instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Write a JSON zone configuration (as produced by `config read`) to the device
+    Write {
+        #[arg(required_unless_present = "hw_template")]
+        file: Option<PathBuf>,
 
+        /// Write the built-in template of this hardware revision instead of a file
+        #[arg(long, value_name = "NAME", conflicts_with = "file")]
+        hw_template: Option<String>,
+    },
+    /// Print the zone configuration a C header of the firmware initializes
+    /// its zone config array with, as JSON for `config write`
+    ImportHeader {
+        header: PathBuf,
 
+        /// Write to this file instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+}
 
-#[tokio::main]
-async fn main() -> Result<(), SampleError> {
-    // Parse command line arguments
-    let args = Args::parse();
+/// How long to wait for note-offs and the BLE disconnect after the window closes
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
 
-    let sensor_data = Arc::new(Mutex::new(Default::default()));
-    let zone_configs = Arc::new(Mutex::new([DildonicaZoneConfig::default(); NUM_ZONES]));
-    let app_config = Arc::new(Mutex::new(AppConfig::load_from_file()));
-    let (tx, rx) = mpsc::channel(100);
+/// Where the frontend gets its samples from
+enum SampleSource {
+    Device,
+    /// Nothing: another instance holds the device, so neither it nor MIDI
+    /// are opened
+    ViewOnly(LockHolder),
+    /// The samples relayed by another instance, at this `host:port`
+    Upstream(String),
+    Recording {
+        samples: Vec<Sample>,
+        /// Markers of the recording, shown as the replay gets to them
+        markers: Vec<SessionMarker>,
+        speed: f64,
+        /// Device zone configs of a session bundle replayed `--with-config`
+        zone_configs: Option<[DildonicaZoneConfig; NUM_ZONES]>,
+    },
+}
+
+/// Loads the config selected by `--config`/`--profile` and applies overrides,
+/// `replayed` ones (from a session bundle) first.
+fn load_app_config(global: &GlobalArgs, replayed: Vec<ConfigOverride>) -> AppConfig {
+    let config_path = match (&global.config, &global.profile) {
+        (Some(path), _) => path.clone(),
+        (None, Some(profile)) => AppConfig::profile_path(profile),
+        (None, None) => AppConfig::config_file_path(),
+    };
+    let mut loaded_config = AppConfig::load_from_file(&config_path, global.profile.clone());
+    // Command line overrides come last so they win over the environment
+    let mut overrides = replayed;
+    overrides.extend(overrides_from_env());
+    if let Some(name) = &global.map_preset {
+        let Some(preset) = loaded_config.zone_map_preset(name) else {
+            error!("Unknown zone map preset `{}`", name);
+            std::process::exit(2);
+        };
+        overrides.push(ConfigOverride {
+            key: "zone_map".to_string(),
+            value: serde_json::json!(preset.zone_map),
+            original: serde_json::Value::Null,
+        });
+    }
+    for arg in &global.overrides {
+        match ConfigOverride::parse(arg) {
+            Ok(config_override) => overrides.push(config_override),
+            Err(e) => {
+                error!("{}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Err(e) = loaded_config.apply_overrides(overrides) {
+        error!("Failed to apply config overrides: {}", e);
+        std::process::exit(2);
+    }
+    loaded_config
+}
+
+/// Opens the MIDI output port saved in the config. On first run, when no port
+/// has been chosen yet, the first one is used without asking, and saved if it
+/// is the only one. A saved port that is missing leaves the output waiting
+/// for `maintain_connection`.
+async fn open_midi_output(app_config: &Arc<TrackedMutex<ConfigStore>>) -> MidiOutputState {
+    let target = PortTarget::from_config(&app_config.lock_or_recover().midi);
+    let mut midi_output = MidiOutputState::new(target.clone());
+
+    let result = if target == PortTarget::Any {
+        midi::choose_port()
+    } else {
+        midi::open_port(&target).map(|(connection, port_name)| (connection, port_name, false))
+    };
+    match result {
+        Ok((connection, port_name, chosen)) => {
+            let target = if chosen {
+                let mut app_config = app_config.lock_or_recover();
+                app_config.midi.output_port = Some(port_name.clone());
+                app_config.mark_dirty();
+                app_config.flush();
+                PortTarget::Named(port_name.clone())
+            } else {
+                target
+            };
+            midi_output.set_connected(connection, target, port_name);
+        }
+        Err(e) => warn!("MIDI output {} not available, will keep retrying: {}", target, e),
+    }
+    midi_output
+}
+
+/// Runs the GUI (or the TUI, or headless MIDI output) fed by `source`.
+async fn run_frontend(
+    global: &GlobalArgs,
+    run_args: &RunArgs,
+    mut source: SampleSource,
+    replayed: Vec<ConfigOverride>,
+) -> Result<(), AppError> {
+    if !run_args.headless() && (run_args.duration.is_some() || run_args.max_samples.is_some()) {
+        return Err(AppError::InvalidArgs("--duration and --max-samples require --headless"));
+    }
+    if cfg!(not(feature = "gui")) && !run_args.headless() && !run_args.tui {
+        return Err(AppError::InvalidArgs("Built without the gui feature, use --headless or --tui"));
+    }
+    let duration = run_args
+        .duration
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|_| AppError::InvalidArgs("--duration must be a positive number"))?;
+    #[cfg(all(unix, feature = "systemd"))]
+    let daemon_options = DaemonOptions {
+        ready_timeout: Duration::try_from_secs_f64(run_args.ready_timeout.unwrap_or(60.0))
+            .map_err(|_| AppError::InvalidArgs("--ready-timeout must be a positive number"))?,
+        stall_timeout: Duration::try_from_secs_f64(run_args.stall_timeout.unwrap_or(5.0))
+            .map_err(|_| AppError::InvalidArgs("--stall-timeout must be a positive number"))?,
+    };
+
+    let app_config = Arc::new(tracked("app_config", ConfigStore::new(load_app_config(global, replayed))));
+    // Only a device has a connection to hold back and configs to write
+    let startup = app_config.lock_or_recover().startup.clone();
+    let startup = (matches!(source, SampleSource::Device) && !run_args.no_startup && startup.is_active()).then_some(startup);
+    let address = startup
+        .as_ref()
+        .and_then(StartupConfig::connect_to)
+        .unwrap_or(&global.device)
+        .to_string();
+    // A `ConnectTo` step holds the connection back until the sequence gets there
+    let (connect_tx, connect_gate) = match startup.as_ref().and_then(StartupConfig::connect_to) {
+        Some(_) => {
+            let (tx, rx) = oneshot::channel::<()>();
+            (Some(tx), Some(rx))
+        }
+        None => (None, None),
+    };
+    let zone_configs = match &source {
+        SampleSource::Recording {
+            zone_configs: Some(recorded),
+            ..
+        } => *recorded,
+        _ => [DildonicaZoneConfig::default(); NUM_ZONES],
+    };
+    let zone_configs = Arc::new(tracked("zone_configs", zone_configs));
+    let plot_feed = PlotFeed::default();
     let (config_tx, config_rx) = mpsc::channel::<[DildonicaZoneConfig; NUM_ZONES]>(10);
+    // Only the GUI asks for the device configs again, or updates its firmware
+    #[cfg_attr(not(feature = "gui"), allow(unused_variables))]
     let (config_read_tx, config_read_rx) = mpsc::channel::<()>(10);
-    let mut zone_averages = {
-        let config = app_config.lock().unwrap();
-        [exponential_average::ExponentialAverage::new(config.exponential_alpha); NUM_ZONES]
+    #[cfg_attr(not(feature = "gui"), allow(unused_variables))]
+    let (dfu_tx, dfu_rx) = mpsc::channel::<DfuRequest>(1);
+    let config_activity = ConfigActivity::new();
+    let config_compare = ConfigCompare::new();
+    let link_health = LinkHealth::new();
+    #[cfg_attr(not(feature = "gui"), allow(unused_variables))]
+    let (batching_tx, batching_rx) = mpsc::channel::<BatchingConfig>(10);
+    let batching_status = BatchingStatus::new();
+    let mut requests = SessionRequests {
+        configs: config_rx,
+        config_reads: config_read_rx,
+        dfu: dfu_rx,
+        activity: config_activity.clone(),
+        history: Some(ConfigHistory::beside(&app_config.lock_or_recover().config_path)),
+        compare: Some(config_compare.clone()),
+        watchdog: Some(app_config.lock_or_recover().watchdog.clone()),
+        link: link_health.clone(),
+        batching: Some(BatchingRequests {
+            config: app_config.lock_or_recover().batching.clone(),
+            updates: batching_rx,
+            status: batching_status.clone(),
+        }),
     };
-    let mut midi_device = midi::create_midi_device().unwrap();
-    let mut midi_processor = midi::MidiProcessor::new();
 
-    // Spawn BLE connection and data processing task
-    let zone_configs_clone = zone_configs.clone();
+    let shutdown = CancellationToken::new();
+
+    let midi_output = if run_args.no_midi || matches!(source, SampleSource::ViewOnly(_)) {
+        info!("MIDI output disabled");
+        Arc::new(Mutex::new(MidiOutputState::disabled()))
+    } else {
+        let midi_output = Arc::new(Mutex::new(open_midi_output(&app_config).await));
+        tokio::spawn(midi::maintain_connection(midi_output.clone(), app_config.clone(), shutdown.clone()));
+        midi_output
+    };
+    let looper = LooperHandle::new();
+    tokio::spawn(looper::run(looper.clone(), midi_output.clone(), shutdown.clone()));
+    let metronome = Metronome::new();
+    tokio::spawn(metronome::run(
+        metronome.clone(),
+        midi_output.clone(),
+        app_config.clone(),
+        shutdown.clone(),
+    ));
+    let midi_clock = MidiClock::new();
+    tokio::spawn(midi_clock::run(
+        midi_clock.clone(),
+        midi_output.clone(),
+        app_config.clone(),
+        shutdown.clone(),
+    ));
+
+    // Poll the config file so external edits are picked up while running
     let app_config_clone = app_config.clone();
-    let ble_handle = tokio::spawn(async move {
-        println!("Starting");
-
-        let manager = Manager::new().await.unwrap();
-        let adapters = manager.adapters().await.unwrap();
-        let central = adapters
-            .into_iter()
-            .next()
-            .expect("No Bluetooth adapters found");
-
-        central.start_scan(ScanFilter::default()).await.unwrap();
-        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-
-        let peripherals = central.peripherals().await.unwrap();
-        let device = peripherals
-            .into_iter()
-            .find(|p| p.address().to_string() == DEVICE_MAC)
-            .expect("Device not found");
-
-        println!("Connecting to device...");
-        device.connect().await.unwrap();
-
-        println!("Discovering services...");
-        device.discover_services().await.unwrap();
-
-        let chars = device.characteristics();
-        let sample_char = chars
-            .iter()
-            .find(|c| c.uuid == Uuid::from_str(&CHARACTERISTIC_UUID.to_string()).unwrap())
-            .expect("Sample characteristic not found");
-
-        let config_char = chars
-            .iter()
-            .find(|c| c.uuid == Uuid::from_str(&CONFIG_CHARACTERISTIC_UUID.to_string()).unwrap())
-            .expect("Config characteristic not found");
-
-        // Read initial configuration
-        match read_zone_configs(&device, config_char, NUM_ZONES).await {
-            Ok(configs) => {
-                println!("Read initial configuration from device");
-                let configs_array: [DildonicaZoneConfig; NUM_ZONES] = configs.try_into().unwrap();
-                *zone_configs_clone.lock().unwrap() = configs_array;
+    let shutdown_clone = shutdown.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown_clone.cancelled() => return,
             }
-            Err(e) => eprintln!("Failed to read initial configuration: {}", e),
+            let mut app_config = app_config_clone.lock_or_recover();
+            app_config.check_external_change();
+            // Headless there is no GUI saving changes made through the APIs
+            app_config.flush_if_due();
         }
+    });
 
-        // Also trigger a read after startup
-        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-        match read_zone_configs(&device, config_char, NUM_ZONES).await {
-            Ok(configs) => {
-                println!("Re-read configuration from device after startup");
-                let configs_array: [DildonicaZoneConfig; NUM_ZONES] = configs.try_into().unwrap();
-                *zone_configs_clone.lock().unwrap() = configs_array;
-            }
-            Err(e) => eprintln!("Failed to re-read configuration after startup: {}", e),
+    // Nothing is plotted without the GUI or TUI
+    let plot = (!run_args.headless()).then(|| plot_feed.clone());
+    let mut midi = MidiOutputSink::new(midi_output.clone());
+    midi.set_looper(looper.clone());
+    midi.set_midi_clock(midi_clock);
+    let patches = PatchSelector::new();
+    midi.set_patch_selector(patches.clone());
+    let sustain = SustainPedal::new();
+    midi.set_sustain_pedal(sustain.clone());
+    let zone_mutes = ZoneMutes::new();
+    midi.set_zone_mutes(zone_mutes.clone());
+    let mut pipeline = Pipeline::new(app_config.clone(), midi, plot);
+    pipeline.set_metronome(metronome.clone());
+    if let SampleSource::Recording { markers, .. } = &mut source {
+        pipeline.markers().extend(std::mem::take(markers));
+    }
+    if matches!(
+        source,
+        SampleSource::Device | SampleSource::Recording { zone_configs: Some(_), .. }
+    ) {
+        pipeline.set_zone_configs(zone_configs.clone());
+    }
+    let auto_gain = AutoGain::new(&app_config.lock_or_recover().auto_gain);
+    pipeline.set_auto_gain(auto_gain.clone());
+    let usage = UsageStats::new();
+    pipeline.set_usage(usage.clone());
+    let stats_file = match &run_args.stats_file {
+        Some(path) => Some(StatsFile::start(path, usage.clone()).map_err(AppError::file(path))?),
+        None => None,
+    };
+    #[cfg(all(unix, feature = "systemd"))]
+    let daemon = run_args.daemon.then(|| {
+        let health = SampleHealth::new();
+        pipeline.set_health(health.clone());
+        tokio::spawn(daemon::supervise(health, midi_output.clone(), daemon_options, shutdown.clone()))
+    });
+    pipeline.add_output(Box::new(osc::start(app_config.clone())));
+    pipeline.add_output(Box::new(artnet::start(app_config.clone())));
+    #[cfg(feature = "synth")]
+    pipeline.add_output(Box::new(synth::start(app_config.clone())));
+    #[cfg(all(target_os = "linux", feature = "gamepad"))]
+    pipeline.add_output(Box::new(gamepad::start(app_config.clone())));
+    #[cfg(feature = "keys")]
+    pipeline.add_output(Box::new(keys::start(app_config.clone())));
+    if let Some(port) = run_args.ws_port {
+        let tap = ws::start(port, run_args.ws_allow_control, app_config.clone(), shutdown.clone())
+            .await
+            .map_err(|source| AppError::Server {
+                server: "WebSocket server",
+                port,
+                source,
+            })?;
+        pipeline.add_tap(tap);
+    }
+    if let Some(port) = run_args.relay_port {
+        let bind = run_args.relay_bind.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let tap = relay::serve(bind, port, relay_token(run_args)?, shutdown.clone())
+            .await
+            .map_err(|source| AppError::Server {
+                server: "relay",
+                port,
+                source,
+            })?;
+        pipeline.add_tap(tap);
+    }
+    if let Some(port) = run_args.http_port {
+        let state = http::ApiState {
+            app_config: app_config.clone(),
+            zone_configs: zone_configs.clone(),
+            config_tx: matches!(source, SampleSource::Device).then(|| config_tx.clone()),
+            commands: pipeline.command_sender(),
+        };
+        let bind = run_args.http_bind.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        http::start(bind, port, state, shutdown.clone())
+            .await
+            .map_err(|source| AppError::Server {
+                server: "HTTP API",
+                port,
+                source,
+            })?;
+    }
+    #[cfg(feature = "gui")]
+    let selftest = {
+        let (selftest, tap) = SelfTest::start();
+        pipeline.add_tap(tap);
+        selftest
+    };
+    #[cfg(feature = "gui")]
+    let (shared_stats, output_status, sample_capture) = {
+        let shared_stats = SharedStats::new();
+        pipeline.set_shared_stats(shared_stats.clone());
+        let output_status = OutputStatus::new();
+        pipeline.set_output_status(output_status.clone());
+        let (sample_capture, tap) = SampleCapture::start();
+        pipeline.add_tap(tap);
+        (shared_stats, output_status, sample_capture)
+    };
+    #[cfg(feature = "gui")]
+    let midi_histograms = {
+        let (midi_histograms, tap) = MidiHistograms::start();
+        pipeline.add_tap(tap);
+        midi_histograms
+    };
+    let printer = run_args.print.then(|| {
+        let format = run_args.print_format.unwrap_or(PrintFormat::Text);
+        let (printer, tap) = Printer::start(format, run_args.print_zone.map(usize::from));
+        pipeline.add_tap(tap);
+        printer
+    });
+    if let Some(max_samples) = run_args.max_samples {
+        pipeline.set_max_samples(max_samples);
+    }
+    let recorder = match &run_args.record {
+        Some(path) => {
+            let (recorder, tap) = Recorder::start(path, app_config.clone(), zone_configs.clone(), pipeline.markers())
+                .map_err(AppError::file(path))?;
+            pipeline.add_tap(tap);
+            Some(recorder)
         }
-
-        if sample_char.properties.contains(CharPropFlags::NOTIFY) {
-            println!("Subscribing to notifications...");
-            device.subscribe(&sample_char).await.unwrap();
-
-            let mut notification_stream = device.notifications().await.unwrap();
-            println!("Listening for notifications...");
-
-            let mut config_rx = config_rx;
-            let mut config_read_rx = config_read_rx;
-            loop {
-                tokio::select! {
-                    Some(data) = notification_stream.next() => {
-                        match Sample::from_bytes(&data.value) {
-                            Ok(sample) => {
-                                let processed_sample = process_sample(sample, &mut zone_averages, &app_config_clone);
-                                {
-                                    let app_config = app_config_clone.lock().unwrap();
-                                    let _ = midi_processor.process_sample(&mut midi_device, processed_sample.zone, processed_sample.value_normalized, &app_config.midi);
-                                }
-                                if tx.send(processed_sample).await.is_err() {
-                                    println!("Exiting");
-                                    break;
-                                }
-                            }
-                            Err(e) => eprintln!("Error parsing sensor data: {}", e),
-                        };
+        None => None,
+    };
+    #[cfg(feature = "sqlite")]
+    #[cfg_attr(not(feature = "gui"), allow(unused_variables))]
+    let (db_recorder, db_recording) = {
+        let path = run_args.record_db.clone().unwrap_or_else(DbRecording::default_path);
+        let recording = DbRecording::new(path);
+        if run_args.record_db.is_some() {
+            recording.start();
+        }
+        let (db_recorder, tap) =
+            DbRecorder::start(recording.clone(), app_config.clone()).map_err(|source| AppError::SessionDb {
+                path: recording.path().to_path_buf(),
+                source,
+            })?;
+        pipeline.add_tap(tap);
+        (db_recorder, recording)
+    };
+    let consumers = Consumers {
+        printer,
+        recorder,
+        stats_file,
+        #[cfg(feature = "sqlite")]
+        db_recorder,
+    };
+    let from_device = matches!(source, SampleSource::Device);
+    #[cfg_attr(not(feature = "gui"), allow(unused_variables))]
+    let view_only = match &source {
+        SampleSource::ViewOnly(holder) => Some(holder.clone()),
+        _ => None,
+    };
+    let commands = pipeline.command_sender();
+    #[cfg_attr(not(feature = "gui"), allow(unused_variables))]
+    let takes = pipeline.takes();
+    #[cfg_attr(not(feature = "gui"), allow(unused_variables))]
+    let markers = pipeline.markers();
+    #[cfg_attr(not(feature = "gui"), allow(unused_variables))]
+    let startup_progress = startup.map(|startup| {
+        let progress = StartupProgress::new();
+        let handles = StartupHandles {
+            app_config: app_config.clone(),
+            commands: commands.clone(),
+            configs: config_tx.clone(),
+            activity: config_activity.clone(),
+            history: ConfigHistory::beside(&app_config.lock_or_recover().config_path),
+            link: link_health.clone(),
+            connect: connect_tx,
+        };
+        tokio::spawn(startup::run(startup.actions, handles, progress.clone()));
+        progress
+    });
+    let zone_configs_clone = zone_configs.clone();
+    let app_config_clone = app_config.clone();
+    let adapter = global.adapter;
+    let locked_address = global.device.clone();
+    let upstream_token = run_args.relay_token.clone().unwrap_or_default();
+    let address_clone = address.clone();
+    let shutdown_clone = shutdown.clone();
+    let source_task = async move {
+        let result: Result<(), AppError> = match source {
+            SampleSource::Device => {
+                let address = address_clone;
+                if let Some(gate) = connect_gate {
+                    info!("Waiting for the startup sequence to connect");
+                    tokio::select! {
+                        // Sent or dropped, the sequence got there or ended before
+                        _ = gate => {}
+                        _ = shutdown_clone.cancelled() => return Ok(()),
                     }
-                    Some(new_configs) = config_rx.recv() => {
-                        println!("Writing new configuration to device...");
-                        match write_zone_configs(&device, config_char, &new_configs).await {
-                            Ok(()) => {
-                                println!("Configuration written successfully");
-                                *zone_configs_clone.lock().unwrap() = new_configs;
+                }
+                // `run` locked `--device`, the sequence may connect to another
+                let _lock = if address.eq_ignore_ascii_case(&locked_address) {
+                    None
+                } else {
+                    acquire_device_lock(&address)?
+                };
+                info!("Starting");
+                // Read at every connect, so edits in the GUI apply at the next one
+                let uuids = || app_config_clone.lock_or_recover().ble;
+                let first = uuids();
+                let mut connection = tokio::select! {
+                    connection = ble::connect(adapter, &address, &first) => connection?,
+                    _ = shutdown_clone.cancelled() => return Ok(()),
+                };
+                // A firmware update ends the session; once it's done the device is connected again
+                loop {
+                    let end = ble::run_session(
+                        connection,
+                        &mut pipeline,
+                        zone_configs_clone.clone(),
+                        &mut requests,
+                        &shutdown_clone,
+                    )
+                    .await?;
+                    connection = match end {
+                        SessionEnd::Closed => break Ok(()),
+                        SessionEnd::FirmwareUpdate(update) => {
+                            match dfu::update_and_reconnect(adapter, &address, &uuids(), &update, &shutdown_clone).await {
+                                Ok(connection) => connection,
+                                Err(DfuError::Cancelled) => break Ok(()),
+                                Err(e) => break Err(e.into()),
                             }
-                            Err(e) => eprintln!("Failed to write configuration: {}", e),
                         }
-                    }
-                    Some(()) = config_read_rx.recv() => {
-                        println!("Reading configuration from device...");
-                        match read_zone_configs(&device, config_char, NUM_ZONES).await {
-                            Ok(configs) => {
-                                println!("Configuration read successfully");
-                                let configs_array: [DildonicaZoneConfig; NUM_ZONES] = configs.try_into().unwrap();
-                                *zone_configs_clone.lock().unwrap() = configs_array;
+                        SessionEnd::Stalled => {
+                            let uuids = uuids();
+                            tokio::select! {
+                                connection = ble::connect(adapter, &address, &uuids) => connection?,
+                                _ = shutdown_clone.cancelled() => break Ok(()),
                             }
-                            Err(e) => eprintln!("Failed to read configuration: {}", e),
                         }
-                    }
+                    };
                 }
             }
-        } else {
-            println!("Sample characteristic does not support notifications");
+            SampleSource::Upstream(upstream) => relay::consume(&upstream, &upstream_token, &mut pipeline, &shutdown_clone)
+                .await
+                .map_err(AppError::from),
+            SampleSource::Recording { samples, speed, .. } => {
+                replay::replay(samples, speed, &mut pipeline, shutdown_clone).await;
+                Ok(())
+            }
+            SampleSource::ViewOnly(_) => {
+                shutdown_clone.cancelled().await;
+                Ok(())
+            }
+        };
+        info!("Processed {}", pipeline.stats());
+        result
+    };
+
+    if let Some(duration) = duration {
+        let shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = tokio::time::sleep(duration) => {
+                    info!("Ran for {:.1} s, stopping", duration.as_secs_f64());
+                    shutdown.cancel();
+                }
+                _ = shutdown.cancelled() => {}
+            }
+        });
+    }
+
+    // Every frontend stops on Ctrl+C; the GUI closes its window for it
+    shutdown::spawn_signal_handler(shutdown.clone());
+
+    if run_args.headless() {
+        info!("Running in headless mode (MIDI output only)");
+        // Keep the program running until the source ends, but once shutdown
+        // starts only give it SHUTDOWN_TIMEOUT to release notes and disconnect
+        let stalled = async {
+            shutdown.cancelled().await;
+            tokio::time::sleep(SHUTDOWN_TIMEOUT).await;
+        };
+        let result = tokio::select! {
+            result = source_task => Some(result),
+            _ = stalled => None,
+        };
+        shutdown.cancel();
+        let result = match result {
+            Some(result) => {
+                consumers.finish();
+                result
+            }
+            None => {
+                warn!("Timed out waiting for the device to disconnect");
+                Ok(())
+            }
+        };
+        app_config.lock_or_recover().flush();
+        #[cfg(all(unix, feature = "systemd"))]
+        if let Some(daemon) = daemon {
+            if let Ok(Err(e)) = daemon.await {
+                return Err(e.into());
+            }
+        }
+        return result;
+    }
+
+    let source_handle = tokio::spawn(async move {
+        if let Err(e) = source_task.await {
+            error!("Device connection failed: {}", e);
         }
     });
 
-    // Run GUI if not in headless mode
-    if !args.headless {
+    if run_args.tui {
+        let source_name = if from_device {
+            "device"
+        } else if run_args.upstream.is_some() {
+            "upstream"
+        } else {
+            "recording"
+        };
+        let tui = Tui::new(plot_feed, app_config.clone(), zone_configs, midi_output, commands, source_name);
+        let result = tui.run(shutdown.clone()).await;
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, source_handle).await.is_err() {
+            warn!("Timed out waiting for the device to disconnect");
+        } else {
+            consumers.finish();
+        }
+        app_config.lock_or_recover().flush();
+        return result.map_err(AppError::Terminal);
+    }
+    #[cfg(feature = "gui")]
+    {
         let options = eframe::NativeOptions::default();
+        let locked = run_args.locked;
+        let device = (address, global.adapter);
+        let gui_shutdown = shutdown.clone();
         eframe::run_native(
             "Dildonica Sensor Data Plot",
             options,
             Box::new(move |_cc| {
-                Ok(Box::new(PlotApp::new(
-                    sensor_data,
-                    rx,
+                let mut app = PlotApp::new(
+                    plot_feed,
                     zone_configs,
                     config_tx,
                     config_read_tx,
                     app_config,
-                )))
+                    midi_output,
+                );
+                app.locked = locked;
+                app.shutdown = Some(gui_shutdown);
+                app.looper = Some(looper);
+                app.metronome = Some(metronome);
+                app.takes = Some(takes);
+                app.markers = markers;
+                app.patches = patches;
+                app.auto_gain = Some(auto_gain);
+                app.usage = Some(usage);
+                app.sustain = sustain;
+                app.zone_mutes = zone_mutes;
+                app.selftest = Some(selftest);
+                app.midi_histograms = Some(midi_histograms);
+                app.device = device;
+                app.shared_stats = Some(shared_stats);
+                app.outputs = Some(output_status);
+                app.sample_capture = Some(sample_capture);
+                app.dfu_tx = from_device.then_some(dfu_tx);
+                app.config_activity = config_activity;
+                app.config_compare = from_device.then_some(config_compare);
+                app.view_only = view_only;
+                app.link_health = from_device.then_some(link_health);
+                app.batching_tx = from_device.then_some(batching_tx);
+                app.batching_status = from_device.then_some(batching_status);
+                app.startup = startup_progress;
+                #[cfg(feature = "sqlite")]
+                {
+                    app.db_recording = Some(db_recording);
+                }
+                // A recording has no device to read or write configs from
+                if !from_device {
+                    app.config_tx = None;
+                    app.config_read_tx = None;
+                }
+                Ok(Box::new(app))
             }),
-        )
-        .unwrap();
+        )?;
+
+        // The window is closed: give the source a moment to release notes and disconnect
+        shutdown.cancel();
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, source_handle).await.is_err() {
+            warn!("Timed out waiting for the device to disconnect");
+        } else {
+            consumers.finish();
+        }
+    }
+    Ok(())
+}
+
+/// Output threads fed from pipeline taps; the output sinks end with the
+/// pipeline itself
+struct Consumers {
+    printer: Option<Printer>,
+    recorder: Option<Recorder>,
+    stats_file: Option<StatsFile>,
+    #[cfg(feature = "sqlite")]
+    db_recorder: DbRecorder,
+}
+
+impl Consumers {
+    /// Waits for all output to be written out. Only call this once the source
+    /// has dropped the pipeline, which ends their input.
+    fn finish(self) {
+        if let Some(printer) = self.printer {
+            printer.finish();
+        }
+        if let Some(recorder) = self.recorder {
+            recorder.finish();
+        }
+        if let Some(stats_file) = self.stats_file {
+            stats_file.finish();
+        }
+        #[cfg(feature = "sqlite")]
+        self.db_recorder.finish();
+    }
+}
+
+async fn run(global: &GlobalArgs, run_args: &RunArgs) -> Result<(), AppError> {
+    if run_args.bench {
+        return bench(global, run_args).await;
+    }
+    // Another instance holds the device and its lock
+    if let Some(upstream) = &run_args.upstream {
+        relay_token(run_args)?;
+        return run_frontend(global, run_args, SampleSource::Upstream(upstream.clone()), Vec::new()).await;
+    }
+    // The GUI still shows the config of an instance started twice; headless
+    // there is nothing to do without the device
+    let gui = !run_args.headless() && !run_args.tui;
+    match acquire_device_lock(&global.device) {
+        Err(InstanceLockError::Held { address, holder }) if gui => {
+            warn!("Device {} is in use by {}, starting view-only without BLE and MIDI", address, holder);
+            run_frontend(global, run_args, SampleSource::ViewOnly(holder), Vec::new()).await
+        }
+        Err(e) => Err(e.into()),
+        Ok(_lock) => run_frontend(global, run_args, SampleSource::Device, Vec::new()).await,
+    }
+}
+
+/// The `--relay-token`, which clap already requires with the relay options
+fn relay_token(run_args: &RunArgs) -> Result<String, AppError> {
+    match &run_args.relay_token {
+        Some(token) if !token.trim().is_empty() => Ok(token.clone()),
+        _ => Err(AppError::InvalidArgs("--relay-token must not be empty")),
+    }
+}
+
+/// Locks the device at `address` against other instances of the app. A
+/// lock directory that can't be written only warns, the lock then protects
+/// nothing.
+fn acquire_device_lock(address: &str) -> Result<Option<InstanceLock>, InstanceLockError> {
+    // The default config directory, so profiles and --config share the locks
+    let config_path = AppConfig::config_file_path();
+    let directory = config_path.parent().unwrap_or(Path::new("."));
+    match InstanceLock::acquire(directory, address) {
+        Ok(lock) => Ok(Some(lock)),
+        Err(e @ InstanceLockError::Held { .. }) => Err(e),
+        Err(e) => {
+            warn!("{}; not guarding against other instances", e);
+            Ok(None)
+        }
+    }
+}
+
+async fn bench(global: &GlobalArgs, run_args: &RunArgs) -> Result<(), AppError> {
+    let options = BenchOptions {
+        rate: run_args.bench_rate.unwrap_or(5000.0),
+        duration: Duration::try_from_secs_f64(run_args.bench_secs.unwrap_or(10.0))
+            .map_err(|_| AppError::InvalidArgs("--bench-secs must be a positive number"))?,
+        max_drop_percent: run_args.bench_max_drops.unwrap_or(0.1),
+    };
+    if options.rate <= 0.0 {
+        return Err(AppError::InvalidArgs("--bench-rate must be positive"));
+    }
+    Ok(bench::run(load_app_config(global, Vec::new()), &options).await?)
+}
+
+async fn scan(global: &GlobalArgs, all: bool) -> Result<(), AppError> {
+    info!("Scanning...");
+    let results = ble::scan(global.adapter, &load_app_config(global, Vec::new()).ble).await?;
+    let mut found = false;
+    for result in results.iter().filter(|r| all || r.advertises_service) {
+        let rssi = result.rssi.map_or("?".to_string(), |rssi| format!("{} dBm", rssi));
+        let name = result.name.as_deref().unwrap_or("(unknown)");
+        println!("{}  {:>8}  {}", result.address, rssi, name);
+        found = true;
+    }
+    if !found {
+        println!("No devices found");
+    }
+    Ok(())
+}
+
+async fn ports(global: &GlobalArgs, choose: bool) -> Result<(), AppError> {
+    let ports = midi::list_output_ports();
+    if ports.is_empty() {
+        println!("No MIDI output ports found");
+        return Ok(());
+    }
+    if !choose {
+        for (i, port) in ports.iter().enumerate() {
+            println!("{}: {}", i, port);
+        }
+        return Ok(());
+    }
+
+    if !(io::stdin().is_terminal() && io::stdout().is_terminal()) {
+        return Err(AppError::InvalidArgs("ports --choose needs a terminal"));
+    }
+    let Some(index) = midi::prompt_for_port(&ports).await else {
+        warn!("No output port selected, leaving the config as it is");
+        return Ok(());
+    };
+    let mut app_config = load_app_config(global, Vec::new());
+    app_config.midi.output_port = Some(ports[index].clone());
+    app_config.midi.virtual_port = false;
+    app_config.save_to_file()?;
+    info!("Saved {} as the MIDI output port", ports[index]);
+    Ok(())
+}
+
+async fn config_read(global: &GlobalArgs, output: Option<&Path>) -> Result<(), AppError> {
+    let uuids = load_app_config(global, Vec::new()).ble;
+    let _lock = acquire_device_lock(&global.device)?;
+    let connection = ble::connect(global.adapter, &global.device, &uuids).await?;
+    let configs = connection.read_configs().await?;
+    let json = serde_json::to_string_pretty(&configs)?;
+    match output {
+        Some(path) => {
+            fs::write(path, json + "\n").map_err(AppError::file(path))?;
+            info!("Wrote device configuration to {}", path.display());
+        }
+        None => println!("{}", json),
+    }
+    connection.disconnect().await?;
+    Ok(())
+}
+
+/// Writes the configs in `file`, or those of the hardware template named
+/// `template`; clap makes sure there is one of them.
+async fn config_write(global: &GlobalArgs, file: Option<&Path>, template: Option<&str>) -> Result<(), AppError> {
+    let app_config = load_app_config(global, Vec::new());
+    let (configs, limits): (Vec<DildonicaZoneConfig>, _) = match (template, file) {
+        (Some(name), _) => {
+            let template = hardware_template(name).ok_or_else(|| AppError::UnknownTemplate {
+                name: name.to_string(),
+                known: HARDWARE_TEMPLATES.iter().map(|template| template.name).collect::<Vec<_>>().join(", "),
+            })?;
+            info!("Writing the {} template: {}", template.name, template.description);
+            (template.zones.to_vec(), template.limits)
+        }
+        (None, Some(file)) => {
+            let contents = fs::read_to_string(file).map_err(AppError::file(file))?;
+            (serde_json::from_str(&contents)?, app_config.zone_limits(&global.device))
+        }
+        (None, None) => return Err(AppError::InvalidArgs("Give a config file or --hw-template")),
+    };
+    if configs.len() != NUM_ZONES {
+        return Err(AppError::ZoneCount {
+            expected: NUM_ZONES,
+            found: configs.len(),
+        });
+    }
+    // The device would store something else than asked for
+    for (zone, config) in configs.iter().enumerate() {
+        config
+            .validate_within(&limits)
+            .map_err(|message| AppError::InvalidZoneConfig { zone, message })?;
+    }
+    let uuids = app_config.ble;
+    let _lock = acquire_device_lock(&global.device)?;
+    let connection = ble::connect(global.adapter, &global.device, &uuids).await?;
+    connection.write_configs(&configs).await?;
+    info!("Configuration written successfully");
+    connection.disconnect().await?;
+    Ok(())
+}
+
+/// Prints the configs of `header` as `config read` prints the device's;
+/// the device isn't needed.
+fn config_import_header(header: &Path, output: Option<&Path>) -> Result<(), AppError> {
+    let configs = import_c_header(header)?;
+    if configs.len() != NUM_ZONES {
+        return Err(AppError::ZoneCount {
+            expected: NUM_ZONES,
+            found: configs.len(),
+        });
+    }
+    let json = serde_json::to_string_pretty(&configs)?;
+    match output {
+        Some(path) => {
+            fs::write(path, json + "\n").map_err(AppError::file(path))?;
+            info!("Wrote the configuration of {} to {}", header.display(), path.display());
+        }
+        None => println!("{}", json),
+    }
+    Ok(())
+}
+
+/// Longer than this past the test's duration without it finishing, the
+/// device has stopped sending and the report is made from what arrived
+const SELFTEST_GRACE: Duration = Duration::from_secs(5);
+
+async fn selftest(global: &GlobalArgs, secs: Option<f64>) -> Result<(), AppError> {
+    let app_config = load_app_config(global, Vec::new());
+    let config = app_config.selftest.clone();
+    let duration = match secs {
+        Some(secs) => Duration::try_from_secs_f64(secs)
+            .ok()
+            .filter(|duration| !duration.is_zero())
+            .ok_or(AppError::InvalidArgs("--secs must be a positive number"))?,
+        None => config.duration(),
+    };
+    let uuids = app_config.ble;
+    let app_config = Arc::new(tracked("app_config", ConfigStore::new(app_config)));
+    let _lock = acquire_device_lock(&global.device)?;
+    let connection = ble::connect(global.adapter, &global.device, &uuids).await?;
+
+    let zone_configs = Arc::new(tracked("zone_configs", [DildonicaZoneConfig::default(); NUM_ZONES]));
+    let midi_output = Arc::new(Mutex::new(MidiOutputState::disabled()));
+    let mut pipeline = Pipeline::new(app_config, MidiOutputSink::new(midi_output), None);
+    pipeline.set_zone_configs(zone_configs.clone());
+    let (selftest, tap) = SelfTest::start();
+    pipeline.add_tap(tap);
+    let (_config_tx, configs) = mpsc::channel(1);
+    let (_config_read_tx, config_reads) = mpsc::channel(1);
+    let (_dfu_tx, dfu) = mpsc::channel(1);
+    let mut requests = SessionRequests {
+        configs,
+        config_reads,
+        dfu,
+        activity: ConfigActivity::new(),
+        history: None,
+        compare: None,
+        watchdog: None,
+        link: LinkHealth::new(),
+        batching: None,
+    };
+    let shutdown = CancellationToken::new();
+    shutdown::spawn_signal_handler(shutdown.clone());
+
+    selftest.begin();
+    let stop = async {
+        let deadline = tokio::time::Instant::now() + duration + SELFTEST_GRACE;
+        while selftest.elapsed() < duration && tokio::time::Instant::now() < deadline {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+                _ = shutdown.cancelled() => return,
+            }
+        }
+        shutdown.cancel();
+    };
+    let session = ble::run_session(
+        connection,
+        &mut pipeline,
+        zone_configs.clone(),
+        &mut requests,
+        &shutdown,
+    );
+    let (result, ()) = tokio::join!(session, stop);
+    result?;
+
+    let report = selftest.finish(&zone_configs.lock_or_recover(), &config);
+    println!("{}", report);
+    if report.passed() {
+        Ok(())
     } else {
-        println!("Running in headless mode (MIDI output only)");
-        // Keep the program running in headless mode
-        ble_handle.await.unwrap();
+        Err(AppError::SelfTestFailed)
     }
+}
+
+/// Longer than this past the capture's duration without it finishing, the
+/// device has stopped sending and the bundle holds what arrived
+const CAPTURE_GRACE: Duration = Duration::from_secs(5);
+
+async fn diagnostics(global: &GlobalArgs, output: Option<&Path>, capture: Option<f64>) -> Result<(), AppError> {
+    let capture_duration = capture
+        .map(|secs| {
+            Duration::try_from_secs_f64(secs)
+                .ok()
+                .filter(|duration| !duration.is_zero())
+                .ok_or(AppError::InvalidArgs("--capture must be a positive number"))
+        })
+        .transpose()?;
+    let path = output.map_or_else(
+        || PathBuf::from(diagnostics_bundle::file_name(chrono::Local::now())),
+        Path::to_path_buf,
+    );
+    let app_config = load_app_config(global, Vec::new());
+    let zone_configs = Arc::new(tracked("zone_configs", [DildonicaZoneConfig::default(); NUM_ZONES]));
+    let mut blob = None;
+    let mut stats = None;
+    let mut samples = None;
+    let mut markers = Vec::new();
+
+    // An unreachable device is what many reports are about, so that is
+    // recorded rather than failing, as is another instance holding it
+    let connection = match acquire_device_lock(&global.device) {
+        Ok(lock) => ble::connect(global.adapter, &global.device, &app_config.ble)
+            .await
+            .map(|connection| (connection, lock))
+            .map_err(AppError::from),
+        Err(e) => Err(e.into()),
+    };
+    let error = match connection {
+        Err(e) => {
+            error!("Failed to connect: {}", e);
+            Some(e.to_string())
+        }
+        Ok((connection, _lock)) => match capture_duration {
+            None => {
+                let result = match connection.read_config_blob().await {
+                    Ok(read) => {
+                        let parsed = connection.parse_configs(&read);
+                        blob = Some(read);
+                        parsed.map(|configs| *zone_configs.lock_or_recover() = configs)
+                    }
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = connection.disconnect().await {
+                    warn!("Failed to disconnect: {}", e);
+                }
+                result.err().map(|e| e.to_string())
+            }
+            Some(duration) => {
+                let app_config = Arc::new(tracked("app_config", ConfigStore::new(app_config.clone())));
+                let midi_output = Arc::new(Mutex::new(MidiOutputState::disabled()));
+                let mut pipeline = Pipeline::new(app_config, MidiOutputSink::new(midi_output), None);
+                pipeline.set_zone_configs(zone_configs.clone());
+                let (capture, tap) = SampleCapture::start();
+                pipeline.add_tap(tap);
+                let (_config_tx, configs) = mpsc::channel(1);
+                let (_config_read_tx, config_reads) = mpsc::channel(1);
+                let (_dfu_tx, dfu) = mpsc::channel(1);
+                let activity = ConfigActivity::new();
+                let mut requests = SessionRequests {
+                    configs,
+                    config_reads,
+                    dfu,
+                    activity: activity.clone(),
+                    history: None,
+                    compare: None,
+                    watchdog: None,
+                    link: LinkHealth::new(),
+                    batching: None,
+                };
+                let shutdown = CancellationToken::new();
+                shutdown::spawn_signal_handler(shutdown.clone());
 
+                capture.begin(duration);
+                let stop = async {
+                    let deadline = tokio::time::Instant::now() + duration + CAPTURE_GRACE;
+                    while capture.progress().is_some() && tokio::time::Instant::now() < deadline {
+                        tokio::select! {
+                            _ = tokio::time::sleep(Duration::from_millis(100)) => {}
+                            _ = shutdown.cancelled() => return,
+                        }
+                    }
+                    shutdown.cancel();
+                };
+                let session = ble::run_session(
+                    connection,
+                    &mut pipeline,
+                    zone_configs.clone(),
+                    &mut requests,
+                    &shutdown,
+                );
+                let (result, ()) = tokio::join!(session, stop);
+                blob = activity.config_blob();
+                stats = Some(pipeline.stats().snapshot());
+                samples = Some(capture.stop());
+                markers = pipeline.markers().all();
+                let read_error = match activity.take_finished() {
+                    Some((_, Err(e))) => Some(e),
+                    _ => None,
+                };
+                result.err().map(|e| e.to_string()).or(read_error)
+            }
+        },
+    };
+
+    let snapshot = DiagnosticsSnapshot {
+        system: SystemInfo::current(),
+        device: DeviceInfo::new(
+            global.device.clone(),
+            global.adapter,
+            error,
+            blob.as_deref(),
+            *zone_configs.lock_or_recover(),
+        ),
+        stats,
+        log_lines: logging::recent_lines(),
+        capture: samples,
+        markers,
+        app_config,
+    };
+    diagnostics_bundle::save_bundle(&path, &snapshot).map_err(|source| AppError::DiagnosticsBundle {
+        path: path.clone(),
+        source,
+    })?;
+    println!("{}", path.display());
     Ok(())
 }
+
+async fn replay(
+    global: &GlobalArgs,
+    file: &Path,
+    speed: f64,
+    with_config: bool,
+    run_args: &RunArgs,
+) -> Result<(), AppError> {
+    if speed <= 0.0 {
+        return Err(AppError::InvalidArgs("Replay speed must be positive"));
+    }
+    let recording = replay::read_recording(file)?;
+    let (replayed, zone_configs) = match (&recording.header, with_config) {
+        (Some(header), true) => {
+            info!("Using the settings recorded with dildonica {}", header.app_version);
+            (
+                header.config_overrides().map_err(replay::ReplayError::from)?,
+                Some(header.zone_configs().map_err(replay::ReplayError::from)?),
+            )
+        }
+        (None, true) => return Err(AppError::InvalidArgs("--with-config requires a session bundle")),
+        (_, false) => (Vec::new(), None),
+    };
+    let samples = recording.samples;
+    info!("Replaying {} samples from {}", samples.len(), file.display());
+    let source = SampleSource::Recording {
+        samples,
+        markers: recording.markers,
+        speed,
+        zone_configs,
+    };
+    run_frontend(global, run_args, source, replayed).await
+}
+
+#[tokio::main]
+async fn main() {
+    // Parse command line arguments
+    let cli = Cli::parse();
+
+    logging::init(cli.global.verbose, cli.global.quiet, cli.global.log_format);
+
+    if let Err(e) = execute(&cli).await {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Runs the subcommand `cli` asks for, the frontend without one
+async fn execute(cli: &Cli) -> Result<(), AppError> {
+    let global = &cli.global;
+    match &cli.command {
+        None => run(global, &cli.run).await,
+        Some(Command::Run(run_args)) => run(global, &run_args.merged_with(&cli.run)).await,
+        Some(Command::Scan { all }) => scan(global, *all).await,
+        Some(Command::Ports { choose }) => ports(global, *choose).await,
+        Some(Command::Config(ConfigCommand::Read { output })) => config_read(global, output.as_deref()).await,
+        Some(Command::Config(ConfigCommand::Write { file, hw_template })) => {
+            config_write(global, file.as_deref(), hw_template.as_deref()).await
+        }
+        Some(Command::Config(ConfigCommand::ImportHeader { header, output })) => config_import_header(header, output.as_deref()),
+        Some(Command::Selftest { secs }) => selftest(global, *secs).await,
+        Some(Command::Diagnostics { output, capture }) => diagnostics(global, output.as_deref(), *capture).await,
+        Some(Command::Check { fixture, write_fixture }) => check::run(&CheckOptions {
+            fixture: fixture.clone(),
+            write_fixture: write_fixture.clone(),
+        })
+        .map_err(AppError::from),
+        Some(Command::Replay {
+            file,
+            speed,
+            with_config,
+            run,
+        }) => replay(global, file, *speed, *with_config, &run.merged_with(&cli.run)).await,
+        #[cfg(feature = "sqlite")]
+        Some(Command::Sessions(SessionsCommand::List { file })) => {
+            session_db::list_sessions(file).map_err(|source| AppError::SessionDb {
+                path: file.clone(),
+                source,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HeaderError;
+    use replay::ReplayError;
+    use tempfile::TempDir;
+
+    /// Runs the command line `args` with a config file of its own in `dir`
+    async fn execute_in(dir: &TempDir, args: &[&str]) -> Result<(), AppError> {
+        let config = dir.path().join("config.json");
+        let cli = Cli::try_parse_from(
+            ["dildonica", "--config", config.to_str().unwrap()].iter().chain(args),
+        )
+        .unwrap();
+        execute(&cli).await
+    }
+
+    /// A one-sample CSV recording in `dir`
+    fn recording(dir: &TempDir) -> String {
+        let path = dir.path().join("take.csv");
+        fs::write(&path, "timestamp,zone,value\n0,0,5000\n").unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn replay_refuses_a_speed_that_isnt_positive() {
+        let dir = TempDir::new().unwrap();
+        let file = recording(&dir);
+        let result = execute_in(&dir, &["replay", &file, "--speed", "0"]).await;
+        assert!(matches!(result, Err(AppError::InvalidArgs("Replay speed must be positive"))));
+    }
+
+    #[tokio::test]
+    async fn replaying_a_missing_file_names_it() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("missing.csv");
+        let error = execute_in(&dir, &["replay", file.to_str().unwrap()]).await.unwrap_err();
+        assert!(matches!(error, AppError::Replay(ReplayError::Io(..))));
+        assert!(error.to_string().contains("missing.csv"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn replay_with_config_needs_a_session_bundle() {
+        let dir = TempDir::new().unwrap();
+        let file = recording(&dir);
+        let result = execute_in(&dir, &["replay", &file, "--with-config"]).await;
+        assert!(matches!(result, Err(AppError::InvalidArgs("--with-config requires a session bundle"))));
+    }
+
+    #[tokio::test]
+    async fn duration_needs_headless() {
+        let dir = TempDir::new().unwrap();
+        let file = recording(&dir);
+        let result = execute_in(&dir, &["replay", &file, "--duration", "1"]).await;
+        assert!(matches!(result, Err(AppError::InvalidArgs("--duration and --max-samples require --headless"))));
+    }
+
+    #[tokio::test]
+    async fn config_write_names_a_missing_file() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("zones.json");
+        let error = execute_in(&dir, &["config", "write", file.to_str().unwrap()]).await.unwrap_err();
+        assert!(matches!(&error, AppError::File { path, .. } if *path == file));
+        assert!(error.to_string().contains("zones.json"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn config_write_refuses_invalid_json_and_the_wrong_zone_count() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("zones.json");
+        fs::write(&file, "[{").unwrap();
+        let result = execute_in(&dir, &["config", "write", file.to_str().unwrap()]).await;
+        assert!(matches!(result, Err(AppError::Json(_))));
+
+        fs::write(&file, "[]").unwrap();
+        let result = execute_in(&dir, &["config", "write", file.to_str().unwrap()]).await;
+        assert!(matches!(result, Err(AppError::ZoneCount { expected: NUM_ZONES, found: 0 })));
+    }
+
+    #[tokio::test]
+    async fn config_write_refuses_an_unknown_template() {
+        let dir = TempDir::new().unwrap();
+        let error = execute_in(&dir, &["config", "write", "--hw-template", "nope"]).await.unwrap_err();
+        assert!(matches!(&error, AppError::UnknownTemplate { name, .. } if name == "nope"));
+    }
+
+    #[tokio::test]
+    async fn importing_a_missing_header_names_it() {
+        let dir = TempDir::new().unwrap();
+        let header = dir.path().join("zones.h");
+        let error = execute_in(&dir, &["config", "import-header", header.to_str().unwrap()]).await.unwrap_err();
+        assert!(matches!(error, AppError::Header(HeaderError::Io { .. })));
+        assert!(error.to_string().contains("zones.h"), "{}", error);
+    }
+}
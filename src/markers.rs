@@ -0,0 +1,312 @@
+//! Named markers dropped into the timeline of a session ("good take",
+//! "glitch here"), from the GUI or a zone gesture, and how recordings keep
+//! them.
+
+use crate::sync::LockExt;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Name of a marker dropped without one
+pub const DEFAULT_MARKER_NAME: &str = "Marker";
+/// Longer names are cut, they label a line in the plot
+pub const MAX_MARKER_NAME_CHARS: usize = 64;
+/// Starts the markers section a CSV recording's session ends with
+pub const CSV_MARKERS_HEADER: &str = "# markers: host_time,device_timestamp,zone,name";
+/// Starts every line of the markers section
+const CSV_MARKER_PREFIX: &str = "# marker,";
+
+/// A named point in a session's timeline, at the sample handled after it
+/// was dropped
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionMarker {
+    /// Host time the sample was handled at, seconds since the Unix epoch
+    pub host_time: f64,
+    /// Device timestamp of the sample in ms, where the plot shows it
+    pub timestamp: i32,
+    pub name: String,
+    /// The zone whose gesture dropped it, `None` from the GUI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<usize>,
+}
+
+impl SessionMarker {
+    /// Device time in seconds, the time axis of the plot
+    pub fn time(&self) -> f64 {
+        f64::from(self.timestamp) / 1000.0
+    }
+
+    /// The line of the markers section of a CSV recording; the name goes
+    /// last, so it may hold commas
+    pub fn csv_line(&self) -> String {
+        format!(
+            "{}{:.3},{},{},{}",
+            CSV_MARKER_PREFIX,
+            self.host_time,
+            self.timestamp,
+            self.zone.map_or_else(String::new, |zone| zone.to_string()),
+            self.name
+        )
+    }
+
+    /// The marker of a CSV recording's line, `None` for any other line
+    pub fn from_csv_line(line: &str) -> Option<Result<Self, String>> {
+        line.strip_prefix(CSV_MARKER_PREFIX).map(Self::from_csv_fields)
+    }
+
+    fn from_csv_fields(fields: &str) -> Result<Self, String> {
+        let mut fields = fields.splitn(4, ',');
+        let mut field = |name: &str| fields.next().map(str::trim).ok_or_else(|| format!("marker has no {}", name));
+        let host_time = field("host_time")?.parse().map_err(|e| format!("invalid marker host_time: {}", e))?;
+        let timestamp = field("device_timestamp")?.parse().map_err(|e| format!("invalid marker timestamp: {}", e))?;
+        let zone = match field("zone")? {
+            "" => None,
+            zone => Some(zone.parse().map_err(|e| format!("invalid marker zone: {}", e))?),
+        };
+        Ok(Self {
+            host_time,
+            timestamp,
+            zone,
+            name: field("name")?.to_string(),
+        })
+    }
+}
+
+/// `name` fit to label a plot line and a line of a recording: on one line,
+/// trimmed and at most `MAX_MARKER_NAME_CHARS`, `DEFAULT_MARKER_NAME` if
+/// nothing is left
+pub fn marker_name(name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(MAX_MARKER_NAME_CHARS)
+        .collect();
+    match name.trim() {
+        "" => DEFAULT_MARKER_NAME.to_string(),
+        name => name.to_string(),
+    }
+}
+
+#[derive(Debug, Default)]
+struct MarkerState {
+    /// Oldest first
+    markers: Vec<SessionMarker>,
+    /// Names dropped since the last sample
+    requested: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    /// Whether `requested` holds any, so samples don't lock for nothing
+    pending: AtomicBool,
+    state: Mutex<MarkerState>,
+}
+
+/// The markers of the session: dropped from the GUI, stamped by the
+/// pipeline with the next sample, and read by the plot, the recorder, takes
+/// and diagnostics bundles. Kept for the whole session, whatever the plot
+/// still holds.
+#[derive(Debug, Clone, Default)]
+pub struct SessionMarkers {
+    shared: Arc<Shared>,
+}
+
+impl SessionMarkers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops a marker called `name` at the next sample, so not while no
+    /// samples arrive.
+    pub fn request(&self, name: &str) {
+        self.shared.state.lock_or_recover().requested.push(marker_name(name));
+        self.shared.pending.store(true, Ordering::Relaxed);
+    }
+
+    /// Stamps the markers requested since the last sample, and one called
+    /// `name` for the gesture of `zone` if it dropped one, with the sample
+    /// of device `timestamp` handled at `host_time`.
+    pub fn stamp(&self, host_time: f64, timestamp: i32, gesture: Option<(usize, &str)>) {
+        if gesture.is_none() && !self.shared.pending.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut state = self.shared.state.lock_or_recover();
+        self.shared.pending.store(false, Ordering::Relaxed);
+        let requested = std::mem::take(&mut state.requested);
+        let marker = |name, zone| SessionMarker {
+            host_time,
+            timestamp,
+            name,
+            zone,
+        };
+        let dropped: Vec<SessionMarker> = requested
+            .into_iter()
+            .map(|name| marker(name, None))
+            .chain(gesture.map(|(zone, name)| marker(marker_name(name), Some(zone))))
+            .collect();
+        state.markers.extend(dropped);
+    }
+
+    /// Adds the markers of a replayed recording, and ones stamped elsewhere.
+    pub fn extend(&self, markers: impl IntoIterator<Item = SessionMarker>) {
+        self.shared.state.lock_or_recover().markers.extend(markers);
+    }
+
+    pub fn len(&self) -> usize {
+        self.shared.state.lock_or_recover().markers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every marker so far, oldest first
+    pub fn all(&self) -> Vec<SessionMarker> {
+        self.shared.state.lock_or_recover().markers.clone()
+    }
+
+    /// The markers at host times from `start` on, e.g. those of a take
+    pub fn since(&self, start: f64) -> Vec<SessionMarker> {
+        let state = self.shared.state.lock_or_recover();
+        state.markers.iter().filter(|marker| marker.host_time >= start).cloned().collect()
+    }
+
+    /// The markers at device times within `start..=end` seconds, each with
+    /// its number in the session counted from 1, for the plot window
+    pub fn within(&self, start: f64, end: f64) -> Vec<(usize, SessionMarker)> {
+        let state = self.shared.state.lock_or_recover();
+        state
+            .markers
+            .iter()
+            .enumerate()
+            .filter(|(_, marker)| (start..=end).contains(&marker.time()))
+            .map(|(index, marker)| (index + 1, marker.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bundle::{self, BundleHeader, BundleRecord, BundleSample};
+    use crate::config::{AppConfig, HARDWARE_TEMPLATES};
+    use crate::recorder::{write_markers, RecordFormat};
+    use crate::replay;
+    use crate::simulator::Simulator;
+
+    fn marker(host_time: f64, timestamp: i32, name: &str, zone: Option<usize>) -> SessionMarker {
+        SessionMarker {
+            host_time,
+            timestamp,
+            name: name.to_string(),
+            zone,
+        }
+    }
+
+    /// Two markers dropped from the GUI and one by zone 3's gesture, at
+    /// three samples
+    fn stamped() -> SessionMarkers {
+        let markers = SessionMarkers::new();
+        markers.request(" good\ttake ");
+        markers.request("");
+        markers.stamp(1000.5, 1500, None);
+        markers.stamp(1001.0, 2500, None);
+        markers.stamp(1002.25, 3500, Some((3, "hold, here")));
+        markers
+    }
+
+    fn expected() -> [SessionMarker; 3] {
+        [
+            marker(1000.5, 1500, "good take", None),
+            marker(1000.5, 1500, DEFAULT_MARKER_NAME, None),
+            marker(1002.25, 3500, "hold, here", Some(3)),
+        ]
+    }
+
+    #[test]
+    fn names_fit_on_one_line() {
+        assert_eq!(marker_name("  glitch here "), "glitch here");
+        assert_eq!(marker_name("a\nb\tc"), "a b c");
+        assert_eq!(marker_name(""), DEFAULT_MARKER_NAME);
+        assert_eq!(marker_name(" \t\n"), DEFAULT_MARKER_NAME);
+        assert_eq!(marker_name(&"x".repeat(100)).chars().count(), MAX_MARKER_NAME_CHARS);
+    }
+
+    #[test]
+    fn nothing_is_stamped_without_being_dropped() {
+        let markers = SessionMarkers::new();
+        markers.stamp(1000.0, 1000, None);
+        assert!(markers.is_empty());
+    }
+
+    #[test]
+    fn markers_are_stamped_with_the_next_sample_in_order() {
+        let markers = stamped();
+        assert_eq!(markers.all(), expected());
+        assert_eq!(markers.len(), 3);
+    }
+
+    #[test]
+    fn markers_are_picked_by_device_and_host_time() {
+        let markers = stamped();
+        let window: Vec<usize> = markers.within(1.0, 2.0).into_iter().map(|(number, _)| number).collect();
+        assert_eq!(window, [1, 2]);
+        let window: Vec<usize> = markers.within(3.5, 10.0).into_iter().map(|(number, _)| number).collect();
+        assert_eq!(window, [3]);
+        assert_eq!(markers.since(1001.0), expected()[2..]);
+        assert_eq!(markers.since(1000.0).len(), 3);
+    }
+
+    #[test]
+    fn extending_adds_markers_after_those_stamped() {
+        let markers = stamped();
+        markers.extend([marker(1003.0, 4000, "replayed", None)]);
+        assert_eq!(markers.all().last().unwrap().name, "replayed");
+        assert_eq!(markers.len(), 4);
+    }
+
+    #[test]
+    fn csv_lines_round_trip() {
+        for marker in expected() {
+            assert_eq!(SessionMarker::from_csv_line(&marker.csv_line()), Some(Ok(marker)));
+        }
+        assert_eq!(SessionMarker::from_csv_line("1000.5,1500,0,0,1200"), None);
+        for line in ["# marker,", "# marker,x,1500,,a", "# marker,1000.5,1500,z,a", "# marker,1000.5,1500"] {
+            assert!(matches!(SessionMarker::from_csv_line(line), Some(Err(_))), "{}", line);
+        }
+    }
+
+    #[test]
+    fn csv_recordings_keep_their_markers() {
+        let mut csv = b"host_time,device_timestamp,device_zone,zone,raw,normalized,derivative,peak\n\
+            1000.500,1500,0,0,1200,0.5,0,0\n"
+            .to_vec();
+        write_markers(&mut csv, RecordFormat::Csv, &expected()).unwrap();
+        let (samples, parsed) = replay::read_csv(&String::from_utf8(csv).unwrap()).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(parsed, expected());
+        let mut empty = Vec::new();
+        write_markers(&mut empty, RecordFormat::Csv, &[]).unwrap();
+        assert!(empty.is_empty(), "no markers section without markers");
+    }
+
+    #[test]
+    fn bundles_keep_their_markers() {
+        let zones = &HARDWARE_TEMPLATES[0].zones;
+        let header = BundleRecord::Header(BundleHeader::new(1000.0, &AppConfig::default(), zones));
+        let sample = BundleRecord::Sample(BundleSample::new(1000.5, &Simulator::new().sample(0)));
+        let mut contents = Vec::new();
+        for record in [header, sample] {
+            serde_json::to_writer(&mut contents, &record).unwrap();
+            contents.push(b'\n');
+        }
+        write_markers(&mut contents, RecordFormat::Bundle, &expected()).unwrap();
+        let contents = String::from_utf8(contents).unwrap();
+        let (_, samples, parsed) = bundle::parse_bundle(&contents).unwrap();
+        assert_eq!(samples.len(), 1);
+        assert_eq!(parsed, expected());
+        let headless = contents.lines().skip(2).collect::<Vec<_>>().join("\n");
+        assert!(bundle::parse_bundle(&headless).is_err());
+    }
+}
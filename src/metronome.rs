@@ -0,0 +1,135 @@
+use crate::config::{ConfigStore, MetronomeClick, MetronomeConfig};
+use crate::midi::MidiOutputState;
+use crate::sync::{LockExt, TrackedMutex};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// How often `run` checks whether the metronome was started
+const IDLE_INTERVAL: Duration = Duration::from_millis(50);
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const CONTROL_CHANGE: u8 = 0xB0;
+
+/// The metronome, started and stopped from the MIDI tab and followed by
+/// `run`; panic stops it and mute silences it, like the looper
+#[derive(Debug, Clone, Default)]
+pub struct Metronome {
+    running: Arc<AtomicBool>,
+    muted: Arc<AtomicBool>,
+    /// Beat of the bar clicked last, from 0
+    beat: Arc<AtomicU32>,
+}
+
+impl Metronome {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    /// Starts clicking, on the first beat of a bar.
+    pub fn start(&self) {
+        if !self.running.swap(true, Ordering::Relaxed) {
+            info!("Metronome started");
+        }
+    }
+
+    pub fn stop(&self) {
+        if self.running.swap(false, Ordering::Relaxed) {
+            info!("Metronome stopped");
+        }
+    }
+
+    pub fn toggle(&self) {
+        if self.is_running() {
+            self.stop();
+        } else {
+            self.start();
+        }
+    }
+
+    /// While muted the beats go on without sending anything.
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    /// Beat of the bar clicked last, from 0
+    pub fn beat(&self) -> u32 {
+        self.beat.load(Ordering::Relaxed)
+    }
+}
+
+/// Sends `message` if MIDI is connected.
+fn send(midi_output: &Mutex<MidiOutputState>, message: &[u8]) {
+    let mut midi_output = midi_output.lock_or_recover();
+    if let Some(Err(e)) = midi_output.connection().map(|connection| connection.send(message)) {
+        midi_output.connection_lost(&e);
+    }
+}
+
+/// The message turning a click on at `velocity`, and the one turning it off
+fn click_messages(config: &MetronomeConfig, velocity: u8) -> ([u8; 3], [u8; 3]) {
+    let channel = config.channel & 0x0F;
+    match config.click {
+        MetronomeClick::Note => (
+            [NOTE_ON | channel, config.number, velocity.max(1)],
+            [NOTE_OFF | channel, config.number, 0],
+        ),
+        MetronomeClick::ControlChange => (
+            [CONTROL_CHANGE | channel, config.number, velocity],
+            [CONTROL_CHANGE | channel, config.number, 0],
+        ),
+    }
+}
+
+/// The metronome task: while started, clicks every beat at `midi.metronome`
+/// as it is at that beat, so a new tempo applies from the next one. Beats
+/// are kept on a steady grid; one that runs late is skipped rather than
+/// made up. Stops with `shutdown`, turning off a click that is on.
+pub async fn run(
+    metronome: Metronome,
+    midi_output: Arc<Mutex<MidiOutputState>>,
+    app_config: Arc<TrackedMutex<ConfigStore>>,
+    shutdown: CancellationToken,
+) {
+    // The next beat and when it is due, while running
+    let mut next: Option<(u32, Instant)> = None;
+    loop {
+        let wake = if metronome.is_running() {
+            let config = app_config.lock_or_recover().midi.metronome.clone();
+            let (beat, due) = next.unwrap_or((0, Instant::now()));
+            let beat = beat % config.beats_per_bar;
+            metronome.beat.store(beat, Ordering::Relaxed);
+            if !metronome.muted.load(Ordering::Relaxed) {
+                let velocity = if beat == 0 { config.accent_velocity } else { config.velocity };
+                let (on, off) = click_messages(&config, velocity);
+                send(&midi_output, &on);
+                tokio::select! {
+                    _ = tokio::time::sleep(config.click_length()) => {}
+                    _ = shutdown.cancelled() => {}
+                }
+                send(&midi_output, &off);
+            }
+            let now = Instant::now();
+            let mut due = due + config.beat_length();
+            if due < now {
+                due = now + config.beat_length();
+            }
+            next = Some((beat + 1, due));
+            due
+        } else {
+            next = None;
+            Instant::now() + IDLE_INTERVAL
+        };
+        tokio::select! {
+            _ = tokio::time::sleep_until(wake.into()) => {}
+            _ = shutdown.cancelled() => break,
+        }
+    }
+}
@@ -1,158 +1,1567 @@
-use crate::config::{ControlChangeConfig, MidiConfig, MidiOutputMethod, NoteConfig};
-use midir::{MidiOutput, MidiOutputConnection, MidiOutputPort};
-use std::error::Error;
+use crate::config::{
+    ConfigStore, ControlChangeConfig, MidiConfig, MidiMapping, MidiOutputMethod, NoteConfig,
+    RequantizeMode, NUM_ZONES,
+};
+use crate::lfo::{self, Lfo};
+use crate::midi_mpe::MPEKeyboard;
+use crate::pipeline::ProcessedSample;
+use crate::sync::{LockExt, TrackedMutex};
+use midir::{ConnectError, ConnectErrorKind, MidiOutput, MidiOutputConnection};
+use serde::Serialize;
+use std::fmt;
 use std::io::{stdin, stdout, Write};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument, warn};
 
+const CLIENT_NAME: &str = "My Virtual MIDI Device";
+const PORT_NAME: &str = "Dildonica MIDI";
+/// How often a missing output port is looked for again
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+/// How long `prompt_for_port` waits for an answer
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Error, Debug)]
+pub enum MidiError {
+    #[error(transparent)]
+    Init(#[from] midir::InitError),
+    #[error("no output port found")]
+    NoPorts,
+    #[error("port not found")]
+    PortNotFound,
+    #[cfg(not(unix))]
+    #[error("virtual MIDI ports are not supported on this platform")]
+    VirtualUnsupported,
+    #[error(transparent)]
+    PortInfo(#[from] midir::PortInfoError),
+    #[error("{0}")]
+    Connect(ConnectErrorKind),
+    #[error(transparent)]
+    Send(#[from] midir::SendError),
+}
+
+// The error carries the unconnected `MidiOutput` back, which isn't needed here
+impl<T> From<ConnectError<T>> for MidiError {
+    fn from(e: ConnectError<T>) -> Self {
+        MidiError::Connect(e.kind())
+    }
+}
+
+/// Which output port the config asks for
+#[derive(Debug, Clone, PartialEq)]
+pub enum PortTarget {
+    /// No port chosen yet: use the first one available
+    Any,
+    Named(String),
+    /// A virtual port created by us
+    Virtual,
+}
+
+impl PortTarget {
+    pub fn from_config(config: &MidiConfig) -> Self {
+        match (&config.output_port, config.virtual_port) {
+            (_, true) => PortTarget::Virtual,
+            (Some(name), false) => PortTarget::Named(name.clone()),
+            (None, false) => PortTarget::Any,
+        }
+    }
+}
+
+impl fmt::Display for PortTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortTarget::Any => write!(f, "any port"),
+            PortTarget::Named(name) => write!(f, "{}", name),
+            PortTarget::Virtual => write!(f, "virtual port {}", PORT_NAME),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MidiStatus {
+    /// The port isn't available (yet); retried in the background
+    Waiting(PortTarget),
+    Connected(String),
+    /// Started with `--no-midi`
+    Disabled,
+}
+
+impl fmt::Display for MidiStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiStatus::Waiting(target) => write!(f, "waiting for {}", target),
+            MidiStatus::Connected(port) => write!(f, "connected to {}", port),
+            MidiStatus::Disabled => write!(f, "disabled"),
+        }
+    }
+}
+
+/// Something MIDI messages can be sent to: a real output port, or a sink
+/// that discards them for benchmarking.
+pub trait MidiSink: Send {
+    fn send(&mut self, message: &[u8]) -> Result<(), MidiError>;
+}
+
+impl MidiSink for MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) -> Result<(), MidiError> {
+        MidiOutputConnection::send(self, message)?;
+        Ok(())
+    }
+}
+
+/// Discards every message
+pub struct NullMidiSink;
+
+impl MidiSink for NullMidiSink {
+    fn send(&mut self, _message: &[u8]) -> Result<(), MidiError> {
+        Ok(())
+    }
+}
+
+/// Keeps every message, for comparing what a run sent with what it should
+/// have; clones share the messages
+#[derive(Debug, Clone, Default)]
+pub struct CaptureMidiSink {
+    messages: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl CaptureMidiSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The messages sent since the last call, oldest first
+    pub fn take(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut *self.messages.lock_or_recover())
+    }
+}
+
+impl MidiSink for CaptureMidiSink {
+    fn send(&mut self, message: &[u8]) -> Result<(), MidiError> {
+        self.messages.lock_or_recover().push(message.to_vec());
+        Ok(())
+    }
+}
+
+/// The MIDI output connection shared between the processing task, which
+/// sends on it, and `maintain_connection`, which (re)opens it.
+pub struct MidiOutputState {
+    connection: Option<Box<dyn MidiSink>>,
+    /// Target the connection was opened for, so config changes trigger a reconnect
+    target: PortTarget,
+    status: MidiStatus,
+}
+
+impl MidiOutputState {
+    pub fn new(target: PortTarget) -> Self {
+        Self {
+            connection: None,
+            status: MidiStatus::Waiting(target.clone()),
+            target,
+        }
+    }
+
+    /// An output that is never connected
+    pub fn disabled() -> Self {
+        Self {
+            connection: None,
+            target: PortTarget::Any,
+            status: MidiStatus::Disabled,
+        }
+    }
+
+    pub fn status(&self) -> &MidiStatus {
+        &self.status
+    }
+
+    pub fn connection(&mut self) -> Option<&mut (dyn MidiSink + 'static)> {
+        self.connection.as_deref_mut()
+    }
+
+    pub fn set_connected(&mut self, connection: impl MidiSink + 'static, target: PortTarget, port_name: String) {
+        info!("MIDI output connected to {}", port_name);
+        self.connection = Some(Box::new(connection));
+        self.target = target;
+        self.status = MidiStatus::Connected(port_name);
+    }
+
+    /// Drops a connection that failed to send, so it gets reopened.
+    pub fn connection_lost(&mut self, error: &MidiError) {
+        if self.connection.take().is_some() {
+            warn!("MIDI output lost: {}", error);
+            self.status = MidiStatus::Waiting(self.target.clone());
+        }
+    }
+}
+
+/// Keeps the output connected to the port chosen in the config, retrying in
+/// the background while it is missing and reconnecting when the choice changes.
+pub async fn maintain_connection(
+    midi_output: Arc<Mutex<MidiOutputState>>,
+    app_config: Arc<TrackedMutex<ConfigStore>>,
+    shutdown: CancellationToken,
+) {
+    let mut interval = tokio::time::interval(RETRY_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.cancelled() => return,
+        }
+        let target = PortTarget::from_config(&app_config.lock_or_recover().midi);
+        {
+            let state = midi_output.lock_or_recover();
+            if state.connection.is_some() && state.target == target {
+                continue;
+            }
+        }
+
+        let result = open_port(&target);
+        let mut state = midi_output.lock_or_recover();
+        match result {
+            Ok((connection, port_name)) => state.set_connected(connection, target, port_name),
+            Err(e) => {
+                let waiting = MidiStatus::Waiting(target.clone());
+                if state.status != waiting {
+                    warn!("MIDI output {} not available: {}", target, e);
+                }
+                state.connection = None;
+                state.target = target;
+                state.status = waiting;
+            }
+        }
+    }
+}
+
+/// Names of the currently available MIDI output ports.
+pub fn list_output_ports() -> Vec<String> {
+    let Ok(midi_out) = MidiOutput::new(CLIENT_NAME) else {
+        return Vec::new();
+    };
+    midi_out
+        .ports()
+        .iter()
+        .filter_map(|p| midi_out.port_name(p).ok())
+        .collect()
+}
+
+/// Opens `target` without any user interaction.
+#[instrument(name = "midi_connect", skip_all, fields(target = %target))]
+pub fn open_port(target: &PortTarget) -> Result<(MidiOutputConnection, String), MidiError> {
+    let midi_out = MidiOutput::new(CLIENT_NAME)?;
+    match target {
+        PortTarget::Virtual => open_virtual_port(midi_out),
+        _ => {
+            let out_ports = midi_out.ports();
+            let out_port = out_ports
+                .iter()
+                .find(|p| match target {
+                    PortTarget::Named(name) => midi_out.port_name(p).is_ok_and(|n| n == *name),
+                    _ => true,
+                })
+                .ok_or(MidiError::PortNotFound)?;
+            let port_name = midi_out.port_name(out_port)?;
+            let conn_out = midi_out.connect(out_port, PORT_NAME)?;
+            Ok((conn_out, port_name))
+        }
+    }
+}
+
+#[cfg(unix)]
+fn open_virtual_port(midi_out: MidiOutput) -> Result<(MidiOutputConnection, String), MidiError> {
+    use midir::os::unix::VirtualOutput;
+    let conn_out = midi_out.create_virtual(PORT_NAME)?;
+    Ok((conn_out, PORT_NAME.to_string()))
+}
+
+#[cfg(not(unix))]
+fn open_virtual_port(_midi_out: MidiOutput) -> Result<(MidiOutputConnection, String), MidiError> {
+    Err(MidiError::VirtualUnsupported)
+}
+
+/// The message `MidiProcessor` sent for a sample
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MidiAction {
+    ControlChange { control: u8, value: u8 },
+    NoteOn { note: u8, velocity: u8 },
+    KeyPressure { note: u8, pressure: u8 },
+    NoteOff { note: u8 },
+}
+
+impl fmt::Display for MidiAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiAction::ControlChange { control, value } => write!(f, "cc {} {}", control, value),
+            MidiAction::NoteOn { note, velocity } => write!(f, "note_on {} {}", note, velocity),
+            MidiAction::KeyPressure { note, pressure } => write!(f, "key_pressure {} {}", note, pressure),
+            MidiAction::NoteOff { note } => write!(f, "note_off {}", note),
+        }
+    }
+}
+
+/// The sustain pedal, pressed from the MIDI tab or a zone gesture; the
+/// pipeline follows it with the next sample
+#[derive(Debug, Clone, Default)]
+pub struct SustainPedal {
+    down: Arc<AtomicBool>,
+}
+
+impl SustainPedal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_down(&self) -> bool {
+        self.down.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, down: bool) {
+        self.down.store(down, Ordering::Relaxed);
+    }
+
+    pub fn toggle(&self) {
+        self.down.fetch_xor(true, Ordering::Relaxed);
+    }
+}
+
+/// Output zones whose samples send no MIDI, set by the GUI's zone focus; the
+/// pipeline follows them with the next sample
+#[derive(Debug, Clone, Default)]
+pub struct ZoneMutes {
+    /// Bit `zone` set for every muted zone
+    muted: Arc<AtomicU32>,
+}
+
+impl ZoneMutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_muted(&self, zone: usize) -> bool {
+        self.mask() & (1 << zone) != 0
+    }
+
+    /// Every muted zone as bit `zone`
+    pub fn mask(&self) -> u32 {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    pub fn set_mask(&self, mask: u32) {
+        self.muted.store(mask, Ordering::Relaxed);
+    }
+}
+
+/// A Control Change jump the mapping caused, eased in: what the value
+/// jumped by, shrinking to nothing over `duration`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CcRamp {
+    offset: f64,
+    started: Instant,
+    duration: Duration,
+}
+
+impl CcRamp {
+    /// The offset left at `now`, `None` once the ramp is over
+    pub fn remaining(&self, now: Instant) -> Option<f64> {
+        let progress = now.saturating_duration_since(self.started).as_secs_f64() / self.duration.as_secs_f64();
+        (progress < 1.0).then_some(self.offset * (1.0 - progress))
+    }
+}
+
+/// The ramp running on a zone's controller, for the MIDI clock to go on
+/// easing it between the zone's samples; a quiet zone sends none
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RunningCcRamp {
+    pub control: u8,
+    /// The zone's level at its last sample, without the ramp
+    pub level: f64,
+    /// Value last sent on `control`
+    pub sent: u8,
+    pub ramp: CcRamp,
+}
+
+impl RunningCcRamp {
+    /// The value the ramp has got to at `now`, and whether it is over
+    pub fn value(&self, now: Instant) -> (u8, bool) {
+        let remaining = self.ramp.remaining(now);
+        (cc_value(self.level + remaining.unwrap_or(0.0)), remaining.is_none())
+    }
+}
+
+/// The Control Change value of `level`, 0 to 1
+fn cc_value(level: f64) -> u8 {
+    (127.0 * level.clamp(0.0, 1.0)).round() as u8
+}
+
+/// A note sounding for a zone and the channel it went out on
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HeldNote {
+    note: u8,
+    channel: u8,
+}
+
+/// Hands out the channels of a list in turn, for `NoteConfig::channel_rotation`
+/// and the member channels of `MPEKeyboard`
+#[derive(Debug, Clone, Default)]
+pub struct ChannelRotation {
+    next_index: usize,
+}
+
+impl ChannelRotation {
+    /// The first channel of `channels` that isn't `busy`, looking from the
+    /// one after the channel handed out last; `None` if all are
+    pub fn next_free(&mut self, channels: &[u8], busy: impl Fn(u8) -> bool) -> Option<u8> {
+        let count = channels.len();
+        let index = (0..count)
+            .map(|offset| (self.next_index + offset) % count)
+            .find(|&index| !busy(channels[index]))?;
+        self.next_index = (index + 1) % count;
+        Some(channels[index])
+    }
+
+    /// The channel after the one handed out last, busy or not
+    pub fn next_any(&mut self, channels: &[u8]) -> Option<u8> {
+        if channels.is_empty() {
+            return None;
+        }
+        let index = self.next_index % channels.len();
+        self.next_index = (index + 1) % channels.len();
+        Some(channels[index])
+    }
+}
 
 pub struct MidiProcessor {
-    note_states: [bool; 8], // Track which notes are currently on
+    note_states: [Option<HeldNote>; NUM_ZONES], // Note currently sounding for each zone, if any
+    /// Mixed into Control Change output when `MidiConfig::lfo` is enabled
+    lfo: Lfo,
+    /// Peak value last sent on each zone's peak controller
+    peak_values: [Option<u8>; NUM_ZONES],
+    /// While the sustain pedal is down a zone's Note Off waits in
+    /// `pending_offs`, its note still sounding
+    sustain: bool,
+    pending_offs: [Option<HeldNote>; NUM_ZONES],
+    /// Velocity or pressure last sent for each zone's held note
+    velocities: [u8; NUM_ZONES],
+    /// When the Note On or Key Pressure of `velocities` was sent
+    pressure_sent: [Option<Instant>; NUM_ZONES],
+    /// Zones holding each note on each channel, in `note_states` or
+    /// `pending_offs`: zones with the same note on the same channel share one
+    /// Note On and the last one to let go sends its Note Off
+    note_holders: [[u8; 128]; 16],
+    /// Where the next Note On goes with `NoteConfig::channel_rotation`
+    channel_rotation: ChannelRotation,
+    /// Control Change mapping each zone last sent with, and the ramp of its
+    /// last jump if still running
+    cc_configs: [Option<ControlChangeConfig>; NUM_ZONES],
+    cc_ramps: [Option<CcRamp>; NUM_ZONES],
+    /// Controller, level without the ramp and value of each zone's last
+    /// Control Change
+    cc_sent: [Option<(u8, f64, u8)>; NUM_ZONES],
+    /// Plays the notes while `MidiConfig::mpe` is enabled
+    mpe: MPEKeyboard,
+    /// Note each zone pressed on `mpe`, if any
+    mpe_notes: [Option<u8>; NUM_ZONES],
+}
+
+impl Default for MidiProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MidiProcessor {
     pub fn new() -> Self {
         Self {
-            note_states: [false; 8],
+            note_states: [None; NUM_ZONES],
+            lfo: Lfo::default(),
+            peak_values: [None; NUM_ZONES],
+            sustain: false,
+            pending_offs: [None; NUM_ZONES],
+            velocities: [0; NUM_ZONES],
+            pressure_sent: [None; NUM_ZONES],
+            note_holders: [[0; 128]; 16],
+            channel_rotation: ChannelRotation::default(),
+            cc_configs: [None; NUM_ZONES],
+            cc_ramps: [None; NUM_ZONES],
+            cc_sent: [None; NUM_ZONES],
+            mpe: MPEKeyboard::new(),
+            mpe_notes: [None; NUM_ZONES],
+        }
+    }
+
+    /// Takes hold of `held` for a zone. Its Note On is only sent if no other
+    /// zone sounds it already, unless `retrigger`. Returns whether it was.
+    fn note_on(&mut self, conn_out: &mut dyn MidiSink, held: HeldNote, velocity: u8, retrigger: bool) -> Result<bool, MidiError> {
+        let holders = &mut self.note_holders[usize::from(held.channel)][usize::from(held.note)];
+        *holders = holders.saturating_add(1);
+        if *holders > 1 && !retrigger {
+            return Ok(false);
         }
+        send_note_on(conn_out, held.channel, held.note, velocity)?;
+        Ok(true)
     }
 
+    /// Lets go of `held` for a zone. Its Note Off is only sent once no zone
+    /// holds it any more. Returns whether it was.
+    fn note_off(&mut self, conn_out: &mut dyn MidiSink, held: HeldNote) -> Result<bool, MidiError> {
+        let holders = &mut self.note_holders[usize::from(held.channel)][usize::from(held.note)];
+        *holders = holders.saturating_sub(1);
+        if *holders > 0 {
+            return Ok(false);
+        }
+        send_note_off(conn_out, held.channel, held.note)?;
+        Ok(true)
+    }
+
+    /// The channel a new note goes out on: the next free one of
+    /// `config.channel_rotation`, the next one if every one sounds a note,
+    /// and 0 without a rotation
+    fn next_channel(&mut self, config: &NoteConfig) -> u8 {
+        let Some(channels) = config.channel_rotation.as_deref() else {
+            return 0;
+        };
+        let busy = |channel| {
+            self.note_states
+                .iter()
+                .chain(&self.pending_offs)
+                .flatten()
+                .any(|held: &HeldNote| held.channel == channel)
+        };
+        self.channel_rotation
+            .next_free(channels, busy)
+            .or_else(|| self.channel_rotation.next_any(channels))
+            .unwrap_or(0)
+    }
+
+    /// Sends Note Off for every held note, also those only sounding through
+    /// the sustain pedal, e.g. before the note mapping changes. The pedal
+    /// itself stays as it is.
+    pub fn release_all_notes(
+        &mut self,
+        conn_out: &mut dyn MidiSink,
+    ) -> Result<(), MidiError> {
+        for zone in 0..NUM_ZONES {
+            self.release_note(conn_out, zone)?;
+        }
+        Ok(())
+    }
+
+    /// Lets go of the note held on `zone`, if any, sustained or not; its
+    /// Note Off is sent unless another zone still holds it.
+    pub fn release_note(&mut self, conn_out: &mut dyn MidiSink, zone: usize) -> Result<(), MidiError> {
+        if let Some(held) = self.note_states[zone].take() {
+            self.note_off(conn_out, held)?;
+        }
+        if let Some(held) = self.pending_offs[zone].take() {
+            self.note_off(conn_out, held)?;
+        }
+        if let Some(note) = self.mpe_notes[zone].take() {
+            self.mpe.handle_key_release(conn_out, note, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Presses or releases the sustain pedal. Releasing it sends the Note Offs
+    /// deferred while it was down, but of notes no zone holds any more, and
+    /// returns them.
+    pub fn set_sustain(&mut self, conn_out: &mut dyn MidiSink, sustain: bool) -> Result<Vec<MidiAction>, MidiError> {
+        if sustain == self.sustain {
+            return Ok(Vec::new());
+        }
+        self.sustain = sustain;
+        info!("Sustain pedal {}", if sustain { "down" } else { "up" });
+        let mut released = Vec::new();
+        if !sustain {
+            for zone in 0..NUM_ZONES {
+                if let Some(held) = self.pending_offs[zone].take() {
+                    if self.note_off(conn_out, held)? {
+                        released.push(MidiAction::NoteOff { note: held.note });
+                    }
+                }
+            }
+        }
+        Ok(released)
+    }
+
+    /// In `Immediate` mode, moves every held note whose zone now maps to
+    /// another note under `config` to that note: all Note Offs of the old
+    /// notes go first, so a chord whose notes trade places keeps every new
+    /// one, then the Note Ons with each zone's last velocity. Notes only
+    /// sounding through the sustain pedal are left alone, a moved note keeps
+    /// its channel, and a note another zone shares is only ended or started
+    /// as `note_on` and `note_off` say. Returns the messages sent.
+    pub fn requantize(
+        &mut self,
+        conn_out: &mut dyn MidiSink,
+        config: &NoteConfig,
+        mode: RequantizeMode,
+        retrigger: bool,
+    ) -> Result<Vec<MidiAction>, MidiError> {
+        let mut sent = Vec::new();
+        if mode != RequantizeMode::Immediate {
+            return Ok(sent);
+        }
+        let moved: Vec<(usize, HeldNote)> = (0..NUM_ZONES)
+            .filter_map(|zone| {
+                let held = self.note_states[zone]?;
+                let note = config.zone_note(zone);
+                (note != held.note).then_some((zone, held))
+            })
+            .collect();
+        for &(_, held) in &moved {
+            if self.note_off(conn_out, held)? {
+                sent.push(MidiAction::NoteOff { note: held.note });
+            }
+        }
+        for &(zone, held) in &moved {
+            let note = config.zone_note(zone);
+            let velocity = self.velocities[zone];
+            let held = HeldNote { note, ..held };
+            self.note_states[zone] = Some(held);
+            if self.note_on(conn_out, held, velocity, retrigger)? {
+                sent.push(MidiAction::NoteOn { note, velocity });
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Sends the message for `sample`, which was received at `now`, as
+    /// `mapping` says; the rest of `config` applies as it is. `retuned` tells
+    /// that the config changed the mapping since the zone's last sample, so
+    /// a jump of its Control Change is ramped as `config.cc_ramp` says.
     pub fn process_sample(
         &mut self,
-        conn_out: &mut MidiOutputConnection,
+        conn_out: &mut dyn MidiSink,
+        sample: &ProcessedSample,
+        config: &MidiConfig,
+        mapping: &MidiMapping,
+        now: Instant,
+        retuned: bool,
+    ) -> Result<Option<MidiAction>, MidiError> {
+        let zone = sample.zone;
+        // A saturated count is only a bound, so it can't mean more than full scale
+        let normalized_value = if sample.saturated {
+            sample.value_normalized.clamp(-1.0, 1.0)
+        } else {
+            sample.value_normalized
+        };
+        match mapping.method {
+            MidiOutputMethod::ControlChange => {
+                if config.lfo.enabled {
+                    self.lfo.advance(now, config.lfo.rate(config.looper.tempo_bpm));
+                }
+                if config.peak.enabled && config.peak.send_cc {
+                    self.send_peak(conn_out, zone, sample.peak, config, &mapping.control_change_config)?;
+                }
+                let cc_config = &mapping.control_change_config;
+                let offset = self.cc_ramp_offset(zone, normalized_value, config, cc_config, retuned, now);
+                self.send_control_change(conn_out, zone, normalized_value, config, cc_config, offset)
+            }
+            MidiOutputMethod::Notes if config.mpe.enabled => {
+                self.send_mpe_note(conn_out, zone, normalized_value, &mapping.note_config, config, now)
+            }
+            MidiOutputMethod::Notes => self.send_note(conn_out, zone, normalized_value, &mapping.note_config, config, now),
+        }
+    }
+
+    /// The Control Change level of `zone`, 0 to 1, under `cc_config`
+    fn cc_level(&self, zone: usize, normalized_value: f64, config: &MidiConfig, cc_config: &ControlChangeConfig) -> f64 {
+        let mut level = f64::min(normalized_value.abs() * cc_config.control_slope, 1.0);
+        let lfo_zone = &config.lfo.zones[zone];
+        if config.lfo.enabled && lfo_zone.enabled {
+            let value = self.lfo.value(config.lfo.waveform);
+            level = lfo::mix(level, value, lfo_zone.depth, config.lfo.mix);
+        }
+        level
+    }
+
+    /// What is added to the level of `zone` at `now` while a jump of it is
+    /// ramped. A ramp starts when the zone is `retuned` and the mapping it
+    /// last sent with would give a level more than `cc_ramp.min_step` away;
+    /// it starts from where the last ramp, if any, had got to. Moving the
+    /// sensor changes both levels alike, so it still shows at once.
+    fn cc_ramp_offset(
+        &mut self,
         zone: usize,
         normalized_value: f64,
         config: &MidiConfig,
-    ) -> Result<(), Box<dyn Error>> {
-        match config.method {
-            MidiOutputMethod::ControlChange => self.send_control_change(
-                conn_out,
-                zone,
-                normalized_value,
-                &config.control_change_config,
-            ),
-            MidiOutputMethod::Notes => {
-                self.send_note(conn_out, zone, normalized_value, &config.note_config)
+        cc_config: &ControlChangeConfig,
+        retuned: bool,
+        now: Instant,
+    ) -> f64 {
+        let previous = self.cc_configs[zone].replace(*cc_config);
+        let ramp = &config.cc_ramp;
+        let left = self.cc_ramps[zone].and_then(|ramp| ramp.remaining(now));
+        if !ramp.enabled {
+            self.cc_ramps[zone] = None;
+            return 0.0;
+        }
+        if !retuned {
+            if left.is_none() {
+                self.cc_ramps[zone] = None;
             }
+            return left.unwrap_or(0.0);
         }
+        // On another controller there's nothing to ramp from
+        let Some(previous) = previous.filter(|previous| previous.base_control_number == cc_config.base_control_number) else {
+            self.cc_ramps[zone] = None;
+            return 0.0;
+        };
+        let before = (self.cc_level(zone, normalized_value, config, &previous) + left.unwrap_or(0.0)).clamp(0.0, 1.0);
+        let offset = before - self.cc_level(zone, normalized_value, config, cc_config);
+        self.cc_ramps[zone] = (127.0 * offset.abs() > f64::from(ramp.min_step)).then_some(CcRamp {
+            offset,
+            started: now,
+            duration: ramp.ramp(),
+        });
+        if self.cc_ramps[zone].is_some() {
+            offset
+        } else {
+            0.0
+        }
+    }
+
+    /// The ramp running on the controller of `zone` since its last Control
+    /// Change, if any
+    pub fn cc_ramp(&self, zone: usize) -> Option<RunningCcRamp> {
+        let ramp = self.cc_ramps.get(zone).copied().flatten()?;
+        let (control, level, sent) = self.cc_sent[zone]?;
+        Some(RunningCcRamp {
+            control,
+            level,
+            sent,
+            ramp,
+        })
     }
 
     fn send_control_change(
-        &self,
-        conn_out: &mut MidiOutputConnection,
+        &mut self,
+        conn_out: &mut dyn MidiSink,
+        zone: usize,
+        normalized_value: f64,
+        config: &MidiConfig,
+        cc_config: &ControlChangeConfig,
+        ramp_offset: f64,
+    ) -> Result<Option<MidiAction>, MidiError> {
+        let level = self.cc_level(zone, normalized_value, config, cc_config);
+        let midi_control_value = cc_value(level + ramp_offset);
+        let midi_control_channel = zone as u8 + cc_config.base_control_number;
+        self.cc_sent[zone] = Some((midi_control_channel, level, midi_control_value));
+        send_control_change(conn_out, midi_control_channel, midi_control_value)?;
+        Ok(Some(MidiAction::ControlChange {
+            control: midi_control_channel,
+            value: midi_control_value,
+        }))
+    }
+
+    /// Sends the held peak of `zone` on its peak controller when it changed.
+    fn send_peak(
+        &mut self,
+        conn_out: &mut dyn MidiSink,
+        zone: usize,
+        peak: f64,
+        config: &MidiConfig,
+        cc_config: &ControlChangeConfig,
+    ) -> Result<(), MidiError> {
+        let control = zone as u16 + u16::from(cc_config.base_control_number) + u16::from(config.peak.peak_cc_offset);
+        if control > 127 {
+            return Ok(());
+        }
+        let value = (127.0 * f64::min(peak * cc_config.control_slope, 1.0)).round() as u8;
+        if self.peak_values[zone] != Some(value) {
+            send_control_change(conn_out, control as u8, value)?;
+            self.peak_values[zone] = Some(value);
+        }
+        Ok(())
+    }
+
+    /// Whether `velocity` is pressure to send for the note held on `zone` at
+    /// `now`, as `midi_config.pressure` allows
+    fn pressure_due(&self, zone: usize, velocity: u8, midi_config: &MidiConfig, now: Instant) -> bool {
+        let pressure = &midi_config.pressure;
+        let last = self.velocities[zone];
+        let due = self.pressure_sent[zone]
+            .is_none_or(|sent| now.saturating_duration_since(sent) >= pressure.min_interval());
+        let changed = velocity.abs_diff(last) >= pressure.min_delta && (!pressure.increase_only || velocity > last);
+        due && changed
+    }
+
+    /// Note On, channel pressure or Note Off for `zone` at `now` through the
+    /// MPE keyboard, which gives every note a member channel of its own as
+    /// `midi_config.mpe` says; pressure only as often as
+    /// `midi_config.pressure` allows. A note the voice policy refused is
+    /// pressed again with the zone's next sample; a zone whose note was
+    /// stolen sends nothing until it lets go and is touched again.
+    fn send_mpe_note(
+        &mut self,
+        conn_out: &mut dyn MidiSink,
         zone: usize,
         normalized_value: f64,
-        config: &ControlChangeConfig,
-    ) -> Result<(), Box<dyn Error>> {
-        let midi_control_value = f64::min(normalized_value.abs() * config.control_slope, 1.0);
-        let midi_control_value = (127.0 * midi_control_value).round() as u8;
-        let midi_control_channel = zone as u8 + config.base_control_number;
-        send_control_change(conn_out, midi_control_channel, midi_control_value)
+        config: &NoteConfig,
+        midi_config: &MidiConfig,
+        now: Instant,
+    ) -> Result<Option<MidiAction>, MidiError> {
+        self.mpe.set_member_channels(midi_config.mpe.member_channels);
+        self.mpe.set_voice_policy(midi_config.mpe.voice_policy);
+        let magnitude = normalized_value.abs();
+        if magnitude <= config.threshold {
+            let Some(note) = self.mpe_notes[zone].take() else {
+                return Ok(None);
+            };
+            let released = self.mpe.handle_key_release(conn_out, note, 0)?;
+            return Ok(released.then_some(MidiAction::NoteOff { note }));
+        }
+        let velocity = (f64::min(magnitude * config.velocity_slope, 127.0) as u8).max(1);
+        let Some(note) = self.mpe_notes[zone] else {
+            let note = config.zone_note(zone);
+            if self.mpe.handle_key_press(conn_out, note, velocity, 0)?.is_none() {
+                return Ok(None);
+            }
+            self.mpe_notes[zone] = Some(note);
+            self.velocities[zone] = velocity;
+            self.pressure_sent[zone] = Some(now);
+            return Ok(Some(MidiAction::NoteOn { note, velocity }));
+        };
+        if !self.pressure_due(zone, velocity, midi_config, now)
+            || !self.mpe.handle_key_pressure_change(conn_out, note, velocity)?
+        {
+            return Ok(None);
+        }
+        self.velocities[zone] = velocity;
+        self.pressure_sent[zone] = Some(now);
+        Ok(Some(MidiAction::KeyPressure { note, pressure: velocity }))
     }
 
+    /// Note On, Note Off or Key Pressure for `zone` at `now`; pressure only
+    /// as often as `midi_config.pressure` allows. A Note On takes its channel
+    /// from `config.channel_rotation`, its pressure and Note Off follow it
+    /// there. A Note On or Off of a note other zones share is one that may
+    /// not be sent, see `note_on`.
     fn send_note(
         &mut self,
-        conn_out: &mut MidiOutputConnection,
+        conn_out: &mut dyn MidiSink,
         zone: usize,
         normalized_value: f64,
         config: &NoteConfig,
-    ) -> Result<(), Box<dyn Error>> {
-        if zone >= 8 {
-            return Ok(()); // Safety check
+        midi_config: &MidiConfig,
+        now: Instant,
+    ) -> Result<Option<MidiAction>, MidiError> {
+        if zone >= NUM_ZONES {
+            return Ok(None);
         }
 
         let magnitude = normalized_value.abs();
-        let note_number = config.scale.map_zone_to_note(config.base_note, zone);
+        let note_number = config.zone_note(zone);
 
         if magnitude > config.threshold {
             // Calculate velocity based on magnitude
             let velocity = f64::min(magnitude * config.velocity_slope, 127.0) as u8;
             let velocity = velocity.max(1); // Ensure velocity is at least 1
 
-            if !self.note_states[zone] {
-                // Send note on
-                send_note_on(conn_out, note_number, velocity)?;
-                self.note_states[zone] = true;
-            } else {
-                // Send key pressure (aftertouch)
-                send_key_pressure(conn_out, note_number, velocity)?;
+            match self.note_states[zone] {
+                None => {
+                    // Striking a sustained note again ends its old sound first
+                    if let Some(sustained) = self.pending_offs[zone].take() {
+                        self.note_off(conn_out, sustained)?;
+                    }
+                    let held = HeldNote {
+                        note: note_number,
+                        channel: self.next_channel(config),
+                    };
+                    self.note_states[zone] = Some(held);
+                    self.velocities[zone] = velocity;
+                    self.pressure_sent[zone] = Some(now);
+                    let sent = self.note_on(conn_out, held, velocity, midi_config.retrigger_shared_notes)?;
+                    Ok(sent.then_some(MidiAction::NoteOn {
+                        note: note_number,
+                        velocity,
+                    }))
+                }
+                Some(held) => {
+                    if !self.pressure_due(zone, velocity, midi_config, now) {
+                        return Ok(None);
+                    }
+                    // Send key pressure (aftertouch) for the note that is actually sounding
+                    send_key_pressure(conn_out, held.channel, held.note, velocity)?;
+                    self.velocities[zone] = velocity;
+                    self.pressure_sent[zone] = Some(now);
+                    Ok(Some(MidiAction::KeyPressure {
+                        note: held.note,
+                        pressure: velocity,
+                    }))
+                }
+            }
+        } else if let Some(held) = self.note_states[zone].take() {
+            if self.sustain {
+                // Sounds on until the pedal is released
+                self.pending_offs[zone] = Some(held);
+                return Ok(None);
             }
-        } else if self.note_states[zone] {
-            // Send note off
-            send_note_off(conn_out, note_number)?;
-            self.note_states[zone] = false;
+            let sent = self.note_off(conn_out, held)?;
+            Ok(sent.then_some(MidiAction::NoteOff { note: held.note }))
+        } else {
+            Ok(None)
         }
-
-        Ok(())
     }
 }
 
-pub fn create_midi_device() -> Result<MidiOutputConnection, Box<dyn Error>> {
-    let midi_out = MidiOutput::new("My Virtual MIDI Device")?;
-
-    // Get an output port
+/// Opens an output port for a config that hasn't chosen one yet, without
+/// asking: the first port. Returns the connection, the port name and whether
+/// it counts as chosen, which only the only port does.
+pub fn choose_port() -> Result<(MidiOutputConnection, String, bool), MidiError> {
+    let midi_out = MidiOutput::new(CLIENT_NAME)?;
     let out_ports = midi_out.ports();
-    let out_port: &MidiOutputPort = match out_ports.len() {
-        0 => return Err("no output port found".into()),
+    let names = out_ports
+        .iter()
+        .map(|p| midi_out.port_name(p))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let chosen = match names.len() {
+        0 => return Err(MidiError::NoPorts),
         1 => {
-            println!(
-                "Choosing the only available output port: {}",
-                midi_out.port_name(&out_ports[0])?
-            );
-            &out_ports[0]
+            info!("Choosing the only available output port: {}", names[0]);
+            true
         }
-        _ => {
-            println!("\nAvailable output ports:");
-            for (i, p) in out_ports.iter().enumerate() {
-                println!("{}: {}", i, midi_out.port_name(p)?);
-            }
-            print!("Please select output port: ");
-            stdout().flush()?;
-            let mut input = String::new();
-            stdin().read_line(&mut input)?;
-            out_ports
-                .get(input.trim().parse::<usize>()?)
-                .ok_or("invalid output port selected")?
+        count => {
+            info!(
+                "{} output ports available, using {}; set midi.output_port or run `ports --choose` to pick another",
+                count, names[0]
+            );
+            false
         }
     };
 
-    println!("\nOpening connection");
-    let conn_out = midi_out.connect(out_port, "Dildonica MIDI")?;
-    println!("Connection open. Listen to your virtual MIDI device.");
+    info!("Opening connection");
+    let conn_out = midi_out.connect(&out_ports[0], PORT_NAME)?;
+    info!("Connection open. Listen to your virtual MIDI device.");
+    Ok((conn_out, names[0].clone(), chosen))
+}
+
+/// Lists `names` and asks on stdin which one to use, for `ports --choose`.
+/// `None` on end of input or when nothing was entered within
+/// `PROMPT_TIMEOUT`.
+pub async fn prompt_for_port(names: &[String]) -> Option<usize> {
+    println!("\nAvailable output ports:");
+    for (i, name) in names.iter().enumerate() {
+        println!("{}: {}", i, name);
+    }
+    let count = names.len();
+    let (tx, rx) = oneshot::channel();
+    // A plain thread rather than spawn_blocking: a read nobody answers would
+    // keep the runtime from shutting down, while this thread just ends with
+    // the process
+    std::thread::spawn(move || {
+        let _ = tx.send(read_port_selection(count));
+    });
+    tokio::time::timeout(PROMPT_TIMEOUT, rx).await.ok()?.ok().flatten()
+}
 
-    Ok(conn_out)
+fn read_port_selection(count: usize) -> Option<usize> {
+    loop {
+        print!("Please select output port: ");
+        stdout().flush().ok()?;
+        let mut input = String::new();
+        if stdin().read_line(&mut input).ok()? == 0 {
+            return None;
+        }
+        match input.trim().parse::<usize>() {
+            Ok(index) if index < count => return Some(index),
+            _ => println!("Enter a number from 0 to {}", count - 1),
+        }
+    }
 }
 
 pub fn send_control_change(
-    conn_out: &mut MidiOutputConnection,
+    conn_out: &mut dyn MidiSink,
     control_num: u8,
     control_value: u8,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), MidiError> {
     const CC_MSG: u8 = 0xB0;
     conn_out.send(&[CC_MSG, control_num, control_value])?;
     Ok(())
 }
 
+/// Note On on `channel` (0-15)
 pub fn send_note_on(
-    conn_out: &mut MidiOutputConnection,
+    conn_out: &mut dyn MidiSink,
+    channel: u8,
     note: u8,
     velocity: u8,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), MidiError> {
     const NOTE_ON_MSG: u8 = 0x90;
-    conn_out.send(&[NOTE_ON_MSG, note, velocity])?;
+    conn_out.send(&[NOTE_ON_MSG | (channel & 0x0F), note, velocity])?;
     Ok(())
 }
 
-pub fn send_note_off(conn_out: &mut MidiOutputConnection, note: u8) -> Result<(), Box<dyn Error>> {
+/// Note Off on `channel` (0-15)
+pub fn send_note_off(conn_out: &mut dyn MidiSink, channel: u8, note: u8) -> Result<(), MidiError> {
     const NOTE_OFF_MSG: u8 = 0x80;
-    conn_out.send(&[NOTE_OFF_MSG, note, 0])?;
+    conn_out.send(&[NOTE_OFF_MSG | (channel & 0x0F), note, 0])?;
     Ok(())
 }
 
+/// Program Change on `channel` (0-15)
+pub fn send_program_change(conn_out: &mut dyn MidiSink, channel: u8, program: u8) -> Result<(), MidiError> {
+    const PROGRAM_CHANGE_MSG: u8 = 0xC0;
+    conn_out.send(&[PROGRAM_CHANGE_MSG | (channel & 0x0F), program & 0x7F])?;
+    Ok(())
+}
+
+/// Bank Select on `channel` (0-15): the high 7 bits of `bank` as CC 0, then
+/// the low 7 bits as CC 32. Synths apply it at the next Program Change.
+pub fn send_bank_select(conn_out: &mut dyn MidiSink, channel: u8, bank: u16) -> Result<(), MidiError> {
+    const CC_MSG: u8 = 0xB0;
+    const BANK_SELECT_MSB: u8 = 0;
+    const BANK_SELECT_LSB: u8 = 32;
+    let status = CC_MSG | (channel & 0x0F);
+    conn_out.send(&[status, BANK_SELECT_MSB, ((bank >> 7) & 0x7F) as u8])?;
+    conn_out.send(&[status, BANK_SELECT_LSB, (bank & 0x7F) as u8])?;
+    Ok(())
+}
+
+/// Key Pressure on `channel` (0-15)
 pub fn send_key_pressure(
-    conn_out: &mut MidiOutputConnection,
+    conn_out: &mut dyn MidiSink,
+    channel: u8,
     note: u8,
     pressure: u8,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), MidiError> {
     const KEY_PRESSURE_MSG: u8 = 0xA0;
-    conn_out.send(&[KEY_PRESSURE_MSG, note, pressure])?;
+    conn_out.send(&[KEY_PRESSURE_MSG | (channel & 0x0F), note, pressure])?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{KeyPressureConfig, MusicalScale};
+
+    #[test]
+    fn connect_errors_keep_their_kind() {
+        let error = MidiError::from(ConnectError::new(ConnectErrorKind::InvalidPort, ()));
+        assert!(matches!(error, MidiError::Connect(ConnectErrorKind::InvalidPort)));
+        let error = MidiError::from(ConnectError::new(ConnectErrorKind::Other("busy"), ()));
+        assert_eq!(error.to_string(), "busy");
+    }
+
+    fn notes_mapping(scale: MusicalScale) -> MidiMapping {
+        let mut mapping = MidiMapping {
+            method: MidiOutputMethod::Notes,
+            ..Default::default()
+        };
+        mapping.note_config.scale = scale;
+        mapping
+    }
+
+    /// Plays `value` on `zone` under `mapping`
+    fn play(
+        processor: &mut MidiProcessor,
+        sink: &mut CaptureMidiSink,
+        zone: usize,
+        value: f64,
+        mapping: &MidiMapping,
+    ) -> Option<MidiAction> {
+        play_with(processor, sink, zone, value, mapping, &MidiConfig::default())
+    }
+
+    /// Plays `value` on `zone` under `mapping` and `config`
+    fn play_with(
+        processor: &mut MidiProcessor,
+        sink: &mut CaptureMidiSink,
+        zone: usize,
+        value: f64,
+        mapping: &MidiMapping,
+        config: &MidiConfig,
+    ) -> Option<MidiAction> {
+        let sample = ProcessedSample {
+            timestamp: 0,
+            zone,
+            value_raw: 0.0,
+            value_normalized: value,
+            derivative: 0.0,
+            peak: 0.0,
+            saturated: false,
+        };
+        processor
+            .process_sample(sink, &sample, config, mapping, Instant::now(), false)
+            .unwrap()
+    }
+
+    /// A chromatic two-zone chord, 60 at velocity 50 and 61 at velocity 80
+    fn chord() -> (MidiProcessor, CaptureMidiSink) {
+        let mut processor = MidiProcessor::new();
+        let mut sink = CaptureMidiSink::new();
+        let chromatic = notes_mapping(MusicalScale::Chromatic);
+        play(&mut processor, &mut sink, 0, 0.5, &chromatic);
+        play(&mut processor, &mut sink, 1, 0.8, &chromatic);
+        assert_eq!(sink.take(), [[0x90, 60, 50], [0x90, 61, 80]]);
+        (processor, sink)
+    }
+
+    #[test]
+    fn a_scale_change_moves_the_held_notes_of_a_chord() {
+        let (mut processor, mut sink) = chord();
+        let major = notes_mapping(MusicalScale::Major);
+        let sent = processor
+            .requantize(&mut sink, &major.note_config, RequantizeMode::Immediate, false)
+            .unwrap();
+        // Zone 0 is 60 in both scales, zone 1 moves to 62 with its velocity
+        assert_eq!(sent, [MidiAction::NoteOff { note: 61 }, MidiAction::NoteOn { note: 62, velocity: 80 }]);
+        assert_eq!(sink.take(), [[0x80, 61, 0], [0x90, 62, 80]]);
+
+        play(&mut processor, &mut sink, 1, 0.0, &major);
+        play(&mut processor, &mut sink, 0, 0.0, &major);
+        assert_eq!(sink.take(), [[0x80, 62, 0], [0x80, 60, 0]]);
+    }
+
+    #[test]
+    fn chord_notes_trading_places_all_sound() {
+        let (mut processor, mut sink) = chord();
+        let mut higher = notes_mapping(MusicalScale::Chromatic);
+        higher.note_config.base_note = 61;
+        processor
+            .requantize(&mut sink, &higher.note_config, RequantizeMode::Immediate, false)
+            .unwrap();
+        // Zone 0 moves onto the 61 zone 1 let go of
+        assert_eq!(sink.take(), [[0x80, 60, 0], [0x80, 61, 0], [0x90, 61, 50], [0x90, 62, 80]]);
+    }
+
+    #[test]
+    fn retrigger_mode_leaves_held_notes_until_touched_again() {
+        let (mut processor, mut sink) = chord();
+        let major = notes_mapping(MusicalScale::Major);
+        let sent = processor
+            .requantize(&mut sink, &major.note_config, RequantizeMode::Retrigger, false)
+            .unwrap();
+        assert!(sent.is_empty());
+        assert!(sink.take().is_empty());
+
+        play(&mut processor, &mut sink, 1, 0.0, &major);
+        play(&mut processor, &mut sink, 1, 0.8, &major);
+        assert_eq!(sink.take(), [[0x80, 61, 0], [0x90, 62, 80]]);
+    }
+
+    /// A slow swell, velocity 50 to 70 over the second
+    fn slow_hold(t: f64) -> f64 {
+        0.5 + 0.2 * t
+    }
+
+    /// Velocity 50 to 70 and back at 40 Hz
+    fn wobble(t: f64) -> f64 {
+        0.6 + 0.1 * (2.0 * std::f64::consts::PI * 40.0 * t).sin()
+    }
+
+    /// Key Pressure messages sent for a note held one second at 1 kHz at
+    /// `value` over time, under `pressure`
+    fn pressures(value: fn(f64) -> f64, pressure: KeyPressureConfig) -> usize {
+        let config = MidiConfig {
+            pressure,
+            ..Default::default()
+        };
+        let mapping = notes_mapping(MusicalScale::Chromatic);
+        let mut processor = MidiProcessor::new();
+        let mut sink = CaptureMidiSink::new();
+        let start = Instant::now();
+        for index in 0..1000 {
+            let sample = ProcessedSample {
+                timestamp: index,
+                zone: 0,
+                value_raw: 0.0,
+                value_normalized: value(f64::from(index) / 1000.0),
+                derivative: 0.0,
+                peak: 0.0,
+                saturated: false,
+            };
+            let now = start + Duration::from_millis(index as u64);
+            processor.process_sample(&mut sink, &sample, &config, &mapping, now, false).unwrap();
+        }
+        let messages = sink.take();
+        assert_eq!(messages[0][0], 0x90, "the hold starts with a Note On");
+        messages.iter().filter(|message| message[0] & 0xF0 == 0xA0).count()
+    }
+
+    #[test]
+    fn pressure_is_sent_every_sample_by_default() {
+        assert_eq!(pressures(slow_hold, KeyPressureConfig::default()), 999);
+        assert_eq!(pressures(wobble, KeyPressureConfig::default()), 999);
+    }
+
+    #[test]
+    fn the_interval_limits_pressure_whatever_the_signal() {
+        let pressure = || KeyPressureConfig {
+            min_interval_ms: 20.0,
+            ..Default::default()
+        };
+        assert_eq!(pressures(slow_hold, pressure()), 49);
+        assert_eq!(pressures(wobble, pressure()), 49);
+    }
+
+    #[test]
+    fn the_delta_quiets_a_slow_hold_but_not_a_wobble() {
+        let pressure = || KeyPressureConfig {
+            min_delta: 4,
+            ..Default::default()
+        };
+        assert_eq!(pressures(slow_hold, pressure()), 4);
+        // Every step of 4 of its swing, up and down, 40 times a second
+        assert!(pressures(wobble, pressure()) > 300);
+    }
+
+    #[test]
+    fn increase_only_follows_the_peak() {
+        let pressure = || KeyPressureConfig {
+            increase_only: true,
+            ..Default::default()
+        };
+        assert_eq!(pressures(slow_hold, pressure()), 19);
+        // Rises to 70 in the first quarter wobble, then never past it
+        assert!(pressures(wobble, pressure()) <= 20);
+    }
+
+    #[test]
+    fn sustained_notes_are_not_requantized() {
+        let (mut processor, mut sink) = chord();
+        let chromatic = notes_mapping(MusicalScale::Chromatic);
+        processor.set_sustain(&mut sink, true).unwrap();
+        play(&mut processor, &mut sink, 1, 0.0, &chromatic);
+        let major = notes_mapping(MusicalScale::Major);
+        processor
+            .requantize(&mut sink, &major.note_config, RequantizeMode::Immediate, false)
+            .unwrap();
+        assert!(sink.take().is_empty(), "61 only sounds through the pedal");
+    }
+
+    /// Zones 0 and 1 both on note 127, the top of the note range clamping
+    /// zone 1 onto it
+    fn shared_note() -> MidiMapping {
+        let mut mapping = notes_mapping(MusicalScale::Chromatic);
+        mapping.note_config.base_note = 127;
+        mapping
+    }
+
+    /// The messages of `zone` pressing (`true`) or releasing in `order`
+    fn share(order: &[(usize, bool)], config: &MidiConfig) -> Vec<Vec<u8>> {
+        let mapping = shared_note();
+        let mut processor = MidiProcessor::new();
+        let mut sink = CaptureMidiSink::new();
+        for &(zone, press) in order {
+            play_with(&mut processor, &mut sink, zone, if press { 0.5 } else { 0.0 }, &mapping, config);
+        }
+        sink.take()
+    }
+
+    #[test]
+    fn zones_sharing_a_note_overlapping_sound_it_once() {
+        let order = [(0, true), (1, true), (0, false), (1, false)];
+        assert_eq!(share(&order, &MidiConfig::default()), [[0x90, 127, 50], [0x80, 127, 0]]);
+    }
+
+    #[test]
+    fn zones_sharing_a_note_nested_sound_it_once() {
+        let order = [(0, true), (1, true), (1, false), (0, false)];
+        assert_eq!(share(&order, &MidiConfig::default()), [[0x90, 127, 50], [0x80, 127, 0]]);
+    }
+
+    #[test]
+    fn the_actions_are_only_what_was_sent() {
+        let mapping = shared_note();
+        let mut processor = MidiProcessor::new();
+        let mut sink = CaptureMidiSink::new();
+        let on = Some(MidiAction::NoteOn { note: 127, velocity: 50 });
+        assert_eq!(play(&mut processor, &mut sink, 0, 0.5, &mapping), on);
+        assert_eq!(play(&mut processor, &mut sink, 1, 0.5, &mapping), None);
+        assert_eq!(play(&mut processor, &mut sink, 0, 0.0, &mapping), None);
+        assert_eq!(play(&mut processor, &mut sink, 1, 0.0, &mapping), Some(MidiAction::NoteOff { note: 127 }));
+    }
+
+    #[test]
+    fn a_shared_note_can_be_struck_again() {
+        let config = MidiConfig {
+            retrigger_shared_notes: true,
+            ..Default::default()
+        };
+        let order = [(0, true), (1, true), (0, false), (1, false)];
+        assert_eq!(share(&order, &config), [[0x90, 127, 50], [0x90, 127, 50], [0x80, 127, 0]]);
+    }
+
+    #[test]
+    fn a_shared_note_released_by_both_starts_fresh() {
+        let order = [(0, true), (1, true), (0, false), (1, false), (1, true), (1, false)];
+        assert_eq!(
+            share(&order, &MidiConfig::default()),
+            [[0x90, 127, 50], [0x80, 127, 0], [0x90, 127, 50], [0x80, 127, 0]]
+        );
+    }
+
+    #[test]
+    fn a_sustained_shared_note_ends_with_the_pedal_and_the_last_zone() {
+        let mapping = shared_note();
+        let mut processor = MidiProcessor::new();
+        let mut sink = CaptureMidiSink::new();
+        play(&mut processor, &mut sink, 0, 0.5, &mapping);
+        assert!(processor.set_sustain(&mut sink, true).unwrap().is_empty());
+        play(&mut processor, &mut sink, 1, 0.5, &mapping);
+        play(&mut processor, &mut sink, 0, 0.0, &mapping);
+        // Zone 1 still holds it
+        assert!(processor.set_sustain(&mut sink, false).unwrap().is_empty());
+        assert_eq!(sink.take(), [[0x90, 127, 50]]);
+        play(&mut processor, &mut sink, 1, 0.0, &mapping);
+        assert_eq!(sink.take(), [[0x80, 127, 0]]);
+
+        // Both let go under the pedal, released with it
+        play(&mut processor, &mut sink, 0, 0.5, &mapping);
+        play(&mut processor, &mut sink, 1, 0.5, &mapping);
+        processor.set_sustain(&mut sink, true).unwrap();
+        play(&mut processor, &mut sink, 0, 0.0, &mapping);
+        play(&mut processor, &mut sink, 1, 0.0, &mapping);
+        let released = processor.set_sustain(&mut sink, false).unwrap();
+        assert_eq!(released, [MidiAction::NoteOff { note: 127 }]);
+        assert_eq!(sink.take(), [[0x90, 127, 50], [0x80, 127, 0]]);
+    }
+
+    #[test]
+    fn releasing_every_note_ends_a_shared_one_once() {
+        let mapping = shared_note();
+        let mut processor = MidiProcessor::new();
+        let mut sink = CaptureMidiSink::new();
+        play(&mut processor, &mut sink, 0, 0.5, &mapping);
+        play(&mut processor, &mut sink, 1, 0.5, &mapping);
+        processor.release_all_notes(&mut sink).unwrap();
+        assert_eq!(sink.take(), [[0x90, 127, 50], [0x80, 127, 0]]);
+        play(&mut processor, &mut sink, 0, 0.5, &mapping);
+        assert_eq!(sink.take(), [[0x90, 127, 50]]);
+    }
+
+    /// CC ramps on, 250 ms for jumps over 8 steps
+    fn ramp_config() -> MidiConfig {
+        let mut config = MidiConfig::default();
+        config.cc_ramp.enabled = true;
+        config
+    }
+
+    fn cc_mapping(slope: f64) -> MidiMapping {
+        let mut mapping = MidiMapping::default();
+        mapping.control_change_config.control_slope = slope;
+        mapping
+    }
+
+    /// The Control Change value zone 0 sends for `value` at `now`
+    fn cc_at(
+        processor: &mut MidiProcessor,
+        value: f64,
+        mapping: &MidiMapping,
+        now: Instant,
+        retuned: bool,
+    ) -> u8 {
+        let sample = ProcessedSample {
+            timestamp: 0,
+            zone: 0,
+            value_raw: 0.0,
+            value_normalized: value,
+            derivative: 0.0,
+            peak: 0.0,
+            saturated: false,
+        };
+        let mut sink = CaptureMidiSink::new();
+        let action = processor
+            .process_sample(&mut sink, &sample, &ramp_config(), mapping, now, retuned)
+            .unwrap();
+        match action {
+            Some(MidiAction::ControlChange { value, .. }) => value,
+            other => panic!("expected a Control Change, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_retuned_jump_ramps_over_the_ramp_time() {
+        let mut processor = MidiProcessor::new();
+        let start = Instant::now();
+        assert_eq!(cc_at(&mut processor, 0.25, &cc_mapping(1.0), start, false), 32);
+        // Doubling the slope would jump to 64 at once
+        let doubled = cc_mapping(2.0);
+        let values: Vec<u8> = (0..=12)
+            .map(|step| {
+                let now = start + Duration::from_millis(25 * step);
+                cc_at(&mut processor, 0.25, &doubled, now, step == 0)
+            })
+            .collect();
+        assert_eq!(values[0], 32);
+        assert_eq!(values[5], 48, "half way at 125 ms");
+        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(values[9] < 64, "still ramping at 225 ms");
+        assert_eq!(values[10..], [64, 64, 64], "there from 250 ms on");
+        assert!(processor.cc_ramp(0).is_none());
+    }
+
+    #[test]
+    fn sensor_changes_are_immediate() {
+        let mut processor = MidiProcessor::new();
+        let start = Instant::now();
+        let mapping = cc_mapping(1.0);
+        // No retune, no ramp: the sensor's jump goes straight out
+        assert_eq!(cc_at(&mut processor, 0.25, &mapping, start, false), 32);
+        assert_eq!(cc_at(&mut processor, 0.75, &mapping, start + Duration::from_millis(1), false), 95);
+        assert_eq!(cc_at(&mut processor, 0.0, &mapping, start + Duration::from_millis(2), false), 0);
+        assert!(processor.cc_ramp(0).is_none());
+    }
+
+    #[test]
+    fn sensor_changes_show_at_once_during_a_ramp() {
+        let mut processor = MidiProcessor::new();
+        let start = Instant::now();
+        cc_at(&mut processor, 0.25, &cc_mapping(1.0), start, false);
+        let doubled = cc_mapping(2.0);
+        assert_eq!(cc_at(&mut processor, 0.25, &doubled, start, true), 32);
+        // Pressing harder adds its whole change on top of the ramp
+        let now = start + Duration::from_millis(125);
+        assert_eq!(cc_at(&mut processor, 0.25, &doubled, now, false), 48);
+        assert_eq!(cc_at(&mut processor, 0.375, &doubled, now, false), 79);
+    }
+
+    #[test]
+    fn small_retuned_jumps_are_sent_at_once() {
+        let mut processor = MidiProcessor::new();
+        let start = Instant::now();
+        assert_eq!(cc_at(&mut processor, 0.25, &cc_mapping(1.0), start, false), 32);
+        // 32 to 38 is within min_step
+        assert_eq!(cc_at(&mut processor, 0.25, &cc_mapping(1.2), start, true), 38);
+        assert!(processor.cc_ramp(0).is_none());
+    }
+
+    #[test]
+    fn a_running_ramp_is_handed_out_with_its_controller_and_level() {
+        let mut processor = MidiProcessor::new();
+        let start = Instant::now();
+        cc_at(&mut processor, 0.25, &cc_mapping(1.0), start, false);
+        cc_at(&mut processor, 0.25, &cc_mapping(2.0), start, true);
+        let ramp = processor.cc_ramp(0).unwrap();
+        assert_eq!((ramp.control, ramp.level, ramp.sent), (41, 0.5, 32));
+        assert_eq!(ramp.value(start + Duration::from_millis(125)), (48, false));
+        assert_eq!(ramp.value(start + Duration::from_millis(250)), (64, true));
+        assert!(processor.cc_ramp(1).is_none());
+    }
+
+    fn rotating(channels: &[u8]) -> MidiMapping {
+        let mut mapping = notes_mapping(MusicalScale::Chromatic);
+        mapping.note_config.channel_rotation = Some(channels.to_vec());
+        mapping
+    }
+
+    #[test]
+    fn rotation_hands_out_free_channels_in_turn() {
+        let mut rotation = ChannelRotation::default();
+        let channels = [3, 5, 7];
+        assert_eq!(rotation.next_free(&channels, |_| false), Some(3));
+        assert_eq!(rotation.next_free(&channels, |_| false), Some(5));
+        // 7 is busy, so it wraps round to 3
+        assert_eq!(rotation.next_free(&channels, |channel| channel == 7), Some(3));
+        assert_eq!(rotation.next_free(&channels, |_| true), None);
+        assert_eq!(rotation.next_any(&channels), Some(5));
+        assert_eq!(rotation.next_any(&channels), Some(7));
+        assert_eq!(rotation.next_any(&channels), Some(3));
+        assert_eq!(rotation.next_any(&[]), None);
+    }
+
+    #[test]
+    fn interleaved_zones_rotate_over_the_channels() {
+        let mut processor = MidiProcessor::new();
+        let mut sink = CaptureMidiSink::new();
+        let mapping = rotating(&[0, 1, 2]);
+        play(&mut processor, &mut sink, 0, 0.5, &mapping);
+        play(&mut processor, &mut sink, 1, 0.5, &mapping);
+        play(&mut processor, &mut sink, 0, 0.0, &mapping);
+        // Channel 0 is free again, but the turn goes on from channel 1
+        play(&mut processor, &mut sink, 2, 0.5, &mapping);
+        play(&mut processor, &mut sink, 3, 0.5, &mapping);
+        assert_eq!(
+            sink.take(),
+            [[0x90, 60, 50], [0x91, 61, 50], [0x80, 60, 0], [0x92, 62, 50], [0x90, 63, 50]]
+        );
+        // All three are busy: a mono synth cuts over to the next in turn
+        play(&mut processor, &mut sink, 4, 0.5, &mapping);
+        assert_eq!(sink.take(), [[0x91, 64, 50]]);
+    }
+
+    #[test]
+    fn pressure_and_release_follow_each_note_to_its_channel() {
+        let mut processor = MidiProcessor::new();
+        let mut sink = CaptureMidiSink::new();
+        let mapping = rotating(&[4, 9]);
+        play(&mut processor, &mut sink, 0, 0.5, &mapping);
+        play(&mut processor, &mut sink, 1, 0.5, &mapping);
+        play(&mut processor, &mut sink, 0, 0.6, &mapping);
+        play(&mut processor, &mut sink, 1, 0.7, &mapping);
+        play(&mut processor, &mut sink, 1, 0.0, &mapping);
+        assert_eq!(
+            sink.take(),
+            [[0x94, 60, 50], [0x99, 61, 50], [0xA4, 60, 60], [0xA9, 61, 70], [0x89, 61, 0]]
+        );
+        // It's channel 4's turn, but zone 0 still sounds there
+        play(&mut processor, &mut sink, 1, 0.5, &mapping);
+        processor.release_all_notes(&mut sink).unwrap();
+        assert_eq!(sink.take(), [[0x99, 61, 50], [0x84, 60, 0], [0x89, 61, 0]]);
+    }
+
+    #[test]
+    fn zones_share_a_note_only_on_one_channel() {
+        let mut processor = MidiProcessor::new();
+        let mut sink = CaptureMidiSink::new();
+        let mut mapping = rotating(&[0, 1]);
+        mapping.note_config.base_note = 127;
+        play(&mut processor, &mut sink, 0, 0.5, &mapping);
+        play(&mut processor, &mut sink, 1, 0.5, &mapping);
+        // One 127 per channel, so each release ends a note
+        play(&mut processor, &mut sink, 0, 0.0, &mapping);
+        play(&mut processor, &mut sink, 1, 0.0, &mapping);
+        assert_eq!(sink.take(), [[0x90, 127, 50], [0x91, 127, 50], [0x80, 127, 0], [0x81, 127, 0]]);
+    }
+
+    #[test]
+    fn without_rotation_notes_stay_on_the_first_channel() {
+        let mut processor = MidiProcessor::new();
+        let mut sink = CaptureMidiSink::new();
+        let mapping = notes_mapping(MusicalScale::Chromatic);
+        for zone in 0..3 {
+            play(&mut processor, &mut sink, zone, 0.5, &mapping);
+        }
+        assert!(sink.take().iter().all(|message| message[0] == 0x90));
+    }
+
+    #[test]
+    fn mpe_notes_follow_the_voice_policy_of_the_config() {
+        use crate::config::VoicePolicy;
+        let mapping = notes_mapping(MusicalScale::Chromatic);
+        let released = Some(MidiAction::NoteOff { note: 60 });
+        let cases = [
+            (VoicePolicy::StealOldest, vec![[0x92, 60, 0], [0x92, 63, 50]], None, 3),
+            (VoicePolicy::StealQuietest, vec![[0x93, 61, 0], [0x93, 63, 50]], released, 2),
+            (VoicePolicy::RefuseNew, vec![], released, 2),
+        ];
+        for (policy, stolen, release, sounding) in cases {
+            let mut config = MidiConfig::default();
+            config.mpe.enabled = true;
+            config.mpe.member_channels = 3;
+            config.mpe.voice_policy = policy;
+            let mut processor = MidiProcessor::new();
+            let mut sink = CaptureMidiSink::new();
+            // Zone 1 is the quietest
+            for (zone, value) in [(0, 0.5), (1, 0.3), (2, 0.6)] {
+                play_with(&mut processor, &mut sink, zone, value, &mapping, &config);
+            }
+            let notes: Vec<Vec<u8>> = sink.take().into_iter().filter(|message| message[0] & 0xF0 != 0xB0).collect();
+            assert_eq!(notes, [[0x92, 60, 50], [0x93, 61, 30], [0x94, 62, 60]], "{:?}", policy);
+            let pressed = play_with(&mut processor, &mut sink, 3, 0.5, &mapping, &config);
+            assert_eq!(sink.take(), stolen, "{:?}", policy);
+            assert_eq!(pressed.is_some(), policy != VoicePolicy::RefuseNew, "{:?}", policy);
+            // A stolen note is not ended twice
+            assert_eq!(play_with(&mut processor, &mut sink, 0, 0.0, &mapping, &config), release, "{:?}", policy);
+            sink.take();
+            processor.release_all_notes(&mut sink).unwrap();
+            assert_eq!(sink.take().len(), sounding, "{:?}", policy);
+        }
+    }
+}
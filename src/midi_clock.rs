@@ -0,0 +1,374 @@
+use crate::config::{ConfigStore, MidiUpdateMode, NUM_ZONES};
+use crate::midi::{MidiError, MidiOutputState, MidiSink, RunningCcRamp};
+use crate::sync::{LockExt, TrackedMutex};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// How often `run` checks the config while samples are sent as they arrive
+const IDLE_INTERVAL: Duration = Duration::from_millis(100);
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const KEY_PRESSURE: u8 = 0xA0;
+const CONTROL_CHANGE: u8 = 0xB0;
+/// Data Entry and the RPN and NRPN selects, which set a parameter in order
+const PARAMETER_CONTROLS: [u8; 6] = [6, 38, 98, 99, 100, 101];
+
+/// Latest value of every continuous message not sent yet, by channel and
+/// control number or note
+#[derive(Default)]
+struct Pending {
+    control_changes: BTreeMap<(u8, u8), u8>,
+    key_pressure: BTreeMap<(u8, u8), u8>,
+}
+
+/// The queue of `MidiUpdateMode::FixedRate`: the pipeline leaves continuous
+/// values here through a `ClockedSink`, and `run` sends the latest of each
+/// once per tick. It also eases the Control Change ramps of `midi.cc_ramp`
+/// on every tick, in either mode, so a zone without samples still finishes
+/// its ramp. Shared between the pipeline and `run`.
+#[derive(Clone)]
+pub struct MidiClock {
+    pending: Arc<Mutex<Pending>>,
+    /// The ramp running on each zone, as of its last sample
+    ramps: Arc<Mutex<[Option<RunningCcRamp>; NUM_ZONES]>>,
+    /// Wakes `run` from its idle interval when a ramp starts
+    ramp_started: Arc<Notify>,
+}
+
+impl Default for MidiClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiClock {
+    pub fn new() -> Self {
+        Self {
+            pending: Arc::new(Mutex::new(Pending::default())),
+            ramps: Arc::new(Mutex::new([None; NUM_ZONES])),
+            ramp_started: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Drops every queued value and ramp, e.g. after a panic, so nothing is
+    /// sent for zones that were just released.
+    pub fn clear(&self) {
+        *self.pending.lock_or_recover() = Pending::default();
+        *self.ramps.lock_or_recover() = [None; NUM_ZONES];
+    }
+
+    /// Eases the controller of `zone` on every tick as `ramp` says, from
+    /// the zone's latest sample on; `None` stops it.
+    pub fn set_ramp(&self, zone: usize, ramp: Option<RunningCcRamp>) {
+        let mut ramps = self.ramps.lock_or_recover();
+        let Some(slot) = ramps.get_mut(zone) else {
+            return;
+        };
+        let started = slot.is_none() && ramp.is_some();
+        *slot = ramp;
+        drop(ramps);
+        if started {
+            self.ramp_started.notify_one();
+        }
+    }
+
+    /// Whether a ramp is running, which the clock ticks for in either mode
+    fn ramping(&self) -> bool {
+        self.ramps.lock_or_recover().iter().any(Option::is_some)
+    }
+
+    /// Queues the values the ramps have got to at `now` where they changed,
+    /// over any queued by a sample, and forgets the ramps that are over once
+    /// their last value is queued.
+    fn advance_ramps(&self, now: Instant) {
+        let mut ramps = self.ramps.lock_or_recover();
+        let mut pending = self.pending.lock_or_recover();
+        for slot in ramps.iter_mut() {
+            let Some(ramp) = slot else {
+                continue;
+            };
+            let (value, over) = ramp.value(now);
+            if value != ramp.sent {
+                // Control changes go out on the first channel
+                pending.control_changes.insert((0, ramp.control), value);
+                ramp.sent = value;
+            }
+            if over {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Sends the ramps' values at `now` and every queued value to `sink`, or
+    /// drops them without a connection.
+    fn tick(&self, sink: Option<&mut (dyn MidiSink + 'static)>, now: Instant) -> Result<(), MidiError> {
+        self.advance_ramps(now);
+        let pending = std::mem::take(&mut *self.pending.lock_or_recover());
+        let Some(sink) = sink else {
+            return Ok(());
+        };
+        for (&(channel, control), &value) in &pending.control_changes {
+            sink.send(&[CONTROL_CHANGE | channel, control, value])?;
+        }
+        for (&(channel, note), &pressure) in &pending.key_pressure {
+            sink.send(&[KEY_PRESSURE | channel, note, pressure])?;
+        }
+        Ok(())
+    }
+}
+
+/// A sink for the pipeline in `FixedRate` mode: Note On and Off go straight
+/// to the connection, Control Change and key pressure wait for the clock.
+/// Parameter selects, like the MPE configuration, go straight too, as they
+/// must stay in order.
+pub struct ClockedSink<'a> {
+    sink: &'a mut dyn MidiSink,
+    clock: &'a MidiClock,
+}
+
+impl<'a> ClockedSink<'a> {
+    pub fn new(sink: &'a mut dyn MidiSink, clock: &'a MidiClock) -> Self {
+        Self { sink, clock }
+    }
+}
+
+impl MidiSink for ClockedSink<'_> {
+    fn send(&mut self, message: &[u8]) -> Result<(), MidiError> {
+        let &[status, data, value] = message else {
+            return self.sink.send(message);
+        };
+        let channel = status & 0x0F;
+        let mut pending = self.clock.pending.lock_or_recover();
+        // Notes may go out on any channel with `NoteConfig::channel_rotation`
+        match status & 0xF0 {
+            CONTROL_CHANGE if !PARAMETER_CONTROLS.contains(&data) => {
+                pending.control_changes.insert((channel, data), value);
+            }
+            KEY_PRESSURE => {
+                pending.key_pressure.insert((channel, data), value);
+            }
+            NOTE_ON | NOTE_OFF => {
+                // Pressure queued for the previous note must not follow its Note Off
+                pending.key_pressure.remove(&(channel, data));
+                drop(pending);
+                return self.sink.send(message);
+            }
+            _ => {
+                drop(pending);
+                return self.sink.send(message);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The clock task: in `FixedRate` mode, or while a Control Change ramp runs,
+/// sends the queued and ramped values every `midi.update.rate_hz`, until
+/// `shutdown` is cancelled. A tick that runs late is skipped rather than
+/// made up, so the rate never bursts.
+pub async fn run(
+    clock: MidiClock,
+    midi_output: Arc<Mutex<MidiOutputState>>,
+    app_config: Arc<TrackedMutex<ConfigStore>>,
+    shutdown: CancellationToken,
+) {
+    let mut next_tick = Instant::now();
+    loop {
+        let (idle, interval) = {
+            let app_config = app_config.lock_or_recover();
+            let update = &app_config.midi.update;
+            // Only values queued before switching modes are left to send
+            let idle = update.mode == MidiUpdateMode::PerSample && !clock.ramping();
+            (idle, if idle { IDLE_INTERVAL } else { update.interval() })
+        };
+        {
+            let mut midi_output = midi_output.lock_or_recover();
+            if let Err(e) = clock.tick(midi_output.connection(), Instant::now()) {
+                midi_output.connection_lost(&e);
+            }
+        }
+        next_tick += interval;
+        let now = Instant::now();
+        if next_tick < now {
+            next_tick = now + interval;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_tick.into()) => {}
+            // A ramp starting while idle doesn't wait out the idle interval
+            _ = clock.ramp_started.notified(), if idle => next_tick = Instant::now(),
+            _ = shutdown.cancelled() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, MidiConfig, MidiMapping, MidiOutputMethod, MusicalScale};
+    use crate::midi::{CaptureMidiSink, MidiProcessor, PortTarget};
+    use crate::pipeline::ProcessedSample;
+    use crate::sync::tracked;
+
+    /// Zone 0's controller, 41 by default, ramping from 32 up to 64 over
+    /// 250 ms from `start`, as retuning its slope from 1 to 2 starts
+    fn ramp_from(start: Instant) -> RunningCcRamp {
+        let mut config = MidiConfig::default();
+        config.cc_ramp.enabled = true;
+        let sample = ProcessedSample {
+            timestamp: 0,
+            zone: 0,
+            value_raw: 0.0,
+            value_normalized: 0.25,
+            derivative: 0.0,
+            peak: 0.0,
+            saturated: false,
+        };
+        let mut processor = MidiProcessor::new();
+        let mut sink = CaptureMidiSink::new();
+        let mut mapping = MidiMapping::default();
+        mapping.control_change_config.control_slope = 1.0;
+        processor.process_sample(&mut sink, &sample, &config, &mapping, start, false).unwrap();
+        mapping.control_change_config.control_slope = 2.0;
+        processor.process_sample(&mut sink, &sample, &config, &mapping, start, true).unwrap();
+        processor.cc_ramp(0).expect("a ramp")
+    }
+
+    /// The values sent on controller 41, which must be all that was sent
+    fn control_values(messages: Vec<Vec<u8>>) -> Vec<u8> {
+        messages
+            .into_iter()
+            .map(|message| {
+                assert_eq!(message[..2], [CONTROL_CHANGE, 41]);
+                message[2]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_quiet_zone_finishes_its_ramp_on_ticks() {
+        let start = Instant::now();
+        let clock = MidiClock::new();
+        clock.set_ramp(0, Some(ramp_from(start)));
+        assert!(clock.ramping());
+        let capture = CaptureMidiSink::new();
+        let mut sink: Box<dyn MidiSink> = Box::new(capture.clone());
+        for tick in 1..=30 {
+            clock.tick(Some(sink.as_mut()), start + Duration::from_millis(10 * tick)).unwrap();
+        }
+        let values = control_values(capture.take());
+        assert_eq!(values.first(), Some(&33));
+        assert_eq!(values.last(), Some(&64));
+        assert!(values.windows(2).all(|pair| pair[0] < pair[1]), "only changes are sent: {:?}", values);
+        assert!(!clock.ramping(), "the ramp is dropped once over");
+        clock.tick(Some(sink.as_mut()), start + Duration::from_secs(1)).unwrap();
+        assert!(capture.take().is_empty());
+    }
+
+    #[test]
+    fn ramped_values_replace_queued_ones() {
+        let start = Instant::now();
+        let clock = MidiClock::new();
+        let capture = CaptureMidiSink::new();
+        let mut inner = capture.clone();
+        ClockedSink::new(&mut inner, &clock).send(&[CONTROL_CHANGE, 41, 32]).unwrap();
+        clock.set_ramp(0, Some(ramp_from(start)));
+        let mut sink: Box<dyn MidiSink> = Box::new(capture.clone());
+        clock.tick(Some(sink.as_mut()), start + Duration::from_millis(125)).unwrap();
+        assert_eq!(control_values(capture.take()), [48]);
+    }
+
+    #[test]
+    fn clearing_drops_ramps() {
+        let start = Instant::now();
+        let clock = MidiClock::new();
+        clock.set_ramp(0, Some(ramp_from(start)));
+        clock.clear();
+        assert!(!clock.ramping());
+        clock.set_ramp(1, Some(ramp_from(start)));
+        clock.set_ramp(1, None);
+        assert!(!clock.ramping());
+        // Zones that don't exist are ignored
+        clock.set_ramp(NUM_ZONES, Some(ramp_from(start)));
+        assert!(!clock.ramping());
+    }
+
+    #[tokio::test]
+    async fn the_task_ramps_without_samples_while_sending_per_sample() {
+        let capture = CaptureMidiSink::new();
+        let mut midi_output = MidiOutputState::new(PortTarget::Any);
+        midi_output.set_connected(capture.clone(), PortTarget::Any, "test capture".to_string());
+        let midi_output = Arc::new(Mutex::new(midi_output));
+        let config = AppConfig::default();
+        assert_eq!(config.midi.update.mode, MidiUpdateMode::PerSample);
+        let app_config = Arc::new(tracked("app_config", ConfigStore::new(config)));
+        let clock = MidiClock::new();
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(run(clock.clone(), midi_output, app_config, shutdown.clone()));
+        // Let the task go idle first, the ramp has to wake it
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        clock.set_ramp(0, Some(ramp_from(Instant::now())));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while clock.ramping() && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        shutdown.cancel();
+        task.await.unwrap();
+        let values = control_values(capture.take());
+        assert!(values.len() > 2, "eased on ticks, not in one step: {:?}", values);
+        assert_eq!(values.last(), Some(&64));
+    }
+
+    #[test]
+    fn rotated_notes_queue_their_pressure_on_their_own_channel() {
+        let start = Instant::now();
+        let clock = MidiClock::new();
+        let capture = CaptureMidiSink::new();
+        let mut inner = capture.clone();
+        let mut processor = MidiProcessor::new();
+        let mut mapping = MidiMapping {
+            method: MidiOutputMethod::Notes,
+            ..Default::default()
+        };
+        mapping.note_config.scale = MusicalScale::Chromatic;
+        mapping.note_config.channel_rotation = Some(vec![4, 9]);
+        let config = MidiConfig::default();
+        let mut play = |zone, value, millis| {
+            let sample = ProcessedSample {
+                timestamp: 0,
+                zone,
+                value_raw: 0.0,
+                value_normalized: value,
+                derivative: 0.0,
+                peak: 0.0,
+                saturated: false,
+            };
+            let now = start + Duration::from_millis(millis);
+            let mut sink = ClockedSink::new(&mut inner, &clock);
+            processor.process_sample(&mut sink, &sample, &config, &mapping, now, false).unwrap();
+        };
+        play(0, 0.5, 0);
+        play(1, 0.5, 0);
+        play(0, 0.6, 100);
+        play(1, 0.7, 100);
+        // Ends zone 0's note on channel 4, and only its queued pressure
+        play(0, 0.0, 200);
+        assert_eq!(capture.take(), [[0x94, 60, 50], [0x99, 61, 50], [0x84, 60, 0]]);
+        let mut sink: Box<dyn MidiSink> = Box::new(capture.clone());
+        clock.tick(Some(sink.as_mut()), start + Duration::from_millis(210)).unwrap();
+        assert_eq!(capture.take(), [[0xA9, 61, 70]]);
+
+        // The same note on another channel keeps its pressure, and so do controllers
+        let mut clocked = ClockedSink::new(&mut inner, &clock);
+        clocked.send(&[KEY_PRESSURE | 9, 60, 40]).unwrap();
+        clocked.send(&[CONTROL_CHANGE | 3, 41, 20]).unwrap();
+        clocked.send(&[NOTE_OFF | 4, 60, 0]).unwrap();
+        clocked.send(&[CONTROL_CHANGE | 2, 100, 0]).unwrap();
+        assert_eq!(capture.take(), [[0x84, 60, 0], [0xB2, 100, 0]]);
+        clock.tick(Some(sink.as_mut()), start + Duration::from_millis(220)).unwrap();
+        assert_eq!(capture.take(), [[0xB3, 41, 20], [0xA9, 60, 40]]);
+    }
+}
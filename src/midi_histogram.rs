@@ -0,0 +1,282 @@
+use crate::config::NUM_ZONES;
+use crate::midi::MidiAction;
+use crate::pipeline::SampleTap;
+use crate::sync::LockExt;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Samples buffered for the histograms; they only count, so they rarely lag
+const HISTOGRAM_QUEUE_SIZE: usize = 1024;
+/// How far back the MIDI tab's histograms reach
+pub const HISTOGRAM_WINDOW: Duration = Duration::from_secs(60);
+/// Slices of the window, each counting its own time; the oldest is dropped
+/// as a whole
+const WINDOW_SLICES: usize = 6;
+
+/// Counts of the MIDI data values 0-127 sent within a rolling window, for
+/// velocities, pressures or controller values alike
+#[derive(Debug, Clone)]
+pub struct ValueHistogram {
+    window: Duration,
+    /// Counts of every slice of the window, `slice` is the current one
+    slices: [[u32; 128]; WINDOW_SLICES],
+    slice: usize,
+    slice_started: Option<Instant>,
+}
+
+impl ValueHistogram {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            slices: [[0; 128]; WINDOW_SLICES],
+            slice: 0,
+            slice_started: None,
+        }
+    }
+
+    /// Counts `value`, sent at `now`. Values above 127 count as 127.
+    pub fn add(&mut self, value: u8, now: Instant) {
+        self.advance(now);
+        self.slices[self.slice][usize::from(value.min(127))] += 1;
+    }
+
+    /// Counts of every value within the window ending at `now`
+    pub fn counts(&mut self, now: Instant) -> [u32; 128] {
+        self.advance(now);
+        let mut counts = [0; 128];
+        for slice in &self.slices {
+            for (count, slice_count) in counts.iter_mut().zip(slice) {
+                *count += slice_count;
+            }
+        }
+        counts
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.window);
+    }
+
+    fn advance(&mut self, now: Instant) {
+        let slice_length = self.window / WINDOW_SLICES as u32;
+        let Some(started) = self.slice_started else {
+            self.slice_started = Some(now);
+            return;
+        };
+        if now.saturating_duration_since(started) >= self.window {
+            self.slices = [[0; 128]; WINDOW_SLICES];
+            self.slice_started = Some(now);
+            return;
+        }
+        let mut started = started;
+        while now.saturating_duration_since(started) >= slice_length {
+            self.slice = (self.slice + 1) % WINDOW_SLICES;
+            self.slices[self.slice] = [0; 128];
+            started += slice_length;
+        }
+        self.slice_started = Some(started);
+    }
+}
+
+/// Summary of a histogram's counts
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HistogramSummary {
+    pub total: u32,
+    /// Values at the top, 127, likely clipped by the slope
+    pub clipped: u32,
+    /// Values at the bottom, 1, the lowest velocity that still sounds
+    pub bottomed: u32,
+}
+
+impl HistogramSummary {
+    pub fn of(counts: &[u32; 128]) -> Self {
+        Self {
+            total: counts.iter().sum(),
+            clipped: counts[127],
+            bottomed: counts[1],
+        }
+    }
+}
+
+/// Which of the histograms of `MidiHistograms`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistogramKind {
+    /// Note On velocities
+    Velocity,
+    /// Key pressure values
+    Pressure,
+}
+
+struct Histograms {
+    velocities: Vec<ValueHistogram>,
+    pressures: Vec<ValueHistogram>,
+}
+
+/// Histograms of the note velocities and key pressures the pipeline sent
+/// per output zone over the last `HISTOGRAM_WINDOW`, shown in the MIDI tab
+#[derive(Clone)]
+pub struct MidiHistograms {
+    histograms: Arc<Mutex<Histograms>>,
+}
+
+impl MidiHistograms {
+    /// Starts the counting thread, which ends with the pipeline.
+    pub fn start() -> (Self, SampleTap) {
+        let (tap, mut rx, dropped) = SampleTap::new(HISTOGRAM_QUEUE_SIZE);
+        let histograms = Arc::new(Mutex::new(Histograms {
+            velocities: vec![ValueHistogram::new(HISTOGRAM_WINDOW); NUM_ZONES],
+            pressures: vec![ValueHistogram::new(HISTOGRAM_WINDOW); NUM_ZONES],
+        }));
+        let thread_histograms = histograms.clone();
+        std::thread::spawn(move || {
+            while let Some(event) = rx.blocking_recv() {
+                let zone = event.processed.zone;
+                let now = Instant::now();
+                let mut histograms = thread_histograms.lock_or_recover();
+                match event.midi_action {
+                    Some(MidiAction::NoteOn { velocity, .. }) => histograms.velocities[zone].add(velocity, now),
+                    Some(MidiAction::KeyPressure { pressure, .. }) => histograms.pressures[zone].add(pressure, now),
+                    _ => {}
+                }
+            }
+            let dropped = dropped.load(Ordering::Relaxed);
+            if dropped > 0 {
+                warn!("MIDI histograms fell behind, dropped {} samples", dropped);
+            }
+        });
+        (Self { histograms }, tap)
+    }
+
+    /// Counts of `kind` within the window, of `zone` or all zones summed
+    pub fn counts(&self, kind: HistogramKind, zone: Option<usize>) -> [u32; 128] {
+        let mut histograms = self.histograms.lock_or_recover();
+        let zones = match kind {
+            HistogramKind::Velocity => &mut histograms.velocities,
+            HistogramKind::Pressure => &mut histograms.pressures,
+        };
+        let now = Instant::now();
+        let mut counts = [0; 128];
+        for (index, histogram) in zones.iter_mut().enumerate() {
+            if zone.is_none_or(|zone| zone == index) {
+                for (count, zone_count) in counts.iter_mut().zip(histogram.counts(now)) {
+                    *count += zone_count;
+                }
+            }
+        }
+        counts
+    }
+
+    pub fn reset(&self) {
+        let mut guard = self.histograms.lock_or_recover();
+        let histograms = &mut *guard;
+        for histogram in histograms.velocities.iter_mut().chain(histograms.pressures.iter_mut()) {
+            histogram.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::{ProcessedSample, SampleEvent};
+    use crate::sample::Sample;
+    use std::time::SystemTime;
+
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn counts_values_and_clamps_above_127() {
+        let now = Instant::now();
+        let mut histogram = ValueHistogram::new(WINDOW);
+        for value in [1, 64, 64, 127, 200] {
+            histogram.add(value, now);
+        }
+        let counts = histogram.counts(now);
+        assert_eq!((counts[1], counts[64], counts[127]), (1, 2, 2));
+        assert_eq!(counts.iter().sum::<u32>(), 5);
+    }
+
+    #[test]
+    fn old_slices_leave_the_window() {
+        let start = Instant::now();
+        let mut histogram = ValueHistogram::new(WINDOW);
+        histogram.add(10, start);
+        histogram.add(20, start + Duration::from_secs(25));
+        assert_eq!(histogram.counts(start + Duration::from_secs(59))[10], 1);
+        // The first 10 s slice has run out, the one at 25 s hasn't
+        let counts = histogram.counts(start + Duration::from_secs(61));
+        assert_eq!((counts[10], counts[20]), (0, 1));
+        // Longer than the window, everything goes
+        assert_eq!(histogram.counts(start + Duration::from_secs(200)), [0; 128]);
+    }
+
+    #[test]
+    fn reset_forgets_every_count() {
+        let now = Instant::now();
+        let mut histogram = ValueHistogram::new(WINDOW);
+        histogram.add(99, now);
+        histogram.reset();
+        assert_eq!(histogram.counts(now), [0; 128]);
+    }
+
+    #[test]
+    fn summary_counts_clipped_and_bottomed_values() {
+        let mut counts = [0; 128];
+        counts[1] = 3;
+        counts[50] = 4;
+        counts[127] = 2;
+        let summary = HistogramSummary::of(&counts);
+        assert_eq!((summary.total, summary.clipped, summary.bottomed), (9, 2, 3));
+    }
+
+    fn event(zone: usize, midi_action: Option<MidiAction>) -> SampleEvent {
+        SampleEvent {
+            sample: Sample {
+                timestamp: 0,
+                zone,
+                value: Some(5000),
+                flags: None,
+                sequence: None,
+            },
+            received: SystemTime::UNIX_EPOCH,
+            processed: ProcessedSample {
+                timestamp: 0,
+                zone,
+                value_raw: 5000.0,
+                value_normalized: 0.0,
+                derivative: 0.0,
+                peak: 0.0,
+                saturated: false,
+            },
+            midi_action,
+            gestures: Default::default(),
+        }
+    }
+
+    #[test]
+    fn midi_histograms_count_velocities_and_pressures_per_zone() {
+        let (histograms, tap) = MidiHistograms::start();
+        tap.offer(event(2, Some(MidiAction::NoteOn { note: 60, velocity: 100 })));
+        tap.offer(event(3, Some(MidiAction::NoteOn { note: 62, velocity: 127 })));
+        tap.offer(event(3, Some(MidiAction::KeyPressure { note: 62, pressure: 40 })));
+        tap.offer(event(3, Some(MidiAction::NoteOff { note: 62 })));
+        tap.offer(event(4, None));
+        // Counted in order, so the note events are in once the pressure is
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while HistogramSummary::of(&histograms.counts(HistogramKind::Pressure, None)).total == 0 {
+            assert!(Instant::now() < deadline, "the pressure was never counted");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let velocities = histograms.counts(HistogramKind::Velocity, None);
+        assert_eq!((velocities[100], velocities[127]), (1, 1));
+        assert_eq!(histograms.counts(HistogramKind::Velocity, Some(2))[100], 1);
+        assert_eq!(histograms.counts(HistogramKind::Velocity, Some(3))[100], 0);
+        let pressures = histograms.counts(HistogramKind::Pressure, Some(3));
+        assert_eq!(HistogramSummary::of(&pressures).total, 1);
+        assert_eq!(pressures[40], 1);
+        histograms.reset();
+        assert_eq!(histograms.counts(HistogramKind::Velocity, None), [0; 128]);
+    }
+}
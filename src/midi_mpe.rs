@@ -1,9 +1,10 @@
+use crate::config::VoicePolicy;
+use crate::midi::{ChannelRotation, MidiError, MidiSink};
 use std::collections::HashMap;
 
 // MIDI status constants
 const STATUS_CONTROL_CHANGE: u8 = 0xB0;
 const STATUS_NOTE_ON: u8 = 0x90;
-const STATUS_NOTE_OFF: u8 = 0x80;
 const STATUS_CHANNEL_AFTERTOUCH: u8 = 0xD0;
 
 // MIDI control change constants
@@ -11,60 +12,90 @@ const CHANNEL_DATA_ENTRY_MSB: u8 = 0x06;
 const CHANNEL_RPN_LSB: u8 = 0x64;
 const CHANNEL_RPN_MSB: u8 = 0x65;
 
+/// The note sounding on a member channel
+#[derive(Debug, Clone, Copy)]
+struct Voice {
+    note_number: u8,
+    /// Order the notes started in
+    started: u64,
+    /// Latest pressure, or the velocity before any
+    level: u8,
+}
+
 #[derive(Debug)]
 struct ZoneConfig {
     master_channel: u8,
     member_channels: Vec<u8>,
-    active: bool,
 }
 
+/// The MPE notes of `MidiProcessor`, each on a member channel of its own
 pub struct MPEKeyboard {
-    // MIDI interface will be added later
     lower_zone: ZoneConfig,
-    upper_zone: ZoneConfig,
-    active_notes: HashMap<u8, u8>,  // note_number -> channel
-    channel_notes: HashMap<u8, u8>, // channel -> note_number
-    next_channel_index: usize,
+    active_notes: HashMap<u8, u8>,     // note_number -> channel
+    channel_notes: HashMap<u8, Voice>, // channel -> voice
+    rotation: ChannelRotation,
+    notes_started: u64,
+    voice_policy: VoicePolicy,
     master_pitch_bend_range: u8,
     note_pitch_bend_range: u8,
     rpn_msb: u8,
     rpn_lsb: u8,
+    /// Whether the MPE configuration went out for the current member channels
+    configured: bool,
+}
+
+impl Default for MPEKeyboard {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MPEKeyboard {
     pub fn new() -> Self {
-        let mut keyboard = MPEKeyboard {
+        MPEKeyboard {
             lower_zone: ZoneConfig {
                 master_channel: 1,
                 member_channels: (2..16).collect(), // Default to using all available channels
-                active: true,
-            },
-            upper_zone: ZoneConfig {
-                master_channel: 16,
-                member_channels: Vec::new(),
-                active: false,
             },
             active_notes: HashMap::new(),
             channel_notes: HashMap::new(),
-            next_channel_index: 0,
+            rotation: ChannelRotation::default(),
+            notes_started: 0,
+            voice_policy: VoicePolicy::default(),
             master_pitch_bend_range: 2,
             note_pitch_bend_range: 48,
             rpn_msb: 0,
             rpn_lsb: 0,
-        };
+            configured: false,
+        }
+    }
 
-        keyboard.send_mpe_configuration();
-        keyboard
+    pub fn set_voice_policy(&mut self, policy: VoicePolicy) {
+        self.voice_policy = policy;
     }
 
-    // This will be implemented when MIDI interface is added
-    fn send_midi_message(&self, status: u8, data1: u8, data2: Option<u8>) {
-        // Placeholder for actual MIDI sending implementation
-        let message = match data2 {
-            Some(d2) => format!("MIDI Message: [{:02X}, {:02X}, {:02X}]", status, data1, d2),
-            None => format!("MIDI Message: [{:02X}, {:02X}]", status, data1),
-        };
-        println!("{}", message);
+    /// Uses the `count` channels after the master channel as member
+    /// channels; the MPE configuration goes out again with the next note
+    /// if that changes them. Notes sounding on a dropped channel keep it.
+    pub fn set_member_channels(&mut self, count: u8) {
+        let channels: Vec<u8> = (2..16).take(usize::from(count)).collect();
+        if channels != self.lower_zone.member_channels {
+            self.lower_zone.member_channels = channels;
+            self.configured = false;
+        }
+    }
+
+    fn send_midi_message(
+        &self,
+        conn_out: &mut dyn MidiSink,
+        status: u8,
+        data1: u8,
+        data2: Option<u8>,
+    ) -> Result<(), MidiError> {
+        match data2 {
+            Some(data2) => conn_out.send(&[status, data1, data2]),
+            None => conn_out.send(&[status, data1]),
+        }
     }
 
     pub fn receive_midi_message(&mut self, message: &[u8]) {
@@ -91,62 +122,144 @@ impl MPEKeyboard {
         }
     }
 
-    fn send_mpe_configuration(&self) {
+    /// Announces the lower zone and its pitch bend ranges to the synth;
+    /// the first note sends it if nothing did.
+    pub fn send_mpe_configuration(&mut self, conn_out: &mut dyn MidiSink) -> Result<(), MidiError> {
         // Select RPN 6 (MPE Configuration)
-        self.send_midi_message(STATUS_CONTROL_CHANGE, CHANNEL_RPN_LSB, Some(0x06));
-        self.send_midi_message(STATUS_CONTROL_CHANGE, CHANNEL_RPN_MSB, Some(0x00));
-        // Set number of member channels (14 for lower zone)
-        self.send_midi_message(STATUS_CONTROL_CHANGE, CHANNEL_DATA_ENTRY_MSB, Some(0x0E));
+        self.send_midi_message(conn_out, STATUS_CONTROL_CHANGE, CHANNEL_RPN_LSB, Some(0x06))?;
+        self.send_midi_message(conn_out, STATUS_CONTROL_CHANGE, CHANNEL_RPN_MSB, Some(0x00))?;
+        // Set number of member channels of the lower zone
+        let count = self.lower_zone.member_channels.len() as u8;
+        self.send_midi_message(conn_out, STATUS_CONTROL_CHANGE, CHANNEL_DATA_ENTRY_MSB, Some(count))?;
 
         // Set default pitch bend ranges
-        self.send_pitch_bend_range(self.lower_zone.master_channel, self.master_pitch_bend_range);
+        self.send_pitch_bend_range(conn_out, self.lower_zone.master_channel, self.master_pitch_bend_range)?;
         for &channel in &self.lower_zone.member_channels {
-            self.send_pitch_bend_range(channel, self.note_pitch_bend_range);
+            self.send_pitch_bend_range(conn_out, channel, self.note_pitch_bend_range)?;
         }
+        self.configured = true;
+        Ok(())
     }
 
-    fn send_pitch_bend_range(&self, channel: u8, range_semitones: u8) {
-        self.send_midi_message(STATUS_CONTROL_CHANGE | channel, CHANNEL_RPN_LSB, Some(0x00));
-        self.send_midi_message(STATUS_CONTROL_CHANGE | channel, CHANNEL_RPN_MSB, Some(0x00));
-        self.send_midi_message(STATUS_CONTROL_CHANGE | channel, CHANNEL_DATA_ENTRY_MSB, Some(range_semitones));
+    fn send_pitch_bend_range(
+        &self,
+        conn_out: &mut dyn MidiSink,
+        channel: u8,
+        range_semitones: u8,
+    ) -> Result<(), MidiError> {
+        let status = STATUS_CONTROL_CHANGE | channel;
+        self.send_midi_message(conn_out, status, CHANNEL_RPN_LSB, Some(0x00))?;
+        self.send_midi_message(conn_out, status, CHANNEL_RPN_MSB, Some(0x00))?;
+        self.send_midi_message(conn_out, status, CHANNEL_DATA_ENTRY_MSB, Some(range_semitones))
     }
 
-    fn get_next_channel(&mut self) -> u8 {
+    /// The next member channel without a sounding note, round-robin. With
+    /// every channel busy the voice policy decides: a stolen channel has its
+    /// note ended first, and `None` refuses the new note.
+    fn get_next_channel(&mut self, conn_out: &mut dyn MidiSink) -> Result<Option<u8>, MidiError> {
         let available_channels = &self.lower_zone.member_channels;
-        let channel = available_channels[self.next_channel_index];
-        self.next_channel_index = (self.next_channel_index + 1) % available_channels.len();
-        channel
+        let channel_notes = &self.channel_notes;
+        if let Some(channel) = self.rotation.next_free(available_channels, |channel| channel_notes.contains_key(&channel)) {
+            return Ok(Some(channel));
+        }
+
+        let channels = self.channel_notes.iter().filter(|(channel, _)| available_channels.contains(channel));
+        let stolen = match self.voice_policy {
+            VoicePolicy::RefuseNew => None,
+            VoicePolicy::StealOldest => channels.min_by_key(|(_, voice)| voice.started),
+            VoicePolicy::StealQuietest => channels.min_by_key(|(_, voice)| (voice.level, voice.started)),
+        };
+        let Some((&channel, _)) = stolen else {
+            return Ok(None);
+        };
+        self.end_voice(conn_out, channel)?;
+        Ok(Some(channel))
+    }
+
+    /// Sends Note Off for the note on `channel` and forgets it; returns
+    /// whether there was one.
+    fn end_voice(&mut self, conn_out: &mut dyn MidiSink, channel: u8) -> Result<bool, MidiError> {
+        let Some(voice) = self.channel_notes.remove(&channel) else {
+            return Ok(false);
+        };
+        self.active_notes.remove(&voice.note_number);
+        // Send Note Off (using note-on with velocity 0)
+        self.send_midi_message(conn_out, STATUS_NOTE_ON | channel, voice.note_number, Some(0))?;
+        Ok(true)
     }
 
-    pub fn handle_key_press(&mut self, note_number: u8, velocity: u8, initial_pressure: u8) {
-        let channel = self.get_next_channel();
+    /// Starts `note_number` on a member channel and returns the channel, or
+    /// `None` if the voice policy refused it.
+    pub fn handle_key_press(
+        &mut self,
+        conn_out: &mut dyn MidiSink,
+        note_number: u8,
+        velocity: u8,
+        initial_pressure: u8,
+    ) -> Result<Option<u8>, MidiError> {
+        if !self.configured {
+            self.send_mpe_configuration(conn_out)?;
+        }
+        // The same note again ends the one still sounding, so no channel keeps a note nobody releases
+        if let Some(&channel) = self.active_notes.get(&note_number) {
+            self.end_voice(conn_out, channel)?;
+        }
+        let Some(channel) = self.get_next_channel(conn_out)? else {
+            // Every member channel is busy under VoicePolicy::RefuseNew
+            return Ok(None);
+        };
+        self.notes_started += 1;
         self.active_notes.insert(note_number, channel);
-        self.channel_notes.insert(channel, note_number);
+        self.channel_notes.insert(
+            channel,
+            Voice {
+                note_number,
+                started: self.notes_started,
+                level: if initial_pressure > 0 { initial_pressure } else { velocity },
+            },
+        );
 
         // Send Note On with velocity
-        self.send_midi_message(STATUS_NOTE_ON | channel, note_number, Some(velocity));
+        self.send_midi_message(conn_out, STATUS_NOTE_ON | channel, note_number, Some(velocity))?;
 
         // Send initial pressure if greater than 0
         if initial_pressure > 0 {
-            self.send_midi_message(STATUS_CHANNEL_AFTERTOUCH | channel, initial_pressure, None);
+            self.send_midi_message(conn_out, STATUS_CHANNEL_AFTERTOUCH | channel, initial_pressure, None)?;
         }
+        Ok(Some(channel))
     }
 
-    pub fn handle_key_release(&mut self, note_number: u8, release_velocity: u8) {
-        if let Some(&channel) = self.active_notes.get(&note_number) {
-            // Send Note Off (using note-on with velocity 0)
-            self.send_midi_message(STATUS_NOTE_ON | channel, note_number, Some(0));
-            // Clean up tracking
-            self.active_notes.remove(&note_number);
-            self.channel_notes.remove(&channel);
+    /// Ends `note_number`; returns whether it was still sounding, and not
+    /// stolen or refused.
+    pub fn handle_key_release(
+        &mut self,
+        conn_out: &mut dyn MidiSink,
+        note_number: u8,
+        _release_velocity: u8,
+    ) -> Result<bool, MidiError> {
+        match self.active_notes.get(&note_number) {
+            Some(&channel) => self.end_voice(conn_out, channel),
+            None => Ok(false),
         }
     }
 
-    pub fn handle_key_pressure_change(&mut self, note_number: u8, new_pressure: u8) {
-        if let Some(&channel) = self.active_notes.get(&note_number) {
-            // Send Channel Pressure message
-            self.send_midi_message(STATUS_CHANNEL_AFTERTOUCH | channel, new_pressure, None);
+    /// Sends the pressure of `note_number`; returns whether it was still
+    /// sounding.
+    pub fn handle_key_pressure_change(
+        &mut self,
+        conn_out: &mut dyn MidiSink,
+        note_number: u8,
+        new_pressure: u8,
+    ) -> Result<bool, MidiError> {
+        let Some(&channel) = self.active_notes.get(&note_number) else {
+            return Ok(false);
+        };
+        if let Some(voice) = self.channel_notes.get_mut(&channel) {
+            voice.level = new_pressure;
         }
+        // Send Channel Pressure message
+        self.send_midi_message(conn_out, STATUS_CHANNEL_AFTERTOUCH | channel, new_pressure, None)?;
+        Ok(true)
     }
 
     fn handle_rpn(&mut self, channel: u8, msb: u8, lsb: u8, value: u8) {
@@ -155,4 +268,175 @@ impl MPEKeyboard {
         println!("Handling RPN - Channel: {}, MSB: {}, LSB: {}, Value: {}",
                 channel, msb, lsb, value);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::CaptureMidiSink;
+
+    /// A keyboard with member channels 2 to 4, playing into a capture
+    struct Keys {
+        keyboard: MPEKeyboard,
+        sink: CaptureMidiSink,
+    }
+
+    impl Keys {
+        /// Under `policy`, its MPE configuration already taken
+        fn new(policy: VoicePolicy) -> Self {
+            let mut keyboard = MPEKeyboard::new();
+            keyboard.set_member_channels(3);
+            keyboard.set_voice_policy(policy);
+            let mut sink = CaptureMidiSink::new();
+            keyboard.send_mpe_configuration(&mut sink).unwrap();
+            sink.take();
+            Self { keyboard, sink }
+        }
+
+        fn press(&mut self, note: u8, velocity: u8, pressure: u8) {
+            self.keyboard.handle_key_press(&mut self.sink, note, velocity, pressure).unwrap();
+        }
+
+        fn release(&mut self, note: u8) {
+            self.keyboard.handle_key_release(&mut self.sink, note, 0).unwrap();
+        }
+
+        fn pressure(&mut self, note: u8, pressure: u8) {
+            self.keyboard.handle_key_pressure_change(&mut self.sink, note, pressure).unwrap();
+        }
+
+        fn take_sent(&self) -> Vec<Vec<u8>> {
+            self.sink.take()
+        }
+
+        /// Note on channel, by channel, checking both maps agree
+        fn sounding(&self) -> Vec<(u8, u8)> {
+            let keyboard = &self.keyboard;
+            assert_eq!(keyboard.active_notes.len(), keyboard.channel_notes.len());
+            let mut sounding: Vec<(u8, u8)> = keyboard
+                .channel_notes
+                .iter()
+                .map(|(&channel, voice)| {
+                    assert_eq!(keyboard.active_notes.get(&voice.note_number), Some(&channel));
+                    (channel, voice.note_number)
+                })
+                .collect();
+            sounding.sort();
+            sounding
+        }
+
+        /// Five zones pressing notes 60 to 64 one after another; the middle
+        /// note is pressed softest and the first gets the least pressure
+        fn press_five(&mut self) -> Vec<Vec<u8>> {
+            self.press(60, 100, 0);
+            self.press(61, 50, 0);
+            self.press(62, 90, 0);
+            self.pressure(60, 10);
+            self.take_sent();
+            self.press(63, 120, 0);
+            self.press(64, 70, 0);
+            self.take_sent()
+        }
+    }
+
+    #[test]
+    fn notes_take_the_free_member_channels_in_turn() {
+        let mut keys = Keys::new(VoicePolicy::StealOldest);
+        keys.press(60, 100, 0);
+        keys.press(61, 50, 20);
+        assert_eq!(keys.take_sent(), vec![vec![0x92, 60, 100], vec![0x93, 61, 50], vec![0xD3, 20]]);
+        keys.release(60);
+        keys.press(62, 90, 0);
+        keys.press(63, 80, 0);
+        // Channel 2 is free again once channel 4 is taken
+        assert_eq!(keys.take_sent(), vec![vec![0x92, 60, 0], vec![0x94, 62, 90], vec![0x92, 63, 80]]);
+        assert_eq!(keys.sounding(), vec![(2, 63), (3, 61), (4, 62)]);
+    }
+
+    #[test]
+    fn the_oldest_notes_are_stolen() {
+        let mut keys = Keys::new(VoicePolicy::StealOldest);
+        assert_eq!(
+            keys.press_five(),
+            vec![vec![0x92, 60, 0], vec![0x92, 63, 120], vec![0x93, 61, 0], vec![0x93, 64, 70]]
+        );
+        assert_eq!(keys.sounding(), vec![(2, 63), (3, 64), (4, 62)]);
+    }
+
+    #[test]
+    fn the_quietest_notes_are_stolen() {
+        let mut keys = Keys::new(VoicePolicy::StealQuietest);
+        // Note 60 has pressure 10, then note 61 its velocity 50 is the lowest
+        assert_eq!(
+            keys.press_five(),
+            vec![vec![0x92, 60, 0], vec![0x92, 63, 120], vec![0x93, 61, 0], vec![0x93, 64, 70]]
+        );
+        assert_eq!(keys.sounding(), vec![(2, 63), (3, 64), (4, 62)]);
+    }
+
+    #[test]
+    fn the_quietest_of_equals_is_the_oldest() {
+        let mut keys = Keys::new(VoicePolicy::StealQuietest);
+        keys.press(60, 90, 0);
+        keys.press(61, 40, 0);
+        keys.press(62, 40, 0);
+        keys.take_sent();
+        keys.press(63, 100, 0);
+        keys.press(64, 100, 0);
+        assert_eq!(
+            keys.take_sent(),
+            vec![vec![0x93, 61, 0], vec![0x93, 63, 100], vec![0x94, 62, 0], vec![0x94, 64, 100]]
+        );
+        assert_eq!(keys.sounding(), vec![(2, 60), (3, 63), (4, 64)]);
+    }
+
+    #[test]
+    fn new_notes_are_refused() {
+        let mut keys = Keys::new(VoicePolicy::RefuseNew);
+        assert_eq!(keys.press_five(), Vec::<Vec<u8>>::new());
+        assert_eq!(keys.sounding(), vec![(2, 60), (3, 61), (4, 62)]);
+        // Nothing is left to release for a refused note
+        keys.release(63);
+        keys.pressure(64, 30);
+        assert!(keys.take_sent().is_empty());
+    }
+
+    #[test]
+    fn a_note_pressed_again_ends_the_one_sounding() {
+        let mut keys = Keys::new(VoicePolicy::StealOldest);
+        keys.press(60, 100, 0);
+        keys.press(60, 80, 0);
+        assert_eq!(keys.take_sent(), vec![vec![0x92, 60, 100], vec![0x92, 60, 0], vec![0x93, 60, 80]]);
+        assert_eq!(keys.sounding(), vec![(3, 60)]);
+    }
+
+    #[test]
+    fn without_member_channels_notes_are_dropped() {
+        let mut keys = Keys::new(VoicePolicy::StealOldest);
+        keys.keyboard.lower_zone.member_channels.clear();
+        keys.press(60, 100, 0);
+        assert!(keys.take_sent().is_empty());
+        assert!(keys.sounding().is_empty());
+    }
+
+    #[test]
+    fn the_configuration_goes_out_before_the_first_note_and_after_a_change() {
+        let mut keyboard = MPEKeyboard::new();
+        let mut sink = CaptureMidiSink::new();
+        keyboard.set_member_channels(1);
+        keyboard.handle_key_press(&mut sink, 60, 100, 0).unwrap();
+        let rpn = |channel: u8, value| {
+            let status = 0xB0 | channel;
+            [vec![status, 0x64, 0], vec![status, 0x65, 0], vec![status, 0x06, value]]
+        };
+        let mut expected = vec![vec![0xB0, 0x64, 0x06], vec![0xB0, 0x65, 0x00], vec![0xB0, 0x06, 1]];
+        expected.extend(rpn(1, 2));
+        expected.extend(rpn(2, 48));
+        expected.push(vec![0x92, 60, 100]);
+        assert_eq!(sink.take(), expected);
+        keyboard.handle_key_press(&mut sink, 61, 100, 0).unwrap();
+        assert_eq!(sink.take(), [[0x92, 60, 0], [0x92, 61, 100]]);
+        keyboard.set_member_channels(2);
+        keyboard.handle_key_press(&mut sink, 62, 100, 0).unwrap();
+        assert_eq!(sink.take()[2], [0xB0, 0x06, 2]);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::config::{AppConfig, ConfigStore, MidiMapping, MidiOutputMethod, MidiUpdateMode, NUM_ZONES};
+use crate::gesture::Gestures;
+use crate::looper::LooperHandle;
+use crate::midi::{MidiOutputState, MidiProcessor, MidiSink, SustainPedal, ZoneMutes};
+use crate::midi_clock::{ClockedSink, MidiClock};
+use crate::output::{OutputError, OutputSink, SinkSample};
+use crate::patch::{self, PatchSelector};
+use crate::sync::LockExt;
+use std::sync::{Arc, Mutex};
+
+/// The MIDI output as the pipeline's first sink: the patches bound to zone
+/// gestures, the sustain pedal, requantizing held notes and the message of
+/// every sample, sent on the shared connection. It runs on the pipeline, as
+/// the message sent goes into the sample's event. There's no config switch
+/// for it; the pipeline mutes it instead.
+pub struct MidiOutputSink {
+    midi_processor: MidiProcessor,
+    midi_output: Arc<Mutex<MidiOutputState>>,
+    /// Captures the notes sent
+    looper: Option<LooperHandle>,
+    /// Sends continuous values in `MidiUpdateMode::FixedRate`; without it
+    /// every message is sent per sample
+    midi_clock: Option<MidiClock>,
+    /// Sends the patches bound to zone gestures
+    patches: Option<PatchSelector>,
+    /// Defers Note Offs while down
+    sustain: SustainPedal,
+    /// Output zones that send nothing, their gestures included
+    mutes: ZoneMutes,
+    /// Output zones muted at their last sample, whose notes were released
+    muted: [bool; NUM_ZONES],
+    /// Latest magnitude of every output zone, summed for the morph
+    magnitudes: [f64; NUM_ZONES],
+    /// Method of the mapping of the last sample, which a morph can switch
+    method: Option<MidiOutputMethod>,
+    /// Mapping of the last sample and the summed magnitude it was made at
+    last_mapping: Option<(MidiMapping, f64)>,
+    /// Zones whose mapping the config changed since their last sample
+    retuned: [bool; NUM_ZONES],
+    /// Zone groups of the last sample; notes are held per logical zone
+    zone_groups: Vec<Vec<usize>>,
+    config_generation: Option<u64>,
+}
+
+impl MidiOutputSink {
+    pub fn new(midi_output: Arc<Mutex<MidiOutputState>>) -> Self {
+        Self {
+            midi_processor: MidiProcessor::new(),
+            midi_output,
+            looper: None,
+            midi_clock: None,
+            patches: None,
+            sustain: SustainPedal::new(),
+            mutes: ZoneMutes::new(),
+            muted: [false; NUM_ZONES],
+            magnitudes: [0.0; NUM_ZONES],
+            method: None,
+            last_mapping: None,
+            retuned: [false; NUM_ZONES],
+            zone_groups: Vec::new(),
+            config_generation: None,
+        }
+    }
+
+    /// Offers every note sent to `looper`.
+    pub fn set_looper(&mut self, looper: LooperHandle) {
+        self.looper = Some(looper);
+    }
+
+    pub fn looper(&self) -> Option<&LooperHandle> {
+        self.looper.as_ref()
+    }
+
+    /// Queues Control Change and key pressure in `midi_clock` while
+    /// `midi.update.mode` is `FixedRate`, instead of sending them per sample.
+    pub fn set_midi_clock(&mut self, midi_clock: MidiClock) {
+        self.midi_clock = Some(midi_clock);
+    }
+
+    /// Sends the patch slots bound to the gestures of each sample.
+    pub fn set_patch_selector(&mut self, patches: PatchSelector) {
+        self.patches = Some(patches);
+    }
+
+    /// Follows `sustain`, which the gesture in `midi.sustain_toggle` also
+    /// presses and releases.
+    pub fn set_sustain_pedal(&mut self, sustain: SustainPedal) {
+        self.sustain = sustain;
+    }
+
+    /// Sends nothing for the samples of the zones `mutes` has muted and
+    /// releases the notes they held.
+    pub fn set_zone_mutes(&mut self, mutes: ZoneMutes) {
+        self.mutes = mutes;
+    }
+}
+
+impl OutputSink for MidiOutputSink {
+    fn name(&self) -> &'static str {
+        "MIDI"
+    }
+
+    fn enabled(&self, _config: &AppConfig) -> bool {
+        true
+    }
+
+    fn handle(&mut self, sample: &mut SinkSample, config: &ConfigStore) -> Result<(), OutputError> {
+        let zone = sample.event.processed.zone;
+        let muted = self.mutes.is_muted(zone);
+        let newly_muted = muted && !self.muted[zone];
+        self.muted[zone] = muted;
+        // A muted zone's gestures and magnitude move nothing either
+        let gestures = if muted { Gestures::default() } else { sample.event.gestures };
+        let now = sample.now;
+        if patch::triggered(config.midi.sustain_toggle, sample.midi_zone, gestures) {
+            self.sustain.toggle();
+        }
+        self.magnitudes[zone] = if muted { 0.0 } else { sample.event.processed.value_normalized.abs() };
+        let pressure = self.magnitudes.iter().sum();
+        let mapping = config.midi.active_mapping(pressure);
+        // Only the config can make the same magnitude give another mapping;
+        // the magnitude moving the morph is the sensors
+        if let Some((last, last_pressure)) = &self.last_mapping {
+            if config.midi.active_mapping(*last_pressure) != *last {
+                self.retuned = [true; NUM_ZONES];
+            }
+        }
+        self.last_mapping = Some((mapping.clone(), pressure));
+        let retuned = std::mem::take(&mut self.retuned[sample.midi_sample.zone]);
+        // After a reload, a change of method or of the zone groups held
+        // notes may no longer match the mapping
+        let remapped = self.config_generation.is_some_and(|generation| generation != config.generation())
+            || self.method.is_some_and(|method| method != mapping.method)
+            || config.zone_groups != self.zone_groups;
+        self.config_generation = Some(config.generation());
+        if config.zone_groups != self.zone_groups {
+            self.zone_groups = config.zone_groups.clone();
+        }
+        self.method = Some(mapping.method);
+
+        let mut midi_output = self.midi_output.lock_or_recover();
+        let Some(connection) = midi_output.connection() else {
+            return Ok(());
+        };
+        let midi_clock = self
+            .midi_clock
+            .as_ref()
+            .filter(|_| config.midi.update.mode == MidiUpdateMode::FixedRate);
+        // Before the sample's own message, so it already plays the new patch
+        let result = match &self.patches {
+            Some(patches) if !gestures.is_empty() => {
+                patches.handle_gestures(&mut *connection, &config.midi.patches, sample.midi_zone, gestures)
+            }
+            _ => Ok(()),
+        };
+        let mut clocked;
+        let midi_device: &mut dyn MidiSink = match midi_clock {
+            Some(midi_clock) => {
+                clocked = ClockedSink::new(connection, midi_clock);
+                &mut clocked
+            }
+            None => connection,
+        };
+        let result = result.and_then(|()| {
+            if remapped {
+                self.midi_processor.release_all_notes(midi_device)
+            } else {
+                Ok(())
+            }
+        });
+        let result = result.and_then(|()| {
+            let released = self.midi_processor.set_sustain(midi_device, self.sustain.is_down())?;
+            if let Some(looper) = &self.looper {
+                for action in released {
+                    looper.capture(action, now);
+                }
+            }
+            Ok(())
+        });
+        let result = result.and_then(|()| {
+            if mapping.method != MidiOutputMethod::Notes {
+                return Ok(());
+            }
+            let requantized =
+                self.midi_processor
+                    .requantize(
+                        midi_device,
+                        &mapping.note_config,
+                        config.midi.requantize,
+                        config.midi.retrigger_shared_notes,
+                    )?;
+            if let Some(looper) = &self.looper {
+                for action in requantized {
+                    looper.capture(action, now);
+                }
+            }
+            Ok(())
+        });
+        let result = result.and_then(|()| {
+            if !muted {
+                return self
+                    .midi_processor
+                    .process_sample(midi_device, &sample.midi_sample, &config.midi, &mapping, now, retuned);
+            }
+            if newly_muted {
+                self.midi_processor.release_note(midi_device, sample.midi_sample.zone)?;
+            }
+            Ok(None)
+        });
+        // The clock eases a ramp on between the zone's samples
+        if let Some(midi_clock) = &self.midi_clock {
+            let zone = sample.midi_sample.zone;
+            let controls = !muted && mapping.method == MidiOutputMethod::ControlChange;
+            midi_clock.set_ramp(zone, self.midi_processor.cc_ramp(zone).filter(|_| controls));
+        }
+        match result {
+            Ok(action) => {
+                if let (Some(looper), Some(action)) = (&self.looper, action) {
+                    looper.capture(action, now);
+                }
+                sample.event.midi_action = action;
+                Ok(())
+            }
+            Err(e) => {
+                midi_output.connection_lost(&e);
+                Err(e.into())
+            }
+        }
+    }
+
+    /// Sends Note Off for every held note and forgets the queued values,
+    /// which would otherwise be sent for the released zones.
+    fn flush(&mut self, _config: &ConfigStore) -> Result<(), OutputError> {
+        let mut midi_output = self.midi_output.lock_or_recover();
+        let result = match midi_output.connection() {
+            Some(midi_device) => self.midi_processor.release_all_notes(midi_device),
+            None => Ok(()),
+        };
+        if let Some(midi_clock) = &self.midi_clock {
+            midi_clock.clear();
+        }
+        if let Err(e) = &result {
+            midi_output.connection_lost(e);
+        }
+        Ok(result?)
+    }
+
+    fn release_zone(&mut self, _zone: usize, logical_zone: usize, _config: &ConfigStore) -> Result<(), OutputError> {
+        let mut midi_output = self.midi_output.lock_or_recover();
+        let result = match midi_output.connection() {
+            Some(midi_device) => self.midi_processor.release_note(midi_device, logical_zone),
+            None => Ok(()),
+        };
+        if let Some(midi_clock) = &self.midi_clock {
+            midi_clock.clear();
+        }
+        if let Err(e) = &result {
+            midi_output.connection_lost(e);
+        }
+        Ok(result?)
+    }
+
+    fn detail(&self) -> Option<String> {
+        Some(self.midi_output.lock_or_recover().status().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::{CaptureMidiSink, PortTarget};
+    use crate::pipeline::{Pipeline, PipelineCommand};
+    use crate::simulator::Simulator;
+    use crate::sync::tracked;
+
+    /// What the pipeline sent over MIDI for this run before MIDI became an
+    /// `OutputSink`, one message per line in hex
+    const BEFORE_SINKS: &str = include_str!("../tests/fixtures/midi/pipeline_6000_samples.hex");
+
+    #[test]
+    fn the_sink_sends_the_bytes_the_pipeline_sent_before() {
+        let mut config = AppConfig::default();
+        // Baselines had no warmup yet
+        config.normalization.baseline_warmup_samples = 0;
+        let app_config = Arc::new(tracked("app_config", ConfigStore::new(config)));
+        let capture = CaptureMidiSink::new();
+        let mut midi_output = MidiOutputState::new(PortTarget::Any);
+        midi_output.set_connected(capture.clone(), PortTarget::Any, "test capture".to_string());
+        let midi = MidiOutputSink::new(Arc::new(Mutex::new(midi_output)));
+        let mut pipeline = Pipeline::new(app_config.clone(), midi, None);
+        let commands = pipeline.command_sender();
+        let mut simulator = Simulator::new();
+        for index in 0..6000 {
+            match index {
+                1500 => commands.try_send(PipelineCommand::Panic).unwrap(),
+                2500 => commands.try_send(PipelineCommand::SetMuted(true)).unwrap(),
+                3000 => commands.try_send(PipelineCommand::SetMuted(false)).unwrap(),
+                3500 => app_config.lock_or_recover().midi.method = MidiOutputMethod::Notes,
+                5000 => app_config.lock_or_recover().midi.method = MidiOutputMethod::ControlChange,
+                _ => {}
+            }
+            pipeline.handle_sample(simulator.sample(index));
+        }
+        drop(pipeline);
+
+        let expected: Vec<Vec<u8>> = BEFORE_SINKS
+            .lines()
+            .map(|line| line.split(' ').map(|byte| u8::from_str_radix(byte, 16).unwrap()).collect())
+            .collect();
+        let sent = capture.take();
+        for (index, (sent, expected)) in sent.iter().zip(&expected).enumerate() {
+            assert_eq!(sent, expected, "message {}", index);
+        }
+        assert_eq!(sent.len(), expected.len());
+    }
+
+    /// A pipeline over the default config with `method`, whose MIDI goes to
+    /// the returned capture and follows the returned mutes
+    fn muting_pipeline(method: MidiOutputMethod) -> (Pipeline, CaptureMidiSink, ZoneMutes) {
+        let mut config = AppConfig::default();
+        config.midi.method = method;
+        let app_config = Arc::new(tracked("app_config", ConfigStore::new(config)));
+        let capture = CaptureMidiSink::new();
+        let mut midi_output = MidiOutputState::new(PortTarget::Any);
+        midi_output.set_connected(capture.clone(), PortTarget::Any, "test capture".to_string());
+        let mut midi = MidiOutputSink::new(Arc::new(Mutex::new(midi_output)));
+        let mutes = ZoneMutes::new();
+        midi.set_zone_mutes(mutes.clone());
+        (Pipeline::new(app_config, midi, None), capture, mutes)
+    }
+
+    #[test]
+    fn muted_zones_send_no_control_changes() {
+        let (mut pipeline, capture, mutes) = muting_pipeline(MidiOutputMethod::ControlChange);
+        let mut simulator = Simulator::new();
+        let controls = |messages: Vec<Vec<u8>>| {
+            let mut controls: Vec<u8> = messages.iter().map(|message| message[1]).collect();
+            controls.sort_unstable();
+            controls.dedup();
+            controls
+        };
+        for index in 0..2000 {
+            pipeline.handle_sample(simulator.sample(index));
+        }
+        assert_eq!(controls(capture.take()), (41..41 + NUM_ZONES as u8).collect::<Vec<_>>());
+        // Focused on zone 2: only its controller moves
+        mutes.set_mask(0b1111_1011);
+        for index in 2000..4000 {
+            pipeline.handle_sample(simulator.sample(index));
+        }
+        assert_eq!(controls(capture.take()), [43]);
+        mutes.set_mask(0);
+        for index in 4000..6000 {
+            pipeline.handle_sample(simulator.sample(index));
+        }
+        assert_eq!(controls(capture.take()).len(), NUM_ZONES);
+    }
+
+    #[test]
+    fn muting_releases_a_zones_note_once() {
+        let (mut pipeline, capture, mutes) = muting_pipeline(MidiOutputMethod::Notes);
+        let note_config = AppConfig::default().midi.note_config;
+        let zone_2 = note_config.zone_note(2);
+        let mut simulator = Simulator::new();
+        // Play until zone 0's note and another one sound
+        let mut held = Vec::new();
+        let mut index = 0;
+        while !(held.contains(&note_config.zone_note(0)) && held.len() > 1) {
+            pipeline.handle_sample(simulator.sample(index));
+            for message in capture.take() {
+                match message[0] {
+                    0x90 => held.push(message[1]),
+                    0x80 => held.retain(|&note| note != message[1]),
+                    _ => {}
+                }
+            }
+            index += 1;
+            assert!(index < 20_000, "the simulator never held two notes");
+        }
+        mutes.set_mask(0b1111_1011);
+        for index in index..index + 8000 {
+            pipeline.handle_sample(simulator.sample(index));
+        }
+        let sent = capture.take();
+        // Each muted zone's note ends once, zone 2 plays on
+        for &note in held.iter().filter(|&&note| note != zone_2) {
+            let offs = sent.iter().filter(|message| message[..2] == [0x80, note]).count();
+            assert_eq!(offs, 1, "note {}", note);
+        }
+        assert!(sent
+            .iter()
+            .filter(|message| message[0] != 0x80)
+            .all(|message| message[1] == zone_2));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::config::{ConfigStore, OscConfig, NUM_ZONES};
+use crate::output::{SinkErrors, SinkMessage, ThreadedSink};
+use crate::pipeline::SampleEvent;
+use crate::sync::{LockExt, TrackedMutex};
+use crate::udp::{Destination, ErrorReporter};
+use rosc::{encoder, OscMessage, OscPacket, OscType};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Starts the sender thread. It follows the `osc` section of the config while
+/// running, so output can be switched on and retargeted from the GUI.
+pub fn start(app_config: Arc<TrackedMutex<ConfigStore>>) -> ThreadedSink {
+    ThreadedSink::spawn("OSC", |config| config.osc.enabled, move |rx, sink_errors| {
+        let mut sender = OscSender::new(sink_errors);
+        while let Ok(message) = rx.recv() {
+            let config = app_config.lock_or_recover().osc.clone();
+            match message {
+                SinkMessage::Sample(event) if config.enabled => sender.send_event(&config, &event),
+                // Release whatever was held when output got switched off
+                _ => {
+                    sender.close_gates(&config);
+                    sender.reset();
+                }
+            }
+        }
+
+        let config = app_config.lock_or_recover().osc.clone();
+        if config.enabled {
+            sender.close_gates(&config);
+        }
+    })
+}
+
+struct OscSender {
+    destination: Option<Destination>,
+    /// When each zone's value was last sent, for the rate limit
+    last_sent: [Option<Instant>; NUM_ZONES],
+    gate_open: [bool; NUM_ZONES],
+    errors: ErrorReporter,
+}
+
+impl OscSender {
+    fn new(sink_errors: SinkErrors) -> Self {
+        Self {
+            destination: None,
+            last_sent: [None; NUM_ZONES],
+            gate_open: [false; NUM_ZONES],
+            errors: ErrorReporter::new("OSC output", sink_errors),
+        }
+    }
+
+    /// Forgets all state, used once output is flushed or disabled.
+    fn reset(&mut self) {
+        self.destination = None;
+        self.last_sent = [None; NUM_ZONES];
+        self.gate_open = [false; NUM_ZONES];
+    }
+
+    fn send_event(&mut self, config: &OscConfig, event: &SampleEvent) {
+        let zone = event.processed.zone;
+        let value = event.processed.value_normalized;
+        let mut messages = Vec::with_capacity(2);
+
+        let gate = value.abs() > config.gate_threshold;
+        if gate != self.gate_open[zone] {
+            self.gate_open[zone] = gate;
+            messages.push(gate_message(config, zone, gate));
+        }
+        if self.value_due(zone, config.max_rate_hz) {
+            messages.push(OscMessage {
+                addr: OscConfig::address(&config.value_address, zone),
+                args: vec![OscType::Float(value as f32)],
+            });
+        }
+        self.send(config, messages);
+    }
+
+    /// Sends a gate off for every zone that is still open, so receivers don't
+    /// keep a note hanging after the source stops.
+    fn close_gates(&mut self, config: &OscConfig) {
+        let messages = (0..NUM_ZONES)
+            .filter(|&zone| self.gate_open[zone])
+            .map(|zone| gate_message(config, zone, false))
+            .collect();
+        self.gate_open = [false; NUM_ZONES];
+        self.send(config, messages);
+    }
+
+    fn value_due(&mut self, zone: usize, max_rate_hz: f64) -> bool {
+        let now = Instant::now();
+        if max_rate_hz > 0.0 {
+            let interval = Duration::from_secs_f64(1.0 / max_rate_hz);
+            if self.last_sent[zone].is_some_and(|last| now.duration_since(last) < interval) {
+                return false;
+            }
+        }
+        self.last_sent[zone] = Some(now);
+        true
+    }
+
+    fn send(&mut self, config: &OscConfig, messages: Vec<OscMessage>) {
+        if messages.is_empty() {
+            return;
+        }
+        let destination = Destination::get(&mut self.destination, &config.host, config.port);
+        let target = destination.target();
+        let result = messages.into_iter().try_for_each(|message| {
+            let packet = encoder::encode(&OscPacket::Message(message)).map_err(|e| e.to_string())?;
+            destination.send(&packet)
+        });
+        match result {
+            Ok(()) => self.errors.succeeded(&target),
+            Err(e) => self.errors.failed(&target, &e),
+        }
+    }
+}
+
+fn gate_message(config: &OscConfig, zone: usize, open: bool) -> OscMessage {
+    OscMessage {
+        addr: OscConfig::address(&config.gate_address, zone),
+        args: vec![OscType::Int(open as i32)],
+    }
+}
@@ -0,0 +1,397 @@
+use crate::config::{AppConfig, ConfigStore};
+use crate::diagnostics::STATS_PUBLISH_INTERVAL;
+use crate::midi::MidiError;
+use crate::pipeline::{ProcessedSample, SampleEvent};
+use crate::sync::LockExt;
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Instant;
+use thiserror::Error;
+use tracing::{error, warn};
+
+/// Samples buffered for a sink's thread before new ones are dropped
+const SINK_QUEUE_SIZE: usize = 1024;
+
+#[derive(Error, Debug)]
+pub enum OutputError {
+    #[error(transparent)]
+    Midi(#[from] MidiError),
+    /// What the sink's thread reported, e.g. a failed UDP send
+    #[error("{0}")]
+    Failed(String),
+    /// The sink's thread is behind, the sample was not queued
+    #[error("fell behind, sample dropped")]
+    Dropped,
+}
+
+/// What the sinks are handed for every sample
+pub struct SinkSample {
+    /// `midi_action` is set by the MIDI sink, which comes first, for the
+    /// sinks after it and the taps
+    pub event: SampleEvent,
+    /// The sample as MIDI plays it, see `ZoneEngine::logical_sample`
+    pub midi_sample: ProcessedSample,
+    /// The output zone MIDI and the zone bindings play the sample as
+    pub midi_zone: usize,
+    /// When the pipeline started handling the sample
+    pub now: Instant,
+}
+
+/// A consumer of processed samples, like MIDI, OSC or Art-Net. Sinks are
+/// driven by the pipeline's `SinkManager`, in the order they were added, and
+/// each follows its own section of the config.
+pub trait OutputSink: Send {
+    /// Shown in the Outputs tab, e.g. "OSC"
+    fn name(&self) -> &'static str;
+
+    /// Whether the sink's section of `config` has it switched on
+    fn enabled(&self, config: &AppConfig) -> bool;
+
+    /// Sends what `sample` calls for. Only called while enabled.
+    fn handle(&mut self, sample: &mut SinkSample, config: &ConfigStore) -> Result<(), OutputError>;
+
+    /// Releases whatever the sink holds, notes, gates or keys, so nothing
+    /// keeps playing: on panic, at the end of a session and when the sink
+    /// gets switched off.
+    fn flush(&mut self, config: &ConfigStore) -> Result<(), OutputError>;
+
+    /// `zone` stopped sending samples, as it was disabled on the device;
+    /// `logical_zone` is the logical zone MIDI plays it as.
+    fn release_zone(&mut self, _zone: usize, _logical_zone: usize, _config: &ConfigStore) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    /// Errors of work done away from the pipeline since the last call
+    fn take_errors(&mut self) -> Vec<OutputError> {
+        Vec::new()
+    }
+
+    /// A line about the sink's state for the Outputs tab, e.g. the port it
+    /// is connected to
+    fn detail(&self) -> Option<String> {
+        None
+    }
+
+    /// Ends the sink for good, waiting for its thread if it has one.
+    fn shutdown(&mut self) {}
+}
+
+/// Identifies a sink added to a `SinkManager`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SinkId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SinkState {
+    Active,
+    /// Switched off in the config
+    Disabled,
+    /// Switched off at runtime whatever the config says, like MIDI while
+    /// muted
+    Muted,
+}
+
+/// How a sink is doing, as shown in the Outputs tab
+#[derive(Debug, Clone)]
+pub struct SinkStatus {
+    pub name: &'static str,
+    pub state: SinkState,
+    pub detail: Option<String>,
+    /// Samples handed to the sink
+    pub samples: u64,
+    /// Samples its thread was too far behind for
+    pub dropped: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+}
+
+impl SinkStatus {
+    fn record(&mut self, result: Result<(), OutputError>) {
+        match result {
+            Ok(()) => {}
+            Err(OutputError::Dropped) => self.dropped += 1,
+            Err(e) => {
+                self.errors += 1;
+                self.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// The statuses of the sinks as last published, for the GUI
+#[derive(Clone, Default)]
+pub struct OutputStatus {
+    latest: Arc<Mutex<Vec<SinkStatus>>>,
+}
+
+impl OutputStatus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Empty until the pipeline published once
+    pub fn latest(&self) -> Vec<SinkStatus> {
+        self.latest.lock_or_recover().clone()
+    }
+}
+
+struct SinkSlot {
+    sink: Box<dyn OutputSink>,
+    muted: bool,
+    status: SinkStatus,
+}
+
+/// The output sinks of a pipeline. Switching a sink off in the config or
+/// muting it flushes it once, then it gets no samples until it is back on.
+/// Dropping the manager shuts every sink down.
+#[derive(Default)]
+pub struct SinkManager {
+    sinks: Vec<SinkSlot>,
+    /// Where the statuses are published, and when they were last
+    shared: Option<(OutputStatus, Option<Instant>)>,
+}
+
+impl SinkManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, sink: Box<dyn OutputSink>) -> SinkId {
+        let status = SinkStatus {
+            name: sink.name(),
+            state: SinkState::Disabled,
+            detail: None,
+            samples: 0,
+            dropped: 0,
+            errors: 0,
+            last_error: None,
+        };
+        self.sinks.push(SinkSlot {
+            sink,
+            muted: false,
+            status,
+        });
+        SinkId(self.sinks.len() - 1)
+    }
+
+    /// Publishes the statuses to `shared` every `STATS_PUBLISH_INTERVAL`.
+    pub fn set_shared_status(&mut self, shared: OutputStatus) {
+        self.shared = Some((shared, None));
+    }
+
+    /// Mutes or unmutes the sink `id`; muting flushes it first.
+    pub fn set_muted(&mut self, id: SinkId, muted: bool, config: &ConfigStore) {
+        self.sinks[id.0].muted = muted;
+        self.update_states(config);
+    }
+
+    /// Failed sends of the sink `id` so far
+    pub fn errors(&self, id: SinkId) -> u64 {
+        self.sinks[id.0].status.errors
+    }
+
+    /// Hands `sample` to every active sink, in order.
+    pub fn handle(&mut self, sample: &mut SinkSample, config: &ConfigStore) {
+        self.update_states(config);
+        for slot in &mut self.sinks {
+            if slot.status.state == SinkState::Active {
+                slot.status.samples += 1;
+                let result = slot.sink.handle(sample, config);
+                slot.status.record(result);
+            }
+            for e in slot.sink.take_errors() {
+                slot.status.record(Err(e));
+            }
+        }
+        self.publish_if_due(sample.now);
+    }
+
+    /// Flushes every active sink.
+    pub fn flush(&mut self, config: &ConfigStore) {
+        for slot in self.sinks.iter_mut().filter(|slot| slot.status.state == SinkState::Active) {
+            let result = slot.sink.flush(config);
+            slot.status.record(result);
+        }
+    }
+
+    /// Tells every active sink that `zone` stopped sending samples.
+    pub fn release_zone(&mut self, zone: usize, logical_zone: usize, config: &ConfigStore) {
+        for slot in self.sinks.iter_mut().filter(|slot| slot.status.state == SinkState::Active) {
+            let result = slot.sink.release_zone(zone, logical_zone, config);
+            slot.status.record(result);
+        }
+    }
+
+    /// Follows the config and the mutes, flushing the sinks that just went
+    /// off.
+    fn update_states(&mut self, config: &ConfigStore) {
+        for slot in &mut self.sinks {
+            let state = if slot.muted {
+                SinkState::Muted
+            } else if slot.sink.enabled(config) {
+                SinkState::Active
+            } else {
+                SinkState::Disabled
+            };
+            if slot.status.state == SinkState::Active && state != SinkState::Active {
+                let result = slot.sink.flush(config);
+                slot.status.record(result);
+            }
+            slot.status.state = state;
+        }
+    }
+
+    fn publish_if_due(&mut self, now: Instant) {
+        let Some((shared, published)) = &mut self.shared else {
+            return;
+        };
+        if published.is_some_and(|published| now.duration_since(published) < STATS_PUBLISH_INTERVAL) {
+            return;
+        }
+        let statuses = self
+            .sinks
+            .iter_mut()
+            .map(|slot| {
+                slot.status.detail = slot.sink.detail();
+                slot.status.clone()
+            })
+            .collect();
+        *shared.latest.lock_or_recover() = statuses;
+        *published = Some(now);
+    }
+}
+
+impl Drop for SinkManager {
+    fn drop(&mut self) {
+        for slot in &mut self.sinks {
+            slot.sink.shutdown();
+        }
+    }
+}
+
+/// What a sink's thread receives
+pub enum SinkMessage {
+    Sample(SampleEvent),
+    /// See `OutputSink::flush`
+    Flush,
+}
+
+/// Where a sink's thread reports its errors, to be counted in its status
+#[derive(Clone)]
+pub struct SinkErrors {
+    tx: Sender<String>,
+}
+
+impl SinkErrors {
+    pub fn report(&self, error: impl fmt::Display) {
+        // Gone only while the sink shuts down
+        let _ = self.tx.send(error.to_string());
+    }
+
+    /// Errors of a sink tested without a `ThreadedSink`, and where they arrive
+    pub fn channel() -> (Self, Receiver<String>) {
+        let (tx, rx) = mpsc::channel();
+        (Self { tx }, rx)
+    }
+}
+
+/// A sink doing its work on a thread of its own, for outputs that may block
+/// or keep their own pace. The pipeline never waits for the thread: samples
+/// that don't fit in its queue are dropped and counted. The thread ends when
+/// its receiver disconnects, and should release what it holds then.
+pub struct ThreadedSink {
+    name: &'static str,
+    enabled: fn(&AppConfig) -> bool,
+    tx: Option<SyncSender<SinkMessage>>,
+    errors: Receiver<String>,
+    thread: Option<JoinHandle<()>>,
+    /// A flush that didn't fit in the queue, sent ahead of the next sample
+    pending_flush: bool,
+    dropped: u64,
+}
+
+impl ThreadedSink {
+    /// Starts `run` on a new thread, with the receiving end of the queue and
+    /// where to report errors.
+    pub fn spawn(
+        name: &'static str,
+        enabled: fn(&AppConfig) -> bool,
+        run: impl FnOnce(Receiver<SinkMessage>, SinkErrors) + Send + 'static,
+    ) -> Self {
+        let (tx, rx) = mpsc::sync_channel(SINK_QUEUE_SIZE);
+        let (errors_tx, errors) = mpsc::channel();
+        let thread = std::thread::spawn(move || run(rx, SinkErrors { tx: errors_tx }));
+        Self {
+            name,
+            enabled,
+            tx: Some(tx),
+            errors,
+            thread: Some(thread),
+            pending_flush: false,
+            dropped: 0,
+        }
+    }
+
+    fn send(&mut self, message: SinkMessage) -> Result<(), OutputError> {
+        let Some(tx) = &self.tx else {
+            return Ok(());
+        };
+        let try_send = |message| match tx.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(OutputError::Dropped),
+            Err(TrySendError::Disconnected(_)) => Err(OutputError::Failed("output thread stopped".to_string())),
+        };
+        if self.pending_flush {
+            try_send(SinkMessage::Flush)?;
+            self.pending_flush = false;
+        }
+        try_send(message)
+    }
+}
+
+impl OutputSink for ThreadedSink {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn enabled(&self, config: &AppConfig) -> bool {
+        (self.enabled)(config)
+    }
+
+    fn handle(&mut self, sample: &mut SinkSample, _config: &ConfigStore) -> Result<(), OutputError> {
+        let result = self.send(SinkMessage::Sample(sample.event));
+        if matches!(result, Err(OutputError::Dropped)) {
+            self.dropped += 1;
+        }
+        result
+    }
+
+    fn flush(&mut self, _config: &ConfigStore) -> Result<(), OutputError> {
+        // Unlike a sample, a flush must not get lost; but waiting for room
+        // could wait forever on a thread that waits for the config
+        match self.send(SinkMessage::Flush) {
+            Err(OutputError::Dropped) => {
+                self.pending_flush = true;
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn take_errors(&mut self) -> Vec<OutputError> {
+        self.errors.try_iter().map(OutputError::Failed).collect()
+    }
+
+    fn shutdown(&mut self) {
+        self.tx = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("{} output thread panicked", self.name);
+            }
+        }
+        if self.dropped > 0 {
+            warn!("{} output fell behind, dropped {} samples", self.name, self.dropped);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::config::{PatchBinding, PatchConfig, PatchGesture};
+use crate::gesture::{Gesture, Gestures};
+use crate::midi::{self, MidiError, MidiSink};
+use crate::sync::LockExt;
+use std::sync::{Arc, Mutex};
+use tracing::info;
+
+/// The patch slot sent last, shared by the MIDI tab and the pipeline's
+/// gesture bindings so "next" continues from either
+#[derive(Clone, Default)]
+pub struct PatchSelector {
+    current: Arc<Mutex<Option<usize>>>,
+}
+
+impl PatchSelector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index of the slot sent last, if it still exists
+    pub fn current(&self, config: &PatchConfig) -> Option<usize> {
+        (*self.current.lock_or_recover()).filter(|&index| index < config.slots.len())
+    }
+
+    /// Sends slot `index` of `config`: Bank Select if the slot has a bank,
+    /// then the Program Change.
+    pub fn send(&self, sink: &mut dyn MidiSink, config: &PatchConfig, index: usize) -> Result<(), MidiError> {
+        let Some(slot) = config.slots.get(index) else {
+            return Ok(());
+        };
+        if let Some(bank) = slot.bank {
+            midi::send_bank_select(sink, config.channel, bank)?;
+        }
+        midi::send_program_change(sink, config.channel, slot.program)?;
+        *self.current.lock_or_recover() = Some(index);
+        info!("Sent patch {} ({})", index + 1, slot.name);
+        Ok(())
+    }
+
+    /// Sends the slot after the current one, or the first if none was sent.
+    pub fn send_next(&self, sink: &mut dyn MidiSink, config: &PatchConfig) -> Result<(), MidiError> {
+        if config.slots.is_empty() {
+            return Ok(());
+        }
+        let next = self.current(config).map_or(0, |index| (index + 1) % config.slots.len());
+        self.send(sink, config, next)
+    }
+
+    /// Sends the slots bound to one of `gestures` of `zone`.
+    pub fn handle_gestures(
+        &self,
+        sink: &mut dyn MidiSink,
+        config: &PatchConfig,
+        zone: usize,
+        gestures: Gestures,
+    ) -> Result<(), MidiError> {
+        if triggered(config.next, zone, gestures) {
+            self.send_next(sink, config)?;
+        }
+        for index in 0..config.slots.len() {
+            if triggered(config.slots[index].binding, zone, gestures) {
+                self.send(sink, config, index)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `binding` is bound to `zone` and one of `gestures`
+pub fn triggered(binding: Option<PatchBinding>, zone: usize, gestures: Gestures) -> bool {
+    binding.is_some_and(|binding| binding.zone == zone && gestures.contains(gesture(binding.gesture)))
+}
+
+fn gesture(gesture: PatchGesture) -> Gesture {
+    match gesture {
+        PatchGesture::Tap => Gesture::Tap,
+        PatchGesture::DoubleTap => Gesture::DoubleTap,
+        PatchGesture::Hold => Gesture::Hold,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PatchSlot;
+    use crate::midi::CaptureMidiSink;
+
+    fn slot(name: &str, bank: Option<u16>, program: u8, binding: Option<PatchBinding>) -> PatchSlot {
+        PatchSlot {
+            name: name.to_string(),
+            bank,
+            program,
+            binding,
+        }
+    }
+
+    fn gestures(gesture: Gesture) -> Gestures {
+        let mut gestures = Gestures::default();
+        gestures.insert(gesture);
+        gestures
+    }
+
+    fn config() -> PatchConfig {
+        PatchConfig {
+            channel: 2,
+            slots: vec![
+                slot("Pad", Some(300), 5, None),
+                slot("Lead", None, 12, Some(PatchBinding { zone: 3, gesture: PatchGesture::Hold })),
+            ],
+            next: Some(PatchBinding { zone: 7, gesture: PatchGesture::DoubleTap }),
+        }
+    }
+
+    #[test]
+    fn bank_select_then_program_change() {
+        let mut sink = CaptureMidiSink::new();
+        PatchSelector::new().send(&mut sink, &config(), 0).unwrap();
+        assert_eq!(sink.take(), [vec![0xB2, 0x00, 0x02], vec![0xB2, 0x20, 0x2C], vec![0xC2, 0x05]]);
+    }
+
+    #[test]
+    fn a_slot_without_a_bank_only_sends_the_program() {
+        let mut sink = CaptureMidiSink::new();
+        let selector = PatchSelector::new();
+        selector.send(&mut sink, &config(), 1).unwrap();
+        assert_eq!(sink.take(), [vec![0xC2, 12]]);
+        assert_eq!(selector.current(&config()), Some(1));
+        // A missing slot sends nothing and keeps the current one
+        selector.send(&mut sink, &config(), 5).unwrap();
+        assert!(sink.take().is_empty());
+        assert_eq!(selector.current(&config()), Some(1));
+    }
+
+    #[test]
+    fn helpers_mask_channel_and_data() {
+        let mut sink = CaptureMidiSink::new();
+        midi::send_bank_select(&mut sink, 0x1F, 0x3FFF).unwrap();
+        midi::send_program_change(&mut sink, 0x10, 0xFF).unwrap();
+        assert_eq!(sink.take(), [vec![0xBF, 0x00, 0x7F], vec![0xBF, 0x20, 0x7F], vec![0xC0, 0x7F]]);
+    }
+
+    #[test]
+    fn next_starts_at_the_first_slot_and_wraps() {
+        let mut sink = CaptureMidiSink::new();
+        let selector = PatchSelector::new();
+        let config = config();
+        for expected in [0, 1, 0] {
+            selector.send_next(&mut sink, &config).unwrap();
+            assert_eq!(selector.current(&config), Some(expected));
+        }
+        selector.send_next(&mut sink, &PatchConfig::default()).unwrap();
+        assert_eq!(selector.current(&PatchConfig::default()), None);
+    }
+
+    #[test]
+    fn gestures_send_their_bound_slots() {
+        let mut sink = CaptureMidiSink::new();
+        let selector = PatchSelector::new();
+        let config = config();
+        selector.handle_gestures(&mut sink, &config, 3, gestures(Gesture::Hold)).unwrap();
+        assert_eq!(sink.take(), [vec![0xC2, 12]]);
+        // Another zone or gesture sends nothing
+        selector.handle_gestures(&mut sink, &config, 2, gestures(Gesture::Hold)).unwrap();
+        selector.handle_gestures(&mut sink, &config, 3, gestures(Gesture::Tap)).unwrap();
+        assert!(sink.take().is_empty());
+        // "Next" goes on from the slot the gesture sent
+        selector.handle_gestures(&mut sink, &config, 7, gestures(Gesture::DoubleTap)).unwrap();
+        assert_eq!(selector.current(&config), Some(0));
+    }
+}
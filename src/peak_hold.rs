@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+/// The highest recent magnitude of a zone: it jumps up with the level, is
+/// held for a while and then falls at a fixed rate until it meets the level
+/// again, so a short transient stays visible.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PeakHold {
+    peak: f64,
+    /// When the current peak was reached
+    since: Option<Instant>,
+    /// Time of the previous update, the decay runs from there
+    last: Option<Instant>,
+}
+
+impl PeakHold {
+    /// Feeds the magnitude `level` at `now` and returns the peak. The peak
+    /// is held for `hold`, then decays by `decay_per_sec` (in magnitude per
+    /// second), never below `level`.
+    pub fn update(&mut self, level: f64, now: Instant, hold: Duration, decay_per_sec: f64) -> f64 {
+        let last = self.last.replace(now);
+        let held_until = self.since.map(|since| since + hold);
+        if level >= self.peak || held_until.is_none() {
+            self.peak = level;
+            self.since = Some(now);
+            return self.peak;
+        }
+        if let (Some(last), Some(held_until)) = (last, held_until) {
+            // Only the time past the hold counts, also for the update it ran out in
+            let decaying = now.saturating_duration_since(last.max(held_until));
+            self.peak -= decay_per_sec * decaying.as_secs_f64();
+        }
+        if self.peak <= level {
+            self.peak = level;
+            self.since = Some(now);
+        }
+        self.peak
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOLD: Duration = Duration::from_millis(500);
+    const DECAY: f64 = 0.5;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn rises_with_the_level() {
+        let start = Instant::now();
+        let mut peak = PeakHold::default();
+        assert_close(peak.update(0.2, start, HOLD, DECAY), 0.2);
+        assert_close(peak.update(0.6, start + Duration::from_millis(10), HOLD, DECAY), 0.6);
+    }
+
+    #[test]
+    fn is_held_until_the_hold_expires() {
+        let start = Instant::now();
+        let mut peak = PeakHold::default();
+        peak.update(0.8, start, HOLD, DECAY);
+        for ms in [100, 300, 500] {
+            assert_close(peak.update(0.1, start + Duration::from_millis(ms), HOLD, DECAY), 0.8);
+        }
+        // Only the 100 ms past the hold decay, not the whole update interval
+        assert_close(peak.update(0.1, start + Duration::from_millis(600), HOLD, DECAY), 0.75);
+    }
+
+    #[test]
+    fn decays_at_the_configured_slope() {
+        let start = Instant::now();
+        let mut peak = PeakHold::default();
+        peak.update(0.9, start, HOLD, DECAY);
+        let mut previous = 0.9;
+        for step in 1..=5 {
+            let now = start + HOLD + Duration::from_millis(100 * step);
+            let value = peak.update(0.0, now, HOLD, DECAY);
+            assert_close(previous - value, 0.05);
+            previous = value;
+        }
+        assert_close(previous, 0.65);
+    }
+
+    #[test]
+    fn never_falls_below_the_level() {
+        let start = Instant::now();
+        let mut peak = PeakHold::default();
+        peak.update(0.5, start, HOLD, DECAY);
+        assert_close(peak.update(0.45, start + Duration::from_secs(2), HOLD, DECAY), 0.45);
+        // Met by the level, the peak is held again from there
+        assert_close(peak.update(0.1, start + Duration::from_millis(2400), HOLD, DECAY), 0.45);
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Most values kept, so a long window at a high sample rate stays cheap; the
+/// oldest go first
+const MAX_VALUES: usize = 20_000;
+
+/// The values of a rolling time window, kept sorted as well so percentiles
+/// are a lookup
+#[derive(Debug, Clone, Default)]
+pub struct PercentileWindow {
+    /// In arrival order, for dropping the oldest
+    values: VecDeque<(Instant, f64)>,
+    sorted: Vec<f64>,
+}
+
+impl PercentileWindow {
+    /// Adds `value`, received at `now`, and drops the values older than
+    /// `window`. NaN is ignored.
+    pub fn add(&mut self, value: f64, now: Instant, window: Duration) {
+        if value.is_nan() {
+            return;
+        }
+        while let Some(&(time, oldest)) = self.values.front() {
+            if now.saturating_duration_since(time) <= window && self.values.len() < MAX_VALUES {
+                break;
+            }
+            self.values.pop_front();
+            let index = self.sorted.partition_point(|&sorted| sorted < oldest);
+            self.sorted.remove(index);
+        }
+        self.values.push_back((now, value));
+        let index = self.sorted.partition_point(|&sorted| sorted < value);
+        self.sorted.insert(index, value);
+    }
+
+    /// The `percent` percentile of the window (nearest rank), `None` while
+    /// it is empty
+    pub fn percentile(&self, percent: f64) -> Option<f64> {
+        let last = self.sorted.len().checked_sub(1)?;
+        let rank = (percent.clamp(0.0, 100.0) / 100.0 * last as f64).round() as usize;
+        Some(self.sorted[rank])
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.sorted.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn percentiles_are_the_nearest_rank() {
+        let mut window = PercentileWindow::default();
+        assert_eq!(window.percentile(50.0), None);
+        let now = Instant::now();
+        for value in [5.0, 1.0, 4.0, 2.0, 3.0] {
+            window.add(value, now, WINDOW);
+        }
+        assert_eq!(window.percentile(0.0), Some(1.0));
+        assert_eq!(window.percentile(50.0), Some(3.0));
+        assert_eq!(window.percentile(60.0), Some(3.0));
+        assert_eq!(window.percentile(65.0), Some(4.0));
+        assert_eq!(window.percentile(100.0), Some(5.0));
+        assert_eq!(window.percentile(150.0), Some(5.0), "clamped to 100");
+    }
+
+    #[test]
+    fn values_older_than_the_window_go() {
+        let mut window = PercentileWindow::default();
+        let start = Instant::now();
+        window.add(100.0, start, WINDOW);
+        window.add(1.0, start + Duration::from_secs(5), WINDOW);
+        assert_eq!(window.percentile(100.0), Some(100.0));
+        window.add(2.0, start + Duration::from_secs(11), WINDOW);
+        assert_eq!(window.percentile(100.0), Some(2.0));
+        assert_eq!(window.percentile(0.0), Some(1.0));
+    }
+
+    #[test]
+    fn nan_is_ignored() {
+        let mut window = PercentileWindow::default();
+        window.add(f64::NAN, Instant::now(), WINDOW);
+        assert_eq!(window.percentile(50.0), None);
+    }
+
+    #[test]
+    fn the_oldest_go_past_the_most_values() {
+        let mut window = PercentileWindow::default();
+        let now = Instant::now();
+        window.add(-1.0, now, WINDOW);
+        for _ in 0..MAX_VALUES {
+            window.add(1.0, now, WINDOW);
+        }
+        assert_eq!(window.values.len(), MAX_VALUES);
+        assert_eq!(window.percentile(0.0), Some(1.0));
+    }
+
+    #[test]
+    fn clear_empties_it() {
+        let mut window = PercentileWindow::default();
+        window.add(1.0, Instant::now(), WINDOW);
+        window.clear();
+        assert_eq!(window.percentile(50.0), None);
+    }
+}
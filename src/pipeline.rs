@@ -0,0 +1,710 @@
+use crate::auto_gain::AutoGain;
+use crate::config::{ConfigStore, DildonicaZoneConfig, MAX_PLOT_DURATION_SECS, NUM_ZONES};
+use crate::diagnostics::{PipelineStats, SampleHealth, SharedStats, STATS_PUBLISH_INTERVAL};
+use crate::drift::DriftChange;
+use crate::gesture::{GestureDetector, Gestures};
+use crate::looper::LooperHandle;
+use crate::markers::SessionMarkers;
+use crate::metronome::Metronome;
+use crate::midi::MidiAction;
+use crate::midi_output::MidiOutputSink;
+use crate::output::{OutputSink, OutputStatus, SinkId, SinkManager, SinkSample};
+use crate::patch;
+use crate::plot_history::{PlotFeed, PlotHistory, PlotPoint};
+use crate::recorder::unix_time;
+use crate::sample::Sample;
+use crate::sync::{enter_side, LockExt, LockSide, TrackedMutex};
+use crate::take_buffer::{TakeBuffer, Takes};
+use crate::usage::UsageStats;
+use crate::zone_engine::ZoneEngine;
+pub use crate::zone_engine::ZoneStats;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// Commands buffered until the next sample
+const COMMAND_QUEUE_SIZE: usize = 16;
+/// How long `PipelineCommand::Calibrate` averages at `CALIBRATION_ALPHA`,
+/// unless told otherwise
+pub const CALIBRATION_TIME: Duration = Duration::from_secs(2);
+/// Averaging factor while calibrating, so the baselines settle within
+/// `CALIBRATION_TIME` instead of drifting there slowly
+const CALIBRATION_ALPHA: f64 = 0.05;
+
+/// A sample as it leaves the pipeline, for the GUI plot and the TUI meters
+#[derive(Clone, Copy)]
+pub struct ProcessedSample {
+    pub timestamp: i32,
+    /// Output zone, after the zone map; the device zone with
+    /// `ZoneMapScope::MidiOnly`
+    pub zone: usize,
+    pub value_raw: f64,
+    pub value_normalized: f64,
+    /// Rate of change of `value_normalized` per second of device time
+    pub derivative: f64,
+    /// Held peak of the magnitude, the magnitude itself without peak hold
+    pub peak: f64,
+    /// The device flagged the raw value as saturated
+    pub saturated: bool,
+}
+
+/// Requests from outside the source task, handled before the next sample
+#[derive(Debug, Clone, Copy)]
+pub enum PipelineCommand {
+    /// Note Off for every held note
+    Panic,
+    /// Forgets every zone's baseline, the next sample starts a new one
+    ResetBaselines,
+    /// Resets the baselines and lets them settle quickly on the current
+    /// resting values for this long; don't touch the instrument meanwhile
+    Calibrate(Duration),
+    /// Stops or resumes MIDI output; muting releases held notes first
+    SetMuted(bool),
+}
+
+/// What a tap sees for every sample: the sample as received from the source,
+/// the processed values, the MIDI message sent for them, if any, and the
+/// touch gestures the sample completed. In fixed-rate mode a continuous
+/// message is only queued for the next tick of the MIDI clock.
+#[derive(Clone, Copy)]
+pub struct SampleEvent {
+    pub sample: Sample,
+    /// Host wall-clock time the sample was handled at
+    pub received: SystemTime,
+    pub processed: ProcessedSample,
+    pub midi_action: Option<MidiAction>,
+    pub gestures: Gestures,
+}
+
+/// A non-blocking consumer of `SampleEvent`s. A slow reader never stalls the
+/// pipeline, or MIDI output with it: events that don't fit in the channel are
+/// dropped, newest first, and counted both here and per zone in the
+/// pipeline's stats.
+pub struct SampleTap {
+    tx: mpsc::Sender<SampleEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl SampleTap {
+    /// Creates a tap and the receiving end for its consumer.
+    pub fn new(capacity: usize) -> (Self, mpsc::Receiver<SampleEvent>, Arc<AtomicU64>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let tap = Self {
+            tx,
+            dropped: dropped.clone(),
+        };
+        (tap, rx, dropped)
+    }
+
+    /// Returns false if the event was dropped because the consumer is behind.
+    pub fn offer(&self, event: SampleEvent) -> bool {
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.tx.try_send(event) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+}
+
+/// Turns raw samples from any source (BLE, replay) into normalized values,
+/// drives the output sinks and forwards the results to the taps and the plot.
+pub struct Pipeline {
+    zones: ZoneEngine,
+    /// MIDI first, so the others see the message it sent
+    outputs: SinkManager,
+    midi: SinkId,
+    gesture_detector: GestureDetector,
+    app_config: Arc<TrackedMutex<ConfigStore>>,
+    /// `None` in headless mode, where nothing is plotted
+    plot: Option<PlotHistory>,
+    taps: Vec<SampleTap>,
+    stats: PipelineStats,
+    /// Stop after this many samples
+    max_samples: Option<u64>,
+    command_tx: mpsc::Sender<PipelineCommand>,
+    command_rx: mpsc::Receiver<PipelineCommand>,
+    calibrating_until: Option<Instant>,
+    muted: bool,
+    /// The device's zone configs, `None` without a device
+    zone_configs: Option<Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>>,
+    /// Device zones disabled in `zone_configs` as of the last sample
+    disabled_zones: [bool; NUM_ZONES],
+    /// The MIDI sink's looper, which follows panic and mute
+    looper: Option<LooperHandle>,
+    /// Stopped by panic and silenced by mute
+    metronome: Option<Metronome>,
+    /// Told about every sample, for checking they keep coming
+    health: Option<SampleHealth>,
+    /// Counts the touches of every zone
+    usage: Option<UsageStats>,
+    /// Where `stats` are published, and when they were last
+    shared_stats: Option<(SharedStats, Option<Instant>)>,
+    /// The raw samples of the last minutes, for saving takes
+    takes: TakeBuffer,
+    /// Stamped with the samples they were dropped at
+    markers: SessionMarkers,
+}
+
+impl Pipeline {
+    pub fn new(app_config: Arc<TrackedMutex<ConfigStore>>, midi: MidiOutputSink, plot_feed: Option<PlotFeed>) -> Self {
+        let (command_tx, command_rx) = mpsc::channel(COMMAND_QUEUE_SIZE);
+        let alpha = app_config.lock_or_recover().exponential_alpha;
+        let looper = midi.looper().cloned();
+        let mut outputs = SinkManager::new();
+        let midi = outputs.add(Box::new(midi));
+        Self {
+            zones: ZoneEngine::new(NUM_ZONES, alpha),
+            outputs,
+            midi,
+            gesture_detector: GestureDetector::new(),
+            app_config,
+            plot: plot_feed.map(PlotHistory::new),
+            taps: Vec::new(),
+            stats: PipelineStats::new(),
+            max_samples: None,
+            command_tx,
+            command_rx,
+            calibrating_until: None,
+            muted: false,
+            zone_configs: None,
+            disabled_zones: [false; NUM_ZONES],
+            looper,
+            metronome: None,
+            health: None,
+            usage: None,
+            shared_stats: None,
+            takes: TakeBuffer::new(),
+            markers: SessionMarkers::new(),
+        }
+    }
+
+    /// Sends `PipelineCommand`s to this pipeline. Sending fails once the
+    /// pipeline is gone.
+    pub fn command_sender(&self) -> mpsc::Sender<PipelineCommand> {
+        self.command_tx.clone()
+    }
+
+    /// Saves takes from this pipeline's take buffer.
+    pub fn takes(&self) -> Takes {
+        self.takes.takes()
+    }
+
+    /// The markers of this pipeline's session, dropped at its samples.
+    pub fn markers(&self) -> SessionMarkers {
+        self.markers.clone()
+    }
+
+    pub fn set_max_samples(&mut self, max_samples: u64) {
+        self.max_samples = Some(max_samples);
+    }
+
+    /// Ignores samples of device zones disabled in `zone_configs` and takes
+    /// the cycle count windows from them, following the configs as they are
+    /// read from or written to the device.
+    pub fn set_zone_configs(&mut self, zone_configs: Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>) {
+        self.zone_configs = Some(zone_configs);
+    }
+
+    /// Applies and learns the zone gains in `auto_gain` while `auto_gain` is
+    /// enabled in the config.
+    pub fn set_auto_gain(&mut self, auto_gain: AutoGain) {
+        self.zones.set_auto_gain(auto_gain);
+    }
+
+    /// Records every sample in `health`, also those of disabled zones.
+    pub fn set_health(&mut self, health: SampleHealth) {
+        self.health = Some(health);
+    }
+
+    /// Stops `metronome` on panic and silences it while muted.
+    pub fn set_metronome(&mut self, metronome: Metronome) {
+        self.metronome = Some(metronome);
+    }
+
+    /// Counts the touches of enabled zones in `usage`.
+    pub fn set_usage(&mut self, usage: UsageStats) {
+        self.usage = Some(usage);
+    }
+
+    /// Publishes the stats to `shared` every `STATS_PUBLISH_INTERVAL`.
+    pub fn set_shared_stats(&mut self, shared: SharedStats) {
+        self.shared_stats = Some((shared, None));
+    }
+
+    /// Hands every sample to `sink` after the MIDI output and the sinks
+    /// added before.
+    pub fn add_output(&mut self, sink: Box<dyn OutputSink>) {
+        self.outputs.add(sink);
+    }
+
+    /// Publishes the statuses of the output sinks to `shared` every
+    /// `STATS_PUBLISH_INTERVAL`.
+    pub fn set_output_status(&mut self, shared: OutputStatus) {
+        self.outputs.set_shared_status(shared);
+    }
+
+    pub fn add_tap(&mut self, tap: SampleTap) {
+        self.taps.push(tap);
+    }
+
+    pub fn stats(&self) -> &PipelineStats {
+        &self.stats
+    }
+
+    /// Baseline and newest values of every device zone
+    pub fn zone_stats(&self) -> Vec<ZoneStats> {
+        self.zones.snapshot_stats()
+    }
+
+    /// Counts a packet from the source that wasn't a valid sample.
+    pub fn record_parse_error(&mut self) {
+        self.stats.parse_errors += 1;
+    }
+
+    /// Counts a stall of the sample stream noticed by the session's watchdog.
+    pub fn record_stall(&mut self) {
+        self.stats.stalls += 1;
+    }
+
+    /// Flushes every output, so no note, gate or key is left on after the
+    /// source stops.
+    pub fn flush_outputs(&mut self) {
+        let config = self.app_config.lock_or_recover();
+        self.outputs.flush(&config);
+        self.stats.midi_errors = self.outputs.errors(self.midi);
+    }
+
+    fn run_command(&mut self, command: PipelineCommand, now: Instant) {
+        match command {
+            PipelineCommand::Panic => {
+                info!("Releasing all notes");
+                self.flush_outputs();
+                if let Some(looper) = &self.looper {
+                    looper.control(|looper| looper.panic());
+                }
+                if let Some(metronome) = &self.metronome {
+                    metronome.stop();
+                }
+            }
+            PipelineCommand::ResetBaselines => {
+                info!("Resetting zone baselines");
+                self.reset_baselines();
+            }
+            PipelineCommand::Calibrate(duration) => {
+                info!("Calibrating for {:.1} s, don't touch the instrument", duration.as_secs_f64());
+                self.reset_baselines();
+                self.calibrating_until = Some(now + duration);
+            }
+            PipelineCommand::SetMuted(muted) => {
+                if muted && !self.muted {
+                    info!("MIDI output muted");
+                } else if !muted && self.muted {
+                    info!("MIDI output unmuted");
+                }
+                self.muted = muted;
+                // Muting flushes it
+                self.outputs.set_muted(self.midi, muted, &self.app_config.lock_or_recover());
+                self.stats.midi_errors = self.outputs.errors(self.midi);
+                if let Some(looper) = &self.looper {
+                    looper.control(|looper| looper.set_muted(muted));
+                }
+                if let Some(metronome) = &self.metronome {
+                    metronome.set_muted(muted);
+                }
+            }
+        }
+    }
+
+    /// Picks up the cycle count windows and the zones enabled or disabled
+    /// since the last sample, and releases the notes of the zones that were
+    /// just disabled, since their samples won't release them anymore.
+    fn update_zone_configs(&mut self) {
+        let Some(zone_configs) = &self.zone_configs else {
+            return;
+        };
+        let zone_configs = *zone_configs.lock_or_recover();
+        let windows = zone_configs.map(|config| (config.cycle_count_begin, config.cycle_count_end));
+        if windows.as_slice() != self.zones.cycle_windows() {
+            let config = self.app_config.lock_or_recover();
+            self.zones.set_cycle_windows(&windows, &config);
+        }
+        let disabled = zone_configs.map(|config| !config.enabled);
+        if disabled == self.disabled_zones {
+            return;
+        }
+        let config = self.app_config.lock_or_recover();
+        let was_disabled = self.disabled_zones;
+        for device_zone in (0..NUM_ZONES).filter(|&zone| disabled[zone] && !was_disabled[zone]) {
+            let zone = self.zones.processed_zone(device_zone, &config);
+            info!("Zone {} disabled on the device, ignoring its samples", zone);
+            // Notes are held per logical zone
+            let midi_zone = self.zones.midi_zone(zone, &config);
+            let logical = self.zones.logical_zone(midi_zone, &config.zone_groups);
+            self.outputs.release_zone(zone, logical, &config);
+        }
+        self.stats.midi_errors = self.outputs.errors(self.midi);
+        self.disabled_zones = disabled;
+    }
+
+    fn reset_baselines(&mut self) {
+        self.zones.reset();
+    }
+
+    /// Processes one sample. Returns false when the source should stop
+    /// because the sample limit was reached.
+    pub fn handle_sample(&mut self, sample: Sample) -> bool {
+        self.handle_sample_at(sample, SystemTime::now(), Instant::now())
+    }
+
+    /// `handle_sample` as if the sample was received at `received` and
+    /// handled from `started` on, which every timing (gestures, ramps, the
+    /// LFO) follows; runs fed a fixed timeline send the same MIDI every time.
+    pub fn handle_sample_at(&mut self, sample: Sample, received: SystemTime, started: Instant) -> bool {
+        let _side = enter_side(LockSide::Pipeline);
+        // The latency is real time whatever the timeline
+        let entered = Instant::now();
+        if let Some(health) = &self.health {
+            health.record(started);
+        }
+        while let Ok(command) = self.command_rx.try_recv() {
+            self.run_command(command, started);
+        }
+        let calibrating = match self.calibrating_until {
+            Some(until) if started >= until => {
+                info!("Calibration finished");
+                self.calibrating_until = None;
+                self.zones.set_drift_references();
+                false
+            }
+            Some(_) => true,
+            None => false,
+        };
+        self.update_zone_configs();
+        if self.disabled_zones[sample.zone] {
+            self.stats.disabled_zone_samples += 1;
+            return true;
+        }
+        let (processed_sample, midi_sample, midi_zone) = {
+            let config = self.app_config.lock_or_recover();
+            self.takes.push(unix_time(received), sample, &config.takes);
+            let alpha = if calibrating {
+                config.exponential_alpha.max(CALIBRATION_ALPHA)
+            } else {
+                config.exponential_alpha
+            };
+            let processed_sample = self.zones.ingest(sample, &config, alpha, started);
+            let zone = processed_sample.zone;
+            match self.zones.watch_drift(sample.zone, &config.drift, started) {
+                Some(DriftChange::Drifted(drift)) => warn!(
+                    "Zone {} baseline drifted {:+.1}% since calibration; recalibrate or adjust the device's comparator thresholds",
+                    zone,
+                    drift * 100.0
+                ),
+                Some(DriftChange::Recovered) => info!("Zone {} baseline is back near its calibrated value", zone),
+                None => {}
+            }
+            self.stats.baseline_drift[zone] = self.zones.drifted(sample.zone);
+            (
+                processed_sample,
+                self.zones.logical_sample(&processed_sample, &config),
+                self.zones.midi_zone(processed_sample.zone, &config),
+            )
+        };
+        let gestures;
+        let plot_memory;
+        let mut sink_sample;
+        {
+            let app_config = self.app_config.lock_or_recover();
+            plot_memory = (app_config.plot_memory_mb * 1024.0 * 1024.0) as usize;
+            gestures = self.gesture_detector.update(
+                processed_sample.zone,
+                processed_sample.value_normalized.abs(),
+                app_config.midi.note_config.threshold,
+                &app_config.gesture,
+                started,
+            );
+            if let Some(usage) = &self.usage {
+                usage.record(processed_sample.zone, gestures, started);
+            }
+            let gesture = patch::triggered(app_config.markers.binding, midi_zone, gestures)
+                .then(|| (midi_zone, app_config.markers.name.as_str()));
+            self.markers.stamp(unix_time(received), processed_sample.timestamp, gesture);
+            if self.takes.take_request() || patch::triggered(app_config.takes.save_binding, midi_zone, gestures) {
+                self.takes.save(
+                    unix_time(received),
+                    &app_config.takes,
+                    &self.markers,
+                    self.app_config.clone(),
+                    self.zone_configs.clone(),
+                );
+            }
+            sink_sample = SinkSample {
+                event: SampleEvent {
+                    sample,
+                    received,
+                    processed: processed_sample,
+                    midi_action: None,
+                    gestures,
+                },
+                midi_sample,
+                midi_zone,
+                now: started,
+            };
+            self.outputs.handle(&mut sink_sample, &app_config);
+            self.stats.midi_errors = self.outputs.errors(self.midi);
+        }
+
+        let event = sink_sample.event;
+        let midi_action = event.midi_action;
+        for tap in &self.taps {
+            if !tap.offer(event) {
+                self.stats.tap_drops[processed_sample.zone] += 1;
+            }
+        }
+        if let Some(plot) = &mut self.plot {
+            let point = PlotPoint {
+                time: processed_sample.timestamp as f64 / 1000.0,
+                raw: processed_sample.value_raw,
+                normalized: processed_sample.value_normalized,
+                derivative: processed_sample.derivative,
+                peak: processed_sample.peak,
+                saturated: processed_sample.saturated,
+            };
+            plot.push(processed_sample.zone, point, midi_action, MAX_PLOT_DURATION_SECS, plot_memory, started);
+        }
+        self.stats.processed += 1;
+        self.stats.per_zone[processed_sample.zone] += 1;
+        self.stats.latency.record(entered.elapsed());
+        if let Some((shared, published)) = &mut self.shared_stats {
+            if published.is_none_or(|published| started.duration_since(published) >= STATS_PUBLISH_INTERVAL) {
+                let mut snapshot = self.stats.snapshot();
+                snapshot.plot_memory = self.plot.as_ref().map(PlotHistory::memory_usage);
+                shared.publish(snapshot);
+                *published = Some(started);
+            }
+        }
+        if self.max_samples.is_some_and(|max| self.stats.processed >= max) {
+            info!("Reached {} samples, stopping", self.stats.processed);
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::overrides::ConfigOverride;
+    use crate::config::{AppConfig, MidiOutputMethod, ZoneMapScope};
+    use crate::diagnostics::StatsSnapshot;
+    use crate::midi::{CaptureMidiSink, MidiOutputState, PortTarget};
+    use crate::simulator::Simulator;
+    use crate::sync::tracked;
+    use std::sync::Mutex;
+    use std::time::UNIX_EPOCH;
+
+    /// Simulated samples fed to every run, five seconds at 1 kHz
+    const SAMPLES: u64 = 5000;
+
+    /// A pipeline sending its MIDI to the capture returned, and the config
+    /// it reads
+    fn pipeline(config: AppConfig) -> (Pipeline, Arc<TrackedMutex<ConfigStore>>, CaptureMidiSink) {
+        let app_config = Arc::new(tracked("app_config", ConfigStore::new(config)));
+        let capture = CaptureMidiSink::new();
+        let mut midi_output = MidiOutputState::new(PortTarget::Any);
+        midi_output.set_connected(capture.clone(), PortTarget::Any, "test capture".to_string());
+        let midi = MidiOutputSink::new(Arc::new(Mutex::new(midi_output)));
+        (Pipeline::new(app_config.clone(), midi, None), app_config, capture)
+    }
+
+    /// The MIDI messages a run over the simulator sends, with or without a
+    /// tap nobody reads, and the pipeline's stats
+    fn run(with_stuck_tap: bool) -> (Vec<Vec<u8>>, StatsSnapshot) {
+        let (mut pipeline, _, capture) = pipeline(AppConfig::default());
+        // Held to the end, so the tap's channel stays full instead of closed
+        let stuck = with_stuck_tap.then(|| {
+            let (tap, rx, dropped) = SampleTap::new(1);
+            pipeline.add_tap(tap);
+            (rx, dropped)
+        });
+
+        let mut simulator = Simulator::new();
+        let epoch = Instant::now();
+        for index in 0..SAMPLES {
+            let offset = Duration::from_millis(index);
+            pipeline.handle_sample_at(simulator.sample(offset.as_millis() as i32), UNIX_EPOCH + offset, epoch + offset);
+        }
+        pipeline.flush_outputs();
+        let stats = pipeline.stats().snapshot();
+        if let Some((_, dropped)) = &stuck {
+            assert_eq!(dropped.load(Ordering::Relaxed), SAMPLES - 1);
+        }
+        (capture.take(), stats)
+    }
+
+    #[test]
+    fn a_flooded_tap_does_not_change_the_midi_sent() {
+        let (expected, _) = run(false);
+        let (sent, stats) = run(true);
+        assert!(!expected.is_empty());
+        assert_eq!(sent, expected);
+        assert_eq!(stats.processed, SAMPLES);
+    }
+
+    #[test]
+    fn tap_drops_are_counted_per_zone() {
+        let (_, stats) = run(true);
+        // Only the first sample fit in the tap
+        assert_eq!(stats.tap_drops.iter().sum::<u64>(), SAMPLES - 1);
+        for zone in 0..NUM_ZONES {
+            let first = u64::from(zone == 0);
+            assert_eq!(stats.tap_drops[zone], stats.per_zone[zone] - first, "zone {}", zone);
+        }
+    }
+
+    /// The output zones the tap saw and the controllers MIDI sent on for
+    /// samples of device zone 0
+    fn zone_0_played_as(
+        pipeline: &mut Pipeline,
+        rx: &mut mpsc::Receiver<SampleEvent>,
+        capture: &CaptureMidiSink,
+    ) -> (Vec<usize>, Vec<u8>) {
+        for value in [5000, 5500, 6000, 6500] {
+            pipeline.handle_sample(Sample {
+                timestamp: value,
+                zone: 0,
+                value: Some(value),
+                flags: None,
+                sequence: None,
+            });
+        }
+        let mut zones = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            zones.push(event.processed.zone);
+        }
+        let controllers = capture.take().iter().map(|message| message[1]).collect();
+        (zones, controllers)
+    }
+
+    #[test]
+    fn set_and_gui_zone_map_edits_play_the_same_midi_in_both_scopes() {
+        // The output zone the tap sees for device zone 0 under the reversed map
+        for (scope, tap_zone) in [(ZoneMapScope::Everything, 7), (ZoneMapScope::MidiOnly, 0)] {
+            let mut config = AppConfig {
+                zone_map_scope: scope,
+                ..Default::default()
+            };
+            config.midi.method = MidiOutputMethod::ControlChange;
+            let base = config.midi.control_change_config.base_control_number;
+            config
+                .apply_overrides(vec![ConfigOverride::parse("zone_map=[7,6,5,4,3,2,1,0]").unwrap()])
+                .unwrap();
+            let (mut pipeline, app_config, capture) = pipeline(config);
+            let (tap, mut rx, _) = SampleTap::new(16);
+            pipeline.add_tap(tap);
+
+            let (zones, controllers) = zone_0_played_as(&mut pipeline, &mut rx, &capture);
+            assert_eq!(zones, [tap_zone; 4], "{:?}", scope);
+            assert!(!controllers.is_empty());
+            assert!(controllers.iter().all(|&control| control == base + 7), "{:?}: {:?}", scope, controllers);
+
+            // The GUI's editor changes the shared config in place
+            app_config.lock_or_recover().zone_map.swap(0, 7);
+            let (zones, controllers) = zone_0_played_as(&mut pipeline, &mut rx, &capture);
+            assert_eq!(zones, [0; 4], "{:?}", scope);
+            assert!(!controllers.is_empty());
+            assert!(controllers.iter().all(|&control| control == base), "{:?}: {:?}", scope, controllers);
+        }
+    }
+
+    /// The note ons and offs as (note, on) a pipeline grouping output zones 0
+    /// and 1 sends for `presses` of (zone, value), after a rest of 5000
+    fn grouped_notes(presses: &[(usize, i32)]) -> Vec<(u8, bool)> {
+        let mut config = AppConfig {
+            zone_groups: vec![vec![0, 1]],
+            // The first value stays the baseline
+            exponential_alpha: 0.0,
+            ..Default::default()
+        };
+        config.normalization.baseline_warmup_samples = 0;
+        config.midi.method = MidiOutputMethod::Notes;
+        let (mut pipeline, _, capture) = pipeline(config);
+        let rest = (0..NUM_ZONES).map(|zone| (zone, 5000));
+        for (timestamp, (zone, value)) in rest.chain(presses.iter().copied()).enumerate() {
+            pipeline.handle_sample(Sample {
+                timestamp: timestamp as i32,
+                zone,
+                value: Some(value),
+                flags: None,
+                sequence: None,
+            });
+        }
+        pipeline.flush_outputs();
+        capture
+            .take()
+            .iter()
+            .filter_map(|message| match message[0] & 0xF0 {
+                0x90 if message[2] > 0 => Some((message[1], true)),
+                0x80 | 0x90 => Some((message[1], false)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_group_holds_one_note_from_the_first_press_to_the_last_release() {
+        // Zone 0 pressed, then zone 1, then zone 0 let go before zone 1
+        let notes = grouped_notes(&[(0, 6000), (1, 6000), (0, 5000), (1, 6000), (1, 5000)]);
+        assert_eq!(notes, [(60, true), (60, false)]);
+    }
+
+    #[test]
+    fn zones_after_a_group_play_the_next_notes() {
+        // Output zone 2 is logical zone 1 with 0 and 1 grouped
+        let notes = grouped_notes(&[(2, 6000), (2, 5000), (1, 6000), (1, 5000)]);
+        assert_eq!(notes, [(61, true), (61, false), (60, true), (60, false)]);
+    }
+
+    #[test]
+    fn a_read_tap_drops_nothing() {
+        let (_, stats) = run(false);
+        assert_eq!(stats.tap_drops, [0; NUM_ZONES]);
+    }
+
+    #[test]
+    fn remapping_resting_coils_plays_no_notes() {
+        let mut config = AppConfig::default();
+        config.normalization.baseline_warmup_samples = 0;
+        config.midi.method = MidiOutputMethod::Notes;
+        let (mut pipeline, app_config, capture) = pipeline(config);
+        // Every coil at rest, each at its own value
+        let rest = |pipeline: &mut Pipeline, from: i32| {
+            for timestamp in from..from + 500 {
+                for zone in 0..NUM_ZONES {
+                    let value = Some(4000 + 500 * zone as i32);
+                    pipeline.handle_sample(Sample {
+                        timestamp,
+                        zone,
+                        value,
+                        flags: None,
+                        sequence: None,
+                    });
+                }
+            }
+        };
+        rest(&mut pipeline, 0);
+        for swap in [(0, 7), (1, 2), (0, 1)] {
+            app_config.lock_or_recover().zone_map.swap(swap.0, swap.1);
+            rest(&mut pipeline, 500);
+        }
+        app_config.lock_or_recover().zone_map_scope = ZoneMapScope::MidiOnly;
+        rest(&mut pipeline, 1000);
+        pipeline.flush_outputs();
+        let note_ons: Vec<Vec<u8>> =
+            capture.take().into_iter().filter(|message| message[0] & 0xF0 == 0x90 && message[2] > 0).collect();
+        assert_eq!(note_ons, Vec::<Vec<u8>>::new());
+    }
+}
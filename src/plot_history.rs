@@ -0,0 +1,597 @@
+use crate::config::{PlotSource, NUM_ZONES};
+use crate::device_clock::DeviceClock;
+use crate::midi::MidiAction;
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Points per sealed chunk, so publishing copies at most this many per zone
+const CHUNK_POINTS: usize = 256;
+/// Sealed chunks of one level merged into one chunk of the next level when
+/// the history is over its memory budget
+const COMPACT_CHUNKS: usize = 8;
+/// Points whose lowest and highest values make up two points of the merged
+/// chunk, so it holds `CHUNK_POINTS` again
+const COMPACT_BUCKET: usize = COMPACT_CHUNKS * 2;
+/// How often the pipeline publishes a new frame, about once per GUI frame
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(16);
+/// Device time between two Control Change markers of a zone; CC mode sends
+/// one per sample, far more than the plot can show
+const CC_MARKER_INTERVAL: f64 = 0.1;
+
+/// One processed sample as plotted
+#[derive(Debug, Clone, Copy)]
+pub struct PlotPoint {
+    /// Device time in seconds
+    pub time: f64,
+    pub raw: f64,
+    pub normalized: f64,
+    /// Rate of change of `normalized` per second
+    pub derivative: f64,
+    /// Held peak of the magnitude
+    pub peak: f64,
+    pub saturated: bool,
+}
+
+impl PlotPoint {
+    /// The value plotted for `source`
+    pub fn value(&self, source: PlotSource) -> f64 {
+        match source {
+            PlotSource::Raw => self.raw,
+            PlotSource::Normalized => self.normalized,
+            PlotSource::Derivative => self.derivative,
+            PlotSource::Peak => self.peak,
+        }
+    }
+}
+
+impl PlotPoint {
+    /// The lowest and the highest of every value of `points`, as points at
+    /// the time of the first and the last; saturated if any was
+    fn bounds(points: &[PlotPoint]) -> [PlotPoint; 2] {
+        let (mut low, mut high) = (points[0], points[points.len() - 1]);
+        for point in points {
+            low.raw = low.raw.min(point.raw);
+            low.normalized = low.normalized.min(point.normalized);
+            low.derivative = low.derivative.min(point.derivative);
+            low.peak = low.peak.min(point.peak);
+            high.raw = high.raw.max(point.raw);
+            high.normalized = high.normalized.max(point.normalized);
+            high.derivative = high.derivative.max(point.derivative);
+            high.peak = high.peak.max(point.peak);
+            high.saturated |= point.saturated;
+        }
+        low.saturated = high.saturated;
+        [low, high]
+    }
+}
+
+/// Memory held by the plot history
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct PlotMemory {
+    /// Bytes of points and markers held across all zones
+    pub bytes: usize,
+    /// `plot_memory_mb` in bytes
+    pub budget: usize,
+    /// Sealed chunks reduced to the lowest and highest values of their
+    /// points, across all zones
+    pub compacted_chunks: usize,
+    /// Chunks dropped before the plot duration because nothing was left to
+    /// compact, since the pipeline started
+    pub dropped_chunks: u64,
+}
+
+/// A MIDI message sent for a sample, marked on the plot at that sample
+#[derive(Debug, Clone, Copy)]
+pub struct MidiMarker {
+    pub point: PlotPoint,
+    pub action: MidiAction,
+}
+
+/// The recent history of every zone, frozen when it was published. Sealed
+/// chunks are shared between frames instead of copied.
+#[derive(Default)]
+pub struct PlotFrame {
+    zones: [Vec<Arc<[PlotPoint]>>; NUM_ZONES],
+    markers: [Arc<[MidiMarker]>; NUM_ZONES],
+    /// Points pushed to each zone so far; the points only differ between two
+    /// frames for zones where this differs
+    generations: [u64; NUM_ZONES],
+    /// Device time when the newest sample was processed, as estimated by the
+    /// pipeline's `DeviceClock`, and when that was
+    latest: Option<(f64, Instant)>,
+    /// Samples processed since the pipeline started
+    pub samples: u64,
+    pub memory: PlotMemory,
+}
+
+impl PlotFrame {
+    /// Points of `zone`, oldest first
+    pub fn points(&self, zone: usize) -> impl Iterator<Item = &PlotPoint> {
+        self.zones[zone].iter().flat_map(|chunk| chunk.iter())
+    }
+
+    /// The newest point of `zone`
+    pub fn last(&self, zone: usize) -> Option<&PlotPoint> {
+        self.zones[zone].iter().rev().find_map(|chunk| chunk.last())
+    }
+
+    /// MIDI markers of `zone` within the plot window, oldest first. Note Ons
+    /// and Offs are all kept, Control Changes at most every
+    /// `CC_MARKER_INTERVAL` and key pressure not at all.
+    pub fn markers(&self, zone: usize) -> &[MidiMarker] {
+        &self.markers[zone]
+    }
+
+    /// Changes whenever the points of `zone` change
+    pub fn generation(&self, zone: usize) -> u64 {
+        self.generations[zone]
+    }
+
+    /// Replaces `out` with the points of `zone` reduced to the lowest and
+    /// highest value of every `bucket_secs`, in time order, so peaks survive
+    /// at any sample rate. Buckets are aligned to the device clock, so the
+    /// result only changes with the points.
+    pub fn decimate_into(&self, zone: usize, bucket_secs: f64, source: PlotSource, out: &mut Vec<[f64; 2]>) {
+        out.clear();
+        // Bucket index with its lowest and highest point
+        let mut bucket: Option<(i64, [f64; 2], [f64; 2])> = None;
+        for point in self.points(zone) {
+            let point = [point.time, point.value(source)];
+            let index = (point[0] / bucket_secs).floor() as i64;
+            match &mut bucket {
+                Some((current, low, high)) if *current == index => {
+                    if point[1] < low[1] {
+                        *low = point;
+                    }
+                    if point[1] > high[1] {
+                        *high = point;
+                    }
+                }
+                _ => {
+                    if let Some((_, low, high)) = bucket {
+                        push_bucket(out, low, high);
+                    }
+                    bucket = Some((index, point, point));
+                }
+            }
+        }
+        if let Some((_, low, high)) = bucket {
+            push_bucket(out, low, high);
+        }
+    }
+
+    /// Replaces `out` with the first saturated point of every `bucket_secs`
+    /// of `zone`, to mark them on the decimated line.
+    pub fn saturated_into(&self, zone: usize, bucket_secs: f64, source: PlotSource, out: &mut Vec<[f64; 2]>) {
+        out.clear();
+        let mut last_bucket = None;
+        for point in self.points(zone).filter(|point| point.saturated) {
+            let index = (point.time / bucket_secs).floor() as i64;
+            if last_bucket != Some(index) {
+                out.push([point.time, point.value(source)]);
+                last_bucket = Some(index);
+            }
+        }
+    }
+
+    /// Points held across all zones
+    pub fn len(&self) -> usize {
+        self.zones.iter().flatten().map(|chunk| chunk.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The current device time, extrapolated from the newest sample's
+    /// estimate, for the right edge of the plot
+    pub fn now(&self) -> Option<f64> {
+        self.latest.map(|(time, at)| time + at.elapsed().as_secs_f64())
+    }
+
+    /// When the newest sample was processed
+    pub fn received_at(&self) -> Option<Instant> {
+        self.latest.map(|(_, at)| at)
+    }
+}
+
+fn push_bucket(out: &mut Vec<[f64; 2]>, low: [f64; 2], high: [f64; 2]) {
+    if low[0] == high[0] {
+        out.push(low);
+    } else if low[0] < high[0] {
+        out.extend([low, high]);
+    } else {
+        out.extend([high, low]);
+    }
+}
+
+/// Hands plot frames from the pipeline to the GUI or TUI. The pipeline swaps
+/// in each new frame and readers take the current one; neither side locks or
+/// waits for the other.
+#[derive(Clone, Default)]
+pub struct PlotFeed(Arc<ArcSwap<PlotFrame>>);
+
+impl PlotFeed {
+    /// The latest frame, cheap enough to take every GUI frame
+    pub fn load(&self) -> Arc<PlotFrame> {
+        self.0.load_full()
+    }
+
+    fn publish(&self, frame: PlotFrame) {
+        self.0.store(Arc::new(frame));
+    }
+}
+
+/// A full chunk of a zone's history. Chunks of level 0 hold the points as
+/// pushed; every level above is `COMPACT_CHUNKS` chunks of the level below
+/// reduced to the lowest and highest values of every `COMPACT_BUCKET` points.
+struct SealedChunk {
+    points: Arc<[PlotPoint]>,
+    level: u32,
+}
+
+impl SealedChunk {
+    fn last(&self) -> &PlotPoint {
+        &self.points[self.points.len() - 1]
+    }
+}
+
+#[derive(Default)]
+struct ZoneHistory {
+    /// Oldest first, so the levels never rise from front to back
+    sealed: VecDeque<SealedChunk>,
+    current: Vec<PlotPoint>,
+    /// Points pushed since the pipeline started, kept when the history is cleared
+    pushed: u64,
+    markers: VecDeque<MidiMarker>,
+    /// `markers` as last published, `None` once they changed since
+    published_markers: Option<Arc<[MidiMarker]>>,
+    /// Device time of the newest Control Change marker
+    last_cc_marker: Option<f64>,
+}
+
+impl ZoneHistory {
+    fn push_marker(&mut self, point: PlotPoint, action: MidiAction) {
+        match action {
+            MidiAction::ControlChange { .. } => {
+                if self.last_cc_marker.is_some_and(|last| point.time - last < CC_MARKER_INTERVAL) {
+                    return;
+                }
+                self.last_cc_marker = Some(point.time);
+            }
+            MidiAction::KeyPressure { .. } => return,
+            MidiAction::NoteOn { .. } | MidiAction::NoteOff { .. } => {}
+        }
+        self.markers.push_back(MidiMarker { point, action });
+        self.published_markers = None;
+    }
+
+    fn prune_markers(&mut self, oldest: f64) {
+        while self.markers.front().is_some_and(|marker| marker.point.time < oldest) {
+            self.markers.pop_front();
+            self.published_markers = None;
+        }
+    }
+
+    /// Bytes of points and markers held; the current chunk counts with its
+    /// capacity, allocated in full when it starts
+    fn memory(&self) -> usize {
+        (self.sealed.len() * CHUNK_POINTS + self.current.capacity()) * std::mem::size_of::<PlotPoint>()
+            + self.markers.len() * std::mem::size_of::<MidiMarker>()
+    }
+
+    /// Merges the oldest `COMPACT_CHUNKS` chunks of equal level that follow
+    /// each other into one chunk of the next level, so older history gets
+    /// coarser but keeps its peaks. `false` if no chunks are left to merge.
+    fn compact(&mut self) -> bool {
+        let mut start = 0;
+        for index in 1..=self.sealed.len() {
+            if index - start == COMPACT_CHUNKS {
+                let level = self.sealed[start].level + 1;
+                let mut points = Vec::with_capacity(COMPACT_CHUNKS * CHUNK_POINTS);
+                for chunk in self.sealed.drain(start..index) {
+                    points.extend_from_slice(&chunk.points);
+                }
+                let points = points.chunks(COMPACT_BUCKET).flat_map(PlotPoint::bounds).collect();
+                self.sealed.insert(start, SealedChunk { points, level });
+                return true;
+            }
+            if index < self.sealed.len() && self.sealed[index].level != self.sealed[start].level {
+                start = index;
+            }
+        }
+        false
+    }
+
+    /// Drops the oldest chunk and the markers before what is left.
+    fn drop_oldest(&mut self) {
+        self.sealed.pop_front();
+        let oldest = self.sealed.front().map_or(self.current.first(), |chunk| chunk.points.first());
+        if let Some(oldest) = oldest {
+            self.prune_markers(oldest.time);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.sealed.clear();
+        self.current.clear();
+        self.markers.clear();
+        self.published_markers = None;
+        self.last_cc_marker = None;
+    }
+}
+
+/// The plot history, owned by the pipeline and published to a `PlotFeed`
+pub struct PlotHistory {
+    feed: PlotFeed,
+    zones: [ZoneHistory; NUM_ZONES],
+    device_clock: DeviceClock,
+    latest: Option<(f64, Instant)>,
+    samples: u64,
+    budget: usize,
+    dropped_chunks: u64,
+    published_at: Instant,
+}
+
+impl PlotHistory {
+    pub fn new(feed: PlotFeed) -> Self {
+        Self {
+            feed,
+            zones: Default::default(),
+            device_clock: DeviceClock::new(),
+            latest: None,
+            samples: 0,
+            budget: usize::MAX,
+            dropped_chunks: 0,
+            published_at: Instant::now(),
+        }
+    }
+
+    /// Adds a point to `zone` with the MIDI message sent for it, if any,
+    /// dropping chunks and markers older than `window_secs`, compacting the
+    /// history to `budget` bytes, and publishes a frame if the last one is
+    /// due for replacement.
+    pub fn push(
+        &mut self,
+        zone: usize,
+        point: PlotPoint,
+        midi_action: Option<MidiAction>,
+        window_secs: f64,
+        budget: usize,
+        now: Instant,
+    ) {
+        let history = &mut self.zones[zone];
+        let previous = history.current.last().or_else(|| history.sealed.back().map(SealedChunk::last));
+        if previous.is_some_and(|previous| previous.time > point.time) {
+            // The device clock restarted, e.g. after reconnecting
+            history.clear();
+        }
+        history.current.push(point);
+        let mut grown = false;
+        if let Some(action) = midi_action {
+            let markers = history.markers.len();
+            history.push_marker(point, action);
+            grown = history.markers.len() > markers;
+        }
+        history.pushed += 1;
+        if history.current.len() == CHUNK_POINTS {
+            let full = std::mem::replace(&mut history.current, Vec::with_capacity(CHUNK_POINTS));
+            history.sealed.push_back(SealedChunk {
+                points: full.into(),
+                level: 0,
+            });
+            grown = true;
+        }
+        // Whole chunks only; the plot bounds hide the rest
+        while history
+            .sealed
+            .front()
+            .is_some_and(|chunk| chunk.last().time < point.time - window_secs)
+        {
+            history.sealed.pop_front();
+        }
+        history.prune_markers(point.time - window_secs);
+        let lowered = budget < self.budget;
+        self.budget = budget;
+        if grown || lowered {
+            self.enforce_budget();
+        }
+
+        self.device_clock.update(point.time, now);
+        self.latest = self.device_clock.device_time(now).map(|time| (time, now));
+        self.samples += 1;
+        if now.duration_since(self.published_at) >= PUBLISH_INTERVAL {
+            self.publish(now);
+        }
+    }
+
+    /// Compacts the zones with the most chunks first until the history fits
+    /// the budget, dropping their oldest chunks once there is nothing left
+    /// to compact. Only the current chunks are always kept.
+    fn enforce_budget(&mut self) {
+        while self.memory() > self.budget {
+            let Some(history) = self
+                .zones
+                .iter_mut()
+                .filter(|history| !history.sealed.is_empty())
+                .max_by_key(|history| history.sealed.len())
+            else {
+                break;
+            };
+            if !history.compact() {
+                history.drop_oldest();
+                self.dropped_chunks += 1;
+                if self.dropped_chunks == 1 {
+                    warn!("Plot history doesn't fit plot_memory_mb even compacted, dropping its oldest points");
+                }
+            }
+        }
+    }
+
+    /// Bytes held across all zones, see `ZoneHistory::memory`
+    fn memory(&self) -> usize {
+        self.zones.iter().map(ZoneHistory::memory).sum()
+    }
+
+    fn publish(&mut self, now: Instant) {
+        let markers = std::array::from_fn(|zone| {
+            let history = &mut self.zones[zone];
+            history
+                .published_markers
+                .get_or_insert_with(|| history.markers.iter().copied().collect())
+                .clone()
+        });
+        let zones = std::array::from_fn(|zone| {
+            let history = &self.zones[zone];
+            let mut chunks: Vec<Arc<[PlotPoint]>> = history.sealed.iter().map(|chunk| chunk.points.clone()).collect();
+            if !history.current.is_empty() {
+                chunks.push(history.current.as_slice().into());
+            }
+            chunks
+        });
+        self.feed.publish(PlotFrame {
+            zones,
+            markers,
+            generations: std::array::from_fn(|zone| self.zones[zone].pushed),
+            latest: self.latest,
+            samples: self.samples,
+            memory: self.memory_usage(),
+        });
+        self.published_at = now;
+    }
+
+    pub fn memory_usage(&self) -> PlotMemory {
+        PlotMemory {
+            bytes: self.memory(),
+            budget: self.budget,
+            compacted_chunks: self
+                .zones
+                .iter()
+                .map(|history| history.sealed.iter().filter(|chunk| chunk.level > 0).count())
+                .sum(),
+            dropped_chunks: self.dropped_chunks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: usize = 1024 * 1024;
+    /// An hour at 1 kHz, spread over the zones in turn
+    const HOUR_SAMPLES: u64 = 3_600_000;
+    const SPIKE_ZONE: usize = 3;
+    const SPIKE_RAW: f64 = 1_000_000.0;
+
+    fn point(time: f64, raw: f64) -> PlotPoint {
+        PlotPoint {
+            time,
+            raw,
+            normalized: raw / 10_000.0,
+            derivative: 0.0,
+            peak: 0.0,
+            saturated: false,
+        }
+    }
+
+    /// A history fed an hour of 1 kHz data within `budget`, with a spike on
+    /// `SPIKE_ZONE` every ten minutes and one saturated point, and the most
+    /// memory it held after any sample
+    fn an_hour_within(budget: usize) -> (PlotHistory, PlotFeed, usize) {
+        let feed = PlotFeed::default();
+        let mut history = PlotHistory::new(feed.clone());
+        let start = Instant::now();
+        let mut most = 0;
+        for index in 0..HOUR_SAMPLES {
+            let zone = index as usize % NUM_ZONES;
+            let time = index as f64 / 1000.0;
+            let mut point = point(time, 5000.0 + (index % 100) as f64);
+            if zone == SPIKE_ZONE && index % 600_000 == 300_003 {
+                point.raw = SPIKE_RAW;
+            }
+            point.saturated = index == 1_234_565;
+            // Only the last sample publishes, publishing every 16 ms makes it slow
+            let now = if index + 1 == HOUR_SAMPLES { start + PUBLISH_INTERVAL } else { start };
+            history.push(zone, point, None, 7200.0, budget, now);
+            most = most.max(history.memory());
+        }
+        (history, feed, most)
+    }
+
+    #[test]
+    fn an_hour_fits_the_budget_and_keeps_its_peaks() {
+        let budget = 4 * MB;
+        let (history, feed, most) = an_hour_within(budget);
+        assert!(most <= budget, "held {} bytes of {}", most, budget);
+        let memory = history.memory_usage();
+        assert!(memory.compacted_chunks > 0);
+        assert_eq!(memory.dropped_chunks, 0);
+
+        let frame = feed.load();
+        for zone in 0..NUM_ZONES {
+            let first = frame.points(zone).next().unwrap();
+            assert!(first.time < 0.01, "zone {} starts at {}", zone, first.time);
+        }
+        let spikes: Vec<f64> = frame.points(SPIKE_ZONE).filter(|point| point.raw == SPIKE_RAW).map(|point| point.time).collect();
+        assert_eq!(spikes.len(), 6, "{:?}", spikes);
+        for (spike, expected) in spikes.iter().zip((0..6).map(|n| 300.003 + 600.0 * n as f64)) {
+            // A compacted spike moves at most to the end of its bucket
+            assert!((spike - expected).abs() < 60.0, "spike at {} for {}", spike, expected);
+        }
+        let saturated = 1_234_565 % NUM_ZONES;
+        assert!(frame.points(saturated).any(|point| point.saturated));
+        assert!(frame.points(SPIKE_ZONE).all(|point| point.raw <= SPIKE_RAW && point.raw >= 5000.0));
+    }
+
+    #[test]
+    fn without_enough_budget_the_oldest_points_go() {
+        let feed = PlotFeed::default();
+        let mut history = PlotHistory::new(feed.clone());
+        let budget = 64 * 1024;
+        let start = Instant::now();
+        for index in 0..200_000 {
+            let time = index as f64 / 1000.0;
+            history.push(0, point(time, 5000.0), None, 7200.0, budget, start + Duration::from_millis(index));
+            assert!(history.memory_usage().bytes <= budget);
+        }
+        let memory = history.memory_usage();
+        assert!(memory.dropped_chunks > 0);
+        assert!(feed.load().points(0).next().unwrap().time > 0.0);
+        assert!(feed.load().last(0).unwrap().time > 199.9);
+    }
+
+    #[test]
+    fn lowering_the_budget_compacts_at_once() {
+        let feed = PlotFeed::default();
+        let mut history = PlotHistory::new(feed);
+        let start = Instant::now();
+        for index in 0..(CHUNK_POINTS * COMPACT_CHUNKS) as u64 {
+            let time = index as f64 / 1000.0;
+            history.push(0, point(time, 5000.0), None, 7200.0, 64 * MB, start + Duration::from_millis(index));
+        }
+        assert_eq!(history.memory_usage().compacted_chunks, 0);
+        let budget = history.memory_usage().bytes - 1;
+        history.push(0, point(10.0, 5000.0), None, 7200.0, budget, start);
+        let memory = history.memory_usage();
+        assert_eq!(memory.compacted_chunks, 1);
+        assert!(memory.bytes <= budget);
+    }
+
+    #[test]
+    fn compacted_bounds_keep_the_extremes() {
+        let mut points: Vec<PlotPoint> = (0..COMPACT_BUCKET).map(|index| point(index as f64, 5000.0)).collect();
+        points[3].raw = 9000.0;
+        points[7].normalized = -2.0;
+        points[9].saturated = true;
+        let [low, high] = PlotPoint::bounds(&points);
+        assert_eq!((low.time, high.time), (0.0, (COMPACT_BUCKET - 1) as f64));
+        assert_eq!((low.raw, high.raw), (5000.0, 9000.0));
+        assert_eq!((low.normalized, high.normalized), (-2.0, 0.5));
+        assert!(low.saturated && high.saturated);
+    }
+}
@@ -0,0 +1,278 @@
+use crate::gesture::Gestures;
+use crate::midi::MidiAction;
+use crate::pipeline::{SampleEvent, SampleTap};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+
+/// Samples buffered for stdout before new ones are dropped
+const PRINT_QUEUE_SIZE: usize = 1024;
+/// Most lines printed per lock of stdout, so other output gets its turn
+/// while samples keep coming
+const PRINT_BATCH_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum PrintFormat {
+    /// Aligned columns for reading in a terminal
+    Text,
+    Csv,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Serialize)]
+struct JsonLine {
+    timestamp: i32,
+    zone: usize,
+    raw: f64,
+    normalized: f64,
+    derivative: f64,
+    peak: f64,
+    midi: Option<MidiAction>,
+    #[serde(skip_serializing_if = "Gestures::is_empty")]
+    gestures: Gestures,
+}
+
+/// The thread behind `--print`. Call `finish()` once the pipeline has been
+/// dropped so no lines are lost at exit.
+pub struct Printer {
+    thread: JoinHandle<()>,
+}
+
+impl Printer {
+    /// Starts a thread printing one line per sample to stdout and returns the
+    /// tap feeding it. `zone` restricts output to one output zone.
+    pub fn start(format: PrintFormat, zone: Option<usize>) -> (Self, SampleTap) {
+        let (tap, rx, dropped) = SampleTap::new(PRINT_QUEUE_SIZE);
+        let thread = std::thread::spawn(move || print_events(rx, dropped, format, zone, || io::stdout().lock()));
+        (Self { thread }, tap)
+    }
+
+    /// Waits until every queued sample has been printed.
+    pub fn finish(self) {
+        if self.thread.join().is_err() {
+            error!("Printer thread panicked");
+        }
+    }
+}
+
+/// Prints every event of `rx` to the writer `lock` returns. It is locked per
+/// batch of the events queued at the time, at most `PRINT_BATCH_SIZE`, not
+/// for the life of the thread.
+fn print_events<W: Write>(
+    mut rx: mpsc::Receiver<SampleEvent>,
+    dropped: Arc<AtomicU64>,
+    format: PrintFormat,
+    zone: Option<usize>,
+    mut lock: impl FnMut() -> W,
+) {
+    if format == PrintFormat::Csv {
+        let _ = writeln!(lock(), "timestamp,zone,raw,normalized,midi,derivative,peak");
+    }
+    while let Some(first) = rx.blocking_recv() {
+        let total_dropped = dropped.swap(0, Ordering::Relaxed);
+        if total_dropped > 0 {
+            warn!("Printer fell behind, dropped {} samples", total_dropped);
+        }
+        let mut out = lock();
+        let batch = std::iter::once(first).chain(std::iter::from_fn(|| rx.try_recv().ok()));
+        for event in batch.take(PRINT_BATCH_SIZE) {
+            if zone.is_some_and(|zone| zone != event.processed.zone) {
+                continue;
+            }
+            if let Err(e) = print_event(&mut out, format, &event) {
+                // Usually a closed pipe, e.g. `dildonica --print | head`
+                error!("Failed to print sample: {}", e);
+                return;
+            }
+        }
+        if let Err(e) = out.flush() {
+            error!("Failed to print sample: {}", e);
+            return;
+        }
+    }
+}
+
+/// Cycle counts as whole numbers, raw values relative to their cycle count
+/// window with decimals
+fn format_raw(raw: f64) -> String {
+    if raw.fract() == 0.0 {
+        format!("{:.0}", raw)
+    } else {
+        format!("{:.5}", raw)
+    }
+}
+
+fn print_event(out: &mut impl Write, format: PrintFormat, event: &SampleEvent) -> io::Result<()> {
+    let sample = &event.processed;
+    let midi = event.midi_action.map(|action| action.to_string()).unwrap_or_default();
+    match format {
+        PrintFormat::Text => {
+            let gestures: Vec<String> = event.gestures.iter().map(|gesture| gesture.to_string()).collect();
+            let line = format!(
+                "{:>10}  zone {}  raw {:>10}  norm {:>9.5}  {:<12}  {}",
+                sample.timestamp,
+                sample.zone,
+                format_raw(sample.value_raw),
+                sample.value_normalized,
+                midi,
+                gestures.join(" ")
+            );
+            writeln!(out, "{}", line.trim_end())
+        }
+        PrintFormat::Csv => writeln!(
+            out,
+            "{},{},{},{},{},{},{}",
+            sample.timestamp,
+            sample.zone,
+            sample.value_raw,
+            sample.value_normalized,
+            midi,
+            sample.derivative,
+            sample.peak
+        ),
+        PrintFormat::Json => {
+            let line = JsonLine {
+                timestamp: sample.timestamp,
+                zone: sample.zone,
+                raw: sample.value_raw,
+                normalized: sample.value_normalized,
+                derivative: sample.derivative,
+                peak: sample.peak,
+                midi: event.midi_action,
+                gestures: event.gestures,
+            };
+            serde_json::to_writer(&mut *out, &line)?;
+            writeln!(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gesture::Gesture;
+    use crate::pipeline::ProcessedSample;
+    use crate::sample::Sample;
+    use std::sync::Mutex;
+    use std::time::SystemTime;
+
+    fn event(timestamp: i32, zone: usize, midi_action: Option<MidiAction>) -> SampleEvent {
+        SampleEvent {
+            sample: Sample {
+                timestamp,
+                zone,
+                value: Some(12000),
+                flags: None,
+                sequence: None,
+            },
+            received: SystemTime::UNIX_EPOCH,
+            processed: ProcessedSample {
+                timestamp,
+                zone,
+                value_raw: 12000.0,
+                value_normalized: 0.25,
+                derivative: -1.5,
+                peak: 0.5,
+                saturated: false,
+            },
+            midi_action,
+            gestures: Gestures::default(),
+        }
+    }
+
+    fn printed(format: PrintFormat, event: &SampleEvent) -> String {
+        let mut out = Vec::new();
+        print_event(&mut out, format, event).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    /// Output shared by every lock, counting the locks
+    #[derive(Default)]
+    struct Output {
+        bytes: Mutex<Vec<u8>>,
+        locks: Mutex<usize>,
+    }
+
+    struct Guard<'a>(&'a Output);
+
+    impl Write for Guard<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.bytes.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn print_all(events: Vec<SampleEvent>, format: PrintFormat, zone: Option<usize>) -> (String, usize) {
+        let (tx, rx) = mpsc::channel(PRINT_QUEUE_SIZE);
+        for event in events {
+            tx.try_send(event).unwrap();
+        }
+        drop(tx);
+        let output = Output::default();
+        print_events(rx, Arc::default(), format, zone, || {
+            *output.locks.lock().unwrap() += 1;
+            Guard(&output)
+        });
+        let bytes = output.bytes.into_inner().unwrap();
+        (String::from_utf8(bytes).unwrap(), output.locks.into_inner().unwrap())
+    }
+
+    #[test]
+    fn text_lines_are_aligned_and_trimmed() {
+        let note_on = Some(MidiAction::NoteOn { note: 60, velocity: 100 });
+        assert_eq!(
+            printed(PrintFormat::Text, &event(1500, 3, note_on)),
+            "      1500  zone 3  raw      12000  norm   0.25000  note_on 60 100\n"
+        );
+        let mut with_gesture = event(1500, 3, None);
+        with_gesture.gestures.insert(Gesture::Tap);
+        assert_eq!(
+            printed(PrintFormat::Text, &with_gesture),
+            "      1500  zone 3  raw      12000  norm   0.25000                tap\n"
+        );
+    }
+
+    #[test]
+    fn csv_and_json_lines() {
+        let cc = Some(MidiAction::ControlChange { control: 41, value: 64 });
+        assert_eq!(printed(PrintFormat::Csv, &event(20, 1, cc)), "20,1,12000,0.25,cc 41 64,-1.5,0.5\n");
+        assert_eq!(
+            printed(PrintFormat::Json, &event(20, 1, cc)),
+            "{\"timestamp\":20,\"zone\":1,\"raw\":12000.0,\"normalized\":0.25,\"derivative\":-1.5,\"peak\":0.5,\
+             \"midi\":{\"type\":\"control_change\",\"control\":41,\"value\":64}}\n"
+        );
+    }
+
+    #[test]
+    fn raw_values_keep_decimals_only_when_they_have_some() {
+        assert_eq!(format_raw(12000.0), "12000");
+        assert_eq!(format_raw(0.125), "0.12500");
+    }
+
+    #[test]
+    fn stdout_is_locked_per_batch() {
+        let events = (0..PRINT_BATCH_SIZE as i32 + 10).map(|t| event(t, 0, None)).collect();
+        let (output, locks) = print_all(events, PrintFormat::Csv, None);
+        assert_eq!(output.lines().count(), PRINT_BATCH_SIZE + 11);
+        assert!(output.starts_with("timestamp,zone,raw,normalized,midi,derivative,peak\n0,0,"));
+        // The header, then one full batch and the rest
+        assert_eq!(locks, 3);
+    }
+
+    #[test]
+    fn zone_filter_skips_other_zones() {
+        let events = vec![event(1, 0, None), event(2, 2, None), event(3, 0, None)];
+        let (output, _) = print_all(events, PrintFormat::Csv, Some(2));
+        assert_eq!(output, "timestamp,zone,raw,normalized,midi,derivative,peak\n2,2,12000,0.25,,-1.5,0.5\n");
+    }
+}
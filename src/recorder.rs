@@ -0,0 +1,326 @@
+use crate::bundle::{BundleHeader, BundleRecord, BundleSample};
+use crate::config::{ConfigStore, DildonicaZoneConfig, NUM_ZONES};
+use crate::markers::{SessionMarker, SessionMarkers, CSV_MARKERS_HEADER};
+use crate::pipeline::{SampleEvent, SampleTap};
+use crate::sync::{LockExt, TrackedMutex};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+/// Samples buffered for the writer thread before new ones are dropped
+const RECORD_QUEUE_SIZE: usize = 65536;
+/// How often buffered rows are flushed to disk
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Columns added later go last, so files appended to across versions still
+/// replay by their first header
+const CSV_HEADER: &str = "host_time,device_timestamp,device_zone,zone,raw,normalized,derivative,peak";
+
+/// File format of a `--record` file, chosen by its extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    /// Processed values, one row per sample
+    Csv,
+    /// A session bundle, see `bundle`
+    Bundle,
+}
+
+impl RecordFormat {
+    /// `.jsonl` files are session bundles, everything else is CSV.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("jsonl") => RecordFormat::Bundle,
+            _ => RecordFormat::Csv,
+        }
+    }
+}
+
+/// The settings a bundle header is taken from, read at the first sample so
+/// the device configs have been read by then
+struct BundleSettings {
+    app_config: Arc<TrackedMutex<ConfigStore>>,
+    zone_configs: Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>,
+}
+
+/// The writer thread of a `--record` file. Call `finish()` once the pipeline
+/// has been dropped to wait for the final flush.
+pub struct Recorder {
+    thread: JoinHandle<()>,
+}
+
+impl Recorder {
+    /// Opens `path` and starts the writer thread; the format follows
+    /// `RecordFormat::from_path`.
+    ///
+    /// CSV files are appended to. New ones get a CSV header, and every
+    /// session starts with a comment naming the app version and zone map,
+    /// so recordings can be replayed later. Session bundles hold a single
+    /// session and are never overwritten. Either ends with the `markers` of
+    /// the session, once it's over.
+    pub fn start(
+        path: &Path,
+        app_config: Arc<TrackedMutex<ConfigStore>>,
+        zone_configs: Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>,
+        markers: SessionMarkers,
+    ) -> io::Result<(Self, SampleTap)> {
+        let format = RecordFormat::from_path(path);
+        let mut bundle_settings = None;
+        let mut writer = match format {
+            RecordFormat::Csv => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                let is_new = file.metadata()?.len() == 0;
+                let mut writer = BufWriter::new(file);
+                let zone_map: Vec<String> =
+                    app_config.lock_or_recover().zone_map.iter().map(usize::to_string).collect();
+                writeln!(
+                    writer,
+                    "# dildonica {} recording started at {:.3}, zone_map {}",
+                    env!("CARGO_PKG_VERSION"),
+                    unix_time(SystemTime::now()),
+                    zone_map.join(" ")
+                )?;
+                if is_new {
+                    writeln!(writer, "{}", CSV_HEADER)?;
+                }
+                writer.flush()?;
+                writer
+            }
+            RecordFormat::Bundle => {
+                let file = OpenOptions::new().write(true).create_new(true).open(path)?;
+                bundle_settings = Some(BundleSettings {
+                    app_config,
+                    zone_configs,
+                });
+                BufWriter::new(file)
+            }
+        };
+        info!("Recording samples to {}", path.display());
+
+        let (tap, mut rx, dropped) = SampleTap::new(RECORD_QUEUE_SIZE);
+        let thread = std::thread::spawn(move || {
+            let mut last_flush = Instant::now();
+            let mut rows: u64 = 0;
+            while let Some(event) = rx.blocking_recv() {
+                let result = match format {
+                    RecordFormat::Csv => write_row(&mut writer, &event),
+                    RecordFormat::Bundle => write_bundle_record(&mut writer, &event, &mut bundle_settings),
+                };
+                if let Err(e) = result {
+                    error!("Failed to write recording: {}", e);
+                    return;
+                }
+                rows += 1;
+                if last_flush.elapsed() >= FLUSH_INTERVAL {
+                    if let Err(e) = writer.flush() {
+                        error!("Failed to write recording: {}", e);
+                        return;
+                    }
+                    last_flush = Instant::now();
+                }
+            }
+
+            // An empty session still gets its header, so the bundle can be read
+            if let Some(settings) = bundle_settings {
+                if let Err(e) = write_bundle_header(&mut writer, unix_time(SystemTime::now()), &settings) {
+                    error!("Failed to write recording: {}", e);
+                }
+            }
+            let markers = markers.all();
+            if let Err(e) = write_markers(&mut writer, format, &markers) {
+                error!("Failed to write the markers of the recording: {}", e);
+            }
+            if let Err(e) = writer.flush() {
+                error!("Failed to write recording: {}", e);
+            }
+            let dropped = dropped.load(Ordering::Relaxed);
+            if dropped > 0 {
+                warn!("Recording fell behind, dropped {} samples", dropped);
+            }
+            info!("Recorded {} samples and {} markers", rows, markers.len());
+        });
+        Ok((Self { thread }, tap))
+    }
+
+    /// Waits until every queued sample has been written and flushed.
+    pub fn finish(self) {
+        if self.thread.join().is_err() {
+            error!("Recording thread panicked");
+        }
+    }
+}
+
+fn write_row(writer: &mut BufWriter<File>, event: &SampleEvent) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{:.3},{},{},{},{},{},{},{}",
+        unix_time(event.received),
+        event.sample.timestamp,
+        event.sample.zone,
+        event.processed.zone,
+        event.processed.value_raw,
+        event.processed.value_normalized,
+        event.processed.derivative,
+        event.processed.peak
+    )
+}
+
+/// Writes the sample of `event`, preceded by the header if `settings` is
+/// still waiting to be written.
+fn write_bundle_record(
+    writer: &mut BufWriter<File>,
+    event: &SampleEvent,
+    settings: &mut Option<BundleSettings>,
+) -> io::Result<()> {
+    let host_time = unix_time(event.received);
+    if let Some(settings) = settings.take() {
+        write_bundle_header(writer, host_time, &settings)?;
+    }
+    let record = BundleRecord::Sample(BundleSample::new(host_time, &event.sample));
+    serde_json::to_writer(&mut *writer, &record)?;
+    writeln!(writer)
+}
+
+fn write_bundle_header(writer: &mut BufWriter<File>, started: f64, settings: &BundleSettings) -> io::Result<()> {
+    let zone_configs = *settings.zone_configs.lock_or_recover();
+    let header = BundleHeader::new(started, &settings.app_config.lock_or_recover(), &zone_configs);
+    serde_json::to_writer(&mut *writer, &BundleRecord::Header(header))?;
+    writeln!(writer)
+}
+
+/// Writes the markers section of a recording in `format`: comment lines
+/// after a CSV session's rows, nothing if there are none, or marker records
+/// after a bundle's samples
+pub fn write_markers(writer: &mut impl Write, format: RecordFormat, markers: &[SessionMarker]) -> io::Result<()> {
+    match format {
+        RecordFormat::Csv if markers.is_empty() => {}
+        RecordFormat::Csv => {
+            writeln!(writer, "{}", CSV_MARKERS_HEADER)?;
+            for marker in markers {
+                writeln!(writer, "{}", marker.csv_line())?;
+            }
+        }
+        RecordFormat::Bundle => {
+            for marker in markers {
+                serde_json::to_writer(&mut *writer, &BundleRecord::Marker(marker.clone()))?;
+                writeln!(writer)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Seconds since the Unix epoch
+pub fn unix_time(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).map_or(0.0, |d| d.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, MidiOutputMethod, HARDWARE_TEMPLATES};
+    use crate::midi::{CaptureMidiSink, MidiOutputState, PortTarget};
+    use crate::midi_output::MidiOutputSink;
+    use crate::pipeline::Pipeline;
+    use crate::replay;
+    use crate::sample::Sample;
+    use crate::simulator::Simulator;
+    use crate::sync::tracked;
+    use std::fs;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    /// A pipeline of `app_config` with `zone_configs`, sending to the
+    /// returned capture
+    fn pipeline(
+        app_config: Arc<TrackedMutex<ConfigStore>>,
+        zone_configs: Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>,
+    ) -> (Pipeline, CaptureMidiSink) {
+        let capture = CaptureMidiSink::new();
+        let mut midi_output = MidiOutputState::new(PortTarget::Any);
+        midi_output.set_connected(capture.clone(), PortTarget::Any, "test capture".to_string());
+        let mut pipeline = Pipeline::new(app_config, MidiOutputSink::new(Arc::new(Mutex::new(midi_output))), None);
+        pipeline.set_zone_configs(zone_configs);
+        (pipeline, capture)
+    }
+
+    /// The MIDI `pipeline` sends for `samples`, each handled at its device
+    /// time, as a replay at full speed would
+    fn play(
+        mut pipeline: Pipeline,
+        capture: &CaptureMidiSink,
+        samples: impl IntoIterator<Item = Sample>,
+    ) -> Vec<Vec<u8>> {
+        let epoch = Instant::now();
+        for sample in samples {
+            let offset = Duration::from_millis(sample.timestamp as u64);
+            pipeline.handle_sample_at(sample, UNIX_EPOCH + offset, epoch + offset);
+        }
+        pipeline.flush_outputs();
+        capture.take()
+    }
+
+    #[test]
+    fn a_recorded_bundle_replays_to_the_same_midi() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let mut recorded_config = AppConfig {
+            exponential_alpha: 0.01,
+            zone_map: (0..NUM_ZONES).rev().collect(),
+            ..Default::default()
+        };
+        recorded_config.midi.method = MidiOutputMethod::Notes;
+        let app_config = Arc::new(tracked("app_config", ConfigStore::new(recorded_config)));
+        let mut zones = HARDWARE_TEMPLATES[0].zones;
+        zones[2].enabled = false;
+        let zone_configs = Arc::new(tracked("zone_configs", zones));
+
+        let (recorder, tap) =
+            Recorder::start(&path, app_config.clone(), zone_configs.clone(), SessionMarkers::new()).unwrap();
+        let (mut recording, capture) = pipeline(app_config, zone_configs);
+        recording.add_tap(tap);
+        let mut simulator = Simulator::new();
+        let recorded = play(recording, &capture, (0..2400).map(|index| simulator.sample(index * 5 / 2)));
+        recorder.finish();
+        assert!(!recorded.is_empty());
+
+        let replayed = replay::read_recording(&path).unwrap();
+        let header = replayed.header.unwrap();
+        let mut local = AppConfig::default();
+        local.apply_overrides(header.config_overrides().unwrap()).unwrap();
+        let local = Arc::new(tracked("app_config", ConfigStore::new(local)));
+        let zone_configs = Arc::new(tracked("zone_configs", header.zone_configs().unwrap()));
+        let (replaying, capture) = pipeline(local, zone_configs);
+        assert_eq!(play(replaying, &capture, replayed.samples.iter().copied()), recorded);
+
+        // Without the recorded settings the same samples play differently
+        let default = Arc::new(tracked("app_config", ConfigStore::new(AppConfig::default())));
+        let zone_configs = Arc::new(tracked("zone_configs", HARDWARE_TEMPLATES[0].zones));
+        let (plain, capture) = pipeline(default, zone_configs);
+        assert_ne!(play(plain, &capture, replayed.samples), recorded);
+    }
+
+    #[test]
+    fn bundles_are_never_overwritten() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("session.jsonl");
+        fs::write(&path, "kept").unwrap();
+        let app_config = Arc::new(tracked("app_config", ConfigStore::new(AppConfig::default())));
+        let zone_configs = Arc::new(tracked("zone_configs", HARDWARE_TEMPLATES[0].zones));
+        let result = Recorder::start(&path, app_config, zone_configs, SessionMarkers::new());
+        assert_eq!(result.err().map(|e| e.kind()), Some(io::ErrorKind::AlreadyExists));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "kept");
+    }
+
+    #[test]
+    fn record_format_follows_the_extension() {
+        assert_eq!(RecordFormat::from_path(Path::new("take.jsonl")), RecordFormat::Bundle);
+        assert_eq!(RecordFormat::from_path(Path::new("take.JSONL")), RecordFormat::Bundle);
+        assert_eq!(RecordFormat::from_path(Path::new("take.csv")), RecordFormat::Csv);
+        assert_eq!(RecordFormat::from_path(Path::new("take")), RecordFormat::Csv);
+    }
+}
@@ -0,0 +1,357 @@
+//! Relay mode: the instance connected to the device serves the raw samples
+//! it receives over WebSocket (`--relay-port`), and other instances started
+//! with `--upstream` feed them into their own pipeline instead of BLE.
+//! Both sides share a token, sent as a bearer token in the handshake.
+
+use crate::bundle::BundleSample;
+use crate::config::NUM_ZONES;
+use crate::http::tokens_match;
+use crate::pipeline::{Pipeline, SampleEvent, SampleTap};
+use crate::recorder::unix_time;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::{header, HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::{self, Message, Utf8Bytes};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Samples buffered between the pipeline and the broadcaster
+const RELAY_QUEUE_SIZE: usize = 1024;
+/// Frames buffered per downstream instance; one that falls further behind
+/// loses the oldest ones
+const CLIENT_QUEUE_SIZE: usize = 1024;
+/// Wait before connecting to the upstream instance again
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Error, Debug)]
+pub enum RelayError {
+    #[error("Invalid upstream address `{0}`: {1}")]
+    InvalidAddress(String, tungstenite::Error),
+    #[error("The upstream instance refused the relay token")]
+    Rejected,
+    #[error("The relay token can't be sent in a header")]
+    InvalidToken,
+}
+
+/// Everything the relay sends, tagged with a `type` field like the frames of
+/// the WebSocket server
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RelayFrame {
+    /// A sample as received from the device, before the zone map
+    Sample(BundleSample),
+}
+
+/// Starts serving every raw sample to the downstream instances that connect
+/// on `bind`:`port` with `token`.
+pub async fn serve(bind: IpAddr, port: u16, token: String, shutdown: CancellationToken) -> io::Result<SampleTap> {
+    let listener = TcpListener::bind((bind, port)).await?;
+    info!("Relaying samples on ws://{}", listener.local_addr()?);
+    Ok(serve_on(listener, token, shutdown))
+}
+
+/// Serves every raw sample to the downstream instances that connect to
+/// `listener` with `token`.
+fn serve_on(listener: TcpListener, token: String, shutdown: CancellationToken) -> SampleTap {
+    let (tap, rx, dropped) = SampleTap::new(RELAY_QUEUE_SIZE);
+    let (frames, _) = broadcast::channel(CLIENT_QUEUE_SIZE);
+    tokio::spawn(broadcast_samples(rx, dropped, frames.clone()));
+    let token: Arc<str> = token.into();
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept relay connection: {}", e);
+                        continue;
+                    }
+                },
+                _ = shutdown.cancelled() => return,
+            };
+            tokio::spawn(serve_client(stream, peer, frames.subscribe(), token.clone(), shutdown.clone()));
+        }
+    });
+    tap
+}
+
+/// Serializes each sample once and hands it to every downstream queue.
+async fn broadcast_samples(
+    mut rx: mpsc::Receiver<SampleEvent>,
+    dropped: Arc<AtomicU64>,
+    frames: broadcast::Sender<Utf8Bytes>,
+) {
+    while let Some(event) = rx.recv().await {
+        if frames.receiver_count() == 0 {
+            continue;
+        }
+        let frame = RelayFrame::Sample(BundleSample::new(unix_time(event.received), &event.sample));
+        if let Ok(json) = serde_json::to_string(&frame) {
+            // Fails only when the last client just left
+            let _ = frames.send(json.into());
+        }
+    }
+
+    let dropped = dropped.load(Ordering::Relaxed);
+    if dropped > 0 {
+        warn!("Relay fell behind, dropped {} samples", dropped);
+    }
+}
+
+async fn serve_client(
+    stream: TcpStream,
+    peer: SocketAddr,
+    mut frames: broadcast::Receiver<Utf8Bytes>,
+    token: Arc<str>,
+    shutdown: CancellationToken,
+) {
+    // The handshake callback's signature is tungstenite's
+    #[allow(clippy::result_large_err)]
+    let authorize = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        let sent = request.headers().get(header::AUTHORIZATION).and_then(|value| value.to_str().ok());
+        match sent.and_then(|value| value.strip_prefix("Bearer ")) {
+            Some(sent) if tokens_match(sent, &token) => Ok(response),
+            _ => {
+                let mut refused = ErrorResponse::new(Some("Invalid relay token".to_string()));
+                *refused.status_mut() = StatusCode::UNAUTHORIZED;
+                Err(refused)
+            }
+        }
+    };
+    let mut ws = match tokio_tungstenite::accept_hdr_async(stream, authorize).await {
+        Ok(ws) => ws,
+        Err(tungstenite::Error::Http(_)) => {
+            warn!("Relay client {} sent a wrong token, refused", peer);
+            return;
+        }
+        Err(e) => {
+            debug!("Relay handshake with {} failed: {}", peer, e);
+            return;
+        }
+    };
+    info!("Relay client {} connected", peer);
+
+    let mut dropped = 0;
+    loop {
+        let result = tokio::select! {
+            frame = frames.recv() => match frame {
+                Ok(frame) => ws.send(Message::Text(frame)).await,
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    dropped += count;
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            message = ws.next() => match message {
+                Some(Ok(Message::Close(_))) | None => break,
+                // Nothing is taken from downstream; pings are answered by tungstenite
+                Some(Ok(_)) => Ok(()),
+                Some(Err(e)) => Err(e),
+            },
+            _ = shutdown.cancelled() => {
+                let _ = ws.close(None).await;
+                break;
+            }
+        };
+        if let Err(e) = result {
+            debug!("Relay client {} failed: {}", peer, e);
+            break;
+        }
+    }
+
+    if dropped > 0 {
+        warn!("Relay client {} disconnected, {} samples dropped while it was behind", peer, dropped);
+    } else {
+        info!("Relay client {} disconnected", peer);
+    }
+}
+
+/// Feeds the samples relayed by the instance at `upstream` (`host:port`)
+/// into `pipeline` until shutdown, connecting again whenever the connection
+/// is lost. Only a refused token ends it early.
+pub async fn consume(
+    upstream: &str,
+    token: &str,
+    pipeline: &mut Pipeline,
+    shutdown: &CancellationToken,
+) -> Result<(), RelayError> {
+    let mut request = format!("ws://{}", upstream)
+        .into_client_request()
+        .map_err(|e| RelayError::InvalidAddress(upstream.to_string(), e))?;
+    let authorization = HeaderValue::from_str(&format!("Bearer {}", token)).map_err(|_| RelayError::InvalidToken)?;
+    request.headers_mut().insert(header::AUTHORIZATION, authorization);
+
+    loop {
+        let connected = tokio::select! {
+            connected = tokio_tungstenite::connect_async(request.clone()) => connected,
+            _ = shutdown.cancelled() => break,
+        };
+        match connected {
+            Ok((ws, _)) => {
+                info!("Connected to the upstream instance at {}", upstream);
+                if !relay_samples(ws, pipeline, shutdown).await {
+                    break;
+                }
+                warn!("Lost the upstream instance at {}, connecting again", upstream);
+            }
+            Err(tungstenite::Error::Http(response)) if response.status() == StatusCode::UNAUTHORIZED => {
+                return Err(RelayError::Rejected);
+            }
+            Err(e) => warn!("Failed to connect to the upstream instance at {}: {}", upstream, e),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RECONNECT_DELAY) => {}
+            _ = shutdown.cancelled() => break,
+        }
+    }
+    pipeline.flush_outputs();
+    Ok(())
+}
+
+/// Hands the samples of one connection to `pipeline`. Returns false once
+/// the source should stop: at shutdown or the pipeline's sample limit.
+async fn relay_samples(
+    mut ws: tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<TcpStream>>,
+    pipeline: &mut Pipeline,
+    shutdown: &CancellationToken,
+) -> bool {
+    loop {
+        let message = tokio::select! {
+            message = ws.next() => message,
+            _ = shutdown.cancelled() => {
+                let _ = ws.close(None).await;
+                return false;
+            }
+        };
+        let text = match message {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | None => return true,
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => {
+                debug!("Upstream connection failed: {}", e);
+                return true;
+            }
+        };
+        match serde_json::from_str(&text) {
+            Ok(RelayFrame::Sample(sample)) if sample.zone < NUM_ZONES => {
+                if !pipeline.handle_sample(sample.to_sample()) {
+                    return false;
+                }
+            }
+            Ok(RelayFrame::Sample(sample)) => debug!("Ignoring a relayed sample of zone {}", sample.zone),
+            Err(e) => debug!("Ignoring an invalid relay frame: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, ConfigStore};
+    use crate::midi::{CaptureMidiSink, MidiOutputState, PortTarget};
+    use crate::midi_output::MidiOutputSink;
+    use crate::sample::Sample;
+    use crate::simulator::Simulator;
+    use crate::sync::tracked;
+    use std::net::Ipv4Addr;
+    use std::sync::Mutex;
+    use tokio::time::timeout;
+
+    const TOKEN: &str = "show night";
+    const TIMEOUT: Duration = Duration::from_secs(10);
+    /// Samples the downstream instance takes before it stops
+    const DOWNSTREAM_SAMPLES: u64 = 200;
+
+    /// A pipeline with the default config, sending MIDI to a capture
+    fn pipeline() -> (Pipeline, CaptureMidiSink) {
+        let app_config = Arc::new(tracked("app_config", ConfigStore::new(AppConfig::default())));
+        let capture = CaptureMidiSink::new();
+        let mut midi_output = MidiOutputState::new(PortTarget::Any);
+        midi_output.set_connected(capture.clone(), PortTarget::Any, "test capture".to_string());
+        let pipeline = Pipeline::new(app_config, MidiOutputSink::new(Arc::new(Mutex::new(midi_output))), None);
+        (pipeline, capture)
+    }
+
+    /// An upstream pipeline relaying on a free localhost port, and its
+    /// address
+    async fn upstream(shutdown: &CancellationToken) -> (Pipeline, String) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (mut pipeline, _) = pipeline();
+        pipeline.add_tap(serve_on(listener, TOKEN.to_string(), shutdown.clone()));
+        (pipeline, address)
+    }
+
+    #[tokio::test]
+    async fn a_downstream_pipeline_plays_the_relayed_samples() {
+        let shutdown = CancellationToken::new();
+        let (mut upstream, address) = upstream(&shutdown).await;
+        let (mut downstream, capture) = pipeline();
+        downstream.set_max_samples(DOWNSTREAM_SAMPLES);
+        let (tap, mut received, _) = SampleTap::new(DOWNSTREAM_SAMPLES as usize);
+        downstream.add_tap(tap);
+        let consumer_shutdown = shutdown.clone();
+        let mut consumer = tokio::spawn(async move {
+            consume(&address, TOKEN, &mut downstream, &consumer_shutdown).await
+        });
+
+        // Sent until the downstream instance has its samples, as it
+        // connects at some point meanwhile
+        let mut simulator = Simulator::new();
+        let mut sent = Vec::new();
+        let result = timeout(TIMEOUT, async {
+            loop {
+                tokio::select! {
+                    result = &mut consumer => return result.unwrap(),
+                    _ = tokio::time::sleep(Duration::from_millis(1)) => {}
+                }
+                let sample = simulator.sample(sent.len() as i32);
+                upstream.handle_sample(sample);
+                sent.push(sample);
+            }
+        })
+        .await
+        .unwrap();
+        assert!(result.is_ok());
+        shutdown.cancel();
+
+        let relayed: Vec<Sample> = std::iter::from_fn(|| received.try_recv().ok()).map(|event| event.sample).collect();
+        assert_eq!(relayed.len() as u64, DOWNSTREAM_SAMPLES);
+        // In order and unchanged, from wherever the connection started
+        let first = sent.iter().position(|sample| *sample == relayed[0]).unwrap();
+        assert_eq!(relayed, sent[first..first + relayed.len()]);
+        assert!((0..NUM_ZONES).all(|zone| relayed.iter().any(|sample| sample.zone == zone)));
+        // Played downstream with its own MIDI output
+        assert!(!capture.take().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_wrong_token_is_refused() {
+        let shutdown = CancellationToken::new();
+        let (_upstream, address) = upstream(&shutdown).await;
+        let (mut downstream, _) = pipeline();
+        let result = timeout(TIMEOUT, consume(&address, "guessed", &mut downstream, &shutdown)).await.unwrap();
+        assert!(matches!(result, Err(RelayError::Rejected)));
+        shutdown.cancel();
+    }
+
+    #[tokio::test]
+    async fn an_address_that_does_not_parse_is_an_error() {
+        let shutdown = CancellationToken::new();
+        let (mut downstream, _) = pipeline();
+        let result = consume("not an address", TOKEN, &mut downstream, &shutdown).await;
+        assert!(matches!(result, Err(RelayError::InvalidAddress(address, _)) if address == "not an address"));
+        let result = consume("127.0.0.1:1", "line\nbreak", &mut downstream, &shutdown).await;
+        assert!(matches!(result, Err(RelayError::InvalidToken)));
+    }
+}
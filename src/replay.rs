@@ -0,0 +1,250 @@
+use crate::bundle::{self, BundleError, BundleHeader};
+use crate::markers::SessionMarker;
+use crate::pipeline::Pipeline;
+use crate::sample::Sample;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Accepted header names for each column, most specific first
+const TIMESTAMP_COLUMNS: &[&str] = &["device_timestamp", "timestamp"];
+const ZONE_COLUMNS: &[&str] = &["device_zone", "zone"];
+const VALUE_COLUMNS: &[&str] = &["raw", "value"];
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("Failed to read {0}: {1}")]
+    Io(String, std::io::Error),
+    #[error("Recording has no header line")]
+    MissingHeader,
+    #[error("Recording has no `{0}` column")]
+    MissingColumn(&'static str),
+    #[error("Line {line}: {reason}")]
+    InvalidRow { line: usize, reason: String },
+    #[error(transparent)]
+    Bundle(#[from] BundleError),
+}
+
+/// The samples and markers of a recording, and for session bundles the
+/// settings they were recorded with
+pub struct Recording {
+    pub samples: Vec<Sample>,
+    pub markers: Vec<SessionMarker>,
+    pub header: Option<BundleHeader>,
+}
+
+struct Columns {
+    timestamp: usize,
+    zone: usize,
+    value: usize,
+}
+
+impl Columns {
+    fn from_header(header: &str) -> Result<Self, ReplayError> {
+        let names: Vec<&str> = header.split(',').map(str::trim).collect();
+        let find = |candidates: &[&'static str]| {
+            candidates
+                .iter()
+                .find_map(|candidate| names.iter().position(|name| name == candidate))
+                .ok_or(ReplayError::MissingColumn(candidates[0]))
+        };
+        Ok(Self {
+            timestamp: find(TIMESTAMP_COLUMNS)?,
+            zone: find(ZONE_COLUMNS)?,
+            value: find(VALUE_COLUMNS)?,
+        })
+    }
+}
+
+/// Reads a session bundle or a CSV recording, told apart by their first
+/// line. In CSV recordings, lines starting with `#` are comments, but for
+/// those of the markers sections; columns are matched by header name so
+/// recordings may carry extra columns.
+pub fn read_recording(path: &Path) -> Result<Recording, ReplayError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| ReplayError::Io(path.display().to_string(), e))?;
+    if bundle::is_bundle(&contents) {
+        let (header, samples, markers) = bundle::parse_bundle(&contents)?;
+        return Ok(Recording {
+            samples,
+            markers,
+            header: Some(header),
+        });
+    }
+    let (samples, markers) = read_csv(&contents)?;
+    Ok(Recording {
+        samples,
+        markers,
+        header: None,
+    })
+}
+
+/// The samples and markers of a CSV recording
+pub fn read_csv(contents: &str) -> Result<(Vec<Sample>, Vec<SessionMarker>), ReplayError> {
+    let markers = contents
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| {
+            let marker = SessionMarker::from_csv_line(line.trim())?;
+            Some(marker.map_err(|reason| ReplayError::InvalidRow { line: index + 1, reason }))
+        })
+        .collect::<Result<_, _>>()?;
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+    let (_, header) = lines.next().ok_or(ReplayError::MissingHeader)?;
+    let columns = Columns::from_header(header)?;
+
+    let samples = lines
+        .map(|(line, row)| {
+            parse_row(row, &columns).map_err(|reason| ReplayError::InvalidRow { line, reason })
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((samples, markers))
+}
+
+fn parse_row(row: &str, columns: &Columns) -> Result<Sample, String> {
+    let fields: Vec<&str> = row.split(',').map(str::trim).collect();
+    let field = |index: usize| {
+        fields
+            .get(index)
+            .copied()
+            .ok_or_else(|| format!("expected at least {} fields", index + 1))
+    };
+
+    let timestamp = field(columns.timestamp)?
+        .parse::<i32>()
+        .map_err(|e| format!("invalid timestamp: {}", e))?;
+    let zone = field(columns.zone)?
+        .parse::<usize>()
+        .map_err(|e| format!("invalid zone: {}", e))?;
+    if zone >= crate::config::NUM_ZONES {
+        return Err(format!("zone {} is out of range", zone));
+    }
+    // Raw values are floats in recordings, device samples are integers
+    let value = field(columns.value)?
+        .parse::<f64>()
+        .map_err(|e| format!("invalid value: {}", e))?
+        .round() as i32;
+
+    Ok(Sample {
+        timestamp,
+        zone,
+        value: if value == 0 { None } else { Some(value) },
+        flags: None,
+        sequence: None,
+    })
+}
+
+/// Feeds `samples` into `pipeline`, sleeping between samples so the replay
+/// follows the recorded device timestamps. `speed` scales playback rate.
+pub async fn replay(samples: Vec<Sample>, speed: f64, pipeline: &mut Pipeline, shutdown: CancellationToken) {
+    play(samples, speed, pipeline, &shutdown).await;
+    pipeline.flush_outputs();
+}
+
+async fn play(samples: Vec<Sample>, speed: f64, pipeline: &mut Pipeline, shutdown: &CancellationToken) {
+    let mut previous_timestamp: Option<i32> = None;
+    for sample in samples {
+        if let Some(previous) = previous_timestamp {
+            let delta_ms = (sample.timestamp - previous).max(0) as f64;
+            if delta_ms > 0.0 {
+                let delay = Duration::from_secs_f64(delta_ms / 1000.0 / speed);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.cancelled() => return,
+                }
+            }
+        }
+        previous_timestamp = Some(sample.timestamp);
+
+        if shutdown.is_cancelled() || !pipeline.handle_sample(sample) {
+            info!("Exiting");
+            return;
+        }
+    }
+    info!("Replay finished");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, ConfigStore, MidiOutputMethod};
+    use crate::midi::{CaptureMidiSink, MidiOutputState, PortTarget};
+    use crate::midi_output::MidiOutputSink;
+    use crate::simulator::Simulator;
+    use crate::sync::tracked;
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+
+    /// A pipeline sending notes to the returned capture
+    fn notes_pipeline() -> (Pipeline, CaptureMidiSink) {
+        let mut app_config = AppConfig::default();
+        app_config.midi.method = MidiOutputMethod::Notes;
+        let app_config = Arc::new(tracked("app_config", ConfigStore::new(app_config)));
+        let capture = CaptureMidiSink::new();
+        let mut midi_output = MidiOutputState::new(PortTarget::Any);
+        midi_output.set_connected(capture.clone(), PortTarget::Any, "test capture".to_string());
+        let pipeline = Pipeline::new(app_config, MidiOutputSink::new(Arc::new(Mutex::new(midi_output))), None);
+        (pipeline, capture)
+    }
+
+    /// Six seconds of the simulator, a sample every 4 ms
+    fn simulated() -> Vec<Sample> {
+        let mut simulator = Simulator::new();
+        (0..1500).map(|index| simulator.sample(index * 4)).collect()
+    }
+
+    /// Follows the notes `messages` turn on and off in `held`
+    fn track(held: &mut HashSet<(u8, u8)>, messages: &[Vec<u8>]) {
+        for message in messages {
+            match *message.as_slice() {
+                [status, note, velocity] if status & 0xf0 == 0x90 && velocity > 0 => {
+                    held.insert((status & 0x0f, note));
+                }
+                [status, note, _] if status & 0xf0 == 0x80 || status & 0xf0 == 0x90 => {
+                    held.remove(&(status & 0x0f, note));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelling_mid_stream_releases_the_held_notes() {
+        let (mut pipeline, capture) = notes_pipeline();
+        let shutdown = CancellationToken::new();
+        let mut held = HashSet::new();
+        let mut held_at_cancel = HashSet::new();
+        let watch = async {
+            while held_at_cancel.is_empty() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                track(&mut held, &capture.take());
+                held_at_cancel.clone_from(&held);
+            }
+            shutdown.cancel();
+        };
+        tokio::join!(replay(simulated(), 4.0, &mut pipeline, shutdown.clone()), watch);
+
+        assert!(!held_at_cancel.is_empty());
+        assert!(pipeline.stats().processed < 1500, "the replay ran to its end");
+        track(&mut held, &capture.take());
+        assert!(held.is_empty(), "notes left on: {:?}", held);
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_replay_handles_no_samples() {
+        let (mut pipeline, capture) = notes_pipeline();
+        let shutdown = CancellationToken::new();
+        shutdown.cancel();
+        replay(simulated(), 1.0, &mut pipeline, shutdown).await;
+        assert_eq!(pipeline.stats().processed, 0);
+        assert!(capture.take().is_empty());
+    }
+}
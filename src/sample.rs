@@ -0,0 +1,305 @@
+use crate::config::NUM_ZONES;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SampleError {
+    #[error("Data too short")]
+    DataTooShort,
+    #[error("Unexpected packet length {0}, expected a batch of {legacy} or {extended} byte packets", legacy = Sample::SIZE, extended = Sample::EXTENDED_SIZE)]
+    InvalidLength(usize),
+    #[error("Invalid zone")]
+    InvalidZone,
+}
+
+/// Status bits of an extended sample packet
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SampleFlags(pub u8);
+
+impl SampleFlags {
+    /// The oscillator count hit its limit, so the value is only a bound
+    pub const SATURATED: u8 = 0x01;
+    /// The firmware is running its self-test; the value isn't a reading
+    pub const SELF_TEST: u8 = 0x02;
+    /// The firmware's own touch detection fired for this zone
+    pub const TOUCH: u8 = 0x04;
+
+    pub fn saturated(self) -> bool {
+        self.0 & Self::SATURATED != 0
+    }
+
+    pub fn self_test(self) -> bool {
+        self.0 & Self::SELF_TEST != 0
+    }
+
+    pub fn touch(self) -> bool {
+        self.0 & Self::TOUCH != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub timestamp: i32,
+    pub zone: usize,
+    pub value: Option<i32>,
+    /// Only sent in extended packets
+    pub flags: Option<SampleFlags>,
+    /// Counts up by one per packet in extended packets, wrapping, so gaps show lost packets
+    pub sequence: Option<u16>,
+}
+
+impl Sample {
+    pub const SIZE: usize = 9; // 4 (timestamp) + 4 (value) + 1 (zone), unpadded
+    pub const EXTENDED_SIZE: usize = 16; // SIZE + 1 (flags) + 2 (sequence) + 4 (reserved)
+
+    /// Decodes one sample notification. Both formats start with a
+    /// little-endian `i32` timestamp in bytes 0..4, a little-endian `i32`
+    /// value in 4..8 (0 when the zone has no reading) and the zone in byte 8.
+    /// Extended packets add the flags in byte 9 and a little-endian `u16`
+    /// sequence number in 10..12; bytes 12..16 are reserved. Any other length
+    /// is rejected.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, SampleError> {
+        let extended = match data.len() {
+            Self::SIZE => false,
+            Self::EXTENDED_SIZE => true,
+            len if len < Self::SIZE => return Err(SampleError::DataTooShort),
+            len => return Err(SampleError::InvalidLength(len)),
+        };
+
+        let timestamp = i32::from_le_bytes(data[0..4].try_into().unwrap());
+        let value = i32::from_le_bytes(data[4..8].try_into().unwrap());
+        let zone = u8::from_le_bytes(data[8..9].try_into().unwrap());
+
+        if zone >= NUM_ZONES as u8 {
+            return Err(SampleError::InvalidZone);
+        }
+
+        let (flags, sequence) = if extended {
+            let sequence = u16::from_le_bytes(data[10..12].try_into().unwrap());
+            (Some(SampleFlags(data[9])), Some(sequence))
+        } else {
+            (None, None)
+        };
+
+        Ok(Sample {
+            timestamp,
+            value: if value == 0 { None } else { Some(value) },
+            zone: zone as usize,
+            flags,
+            sequence,
+        })
+    }
+
+    /// Decodes a notification of one sample or of a batch of them, packets
+    /// of one format back to back as the firmware sends them once asked to
+    /// batch. A length both formats fit, e.g. 144 bytes, is read in the
+    /// format `extended` says the device sent last, extended when unknown.
+    pub fn batch_from_bytes(data: &[u8], extended: Option<bool>) -> Result<Vec<Self>, SampleError> {
+        let size = match data.len() {
+            Self::SIZE | Self::EXTENDED_SIZE => return Self::from_bytes(data).map(|sample| vec![sample]),
+            len if len < Self::SIZE => return Err(SampleError::DataTooShort),
+            len => match (len % Self::SIZE == 0, len % Self::EXTENDED_SIZE == 0) {
+                (true, true) if extended == Some(false) => Self::SIZE,
+                (_, true) => Self::EXTENDED_SIZE,
+                (true, false) => Self::SIZE,
+                (false, false) => return Err(SampleError::InvalidLength(len)),
+            },
+        };
+        data.chunks(size).map(Self::from_bytes).collect()
+    }
+
+    pub fn saturated(&self) -> bool {
+        self.flags.is_some_and(SampleFlags::saturated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Timestamp 0x12345678, value 5000, zone 3, as the firmware sends it
+    const PACKET: [u8; Sample::SIZE] = [0x78, 0x56, 0x34, 0x12, 0x88, 0x13, 0x00, 0x00, 0x03];
+
+    #[test]
+    fn a_packet_decodes_field_by_field() {
+        let expected = Sample {
+            timestamp: 0x1234_5678,
+            zone: 3,
+            value: Some(5000),
+            flags: None,
+            sequence: None,
+        };
+        assert_eq!(Sample::from_bytes(&PACKET).unwrap(), expected);
+    }
+
+    #[test]
+    fn values_and_timestamps_are_signed_and_zero_is_no_reading() {
+        let packet = [0xFE, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x07];
+        let sample = Sample::from_bytes(&packet).unwrap();
+        assert_eq!((sample.timestamp, sample.zone, sample.value), (-2, 7, None));
+        let packet = [0x00, 0x00, 0x00, 0x80, 0xFF, 0xFF, 0xFF, 0x7F, 0x00];
+        let sample = Sample::from_bytes(&packet).unwrap();
+        assert_eq!((sample.timestamp, sample.value), (i32::MIN, Some(i32::MAX)));
+    }
+
+    #[test]
+    fn a_truncated_packet_is_too_short() {
+        for len in 0..Sample::SIZE {
+            assert!(matches!(Sample::from_bytes(&PACKET[..len]), Err(SampleError::DataTooShort)), "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn zones_past_the_last_are_rejected() {
+        for zone in [NUM_ZONES as u8, 9, 0x80, 0xFF] {
+            let mut packet = PACKET;
+            packet[8] = zone;
+            assert!(matches!(Sample::from_bytes(&packet), Err(SampleError::InvalidZone)), "zone {}", zone);
+        }
+    }
+
+    /// `PACKET` extended with `flags`, sequence `sequence` and the reserved
+    /// bytes set, which are ignored
+    fn extended(flags: u8, sequence: u16) -> Vec<u8> {
+        let [low, high] = sequence.to_le_bytes();
+        [&PACKET[..], &[flags, low, high, 0xDE, 0xAD, 0xBE, 0xEF]].concat()
+    }
+
+    fn decoded(flags: Option<u8>, sequence: Option<u16>) -> Result<Sample, SampleError> {
+        Ok(Sample {
+            timestamp: 0x1234_5678,
+            zone: 3,
+            value: Some(5000),
+            flags: flags.map(SampleFlags),
+            sequence,
+        })
+    }
+
+    #[test]
+    fn every_accepted_form_decodes() {
+        let cases = [
+            ("legacy", PACKET.to_vec(), decoded(None, None)),
+            ("extended", extended(0, 1), decoded(Some(0), Some(1))),
+            ("saturated", extended(SampleFlags::SATURATED, 2), decoded(Some(0x01), Some(2))),
+            ("self-test", extended(SampleFlags::SELF_TEST, 3), decoded(Some(0x02), Some(3))),
+            ("touch", extended(SampleFlags::TOUCH, 4), decoded(Some(0x04), Some(4))),
+            ("unknown flags kept", extended(0xF8, 5), decoded(Some(0xF8), Some(5))),
+            ("last sequence", extended(0, u16::MAX), decoded(Some(0), Some(u16::MAX))),
+            ("sequence byte order", extended(0, 0x0102), decoded(Some(0), Some(0x0102))),
+        ];
+        for (name, packet, expected) in cases {
+            assert_eq!(Sample::from_bytes(&packet), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn flags_read_their_own_bit() {
+        let cases = [
+            (0x00, [false, false, false]),
+            (0x01, [true, false, false]),
+            (0x02, [false, true, false]),
+            (0x04, [false, false, true]),
+            (0x07, [true, true, true]),
+            (0xF8, [false, false, false]),
+        ];
+        for (bits, expected) in cases {
+            let flags = SampleFlags(bits);
+            assert_eq!([flags.saturated(), flags.self_test(), flags.touch()], expected, "{:#04x}", bits);
+            let sample = Sample::from_bytes(&extended(bits, 0)).unwrap();
+            assert_eq!(sample.saturated(), expected[0], "{:#04x}", bits);
+        }
+        assert!(!Sample::from_bytes(&PACKET).unwrap().saturated(), "legacy packets are never saturated");
+    }
+
+    #[test]
+    fn only_the_two_packet_lengths_are_accepted() {
+        let bytes = extended(0, 0).repeat(3);
+        for len in 0..=bytes.len() {
+            let expected = match len {
+                Sample::SIZE | Sample::EXTENDED_SIZE => None,
+                len if len < Sample::SIZE => Some(SampleError::DataTooShort),
+                len => Some(SampleError::InvalidLength(len)),
+            };
+            assert_eq!(Sample::from_bytes(&bytes[..len]).err(), expected, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn every_rejection() {
+        let full = extended(0, 0);
+        let mut far_zone = full.clone();
+        far_zone[8] = NUM_ZONES as u8;
+        let cases = [
+            ("empty", Vec::new(), SampleError::DataTooShort),
+            ("one short of legacy", PACKET[..Sample::SIZE - 1].to_vec(), SampleError::DataTooShort),
+            ("one past legacy", full[..Sample::SIZE + 1].to_vec(), SampleError::InvalidLength(10)),
+            ("one short of extended", full[..Sample::EXTENDED_SIZE - 1].to_vec(), SampleError::InvalidLength(15)),
+            ("one past extended", [full.clone(), vec![0]].concat(), SampleError::InvalidLength(17)),
+            ("legacy zone past the last", [&PACKET[..8], &[0xFF]].concat(), SampleError::InvalidZone),
+            ("extended zone past the last", far_zone, SampleError::InvalidZone),
+        ];
+        for (name, packet, expected) in cases {
+            assert_eq!(Sample::from_bytes(&packet), Err(expected), "{}", name);
+        }
+    }
+
+    /// A packet of the format `extended` says, every field arbitrary but a
+    /// zone in range
+    fn any_packet(extended: bool) -> impl Strategy<Value = Vec<u8>> {
+        let size = if extended { Sample::EXTENDED_SIZE } else { Sample::SIZE };
+        (proptest::collection::vec(any::<u8>(), size), 0..NUM_ZONES as u8).prop_map(|(mut packet, zone)| {
+            packet[8] = zone;
+            packet
+        })
+    }
+
+    /// Packets of one format, and where the notifications carrying them are
+    /// cut apart
+    fn any_batch() -> impl Strategy<Value = (bool, Vec<Vec<u8>>, Vec<bool>)> {
+        any::<bool>().prop_flat_map(|extended| {
+            proptest::collection::vec(any_packet(extended), 1..20)
+                .prop_flat_map(move |packets| {
+                    let cuts = proptest::collection::vec(any::<bool>(), packets.len());
+                    (Just(extended), Just(packets), cuts)
+                })
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn arbitrary_bytes_are_decoded_or_rejected(
+            data in proptest::collection::vec(any::<u8>(), 0..4 * Sample::EXTENDED_SIZE),
+            extended in any::<Option<bool>>(),
+        ) {
+            match Sample::from_bytes(&data) {
+                Ok(sample) => prop_assert!(sample.zone < NUM_ZONES && data.len() >= Sample::SIZE),
+                Err(SampleError::DataTooShort) => prop_assert!(data.len() < Sample::SIZE),
+                Err(SampleError::InvalidLength(len)) => prop_assert_eq!(len, data.len()),
+                Err(SampleError::InvalidZone) => prop_assert!(usize::from(data[8]) >= NUM_ZONES),
+            }
+            if let Ok(samples) = Sample::batch_from_bytes(&data, extended) {
+                prop_assert!(!samples.is_empty());
+                prop_assert!(samples.iter().all(|sample| sample.zone < NUM_ZONES));
+            }
+        }
+
+        #[test]
+        fn a_batch_decodes_the_same_however_it_is_cut((extended, packets, cuts) in any_batch()) {
+            let expected: Vec<Sample> = packets.iter().map(|packet| Sample::from_bytes(packet).unwrap()).collect();
+            prop_assert_eq!(Sample::batch_from_bytes(&packets.concat(), Some(extended)).unwrap(), expected.clone());
+            // A notification ends after every packet cut after
+            let mut decoded = Vec::new();
+            let mut notification = Vec::new();
+            for (packet, cut) in packets.iter().zip(&cuts) {
+                notification.extend_from_slice(packet);
+                if *cut {
+                    decoded.extend(Sample::batch_from_bytes(&std::mem::take(&mut notification), Some(extended)).unwrap());
+                }
+            }
+            if !notification.is_empty() {
+                decoded.extend(Sample::batch_from_bytes(&notification, Some(extended)).unwrap());
+            }
+            prop_assert_eq!(decoded, expected);
+        }
+    }
+}
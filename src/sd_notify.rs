@@ -0,0 +1,59 @@
+//! The systemd notification protocol: datagrams of `KEY=value` lines sent
+//! to the socket in `$NOTIFY_SOCKET`.
+
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// A connection to the service manager, `None` from `connect` when not
+/// running under systemd
+pub struct Notifier {
+    socket: UnixDatagram,
+    /// From `$NOTIFY_SOCKET`, a path or `@` and an abstract socket name
+    address: String,
+}
+
+impl Notifier {
+    pub fn connect() -> io::Result<Option<Self>> {
+        let Some(address) = env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+        let address = address.to_string_lossy().into_owned();
+        Ok(Some(Self {
+            socket: UnixDatagram::unbound()?,
+            address,
+        }))
+    }
+
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        let sent = match self.address.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+                self.socket.send_to_addr(state.as_bytes(), &address)?
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => return Err(io::Error::new(io::ErrorKind::Unsupported, "abstract notify socket")),
+            None => self.socket.send_to(state.as_bytes(), &self.address)?,
+        };
+        if sent != state.len() {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "notification truncated"));
+        }
+        Ok(())
+    }
+}
+
+/// The watchdog timeout the service manager set for this process, if any;
+/// pings are due at half of it.
+pub fn watchdog_timeout() -> Option<Duration> {
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    // Set for another process, e.g. inherited from a parent
+    if let Some(pid) = env::var("WATCHDOG_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) {
+        if pid != std::process::id() {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
@@ -0,0 +1,486 @@
+use crate::config::{DildonicaZoneConfig, SelfTestConfig, NUM_ZONES};
+use crate::pipeline::SampleTap;
+use crate::sample::Sample;
+use crate::sync::LockExt;
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+
+/// Samples buffered for the self-test; it only counts them, so it rarely lags
+const SELFTEST_QUEUE_SIZE: usize = 1024;
+
+/// Result of a zone, ordered from best to worst so a zone gets the worst
+/// result of its checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SelfTestStatus {
+    /// Disabled on the device, so not tested
+    Disabled,
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for SelfTestStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SelfTestStatus::Disabled => write!(f, "OFF"),
+            SelfTestStatus::Pass => write!(f, "PASS"),
+            SelfTestStatus::Warn => write!(f, "WARN"),
+            SelfTestStatus::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+/// What one device zone sent during the test
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZoneSamples {
+    pub samples: u64,
+    /// Samples without a reading
+    pub missing: u64,
+    pub saturated: u64,
+    /// Samples flagged by the firmware's own self-test, which aren't readings
+    pub firmware_test: u64,
+    pub min: Option<i32>,
+    pub max: Option<i32>,
+    /// Running mean and sum of squared deviations of the readings (Welford)
+    mean: f64,
+    m2: f64,
+}
+
+impl ZoneSamples {
+    pub fn add(&mut self, sample: &Sample) {
+        self.samples += 1;
+        if sample.flags.is_some_and(|flags| flags.self_test()) {
+            self.firmware_test += 1;
+            return;
+        }
+        let Some(value) = sample.value else {
+            self.missing += 1;
+            return;
+        };
+        if sample.saturated() {
+            self.saturated += 1;
+        }
+        self.min = Some(self.min.map_or(value, |min| min.min(value)));
+        self.max = Some(self.max.map_or(value, |max| max.max(value)));
+        let readings = self.readings() as f64;
+        let delta = f64::from(value) - self.mean;
+        self.mean += delta / readings;
+        self.m2 += delta * (f64::from(value) - self.mean);
+    }
+
+    /// Samples with a reading
+    pub fn readings(&self) -> u64 {
+        self.samples - self.missing - self.firmware_test
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.readings() > 0).then_some(self.mean)
+    }
+
+    pub fn std_dev(&self) -> Option<f64> {
+        let readings = self.readings();
+        (readings > 1).then(|| (self.m2 / (readings - 1) as f64).sqrt())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZoneReport {
+    /// Device zone
+    pub zone: usize,
+    pub status: SelfTestStatus,
+    pub rate_hz: f64,
+    pub mean: Option<f64>,
+    /// Standard deviation relative to the mean
+    pub noise: Option<f64>,
+    /// Why the zone didn't pass
+    pub notes: Vec<String>,
+}
+
+impl ZoneReport {
+    fn check(&mut self, status: SelfTestStatus, note: String) {
+        self.status = self.status.max(status);
+        self.notes.push(note);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SelfTestReport {
+    pub duration: Duration,
+    pub zones: Vec<ZoneReport>,
+}
+
+impl SelfTestReport {
+    /// The worst result of any zone
+    pub fn status(&self) -> SelfTestStatus {
+        self.zones
+            .iter()
+            .map(|zone| zone.status)
+            .max()
+            .unwrap_or(SelfTestStatus::Disabled)
+    }
+
+    /// No zone failed; warnings still pass
+    pub fn passed(&self) -> bool {
+        self.status() < SelfTestStatus::Fail
+    }
+
+    /// Logs a line per zone at the level of its result.
+    pub fn log(&self) {
+        for zone in &self.zones {
+            let line = format!("Self-test zone {}: {} {}", zone.zone, zone.status, zone.notes.join("; "));
+            match zone.status {
+                SelfTestStatus::Fail => error!("{}", line.trim_end()),
+                SelfTestStatus::Warn => warn!("{}", line.trim_end()),
+                SelfTestStatus::Pass | SelfTestStatus::Disabled => info!("{}", line.trim_end()),
+            }
+        }
+        info!("Self-test over {:.1} s: {}", self.duration.as_secs_f64(), self.status());
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    /// One row per zone, as printed by the `selftest` command
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<5} {:<6} {:>9} {:>9} {:>7}  Notes", "Zone", "Result", "Rate (Hz)", "Mean", "Noise")?;
+        for zone in &self.zones {
+            let mean = zone.mean.map_or("-".to_string(), |mean| format!("{:.0}", mean));
+            let noise = zone.noise.map_or("-".to_string(), |noise| format!("{:.2}%", noise * 100.0));
+            writeln!(
+                f,
+                "{:<5} {:<6} {:>9.1} {:>9} {:>7}  {}",
+                zone.zone,
+                zone.status,
+                zone.rate_hz,
+                mean,
+                noise,
+                zone.notes.join("; ")
+            )?;
+        }
+        write!(f, "Overall: {}", self.status())
+    }
+}
+
+/// Judges what each zone sent over `duration`. An enabled zone fails when
+/// it sends too slowly or nothing but missing readings, when its mean lies
+/// outside the device's cycle count window or when it is far too noisy;
+/// it warns on missing, saturated or out-of-window readings and moderate
+/// noise.
+pub fn evaluate(
+    samples: &[ZoneSamples; NUM_ZONES],
+    duration: Duration,
+    zone_configs: &[DildonicaZoneConfig; NUM_ZONES],
+    config: &SelfTestConfig,
+) -> SelfTestReport {
+    let secs = duration.as_secs_f64();
+    let zones = (0..NUM_ZONES)
+        .map(|zone| evaluate_zone(zone, &samples[zone], secs, &zone_configs[zone], config))
+        .collect();
+    SelfTestReport { duration, zones }
+}
+
+fn evaluate_zone(
+    zone: usize,
+    samples: &ZoneSamples,
+    secs: f64,
+    zone_config: &DildonicaZoneConfig,
+    config: &SelfTestConfig,
+) -> ZoneReport {
+    let rate_hz = if secs > 0.0 { samples.samples as f64 / secs } else { 0.0 };
+    let mean = samples.mean();
+    let noise = mean.zip(samples.std_dev()).map(|(mean, std_dev)| std_dev / mean.abs().max(1.0));
+    let mut report = ZoneReport {
+        zone,
+        status: SelfTestStatus::Pass,
+        rate_hz,
+        mean,
+        noise,
+        notes: Vec::new(),
+    };
+    if !zone_config.enabled {
+        report.status = SelfTestStatus::Disabled;
+        return report;
+    }
+    if samples.samples == 0 {
+        report.check(SelfTestStatus::Fail, "no samples".to_string());
+        return report;
+    }
+    if rate_hz < config.min_rate_hz {
+        report.check(
+            SelfTestStatus::Fail,
+            format!("{:.1} samples/s, expected at least {:.1}", rate_hz, config.min_rate_hz),
+        );
+    }
+    if samples.firmware_test > 0 {
+        report.check(
+            SelfTestStatus::Warn,
+            format!("{} samples from the firmware self-test ignored", samples.firmware_test),
+        );
+    }
+    let (Some(mean), Some(min), Some(max)) = (mean, samples.min, samples.max) else {
+        report.check(SelfTestStatus::Fail, "no readings".to_string());
+        return report;
+    };
+    let missing = samples.missing as f64 / samples.samples as f64;
+    if missing > config.max_missing_fraction {
+        report.check(SelfTestStatus::Warn, format!("{:.0}% of samples missing", missing * 100.0));
+    }
+    if samples.saturated > 0 {
+        report.check(SelfTestStatus::Warn, format!("{} saturated", samples.saturated));
+    }
+    let window = f64::from(zone_config.cycle_count_begin)..=f64::from(zone_config.cycle_count_end);
+    if !window.contains(&mean) {
+        report.check(
+            SelfTestStatus::Fail,
+            format!(
+                "mean {:.0} outside cycle count window {}..{}",
+                mean, zone_config.cycle_count_begin, zone_config.cycle_count_end
+            ),
+        );
+    } else if !window.contains(&f64::from(min)) || !window.contains(&f64::from(max)) {
+        report.check(
+            SelfTestStatus::Warn,
+            format!("readings {}..{} leave the cycle count window", min, max),
+        );
+    }
+    if let Some(noise) = noise {
+        if noise > config.noise_fail {
+            report.check(SelfTestStatus::Fail, format!("noise {:.2}%, touched?", noise * 100.0));
+        } else if noise > config.noise_warn {
+            report.check(SelfTestStatus::Warn, format!("noise {:.2}%", noise * 100.0));
+        }
+    }
+    report
+}
+
+#[derive(Default)]
+struct Collection {
+    running: bool,
+    /// First sample of the running test, which starts its clock
+    started: Option<Instant>,
+    samples: [ZoneSamples; NUM_ZONES],
+}
+
+/// Collects the samples of a self-test from a pipeline tap. Shared by the
+/// GUI and the `selftest` command: `begin` starts a test, and once
+/// `elapsed` reaches the configured duration `finish` evaluates it.
+#[derive(Clone)]
+pub struct SelfTest {
+    collection: Arc<Mutex<Collection>>,
+}
+
+impl SelfTest {
+    /// Starts the collecting thread, which ends with the pipeline.
+    pub fn start() -> (Self, SampleTap) {
+        let (tap, mut rx, dropped) = SampleTap::new(SELFTEST_QUEUE_SIZE);
+        let collection = Arc::new(Mutex::new(Collection::default()));
+        let thread_collection = collection.clone();
+        std::thread::spawn(move || {
+            while let Some(event) = rx.blocking_recv() {
+                let mut collection = thread_collection.lock_or_recover();
+                if collection.running {
+                    collection.started.get_or_insert_with(Instant::now);
+                    collection.samples[event.sample.zone].add(&event.sample);
+                }
+            }
+            let dropped = dropped.load(Ordering::Relaxed);
+            if dropped > 0 {
+                warn!("Self-test fell behind, dropped {} samples", dropped);
+            }
+        });
+        (Self { collection }, tap)
+    }
+
+    /// Starts a new test, discarding a running one.
+    pub fn begin(&self) {
+        *self.collection.lock_or_recover() = Collection {
+            running: true,
+            ..Collection::default()
+        };
+        info!("Self-test started, don't touch the instrument");
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.collection.lock_or_recover().running
+    }
+
+    /// Time since the running test's first sample, zero until it arrives
+    pub fn elapsed(&self) -> Duration {
+        let collection = self.collection.lock_or_recover();
+        collection.started.map_or(Duration::ZERO, |started| started.elapsed())
+    }
+
+    /// Stops collecting and returns the running test's report, logged.
+    pub fn finish(&self, zone_configs: &[DildonicaZoneConfig; NUM_ZONES], config: &SelfTestConfig) -> SelfTestReport {
+        let (duration, samples) = {
+            let mut collection = self.collection.lock_or_recover();
+            collection.running = false;
+            let duration = collection.started.map_or(Duration::ZERO, |started| started.elapsed());
+            (duration, collection.samples)
+        };
+        let report = evaluate(&samples, duration, zone_configs, config);
+        report.log();
+        report
+    }
+
+    /// Stops collecting without a report.
+    pub fn cancel(&self) {
+        self.collection.lock_or_recover().running = false;
+        info!("Self-test cancelled");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HARDWARE_TEMPLATES;
+    use crate::sample::SampleFlags;
+
+    const SECS: Duration = Duration::from_secs(5);
+
+    fn zone_configs() -> [DildonicaZoneConfig; NUM_ZONES] {
+        HARDWARE_TEMPLATES[0].zones.map(|zone| DildonicaZoneConfig {
+            enabled: true,
+            cycle_count_begin: 4000,
+            cycle_count_end: 6000,
+            ..zone
+        })
+    }
+
+    fn sample(value: Option<i32>, flags: Option<u8>) -> Sample {
+        Sample {
+            timestamp: 0,
+            zone: 0,
+            value,
+            flags: flags.map(SampleFlags),
+            sequence: None,
+        }
+    }
+
+    /// `count` readings alternating `spread` above and below 5000
+    fn readings(count: u64, spread: i32) -> ZoneSamples {
+        let mut samples = ZoneSamples::default();
+        for index in 0..count {
+            let sign = if index % 2 == 0 { 1 } else { -1 };
+            samples.add(&sample(Some(5000 + sign * spread), None));
+        }
+        samples
+    }
+
+    /// The report of zone 0 with `zone_0`, every other zone healthy
+    fn zone_0(zone_0: ZoneSamples) -> ZoneReport {
+        let mut samples = [readings(500, 5); NUM_ZONES];
+        samples[0] = zone_0;
+        evaluate(&samples, SECS, &zone_configs(), &SelfTestConfig::default()).zones.remove(0)
+    }
+
+    #[test]
+    fn statistics_of_the_readings() {
+        let mut samples = readings(4, 10);
+        samples.add(&sample(None, None));
+        samples.add(&sample(Some(1), Some(SampleFlags::SELF_TEST)));
+        samples.add(&sample(Some(5000), Some(SampleFlags::SATURATED)));
+        assert_eq!((samples.samples, samples.readings()), (7, 5));
+        assert_eq!((samples.missing, samples.firmware_test, samples.saturated), (1, 1, 1));
+        assert_eq!((samples.min, samples.max), (Some(4990), Some(5010)));
+        assert_eq!(samples.mean(), Some(5000.0));
+        // Four readings 10 off and one on the mean, over 4 degrees of freedom
+        assert!((samples.std_dev().unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(ZoneSamples::default().mean(), None);
+        assert_eq!(readings(1, 0).std_dev(), None);
+    }
+
+    #[test]
+    fn a_quiet_zone_in_its_window_passes() {
+        let report = evaluate(&[readings(500, 5); NUM_ZONES], SECS, &zone_configs(), &SelfTestConfig::default());
+        assert!(report.zones.iter().all(|zone| zone.status == SelfTestStatus::Pass && zone.notes.is_empty()));
+        assert_eq!(report.zones[0].rate_hz, 100.0);
+        assert!(report.passed());
+    }
+
+    #[test]
+    fn a_disabled_zone_is_not_tested() {
+        let mut configs = zone_configs();
+        configs[0].enabled = false;
+        let report = evaluate(&[ZoneSamples::default(); NUM_ZONES], SECS, &configs, &SelfTestConfig::default());
+        assert_eq!(report.zones[0].status, SelfTestStatus::Disabled);
+        assert_eq!(report.zones[1].status, SelfTestStatus::Fail);
+        assert_eq!(report.status(), SelfTestStatus::Fail);
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn too_few_samples_fail() {
+        assert_eq!(zone_0(ZoneSamples::default()).notes, ["no samples"]);
+        let slow = zone_0(readings(50, 5));
+        assert_eq!(slow.status, SelfTestStatus::Fail);
+        assert_eq!(slow.notes, ["10.0 samples/s, expected at least 20.0"]);
+    }
+
+    #[test]
+    fn only_missing_readings_fail() {
+        let mut samples = ZoneSamples::default();
+        for _ in 0..500 {
+            samples.add(&sample(None, None));
+        }
+        let report = zone_0(samples);
+        assert_eq!(report.status, SelfTestStatus::Fail);
+        assert_eq!(report.notes, ["no readings"]);
+    }
+
+    #[test]
+    fn missing_saturated_and_firmware_test_samples_warn() {
+        let mut samples = readings(400, 5);
+        for _ in 0..100 {
+            samples.add(&sample(None, None));
+        }
+        samples.add(&sample(Some(5000), Some(SampleFlags::SATURATED)));
+        samples.add(&sample(Some(5000), Some(SampleFlags::SELF_TEST)));
+        let report = zone_0(samples);
+        assert_eq!(report.status, SelfTestStatus::Warn);
+        assert_eq!(
+            report.notes,
+            ["1 samples from the firmware self-test ignored", "20% of samples missing", "1 saturated"]
+        );
+    }
+
+    #[test]
+    fn the_cycle_count_window_bounds_the_readings() {
+        let outside = zone_0({
+            let mut samples = ZoneSamples::default();
+            (0..500).for_each(|_| samples.add(&sample(Some(7000), None)));
+            samples
+        });
+        assert_eq!(outside.status, SelfTestStatus::Fail);
+        assert_eq!(outside.notes, ["mean 7000 outside cycle count window 4000..6000"]);
+
+        let mut configs = zone_configs();
+        configs[0].cycle_count_end = 5003;
+        let samples = [readings(500, 5); NUM_ZONES];
+        let report = evaluate(&samples, SECS, &configs, &SelfTestConfig::default()).zones.remove(0);
+        assert_eq!(report.status, SelfTestStatus::Warn);
+        assert_eq!(report.notes, ["readings 4995..5005 leave the cycle count window"]);
+    }
+
+    #[test]
+    fn noise_warns_then_fails() {
+        // The default limits are 0.5% and 2% of the mean
+        let warn = zone_0(readings(500, 40));
+        assert_eq!(warn.status, SelfTestStatus::Warn);
+        assert_eq!(warn.notes, ["noise 0.80%"]);
+        let fail = zone_0(readings(500, 150));
+        assert_eq!(fail.status, SelfTestStatus::Fail);
+        assert_eq!(fail.notes, ["noise 3.00%, touched?"]);
+    }
+
+    #[test]
+    fn a_zone_gets_its_worst_result() {
+        let mut samples = readings(50, 150);
+        samples.add(&sample(Some(5000), Some(SampleFlags::SATURATED)));
+        let report = zone_0(samples);
+        assert_eq!(report.status, SelfTestStatus::Fail);
+        assert_eq!(report.notes.len(), 3);
+    }
+}
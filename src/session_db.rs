@@ -0,0 +1,339 @@
+use crate::config::{AppConfig, ConfigStore};
+use crate::midi::MidiAction;
+use crate::pipeline::{SampleEvent, SampleTap};
+use crate::recorder::unix_time;
+use crate::sync::{LockExt, TrackedMutex};
+use directories::ProjectDirs;
+use rusqlite::{params, Connection, OpenFlags};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::error::TryRecvError;
+use tracing::{error, info, warn};
+
+/// Samples buffered for the writer thread before new ones are dropped
+const DB_QUEUE_SIZE: usize = 65536;
+/// How often queued samples are written, each batch in one transaction
+const BATCH_INTERVAL: Duration = Duration::from_millis(100);
+const DB_FILE_NAME: &str = "sessions.db";
+
+/// Times are seconds since the Unix epoch. `midi_event.number` is the note or
+/// controller, `value` the velocity, pressure or controller value.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS session (
+    id INTEGER PRIMARY KEY,
+    started_at REAL NOT NULL,
+    ended_at REAL,
+    app_version TEXT NOT NULL,
+    zone_map TEXT NOT NULL,
+    config TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS sample (
+    session_id INTEGER NOT NULL REFERENCES session (id),
+    host_time REAL NOT NULL,
+    device_timestamp INTEGER NOT NULL,
+    device_zone INTEGER NOT NULL,
+    zone INTEGER NOT NULL,
+    raw REAL NOT NULL,
+    normalized REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS sample_session_zone ON sample (session_id, zone);
+CREATE TABLE IF NOT EXISTS midi_event (
+    session_id INTEGER NOT NULL REFERENCES session (id),
+    host_time REAL NOT NULL,
+    zone INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    number INTEGER NOT NULL,
+    value INTEGER
+);
+CREATE INDEX IF NOT EXISTS midi_event_session ON midi_event (session_id);
+";
+
+/// Switches database recording on and off while running. Shared between the
+/// writer thread, `--record-db` and the GUI's record button.
+#[derive(Clone)]
+pub struct DbRecording {
+    path: Arc<PathBuf>,
+    state: Arc<Mutex<RecordingState>>,
+}
+
+#[derive(Default)]
+struct RecordingState {
+    requested: bool,
+    /// Samples written to the open session, `None` while no session is open
+    samples: Option<u64>,
+    /// Opening or writing the database failed since recording was requested
+    failed: bool,
+}
+
+impl DbRecording {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path: Arc::new(path),
+            state: Arc::default(),
+        }
+    }
+
+    /// `sessions.db` in the per-user data directory
+    pub fn default_path() -> PathBuf {
+        match ProjectDirs::from("", "", "dildonica") {
+            Some(dirs) => dirs.data_dir().join(DB_FILE_NAME),
+            None => PathBuf::from(DB_FILE_NAME),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts a new session with the next batch of samples.
+    pub fn start(&self) {
+        self.state.lock_or_recover().requested = true;
+    }
+
+    pub fn stop(&self) {
+        self.state.lock_or_recover().requested = false;
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.state.lock_or_recover().requested
+    }
+
+    /// Samples recorded in the current session, if one is open
+    pub fn samples(&self) -> Option<u64> {
+        self.state.lock_or_recover().samples
+    }
+
+    pub fn has_failed(&self) -> bool {
+        self.state.lock_or_recover().failed
+    }
+
+    fn set_samples(&self, samples: Option<u64>) {
+        self.state.lock_or_recover().samples = samples;
+    }
+
+    fn set_failed(&self, failed: bool) {
+        let mut state = self.state.lock_or_recover();
+        state.failed = failed;
+        if failed {
+            state.samples = None;
+        }
+    }
+}
+
+/// The database writer thread. Call `finish()` once the pipeline has been
+/// dropped so the last batch is written and the session is closed.
+pub struct DbRecorder {
+    thread: JoinHandle<()>,
+}
+
+impl DbRecorder {
+    /// Starts the writer thread. If recording is already requested, as with
+    /// `--record-db`, the first session is opened here so a bad path fails
+    /// right away.
+    pub fn start(
+        recording: DbRecording,
+        app_config: Arc<TrackedMutex<ConfigStore>>,
+    ) -> rusqlite::Result<(Self, SampleTap)> {
+        let mut session = None;
+        if recording.is_requested() {
+            session = Some(Session::open(recording.path(), &app_config.lock_or_recover())?);
+            recording.set_samples(Some(0));
+        }
+
+        let (tap, mut rx, dropped) = SampleTap::new(DB_QUEUE_SIZE);
+        let thread = std::thread::spawn(move || {
+            let mut batch = Vec::new();
+            loop {
+                let closed = loop {
+                    match rx.try_recv() {
+                        Ok(event) => batch.push(event),
+                        Err(TryRecvError::Empty) => break false,
+                        Err(TryRecvError::Disconnected) => break true,
+                    }
+                };
+
+                if !recording.is_requested() {
+                    if let Some(session) = session.take() {
+                        session.close();
+                    }
+                    recording.set_samples(None);
+                    recording.set_failed(false);
+                } else if session.is_none() && !recording.has_failed() {
+                    // A file that failed is only retried after toggling
+                    match Session::open(recording.path(), &app_config.lock_or_recover()) {
+                        Ok(opened) => session = Some(opened),
+                        Err(e) => {
+                            error!("Failed to open {}: {}", recording.path().display(), e);
+                            recording.set_failed(true);
+                        }
+                    }
+                }
+                if let Some(open) = &mut session {
+                    match open.write(&batch) {
+                        Ok(()) => recording.set_samples(Some(open.samples)),
+                        Err(e) => {
+                            error!("Failed to write to {}: {}", recording.path().display(), e);
+                            session = None;
+                            recording.set_failed(true);
+                        }
+                    }
+                }
+                batch.clear();
+
+                if closed {
+                    break;
+                }
+                std::thread::sleep(BATCH_INTERVAL);
+            }
+
+            if let Some(session) = session {
+                session.close();
+            }
+            let dropped = dropped.load(Ordering::Relaxed);
+            if dropped > 0 {
+                warn!("Database recording fell behind, dropped {} samples", dropped);
+            }
+        });
+        Ok((Self { thread }, tap))
+    }
+
+    pub fn finish(self) {
+        if self.thread.join().is_err() {
+            error!("Database recording thread panicked");
+        }
+    }
+}
+
+/// One `session` row and the connection its samples are written through
+struct Session {
+    connection: Connection,
+    id: i64,
+    samples: u64,
+}
+
+impl Session {
+    fn open(path: &Path, app_config: &AppConfig) -> rusqlite::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            // Connection::open reports a missing directory only as "unable to open"
+            if let Err(e) = std::fs::create_dir_all(dir) {
+                warn!("Failed to create {}: {}", dir.display(), e);
+            }
+        }
+        let connection = Connection::open(path)?;
+        // Readers such as `sessions list` don't block the writer, and commits
+        // don't wait for a full sync
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+        connection.execute_batch(SCHEMA)?;
+
+        let zone_map: Vec<String> = app_config.zone_map.iter().map(usize::to_string).collect();
+        connection.execute(
+            "INSERT INTO session (started_at, app_version, zone_map, config) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                unix_time(SystemTime::now()),
+                env!("CARGO_PKG_VERSION"),
+                zone_map.join(" "),
+                serde_json::to_string(app_config).unwrap_or_default(),
+            ],
+        )?;
+        let id = connection.last_insert_rowid();
+        info!("Recording session {} to {}", id, path.display());
+        Ok(Self {
+            connection,
+            id,
+            samples: 0,
+        })
+    }
+
+    fn write(&mut self, events: &[SampleEvent]) -> rusqlite::Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let transaction = self.connection.transaction()?;
+        {
+            let mut insert_sample = transaction.prepare_cached(
+                "INSERT INTO sample (session_id, host_time, device_timestamp, device_zone, zone, raw, normalized) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            let mut insert_midi = transaction.prepare_cached(
+                "INSERT INTO midi_event (session_id, host_time, zone, kind, number, value) \
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for event in events {
+                let host_time = unix_time(event.received);
+                insert_sample.execute(params![
+                    self.id,
+                    host_time,
+                    event.sample.timestamp,
+                    event.sample.zone,
+                    event.processed.zone,
+                    event.processed.value_raw,
+                    event.processed.value_normalized,
+                ])?;
+                if let Some(action) = event.midi_action {
+                    let (kind, number, value) = midi_columns(action);
+                    insert_midi.execute(params![self.id, host_time, event.processed.zone, kind, number, value])?;
+                }
+            }
+        }
+        transaction.commit()?;
+        self.samples += events.len() as u64;
+        Ok(())
+    }
+
+    fn close(self) {
+        let result = self.connection.execute(
+            "UPDATE session SET ended_at = ?1 WHERE id = ?2",
+            params![unix_time(SystemTime::now()), self.id],
+        );
+        match result {
+            Ok(_) => info!("Recorded {} samples in session {}", self.samples, self.id),
+            Err(e) => error!("Failed to close session {}: {}", self.id, e),
+        }
+    }
+}
+
+/// `kind`, `number` and `value` of a `midi_event` row
+fn midi_columns(action: MidiAction) -> (&'static str, u8, Option<u8>) {
+    match action {
+        MidiAction::ControlChange { control, value } => ("control_change", control, Some(value)),
+        MidiAction::NoteOn { note, velocity } => ("note_on", note, Some(velocity)),
+        MidiAction::KeyPressure { note, pressure } => ("key_pressure", note, Some(pressure)),
+        MidiAction::NoteOff { note } => ("note_off", note, None),
+    }
+}
+
+/// Prints one line per session recorded in `path`.
+pub fn list_sessions(path: &Path) -> rusqlite::Result<()> {
+    let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut statement = connection.prepare(
+        "SELECT id, datetime(started_at, 'unixepoch', 'localtime'), ended_at - started_at,
+                (SELECT count(*) FROM sample WHERE session_id = session.id),
+                (SELECT count(*) FROM midi_event WHERE session_id = session.id)
+         FROM session ORDER BY id",
+    )?;
+    let mut rows = statement.query([])?;
+    let mut found = false;
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let started: String = row.get(1)?;
+        // A session without an end was cut off by a crash
+        let duration = row
+            .get::<_, Option<f64>>(2)?
+            .map_or("unfinished".to_string(), |secs| format!("{:.1} s", secs));
+        let samples: i64 = row.get(3)?;
+        let midi_events: i64 = row.get(4)?;
+        println!(
+            "{:>4}  {}  {:>10}  {:>9} samples  {:>7} MIDI events",
+            id, started, duration, samples, midi_events
+        );
+        found = true;
+    }
+    if !found {
+        println!("No sessions recorded in {}", path.display());
+    }
+    Ok(())
+}
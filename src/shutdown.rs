@@ -0,0 +1,41 @@
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Exit code used when a second signal cuts the shutdown short (128 + SIGINT)
+const FORCED_EXIT_CODE: i32 = 130;
+
+/// Cancels `shutdown` on the first Ctrl+C (or SIGTERM on unix) and exits the
+/// process immediately on the second one.
+pub fn spawn_signal_handler(shutdown: CancellationToken) {
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        info!("Shutting down, press Ctrl+C again to force exit");
+        shutdown.cancel();
+
+        wait_for_signal().await;
+        warn!("Forced exit");
+        std::process::exit(FORCED_EXIT_CODE);
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        Err(e) => {
+            warn!("Failed to listen for SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,83 @@
+use crate::config::NUM_ZONES;
+use crate::sample::Sample;
+use std::f64::consts::TAU;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio::time::MissedTickBehavior;
+
+/// Resting oscillator period of a simulated zone
+const BASE_VALUE: f64 = 5000.0;
+/// How often a batch of samples is generated
+const TICK: Duration = Duration::from_millis(1);
+
+/// Generates plausible sensor data without a device: every zone rests at a
+/// baseline and is periodically "squeezed", each at its own rate.
+pub struct Simulator {
+    next_zone: usize,
+}
+
+impl Default for Simulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self { next_zone: 0 }
+    }
+
+    /// The next sample, zones taking turns.
+    pub fn sample(&mut self, timestamp_ms: i32) -> Sample {
+        let zone = self.next_zone;
+        self.next_zone = (zone + 1) % NUM_ZONES;
+
+        let t = timestamp_ms as f64 / 1000.0;
+        let frequency = 0.2 + 0.1 * zone as f64;
+        // Only the positive half of the wave deforms the coil, the rest is rest
+        let squeeze = (TAU * frequency * t).sin().max(0.0);
+        let value = BASE_VALUE * (1.0 + 0.2 * squeeze);
+
+        Sample {
+            timestamp: timestamp_ms,
+            zone,
+            value: Some(value.round() as i32),
+            flags: None,
+            sequence: None,
+        }
+    }
+}
+
+/// Totals of a `run`
+pub struct SimulatorReport {
+    pub generated: u64,
+    /// Samples that didn't fit into the channel because the consumer fell behind
+    pub dropped: u64,
+}
+
+/// Sends `rate` samples per second into `tx` for `duration`. Samples are never
+/// waited for: when the channel is full they are dropped and counted.
+pub async fn run(rate: f64, duration: Duration, tx: mpsc::Sender<Sample>) -> SimulatorReport {
+    let mut simulator = Simulator::new();
+    let mut report = SimulatorReport {
+        generated: 0,
+        dropped: 0,
+    };
+    let start = Instant::now();
+    let mut interval = tokio::time::interval(TICK);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    while start.elapsed() < duration {
+        interval.tick().await;
+        let elapsed = start.elapsed().min(duration);
+        let due = (elapsed.as_secs_f64() * rate) as u64;
+        while report.generated < due {
+            let sample = simulator.sample(elapsed.as_millis() as i32);
+            if tx.try_send(sample).is_err() {
+                report.dropped += 1;
+            }
+            report.generated += 1;
+        }
+    }
+    report
+}
@@ -0,0 +1,379 @@
+//! The startup sequence: the `startup` steps run one after another once the
+//! app is up, each through the channel the GUI or the HTTP API would use for
+//! the same thing.
+
+use crate::ble::ConfigActivity;
+use crate::config::{ConfigStore, DildonicaZoneConfig, StartupAction, NUM_ZONES};
+use crate::config_history::{ConfigHistory, ConfigHistoryError};
+use crate::pipeline::PipelineCommand;
+use crate::sync::{LockExt, TrackedMutex};
+use crate::watchdog::LinkHealth;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+/// How often a step checks for samples or a finished write
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Error, Debug)]
+pub enum StartupError {
+    #[error("Aborted")]
+    Aborted,
+    #[error("The device connection ended")]
+    SessionEnded,
+    #[error("No zone map preset `{0}`")]
+    UnknownPreset(String),
+    #[error("The config history has no verified write to repeat")]
+    NothingWritten,
+    #[error("Failed to read the config history: {0}")]
+    History(#[from] ConfigHistoryError),
+    #[error("Failed to write the device config: {0}")]
+    WriteFailed(String),
+}
+
+/// Where the sequence is, by step index
+#[derive(Debug, Clone, PartialEq)]
+pub enum StartupStatus {
+    Running(usize),
+    Finished,
+    /// Stopped at this step, with why
+    Failed(usize, String),
+    Aborted(usize),
+}
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    actions: Vec<StartupAction>,
+    /// `None` before the sequence starts and once dismissed
+    status: Option<StartupStatus>,
+}
+
+/// The startup sequence as the GUI follows it, and its abort button
+#[derive(Debug, Clone, Default)]
+pub struct StartupProgress {
+    state: Arc<Mutex<ProgressState>>,
+    abort: CancellationToken,
+}
+
+impl StartupProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The steps and where the sequence is, `None` while there is nothing
+    /// to show
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn status(&self) -> Option<(Vec<StartupAction>, StartupStatus)> {
+        let state = self.state.lock_or_recover();
+        state.status.clone().map(|status| (state.actions.clone(), status))
+    }
+
+    /// Stops the sequence at the step it is on; the steps after it don't run.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn abort(&self) {
+        self.abort.cancel();
+    }
+
+    /// Hides an ended sequence.
+    #[cfg_attr(not(feature = "gui"), allow(dead_code))]
+    pub fn dismiss(&self) {
+        let mut state = self.state.lock_or_recover();
+        if !matches!(state.status, Some(StartupStatus::Running(_))) {
+            state.status = None;
+        }
+    }
+
+    fn set(&self, status: StartupStatus) {
+        self.state.lock_or_recover().status = Some(status);
+    }
+}
+
+/// What the steps go through
+pub struct StartupHandles {
+    pub app_config: Arc<TrackedMutex<ConfigStore>>,
+    /// Of the pipeline; closed once the device source ended for good
+    pub commands: mpsc::Sender<PipelineCommand>,
+    /// Zone configs for the device session to write
+    pub configs: mpsc::Sender<[DildonicaZoneConfig; NUM_ZONES]>,
+    pub activity: ConfigActivity,
+    pub history: ConfigHistory,
+    pub link: LinkHealth,
+    /// Lets the device source connect, at the `ConnectTo` step; dropped,
+    /// it connects as well, so ending the sequence early still connects
+    pub connect: Option<oneshot::Sender<()>>,
+}
+
+/// Runs `actions` in order until one fails or `progress` is aborted.
+pub async fn run(
+    actions: Vec<StartupAction>,
+    mut handles: StartupHandles,
+    progress: StartupProgress,
+) -> Result<(), StartupError> {
+    progress.state.lock_or_recover().actions = actions.clone();
+    info!("Running the startup sequence, {} steps", actions.len());
+    for (index, action) in actions.iter().enumerate() {
+        progress.set(StartupStatus::Running(index));
+        info!("Startup step {}/{}: {}", index + 1, actions.len(), action);
+        let result = tokio::select! {
+            result = run_action(action, &mut handles) => result,
+            _ = progress.abort.cancelled() => Err(StartupError::Aborted),
+        };
+        match result {
+            Ok(()) => {}
+            Err(StartupError::Aborted) => {
+                warn!("Startup sequence aborted at step {} ({})", index + 1, action);
+                progress.set(StartupStatus::Aborted(index));
+                return Err(StartupError::Aborted);
+            }
+            Err(e) => {
+                error!("Startup step {} ({}) failed: {}", index + 1, action, e);
+                progress.set(StartupStatus::Failed(index, e.to_string()));
+                return Err(e);
+            }
+        }
+    }
+    info!("Startup sequence finished");
+    progress.set(StartupStatus::Finished);
+    Ok(())
+}
+
+async fn run_action(action: &StartupAction, handles: &mut StartupHandles) -> Result<(), StartupError> {
+    match action {
+        StartupAction::ConnectTo(_) => {
+            let received = handles.link.samples();
+            if let Some(connect) = handles.connect.take() {
+                // The source is gone if it doesn't listen, which the wait notices
+                let _ = connect.send(());
+            }
+            sample_after(handles, received).await
+        }
+        StartupAction::WaitForSamples => sample_after(handles, handles.link.samples()).await,
+        StartupAction::Calibrate(secs) => {
+            let duration = Duration::from_secs_f64(*secs);
+            let received = handles.link.samples();
+            send_command(handles, PipelineCommand::Calibrate(duration)).await?;
+            // The pipeline takes commands with the next sample
+            sample_after(handles, received).await?;
+            tokio::time::sleep(duration).await;
+            Ok(())
+        }
+        StartupAction::LoadPreset(name) => {
+            let mut app_config = handles.app_config.lock_or_recover();
+            let zone_map = app_config
+                .zone_map_preset(name)
+                .ok_or_else(|| StartupError::UnknownPreset(name.clone()))?
+                .zone_map
+                .clone();
+            app_config.zone_map = zone_map;
+            app_config.mark_dirty();
+            Ok(())
+        }
+        StartupAction::WriteDeviceConfig => {
+            let entry = handles.history.last_verified()?.ok_or(StartupError::NothingWritten)?;
+            let (writes, _) = handles.activity.writes();
+            handles
+                .configs
+                .send(entry.configs)
+                .await
+                .map_err(|_| StartupError::SessionEnded)?;
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                interval.tick().await;
+                match handles.activity.writes() {
+                    (finished, Some(result)) if finished > writes => return result.map_err(StartupError::WriteFailed),
+                    _ if handles.configs.is_closed() => return Err(StartupError::SessionEnded),
+                    _ => {}
+                }
+            }
+        }
+        StartupAction::SetMidi(enabled) => send_command(handles, PipelineCommand::SetMuted(!enabled)).await,
+    }
+}
+
+async fn send_command(handles: &StartupHandles, command: PipelineCommand) -> Result<(), StartupError> {
+    handles
+        .commands
+        .send(command)
+        .await
+        .map_err(|_| StartupError::SessionEnded)
+}
+
+/// Waits for more than `received` samples in total.
+async fn sample_after(handles: &StartupHandles, received: u64) -> Result<(), StartupError> {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if handles.link.samples() > received {
+            return Ok(());
+        }
+        if handles.commands.is_closed() {
+            return Err(StartupError::SessionEnded);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AppConfig, ZoneMapPreset};
+    use crate::config_history::HistoryEntry;
+    use crate::sync::tracked;
+    use tokio::task::JoinHandle;
+
+    /// The handles of a sequence, the channels the pipeline and the device
+    /// session would take from, and the history in `directory`
+    struct Fixture {
+        handles: StartupHandles,
+        commands: mpsc::Receiver<PipelineCommand>,
+        configs: mpsc::Receiver<[DildonicaZoneConfig; NUM_ZONES]>,
+        connect: oneshot::Receiver<()>,
+    }
+
+    fn fixture(directory: &std::path::Path) -> Fixture {
+        let config = AppConfig {
+            zone_map_presets: vec![ZoneMapPreset {
+                name: "show".to_string(),
+                zone_map: (0..NUM_ZONES).rev().collect(),
+            }],
+            ..AppConfig::default()
+        };
+        let (commands_sender, commands) = mpsc::channel(16);
+        let (configs_sender, configs) = mpsc::channel(1);
+        let (connect_sender, connect) = oneshot::channel();
+        Fixture {
+            handles: StartupHandles {
+                app_config: Arc::new(tracked("app_config", ConfigStore::new(config))),
+                commands: commands_sender,
+                configs: configs_sender,
+                activity: ConfigActivity::new(),
+                history: ConfigHistory::new(directory.join("history.jsonl")),
+                link: LinkHealth::new(),
+                connect: Some(connect_sender),
+            },
+            commands,
+            configs,
+            connect,
+        }
+    }
+
+    /// A device sending a sample every 10 ms
+    fn samples(link: &LinkHealth) -> JoinHandle<()> {
+        let link = link.clone();
+        tokio::spawn(async move {
+            loop {
+                link.record_sample();
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+    }
+
+    fn received(commands: &mut mpsc::Receiver<PipelineCommand>) -> Vec<String> {
+        std::iter::from_fn(|| commands.try_recv().ok()).map(|command| format!("{:?}", command)).collect()
+    }
+
+    #[tokio::test]
+    async fn the_steps_run_in_order() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut fixture = fixture(directory.path());
+        let app_config = fixture.handles.app_config.clone();
+        let device = samples(&fixture.handles.link);
+        let progress = StartupProgress::new();
+        let actions = vec![
+            StartupAction::ConnectTo("AA:BB".to_string()),
+            StartupAction::SetMidi(false),
+            StartupAction::LoadPreset("show".to_string()),
+            StartupAction::Calibrate(0.05),
+            StartupAction::WaitForSamples,
+            StartupAction::SetMidi(true),
+        ];
+        run(actions.clone(), fixture.handles, progress.clone()).await.unwrap();
+        device.abort();
+
+        assert!(fixture.connect.try_recv().is_ok());
+        assert_eq!(
+            received(&mut fixture.commands),
+            vec!["SetMuted(true)", "Calibrate(50ms)", "SetMuted(false)"]
+        );
+        assert_eq!(app_config.lock_or_recover().zone_map, (0..NUM_ZONES).rev().collect::<Vec<_>>());
+        assert_eq!(progress.status(), Some((actions, StartupStatus::Finished)));
+        progress.dismiss();
+        assert_eq!(progress.status(), None);
+    }
+
+    #[tokio::test]
+    async fn an_abort_stops_at_the_current_step() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut fixture = fixture(directory.path());
+        let progress = StartupProgress::new();
+        let actions = vec![
+            StartupAction::SetMidi(false),
+            StartupAction::WaitForSamples,
+            StartupAction::SetMidi(true),
+        ];
+        let sequence = tokio::spawn(run(actions, fixture.handles, progress.clone()));
+        // No samples arrive, so it waits at the second step
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(progress.status().unwrap().1, StartupStatus::Running(1));
+        // Running, it stays shown
+        progress.dismiss();
+        assert!(progress.status().is_some());
+        progress.abort();
+
+        assert!(matches!(sequence.await.unwrap(), Err(StartupError::Aborted)));
+        assert_eq!(progress.status().unwrap().1, StartupStatus::Aborted(1));
+        assert_eq!(received(&mut fixture.commands), vec!["SetMuted(true)"]);
+    }
+
+    #[tokio::test]
+    async fn a_failed_step_stops_the_sequence() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut fixture = fixture(directory.path());
+        let progress = StartupProgress::new();
+        let actions = vec![StartupAction::LoadPreset("missing".to_string()), StartupAction::SetMidi(true)];
+        let result = run(actions, fixture.handles, progress.clone()).await;
+
+        assert!(matches!(result, Err(StartupError::UnknownPreset(name)) if name == "missing"));
+        assert_eq!(
+            progress.status().unwrap().1,
+            StartupStatus::Failed(0, "No zone map preset `missing`".to_string())
+        );
+        assert!(received(&mut fixture.commands).is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_write_needs_a_verified_one_to_repeat() {
+        let directory = tempfile::tempdir().unwrap();
+        let fixture = fixture(directory.path());
+        let result = run(vec![StartupAction::WriteDeviceConfig], fixture.handles, StartupProgress::new()).await;
+        assert!(matches!(result, Err(StartupError::NothingWritten)));
+    }
+
+    #[tokio::test]
+    async fn the_write_goes_to_the_device_session() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut fixture = fixture(directory.path());
+        let configs = [DildonicaZoneConfig::default(); NUM_ZONES];
+        let history = &fixture.handles.history;
+        history.append(&HistoryEntry::new(configs, true, None)).unwrap();
+        history.append(&HistoryEntry::new(configs, false, Some("read back differently".to_string()))).unwrap();
+        let actions = vec![StartupAction::WriteDeviceConfig];
+        let sequence = tokio::spawn(run(actions, fixture.handles, StartupProgress::new()));
+
+        assert_eq!(fixture.configs.recv().await, Some(configs));
+        // The session ends before it finished writing
+        drop(fixture.configs);
+        assert!(matches!(sequence.await.unwrap(), Err(StartupError::SessionEnded)));
+    }
+
+    #[tokio::test]
+    async fn waiting_ends_with_the_pipeline() {
+        let directory = tempfile::tempdir().unwrap();
+        let fixture = fixture(directory.path());
+        drop(fixture.commands);
+        let result = run(vec![StartupAction::WaitForSamples], fixture.handles, StartupProgress::new()).await;
+        assert!(matches!(result, Err(StartupError::SessionEnded)));
+    }
+}
@@ -0,0 +1,307 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+pub trait LockExt<T> {
+    /// Locks the mutex even if a thread panicked while holding it. The config,
+    /// zone and MIDI state behind our mutexes stays usable after such a panic,
+    /// and taking the GUI, the pipeline and every output down with it would
+    /// leave notes hanging.
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockExt<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Who takes a lock, for telling how often the GUI holds up the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockSide {
+    Gui,
+    Pipeline,
+    /// Outputs, servers and everything else
+    Other,
+}
+
+impl LockSide {
+    pub const ALL: [LockSide; 3] = [LockSide::Gui, LockSide::Pipeline, LockSide::Other];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LockSide::Gui => "GUI",
+            LockSide::Pipeline => "Pipeline",
+            LockSide::Other => "Other",
+        }
+    }
+}
+
+/// Lock counts of one side
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LockCounts {
+    pub acquisitions: u64,
+    /// Acquisitions that found the lock held and waited
+    pub contended: u64,
+    pub wait_nanos: u64,
+}
+
+/// The counts of the shared mutexes created under `name`, per side in the
+/// order of `LockSide::ALL`
+#[derive(Debug, Clone)]
+pub struct LockReport {
+    pub name: &'static str,
+    pub sides: [LockCounts; 3],
+}
+
+#[cfg(not(feature = "lock-stats"))]
+mod tracked {
+    use super::{LockReport, LockSide};
+    use std::sync::Mutex;
+
+    /// A shared mutex counted with the `lock-stats` feature; without it
+    /// just a `Mutex`
+    pub type TrackedMutex<T> = Mutex<T>;
+
+    pub fn tracked<T>(_name: &'static str, value: T) -> TrackedMutex<T> {
+        Mutex::new(value)
+    }
+
+    /// Restores the side taking locks on this thread when dropped
+    pub struct SideGuard;
+
+    /// Counts the locks taken on this thread for `side` until the guard is
+    /// dropped; does nothing without the `lock-stats` feature.
+    pub fn enter_side(_side: LockSide) -> SideGuard {
+        SideGuard
+    }
+
+    /// Nothing is counted without the `lock-stats` feature
+    pub fn lock_reports() -> Vec<LockReport> {
+        Vec::new()
+    }
+}
+
+#[cfg(feature = "lock-stats")]
+mod tracked {
+    use super::{LockCounts, LockExt, LockReport, LockSide};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex, MutexGuard, PoisonError, TryLockError};
+    use std::time::Instant;
+
+    #[derive(Default)]
+    struct SideStats {
+        acquisitions: AtomicU64,
+        contended: AtomicU64,
+        wait_nanos: AtomicU64,
+    }
+
+    impl SideStats {
+        fn counts(&self) -> LockCounts {
+            LockCounts {
+                acquisitions: self.acquisitions.load(Ordering::Relaxed),
+                contended: self.contended.load(Ordering::Relaxed),
+                wait_nanos: self.wait_nanos.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    struct LockStats {
+        name: &'static str,
+        sides: [SideStats; 3],
+    }
+
+    /// Every tracked mutex created, also those dropped since, so the
+    /// counts of a run add up at its end
+    static REGISTRY: Mutex<Vec<Arc<LockStats>>> = Mutex::new(Vec::new());
+
+    thread_local! {
+        static SIDE: Cell<LockSide> = const { Cell::new(LockSide::Other) };
+    }
+
+    /// A shared mutex counting how often each side takes it, how often it
+    /// had to wait and for how long
+    pub struct TrackedMutex<T> {
+        inner: Mutex<T>,
+        stats: Arc<LockStats>,
+    }
+
+    pub fn tracked<T>(name: &'static str, value: T) -> TrackedMutex<T> {
+        let stats = Arc::new(LockStats {
+            name,
+            sides: Default::default(),
+        });
+        REGISTRY.lock_or_recover().push(stats.clone());
+        TrackedMutex {
+            inner: Mutex::new(value),
+            stats,
+        }
+    }
+
+    impl<T> LockExt<T> for TrackedMutex<T> {
+        fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+            let side = SIDE.with(Cell::get);
+            let stats = &self.stats.sides[side as usize];
+            stats.acquisitions.fetch_add(1, Ordering::Relaxed);
+            match self.inner.try_lock() {
+                Ok(guard) => guard,
+                Err(TryLockError::Poisoned(e)) => e.into_inner(),
+                Err(TryLockError::WouldBlock) => {
+                    stats.contended.fetch_add(1, Ordering::Relaxed);
+                    let started = Instant::now();
+                    let guard = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+                    stats.wait_nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                    guard
+                }
+            }
+        }
+    }
+
+    impl<T: std::fmt::Debug> std::fmt::Debug for TrackedMutex<T> {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_struct("TrackedMutex")
+                .field("name", &self.stats.name)
+                .field("inner", &self.inner)
+                .finish()
+        }
+    }
+
+    /// Restores the side taking locks on this thread when dropped
+    pub struct SideGuard {
+        previous: LockSide,
+    }
+
+    impl Drop for SideGuard {
+        fn drop(&mut self) {
+            SIDE.with(|side| side.set(self.previous));
+        }
+    }
+
+    /// Counts the locks taken on this thread for `side` until the guard is
+    /// dropped.
+    pub fn enter_side(side: LockSide) -> SideGuard {
+        SideGuard {
+            previous: SIDE.with(|current| current.replace(side)),
+        }
+    }
+
+    /// The counts so far, those of the mutexes created under one name
+    /// added up
+    pub fn lock_reports() -> Vec<LockReport> {
+        let mut reports: Vec<LockReport> = Vec::new();
+        for stats in REGISTRY.lock_or_recover().iter() {
+            let index = match reports.iter().position(|report| report.name == stats.name) {
+                Some(index) => index,
+                None => {
+                    reports.push(LockReport {
+                        name: stats.name,
+                        sides: Default::default(),
+                    });
+                    reports.len() - 1
+                }
+            };
+            for (total, side) in reports[index].sides.iter_mut().zip(&stats.sides) {
+                let counts = side.counts();
+                total.acquisitions += counts.acquisitions;
+                total.contended += counts.contended;
+                total.wait_nanos += counts.wait_nanos;
+            }
+        }
+        reports
+    }
+}
+
+pub use tracked::{enter_side, lock_reports, tracked, TrackedMutex};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn a_poisoned_mutex_keeps_its_data() {
+        let mutex = Arc::new(Mutex::new(1));
+        let poisoner = mutex.clone();
+        let _ = thread::spawn(move || {
+            let mut value = poisoner.lock_or_recover();
+            *value = 2;
+            panic!("poisoning the mutex");
+        })
+        .join();
+        assert!(mutex.is_poisoned());
+        assert_eq!(*mutex.lock_or_recover(), 2);
+        *mutex.lock_or_recover() = 3;
+        assert_eq!(*mutex.lock_or_recover(), 3);
+    }
+
+    /// The counts of the mutexes created under `name`
+    #[cfg(feature = "lock-stats")]
+    fn counts(name: &str) -> [LockCounts; 3] {
+        lock_reports().into_iter().find(|report| report.name == name).unwrap().sides
+    }
+
+    #[cfg(feature = "lock-stats")]
+    #[test]
+    fn contention_is_counted_per_side() {
+        use std::sync::Barrier;
+        use std::time::Duration;
+
+        let mutex = Arc::new(tracked("contention test", 0));
+        let held = Arc::new(Barrier::new(2));
+        let gui = {
+            let mutex = mutex.clone();
+            let held = held.clone();
+            thread::spawn(move || {
+                let _side = enter_side(LockSide::Gui);
+                let mut value = mutex.lock_or_recover();
+                held.wait();
+                thread::sleep(Duration::from_millis(20));
+                *value += 1;
+            })
+        };
+        let _side = enter_side(LockSide::Pipeline);
+        held.wait();
+        // The GUI thread holds it, so this waits
+        *mutex.lock_or_recover() += 1;
+        gui.join().unwrap();
+        for _ in 0..9 {
+            *mutex.lock_or_recover() += 1;
+        }
+        assert_eq!(*mutex.lock_or_recover(), 11);
+
+        let [gui, pipeline, other] = counts("contention test");
+        assert_eq!((gui.acquisitions, gui.contended, gui.wait_nanos), (1, 0, 0));
+        assert_eq!((pipeline.acquisitions, pipeline.contended), (11, 1));
+        assert!(pipeline.wait_nanos >= 10_000_000, "waited {} ns", pipeline.wait_nanos);
+        assert_eq!(other, LockCounts::default());
+    }
+
+    #[cfg(feature = "lock-stats")]
+    #[test]
+    fn mutexes_of_one_name_add_up_and_sides_are_restored() {
+        let first = tracked("shared name test", ());
+        let second = tracked("shared name test", ());
+        {
+            let _side = enter_side(LockSide::Gui);
+            drop(first.lock_or_recover());
+            {
+                let _side = enter_side(LockSide::Pipeline);
+                drop(second.lock_or_recover());
+            }
+            drop(second.lock_or_recover());
+        }
+        drop(first.lock_or_recover());
+        let acquisitions = counts("shared name test").map(|side| side.acquisitions);
+        assert_eq!(acquisitions, [2, 1, 1]);
+    }
+
+    #[cfg(not(feature = "lock-stats"))]
+    #[test]
+    fn without_lock_stats_nothing_is_counted() {
+        let mutex = tracked("uncounted test", 1);
+        let _side = enter_side(LockSide::Gui);
+        *mutex.lock_or_recover() += 1;
+        assert_eq!(*mutex.lock_or_recover(), 2);
+        assert!(lock_reports().is_empty());
+    }
+}
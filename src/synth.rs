@@ -0,0 +1,244 @@
+use crate::config::synth::{SynthConfig, SynthWaveform};
+use crate::config::{ConfigStore, NoteConfig, NUM_ZONES};
+use crate::output::{SinkMessage, ThreadedSink};
+use crate::pipeline::SampleEvent;
+use crate::sync::{LockExt, TrackedMutex};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+use std::f32::consts::TAU;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tracing::{error, info, warn};
+
+#[derive(Error, Debug)]
+pub enum SynthError {
+    #[error("No audio output device found")]
+    NoDevice,
+    #[error("Unsupported audio sample format {0}")]
+    UnsupportedFormat(SampleFormat),
+    #[error(transparent)]
+    DefaultConfig(#[from] cpal::DefaultStreamConfigError),
+    #[error(transparent)]
+    BuildStream(#[from] cpal::BuildStreamError),
+    #[error(transparent)]
+    PlayStream(#[from] cpal::PlayStreamError),
+}
+
+/// An `f32` the audio callback can read without locking
+struct AtomicF32(AtomicU32);
+
+impl AtomicF32 {
+    fn new(value: f32) -> Self {
+        Self(AtomicU32::new(value.to_bits()))
+    }
+
+    fn load(&self) -> f32 {
+        f32::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn store(&self, value: f32) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Snapshot of everything the audio callback needs, written by the parameter
+/// thread and read by the callback once per buffer.
+struct SynthParams {
+    frequency: [AtomicF32; NUM_ZONES],
+    /// Target amplitude of each voice, 0..=1
+    level: [AtomicF32; NUM_ZONES],
+    fm: AtomicBool,
+    volume: AtomicF32,
+    attack_ms: AtomicF32,
+    release_ms: AtomicF32,
+    fm_ratio: AtomicF32,
+    fm_index: AtomicF32,
+}
+
+impl SynthParams {
+    fn new() -> Self {
+        Self {
+            frequency: std::array::from_fn(|_| AtomicF32::new(440.0)),
+            level: std::array::from_fn(|_| AtomicF32::new(0.0)),
+            fm: AtomicBool::new(false),
+            volume: AtomicF32::new(0.0),
+            attack_ms: AtomicF32::new(0.0),
+            release_ms: AtomicF32::new(0.0),
+            fm_ratio: AtomicF32::new(1.0),
+            fm_index: AtomicF32::new(0.0),
+        }
+    }
+
+    fn set_config(&self, synth: &SynthConfig, notes: &NoteConfig) {
+        for (zone, frequency) in self.frequency.iter().enumerate() {
+            let note = notes.zone_note(zone);
+            frequency.store(440.0 * 2f32.powf((note as f32 - 69.0) / 12.0));
+        }
+        self.fm.store(synth.waveform == SynthWaveform::Fm, Ordering::Relaxed);
+        self.volume.store(synth.volume as f32);
+        self.attack_ms.store(synth.attack_ms as f32);
+        self.release_ms.store(synth.release_ms as f32);
+        self.fm_ratio.store(synth.fm_ratio as f32);
+        self.fm_index.store(synth.fm_index as f32);
+    }
+
+    fn silence(&self) {
+        for level in &self.level {
+            level.store(0.0);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Voice {
+    /// Carrier and modulator phase in cycles, 0..1
+    phase: f32,
+    mod_phase: f32,
+    envelope: f32,
+}
+
+/// Starts the synthesizer's parameter thread, which also owns the audio
+/// stream. The audio device is opened while `synth.enabled` is set and closed
+/// again when it is cleared.
+pub fn start(app_config: Arc<TrackedMutex<ConfigStore>>) -> ThreadedSink {
+    ThreadedSink::spawn("Synth", |config| config.synth.enabled, move |rx, sink_errors| {
+        let params = Arc::new(SynthParams::new());
+        let mut stream = None;
+        // Don't retry a broken device for every sample, only after toggling
+        let mut failed = false;
+        let mut release = Duration::ZERO;
+        while let Ok(message) = rx.recv() {
+            let (synth, notes) = {
+                let config = app_config.lock_or_recover();
+                (config.synth.clone(), config.midi.note_config.clone())
+            };
+            let event = match message {
+                SinkMessage::Sample(event) if synth.enabled => event,
+                // A flush only silences the voices, the device stays open
+                // while enabled
+                _ => {
+                    params.silence();
+                    if !synth.enabled {
+                        stream = None;
+                        failed = false;
+                    }
+                    continue;
+                }
+            };
+
+            params.set_config(&synth, &notes);
+            update_level(&params, &synth, &event);
+            release = Duration::from_secs_f64(synth.release_ms / 1000.0);
+            if stream.is_none() && !failed {
+                match open_stream(params.clone()) {
+                    Ok(opened) => stream = Some(opened),
+                    Err(e) => {
+                        error!("Failed to start synth output: {}", e);
+                        sink_errors.report(e);
+                        failed = true;
+                    }
+                }
+            }
+        }
+
+        if stream.is_some() {
+            // Let the release envelope finish before closing the device
+            params.silence();
+            std::thread::sleep(release + Duration::from_millis(50));
+        }
+    })
+}
+
+fn update_level(params: &SynthParams, synth: &SynthConfig, event: &SampleEvent) {
+    let magnitude = event.processed.value_normalized.abs();
+    let level = (magnitude * synth.sensitivity).min(1.0);
+    params.level[event.processed.zone].store(level as f32);
+}
+
+fn open_stream(params: Arc<SynthParams>) -> Result<Stream, SynthError> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or(SynthError::NoDevice)?;
+    let supported = device.default_output_config()?;
+    let format = supported.sample_format();
+    let config: StreamConfig = supported.into();
+    let (sample_rate, channels) = (config.sample_rate.0, config.channels);
+    let stream = match format {
+        SampleFormat::F32 => build_stream::<f32>(&device, config, params)?,
+        SampleFormat::I16 => build_stream::<i16>(&device, config, params)?,
+        SampleFormat::U16 => build_stream::<u16>(&device, config, params)?,
+        SampleFormat::I32 => build_stream::<i32>(&device, config, params)?,
+        other => return Err(SynthError::UnsupportedFormat(other)),
+    };
+    stream.play()?;
+    info!("Synth output started ({} Hz, {} channels)", sample_rate, channels);
+    Ok(stream)
+}
+
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: StreamConfig,
+    params: Arc<SynthParams>,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let sample_rate = config.sample_rate.0 as f32;
+    let channels = config.channels as usize;
+    let mut voices = [Voice::default(); NUM_ZONES];
+    device.build_output_stream(
+        &config,
+        move |output: &mut [T], _| render(output, channels, sample_rate, &mut voices, &params),
+        |e| warn!("Audio stream error: {}", e),
+        None,
+    )
+}
+
+/// Audio callback: mixes all voices into `output`, the same signal on every
+/// channel. Runs on the audio thread, so it must not lock or allocate.
+fn render<T>(
+    output: &mut [T],
+    channels: usize,
+    sample_rate: f32,
+    voices: &mut [Voice; NUM_ZONES],
+    params: &SynthParams,
+) where
+    T: SizedSample + FromSample<f32>,
+{
+    let frequency: [f32; NUM_ZONES] = std::array::from_fn(|zone| params.frequency[zone].load());
+    let level: [f32; NUM_ZONES] = std::array::from_fn(|zone| params.level[zone].load());
+    let fm = params.fm.load(Ordering::Relaxed);
+    let volume = params.volume.load();
+    let fm_ratio = params.fm_ratio.load();
+    let fm_index = params.fm_index.load();
+    // Linear ramps, full scale in the configured time
+    let step = |ms: f32| if ms > 0.0 { 1000.0 / (ms * sample_rate) } else { 1.0 };
+    let attack_step = step(params.attack_ms.load());
+    let release_step = step(params.release_ms.load());
+
+    for frame in output.chunks_mut(channels) {
+        let mut mix = 0.0;
+        for (zone, voice) in voices.iter_mut().enumerate() {
+            voice.envelope = if voice.envelope < level[zone] {
+                (voice.envelope + attack_step).min(level[zone])
+            } else {
+                (voice.envelope - release_step).max(level[zone])
+            };
+            let modulation = if fm {
+                fm_index * (TAU * voice.mod_phase).sin()
+            } else {
+                0.0
+            };
+            mix += voice.envelope * (TAU * voice.phase + modulation).sin();
+            voice.phase = (voice.phase + frequency[zone] / sample_rate).fract();
+            voice.mod_phase = (voice.mod_phase + frequency[zone] * fm_ratio / sample_rate).fract();
+        }
+        // Soft clip, several loud zones at once would exceed full scale
+        let value = T::from_sample((mix * volume).tanh());
+        for sample in frame.iter_mut() {
+            *sample = value;
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! The take buffer: the raw samples of the last minutes, kept by the
+//! pipeline whatever is being recorded, and saved as a session bundle after
+//! the fact when asked for by the GUI or a gesture.
+
+use crate::bundle::{BundleHeader, BundleRecord, BundleSample};
+use crate::config::{ConfigStore, DildonicaZoneConfig, TakeBufferConfig, NUM_ZONES};
+use crate::markers::{SessionMarker, SessionMarkers};
+use crate::recorder::{write_markers, RecordFormat};
+use crate::sample::Sample;
+use crate::sync::{LockExt, TrackedMutex};
+use std::collections::VecDeque;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+
+/// A sample as the buffer keeps it
+#[derive(Clone, Copy)]
+struct Entry {
+    /// Seconds since the Unix epoch
+    host_time: f64,
+    sample: Sample,
+}
+
+/// The samples of the last `window_secs`, oldest first, in a buffer
+/// allocated up front for `memory_bytes`: once it is full the oldest sample
+/// makes room for the newest.
+pub struct SampleRing {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+    window_secs: f64,
+}
+
+impl SampleRing {
+    pub fn new(window_secs: f64, memory_bytes: usize) -> Self {
+        let capacity = capacity_for(memory_bytes);
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            window_secs,
+        }
+    }
+
+    /// Adds `sample`, received at `host_time`, dropping the samples that
+    /// fell out of the window or don't fit. Never allocates.
+    pub fn push(&mut self, host_time: f64, sample: Sample) {
+        let cutoff = host_time - self.window_secs;
+        while self.entries.front().is_some_and(|entry| entry.host_time < cutoff) {
+            self.entries.pop_front();
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry { host_time, sample });
+    }
+
+    /// The samples received at `since` or later, oldest first
+    pub fn since(&self, since: f64) -> Vec<BundleSample> {
+        let start = self.entries.partition_point(|entry| entry.host_time < since);
+        self.entries
+            .range(start..)
+            .map(|entry| BundleSample::new(entry.host_time, &entry.sample))
+            .collect()
+    }
+
+    /// The same samples in a ring of another size, as many of the newest as
+    /// fit.
+    fn resized(&self, window_secs: f64, memory_bytes: usize) -> Self {
+        let mut ring = Self::new(window_secs, memory_bytes);
+        let skip = self.entries.len().saturating_sub(ring.capacity);
+        for entry in self.entries.iter().skip(skip) {
+            ring.push(entry.host_time, entry.sample);
+        }
+        ring
+    }
+}
+
+/// Samples that fit in `memory_bytes`, at least one
+fn capacity_for(memory_bytes: usize) -> usize {
+    (memory_bytes / std::mem::size_of::<Entry>()).max(1)
+}
+
+#[derive(Debug, Default)]
+struct TakeState {
+    requested: AtomicBool,
+    saving: AtomicBool,
+    /// How the last save went, until taken
+    finished: Mutex<Option<Result<PathBuf, String>>>,
+}
+
+/// Saving takes, asked for from the GUI and reporting back to it
+#[derive(Debug, Clone, Default)]
+pub struct Takes {
+    state: Arc<TakeState>,
+}
+
+impl Takes {
+    /// Saves a take with the next sample, so not while no samples arrive.
+    pub fn request_save(&self) {
+        self.state.requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether a take is being written
+    pub fn is_saving(&self) -> bool {
+        self.state.saving.load(Ordering::Relaxed)
+    }
+
+    /// The file of the save finished since the last call, or why it failed
+    pub fn take_finished(&self) -> Option<Result<PathBuf, String>> {
+        self.state.finished.lock_or_recover().take()
+    }
+
+    fn take_request(&self) -> bool {
+        self.state.requested.swap(false, Ordering::Relaxed)
+    }
+
+    fn finish(&self, result: Result<PathBuf, String>) {
+        *self.state.finished.lock_or_recover() = Some(result);
+        self.state.saving.store(false, Ordering::Relaxed);
+    }
+}
+
+/// The pipeline's take buffer, following `takes` in the config as it
+/// changes
+#[derive(Default)]
+pub struct TakeBuffer {
+    /// `None` while disabled
+    ring: Option<SampleRing>,
+    takes: Takes,
+}
+
+impl TakeBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn takes(&self) -> Takes {
+        self.takes.clone()
+    }
+
+    /// Keeps `sample`, received at `host_time`. Only allocates when the
+    /// buffer is enabled or resized in `config`.
+    pub fn push(&mut self, host_time: f64, sample: Sample, config: &TakeBufferConfig) {
+        if !config.enabled {
+            self.ring = None;
+            return;
+        }
+        let window_secs = config.minutes * 60.0;
+        let memory_bytes = config.memory_bytes();
+        let ring = match self.ring.take() {
+            Some(ring) if ring.window_secs == window_secs && ring.capacity == capacity_for(memory_bytes) => ring,
+            Some(ring) => ring.resized(window_secs, memory_bytes),
+            None => SampleRing::new(window_secs, memory_bytes),
+        };
+        self.ring.insert(ring).push(host_time, sample);
+    }
+
+    /// Whether the GUI asked for a take since the last call
+    pub fn take_request(&self) -> bool {
+        self.takes.take_request()
+    }
+
+    /// Saves the last `save_secs` before `now` on a thread of its own, as a
+    /// session bundle with the settings as they are once it's written and
+    /// the `markers` dropped within it. A save asked for while one is
+    /// written is skipped.
+    pub fn save(
+        &self,
+        now: f64,
+        config: &TakeBufferConfig,
+        markers: &SessionMarkers,
+        app_config: Arc<TrackedMutex<ConfigStore>>,
+        zone_configs: Option<Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>>,
+    ) {
+        if self.takes.state.saving.swap(true, Ordering::Relaxed) {
+            info!("Still saving the last take, skipping this one");
+            return;
+        }
+        let samples = self.ring.as_ref().map(|ring| ring.since(now - config.save_secs)).unwrap_or_default();
+        if samples.is_empty() {
+            self.takes.finish(Err("No samples to save".to_string()));
+            return;
+        }
+        let markers = markers.since(samples[0].host_time);
+        let directory = config.directory();
+        let takes = self.takes.clone();
+        std::thread::spawn(move || {
+            let result = write_take(&directory, &samples, &markers, &app_config, zone_configs.as_deref());
+            match &result {
+                Ok(path) => info!(
+                    "Saved a take of {:.0} s, {} samples, to {}",
+                    samples.last().map_or(0.0, |last| last.host_time - samples[0].host_time),
+                    samples.len(),
+                    path.display()
+                ),
+                Err(e) => error!("Failed to save the take: {}", e),
+            }
+            takes.finish(result.map_err(|e| e.to_string()));
+        });
+    }
+}
+
+/// Writes `samples` and `markers` to a new bundle named after the local
+/// time in `directory`.
+fn write_take(
+    directory: &Path,
+    samples: &[BundleSample],
+    markers: &[SessionMarker],
+    app_config: &TrackedMutex<ConfigStore>,
+    zone_configs: Option<&TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>,
+) -> io::Result<PathBuf> {
+    fs::create_dir_all(directory)?;
+    let path = directory.join(format!("take-{}.jsonl", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+    let file = OpenOptions::new().write(true).create_new(true).open(&path)?;
+    let mut writer = BufWriter::new(file);
+    // Without a device the defaults, as in a recording
+    let zone_configs = zone_configs.map_or([DildonicaZoneConfig::default(); NUM_ZONES], |zone_configs| {
+        *zone_configs.lock_or_recover()
+    });
+    let header = BundleHeader::new(samples[0].host_time, &app_config.lock_or_recover(), &zone_configs);
+    serde_json::to_writer(&mut writer, &BundleRecord::Header(header))?;
+    writeln!(writer)?;
+    for sample in samples {
+        serde_json::to_writer(&mut writer, &BundleRecord::Sample(*sample))?;
+        writeln!(writer)?;
+    }
+    write_markers(&mut writer, RecordFormat::Bundle, markers)?;
+    writer.flush()?;
+    Ok(path)
+}
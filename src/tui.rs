@@ -0,0 +1,232 @@
+use crate::config::{ConfigStore, DildonicaZoneConfig, LogicalZones, ZoneMapScope, NUM_ZONES};
+use crate::diagnostics::{self, SAMPLE_TIMEOUT};
+use crate::logging::LogCapture;
+use crate::midi::MidiOutputState;
+use crate::pipeline::PipelineCommand;
+use crate::plot_history::{PlotFeed, PlotFrame};
+use crate::sync::{LockExt, TrackedMutex};
+use crossterm::event::{Event, EventStream, KeyCode, KeyEventKind, KeyModifiers};
+use futures::StreamExt;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Gauge, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+/// Time between redraws
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+/// Log lines shown below the meters, more if the terminal is taller
+const LOG_LINES: u16 = 8;
+
+/// The terminal dashboard: one level meter per zone, source and MIDI status,
+/// and the latest log lines. Reads the same plot frames as the GUI.
+pub struct Tui {
+    plot: PlotFeed,
+    app_config: Arc<TrackedMutex<ConfigStore>>,
+    zone_configs: Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>,
+    midi_output: Arc<Mutex<MidiOutputState>>,
+    commands: mpsc::Sender<PipelineCommand>,
+    /// "device" or "recording", shown in the status line
+    source: &'static str,
+    /// Sample count of the frame at `rate_since`, and the rate of the last
+    /// full second
+    rate_samples: u64,
+    rate_since: Instant,
+    samples_per_sec: f64,
+    muted: bool,
+}
+
+impl Tui {
+    pub fn new(
+        plot: PlotFeed,
+        app_config: Arc<TrackedMutex<ConfigStore>>,
+        zone_configs: Arc<TrackedMutex<[DildonicaZoneConfig; NUM_ZONES]>>,
+        midi_output: Arc<Mutex<MidiOutputState>>,
+        commands: mpsc::Sender<PipelineCommand>,
+        source: &'static str,
+    ) -> Self {
+        Self {
+            plot,
+            app_config,
+            zone_configs,
+            midi_output,
+            commands,
+            source,
+            rate_samples: 0,
+            rate_since: Instant::now(),
+            samples_per_sec: 0.0,
+            muted: false,
+        }
+    }
+
+    /// Runs until the user quits or `shutdown` is cancelled, then cancels
+    /// `shutdown` itself. Log output is shown in the dashboard meanwhile.
+    pub async fn run(mut self, shutdown: CancellationToken) -> io::Result<()> {
+        let mut terminal = ratatui::try_init()?;
+        let logs = LogCapture::start();
+        let result = self.event_loop(&mut terminal, &logs, &shutdown).await;
+        drop(logs);
+        ratatui::restore();
+        shutdown.cancel();
+        result
+    }
+
+    async fn event_loop(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        logs: &LogCapture,
+        shutdown: &CancellationToken,
+    ) -> io::Result<()> {
+        let mut events = EventStream::new();
+        let mut redraw = tokio::time::interval(FRAME_INTERVAL);
+        loop {
+            tokio::select! {
+                event = events.next() => match event {
+                    Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                        let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+                        match key.code {
+                            _ if ctrl_c => return Ok(()),
+                            KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                            KeyCode::Char('p') => self.send(PipelineCommand::Panic),
+                            KeyCode::Char('r') => self.send(PipelineCommand::ResetBaselines),
+                            KeyCode::Char('m') => {
+                                self.muted = !self.muted;
+                                self.send(PipelineCommand::SetMuted(self.muted));
+                            }
+                            _ => {}
+                        }
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e),
+                    None => return Ok(()),
+                },
+                _ = redraw.tick() => {
+                    let plot = self.plot.load();
+                    self.update_rate(&plot);
+                    terminal.draw(|frame| self.render(frame, &plot, logs))?;
+                }
+                _ = shutdown.cancelled() => return Ok(()),
+            }
+        }
+    }
+
+    fn update_rate(&mut self, plot: &PlotFrame) {
+        let elapsed = self.rate_since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.samples_per_sec = (plot.samples - self.rate_samples) as f64 / elapsed.as_secs_f64();
+            self.rate_samples = plot.samples;
+            self.rate_since = Instant::now();
+        }
+    }
+
+    fn send(&self, command: PipelineCommand) {
+        if self.commands.try_send(command).is_err() {
+            tracing::warn!("Sample processing is not running");
+        }
+    }
+
+    fn render(&self, frame: &mut Frame, plot: &PlotFrame, logs: &LogCapture) {
+        let [status_area, meters_area, log_area, keys_area] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(NUM_ZONES as u16 + 2),
+            Constraint::Min(LOG_LINES + 2),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let receiving = diagnostics::receiving(plot.received_at(), SAMPLE_TIMEOUT);
+        let mut status = vec![
+            Span::styled("Source: ", Style::new().bold()),
+            Span::raw(self.source),
+            Span::raw(if receiving { ", receiving" } else { ", waiting for samples" }),
+            Span::raw(format!(" ({:.0} samples/s)", if receiving { self.samples_per_sec } else { 0.0 })),
+            Span::styled("  MIDI: ", Style::new().bold()),
+            Span::raw(self.midi_output.lock_or_recover().status().to_string()),
+        ];
+        if self.muted {
+            status.push(Span::styled("  MUTED", Style::new().fg(Color::Red).bold()));
+        }
+        frame.render_widget(Line::from(status), status_area);
+
+        let (threshold, zone_map, scope, peak_hold, logical_zones, reduction) = {
+            let app_config = self.app_config.lock_or_recover();
+            (
+                app_config.midi.note_config.threshold,
+                app_config.zone_map.clone(),
+                app_config.zone_map_scope,
+                app_config.midi.peak.enabled,
+                LogicalZones::new(&app_config.zone_groups, NUM_ZONES),
+                app_config.zone_group_reduction,
+            )
+        };
+        let zone_configs = *self.zone_configs.lock_or_recover();
+        let meters = Block::bordered().title(" Zones ");
+        let rows = Layout::vertical([Constraint::Length(1); NUM_ZONES]).split(meters.inner(meters_area));
+        frame.render_widget(meters, meters_area);
+        // One meter per logical zone, reduced from its members like for MIDI
+        for (logical, &row) in rows.iter().enumerate().take(logical_zones.len()) {
+            let members = logical_zones.members(logical);
+            let enabled = members.iter().any(|&zone| {
+                zone_map
+                    .get(zone)
+                    .and_then(|&device_zone| zone_configs.get(device_zone))
+                    .is_none_or(|config| config.enabled)
+            });
+            // With `MidiOnly` the plot holds the members under their device zones
+            let plot_zone = |zone: usize| match scope {
+                ZoneMapScope::Everything => zone,
+                ZoneMapScope::MidiOnly => zone_map.get(zone).copied().unwrap_or(zone),
+            };
+            let points = || members.iter().filter_map(|&zone| plot.last(plot_zone(zone)));
+            let magnitude = reduction.reduce(points().map(|point| point.normalized)).abs();
+            let peak = reduction.reduce(points().map(|point| point.peak));
+            let zone = members.iter().map(|zone| zone.to_string()).collect::<Vec<_>>().join("+");
+            let color = if magnitude >= threshold { Color::Green } else { Color::DarkGray };
+            let label = if peak_hold {
+                format!("{} {:.3} peak {:.3}", zone, magnitude, peak)
+            } else {
+                format!("{} {:.3}", zone, magnitude)
+            };
+            let gauge = if enabled {
+                Gauge::default()
+                    .gauge_style(Style::new().fg(color))
+                    .ratio(magnitude.clamp(0.0, 1.0))
+                    .label(label)
+            } else {
+                Gauge::default()
+                    .gauge_style(Style::new().fg(Color::DarkGray))
+                    .ratio(0.0)
+                    .label(format!("{} disabled", zone))
+            };
+            frame.render_widget(gauge, row);
+            if enabled && peak_hold && row.width > 0 && row.height > 0 {
+                let offset = (peak.clamp(0.0, 1.0) * f64::from(row.width - 1)).round() as u16;
+                if let Some(cell) = frame.buffer_mut().cell_mut((row.x + offset, row.y)) {
+                    cell.set_symbol("│").set_fg(Color::Yellow);
+                }
+            }
+        }
+
+        let log = Block::bordered().title(" Log ");
+        let visible = usize::from(log.inner(log_area).height);
+        let lines: Vec<Line> = logs.recent(visible).into_iter().map(Line::from).collect();
+        frame.render_widget(Paragraph::new(lines).block(log), log_area);
+
+        let keys = Line::from(vec![
+            Span::styled("q", Style::new().bold()),
+            Span::raw(" quit  "),
+            Span::styled("p", Style::new().bold()),
+            Span::raw(" panic  "),
+            Span::styled("m", Style::new().bold()),
+            Span::raw(if self.muted { " unmute  " } else { " mute  " }),
+            Span::styled("r", Style::new().bold()),
+            Span::raw(" reset baselines"),
+        ]);
+        frame.render_widget(keys, keys_area);
+    }
+}
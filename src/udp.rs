@@ -0,0 +1,125 @@
+use crate::output::SinkErrors;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// While sends keep failing, a summary is logged at most this often
+const ERROR_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+/// How long to wait before resolving a host name that failed again
+const RESOLVE_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A resolved host/port pair and the socket used to reach it
+pub struct Destination {
+    host: String,
+    port: u16,
+    socket: Result<(UdpSocket, SocketAddr), String>,
+    resolved_at: Instant,
+}
+
+impl Destination {
+    /// The destination in `slot`, resolved again if the host or port changed
+    /// or if resolving failed a while ago.
+    pub fn get<'a>(slot: &'a mut Option<Self>, host: &str, port: u16) -> &'a Self {
+        let stale = match slot {
+            Some(destination) => {
+                destination.host != host
+                    || destination.port != port
+                    || (destination.socket.is_err()
+                        && destination.resolved_at.elapsed() >= RESOLVE_RETRY_INTERVAL)
+            }
+            None => true,
+        };
+        if stale {
+            *slot = None;
+        }
+        slot.get_or_insert_with(|| Self::resolve(host, port))
+    }
+
+    fn resolve(host: &str, port: u16) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            socket: Self::open(host, port).map_err(|e| e.to_string()),
+            resolved_at: Instant::now(),
+        }
+    }
+
+    fn open(host: &str, port: u16) -> std::io::Result<(UdpSocket, SocketAddr)> {
+        let addr = (host, port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| std::io::Error::other("host has no addresses"))?;
+        let socket = if addr.is_ipv4() {
+            let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+            // Otherwise sending to a broadcast address fails
+            socket.set_broadcast(true)?;
+            socket
+        } else {
+            UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?
+        };
+        Ok((socket, addr))
+    }
+
+    /// `host:port`, for log messages
+    pub fn target(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    pub fn send(&self, packet: &[u8]) -> Result<(), String> {
+        match &self.socket {
+            Ok((socket, addr)) => socket.send_to(packet, addr).map(|_| ()).map_err(|e| e.to_string()),
+            Err(e) => Err(e.clone()),
+        }
+    }
+}
+
+/// Logs the first failure of a run of errors and then only a periodic
+/// count, so an unreachable destination doesn't flood the log. Every failure
+/// still counts in the output's status.
+pub struct ErrorReporter {
+    /// Output named in messages, e.g. "OSC output"
+    name: &'static str,
+    sink_errors: SinkErrors,
+    failing: bool,
+    suppressed: u64,
+    last_report: Instant,
+}
+
+impl ErrorReporter {
+    pub fn new(name: &'static str, sink_errors: SinkErrors) -> Self {
+        Self {
+            name,
+            sink_errors,
+            failing: false,
+            suppressed: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    pub fn failed(&mut self, target: &str, error: &str) {
+        self.sink_errors.report(format!("{}: {}", target, error));
+        if !self.failing {
+            warn!("{} to {} failed: {}", self.name, target, error);
+            self.failing = true;
+            self.suppressed = 0;
+            self.last_report = Instant::now();
+            return;
+        }
+        self.suppressed += 1;
+        if self.last_report.elapsed() >= ERROR_REPORT_INTERVAL {
+            warn!(
+                "{} to {} still failing ({} more errors): {}",
+                self.name, target, self.suppressed, error
+            );
+            self.suppressed = 0;
+            self.last_report = Instant::now();
+        }
+    }
+
+    pub fn succeeded(&mut self, target: &str) {
+        if self.failing {
+            info!("{} to {} is working again", self.name, target);
+            self.failing = false;
+        }
+    }
+}
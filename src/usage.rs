@@ -0,0 +1,399 @@
+use crate::config::NUM_ZONES;
+use crate::gesture::{Gesture, Gestures};
+use crate::sync::LockExt;
+use chrono::{DateTime, Local, Timelike};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info};
+
+/// How often `--stats-file` is rewritten
+pub const STATS_FILE_INTERVAL: Duration = Duration::from_secs(60);
+/// Version of the `--stats-file` format, raised when it changes incompatibly
+const STATS_FILE_VERSION: u32 = 1;
+
+/// Touches of one zone and how long they lasted in total
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZoneUsage {
+    pub touches: u64,
+    pub active_secs: f64,
+}
+
+/// Usage counted over some span of time, a session or a day. Counters
+/// saturate instead of wrapping.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsageCounts {
+    pub zones: [ZoneUsage; NUM_ZONES],
+    /// Touches started in every hour of the day, local time
+    pub hourly_touches: [u64; 24],
+}
+
+impl Default for UsageCounts {
+    fn default() -> Self {
+        Self {
+            zones: [ZoneUsage::default(); NUM_ZONES],
+            hourly_touches: [0; 24],
+        }
+    }
+}
+
+impl UsageCounts {
+    pub fn total_touches(&self) -> u64 {
+        self.zones.iter().fold(0, |total, zone| total.saturating_add(zone.touches))
+    }
+
+    /// The hour of the day with the most touches and their count, `None`
+    /// before the first touch. Ties go to the earlier hour.
+    pub fn busiest_hour(&self) -> Option<(usize, u64)> {
+        let (hour, &touches) = self
+            .hourly_touches
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|&(_, touches)| touches)?;
+        (touches > 0).then_some((hour, touches))
+    }
+
+    /// Adds the counts of `other`.
+    pub fn add(&mut self, other: &UsageCounts) {
+        for (zone, other) in self.zones.iter_mut().zip(&other.zones) {
+            zone.touches = zone.touches.saturating_add(other.touches);
+            zone.active_secs += other.active_secs;
+        }
+        for (touches, other) in self.hourly_touches.iter_mut().zip(&other.hourly_touches) {
+            *touches = touches.saturating_add(*other);
+        }
+    }
+
+    fn touch_started(&mut self, zone: usize, hour: usize) {
+        self.zones[zone].touches = self.zones[zone].touches.saturating_add(1);
+        self.hourly_touches[hour] = self.hourly_touches[hour].saturating_add(1);
+    }
+}
+
+/// The day a touch is counted for in the stats file, e.g. "2026-10-14"
+fn day_of(time: DateTime<Local>) -> String {
+    time.format("%Y-%m-%d").to_string()
+}
+
+struct UsageState {
+    session: UsageCounts,
+    since: DateTime<Local>,
+    /// Counts per day not yet written to the stats file
+    unsaved: BTreeMap<String, UsageCounts>,
+    /// Start of the current touch of every zone
+    touching: [Option<Instant>; NUM_ZONES],
+}
+
+/// Touches and active time per zone, counted by the pipeline from the touch
+/// gestures and shown in the Stats tab. A touch counts for the hour and day
+/// it started in, its active time for the day it ended in.
+#[derive(Clone)]
+pub struct UsageStats {
+    state: Arc<Mutex<UsageState>>,
+}
+
+impl Default for UsageStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UsageStats {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(UsageState {
+                session: UsageCounts::default(),
+                since: Local::now(),
+                unsaved: BTreeMap::new(),
+                touching: [None; NUM_ZONES],
+            })),
+        }
+    }
+
+    /// Counts the touches `gestures` of `zone`, completed at `now`, started
+    /// or ended; other gestures are ignored.
+    pub fn record(&self, zone: usize, gestures: Gestures, now: Instant) {
+        let started = gestures.contains(Gesture::TouchStart);
+        let ended = gestures.contains(Gesture::TouchEnd);
+        if !started && !ended {
+            return;
+        }
+        let local = Local::now();
+        let mut state = self.state.lock_or_recover();
+        let state = &mut *state;
+        let day = state.unsaved.entry(day_of(local)).or_default();
+        if ended {
+            if let Some(start) = state.touching[zone].take() {
+                let secs = now.saturating_duration_since(start).as_secs_f64();
+                state.session.zones[zone].active_secs += secs;
+                day.zones[zone].active_secs += secs;
+            }
+        }
+        if started {
+            let hour = local.hour() as usize;
+            state.session.touch_started(zone, hour);
+            day.touch_started(zone, hour);
+            state.touching[zone] = Some(now);
+        }
+    }
+
+    /// Counts of the session and when they started, at startup or the last
+    /// reset
+    pub fn session(&self) -> (UsageCounts, DateTime<Local>) {
+        let state = self.state.lock_or_recover();
+        (state.session, state.since)
+    }
+
+    /// Starts the session counts again. What goes to the stats file is not
+    /// affected.
+    pub fn reset(&self) {
+        let mut state = self.state.lock_or_recover();
+        state.session = UsageCounts::default();
+        state.since = Local::now();
+        info!("Usage stats reset");
+    }
+
+    /// Takes the counts per day since the last call.
+    fn take_unsaved(&self) -> BTreeMap<String, UsageCounts> {
+        std::mem::take(&mut self.state.lock_or_recover().unsaved)
+    }
+}
+
+/// Contents of a `--stats-file`: the usage of every day it was running on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsFileContents {
+    pub version: u32,
+    /// By local date, e.g. "2026-10-14"
+    pub days: BTreeMap<String, UsageCounts>,
+}
+
+impl StatsFileContents {
+    /// Reads `path`, empty if it doesn't exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self {
+                    version: STATS_FILE_VERSION,
+                    days: BTreeMap::new(),
+                })
+            }
+            Err(e) => return Err(e),
+        };
+        let stats: Self = serde_json::from_str(&contents)?;
+        if stats.version > STATS_FILE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("stats file version {} is newer than this frontend", stats.version),
+            ));
+        }
+        Ok(stats)
+    }
+
+    /// Adds `days` to the days already in the file.
+    pub fn add(&mut self, days: BTreeMap<String, UsageCounts>) {
+        for (day, counts) in days {
+            self.days.entry(day).or_default().add(&counts);
+        }
+    }
+
+    /// Writes the file through a temp file, like the config.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        let mut tmp_path = path.to_path_buf().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+/// The thread writing `--stats-file` every `STATS_FILE_INTERVAL`. Call
+/// `finish()` for the final write.
+pub struct StatsFile {
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl StatsFile {
+    /// Reads `path`, so a restart adds to the days already in it, and starts
+    /// the writer thread. Fails if the file exists but can't be read.
+    pub fn start(path: &Path, usage: UsageStats) -> io::Result<Self> {
+        let mut contents = StatsFileContents::load(path)?;
+        let path: PathBuf = path.to_path_buf();
+        let (stop, stopped) = mpsc::channel();
+        let thread = std::thread::spawn(move || loop {
+            let finished = !matches!(stopped.recv_timeout(STATS_FILE_INTERVAL), Err(RecvTimeoutError::Timeout));
+            contents.add(usage.take_unsaved());
+            match contents.save(&path) {
+                Ok(()) => debug!("Usage stats written to {}", path.display()),
+                Err(e) => error!("Failed to write usage stats to {}: {}", path.display(), e),
+            }
+            if finished {
+                break;
+            }
+        });
+        Ok(Self { stop, thread })
+    }
+
+    /// Writes the file a last time and waits for it.
+    pub fn finish(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gestures(list: &[Gesture]) -> Gestures {
+        let mut gestures = Gestures::default();
+        for &gesture in list {
+            gestures.insert(gesture);
+        }
+        gestures
+    }
+
+    /// The counts of every day not yet saved, added up
+    fn unsaved(usage: &UsageStats) -> UsageCounts {
+        let mut total = UsageCounts::default();
+        for counts in usage.take_unsaved().values() {
+            total.add(counts);
+        }
+        total
+    }
+
+    #[test]
+    fn touches_count_on_start_and_active_time_on_end() {
+        let usage = UsageStats::new();
+        let start = Instant::now();
+        usage.record(2, gestures(&[Gesture::TouchStart]), start);
+        let (session, _) = usage.session();
+        assert_eq!(session.zones[2], ZoneUsage { touches: 1, active_secs: 0.0 });
+        assert_eq!(session.hourly_touches.iter().sum::<u64>(), 1);
+
+        usage.record(2, gestures(&[Gesture::TouchEnd]), start + Duration::from_millis(1500));
+        let (session, _) = usage.session();
+        assert_eq!(session.zones[2], ZoneUsage { touches: 1, active_secs: 1.5 });
+        assert_eq!(unsaved(&usage).zones[2], session.zones[2]);
+    }
+
+    #[test]
+    fn other_gestures_and_unstarted_ends_count_nothing() {
+        let usage = UsageStats::new();
+        let now = Instant::now();
+        usage.record(0, Gestures::default(), now);
+        usage.record(0, gestures(&[Gesture::TouchEnd]), now);
+        assert_eq!(usage.session().0, UsageCounts::default());
+    }
+
+    #[test]
+    fn reset_leaves_what_goes_to_the_file() {
+        let usage = UsageStats::new();
+        usage.record(1, gestures(&[Gesture::TouchStart]), Instant::now());
+        usage.reset();
+        assert_eq!(usage.session().0.total_touches(), 0);
+        assert_eq!(unsaved(&usage).zones[1].touches, 1);
+        assert_eq!(unsaved(&usage).total_touches(), 0, "taken once");
+    }
+
+    #[test]
+    fn counters_saturate_instead_of_rolling_over() {
+        let mut counts = UsageCounts::default();
+        counts.zones[0].touches = u64::MAX;
+        counts.zones[1].touches = 5;
+        counts.hourly_touches[3] = u64::MAX;
+        assert_eq!(counts.total_touches(), u64::MAX);
+        counts.touch_started(0, 3);
+        assert_eq!((counts.zones[0].touches, counts.hourly_touches[3]), (u64::MAX, u64::MAX));
+        let copy = counts;
+        counts.add(&copy);
+        assert_eq!(counts.zones[0].touches, u64::MAX);
+        assert_eq!(counts.zones[1].touches, 10);
+    }
+
+    #[test]
+    fn the_busiest_hour_goes_to_the_earlier_of_a_tie() {
+        let mut counts = UsageCounts::default();
+        assert_eq!(counts.busiest_hour(), None);
+        counts.hourly_touches[20] = 4;
+        counts.hourly_touches[9] = 4;
+        counts.hourly_touches[12] = 2;
+        assert_eq!(counts.busiest_hour(), Some((9, 4)));
+    }
+
+    #[test]
+    fn the_stats_file_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+        let zones = [r#"{"touches":3,"active_secs":4.5}"#; NUM_ZONES].join(",");
+        let hours = ["0"; 24].join(",");
+        let json = format!(
+            r#"{{"version":1,"days":{{"2026-10-14":{{"zones":[{}],"hourly_touches":[{}]}}}}}}"#,
+            zones, hours
+        );
+        fs::write(&path, json).unwrap();
+        let loaded = StatsFileContents::load(&path).unwrap();
+        assert_eq!(loaded.version, 1);
+        assert_eq!(loaded.days["2026-10-14"].zones[7], ZoneUsage { touches: 3, active_secs: 4.5 });
+
+        // Missing fields are defaults
+        fs::write(&path, r#"{"version":1,"days":{"2026-10-15":{}}}"#).unwrap();
+        assert_eq!(StatsFileContents::load(&path).unwrap().days["2026-10-15"], UsageCounts::default());
+    }
+
+    #[test]
+    fn the_stats_file_round_trips_and_adds_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+        let mut contents = StatsFileContents::load(&path).unwrap();
+        assert!(contents.days.is_empty());
+        let mut counts = UsageCounts::default();
+        counts.touch_started(4, 13);
+        contents.add(BTreeMap::from([("2026-10-14".to_string(), counts)]));
+        contents.add(BTreeMap::from([("2026-10-14".to_string(), counts)]));
+        contents.save(&path).unwrap();
+        assert!(!dir.path().join("stats.json.tmp").exists());
+
+        let loaded = StatsFileContents::load(&path).unwrap();
+        assert_eq!(loaded.version, STATS_FILE_VERSION);
+        assert_eq!(loaded.days["2026-10-14"].zones[4].touches, 2);
+        assert_eq!(loaded.days["2026-10-14"].hourly_touches[13], 2);
+    }
+
+    #[test]
+    fn unreadable_and_newer_stats_files_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(StatsFileContents::load(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::write(&path, r#"{"version":2,"days":{}}"#).unwrap();
+        let error = StatsFileContents::load(&path).unwrap_err();
+        assert!(error.to_string().contains("newer"), "{}", error);
+        assert!(StatsFile::start(&path, UsageStats::new()).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{"version":2,"days":{}}"#, "left untouched");
+    }
+
+    #[test]
+    fn a_restart_adds_to_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+        for _ in 0..2 {
+            let usage = UsageStats::new();
+            usage.record(0, gestures(&[Gesture::TouchStart]), Instant::now());
+            StatsFile::start(&path, usage).unwrap().finish();
+        }
+        let loaded = StatsFileContents::load(&path).unwrap();
+        let touches: u64 = loaded.days.values().map(|counts| counts.zones[0].touches).sum();
+        assert_eq!(touches, 2);
+    }
+}
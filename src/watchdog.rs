@@ -0,0 +1,179 @@
+use crate::config::WatchdogConfig;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What the watchdog noticed about the sample stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchdogEvent {
+    /// No sample for this long, `stall_secs` or more
+    Suspect(Duration),
+    /// Still none for this long: the connection should be made again
+    Reconnect(Duration),
+}
+
+/// Watches a device session for samples that stopped arriving while the
+/// connection still claims to be up, as btleplug's notification stream
+/// sometimes does. The session feeds it every sample and asks it regularly;
+/// both take the time, so it runs on any clock.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    stall: Duration,
+    reconnect: Duration,
+    /// The newest sample, or the start of the session before the first
+    last_sample: Instant,
+    suspect: bool,
+    /// A reconnect was asked for, the session ends
+    expired: bool,
+}
+
+impl Watchdog {
+    /// A watchdog for a session starting at `now`; `None` when disabled.
+    pub fn new(config: &WatchdogConfig, now: Instant) -> Option<Self> {
+        config.enabled.then(|| Self {
+            stall: config.stall(),
+            reconnect: config.reconnect(),
+            last_sample: now,
+            suspect: false,
+            expired: false,
+        })
+    }
+
+    /// Counts a sample at `now`, which clears a suspect connection.
+    pub fn sample(&mut self, now: Instant) {
+        self.last_sample = now;
+        self.suspect = false;
+    }
+
+    /// Whether the stream went quiet by `now`: once suspect after `stall_secs`
+    /// without a sample, and once a reconnect after `reconnect_secs` more.
+    pub fn check(&mut self, now: Instant) -> Option<WatchdogEvent> {
+        let silent = now.saturating_duration_since(self.last_sample);
+        if !self.suspect && silent >= self.stall {
+            self.suspect = true;
+            Some(WatchdogEvent::Suspect(silent))
+        } else if self.suspect && !self.expired && silent >= self.stall + self.reconnect {
+            self.expired = true;
+            Some(WatchdogEvent::Reconnect(silent))
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether the device session's connection is suspect, shared with the GUI
+/// for its status bar. It stays so through a reconnect, until samples
+/// arrive again. Also counts the samples, for waiting on them.
+#[derive(Debug, Clone, Default)]
+pub struct LinkHealth {
+    suspect: Arc<AtomicBool>,
+    /// Samples received in every session so far
+    samples: Arc<AtomicU64>,
+}
+
+impl LinkHealth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_suspect(&self, suspect: bool) {
+        self.suspect.store(suspect, Ordering::Relaxed);
+    }
+
+    pub fn is_suspect(&self) -> bool {
+        self.suspect.load(Ordering::Relaxed)
+    }
+
+    pub fn record_sample(&self) {
+        self.samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: f64) -> Duration {
+        Duration::from_secs_f64(secs)
+    }
+
+    /// Every event of checking `watchdog` every 100 ms from `start` until
+    /// `end` seconds in, with when it came
+    fn events(watchdog: &mut Watchdog, start: Instant, end: f64) -> Vec<(Duration, WatchdogEvent)> {
+        (0..=(end * 10.0) as u32)
+            .map(|tenth| secs(f64::from(tenth) / 10.0))
+            .filter_map(|at| watchdog.check(start + at).map(|event| (at, event)))
+            .collect()
+    }
+
+    #[test]
+    fn a_silent_stream_is_suspect_then_reconnected_once() {
+        let start = Instant::now();
+        let mut watchdog = Watchdog::new(&WatchdogConfig::default(), start).unwrap();
+        assert_eq!(
+            events(&mut watchdog, start, 60.0),
+            vec![
+                (secs(5.0), WatchdogEvent::Suspect(secs(5.0))),
+                (secs(10.0), WatchdogEvent::Reconnect(secs(10.0))),
+            ]
+        );
+        assert!(watchdog.suspect);
+    }
+
+    #[test]
+    fn samples_keep_it_quiet_and_clear_a_suspect_stream() {
+        let start = Instant::now();
+        let mut watchdog = Watchdog::new(&WatchdogConfig::default(), start).unwrap();
+        for second in 0..30 {
+            watchdog.sample(start + secs(f64::from(second)));
+            assert_eq!(watchdog.check(start + secs(f64::from(second) + 0.9)), None);
+        }
+        assert_eq!(watchdog.check(start + secs(36.0)), Some(WatchdogEvent::Suspect(secs(7.0))));
+        watchdog.sample(start + secs(37.0));
+        assert!(!watchdog.suspect);
+        // Quiet again, timed from the newest sample
+        assert_eq!(watchdog.check(start + secs(41.0)), None);
+        assert_eq!(watchdog.check(start + secs(42.0)), Some(WatchdogEvent::Suspect(secs(5.0))));
+        assert_eq!(watchdog.check(start + secs(47.0)), Some(WatchdogEvent::Reconnect(secs(10.0))));
+    }
+
+    #[test]
+    fn the_intervals_follow_the_config() {
+        let config = WatchdogConfig {
+            stall_secs: 1.0,
+            reconnect_secs: 0.0,
+            ..WatchdogConfig::default()
+        };
+        let start = Instant::now();
+        let mut watchdog = Watchdog::new(&config, start).unwrap();
+        assert_eq!(watchdog.check(start + secs(0.9)), None);
+        assert_eq!(watchdog.check(start + secs(1.0)), Some(WatchdogEvent::Suspect(secs(1.0))));
+        assert_eq!(watchdog.check(start + secs(1.0)), Some(WatchdogEvent::Reconnect(secs(1.0))));
+    }
+
+    #[test]
+    fn a_disabled_watchdog_is_none() {
+        let config = WatchdogConfig {
+            enabled: false,
+            ..WatchdogConfig::default()
+        };
+        assert!(Watchdog::new(&config, Instant::now()).is_none());
+    }
+
+    #[test]
+    fn link_health_is_shared_by_its_clones() {
+        let health = LinkHealth::new();
+        let gui = health.clone();
+        health.set_suspect(true);
+        health.record_sample();
+        health.record_sample();
+        assert!(gui.is_suspect());
+        assert_eq!(gui.samples(), 2);
+        health.set_suspect(false);
+        assert!(!gui.is_suspect());
+    }
+}
@@ -0,0 +1,315 @@
+use crate::config::{AppConfig, ConfigStore};
+use crate::pipeline::{SampleEvent, SampleTap};
+use crate::sync::{LockExt, TrackedMutex};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// Samples buffered between the pipeline and the broadcaster
+const WS_QUEUE_SIZE: usize = 1024;
+/// Frames buffered per client; a client that falls further behind loses the
+/// oldest ones
+const CLIENT_QUEUE_SIZE: usize = 256;
+
+/// Everything the server sends, tagged with a `type` field
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Sample {
+        timestamp: i32,
+        zone: usize,
+        raw: f64,
+        normalized: f64,
+    },
+    Config {
+        config: &'a AppConfig,
+    },
+    Ok,
+    Error {
+        message: String,
+    },
+}
+
+/// Commands a client may send, tagged with a `cmd` field
+#[derive(Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum ClientCommand {
+    GetConfig,
+    /// Sets `midi.note_config.threshold`
+    SetThreshold { value: f64 },
+}
+
+/// Starts a WebSocket server on localhost that sends every processed sample
+/// to all connected clients. Commands are only accepted with `allow_control`.
+pub async fn start(
+    port: u16,
+    allow_control: bool,
+    app_config: Arc<TrackedMutex<ConfigStore>>,
+    shutdown: CancellationToken,
+) -> io::Result<SampleTap> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port)).await?;
+    info!("WebSocket server listening on ws://{}", listener.local_addr()?);
+    Ok(serve(listener, allow_control, app_config, shutdown))
+}
+
+fn serve(
+    listener: TcpListener,
+    allow_control: bool,
+    app_config: Arc<TrackedMutex<ConfigStore>>,
+    shutdown: CancellationToken,
+) -> SampleTap {
+    let (tap, rx, dropped) = SampleTap::new(WS_QUEUE_SIZE);
+    let (frames, _) = broadcast::channel(CLIENT_QUEUE_SIZE);
+    tokio::spawn(broadcast_samples(rx, dropped, frames.clone()));
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("Failed to accept WebSocket connection: {}", e);
+                        continue;
+                    }
+                },
+                _ = shutdown.cancelled() => return,
+            };
+            tokio::spawn(serve_client(
+                stream,
+                peer,
+                frames.subscribe(),
+                allow_control,
+                app_config.clone(),
+                shutdown.clone(),
+            ));
+        }
+    });
+    tap
+}
+
+/// Serializes each sample once and hands it to every client's queue.
+async fn broadcast_samples(
+    mut rx: mpsc::Receiver<SampleEvent>,
+    dropped: Arc<AtomicU64>,
+    frames: broadcast::Sender<Utf8Bytes>,
+) {
+    while let Some(event) = rx.recv().await {
+        if frames.receiver_count() == 0 {
+            continue;
+        }
+        let sample = event.processed;
+        let message = ServerMessage::Sample {
+            timestamp: sample.timestamp,
+            zone: sample.zone,
+            raw: sample.value_raw,
+            normalized: sample.value_normalized,
+        };
+        if let Ok(json) = serde_json::to_string(&message) {
+            // Fails only when the last client just left
+            let _ = frames.send(json.into());
+        }
+    }
+
+    let dropped = dropped.load(Ordering::Relaxed);
+    if dropped > 0 {
+        warn!("WebSocket server fell behind, dropped {} samples", dropped);
+    }
+}
+
+async fn serve_client(
+    stream: TcpStream,
+    peer: SocketAddr,
+    mut frames: broadcast::Receiver<Utf8Bytes>,
+    allow_control: bool,
+    app_config: Arc<TrackedMutex<ConfigStore>>,
+    shutdown: CancellationToken,
+) {
+    let mut ws = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws) => ws,
+        Err(e) => {
+            debug!("WebSocket handshake with {} failed: {}", peer, e);
+            return;
+        }
+    };
+    info!("WebSocket client {} connected", peer);
+
+    let mut dropped = 0;
+    loop {
+        let result = tokio::select! {
+            frame = frames.recv() => match frame {
+                Ok(frame) => ws.send(Message::Text(frame)).await,
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    dropped += count;
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            message = ws.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = handle_command(&text, allow_control, &app_config);
+                    ws.send(Message::Text(reply.into())).await
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                // Pings are answered by tungstenite itself
+                Some(Ok(_)) => Ok(()),
+                Some(Err(e)) => Err(e),
+            },
+            _ = shutdown.cancelled() => {
+                let _ = ws.close(None).await;
+                break;
+            }
+        };
+        if let Err(e) = result {
+            debug!("WebSocket client {} failed: {}", peer, e);
+            break;
+        }
+    }
+
+    if dropped > 0 {
+        info!("WebSocket client {} disconnected, {} frames dropped while it was behind", peer, dropped);
+    } else {
+        info!("WebSocket client {} disconnected", peer);
+    }
+}
+
+/// Runs one client command and returns the JSON reply.
+fn handle_command(text: &str, allow_control: bool, app_config: &TrackedMutex<ConfigStore>) -> String {
+    let reply = |message: &ServerMessage| serde_json::to_string(message).unwrap_or_default();
+    let error = |message: String| reply(&ServerMessage::Error { message });
+
+    let command: ClientCommand = match serde_json::from_str(text) {
+        Ok(command) => command,
+        Err(e) => return error(format!("Invalid command: {}", e)),
+    };
+    if !allow_control {
+        return error("Commands are disabled, start with --ws-allow-control".to_string());
+    }
+
+    let mut app_config = app_config.lock_or_recover();
+    match command {
+        ClientCommand::GetConfig => reply(&ServerMessage::Config { config: &app_config }),
+        ClientCommand::SetThreshold { value } => {
+            if !(0.001..=1.0).contains(&value) {
+                return error("Threshold must be between 0.001 and 1".to_string());
+            }
+            app_config.midi.note_config.threshold = value;
+            app_config.mark_dirty();
+            info!("MIDI note threshold set to {} by a WebSocket client", value);
+            reply(&ServerMessage::Ok)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pipeline::ProcessedSample;
+    use crate::sample::Sample;
+    use crate::sync::tracked;
+    use serde_json::{json, Value};
+    use std::time::{Duration, SystemTime};
+    use tokio::time::timeout;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn store() -> Arc<TrackedMutex<ConfigStore>> {
+        Arc::new(tracked("app_config", ConfigStore::new(AppConfig::default())))
+    }
+
+    fn event(timestamp: i32, zone: usize) -> SampleEvent {
+        SampleEvent {
+            sample: Sample {
+                timestamp,
+                zone,
+                value: Some(5000),
+                flags: None,
+                sequence: None,
+            },
+            received: SystemTime::UNIX_EPOCH,
+            processed: ProcessedSample {
+                timestamp,
+                zone,
+                value_raw: 5000.0,
+                value_normalized: 0.5,
+                derivative: 0.0,
+                peak: 0.5,
+                saturated: false,
+            },
+            midi_action: None,
+            gestures: Default::default(),
+        }
+    }
+
+    async fn next_json<S>(client: &mut S) -> Value
+    where
+        S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        loop {
+            if let Message::Text(text) = timeout(TIMEOUT, client.next()).await.unwrap().unwrap().unwrap() {
+                return serde_json::from_str(&text).unwrap();
+            }
+        }
+    }
+
+    fn reply(text: &str, allow_control: bool, app_config: &TrackedMutex<ConfigStore>) -> Value {
+        serde_json::from_str(&handle_command(text, allow_control, app_config)).unwrap()
+    }
+
+    #[test]
+    fn commands_need_allow_control() {
+        let app_config = store();
+        let message = reply(r#"{"cmd":"get_config"}"#, false, &app_config);
+        assert_eq!(message["type"], "error");
+        assert_eq!(reply("not json", true, &app_config)["type"], "error");
+        assert_eq!(reply(r#"{"cmd":"reboot"}"#, true, &app_config)["type"], "error");
+    }
+
+    #[test]
+    fn set_threshold_is_range_checked() {
+        let app_config = store();
+        assert_eq!(reply(r#"{"cmd":"set_threshold","value":0.25}"#, true, &app_config), json!({ "type": "ok" }));
+        assert_eq!(app_config.lock_or_recover().midi.note_config.threshold, 0.25);
+
+        let message = reply(r#"{"cmd":"set_threshold","value":2.0}"#, true, &app_config);
+        assert_eq!(message["type"], "error");
+        assert_eq!(app_config.lock_or_recover().midi.note_config.threshold, 0.25);
+
+        let message = reply(r#"{"cmd":"get_config"}"#, true, &app_config);
+        assert_eq!(message["type"], "config");
+        assert_eq!(message["config"]["midi"]["note_config"]["threshold"], 0.25);
+    }
+
+    #[tokio::test]
+    async fn client_receives_samples_and_sends_commands() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let tap = serve(listener, true, store(), shutdown.clone());
+
+        let (mut client, _) = timeout(TIMEOUT, tokio_tungstenite::connect_async(format!("ws://{}", addr)))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(tap.offer(event(1234, 2)));
+        assert_eq!(
+            next_json(&mut client).await,
+            json!({ "type": "sample", "timestamp": 1234, "zone": 2, "raw": 5000.0, "normalized": 0.5 })
+        );
+
+        client
+            .send(Message::Text(r#"{"cmd":"set_threshold","value":0.3}"#.into()))
+            .await
+            .unwrap();
+        assert_eq!(next_json(&mut client).await, json!({ "type": "ok" }));
+
+        shutdown.cancel();
+    }
+}
@@ -0,0 +1,715 @@
+use crate::auto_gain::AutoGain;
+use crate::config::{AppConfig, DriftConfig, LogicalZones, NormalizationMode, ZoneMapScope};
+use crate::drift::{DriftChange, DriftDetector};
+use crate::exponential_average::ExponentialAverage;
+use crate::peak_hold::PeakHold;
+use crate::percentile::PercentileWindow;
+use crate::pipeline::ProcessedSample;
+use crate::sample::Sample;
+use std::time::Instant;
+
+/// Everything the pipeline keeps about one device zone between samples, so
+/// a coil keeps its baseline whatever the zone map makes of it
+#[derive(Clone)]
+pub struct ZoneState {
+    /// Resting value the raw values are normalized against
+    baseline: ExponentialAverage,
+    /// The newest sample of this zone, `None` until the first one
+    last: Option<ProcessedSample>,
+    /// Samples seen since the pipeline started
+    samples: u64,
+    peak: PeakHold,
+    /// Recent raw values for `NormalizationMode::PercentileRange`
+    range: PercentileWindow,
+    /// Drift of `baseline` since calibration, started over with it
+    drift: DriftDetector,
+}
+
+impl ZoneState {
+    fn new(alpha: f64) -> Self {
+        Self {
+            baseline: ExponentialAverage::new(alpha),
+            last: None,
+            samples: 0,
+            peak: PeakHold::default(),
+            range: PercentileWindow::default(),
+            drift: DriftDetector::new(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.baseline.reset();
+        self.range.clear();
+        self.drift.reset();
+    }
+}
+
+/// `raw` as a position in the cycle count window `(begin, end)`, `None` for
+/// an empty or reversed window
+pub fn window_relative(raw: f64, (begin, end): (u32, u32)) -> Option<f64> {
+    (end > begin).then(|| (raw - f64::from(begin)) / f64::from(end - begin))
+}
+
+/// A copy of one zone's state for reporting
+#[derive(Debug, Clone, Copy)]
+pub struct ZoneStats {
+    pub baseline: Option<f64>,
+    pub last_raw: Option<f64>,
+    pub last_normalized: Option<f64>,
+    pub samples: u64,
+}
+
+/// The per-zone state of the pipeline: maps device zones to output zones and
+/// normalizes each value against its device zone's baseline. With
+/// `ZoneMapScope::MidiOnly` the map is only applied for MIDI.
+pub struct ZoneEngine {
+    zones: Vec<ZoneState>,
+    /// Output zone of every device zone, the inverse of `zone_map`
+    output_zones: Vec<usize>,
+    /// The config's zone map `output_zones` was built from
+    zone_map: Vec<usize>,
+    auto_gain: AutoGain,
+    logical_zones: LogicalZones,
+    /// The config's zone groups `logical_zones` was built from
+    zone_groups: Vec<Vec<usize>>,
+    /// `cycle_count_begin` and `cycle_count_end` of every device zone as
+    /// last read from the device, empty before
+    cycle_windows: Vec<(u32, u32)>,
+    /// The config's `window_relative_raw` the baselines were built with
+    window_relative_raw: bool,
+}
+
+impl ZoneEngine {
+    pub fn new(num_zones: usize, alpha: f64) -> Self {
+        Self {
+            zones: vec![ZoneState::new(alpha); num_zones],
+            output_zones: Vec::new(),
+            zone_map: Vec::new(),
+            auto_gain: AutoGain::default(),
+            logical_zones: LogicalZones::new(&[], num_zones),
+            zone_groups: Vec::new(),
+            cycle_windows: Vec::new(),
+            window_relative_raw: false,
+        }
+    }
+
+    /// Learns and applies the auto gain of every output zone in `auto_gain`.
+    pub fn set_auto_gain(&mut self, auto_gain: AutoGain) {
+        self.auto_gain = auto_gain;
+    }
+
+    pub fn cycle_windows(&self) -> &[(u32, u32)] {
+        &self.cycle_windows
+    }
+
+    /// Takes the cycle count windows of every device zone from a device
+    /// config just read. With `window_relative_raw` a zone whose window
+    /// changed starts a new baseline and percentile window, as its raw values
+    /// change scale.
+    pub fn set_cycle_windows(&mut self, windows: &[(u32, u32)], config: &AppConfig) {
+        if config.normalization.window_relative_raw {
+            for (device_zone, window) in windows.iter().enumerate().take(self.zones.len()) {
+                if self.cycle_windows.get(device_zone) != Some(window) {
+                    self.zones[device_zone].reset();
+                }
+            }
+        }
+        self.cycle_windows = windows.to_vec();
+    }
+
+    /// Inverts `zone_map`. While the map is being edited it may map a device
+    /// zone twice or not at all: the first output zone wins, and unmapped
+    /// device zones keep their own number.
+    fn set_zone_map(&mut self, zone_map: &[usize]) {
+        self.output_zones = (0..self.zones.len()).collect();
+        for (output_zone, &device_zone) in zone_map.iter().enumerate().rev() {
+            if let Some(slot) = self.output_zones.get_mut(device_zone) {
+                *slot = output_zone;
+            }
+        }
+        self.zone_map = zone_map.to_vec();
+    }
+
+    /// The output zone `device_zone` maps to through `zone_map`
+    pub fn output_zone(&mut self, device_zone: usize, zone_map: &[usize]) -> usize {
+        // GUI edits change the map in place, so compare instead of relying on
+        // the config generation
+        if zone_map != self.zone_map {
+            self.set_zone_map(zone_map);
+        }
+        self.output_zones[device_zone]
+    }
+
+    /// The zone samples of `device_zone` come out of `ingest` with: its
+    /// output zone, or the device zone itself with `ZoneMapScope::MidiOnly`
+    pub fn processed_zone(&mut self, device_zone: usize, config: &AppConfig) -> usize {
+        match config.zone_map_scope {
+            ZoneMapScope::Everything => self.output_zone(device_zone, &config.zone_map),
+            ZoneMapScope::MidiOnly => device_zone,
+        }
+    }
+
+    /// The output zone MIDI and the zone bindings play `zone`, a zone out of
+    /// `ingest`, as
+    pub fn midi_zone(&mut self, zone: usize, config: &AppConfig) -> usize {
+        match config.zone_map_scope {
+            ZoneMapScope::Everything => zone,
+            ZoneMapScope::MidiOnly => self.output_zone(zone, &config.zone_map),
+        }
+    }
+
+    /// The logical zone `zone` belongs to through `zone_groups` (see
+    /// `AppConfig::zone_groups`)
+    pub fn logical_zone(&mut self, zone: usize, zone_groups: &[Vec<usize>]) -> usize {
+        // Like the zone map, edited in place by the GUI
+        if zone_groups != self.zone_groups {
+            self.logical_zones = LogicalZones::new(zone_groups, self.zones.len());
+            self.zone_groups = zone_groups.to_vec();
+        }
+        self.logical_zones.logical_zone(zone)
+    }
+
+    /// What MIDI plays for `processed`, which was just ingested: the sample
+    /// moved to its logical zone, and for a zone group the newest values of
+    /// its members reduced by `config.zone_group_reduction`.
+    pub fn logical_sample(&mut self, processed: &ProcessedSample, config: &AppConfig) -> ProcessedSample {
+        let output_zone = self.midi_zone(processed.zone, config);
+        let logical = self.logical_zone(output_zone, &config.zone_groups);
+        let members = self.logical_zones.members(logical);
+        if members.len() == 1 {
+            return ProcessedSample {
+                zone: logical,
+                ..*processed
+            };
+        }
+        // Members are output zones, their state is under the device zone
+        // mapped to them
+        let state_zone = |zone: usize| config.zone_map.get(zone).copied().unwrap_or(zone);
+        let lasts = || {
+            members
+                .iter()
+                .filter_map(|&zone| self.zones.get(state_zone(zone)).and_then(|state| state.last))
+        };
+        let reduction = config.zone_group_reduction;
+        ProcessedSample {
+            zone: logical,
+            value_normalized: reduction.reduce(lasts().map(|last| last.value_normalized)),
+            derivative: reduction.reduce(lasts().map(|last| last.derivative)),
+            peak: reduction.reduce(lasts().map(|last| last.peak)),
+            saturated: lasts().any(|last| last.saturated),
+            ..*processed
+        }
+    }
+
+    /// Maps `sample` to its output zone through `config.zone_map`, unless
+    /// the map is for MIDI only (see `processed_zone`), scales it to its
+    /// cycle count window with `window_relative_raw` and normalizes its
+    /// value, averaging it into its device zone's baseline at `alpha`. A
+    /// sample without a value leaves the baseline alone and comes out as
+    /// zero. Auto gain scales the normalized value and the zone's peak hold
+    /// follows its magnitude, taking `now` as the sample's time. The
+    /// derivative is taken against the zone's previous sample in device
+    /// time, zero for the first one or when the device clock stood still.
+    pub fn ingest(&mut self, sample: Sample, config: &AppConfig, alpha: f64, now: Instant) -> ProcessedSample {
+        if config.normalization.window_relative_raw != self.window_relative_raw {
+            // Raw values change scale, so the old baselines mean nothing
+            self.reset();
+            self.window_relative_raw = config.normalization.window_relative_raw;
+        }
+        let zone = self.processed_zone(sample.zone, config);
+        let window = self.cycle_windows.get(sample.zone).copied();
+        let state = &mut self.zones[sample.zone];
+        let (value_raw, value_normalized) = if let Some(value) = sample.value {
+            let count = value as f64;
+            let relative = window.and_then(|window| window_relative(count, window));
+            let raw = match relative {
+                Some(relative) if config.normalization.window_relative_raw => relative,
+                _ => count,
+            };
+            state.baseline.set_alpha(alpha);
+            state.baseline.set_warmup(config.normalization.baseline_warmup_samples);
+            state.baseline.update(raw);
+            let normalization = &config.normalization;
+            let normalized = match normalization.mode {
+                NormalizationMode::RelativeToBaseline => {
+                    let average = state.baseline.get_average().unwrap_or(0.0);
+                    if average.abs() < normalization.epsilon {
+                        0.0
+                    } else {
+                        (raw - average) / average
+                    }
+                }
+                NormalizationMode::PercentileRange => {
+                    state.range.add(raw, now, normalization.window());
+                    let low = state.range.percentile(normalization.low_percentile).unwrap_or(raw);
+                    let high = state.range.percentile(normalization.high_percentile).unwrap_or(raw);
+                    if high - low < normalization.epsilon {
+                        0.0
+                    } else {
+                        ((raw - low) / (high - low)).clamp(0.0, 1.0)
+                    }
+                }
+                NormalizationMode::CycleWindow => relative.unwrap_or(0.0),
+            };
+            let gain = self.auto_gain.update(zone, normalized.abs(), now, &config.auto_gain);
+            (raw, normalized * gain)
+        } else {
+            (0.0, 0.0)
+        };
+        let peak = &config.midi.peak;
+        let magnitude = value_normalized.abs();
+        let peak = if peak.enabled {
+            state.peak.update(magnitude, now, peak.hold(), peak.decay_per_sec)
+        } else {
+            magnitude
+        };
+
+        let derivative = state.last.map_or(0.0, |last| {
+            let dt = f64::from(sample.timestamp.wrapping_sub(last.timestamp)) / 1000.0;
+            if dt > 0.0 {
+                (value_normalized - last.value_normalized) / dt
+            } else {
+                0.0
+            }
+        });
+
+        let processed = ProcessedSample {
+            zone,
+            timestamp: sample.timestamp,
+            value_raw,
+            value_normalized,
+            derivative,
+            peak,
+            saturated: sample.saturated(),
+        };
+        state.last = Some(processed);
+        state.samples += 1;
+        processed
+    }
+
+    /// Forgets every zone's baseline and percentile window, the next sample
+    /// starts new ones.
+    pub fn reset(&mut self) {
+        for state in &mut self.zones {
+            state.reset();
+        }
+    }
+
+    /// Takes every zone's baseline as the one its drift is measured from,
+    /// at the end of a calibration.
+    pub fn set_drift_references(&mut self) {
+        for state in &mut self.zones {
+            match state.baseline.get_average() {
+                Some(baseline) => state.drift.set_reference(baseline),
+                None => state.drift.reset(),
+            }
+        }
+    }
+
+    /// Feeds the baseline of `device_zone` to its drift detector, see
+    /// `DriftDetector::update`. Disabled, the detector starts over.
+    pub fn watch_drift(&mut self, device_zone: usize, config: &DriftConfig, now: Instant) -> Option<DriftChange> {
+        let state = &mut self.zones[device_zone];
+        if !config.enabled {
+            state.drift.reset();
+            return None;
+        }
+        let baseline = state.baseline.get_average()?;
+        state.drift.update(baseline, now, config)
+    }
+
+    /// How far the baseline of `device_zone` drifted, while past the
+    /// threshold
+    pub fn drifted(&self, device_zone: usize) -> Option<f64> {
+        self.zones[device_zone].drift.drifted()
+    }
+
+    /// The state of every device zone, in their order
+    pub fn snapshot_stats(&self) -> Vec<ZoneStats> {
+        self.zones
+            .iter()
+            .map(|state| ZoneStats {
+                baseline: state.baseline.get_average(),
+                last_raw: state.last.map(|last| last.value_raw),
+                last_normalized: state.last.map(|last| last.value_normalized),
+                samples: state.samples,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::zones::create_default_zone_map;
+    use crate::config::{NormalizationConfig, NUM_ZONES};
+    use std::time::Duration;
+
+    const ALPHA: f64 = 0.01;
+
+    fn sample(timestamp: i32, zone: usize, value: Option<i32>) -> Sample {
+        Sample {
+            timestamp,
+            zone,
+            value,
+            flags: None,
+            sequence: None,
+        }
+    }
+
+    /// The config the pipeline normalized with before the engine: no
+    /// baseline warmup, and the zone map reversed so mapping shows
+    fn legacy_config() -> AppConfig {
+        AppConfig {
+            zone_map: (0..NUM_ZONES).rev().collect(),
+            normalization: NormalizationConfig {
+                baseline_warmup_samples: 0,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Repeatable raw values between 4000 and 6000
+    fn values() -> impl Iterator<Item = (i32, usize, i32)> {
+        (0..2000).map(|index| (index, index as usize % NUM_ZONES, 4000 + ((index * 7919) % 2000)))
+    }
+
+    #[test]
+    fn ingest_matches_the_old_averaging() {
+        let config = legacy_config();
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        // The pipeline's process_sample before the engine
+        let mut averages = [ExponentialAverage::new(ALPHA); NUM_ZONES];
+        let now = Instant::now();
+        for (timestamp, device_zone, value) in values() {
+            let processed = engine.ingest(sample(timestamp, device_zone, Some(value)), &config, ALPHA, now);
+
+            let zone = config.zone_map.iter().position(|&x| x == device_zone).unwrap_or(device_zone);
+            let raw = value as f64;
+            averages[zone].set_alpha(ALPHA);
+            averages[zone].update(raw);
+            let average = averages[zone].get_average().unwrap_or(0.0);
+            assert_eq!(processed.zone, zone);
+            assert_eq!(processed.value_raw, raw);
+            assert_eq!(processed.value_normalized, (raw - average) / average, "sample {}", timestamp);
+        }
+    }
+
+    #[test]
+    fn a_sample_without_a_value_leaves_the_baseline_alone() {
+        let config = legacy_config();
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        let now = Instant::now();
+        engine.ingest(sample(0, 0, Some(5000)), &config, ALPHA, now);
+        let processed = engine.ingest(sample(1, 0, None), &config, ALPHA, now);
+        assert_eq!((processed.value_raw, processed.value_normalized), (0.0, 0.0));
+        assert_eq!(engine.snapshot_stats()[0].baseline, Some(5000.0));
+    }
+
+    #[test]
+    fn reset_starts_new_baselines() {
+        let config = legacy_config();
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        let now = Instant::now();
+        engine.ingest(sample(0, 1, Some(5000)), &config, ALPHA, now);
+        engine.reset();
+        let processed = engine.ingest(sample(1, 1, Some(6000)), &config, ALPHA, now);
+        assert_eq!(processed.value_normalized, 0.0);
+        assert_eq!(engine.snapshot_stats()[1].baseline, Some(6000.0));
+    }
+
+    #[test]
+    fn snapshot_stats_reports_each_device_zone() {
+        let config = legacy_config();
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        let now = Instant::now();
+        engine.ingest(sample(0, 0, Some(5000)), &config, ALPHA, now);
+        engine.ingest(sample(1, 0, Some(5100)), &config, ALPHA, now);
+        let stats = engine.snapshot_stats();
+        assert_eq!(stats.len(), NUM_ZONES);
+        // Under device zone 0, though it is the last output zone with the
+        // reversed map
+        let first = &stats[0];
+        assert_eq!(first.samples, 2);
+        assert_eq!(first.last_raw, Some(5100.0));
+        assert!(first.last_normalized.is_some_and(|normalized| normalized > 0.0));
+        assert!(stats[1..].iter().all(|zone| zone.samples == 0 && zone.baseline.is_none()));
+    }
+
+    /// Every coil resting at its own value for 100 samples from `from` on,
+    /// as the engine puts them out
+    fn rest_all(engine: &mut ZoneEngine, config: &AppConfig, from: i32) -> Vec<ProcessedSample> {
+        let mut processed = Vec::new();
+        for timestamp in from..from + 100 {
+            for zone in 0..NUM_ZONES {
+                let value = 4000 + 500 * zone as i32;
+                processed.push(engine.ingest(sample(timestamp, zone, Some(value)), config, ALPHA, Instant::now()));
+            }
+        }
+        processed
+    }
+
+    #[test]
+    fn a_remap_keeps_every_coil_on_its_own_baseline() {
+        let mut config = AppConfig {
+            zone_map: create_default_zone_map(NUM_ZONES),
+            ..legacy_config()
+        };
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        let resting = |processed: &[ProcessedSample]| processed.iter().all(|sample| sample.value_normalized == 0.0);
+        assert!(resting(&rest_all(&mut engine, &config, 0)));
+
+        // Edited in place, as the GUI does
+        config.zone_map.swap(0, 1);
+        let processed = rest_all(&mut engine, &config, 100);
+        assert!(resting(&processed));
+        assert_eq!(processed[0].zone, 1, "device zone 0 comes out as output zone 1");
+        assert_eq!(engine.snapshot_stats()[0].baseline, Some(4000.0));
+
+        config.zone_map_scope = ZoneMapScope::MidiOnly;
+        assert!(resting(&rest_all(&mut engine, &config, 200)));
+        config.zone_map_scope = ZoneMapScope::Everything;
+        assert!(resting(&rest_all(&mut engine, &config, 300)));
+    }
+
+    #[test]
+    fn output_zones_invert_the_map() {
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        let zone_map = [3, 0, 1, 2, 7, 6, 5, 4];
+        for (output_zone, &device_zone) in zone_map.iter().enumerate() {
+            assert_eq!(engine.output_zone(device_zone, &zone_map), output_zone);
+        }
+    }
+
+    #[test]
+    fn a_map_edited_in_place_is_picked_up() {
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        let mut zone_map = create_default_zone_map(NUM_ZONES);
+        assert_eq!(engine.output_zone(2, &zone_map), 2);
+        zone_map.swap(2, 5);
+        assert_eq!(engine.output_zone(2, &zone_map), 5);
+        assert_eq!(engine.output_zone(5, &zone_map), 2);
+    }
+
+    #[test]
+    fn a_half_edited_map_keeps_the_fallbacks() {
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        // Device zone 1 twice, device zone 0 nowhere
+        let zone_map = [1, 1, 2, 3, 4, 5, 6, 7];
+        assert_eq!(engine.output_zone(1, &zone_map), 0);
+        assert_eq!(engine.output_zone(0, &zone_map), 0);
+        // Shorter than the zones, the rest keep their number
+        assert_eq!(engine.output_zone(6, &[6]), 0);
+        assert_eq!(engine.output_zone(7, &[6]), 7);
+    }
+
+    #[test]
+    fn midi_only_scope_maps_only_midi() {
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        let mut config = legacy_config();
+        config.zone_map_scope = ZoneMapScope::MidiOnly;
+        let processed = engine.ingest(sample(0, 1, Some(5000)), &config, ALPHA, Instant::now());
+        assert_eq!(processed.zone, 1);
+        assert_eq!(engine.midi_zone(processed.zone, &config), NUM_ZONES - 2);
+
+        config.zone_map_scope = ZoneMapScope::Everything;
+        assert_eq!(engine.processed_zone(1, &config), NUM_ZONES - 2);
+        assert_eq!(engine.midi_zone(NUM_ZONES - 2, &config), NUM_ZONES - 2);
+    }
+
+    #[test]
+    fn derivative_follows_device_time() {
+        let config = legacy_config();
+        let mut engine = ZoneEngine::new(NUM_ZONES, 0.0);
+        let now = Instant::now();
+        // Alpha 0 keeps the first value as the baseline, so halving it is -0.5
+        let first = engine.ingest(sample(1000, 0, Some(4000)), &config, 0.0, now);
+        assert_eq!(first.derivative, 0.0);
+        let second = engine.ingest(sample(1500, 0, Some(2000)), &config, 0.0, now);
+        assert_eq!(second.value_normalized, -0.5);
+        assert_eq!(second.derivative, -1.0);
+        let same_time = engine.ingest(sample(1500, 0, Some(2000)), &config, 0.0, now);
+        assert_eq!(same_time.derivative, 0.0);
+    }
+
+    /// What a synthetic zone reads at sample `index`, every 10 ms: a slow
+    /// upward drift, a 0.5 s press every 5 s and a one-sample spike every 97
+    const PRESS: std::ops::Range<i32> = 400..450;
+    fn drifting(index: i32) -> (i32, bool, bool) {
+        let pressed = PRESS.contains(&(index % 500));
+        let spike = index % 97 == 0;
+        let value = 5000 + index / 10 + if pressed { 800 } else { 0 } + if spike { 20_000 } else { 0 };
+        (value, pressed, spike)
+    }
+
+    /// The normalized values of the drifting zone under `normalization`
+    fn normalize_drifting(normalization: NormalizationConfig) -> Vec<(f64, bool, bool)> {
+        let config = AppConfig {
+            normalization,
+            ..Default::default()
+        };
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        let start = Instant::now();
+        (0..3000)
+            .map(|index| {
+                let (value, pressed, spike) = drifting(index);
+                let now = start + Duration::from_millis(10 * index as u64);
+                let processed = engine.ingest(sample(index * 10, 0, Some(value)), &config, ALPHA, now);
+                (processed.value_normalized, pressed, spike)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn percentile_range_ignores_drift_and_spikes() {
+        let normalized = normalize_drifting(NormalizationConfig {
+            mode: NormalizationMode::PercentileRange,
+            ..Default::default()
+        });
+        // Past the first window, once it holds presses
+        for (index, &(value, pressed, spike)) in normalized.iter().enumerate().skip(1000) {
+            assert!((0.0..=1.0).contains(&value), "sample {} is {}", index, value);
+            if spike {
+                assert_eq!(value, 1.0, "sample {}", index);
+            } else if pressed && index as i32 % 500 >= PRESS.start + 5 {
+                assert!(value > 0.9, "press at sample {} is {}", index, value);
+            } else if !pressed {
+                assert!(value < 0.25, "rest at sample {} is {}", index, value);
+            }
+        }
+    }
+
+    #[test]
+    fn relative_to_baseline_follows_drift_but_not_spikes() {
+        let normalized = normalize_drifting(NormalizationConfig::default());
+        for (index, &(value, pressed, spike)) in normalized.iter().enumerate().skip(1000) {
+            if spike {
+                assert!(value > 3.0, "spike at sample {} is {}", index, value);
+            } else if pressed && index as i32 % 500 == PRESS.start {
+                assert!(value > 0.1, "press at sample {} is {}", index, value);
+            } else if !pressed {
+                assert!(value.abs() < 0.15, "rest at sample {} is {}", index, value);
+            }
+        }
+    }
+
+    #[test]
+    fn a_baseline_below_epsilon_normalizes_to_zero() {
+        let config = legacy_config();
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        let now = Instant::now();
+        for value in [0, 1, 0, 1] {
+            let processed = engine.ingest(sample(0, 0, Some(value)), &config, ALPHA, now);
+            assert_eq!(processed.value_normalized, 0.0);
+        }
+    }
+
+    #[test]
+    fn a_band_below_epsilon_normalizes_to_zero() {
+        let config = AppConfig {
+            normalization: NormalizationConfig {
+                mode: NormalizationMode::PercentileRange,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        let now = Instant::now();
+        for timestamp in 0..100 {
+            let processed = engine.ingest(sample(timestamp, 0, Some(5000)), &config, ALPHA, now);
+            assert_eq!(processed.value_normalized, 0.0);
+        }
+    }
+
+    #[test]
+    fn window_relative_positions() {
+        assert_eq!(window_relative(3000.0, (1000, 5000)), Some(0.5));
+        assert_eq!(window_relative(500.0, (1000, 5000)), Some(-0.125));
+        assert_eq!(window_relative(3000.0, (5000, 5000)), None);
+        assert_eq!(window_relative(3000.0, (5000, 1000)), None);
+    }
+
+    /// Windows 1000-5000 and 2000-10000 on zones 0 and 1, the rest unknown
+    fn windowed_engine(config: &AppConfig) -> ZoneEngine {
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        engine.set_cycle_windows(&[(1000, 5000), (2000, 10000)], config);
+        engine
+    }
+
+    #[test]
+    fn zones_with_other_windows_normalize_alike() {
+        let mut config = AppConfig::default();
+        config.normalization.mode = NormalizationMode::CycleWindow;
+        let mut engine = windowed_engine(&config);
+        let now = Instant::now();
+        let first = engine.ingest(sample(0, 0, Some(3000)), &config, ALPHA, now);
+        let second = engine.ingest(sample(1, 1, Some(6000)), &config, ALPHA, now);
+        assert_eq!((first.value_normalized, second.value_normalized), (0.5, 0.5));
+        assert_eq!((first.value_raw, second.value_raw), (3000.0, 6000.0), "raw stays counts");
+        let unknown = engine.ingest(sample(2, 2, Some(6000)), &config, ALPHA, now);
+        assert_eq!(unknown.value_normalized, 0.0);
+    }
+
+    #[test]
+    fn window_relative_raw_scales_the_raw_values() {
+        let mut config = AppConfig::default();
+        config.normalization.window_relative_raw = true;
+        let mut engine = windowed_engine(&config);
+        let now = Instant::now();
+        let first = engine.ingest(sample(0, 0, Some(3000)), &config, ALPHA, now);
+        let second = engine.ingest(sample(1, 1, Some(6000)), &config, ALPHA, now);
+        assert_eq!((first.value_raw, second.value_raw), (0.5, 0.5));
+        let stats = engine.snapshot_stats();
+        assert_eq!((stats[0].baseline, stats[1].baseline), (Some(0.5), Some(0.5)));
+        // Without a window the counts stay as they are
+        let unknown = engine.ingest(sample(2, 2, Some(6000)), &config, ALPHA, now);
+        assert_eq!(unknown.value_raw, 6000.0);
+    }
+
+    #[test]
+    fn a_changed_window_restarts_only_its_zones_baseline() {
+        let mut config = AppConfig::default();
+        config.normalization.window_relative_raw = true;
+        let mut engine = windowed_engine(&config);
+        let now = Instant::now();
+        engine.ingest(sample(0, 0, Some(3000)), &config, ALPHA, now);
+        engine.ingest(sample(1, 1, Some(6000)), &config, ALPHA, now);
+        engine.set_cycle_windows(&[(1000, 5000), (2000, 6000)], &config);
+        assert_eq!(engine.cycle_windows(), [(1000, 5000), (2000, 6000)]);
+        let stats = engine.snapshot_stats();
+        assert_eq!((stats[0].baseline, stats[1].baseline), (Some(0.5), None));
+    }
+
+    #[test]
+    fn switching_window_relative_raw_restarts_the_baselines() {
+        let mut config = AppConfig::default();
+        let mut engine = windowed_engine(&config);
+        let now = Instant::now();
+        engine.ingest(sample(0, 0, Some(3000)), &config, ALPHA, now);
+        config.normalization.window_relative_raw = true;
+        engine.ingest(sample(1, 1, Some(6000)), &config, ALPHA, now);
+        let stats = engine.snapshot_stats();
+        assert_eq!((stats[0].baseline, stats[1].baseline), (None, Some(0.5)));
+    }
+
+    #[test]
+    fn reset_clears_the_percentile_windows() {
+        let config = AppConfig {
+            normalization: NormalizationConfig {
+                mode: NormalizationMode::PercentileRange,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut engine = ZoneEngine::new(NUM_ZONES, ALPHA);
+        let now = Instant::now();
+        for (timestamp, value) in [(0, 1000), (1, 2000)] {
+            engine.ingest(sample(timestamp, 0, Some(value)), &config, ALPHA, now);
+        }
+        engine.reset();
+        // Without the old band, one value is a band of nothing
+        let processed = engine.ingest(sample(2, 0, Some(2000)), &config, ALPHA, now);
+        assert_eq!(processed.value_normalized, 0.0);
+    }
+}
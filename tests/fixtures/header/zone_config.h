@@ -0,0 +1,47 @@
+/*
+ * zone_config.h - default zone configuration of the Dildonica board
+ *
+ * Sample of the firmware's header, vendored for the parser tests of the
+ * frontend. Don't tidy it up: the odd spots are what is tested.
+ */
+#ifndef ZONE_CONFIG_H
+#define ZONE_CONFIG_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#define NUM_ZONES 8
+#define CC_BASE 0x29
+#define THRESH_MAX (4000u)
+#define ZONE_NAME_FMT "zone {%d}" /* braces in a string */
+
+typedef struct {
+    bool enabled;
+    uint8_t midi_control;
+    uint32_t cycle_count_begin;
+    uint32_t cycle_count_end;
+    uint32_t comp_thresh_lo;
+    uint32_t comp_thresh_hi;
+} zone_config_t;
+
+/* Not the zone configs: the parser must skip this { {1, 2}, } */
+static const struct { uint8_t pin; uint8_t port; } led_pins[2] = {
+    {13, 1},
+    {14, 1},
+};
+
+static const zone_config_t default_zone_configs[NUM_ZONES] = {
+    {true, CC_BASE, 1000, 10000, 100, THRESH_MAX},       // decimal with a #define
+    {1, 0x2A, 0x3E8, 0x2710u, 0x64, 0xFA0},              // hex literals
+    {1, 43, 01750, 023420, 0144, 07640},                 // octal
+    {1, 0b101100, 1000UL, 10000ul, 100, 4000},           // binary and suffixes
+    { .enabled = true, .midi_control = 45,
+      .cycle_count_begin = 2000, .cycle_count_end = 20000,
+      .comp_thresh_lo = 200, .comp_thresh_hi = 3000, }, // designated, trailing comma
+    { .midi_control = 46, .cycle_count_end = 5000 },     // left out fields are 0
+    {false, 47, 1000, 10000, 100, 4000,},                // trailing comma in a zone
+    /* a legacy zone: one threshold for both */
+    {1, 48, 1000, 10000, 2500},
+};
+
+#endif /* ZONE_CONFIG_H */
@@ -0,0 +1,168 @@
+//! The library's pipeline end to end, through its public API only: simulated
+//! samples in, the MIDI the pipeline sends out.
+
+use dildonica::config::{AppConfig, ConfigStore, MidiOutputMethod, NUM_ZONES};
+use dildonica::pipeline::{Pipeline, PipelineCommand};
+use dildonica::{
+    tracked, CaptureMidiSink, LockExt, MidiError, MidiOutputSink, MidiOutputState, MidiSink, PortTarget, Simulator,
+    TrackedMutex,
+};
+use std::collections::BTreeSet;
+use std::sync::{mpsc, Arc, Mutex};
+
+/// Simulated samples per run, three seconds at 1 kHz
+const SAMPLES: i32 = 3000;
+
+/// A pipeline writing to `sink`, and the config it reads
+fn pipeline(config: AppConfig, sink: impl MidiSink + 'static) -> (Pipeline, Arc<TrackedMutex<ConfigStore>>) {
+    let app_config = Arc::new(tracked("app_config", ConfigStore::new(config)));
+    let mut midi_output = MidiOutputState::new(PortTarget::Any);
+    midi_output.set_connected(sink, PortTarget::Any, "test sink".to_string());
+    let midi = MidiOutputSink::new(Arc::new(Mutex::new(midi_output)));
+    (Pipeline::new(app_config.clone(), midi, None), app_config)
+}
+
+fn config(method: MidiOutputMethod) -> AppConfig {
+    let mut config = AppConfig::default();
+    config.midi.method = method;
+    config
+}
+
+/// Feeds the simulator's samples `range` to `pipeline`.
+fn feed(pipeline: &mut Pipeline, simulator: &mut Simulator, range: std::ops::Range<i32>) {
+    for timestamp in range {
+        assert!(pipeline.handle_sample(simulator.sample(timestamp)));
+    }
+}
+
+/// The distinct controllers of the control changes among `messages`
+fn controllers(messages: &[Vec<u8>]) -> BTreeSet<u8> {
+    messages.iter().filter(|message| message[0] & 0xF0 == 0xB0).map(|message| message[1]).collect()
+}
+
+/// The notes `messages` leave held
+fn held_notes(messages: &[Vec<u8>]) -> BTreeSet<u8> {
+    let mut held = BTreeSet::new();
+    for message in messages {
+        match message[0] & 0xF0 {
+            0x90 if message[2] > 0 => held.insert(message[1]),
+            0x80 | 0x90 => held.remove(&message[1]),
+            _ => false,
+        };
+    }
+    held
+}
+
+/// Hands every message to a channel, as an application's own sink would
+struct ChannelSink(mpsc::Sender<Vec<u8>>);
+
+impl MidiSink for ChannelSink {
+    fn send(&mut self, message: &[u8]) -> Result<(), MidiError> {
+        self.0.send(message.to_vec()).map_err(|_| MidiError::NoPorts)
+    }
+}
+
+/// Refuses every message
+struct FailingSink;
+
+impl MidiSink for FailingSink {
+    fn send(&mut self, _message: &[u8]) -> Result<(), MidiError> {
+        Err(MidiError::PortNotFound)
+    }
+}
+
+#[test]
+fn simulated_squeezes_move_every_zones_controller() {
+    let capture = CaptureMidiSink::new();
+    let (mut pipeline, _) = pipeline(config(MidiOutputMethod::ControlChange), capture.clone());
+    feed(&mut pipeline, &mut Simulator::new(), 0..SAMPLES);
+    let messages = capture.take();
+    assert!(messages.iter().all(|message| message.len() == 3 && message[2] <= 127), "{:?}", messages);
+    assert_eq!(controllers(&messages), (41..41 + NUM_ZONES as u8).collect());
+    let stats = pipeline.stats().snapshot();
+    assert_eq!(stats.processed, SAMPLES as u64);
+    assert_eq!(stats.midi_errors, 0);
+}
+
+#[test]
+fn simulated_squeezes_play_notes_that_panic_releases() {
+    let capture = CaptureMidiSink::new();
+    let (mut pipeline, _) = pipeline(config(MidiOutputMethod::Notes), capture.clone());
+    let mut simulator = Simulator::new();
+    let mut messages = Vec::new();
+    // Until some zone is held down
+    for timestamp in 0..SAMPLES {
+        feed(&mut pipeline, &mut simulator, timestamp..timestamp + 1);
+        messages.extend(capture.take());
+        if !held_notes(&messages).is_empty() {
+            break;
+        }
+    }
+    let held = held_notes(&messages);
+    assert!(!held.is_empty(), "no note held within {} samples", SAMPLES);
+    assert!(held.iter().all(|note| (60..60 + NUM_ZONES as u8).contains(note)), "{:?}", held);
+
+    pipeline.command_sender().try_send(PipelineCommand::Panic).unwrap();
+    feed(&mut pipeline, &mut simulator, SAMPLES..SAMPLES + 1);
+    messages.extend(capture.take());
+    assert_eq!(held_notes(&messages), BTreeSet::new());
+}
+
+#[test]
+fn an_applications_sink_gets_what_a_capture_does() {
+    let capture = CaptureMidiSink::new();
+    let (mut captured, _) = pipeline(config(MidiOutputMethod::ControlChange), capture.clone());
+    let (tx, rx) = mpsc::channel();
+    let (mut sent, _) = pipeline(config(MidiOutputMethod::ControlChange), ChannelSink(tx));
+    feed(&mut captured, &mut Simulator::new(), 0..SAMPLES);
+    feed(&mut sent, &mut Simulator::new(), 0..SAMPLES);
+    let expected = capture.take();
+    assert!(!expected.is_empty());
+    assert_eq!(rx.try_iter().collect::<Vec<_>>(), expected);
+}
+
+#[test]
+fn a_failing_sink_is_counted_without_stopping_the_pipeline() {
+    let (mut pipeline, _) = pipeline(config(MidiOutputMethod::ControlChange), FailingSink);
+    feed(&mut pipeline, &mut Simulator::new(), 0..SAMPLES);
+    let stats = pipeline.stats().snapshot();
+    assert_eq!(stats.processed, SAMPLES as u64);
+    assert!(stats.midi_errors > 0);
+}
+
+#[test]
+fn muting_silences_the_pipeline_until_unmuted() {
+    let capture = CaptureMidiSink::new();
+    let (mut pipeline, _) = pipeline(config(MidiOutputMethod::ControlChange), capture.clone());
+    let mut simulator = Simulator::new();
+    let commands = pipeline.command_sender();
+    commands.try_send(PipelineCommand::SetMuted(true)).unwrap();
+    feed(&mut pipeline, &mut simulator, 0..SAMPLES);
+    assert_eq!(capture.take(), Vec::<Vec<u8>>::new());
+    commands.try_send(PipelineCommand::SetMuted(false)).unwrap();
+    feed(&mut pipeline, &mut simulator, SAMPLES..2 * SAMPLES);
+    assert!(!capture.take().is_empty());
+}
+
+#[test]
+fn config_changes_reach_a_running_pipeline() {
+    let capture = CaptureMidiSink::new();
+    let (mut pipeline, app_config) = pipeline(config(MidiOutputMethod::ControlChange), capture.clone());
+    let mut simulator = Simulator::new();
+    feed(&mut pipeline, &mut simulator, 0..SAMPLES);
+    assert_eq!(controllers(&capture.take()), (41..41 + NUM_ZONES as u8).collect());
+
+    // As the GUI's editor changes it, in place
+    app_config.lock_or_recover().midi.control_change_config.base_control_number = 20;
+    feed(&mut pipeline, &mut simulator, SAMPLES..2 * SAMPLES);
+    assert_eq!(controllers(&capture.take()), (20..20 + NUM_ZONES as u8).collect());
+}
+
+#[test]
+fn the_sample_limit_stops_the_source() {
+    let (mut pipeline, _) = pipeline(config(MidiOutputMethod::ControlChange), CaptureMidiSink::new());
+    pipeline.set_max_samples(100);
+    let mut simulator = Simulator::new();
+    feed(&mut pipeline, &mut simulator, 0..99);
+    assert!(!pipeline.handle_sample(simulator.sample(99)));
+}